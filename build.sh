@@ -7,10 +7,26 @@ if [ ! -d limine ]; then
   git clone https://github.com/limine-bootloader/limine.git --branch=v8.x-binary --depth=1 limine
 fi
 
-cargo +nightly build --target x86_64-unknown-none | tee build.log
+# Cargo features, e.g. CARGO_FEATURES=test ./build.sh for the self-tests
+cargo +nightly build --target x86_64-unknown-none ${CARGO_FEATURES:+--features "$CARGO_FEATURES"} \
+  | tee build.log
+
+KERNEL=target/x86_64-unknown-none/debug/SarOS
+
+# Symbol map for backtraces: function addresses and names, in address
+# order, written over the space .ksyms reserves
+KSYMS_SIZE=$(printf '%d' "0x$(objdump -h "$KERNEL" | awk '$2 == ".ksyms" { print $3 }')")
+nm -n -C --defined-only "$KERNEL" \
+  | sed -n 's/^\([0-9a-f]\{16\}\) [tTwW] \(.*\)$/\1 \2/p' > ksyms.txt
+if [ "$(stat -c %s ksyms.txt)" -ge "$KSYMS_SIZE" ]; then
+  echo "warning: symbol map truncated to $KSYMS_SIZE bytes" >&2
+fi
+truncate -s "$KSYMS_SIZE" ksyms.txt
+objcopy --update-section .ksyms=ksyms.txt "$KERNEL"
+rm ksyms.txt
 
 mkdir -p iso/boot/limine
-cp target/x86_64-unknown-none/debug/SarOS iso/boot/kernel
+cp "$KERNEL" iso/boot/kernel
 cp limine/limine-bios.sys limine/limine-bios-cd.bin limine/limine-uefi-cd.bin iso/boot/limine/
 
 cat > iso/boot/limine/limine.conf << 'EOF'
@@ -22,6 +38,30 @@ protocol: limine
 path: boot():/boot/kernel
 EOF
 
+# Kernel command line, e.g. KERNEL_CMDLINE=pmm.selftest ./build.sh
+if [ -n "${KERNEL_CMDLINE:-}" ]; then
+  echo "cmdline: ${KERNEL_CMDLINE}" >> iso/boot/limine/limine.conf
+fi
+
+# Everything under initrd/ is unpacked into the ramfs root at boot
+if [ -d initrd ]; then
+  (cd initrd && find . | cpio -o -H newc --quiet) > iso/boot/initrd.cpio
+  cat >> iso/boot/limine/limine.conf << 'EOF'
+module_path: boot():/boot/initrd.cpio
+module_cmdline: initrd
+EOF
+fi
+
+# A splash image, shown above the boot log until init starts
+for img in splash.png splash.bmp; do
+  if [ -f "$img" ]; then
+    cp "$img" iso/boot/
+    printf 'module_path: boot():/boot/%s\nmodule_cmdline: splash\n' "$img" \
+      >> iso/boot/limine/limine.conf
+    break
+  fi
+done
+
 xorriso -as mkisofs \
   -b boot/limine/limine-bios-cd.bin \
   -no-emul-boot -boot-load-size 4 -boot-info-table \
@@ -1,11 +1,16 @@
+pub mod acpi;
+pub mod apic;
+pub mod fpu;
 pub mod gdt;
 pub mod idt;
 pub mod io;
 pub mod limine;
 pub mod pic;
+pub mod power;
 pub mod syscall_entry;
 pub mod timer;
 
+use core::sync::atomic::{AtomicBool, Ordering};
 use io::*;
 
 pub fn init_bsp(kernel_stack_top: u64) {
@@ -24,32 +29,67 @@ pub fn init_bsp(kernel_stack_top: u64) {
     syscall_entry::init_syscall();
     log::debug!("SYSCALL initialized");
 
+    init_cpu_features();
+    fpu::init();
+}
+
+// ─── CPU protection features ─────────────────────────────────────────────────
+
+static SMAP: AtomicBool = AtomicBool::new(false);
+static NX: AtomicBool = AtomicBool::new(false);
+
+/// SMAP is on: kernel access to user pages needs `stac` first.
+pub fn smap_enabled() -> bool {
+    SMAP.load(Ordering::Relaxed)
+}
+
+/// EFER.NXE is set, so PTE bit 63 means no-execute instead of reserved.
+pub fn nx_enabled() -> bool {
+    NX.load(Ordering::Relaxed)
+}
+
+/// WP always; NX, SMEP and SMAP when the CPU has them. From here on a
+/// kernel write to a read-only page, a jump into user memory or a user
+/// pointer dereferenced outside `mm::uaccess` faults instead of silently
+/// going through.
+fn init_cpu_features() {
+    // CPUID leaf 7, subleaf 0, EBX: FSGSBASE=0, SMEP=7, SMAP=20
+    let cpuid7 = cpuid(7, 0);
+    let has_fsgsbase = cpuid7.ebx & (1 << 0) != 0;
+    let has_smep = cpuid7.ebx & (1 << 7) != 0;
+    let has_smap = cpuid7.ebx & (1 << 20) != 0;
+    // CPUID 0x80000001, EDX bit 20 = NX
+    let has_nx = cpuid(0x8000_0001, 0).edx & (1 << 20) != 0;
+
     unsafe {
-        // CPUID leaf 7, subleaf 0, EBX: FSGSBASE=0, SMEP=7, SMAP=20
-        let cpuid7 = cpuid(7, 0);
-        let has_fsgsbase = cpuid7.ebx & (1 << 0) != 0;
-        let has_smep    = cpuid7.ebx & (1 << 7) != 0;
-        let has_smap    = cpuid7.ebx & (1 << 20) != 0;
-        // CPUID 0x80000001, EDX bit 20 = NX
-        let has_nxe = cpuid(0x8000_0001, 0).edx & (1 << 20) != 0;
-
-        let mut cr4 = read_cr4();
-        cr4 |= CR4_PGE;
-        if has_smep    { cr4 |= CR4_SMEP; }
-        if has_smap    { cr4 |= CR4_SMAP; }
-        if has_fsgsbase { cr4 |= CR4_FSGSBASE; }
+        let mut cr4 = read_cr4() | CR4_PGE;
+        if has_smep {
+            cr4 |= CR4_SMEP;
+        }
+        if has_smap {
+            cr4 |= CR4_SMAP;
+        }
+        if has_fsgsbase {
+            cr4 |= CR4_FSGSBASE;
+        }
         write_cr4(cr4);
 
-        let cr0 = read_cr0();
-        write_cr0(cr0 | CR0_WP);
+        write_cr0(read_cr0() | CR0_WP);
 
-        if has_nxe {
-            let efer = rdmsr(MSR_EFER);
-            wrmsr(MSR_EFER, efer | EFER_NXE);
+        if has_nx {
+            wrmsr(MSR_EFER, rdmsr(MSR_EFER) | EFER_NXE);
         }
     }
+    SMAP.store(has_smap, Ordering::Relaxed);
+    NX.store(has_nx, Ordering::Relaxed);
 
-    log::debug!("CPU features: WP enabled; SMEP/SMAP/FSGSBASE/NXE if supported");
+    let on = |b: bool| if b { "on" } else { "unsupported" };
+    log::info!(
+        "CPU protection: WP on, NX {}, SMEP {}, SMAP {}",
+        on(has_nx),
+        on(has_smep),
+        on(has_smap)
+    );
 }
 
 pub fn udelay(us: u64) {
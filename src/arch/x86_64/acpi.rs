@@ -0,0 +1,278 @@
+//! Минимальный разбор ACPI: RSDP (от Limine) → RSDT/XSDT → поиск таблицы по
+//! сигнатуре. Таблицы читаются через HHDM и никогда не освобождаются.
+use crate::arch::x86_64::limine::{hhdm_offset, phys_to_virt, RSDP_REQUEST};
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_addr: u32,
+    // ACPI 2.0+
+    length: u32,
+    xsdt_addr: u64,
+    ext_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+fn checksum_ok(virt: u64, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(virt as *const u8, len) };
+    bytes.iter().fold(0u8, |a, &b| a.wrapping_add(b)) == 0
+}
+
+fn rsdp_virt() -> Option<u64> {
+    let resp = RSDP_REQUEST.response.load(Ordering::Relaxed);
+    if resp.is_null() {
+        return None;
+    }
+    let addr = unsafe { (*resp).address };
+    if addr == 0 {
+        return None;
+    }
+    // Base revision 0 hands out an HHDM pointer, newer revisions a physical one
+    Some(if addr >= hhdm_offset() { addr } else { phys_to_virt(addr) })
+}
+
+/// Virtual address of the first valid table with signature `sig`
+/// (header included).
+pub fn find_table(sig: &[u8; 4]) -> Option<u64> {
+    let rsdp_va = rsdp_virt()?;
+    let rsdp = unsafe { &*(rsdp_va as *const Rsdp) };
+    if &rsdp.signature != b"RSD PTR " || !checksum_ok(rsdp_va, 20) {
+        log::warn!("ACPI: bad RSDP");
+        return None;
+    }
+
+    // XSDT holds 64-bit pointers, RSDT 32-bit ones
+    let (root_phys, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_addr != 0 {
+        (rsdp.xsdt_addr, 8)
+    } else {
+        (rsdp.rsdt_addr as u64, 4)
+    };
+    let root_va = phys_to_virt(root_phys);
+    let root = unsafe { core::ptr::read_unaligned(root_va as *const SdtHeader) };
+    if !checksum_ok(root_va, root.length as usize) {
+        log::warn!("ACPI: bad root table checksum");
+        return None;
+    }
+
+    let hdr_len = core::mem::size_of::<SdtHeader>();
+    let count = (root.length as usize - hdr_len) / entry_size;
+    for i in 0..count {
+        let slot = root_va + (hdr_len + i * entry_size) as u64;
+        let phys = unsafe {
+            if entry_size == 8 {
+                core::ptr::read_unaligned(slot as *const u64)
+            } else {
+                core::ptr::read_unaligned(slot as *const u32) as u64
+            }
+        };
+        let va = phys_to_virt(phys);
+        let hdr = unsafe { core::ptr::read_unaligned(va as *const SdtHeader) };
+        if &hdr.signature == sig && checksum_ok(va, hdr.length as usize) {
+            return Some(va);
+        }
+    }
+    None
+}
+
+// ─── FADT ────────────────────────────────────────────────────────────────────
+
+// FADT field offsets
+const FADT_DSDT: usize = 40;
+const FADT_SMI_CMD: usize = 48;
+const FADT_ACPI_ENABLE: usize = 52;
+const FADT_PM1A_CNT: usize = 64;
+const FADT_PM1B_CNT: usize = 68;
+const FADT_FLAGS: usize = 112;
+const FADT_RESET_REG: usize = 116;
+const FADT_RESET_VALUE: usize = 128;
+const FADT_X_DSDT: usize = 140;
+const FADT_X_PM1A_CNT: usize = 172;
+const FADT_X_PM1B_CNT: usize = 184;
+const GAS_LEN: usize = 12;
+
+/// Flags: RESET_REG is there to be used.
+const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+
+/// Address spaces of a Generic Address Structure.
+pub const SPACE_MEMORY: u8 = 0;
+pub const SPACE_IO: u8 = 1;
+pub const SPACE_PCI_CONFIG: u8 = 2;
+
+/// A Generic Address Structure: a register in memory, I/O or PCI
+/// configuration space.
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub space: u8,
+    pub address: u64,
+}
+
+/// What shutting down and resetting take from the FADT.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    /// Port that `acpi_enable` is written to for the switch out of legacy
+    /// mode; 0 on systems that are always in ACPI mode.
+    pub smi_cmd: u16,
+    pub acpi_enable: u8,
+    /// PM1 control registers: I/O ports, 0 if absent.
+    pub pm1a_cnt: u16,
+    pub pm1b_cnt: u16,
+    /// The reset register and what to write to it.
+    pub reset: Option<(GenericAddress, u8)>,
+    /// The DSDT, virtual address.
+    pub dsdt: Option<u64>,
+}
+
+/// The FADT (signature "FACP"), if the firmware has one.
+pub fn fadt() -> Option<Fadt> {
+    let va = find_table(b"FACP")?;
+    let hdr = unsafe { core::ptr::read_unaligned(va as *const SdtHeader) };
+    let len = hdr.length as usize;
+    let has = |off: usize, size: usize| off + size <= len;
+    let rd8 = |off: usize| unsafe { *((va as usize + off) as *const u8) };
+    let rd32 = |off: usize| unsafe { core::ptr::read_unaligned((va as usize + off) as *const u32) };
+    let rd64 = |off: usize| unsafe { core::ptr::read_unaligned((va as usize + off) as *const u64) };
+    let gas = |off: usize| {
+        has(off, GAS_LEN).then(|| GenericAddress {
+            space: rd8(off),
+            address: rd64(off + 4),
+        })
+    };
+    // The 64-bit X_ fields win over the old ones where they are filled in
+    let pm1_cnt = |x: usize, old: usize| match gas(x) {
+        Some(g) if g.space == SPACE_IO && g.address != 0 => g.address as u16,
+        _ => rd32(old) as u16,
+    };
+
+    let reset = if has(FADT_RESET_VALUE, 1) && rd32(FADT_FLAGS) & FLAG_RESET_REG_SUP != 0 {
+        gas(FADT_RESET_REG)
+            .filter(|g| g.address != 0)
+            .map(|g| (g, rd8(FADT_RESET_VALUE)))
+    } else {
+        None
+    };
+    let dsdt_phys = match has(FADT_X_DSDT, 8).then(|| rd64(FADT_X_DSDT)) {
+        Some(p) if p != 0 => p,
+        _ => rd32(FADT_DSDT) as u64,
+    };
+    let dsdt = (dsdt_phys != 0).then(|| phys_to_virt(dsdt_phys)).filter(|&d| {
+        let hdr = unsafe { core::ptr::read_unaligned(d as *const SdtHeader) };
+        &hdr.signature == b"DSDT"
+    });
+
+    Some(Fadt {
+        smi_cmd: rd32(FADT_SMI_CMD) as u16,
+        acpi_enable: rd8(FADT_ACPI_ENABLE),
+        pm1a_cnt: pm1_cnt(FADT_X_PM1A_CNT, FADT_PM1A_CNT),
+        pm1b_cnt: if has(FADT_PM1B_CNT, 4) { pm1_cnt(FADT_X_PM1B_CNT, FADT_PM1B_CNT) } else { 0 },
+        reset,
+        dsdt,
+    })
+}
+
+// ─── MCFG ────────────────────────────────────────────────────────────────────
+
+/// Allocation entries start after the header and 8 reserved bytes.
+const MCFG_ENTRIES: usize = 44;
+const MCFG_ENTRY_LEN: usize = 16;
+
+/// One MCFG allocation: where a PCI segment's buses have their ECAM
+/// (memory-mapped configuration space), 1 MiB per bus from `bus_start`.
+#[derive(Debug, Clone, Copy)]
+pub struct McfgEntry {
+    /// Physical address of bus 0's space, even when `bus_start` isn't 0.
+    pub base: u64,
+    pub segment: u16,
+    pub bus_start: u8,
+    pub bus_end: u8,
+}
+
+/// The ECAM regions of the MCFG table; none on machines without PCIe.
+pub fn mcfg() -> Vec<McfgEntry> {
+    let Some(va) = find_table(b"MCFG") else {
+        return Vec::new();
+    };
+    let hdr = unsafe { core::ptr::read_unaligned(va as *const SdtHeader) };
+    let len = hdr.length as usize;
+    (MCFG_ENTRIES..)
+        .step_by(MCFG_ENTRY_LEN)
+        .take_while(|off| off + MCFG_ENTRY_LEN <= len)
+        .map(|off| {
+            let at = va + off as u64;
+            unsafe {
+                McfgEntry {
+                    base: core::ptr::read_unaligned(at as *const u64),
+                    segment: core::ptr::read_unaligned((at + 8) as *const u16),
+                    bus_start: *((at + 10) as *const u8),
+                    bus_end: *((at + 11) as *const u8),
+                }
+            }
+        })
+        .collect()
+}
+
+// ─── \_S5 ────────────────────────────────────────────────────────────────────
+
+const AML_NAME_OP: u8 = 0x08;
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_BYTE_PREFIX: u8 = 0x0A;
+const AML_WORD_PREFIX: u8 = 0x0B;
+
+/// SLP_TYPa and SLP_TYPb of the S5 (soft-off) state: the first two
+/// elements of the `\_S5_` package in the DSDT at `dsdt`.
+///
+/// No AML interpreter: the DSDT is scanned for `Name (_S5, Package …)` and
+/// the package's integer constants are read as they are. A firmware that
+/// computes them in a method isn't covered.
+pub fn s5_sleep_types(dsdt: u64) -> Option<(u8, u8)> {
+    let hdr = unsafe { core::ptr::read_unaligned(dsdt as *const SdtHeader) };
+    let aml = unsafe { core::slice::from_raw_parts(dsdt as *const u8, hdr.length as usize) };
+    let hdr_len = core::mem::size_of::<SdtHeader>();
+    let at = (hdr_len..aml.len().saturating_sub(4)).find(|&i| {
+        &aml[i..i + 4] == b"_S5_"
+            && (aml[i - 1] == AML_NAME_OP || (aml[i - 1] == b'\\' && aml[i - 2] == AML_NAME_OP))
+    })?;
+    let mut p = at + 4;
+    if *aml.get(p)? != AML_PACKAGE_OP {
+        return None;
+    }
+    // PkgLength: bits 7–6 of the lead byte count the bytes that follow it
+    p += 2 + (*aml.get(p + 1)? >> 6) as usize;
+    p += 1; // NumElements
+    let mut int = || -> Option<u8> {
+        let op = *aml.get(p)?;
+        let (v, len) = match op {
+            AML_ZERO_OP => (0, 1),
+            AML_ONE_OP => (1, 1),
+            AML_BYTE_PREFIX => (*aml.get(p + 1)?, 2),
+            // The field is 3 bits wide: the high byte is 0 anyway
+            AML_WORD_PREFIX => (*aml.get(p + 1)?, 3),
+            _ => return None,
+        };
+        p += len;
+        Some(v)
+    };
+    let a = int()?;
+    let b = int()?;
+    Some((a, b))
+}
@@ -0,0 +1,416 @@
+//! Local APIC + IOAPIC. When both are present the legacy 8259 is masked and
+//! ISA IRQs are routed through the IOAPIC to the same vectors the PIC used
+//! (32 + irq), so `idt::irq_dispatch` stays unchanged apart from EOI.
+//! Without an APIC (or without a MADT) the remapped PIC keeps working.
+use crate::arch::x86_64::acpi::{self, SdtHeader};
+use crate::arch::x86_64::io::{
+    cpuid, mmio_read32, mmio_write32, rdmsr, wrmsr, MSR_APIC_BASE, MSR_TSC_DEADLINE,
+};
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::arch::x86_64::pic;
+use crate::sync::spinlock::SpinLock;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+// LAPIC register offsets
+const LAPIC_ID: u64 = 0x20;
+const LAPIC_VERSION: u64 = 0x30;
+const LAPIC_TPR: u64 = 0x80;
+const LAPIC_EOI: u64 = 0xB0;
+const LAPIC_SVR: u64 = 0xF0;
+const LAPIC_LVT_TIMER: u64 = 0x320;
+const LAPIC_LVT_LINT0: u64 = 0x350;
+const LAPIC_LVT_LINT1: u64 = 0x360;
+const LAPIC_TIMER_INITIAL: u64 = 0x380;
+const LAPIC_TIMER_CURRENT: u64 = 0x390;
+const LAPIC_TIMER_DIVIDE: u64 = 0x3E0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_NMI: u32 = 0b100 << 8;
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+/// Divide configuration for the LAPIC timer: bus clock / 16.
+const TIMER_DIVIDE_16: u32 = 0b0011;
+
+// IOAPIC registers (indirect through IOREGSEL / IOWIN)
+const IOAPIC_REGSEL: u64 = 0x00;
+const IOAPIC_WIN: u64 = 0x10;
+const IOAPIC_REG_VER: u32 = 0x01;
+const IOAPIC_REDTBL: u32 = 0x10;
+
+const REDIR_ACTIVE_LOW: u64 = 1 << 13;
+const REDIR_LEVEL: u64 = 1 << 15;
+const REDIR_MASKED: u64 = 1 << 16;
+
+/// ISA IRQs the kernel handles: PIT, keyboard, COM1.
+const ROUTED_IRQS: [u8; 3] = [0, 1, 4];
+
+/// HHDM address of the LAPIC registers; 0 while the PIC is in charge.
+static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy)]
+struct IoApic {
+    id: u8,
+    base: u64,
+    gsi_base: u32,
+    redirs: u32,
+}
+
+/// ISA interrupt source override from the MADT.
+#[derive(Debug, Clone, Copy)]
+struct Override {
+    irq: u8,
+    gsi: u32,
+    flags: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LapicNmi {
+    /// 0xFF = all processors
+    acpi_id: u8,
+    flags: u16,
+    lint: u8,
+}
+
+struct Madt {
+    lapic_phys: u64,
+    cpus: Vec<(u8, u8)>, // (acpi processor id, apic id)
+    ioapics: Vec<IoApic>,
+    overrides: Vec<Override>,
+    nmis: Vec<LapicNmi>,
+}
+
+static IOAPICS: SpinLock<Vec<IoApic>> = SpinLock::new(Vec::new());
+
+// ─── MADT ────────────────────────────────────────────────────────────────────
+
+fn parse_madt() -> Option<Madt> {
+    let va = acpi::find_table(b"APIC")?;
+    let hdr = unsafe { core::ptr::read_unaligned(va as *const SdtHeader) };
+    let len = hdr.length as u64;
+    let hdr_len = core::mem::size_of::<SdtHeader>() as u64;
+
+    let rd8 = |off: u64| unsafe { *((va + off) as *const u8) };
+    let rd16 = |off: u64| unsafe { core::ptr::read_unaligned((va + off) as *const u16) };
+    let rd32 = |off: u64| unsafe { core::ptr::read_unaligned((va + off) as *const u32) };
+    let rd64 = |off: u64| unsafe { core::ptr::read_unaligned((va + off) as *const u64) };
+
+    let mut madt = Madt {
+        lapic_phys: rd32(hdr_len) as u64,
+        cpus: Vec::new(),
+        ioapics: Vec::new(),
+        overrides: Vec::new(),
+        nmis: Vec::new(),
+    };
+
+    // lapic address (u32) + flags (u32), then variable-length entries
+    let mut off = hdr_len + 8;
+    while off + 2 <= len {
+        let kind = rd8(off);
+        let elen = rd8(off + 1) as u64;
+        if elen < 2 || off + elen > len {
+            break;
+        }
+        match kind {
+            0 if rd32(off + 4) & 1 != 0 => madt.cpus.push((rd8(off + 2), rd8(off + 3))),
+            1 => madt.ioapics.push(IoApic {
+                id: rd8(off + 2),
+                base: rd32(off + 4) as u64,
+                gsi_base: rd32(off + 8),
+                redirs: 0,
+            }),
+            2 => madt.overrides.push(Override {
+                irq: rd8(off + 3),
+                gsi: rd32(off + 4),
+                flags: rd16(off + 8),
+            }),
+            4 => madt.nmis.push(LapicNmi {
+                acpi_id: rd8(off + 2),
+                flags: rd16(off + 3),
+                lint: rd8(off + 5),
+            }),
+            5 => madt.lapic_phys = rd64(off + 4),
+            _ => {}
+        }
+        off += elen;
+    }
+    Some(madt)
+}
+
+// ─── Local APIC ──────────────────────────────────────────────────────────────
+
+fn lapic_read(reg: u64) -> u32 {
+    let base = LAPIC_BASE.load(Ordering::Relaxed);
+    unsafe { mmio_read32(base + reg) }
+}
+
+fn lapic_write(reg: u64, val: u32) {
+    let base = LAPIC_BASE.load(Ordering::Relaxed);
+    unsafe { mmio_write32(base + reg, val) }
+}
+
+pub fn is_enabled() -> bool {
+    LAPIC_BASE.load(Ordering::Relaxed) != 0
+}
+
+pub fn lapic_id() -> u8 {
+    if !is_enabled() {
+        return 0;
+    }
+    (lapic_read(LAPIC_ID) >> 24) as u8
+}
+
+/// Acknowledge a hardware IRQ on whichever controller delivered it.
+pub fn send_eoi(irq: u8) {
+    if is_enabled() {
+        lapic_write(LAPIC_EOI, 0);
+    } else {
+        pic::send_eoi(irq);
+    }
+}
+
+fn init_lapic(madt: &Madt) {
+    let msr = unsafe { rdmsr(MSR_APIC_BASE) };
+    let phys = if madt.lapic_phys != 0 {
+        madt.lapic_phys
+    } else {
+        msr & 0x000F_FFFF_FFFF_F000
+    };
+    unsafe { wrmsr(MSR_APIC_BASE, (msr & !0x000F_FFFF_FFFF_F000) | phys | APIC_BASE_ENABLE) };
+    // HHDM covers the low 4 GiB, MMIO holes included (base revision 0)
+    LAPIC_BASE.store(phys_to_virt(phys), Ordering::Relaxed);
+
+    let id = lapic_id();
+    lapic_write(LAPIC_TPR, 0);
+
+    // LINT0 is ExtINT from the 8259 in virtual-wire mode — masked, the
+    // IOAPIC delivers ISA IRQs now. NMI wiring comes from the MADT.
+    lapic_write(LAPIC_LVT_LINT0, LVT_MASKED);
+    lapic_write(LAPIC_LVT_LINT1, LVT_MASKED);
+    for nmi in &madt.nmis {
+        let ours = nmi.acpi_id == 0xFF
+            || madt.cpus.iter().any(|&(acpi, apic)| acpi == nmi.acpi_id && apic == id);
+        if !ours {
+            continue;
+        }
+        let mut lvt = LVT_NMI;
+        if nmi.flags & 0b11 == 0b11 {
+            lvt |= 1 << 13; // active low
+        }
+        if (nmi.flags >> 2) & 0b11 == 0b11 {
+            lvt |= 1 << 15; // level
+        }
+        let reg = if nmi.lint == 0 { LAPIC_LVT_LINT0 } else { LAPIC_LVT_LINT1 };
+        lapic_write(reg, lvt);
+    }
+
+    lapic_write(LAPIC_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+    log::info!(
+        "LAPIC: id={} version={:#x} at phys={:#x}",
+        id,
+        lapic_read(LAPIC_VERSION) & 0xFF,
+        phys
+    );
+}
+
+// ─── LAPIC timer ─────────────────────────────────────────────────────────────
+
+/// How the LAPIC timer is programmed; both modes fire once per arming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// IA32_TSC_DEADLINE holds the absolute TSC value to fire at.
+    TscDeadline,
+    /// Counts down from an initial count at `hz` (bus clock / 16).
+    OneShot { hz: u64 },
+}
+
+/// Set the LAPIC timer up to deliver `vector`, disarmed. None without a
+/// LAPIC. The one-shot rate is measured against the TSC (`tsc_hz`) over
+/// 10 ms, so the TSC must be calibrated already.
+pub fn timer_init(vector: u8, tsc_hz: u64) -> Option<TimerMode> {
+    if !is_enabled() || tsc_hz == 0 {
+        return None;
+    }
+    // CPUID.1:ECX bit 24 — TSC-deadline mode
+    if cpuid(1, 0).ecx & (1 << 24) != 0 {
+        lapic_write(LAPIC_LVT_TIMER, LVT_TIMER_TSC_DEADLINE | vector as u32);
+        return Some(TimerMode::TscDeadline);
+    }
+
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_16);
+    lapic_write(LAPIC_LVT_TIMER, LVT_MASKED | vector as u32);
+    let t0 = super::timer::rdtsc();
+    lapic_write(LAPIC_TIMER_INITIAL, u32::MAX);
+    while super::timer::rdtsc() - t0 < tsc_hz / 100 {
+        core::hint::spin_loop();
+    }
+    let elapsed = u32::MAX - lapic_read(LAPIC_TIMER_CURRENT);
+    lapic_write(LAPIC_TIMER_INITIAL, 0);
+    lapic_write(LAPIC_LVT_TIMER, vector as u32);
+    Some(TimerMode::OneShot {
+        hz: elapsed as u64 * 100,
+    })
+}
+
+/// Arm the timer to fire once `ns` nanoseconds from now (TSC `now_tsc`).
+pub fn timer_arm(mode: TimerMode, now_tsc: u64, ns: u64, tsc_hz: u64) {
+    match mode {
+        TimerMode::TscDeadline => {
+            let delta = (ns as u128 * tsc_hz as u128 / 1_000_000_000) as u64;
+            unsafe { wrmsr(MSR_TSC_DEADLINE, now_tsc + delta.max(1)) };
+        }
+        TimerMode::OneShot { hz } => {
+            let count = (ns as u128 * hz as u128 / 1_000_000_000).clamp(1, u32::MAX as u128);
+            lapic_write(LAPIC_TIMER_INITIAL, count as u32);
+        }
+    }
+}
+
+/// Cancel a pending arming.
+pub fn timer_disarm(mode: TimerMode) {
+    match mode {
+        TimerMode::TscDeadline => unsafe { wrmsr(MSR_TSC_DEADLINE, 0) },
+        TimerMode::OneShot { .. } => lapic_write(LAPIC_TIMER_INITIAL, 0),
+    }
+}
+
+// ─── IOAPIC ──────────────────────────────────────────────────────────────────
+
+fn ioapic_read(io: &IoApic, reg: u32) -> u32 {
+    unsafe {
+        mmio_write32(io.base + IOAPIC_REGSEL, reg);
+        mmio_read32(io.base + IOAPIC_WIN)
+    }
+}
+
+fn ioapic_write(io: &IoApic, reg: u32, val: u32) {
+    unsafe {
+        mmio_write32(io.base + IOAPIC_REGSEL, reg);
+        mmio_write32(io.base + IOAPIC_WIN, val);
+    }
+}
+
+fn set_redirect(io: &IoApic, pin: u32, entry: u64) {
+    let reg = IOAPIC_REDTBL + pin * 2;
+    // Mask first so a half-written entry never fires
+    ioapic_write(io, reg, REDIR_MASKED as u32);
+    ioapic_write(io, reg + 1, (entry >> 32) as u32);
+    ioapic_write(io, reg, entry as u32);
+}
+
+fn ioapic_for(gsi: u32) -> Option<IoApic> {
+    IOAPICS
+        .lock()
+        .iter()
+        .find(|io| gsi >= io.gsi_base && gsi < io.gsi_base + io.redirs)
+        .copied()
+}
+
+/// ISA IRQ → (GSI, redirection flags) honouring the MADT overrides.
+/// ISA defaults are edge-triggered, active high.
+fn isa_route(madt: &Madt, irq: u8) -> (u32, u64) {
+    match madt.overrides.iter().find(|o| o.irq == irq) {
+        Some(o) => {
+            let mut flags = 0;
+            if o.flags & 0b11 == 0b11 {
+                flags |= REDIR_ACTIVE_LOW;
+            }
+            if (o.flags >> 2) & 0b11 == 0b11 {
+                flags |= REDIR_LEVEL;
+            }
+            (o.gsi, flags)
+        }
+        None => (irq as u32, 0),
+    }
+}
+
+/// Unmask ISA `irq` on the IOAPIC, delivered to this CPU as vector 32 + irq.
+fn route_isa_irq(madt: &Madt, irq: u8, dest: u8) {
+    let (gsi, flags) = isa_route(madt, irq);
+    if let Some(io) = ioapic_for(gsi) {
+        let vector = (pic::IRQ_BASE_MASTER + irq) as u64;
+        set_redirect(&io, gsi - io.gsi_base, vector | flags | (dest as u64) << 56);
+        log::debug!("IOAPIC: IRQ {} -> GSI {} vector {:#x}", irq, gsi, vector);
+    }
+}
+
+/// Deliver interrupt line `irq` — ISA numbering, which is also what a PCI
+/// device's Interrupt Line register holds — as vector 32 + irq to this CPU,
+/// for a driver that found its device after `init`. There is no _PRT
+/// lookup: in APIC mode a PCI line is taken to be the GSI of the same
+/// number, which holds for QEMU's i440fx and firmware that sets up PCI
+/// interrupts through the ISA lines.
+pub fn enable_irq(irq: u8) {
+    if !is_enabled() {
+        pic::unmask_irq(irq);
+        if irq >= 8 {
+            pic::unmask_irq(2); // cascade
+        }
+        return;
+    }
+    if let Some(madt) = parse_madt() {
+        route_isa_irq(&madt, irq, lapic_id());
+    }
+}
+
+pub fn init() {
+    if crate::cmdline::options().noapic {
+        log::info!("APIC: disabled on cmdline, keeping 8259 PIC");
+        return;
+    }
+    // CPUID.1:EDX bit 9 — on-chip APIC
+    if cpuid(1, 0).edx & (1 << 9) == 0 {
+        log::info!("APIC: not present, keeping 8259 PIC");
+        return;
+    }
+    let Some(madt) = parse_madt() else {
+        log::info!("APIC: no MADT, keeping 8259 PIC");
+        return;
+    };
+    if madt.ioapics.is_empty() {
+        log::info!("APIC: no IOAPIC in MADT, keeping 8259 PIC");
+        return;
+    }
+
+    {
+        let mut ioapics = IOAPICS.lock();
+        for io in &madt.ioapics {
+            let mut io = *io;
+            io.base = phys_to_virt(io.base);
+            io.redirs = ((ioapic_read(&io, IOAPIC_REG_VER) >> 16) & 0xFF) + 1;
+            // Start from a clean table: everything masked
+            for pin in 0..io.redirs {
+                set_redirect(&io, pin, REDIR_MASKED);
+            }
+            log::info!(
+                "IOAPIC: id={} GSI {}..{}",
+                io.id,
+                io.gsi_base,
+                io.gsi_base + io.redirs - 1
+            );
+            ioapics.push(io);
+        }
+    }
+
+    for irq in ROUTED_IRQS {
+        let (gsi, _) = isa_route(&madt, irq);
+        if ioapic_for(gsi).is_none() {
+            log::warn!("IOAPIC: no controller for GSI {} (IRQ {}), keeping 8259 PIC", gsi, irq);
+            return;
+        }
+    }
+
+    // Interrupts are still off here (init runs before sti): swap controllers
+    pic::disable();
+    init_lapic(&madt);
+
+    let dest = lapic_id();
+    for irq in ROUTED_IRQS {
+        route_isa_irq(&madt, irq, dest);
+    }
+    log::info!("APIC mode enabled, {} CPU(s) in MADT", madt.cpus.len());
+}
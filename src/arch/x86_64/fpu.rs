@@ -0,0 +1,143 @@
+//! x87, SSE and AVX state: turned on at boot and kept per user task.
+//!
+//! The kernel itself is built soft-float and never touches these
+//! registers, so only ring 3 changes them, and they need no saving on
+//! entry to the kernel. Switching is eager: `schedule()` saves the state
+//! of a user task it switches away from and loads the next one's. Kernel
+//! tasks have none, which leaves the registers as the last user task had
+//! them.
+//!
+//! With XSAVE (and XCR0 set to x87 | SSE, plus AVX where the CPU has it)
+//! the area is as big as CPUID leaf 0xD says; without it, FXSAVE's 512
+//! bytes cover x87 and SSE.
+use super::io::{cpuid, read_cr0, read_cr4, write_cr0, write_cr4};
+use super::io::{CR0_EM, CR0_MP, CR0_NE, CR0_TS, CR4_OSFXSR, CR4_OSXMMEXCPT, CR4_OSXSAVE};
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+
+const FXSAVE_SIZE: usize = 512;
+/// Default control words: every exception masked, round to nearest.
+const FCW_DEFAULT: u16 = 0x037F;
+const MXCSR_DEFAULT: u32 = 0x1F80;
+
+static XSAVE: AtomicBool = AtomicBool::new(false);
+static AREA_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_SIZE);
+
+unsafe fn xsetbv(xcr: u32, val: u64) {
+    asm!(
+        "xsetbv",
+        in("ecx") xcr,
+        in("eax") val as u32,
+        in("edx") (val >> 32) as u32,
+        options(nomem, nostack)
+    );
+}
+
+/// Enable the FPU and SSE for ring 3, and XSAVE with AVX if there is one.
+pub fn init() {
+    let leaf1 = cpuid(1, 0);
+    let has_xsave = leaf1.ecx & (1 << 26) != 0;
+    let has_avx = leaf1.ecx & (1 << 28) != 0;
+
+    unsafe {
+        // Native x87 errors (#MF), no emulation, no lazy-switch trap
+        write_cr0((read_cr0() | CR0_MP | CR0_NE) & !(CR0_EM | CR0_TS));
+        let mut cr4 = read_cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT;
+        if has_xsave {
+            cr4 |= CR4_OSXSAVE;
+        }
+        write_cr4(cr4);
+    }
+
+    let mut xcr0 = XCR0_X87 | XCR0_SSE;
+    if has_xsave {
+        let supported = {
+            let r = cpuid(0xD, 0);
+            r.eax as u64 | (r.edx as u64) << 32
+        };
+        if has_avx && supported & XCR0_AVX != 0 {
+            xcr0 |= XCR0_AVX;
+        }
+        unsafe { xsetbv(0, xcr0) };
+        // EBX: the size the features now enabled in XCR0 need
+        AREA_SIZE.store(cpuid(0xD, 0).ebx as usize, Ordering::Relaxed);
+        XSAVE.store(true, Ordering::Relaxed);
+    }
+    unsafe { asm!("fninit", options(nomem, nostack)) };
+
+    log::info!(
+        "FPU: x87, SSE{}, saved with {} ({} bytes per task)",
+        if xcr0 & XCR0_AVX != 0 { ", AVX" } else { "" },
+        if has_xsave { "XSAVE" } else { "FXSAVE" },
+        AREA_SIZE.load(Ordering::Relaxed)
+    );
+}
+
+/// XSAVE needs its area 64-byte aligned.
+#[derive(Clone, Copy)]
+#[repr(C, align(64))]
+struct Chunk([u8; 64]);
+
+/// One task's saved FPU registers.
+#[derive(Clone)]
+pub struct FpuState {
+    area: Box<[Chunk]>,
+}
+
+impl FpuState {
+    /// The state a program starts with: registers clear, exceptions masked.
+    pub fn new() -> Self {
+        let chunks = AREA_SIZE.load(Ordering::Relaxed).div_ceil(64);
+        let mut area = alloc::vec![Chunk([0; 64]); chunks].into_boxed_slice();
+        // The legacy region the FXSAVE and XSAVE layouts share; an all-zero
+        // XSAVE header puts every other component in its initial state
+        let legacy = &mut area[0].0;
+        legacy[0..2].copy_from_slice(&FCW_DEFAULT.to_le_bytes());
+        legacy[24..28].copy_from_slice(&MXCSR_DEFAULT.to_le_bytes());
+        FpuState { area }
+    }
+
+    /// Copy the registers as they are now.
+    pub fn current() -> Self {
+        let mut s = Self::new();
+        s.save();
+        s
+    }
+
+    /// Store the registers here.
+    pub fn save(&mut self) {
+        let ptr = self.area.as_mut_ptr();
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!("xsave64 [{}]", in(reg) ptr, in("eax") u32::MAX, in("edx") u32::MAX,
+                    options(nostack));
+            } else {
+                asm!("fxsave64 [{}]", in(reg) ptr, options(nostack));
+            }
+        }
+    }
+
+    /// Load the registers from here.
+    pub fn restore(&self) {
+        let ptr = self.area.as_ptr();
+        unsafe {
+            if XSAVE.load(Ordering::Relaxed) {
+                asm!("xrstor64 [{}]", in(reg) ptr, in("eax") u32::MAX, in("edx") u32::MAX,
+                    options(nostack, readonly));
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) ptr, options(nostack, readonly));
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,19 +1,21 @@
 use core::mem;
 
-use crate::arch::x86_64::io::{wrmsr, MSR_KERNEL_GS};
+use crate::arch::x86_64::io::{wrmsr, MSR_GS_BASE, MSR_KERNEL_GS};
 
 /// Per-CPU данные для syscall-пути (layout фиксирован — читается в asm)
 /// %gs:0  — зарезервировано
 /// %gs:8  — kernel_rsp: вершина стека ядра текущего процесса
 /// %gs:16 — user_rsp:   scratch-поле для сохранения RSP из userspace
+/// %gs:24 — cpu_id:     логический номер CPU (индекс для `sync::percpu!`)
 #[repr(C)]
 pub struct CpuLocal {
     _pad:           u64,  // offset  0
     pub kernel_rsp: u64,  // offset  8
     pub user_rsp:   u64,  // offset 16
+    pub cpu_id:     u64,  // offset 24
 }
 
-static mut CPU_LOCAL: CpuLocal = CpuLocal { _pad: 0, kernel_rsp: 0, user_rsp: 0 };
+static mut CPU_LOCAL: CpuLocal = CpuLocal { _pad: 0, kernel_rsp: 0, user_rsp: 0, cpu_id: 0 };
 
 pub const SEG_KERNEL_CODE: u16 = 0x08;
 pub const SEG_KERNEL_DATA: u16 = 0x10;
@@ -21,6 +23,29 @@ pub const SEG_USER_DATA: u16 = 0x18 | 3;
 pub const SEG_USER_CODE: u16 = 0x20 | 3;
 pub const SEG_TSS: u16 = 0x28;
 
+/// IST slots: the exceptions that get a stack of their own, because the
+/// one they came from may be the kernel stack that just overflowed, or a
+/// user RSP the syscall entry hasn't switched away from yet.
+pub const IST_DOUBLE_FAULT: u8 = 1;
+pub const IST_NMI: u8 = 2;
+pub const IST_MACHINE_CHECK: u8 = 3;
+pub const IST_PAGE_FAULT: u8 = 4;
+
+const IST_COUNT: usize = 4;
+const IST_STACK_SIZE: usize = 16 * 1024;
+
+#[repr(C, align(16))]
+struct IstStack([u8; IST_STACK_SIZE]);
+
+static mut IST_STACKS: [IstStack; IST_COUNT] =
+    [const { IstStack([0; IST_STACK_SIZE]) }; IST_COUNT];
+
+/// Whether `addr` is on one of the IST stacks.
+pub fn on_ist_stack(addr: u64) -> bool {
+    let start = core::ptr::addr_of!(IST_STACKS) as u64;
+    (start..start + (IST_COUNT * IST_STACK_SIZE) as u64).contains(&addr)
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C, packed(4))]
 pub struct Tss {
@@ -177,18 +202,37 @@ impl CpuGdt {
     }
 }
 
-const MAX_CPUS: usize = 256;
+pub const MAX_CPUS: usize = 256;
 
 static mut CPU_GDTS: [CpuGdt; 1] = [CpuGdt::new()];
 
 pub fn init_bsp(kernel_stack_top: u64) {
     unsafe {
+        let base = core::ptr::addr_of!(IST_STACKS) as u64;
+        for i in 0..IST_COUNT {
+            CPU_GDTS[0].tss.ist[i] = base + ((i + 1) * IST_STACK_SIZE) as u64;
+        }
         CPU_GDTS[0].set_kernel_stack(kernel_stack_top);
         // Инициализируем KERNEL_GS_BASE для корректной работы swapgs в syscall_entry.
         // syscall_entry использует %gs:8 как стек ядра и %gs:16 как scratch для user RSP.
         CPU_LOCAL.kernel_rsp = kernel_stack_top;
-        wrmsr(MSR_KERNEL_GS, &CPU_LOCAL as *const CpuLocal as u64);
+        CPU_LOCAL.cpu_id = 0;
+        // GS_BASE тоже указывает на CPU_LOCAL: код ядра (kernel threads, IRQ из
+        // ring 0) читает %gs:24 без swapgs, а ring 3 в GS пока ничего не хранит.
+        let local = &CPU_LOCAL as *const CpuLocal as u64;
+        wrmsr(MSR_GS_BASE, local);
+        wrmsr(MSR_KERNEL_GS, local);
+    }
+}
+
+/// Logical id of the CPU executing this code. Valid after `init_bsp`.
+#[inline(always)]
+pub fn cpu_id() -> usize {
+    let id: u64;
+    unsafe {
+        core::arch::asm!("mov %gs:24, {}", out(reg) id, options(att_syntax, nostack, readonly, preserves_flags));
     }
+    id as usize
 }
 
 pub fn current_tss() -> &'static mut Tss {
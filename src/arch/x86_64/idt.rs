@@ -89,7 +89,7 @@ impl Idt {
 
 static mut IDT: Idt = Idt::new();
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[repr(C)]
 pub struct InterruptFrame {
     pub r15: u64,
@@ -152,6 +152,12 @@ macro_rules! naked_isr_err {
 #[unsafe(naked)]
 unsafe extern "C" fn isr_common() {
     core::arch::naked_asm!(
+        // Из ring 3 — переключаемся на GS ядра (CS лежит над vector/error_code/RIP)
+        "testb $3, 24(%rsp)",
+        "jz 1f",
+        "swapgs",
+        "1:",
+
         "push %rax",
         "push %rbx",
         "push %rcx",
@@ -168,6 +174,10 @@ unsafe extern "C" fn isr_common() {
         "push %r14",
         "push %r15",
 
+        "mov %rsp, %rdi",
+        "call {leave_ist}",
+        "mov %rax, %rsp",
+
         "mov %rsp, %rdi",
         "and $-16, %rsp",
         "sub $8, %rsp",
@@ -191,17 +201,60 @@ unsafe extern "C" fn isr_common() {
         "pop %rax",
 
         "add $16, %rsp",
+
+        "testb $3, 8(%rsp)",
+        "jz 2f",
+        "swapgs",
+        "2:",
         "iretq",
 
         dispatch = sym interrupt_dispatch,
+        leave_ist = sym leave_ist,
         options(att_syntax)
     );
 }
 
+/// Where the handler of the interrupt whose saved frame is at `frame`
+/// runs. An exception taken on an IST stack, #DF aside, moves its frame to
+/// the stack the CPU would have used without IST if that one is sound: the
+/// handler may sleep (a page fault reading a file in) or fault again, and
+/// either would reuse the IST stack under it. Anything else stays put.
+///
+/// Called before any other kernel code, so it must not fault itself.
+extern "C" fn leave_ist(frame: *mut InterruptFrame) -> *mut InterruptFrame {
+    if !gdt::on_ist_stack(frame as u64) {
+        return frame;
+    }
+    let f = unsafe { &*frame };
+    if f.vector == 8 {
+        return frame;
+    }
+    let size = core::mem::size_of::<InterruptFrame>() as u64;
+    let top = gdt::current_tss().rsp[0];
+    let target = if f.cs & 3 == 3 {
+        top - size
+    } else if f.rsp <= top && f.rsp >= top - crate::mm::kstack::STACK_SIZE as u64 + size + 16 {
+        // Kept 16-aligned, as isr_common expects the frame to be
+        (f.rsp - size) & !15
+    } else {
+        // A corrupt RSP, or one off the task's stack: stay on IST
+        return frame;
+    };
+    let target = target as *mut InterruptFrame;
+    unsafe { core::ptr::copy_nonoverlapping(frame, target, 1) };
+    target
+}
+
 #[no_mangle]
 extern "C" fn interrupt_dispatch(frame: &mut InterruptFrame) {
     let vector = frame.vector as u8;
 
+    // Interrupts keep RFLAGS.AC: user mode could otherwise switch SMAP off
+    // for the kernel. iretq restores the interrupted value.
+    if super::smap_enabled() {
+        unsafe { super::io::clac() };
+    }
+
     match vector {
         0 => exc_divide_error(frame),
         1 => exc_debug(frame),
@@ -224,66 +277,112 @@ extern "C" fn interrupt_dispatch(frame: &mut InterruptFrame) {
 
         32..=47 => irq_dispatch(vector - 32, frame),
 
-        0x80 => crate::syscall::handle_int80(frame),
+        0x80 => crate::syscall::handle_syscall_frame(frame),
+
+        // LAPIC spurious interrupt: must not be acknowledged
+        apic::SPURIOUS_VECTOR => {}
 
         _ => {
             log::warn!("Spurious interrupt vector={:#x}", vector);
         }
     }
+
+    // Only code that had interrupts on may be switched away from here
+    if frame.rflags & super::io::RFLAGS_IF != 0 {
+        crate::proc::preempt();
+    }
+    // Returning to ring 3: run handlers for anything raised meanwhile
+    crate::proc::signal::deliver_pending(frame);
 }
 
-use crate::arch::x86_64::pic;
+use crate::arch::x86_64::{apic, gdt, pic};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+crate::sync::percpu! {
+    /// Per-CPU count of hardware IRQs delivered, indexed by IRQ line.
+    static IRQ_COUNTS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+}
+
+/// Total number of times `irq` fired, summed over all CPUs.
+pub fn irq_count(irq: u8) -> u64 {
+    IRQ_COUNTS
+        .iter()
+        .map(|c| c[irq as usize & 15].load(Ordering::Relaxed))
+        .sum()
+}
 
 fn irq_dispatch(irq: u8, frame: &mut InterruptFrame) {
-    if irq == 7 && pic::is_spurious_irq7() {
-        return;
-    }
-    if irq == 15 && pic::is_spurious_irq15() {
-        pic::send_eoi_master();
-        return;
+    if !apic::is_enabled() {
+        if irq == 7 && pic::is_spurious_irq7() {
+            return;
+        }
+        if irq == 15 && pic::is_spurious_irq15() {
+            pic::send_eoi_master();
+            return;
+        }
     }
 
-    // Send EOI before the handler so the PIC can deliver other IRQs
-    // (e.g. keyboard/IRQ1) even if the timer handler triggers a context
-    // switch that keeps IRQ0 "in service" in the PIC until idle resumes.
-    pic::send_eoi(irq);
+    // Send EOI before the handler so the controller can deliver other IRQs
+    // (e.g. keyboard/IRQ1) while the interrupted task is switched away
+    // from on the way out, which would keep IRQ0 "in service" until it
+    // runs again.
+    apic::send_eoi(irq);
+
+    IRQ_COUNTS.get()[irq as usize & 15].fetch_add(1, Ordering::Relaxed);
 
     match irq {
         0 => crate::arch::x86_64::timer::irq_timer(frame),
         1 => crate::drivers::keyboard::irq_keyboard(frame),
+        3 => crate::gdbstub::irq(frame),
         4 => crate::drivers::serial::irq_serial(frame),
+        14 | 15 => crate::drivers::ide::irq_ide(irq),
+        n if crate::drivers::e1000::owns_irq(n) => crate::drivers::e1000::irq_e1000(n),
         _ => log::debug!("Unhandled IRQ {}", irq),
     }
 }
 
 fn exc_divide_error(frame: &InterruptFrame) {
-    panic!("#DE Divide Error at RIP={:#018x}", frame.rip);
+    if frame.cs & 3 == 3 {
+        deliver_signal(frame, Signal::SIGFPE, "Divide Error", frame.rip);
+    } else {
+        crate::crash::set_frame(frame);
+        panic!("#DE Divide Error at RIP={:#018x}", frame.rip);
+    }
 }
 
-fn exc_debug(frame: &InterruptFrame) {
+fn exc_debug(frame: &mut InterruptFrame) {
+    if crate::gdbstub::debug(frame) {
+        return;
+    }
     log::trace!("#DB Debug exception at RIP={:#018x}", frame.rip);
 }
 
 fn exc_nmi(frame: &InterruptFrame) {
+    crate::crash::set_frame(frame);
     panic!("NMI at RIP={:#018x}", frame.rip);
 }
 
-fn exc_breakpoint(frame: &InterruptFrame) {
-    log::info!("#BP Breakpoint at RIP={:#018x}", frame.rip);
+fn exc_breakpoint(frame: &mut InterruptFrame) {
+    if frame.cs & 3 == 3 {
+        deliver_signal(frame, Signal::SIGTRAP, "Breakpoint", frame.rip);
+    } else if !crate::gdbstub::breakpoint(frame) {
+        log::info!("#BP Breakpoint at RIP={:#018x}", frame.rip);
+    }
 }
 
 fn exc_overflow(frame: &InterruptFrame) {
-    deliver_signal(frame, Signal::SIGSEGV, "Overflow");
+    deliver_signal(frame, Signal::SIGSEGV, "Overflow", frame.rip);
 }
 
 fn exc_bound_range(frame: &InterruptFrame) {
-    deliver_signal(frame, Signal::SIGSEGV, "BOUND Range Exceeded");
+    deliver_signal(frame, Signal::SIGSEGV, "BOUND Range Exceeded", frame.rip);
 }
 
 fn exc_invalid_opcode(frame: &InterruptFrame) {
     if frame.cs & 3 == 3 {
-        deliver_signal(frame, Signal::SIGILL, "Invalid Opcode");
+        deliver_signal(frame, Signal::SIGILL, "Invalid Opcode", frame.rip);
     } else {
+        crate::crash::set_frame(frame);
         panic!("#UD Invalid Opcode in kernel at RIP={:#018x}", frame.rip);
     }
 }
@@ -293,6 +392,16 @@ fn exc_device_not_available(frame: &InterruptFrame) {
 }
 
 fn exc_double_fault(frame: &InterruptFrame) {
+    let cr2: u64;
+    unsafe { asm!("mov %cr2, {}", out(reg) cr2, options(att_syntax)) };
+    crate::crash::set_frame(frame);
+    // Typically a page fault the CPU couldn't push onto an overflowed stack
+    if crate::mm::kstack::in_guard(frame.rsp) || crate::mm::kstack::in_guard(cr2) {
+        panic!(
+            "#DF kernel stack overflow: RSP={:#018x} addr={:#018x} RIP={:#018x}",
+            frame.rsp, cr2, frame.rip
+        );
+    }
     panic!(
         "#DF Double Fault! RSP={:#018x} RIP={:#018x} err={}",
         frame.rsp, frame.rip, frame.error_code
@@ -300,6 +409,7 @@ fn exc_double_fault(frame: &InterruptFrame) {
 }
 
 fn exc_invalid_tss(frame: &InterruptFrame) {
+    crate::crash::set_frame(frame);
     panic!(
         "#TS Invalid TSS error={:#x} at RIP={:#018x}",
         frame.error_code, frame.rip
@@ -308,8 +418,9 @@ fn exc_invalid_tss(frame: &InterruptFrame) {
 
 fn exc_segment_not_present(frame: &InterruptFrame) {
     if frame.cs & 3 == 3 {
-        deliver_signal(frame, Signal::SIGSEGV, "Segment Not Present");
+        deliver_signal(frame, Signal::SIGSEGV, "Segment Not Present", frame.rip);
     } else {
+        crate::crash::set_frame(frame);
         panic!(
             "#NP Segment Not Present error={:#x} at RIP={:#018x}",
             frame.error_code, frame.rip
@@ -318,16 +429,22 @@ fn exc_segment_not_present(frame: &InterruptFrame) {
 }
 
 fn exc_stack_segment_fault(frame: &InterruptFrame) {
-    panic!(
-        "#SS Stack Segment Fault error={:#x} at RIP={:#018x}",
-        frame.error_code, frame.rip
-    );
+    if frame.cs & 3 == 3 {
+        deliver_signal(frame, Signal::SIGBUS, "Stack Segment Fault", frame.rip);
+    } else {
+        crate::crash::set_frame(frame);
+        panic!(
+            "#SS Stack Segment Fault error={:#x} at RIP={:#018x}",
+            frame.error_code, frame.rip
+        );
+    }
 }
 
 fn exc_general_protection(frame: &InterruptFrame) {
     if frame.cs & 3 == 3 {
-        deliver_signal(frame, Signal::SIGSEGV, "General Protection Fault");
+        deliver_signal(frame, Signal::SIGSEGV, "General Protection Fault", frame.rip);
     } else {
+        crate::crash::set_frame(frame);
         panic!(
             "#GP General Protection Fault error={:#x} at RIP={:#018x} CS={:#x}",
             frame.error_code, frame.rip, frame.cs
@@ -357,6 +474,7 @@ fn exc_page_fault(frame: &InterruptFrame) {
     );
 
     if reserved {
+        crate::crash::set_frame(frame);
         panic!("#PF reserved bit violation addr={:#018x}", cr2);
     }
 
@@ -364,34 +482,50 @@ fn exc_page_fault(frame: &InterruptFrame) {
 
     if !handled {
         if user {
-            deliver_signal(frame, Signal::SIGSEGV, "Page Fault");
+            deliver_signal(frame, Signal::SIGSEGV, "Page Fault", cr2);
         } else {
+            // SMEP/SMAP faults: the kernel ran or touched user memory
+            // outside mm::uaccess
+            let what = match (cr2 < crate::mm::vmm::USER_SPACE_END, instruction) {
+                (true, true) => " (SMEP: kernel executed a user page)",
+                (true, false) if present => " (SMAP: kernel touched user memory)",
+                _ => "",
+            };
+            crate::crash::set_frame(frame);
+            if crate::mm::kstack::in_guard(cr2) {
+                panic!(
+                    "#PF kernel stack overflow: addr={:#018x} RSP={:#018x} RIP={:#018x}",
+                    cr2, frame.rsp, frame.rip
+                );
+            }
             panic!(
-                "#PF unhandled in kernel! addr={:#018x} err={:#x} RIP={:#018x}",
-                cr2, frame.error_code, frame.rip
+                "#PF unhandled in kernel! addr={:#018x} err={:#x} RIP={:#018x}{}",
+                cr2, frame.error_code, frame.rip, what
             );
         }
     }
 }
 
 fn exc_x87_fpu(frame: &InterruptFrame) {
-    deliver_signal(frame, Signal::SIGFPE, "x87 FPU Error");
+    deliver_signal(frame, Signal::SIGFPE, "x87 FPU Error", frame.rip);
 }
 
 fn exc_alignment_check(frame: &InterruptFrame) {
     if frame.cs & 3 == 3 {
-        deliver_signal(frame, Signal::SIGBUS, "Alignment Check");
+        deliver_signal(frame, Signal::SIGBUS, "Alignment Check", frame.rip);
     } else {
+        crate::crash::set_frame(frame);
         panic!("#AC Alignment Check in kernel at RIP={:#018x}", frame.rip);
     }
 }
 
 fn exc_machine_check(frame: &InterruptFrame) {
+    crate::crash::set_frame(frame);
     panic!("#MC Machine Check Exception at RIP={:#018x}", frame.rip);
 }
 
 fn exc_simd(frame: &InterruptFrame) {
-    deliver_signal(frame, Signal::SIGFPE, "SIMD Floating-Point Exception");
+    deliver_signal(frame, Signal::SIGFPE, "SIMD Floating-Point Exception", frame.rip);
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -404,14 +538,24 @@ pub enum Signal {
     SIGTRAP = 5,
 }
 
-fn deliver_signal(frame: &InterruptFrame, sig: Signal, reason: &str) {
+/// Queue a fault signal for the current process; it is acted on when
+/// interrupt_dispatch returns. Faults in kernel mode are fatal.
+fn deliver_signal(frame: &InterruptFrame, sig: Signal, reason: &str, addr: u64) {
+    if frame.cs & 3 != 3 {
+        crate::crash::set_frame(frame);
+        panic!(
+            "{} in kernel at RIP={:#018x} (signal {:?})",
+            reason, frame.rip, sig
+        );
+    }
     log::warn!(
-        "Signal {:?} ({}) to current process, RIP={:#018x}",
+        "Signal {:?} ({}) to current process, RIP={:#018x} addr={:#018x}",
         sig,
         reason,
-        frame.rip
+        frame.rip,
+        addr
     );
-    panic!("Unhandled user signal {:?} ({})", sig, reason);
+    crate::proc::signal::force(sig as u32, addr);
 }
 
 // (unused broken macro removed)
@@ -425,12 +569,11 @@ pub fn init() {
             IDT.set_handler(v, make_isr_err(v as u64), 0);
         }
 
-        IDT.entries[8] = IdtEntry::interrupt_gate(
-            make_isr_err(8),
-            crate::arch::x86_64::gdt::SEG_KERNEL_CODE,
-            1,
-            0,
-        );
+        // Exceptions that may come in on a stack that can't take them
+        IDT.set_handler(2, make_isr_no_err(2), gdt::IST_NMI);
+        IDT.set_handler(8, make_isr_err(8), gdt::IST_DOUBLE_FAULT);
+        IDT.set_handler(14, make_isr_err(14), gdt::IST_PAGE_FAULT);
+        IDT.set_handler(18, make_isr_no_err(18), gdt::IST_MACHINE_CHECK);
 
         for irq in 0u8..16 {
             IDT.set_handler(32 + irq, make_isr_no_err(32 + irq as u64), 0);
@@ -438,6 +581,9 @@ pub fn init() {
 
         IDT.set_trap(0x80, make_isr_no_err(0x80), 3);
 
+        // LAPIC spurious vector; the fallback stub already reports 0xFF
+        IDT.set_handler(apic::SPURIOUS_VECTOR, make_isr_no_err(0xFF), 0);
+
         IDT.load();
     }
 }
@@ -1,5 +1,9 @@
+//! Port I/O, MMIO, MSRs, control registers, RFLAGS and CPUID: the
+//! instructions and accesses everything else in the kernel builds on.
 use core::arch::asm;
 
+// ─── Port I/O ─────────────────────────────────────────────────────────────────
+
 #[inline(always)]
 pub unsafe fn outb(port: u16, val: u8) {
     asm!("out %al, %dx", in("dx") port, in("al") val,
@@ -38,7 +42,7 @@ pub unsafe fn outl(port: u16, val: u32) {
 pub unsafe fn inl(port: u16) -> u32 {
     let val: u32;
     asm!("in %dx, %eax", in("dx") port, out("eax") val,
-         options(nomem, nostack, preserves_flags, att_syntax));
+        options(nomem, nostack, preserves_flags, att_syntax));
     val
 }
 
@@ -48,6 +52,107 @@ pub unsafe fn io_wait() {
     outb(0x80, 0x00);
 }
 
+// ─── MMIO ─────────────────────────────────────────────────────────────────────
+
+/// Volatile loads and stores of device registers at kernel virtual address
+/// `addr`, which must be mapped (uncached, from `map_mmio`) and aligned to
+/// the access size. Volatile so the compiler neither merges, reorders nor
+/// drops them: each one is a bus cycle the device sees.
+#[inline(always)]
+pub unsafe fn mmio_read32(addr: u64) -> u32 {
+    core::ptr::read_volatile(addr as *const u32)
+}
+
+#[inline(always)]
+pub unsafe fn mmio_write32(addr: u64, val: u32) {
+    core::ptr::write_volatile(addr as *mut u32, val)
+}
+
+#[inline(always)]
+pub unsafe fn mmio_read64(addr: u64) -> u64 {
+    core::ptr::read_volatile(addr as *const u64)
+}
+
+#[inline(always)]
+pub unsafe fn mmio_write64(addr: u64, val: u64) {
+    core::ptr::write_volatile(addr as *mut u64, val)
+}
+
+/// 32-bit load from physical address `phys` through the HHDM, which
+/// covers the low 4 GiB; `map_mmio` adds what lies above first.
+#[inline(always)]
+pub unsafe fn read32(phys: u64) -> u32 {
+    mmio_read32(super::limine::phys_to_virt(phys))
+}
+
+#[inline(always)]
+pub unsafe fn write32(phys: u64, val: u32) {
+    mmio_write32(super::limine::phys_to_virt(phys), val)
+}
+
+/// A block of device registers: accesses by offset from its base, checked
+/// against its size, so a driver holding one needs no `unsafe` of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct Mmio {
+    base: u64,
+    size: u64,
+}
+
+impl Mmio {
+    /// Registers at kernel virtual `base`, `size` bytes of them.
+    ///
+    /// # Safety
+    /// `base..base + size` must stay mapped to the device for as long as
+    /// the value (or a copy) is used.
+    pub const unsafe fn new(base: u64, size: u64) -> Self {
+        Self { base, size }
+    }
+
+    /// The registers at physical `phys`, mapped uncached. None if the
+    /// mapping couldn't be made.
+    ///
+    /// # Safety
+    /// `phys..phys + size` must be device memory that stays where it is.
+    pub unsafe fn map(phys: u64, size: u64) -> Option<Self> {
+        crate::mm::vmm::map_mmio(phys, size).map(|base| Self { base, size })
+    }
+
+    pub fn base(&self) -> u64 {
+        self.base
+    }
+
+    fn addr(&self, off: u64, width: u64) -> u64 {
+        assert!(
+            off.is_multiple_of(width) && off + width <= self.size,
+            "MMIO access at {:#x} outside the {:#x} bytes at {:#x}",
+            off,
+            self.size,
+            self.base
+        );
+        self.base + off
+    }
+
+    pub fn read32(&self, off: u64) -> u32 {
+        unsafe { mmio_read32(self.addr(off, 4)) }
+    }
+
+    pub fn write32(&self, off: u64, val: u32) {
+        unsafe { mmio_write32(self.addr(off, 4), val) }
+    }
+
+    /// 64-bit registers as two 32-bit halves, low first: not every device
+    /// takes 64-bit accesses.
+    pub fn read64(&self, off: u64) -> u64 {
+        let lo = self.read32(off) as u64;
+        lo | (self.read32(off + 4) as u64) << 32
+    }
+
+    pub fn write64(&self, off: u64, val: u64) {
+        self.write32(off, val as u32);
+        self.write32(off + 4, (val >> 32) as u32);
+    }
+}
+
 // ─── MSR (Model Specific Registers) ──────────────────────────────────────────
 
 pub const MSR_EFER: u32 = 0xC000_0080;
@@ -61,6 +166,7 @@ pub const MSR_KERNEL_GS: u32 = 0xC000_0102; // альтернативный GS (
 pub const MSR_TSC_AUX: u32 = 0xC000_0103;
 pub const MSR_APIC_BASE: u32 = 0x0000_001B;
 pub const MSR_IA32_TSC: u32 = 0x0000_0010;
+pub const MSR_TSC_DEADLINE: u32 = 0x0000_06E0;
 
 #[inline(always)]
 pub unsafe fn rdmsr(msr: u32) -> u64 {
@@ -134,6 +240,18 @@ pub unsafe fn write_cr4(v: u64) {
     asm!("mov {}, %cr4", in(reg) v, options(att_syntax, nomem, nostack));
 }
 
+/// Allow supervisor access to user pages (RFLAGS.AC = 1). #UD without SMAP.
+#[inline(always)]
+pub unsafe fn stac() {
+    asm!("stac", options(nomem, nostack));
+}
+
+/// Forbid supervisor access to user pages again.
+#[inline(always)]
+pub unsafe fn clac() {
+    asm!("clac", options(nomem, nostack));
+}
+
 pub const RFLAGS_IF: u64 = 1 << 9;
 pub const RFLAGS_DF: u64 = 1 << 10;
 pub const RFLAGS_IOPL: u64 = 3 << 12;
@@ -164,6 +282,14 @@ pub fn hlt() {
     unsafe { asm!("hlt", options(nomem, nostack)) };
 }
 
+/// Enable interrupts and halt as one step: `sti` holds interrupts off for
+/// one more instruction, so an IRQ can't land between a check done under
+/// cli and the `hlt`.
+#[inline(always)]
+pub fn sti_hlt() {
+    unsafe { asm!("sti; hlt", options(nomem, nostack)) };
+}
+
 #[inline(always)]
 pub unsafe fn invlpg(addr: u64) {
     asm!("invlpg [{0}]", in(reg) addr, options(nostack, preserves_flags));
@@ -207,6 +333,8 @@ pub fn has_feature_edx(leaf: u32, bit: u32) -> bool {
 pub const CR4_PAE: u64 = 1 << 5;
 pub const CR4_PGE: u64 = 1 << 7;
 pub const CR4_OSFXSR: u64 = 1 << 9;
+pub const CR4_OSXMMEXCPT: u64 = 1 << 10;
+pub const CR4_OSXSAVE: u64 = 1 << 18;
 pub const CR4_SMEP: u64 = 1 << 20;
 pub const CR4_SMAP: u64 = 1 << 21;
 pub const CR4_FSGSBASE: u64 = 1 << 16;
@@ -214,6 +342,10 @@ pub const CR4_FSGSBASE: u64 = 1 << 16;
 pub const CR0_WP: u64 = 1 << 16;
 pub const CR0_PE: u64 = 1 << 0;
 pub const CR0_PG: u64 = 1 << 31;
+pub const CR0_MP: u64 = 1 << 1;
+pub const CR0_EM: u64 = 1 << 2;
+pub const CR0_TS: u64 = 1 << 3;
+pub const CR0_NE: u64 = 1 << 5;
 
 pub const EFER_SCE: u64 = 1 << 0;
 pub const EFER_LME: u64 = 1 << 8;
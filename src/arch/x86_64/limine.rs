@@ -218,6 +218,190 @@ pub static SMP_REQUEST: SmpRequest = SmpRequest {
     flags: 0,
 };
 
+#[repr(C)]
+pub struct RsdpResponse {
+    pub revision: u64,
+    /// HHDM virtual address of the RSDP (physical on base revision 3+)
+    pub address: u64,
+}
+
+#[repr(C)]
+pub struct RsdpRequest {
+    pub id: [u64; 4],
+    pub revision: u64,
+    pub response: AtomicPtr<RsdpResponse>,
+}
+
+unsafe impl Sync for RsdpRequest {}
+
+pub static RSDP_REQUEST: RsdpRequest = RsdpRequest {
+    id: [
+        LIMINE_MAGIC[0],
+        LIMINE_MAGIC[1],
+        0xc5e77b6b397e7b43,
+        0x27637845accdcf3c,
+    ],
+    revision: 0,
+    response: AtomicPtr::new(core::ptr::null_mut()),
+};
+
+#[repr(C)]
+pub struct LimineUuid {
+    pub a: u32,
+    pub b: u16,
+    pub c: u16,
+    pub d: [u8; 8],
+}
+
+#[repr(C)]
+pub struct LimineFile {
+    pub revision: u64,
+    /// HHDM virtual address of the loaded file
+    pub address: *mut u8,
+    pub size: u64,
+    pub path: *const u8,
+    pub cmdline: *const u8,
+    pub media_type: u32,
+    _unused: u32,
+    pub tftp_ip: u32,
+    pub tftp_port: u32,
+    pub partition_index: u32,
+    pub mbr_disk_id: u32,
+    pub gpt_disk_uuid: LimineUuid,
+    pub gpt_part_uuid: LimineUuid,
+    pub part_uuid: LimineUuid,
+}
+
+impl LimineFile {
+    pub fn data(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.address, self.size as usize) }
+    }
+
+    pub fn path(&self) -> &'static str {
+        unsafe { c_str(self.path) }
+    }
+
+    pub fn cmdline(&self) -> &'static str {
+        unsafe { c_str(self.cmdline) }
+    }
+}
+
+/// NUL-terminated string handed over by the bootloader.
+unsafe fn c_str(p: *const u8) -> &'static str {
+    if p.is_null() {
+        return "";
+    }
+    let mut len = 0;
+    while *p.add(len) != 0 {
+        len += 1;
+    }
+    core::str::from_utf8(core::slice::from_raw_parts(p, len)).unwrap_or("")
+}
+
+#[repr(C)]
+pub struct ModuleResponse {
+    pub revision: u64,
+    pub module_count: u64,
+    pub modules: *const *const LimineFile,
+}
+
+unsafe impl Sync for ModuleResponse {}
+
+impl ModuleResponse {
+    pub fn modules(&self) -> &[*const LimineFile] {
+        if self.modules.is_null() {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.modules, self.module_count as usize) }
+    }
+}
+
+#[repr(C)]
+pub struct ModuleRequest {
+    pub id: [u64; 4],
+    pub revision: u64,
+    pub response: AtomicPtr<ModuleResponse>,
+}
+
+unsafe impl Sync for ModuleRequest {}
+
+pub static MODULE_REQUEST: ModuleRequest = ModuleRequest {
+    id: [
+        LIMINE_MAGIC[0],
+        LIMINE_MAGIC[1],
+        0x3e7e279702be32af,
+        0xca1c4f3bd1280cee,
+    ],
+    revision: 0,
+    response: AtomicPtr::new(core::ptr::null_mut()),
+};
+
+/// Modules loaded next to the kernel (`module_path:` in limine.conf).
+pub fn modules() -> &'static [*const LimineFile] {
+    let resp = MODULE_REQUEST.response.load(Ordering::Relaxed);
+    if resp.is_null() {
+        return &[];
+    }
+    unsafe { (*resp).modules() }
+}
+
+#[repr(C)]
+pub struct ExecutableFileResponse {
+    pub revision: u64,
+    pub file: *const LimineFile,
+}
+
+#[repr(C)]
+pub struct ExecutableFileRequest {
+    pub id: [u64; 4],
+    pub revision: u64,
+    pub response: AtomicPtr<ExecutableFileResponse>,
+}
+
+unsafe impl Sync for ExecutableFileRequest {}
+
+pub static EXECUTABLE_FILE_REQUEST: ExecutableFileRequest = ExecutableFileRequest {
+    id: [
+        LIMINE_MAGIC[0],
+        LIMINE_MAGIC[1],
+        0xad97e90e83f1ed67,
+        0x31eb5d1c5ff23b69,
+    ],
+    revision: 0,
+    response: AtomicPtr::new(core::ptr::null_mut()),
+};
+
+/// The kernel command line (`cmdline:` in limine.conf), "" if none.
+pub fn kernel_cmdline() -> &'static str {
+    let resp = EXECUTABLE_FILE_REQUEST.response.load(Ordering::Relaxed);
+    if resp.is_null() {
+        return "";
+    }
+    unsafe {
+        let file = (*resp).file;
+        if file.is_null() {
+            return "";
+        }
+        (*file).cmdline()
+    }
+}
+
+/// Whether the kernel command line has `flag`, bare or as `flag=value`.
+pub fn cmdline_flag(flag: &str) -> bool {
+    kernel_cmdline()
+        .split_whitespace()
+        .any(|w| w == flag || w.strip_prefix(flag).is_some_and(|v| v.starts_with('=')))
+}
+
+/// The value of `key=value` on the kernel command line; the last one if
+/// it is there more than once.
+pub fn cmdline_value(key: &str) -> Option<&'static str> {
+    kernel_cmdline()
+        .split_whitespace()
+        .rev()
+        .find_map(|w| w.strip_prefix(key)?.strip_prefix('='))
+}
+
 pub fn hhdm_offset() -> u64 {
     let resp = HHDM_REQUEST.response.load(Ordering::Relaxed);
     assert!(!resp.is_null(), "Limine HHDM response is null");
@@ -0,0 +1,175 @@
+//! Power off and reset, the ACPI way where the firmware says how, with the
+//! usual emulator and chipset back doors after it.
+//!
+//! Power-off puts the machine in S5: SLP_TYPx from `\_S5` and SLP_EN
+//! written to the PM1a/PM1b control registers, once the system is
+//! switched to ACPI mode. Reset writes the FADT's reset value to its reset
+//! register, then tries the keyboard controller, then a triple fault.
+use super::acpi::{self, GenericAddress, SPACE_IO, SPACE_MEMORY, SPACE_PCI_CONFIG};
+use super::io::{cli, hlt, inb, inw, io_wait, outb, outw};
+
+/// PM1 control: SCI_EN says the system is in ACPI mode.
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP_MASK: u16 = 0b111 << PM1_SLP_TYP_SHIFT;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// How long the firmware gets to switch to ACPI mode, and the machine to
+/// go off or reset once told to.
+const ACPI_ENABLE_TIMEOUT_US: u64 = 300_000;
+const SETTLE_US: u64 = 100_000;
+
+/// Power-off ports of the emulators whose chipset ACPI doesn't reach: QEMU
+/// (PIIX4, newer and older) and VirtualBox.
+const EMULATOR_OFF: [(u16, u16); 3] = [(0x604, 0x2000), (0xB004, 0x2000), (0x4004, 0x3400)];
+
+const KBC_STATUS: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 1 << 1;
+const KBC_PULSE_RESET: u8 = 0xFE;
+
+/// Busy-wait with interrupts off, where the clock may not move: each
+/// write to the POST port takes about a microsecond.
+fn delay_us(us: u64) {
+    for _ in 0..us {
+        unsafe { io_wait() };
+    }
+}
+
+/// Switch from legacy mode to ACPI mode, as the OS must before it may
+/// touch the PM1 registers. True once SCI_EN is set.
+fn enable_acpi(fadt: &acpi::Fadt) -> bool {
+    let sci_en = || unsafe { inw(fadt.pm1a_cnt) } & PM1_SCI_EN != 0;
+    if sci_en() {
+        return true;
+    }
+    if fadt.smi_cmd == 0 || fadt.acpi_enable == 0 {
+        // Nothing to switch with: hardware-reduced or always in ACPI mode
+        return true;
+    }
+    unsafe { outb(fadt.smi_cmd, fadt.acpi_enable) };
+    let mut waited = 0;
+    while !sci_en() {
+        if waited >= ACPI_ENABLE_TIMEOUT_US {
+            return false;
+        }
+        delay_us(1000);
+        waited += 1000;
+    }
+    true
+}
+
+fn acpi_poweroff() {
+    let Some(fadt) = acpi::fadt() else {
+        return;
+    };
+    if fadt.pm1a_cnt == 0 {
+        return;
+    }
+    let Some((typ_a, typ_b)) = fadt.dsdt.and_then(acpi::s5_sleep_types) else {
+        log::warn!("ACPI: no \\_S5 in the DSDT");
+        return;
+    };
+    if !enable_acpi(&fadt) {
+        log::warn!("ACPI: the firmware didn't switch to ACPI mode");
+    }
+    let sleep = |port: u16, typ: u8| unsafe {
+        let v = inw(port) & !PM1_SLP_TYP_MASK;
+        outw(port, v | ((typ as u16) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN);
+    };
+    sleep(fadt.pm1a_cnt, typ_a);
+    if fadt.pm1b_cnt != 0 {
+        sleep(fadt.pm1b_cnt, typ_b);
+    }
+    delay_us(SETTLE_US);
+    log::warn!("ACPI: S5 didn't take");
+}
+
+fn write_register(reg: GenericAddress, value: u8) {
+    match reg.space {
+        SPACE_IO => unsafe { outb(reg.address as u16, value) },
+        SPACE_MEMORY => {
+            if let Some(va) = crate::mm::vmm::map_mmio(reg.address, 1) {
+                unsafe { core::ptr::write_volatile(va as *mut u8, value) };
+            }
+        }
+        SPACE_PCI_CONFIG => {
+            // Segment 0, bus 0: device in bits 47–32, function 31–16,
+            // register offset 15–0
+            let dev = (reg.address >> 32) as u8;
+            let func = (reg.address >> 16) as u8;
+            let off = reg.address as u8;
+            let aligned = off & !3;
+            let shift = (off & 3) * 8;
+            let old = crate::drivers::pci::read_u32(0, dev, func, aligned);
+            let new = (old & !(0xFF << shift)) | ((value as u32) << shift);
+            crate::drivers::pci::write_u32(0, dev, func, aligned, new);
+        }
+        _ => {}
+    }
+}
+
+fn halt_forever() -> ! {
+    loop {
+        cli();
+        hlt();
+    }
+}
+
+/// Turn the machine off. If nothing works it is left halted.
+pub fn poweroff() -> ! {
+    cli();
+    acpi_poweroff();
+    for (port, value) in EMULATOR_OFF {
+        unsafe { outw(port, value) };
+    }
+    delay_us(SETTLE_US);
+    log::warn!("poweroff: nothing turned the machine off; halted");
+    crate::println!("System halted.");
+    halt_forever();
+}
+
+/// QEMU's isa-debug-exit device, as `-device isa-debug-exit,iobase=0xf4`
+/// puts it. Writing `v` makes QEMU exit with status `v << 1 | 1`.
+const DEBUG_EXIT_PORT: u16 = 0xF4;
+const DEBUG_EXIT_PASS: u8 = 0x10;
+const DEBUG_EXIT_FAIL: u8 = 0x11;
+
+/// Leave QEMU with a status that says whether the run went well: 33 for
+/// `success`, 35 otherwise. Off QEMU, or without the device, the machine
+/// is powered off instead.
+pub fn qemu_exit(success: bool) -> ! {
+    cli();
+    let code = if success {
+        DEBUG_EXIT_PASS
+    } else {
+        DEBUG_EXIT_FAIL
+    };
+    unsafe { outb(DEBUG_EXIT_PORT, code) };
+    poweroff();
+}
+
+/// Restart the machine.
+pub fn reboot() -> ! {
+    cli();
+    if let Some((reg, value)) = acpi::fadt().and_then(|f| f.reset) {
+        write_register(reg, value);
+        delay_us(SETTLE_US);
+        log::warn!("ACPI: the reset register didn't reset");
+    }
+
+    for _ in 0..0x10000 {
+        if unsafe { inb(KBC_STATUS) } & KBC_INPUT_FULL == 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    unsafe { outb(KBC_STATUS, KBC_PULSE_RESET) };
+    delay_us(SETTLE_US);
+
+    // An empty IDT makes the next exception a triple fault
+    let idtr = [0u16; 5];
+    unsafe {
+        core::arch::asm!("lidt [{0}]", "int3", in(reg) &idtr, options(nostack));
+    }
+    halt_forever();
+}
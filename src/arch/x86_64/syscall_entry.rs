@@ -1,4 +1,5 @@
-use crate::arch::x86_64::gdt::{SEG_KERNEL_CODE, SEG_USER_DATA};
+use crate::arch::x86_64::gdt::{SEG_KERNEL_CODE, SEG_USER_CODE, SEG_USER_DATA};
+use crate::arch::x86_64::idt::InterruptFrame;
 use crate::arch::x86_64::io::{rdmsr, wrmsr, EFER_SCE, MSR_EFER, MSR_LSTAR, MSR_SFMASK, MSR_STAR};
 
 /// Инициализация SYSCALL/SYSRET
@@ -17,6 +18,10 @@ pub fn init_syscall() {
     log::info!("SYSCALL/SYSRET initialized");
 }
 
+/// Builds a full `InterruptFrame` on the kernel stack — the same layout
+/// isr_common produces — so syscalls share the int 0x80 path and signal
+/// delivery / sigreturn can rewrite any user register. Returns via iretq:
+/// the frame may no longer match what sysretq can restore (RCX/R11).
 #[unsafe(naked)]
 pub unsafe extern "C" fn syscall_entry() {
     core::arch::naked_asm!(
@@ -25,76 +30,66 @@ pub unsafe extern "C" fn syscall_entry() {
         "mov %rsp, %gs:16",
         "mov %gs:8, %rsp",
 
-        "push %r11",
-        "push %rcx",
-        "push %rax",
+        // Hardware part of the frame, as an interrupt from ring 3 would push it
+        "push ${user_ss}",
+        "push %gs:16",              // user RSP
+        "push %r11",                // user RFLAGS
+        "push ${user_cs}",
+        "push %rcx",                // user RIP
+        "push $0",                  // error_code
+        "push $0x80",               // vector
 
+        "push %rax",
         "push %rbx",
+        "push %rcx",
+        "push %rdx",
+        "push %rsi",
+        "push %rdi",
         "push %rbp",
+        "push %r8",
+        "push %r9",
+        "push %r10",
+        "push %r11",
         "push %r12",
         "push %r13",
         "push %r14",
         "push %r15",
 
-        // Linux SYSCALL ABI:  rax=nr, rdi=a0, rsi=a1, rdx=a2, r10=a3, r8=a4, r9=a5
-        // C calling convention: rdi=1st, rsi=2nd, rdx=3rd, rcx=4th, r8=5th, r9=6th
-        // We need nr(rax) in rdi and shift a0..a5 right by one slot.
-        // a5(r9) has no register slot → push it as a stack (7th) argument.
-        "sub $8, %rsp",             // reserve slot for a5
-        "mov %r9, (%rsp)",          // a5 = r9  (7th arg, via stack)
-        "mov %r8,  %r9",            // a4 = r8
-        "mov %r10, %r8",            // a3 = r10  (Linux uses r10 for arg3 in SYSCALL)
-        "mov %rdx, %rcx",           // a2 = rdx
-        "mov %rsi, %rdx",           // a1 = rsi
-        "mov %rdi, %rsi",           // a0 = rdi
-        "mov %rax, %rdi",           // nr = rax  (syscall number)
-
+        // 22 qwords pushed onto the 16-aligned kernel stack top: still aligned
+        "mov %rsp, %rdi",
         "sti",
-
         "call {handler}",
-
-        "add $8, %rsp",             // discard a5 stack slot
+        "cli",
 
         "pop %r15",
         "pop %r14",
         "pop %r13",
         "pop %r12",
+        "pop %r11",
+        "pop %r10",
+        "pop %r9",
+        "pop %r8",
         "pop %rbp",
-        "pop %rbx",
-
-        "add $8, %rsp",
+        "pop %rdi",
+        "pop %rsi",
+        "pop %rdx",
         "pop %rcx",
-        "pop %r11",
-
-        "mov %gs:16, %rsp",
+        "pop %rbx",
+        "pop %rax",
 
-        "cli",
+        "add $16, %rsp",
         "swapgs",
-        "sysretq",
+        "iretq",
 
-        handler = sym syscall_dispatch_entry,
+        handler = sym syscall_frame_entry,
+        user_ss = const SEG_USER_DATA as u64,
+        user_cs = const SEG_USER_CODE as u64,
         options(att_syntax)
     );
 }
 
-extern "C" fn syscall_dispatch_entry(
-    nr: u64,
-    a0: u64,
-    a1: u64,
-    a2: u64,
-    a3: u64,
-    a4: u64,
-    a5: u64,
-) -> i64 {
-    crate::syscall::syscall_dispatch(nr, a0, a1, a2, a3, a4, a5)
-}
-
-pub fn handle_int80(frame: &mut crate::arch::x86_64::idt::InterruptFrame) {
-    let nr = frame.rax;
-    let a0 = frame.rdi;
-    let a1 = frame.rsi;
-    let a2 = frame.rdx;
-
-    let result = crate::syscall::syscall_dispatch(nr, a0, a1, a2, 0, 0, 0);
-    frame.rax = result as u64;
+extern "C" fn syscall_frame_entry(frame: &mut InterruptFrame) {
+    crate::syscall::handle_syscall_frame(frame);
+    crate::proc::preempt();
+    crate::proc::signal::deliver_pending(frame);
 }
@@ -1,6 +1,8 @@
+use crate::arch::x86_64::apic::{self, TimerMode};
 use crate::arch::x86_64::idt::InterruptFrame;
-use crate::arch::x86_64::io::{inb, outb};
-use core::sync::atomic::{AtomicU64, Ordering};
+use crate::arch::x86_64::io::{cli, inb, outb, sti, RFLAGS_IF};
+use crate::sync::seqlock::SeqLock;
+use crate::sync::spinlock::SpinLock;
 
 const PIT_CHANNEL0: u16 = 0x40;
 const PIT_CHANNEL2: u16 = 0x42;
@@ -8,10 +10,35 @@ const PIT_CMD: u16 = 0x43;
 const PIT_FREQ: u64 = 1_193_182;
 
 pub const TIMER_HZ: u64 = 1000;
+const TICK_NS: u64 = 1_000_000_000 / TIMER_HZ;
+
+/// Timekeeping state: written by the timer IRQ / TSC calibration,
+/// read lock-free by `uptime_ms`, `nanos` and `clock_gettime`.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeState {
+    pub ticks: u64,
+    pub uptime_ms: u64,
+    /// 0 until `calibrate_tsc` has run
+    pub tsc_freq_hz: u64,
+    /// TSC value and uptime (ns) at calibration — base for `nanos()`
+    pub tsc_base: u64,
+    pub ns_base: u64,
+    /// Unix time (ns) when `nanos()` read 0; 0 until the RTC has been read
+    pub realtime_base: u64,
+}
 
-static TICK_COUNT: AtomicU64 = AtomicU64::new(0);
-
-static UPTIME_MS: AtomicU64 = AtomicU64::new(0);
+static TIMEKEEPING: SeqLock<TimeState> = SeqLock::new(TimeState {
+    ticks: 0,
+    uptime_ms: 0,
+    tsc_freq_hz: 0,
+    tsc_base: 0,
+    ns_base: 0,
+    realtime_base: 0,
+});
+
+pub fn snapshot() -> TimeState {
+    TIMEKEEPING.read()
+}
 
 pub fn init_pit(hz: u64) {
     let divisor = PIT_FREQ / hz;
@@ -26,20 +53,112 @@ pub fn init_pit(hz: u64) {
     log::info!("PIT initialized: {} Hz (divisor={})", hz, divisor);
 }
 
-pub fn irq_timer(_frame: &mut InterruptFrame) {
-    let tick = TICK_COUNT.fetch_add(1, Ordering::Relaxed);
+/// Where the tick comes from: the PIT until `init_tick_source` finds a
+/// LAPIC timer. That one is armed afresh for every tick, which is what
+/// lets it stay quiet while the CPU idles.
+struct TickSource {
+    lapic: Option<TimerMode>,
+    /// Only the idle task is runnable: no periodic tick, just the next
+    /// sleeper's deadline.
+    idle: bool,
+}
+
+static TICK: SpinLock<TickSource> = SpinLock::new(TickSource {
+    lapic: None,
+    idle: false,
+});
+
+/// Program the LAPIC timer for the next tick boundary, or while idle for
+/// the earliest sleep deadline (none: stay silent until another IRQ).
+fn rearm(mode: TimerMode, idle: bool) {
+    let now = nanos();
+    let at = if idle {
+        match crate::proc::timers::next_deadline() {
+            Some(d) => d.max(now),
+            None => {
+                apic::timer_disarm(mode);
+                return;
+            }
+        }
+    } else {
+        (now / TICK_NS + 1) * TICK_NS
+    };
+    apic::timer_arm(mode, rdtsc(), at - now, tsc_freq_hz());
+}
+
+/// Switch the tick to the LAPIC timer (TSC-deadline if the CPU has it)
+/// and stop the PIT. Needs a calibrated TSC; `nolapictimer` on the kernel
+/// command line keeps the PIT.
+pub fn init_tick_source() {
+    if crate::arch::x86_64::limine::cmdline_flag("nolapictimer") {
+        log::info!("Timer: PIT ({} Hz), LAPIC timer disabled on cmdline", TIMER_HZ);
+        return;
+    }
+    let vector = crate::arch::x86_64::pic::IRQ_BASE_MASTER;
+    let Some(mode) = apic::timer_init(vector, tsc_freq_hz()) else {
+        log::info!("Timer: PIT ({} Hz), no LAPIC timer", TIMER_HZ);
+        return;
+    };
+    let rflags = cli();
+    // Mode 0 without a count: the counter stops and IRQ0 never fires again
+    unsafe { outb(PIT_CMD, 0b0011_0000) };
+    TICK.lock().lapic = Some(mode);
+    rearm(mode, false);
+    if rflags & RFLAGS_IF != 0 {
+        sti();
+    }
+    match mode {
+        TimerMode::TscDeadline => log::info!("Timer: LAPIC TSC-deadline, tickless idle"),
+        TimerMode::OneShot { hz } => {
+            log::info!("Timer: LAPIC one-shot at {} kHz, tickless idle", hz / 1000)
+        }
+    }
+}
+
+/// Called by the scheduler on each switch; `idle` when the idle task is
+/// all that is left to run. Leaving idle restarts the tick at once.
+pub fn set_idle(idle: bool) {
+    let lapic = {
+        let mut t = TICK.lock();
+        if t.idle == idle {
+            return;
+        }
+        t.idle = idle;
+        t.lapic
+    };
+    if let (Some(mode), false) = (lapic, idle) {
+        rearm(mode, false);
+    }
+}
 
-    UPTIME_MS.fetch_add(1000 / TIMER_HZ, Ordering::Relaxed);
+pub fn irq_timer(frame: &mut InterruptFrame) {
+    let (lapic, idle) = {
+        let t = TICK.lock();
+        (t.lapic, t.idle)
+    };
+    let now = nanos();
+    TIMEKEEPING.write(|t| {
+        match lapic {
+            // Ticks skipped while idle are caught up from the TSC
+            Some(_) => t.ticks = t.ticks.max(now / TICK_NS),
+            None => t.ticks += 1,
+        }
+        t.uptime_ms = t.ticks * 1000 / TIMER_HZ;
+    });
+    crate::proc::timers::expire(now);
+    if let Some(mode) = lapic {
+        rearm(mode, idle);
+    }
 
-    crate::proc::scheduler::tick();
+    crate::proc::scheduler::tick(frame.cs & 3 == 3);
 }
 
 pub fn uptime_ms() -> u64 {
-    UPTIME_MS.load(Ordering::Relaxed)
+    TIMEKEEPING.read().uptime_ms
 }
 
 pub fn ticks() -> u64 {
-    TICK_COUNT.load(Ordering::Relaxed)
+    TIMEKEEPING.read().ticks
 }
 
 pub fn sleep_busy(ms: u64) {
@@ -65,8 +184,6 @@ pub fn rdtsc() -> u64 {
     ((hi as u64) << 32) | lo as u64
 }
 
-static TSC_FREQ_HZ: AtomicU64 = AtomicU64::new(0);
-
 pub fn calibrate_tsc() {
     let ms = 10u64;
     let t0 = rdtsc();
@@ -80,20 +197,37 @@ pub fn calibrate_tsc() {
     let elapsed = t1 - t0;
     let freq = elapsed * 1000 / ms;
 
-    TSC_FREQ_HZ.store(freq, Ordering::Relaxed);
+    TIMEKEEPING.write(|t| {
+        t.tsc_freq_hz = freq;
+        t.tsc_base = t1;
+        t.ns_base = t.uptime_ms * 1_000_000;
+    });
     log::info!("TSC frequency: {} MHz", freq / 1_000_000);
 }
 
 pub fn tsc_freq_hz() -> u64 {
-    TSC_FREQ_HZ.load(Ordering::Relaxed)
+    TIMEKEEPING.read().tsc_freq_hz
 }
 
 pub fn nanos() -> u64 {
-    let freq = tsc_freq_hz();
-    if freq == 0 {
-        return uptime_ms() * 1_000_000;
+    let t = TIMEKEEPING.read();
+    if t.tsc_freq_hz == 0 {
+        return t.uptime_ms * 1_000_000;
     }
-    rdtsc() * 1_000_000_000 / freq
+    // u128: (tsc delta * 1e9) overflows u64 after a few seconds at GHz rates
+    let delta = rdtsc().wrapping_sub(t.tsc_base) as u128;
+    t.ns_base + (delta * 1_000_000_000 / t.tsc_freq_hz as u128) as u64
+}
+
+/// Wall-clock time: Unix nanoseconds.
+pub fn realtime() -> u64 {
+    TIMEKEEPING.read().realtime_base + nanos()
+}
+
+/// Set the wall clock to `unix_ns` as of now.
+pub fn set_realtime(unix_ns: u64) {
+    let base = unix_ns.saturating_sub(nanos());
+    TIMEKEEPING.write(|t| t.realtime_base = base);
 }
 
 pub fn init() {
@@ -0,0 +1,144 @@
+//! Kernel backtraces: the frame-pointer chain walked from a panic or a
+//! fatal exception, each return address named from the kernel's own
+//! symbol map.
+//!
+//! The kernel is built with frame pointers, so every frame starts with the
+//! caller's RBP and the return address above it. The map is a text table,
+//! `nm -n` order, one "address name" line per function; `build.sh`
+//! generates it from the linked ELF and writes it into `.ksyms`, which
+//! `KSYMS` reserves. A kernel built without that step has an empty table
+//! and its backtraces show bare addresses.
+
+/// Room for the symbol map; `build.sh` truncates a bigger one.
+const KSYMS_SIZE: usize = 512 * 1024;
+
+/// Frames printed at most.
+const MAX_FRAMES: usize = 32;
+
+#[used]
+#[link_section = ".ksyms"]
+static KSYMS: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+extern "C" {
+    static __ksyms_start: u8;
+    static __ksyms_end: u8;
+    static __text_start: u8;
+    static __text_end: u8;
+}
+
+/// The map as embedded. Read through the linker's symbols: `KSYMS` itself
+/// is all zeros as far as the compiler knows.
+fn table() -> &'static [u8] {
+    let (start, end) = unsafe {
+        (
+            &__ksyms_start as *const u8 as usize,
+            &__ksyms_end as *const u8 as usize,
+        )
+    };
+    let all = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+    let len = all.iter().position(|&b| b == 0).unwrap_or(all.len());
+    &all[..len]
+}
+
+fn in_text(addr: u64) -> bool {
+    let (start, end) = unsafe {
+        (
+            &__text_start as *const u8 as u64,
+            &__text_end as *const u8 as u64,
+        )
+    };
+    (start..end).contains(&addr)
+}
+
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    core::str::from_utf8(s)
+        .ok()
+        .and_then(|s| u64::from_str_radix(s, 16).ok())
+}
+
+/// The function `addr` is in, and how far into it.
+pub fn symbolize(addr: u64) -> Option<(&'static str, u64)> {
+    let mut best = None;
+    for line in table().split(|&b| b == b'\n') {
+        let Some(space) = line.iter().position(|&b| b == b' ') else {
+            continue;
+        };
+        let Some(start) = parse_hex(&line[..space]) else {
+            continue;
+        };
+        if start > addr {
+            // Sorted by address: the one before was it
+            break;
+        }
+        best = Some((&line[space + 1..], start));
+    }
+    let (name, start) = best?;
+    Some((core::str::from_utf8(name).ok()?, addr - start))
+}
+
+/// Whether the 8 bytes at `va` can be read without faulting.
+fn readable(va: u64) -> bool {
+    va.is_multiple_of(8) && crate::mm::vmm::translate_current(va).is_some()
+}
+
+/// Return addresses up the chain from frame pointer `rbp`, innermost
+/// first, until the chain leaves the kernel.
+pub fn walk(mut rbp: u64, mut f: impl FnMut(u64)) {
+    for _ in 0..MAX_FRAMES {
+        if rbp < 0xFFFF_8000_0000_0000 || !readable(rbp) || !readable(rbp + 8) {
+            return;
+        }
+        let (next, ret) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if !in_text(ret) {
+            return;
+        }
+        f(ret);
+        // Stacks grow down: callers' frames are above
+        if next <= rbp {
+            return;
+        }
+        rbp = next;
+    }
+}
+
+fn print_frame(i: usize, addr: u64) {
+    match symbolize(addr) {
+        Some((name, off)) => {
+            crate::serial_println!("  #{:<2} {:#018x} {}+{:#x}", i, addr, name, off);
+            crate::println!("  #{:<2} {:#018x} {}+{:#x}", i, addr, name, off);
+        }
+        None => {
+            crate::serial_println!("  #{:<2} {:#018x}", i, addr);
+            crate::println!("  #{:<2} {:#018x}", i, addr);
+        }
+    }
+}
+
+/// Print the backtrace of code stopped at `rip` with frame pointer `rbp`.
+pub fn print(rip: u64, rbp: u64) {
+    crate::serial_println!("Backtrace:");
+    crate::println!("Backtrace:");
+    print_frame(0, rip);
+    let mut i = 1;
+    walk(rbp, |addr| {
+        // A return address points past the call: name the call itself
+        print_frame(i, addr - 1);
+        i += 1;
+    });
+}
+
+/// The backtrace of the caller.
+#[inline(never)]
+pub fn print_here() {
+    let (rip, rbp): (u64, u64);
+    unsafe {
+        core::arch::asm!(
+            "lea {}, [rip]",
+            "mov {}, rbp",
+            out(reg) rip,
+            out(reg) rbp,
+            options(nomem, nostack)
+        );
+    }
+    print(rip, rbp);
+}
@@ -0,0 +1,93 @@
+//! Boot options from the kernel command line (`cmdline:` in limine.conf),
+//! parsed once at boot into `Options` for the rest of init to read. The
+//! text itself is /proc/cmdline.
+//!
+//! Words are `key=value` or bare flags, as on Linux; ones not known here
+//! are left to whoever looks for them (`limine::cmdline_flag`), and the
+//! last of a repeated key wins.
+use crate::arch::x86_64::limine;
+use crate::sync::spinlock::SpinLock;
+use log::LevelFilter;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// `loglevel=`: the messages that reach the serial port and the log
+    /// VT; the panic report's log keeps everything.
+    pub loglevel: LevelFilter,
+    /// `nosmp`: run on the boot CPU only. Application processors are not
+    /// started yet in any case.
+    pub nosmp: bool,
+    /// `noapic`: leave interrupts on the 8259 PICs.
+    pub noapic: bool,
+    /// `root=`: the drive or partition (`hda1`, `/dev/hda1`, `0`) to mount
+    /// over the ramfs root once the initrd is in.
+    pub root: Option<&'static str>,
+    /// `init=`: the program to start as the first process.
+    pub init: Option<&'static str>,
+    /// `rescue`: the kernel shell on the console instead of init.
+    pub rescue: bool,
+}
+
+impl Options {
+    const DEFAULT: Options = Options {
+        loglevel: LevelFilter::Trace,
+        nosmp: false,
+        noapic: false,
+        root: None,
+        init: None,
+        rescue: false,
+    };
+
+    pub fn parse(cmdline: &'static str) -> Options {
+        let mut opts = Options::DEFAULT;
+        for word in cmdline.split_whitespace() {
+            let (key, value) = word.split_once('=').unwrap_or((word, ""));
+            match key {
+                "loglevel" => match parse_level(value) {
+                    Some(level) => opts.loglevel = level,
+                    None => log::warn!("cmdline: bad loglevel '{}'", value),
+                },
+                "nosmp" => opts.nosmp = true,
+                "noapic" => opts.noapic = true,
+                "root" if !value.is_empty() => opts.root = Some(value),
+                "init" if !value.is_empty() => opts.init = Some(value),
+                "rescue" => opts.rescue = true,
+                _ => {}
+            }
+        }
+        opts
+    }
+}
+
+/// A level by name (`warn`), or as a Linux console loglevel, which shows
+/// the messages of a priority below it: 4 is errors only, 5 and 6 add
+/// warnings, 7 info, 8 debug and 9 or more trace; 0 shows nothing.
+fn parse_level(s: &str) -> Option<LevelFilter> {
+    if let Ok(n) = s.parse::<u32>() {
+        return Some(match n {
+            0 => LevelFilter::Off,
+            1..=4 => LevelFilter::Error,
+            5..=6 => LevelFilter::Warn,
+            7 => LevelFilter::Info,
+            8 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        });
+    }
+    s.parse().ok()
+}
+
+static OPTIONS: SpinLock<Options> = SpinLock::new(Options::DEFAULT);
+
+/// Read the command line, first thing after the logger is up.
+pub fn init() {
+    *OPTIONS.lock() = Options::parse(limine::kernel_cmdline());
+}
+
+pub fn options() -> Options {
+    *OPTIONS.lock()
+}
+
+/// /proc/cmdline.
+pub fn proc_cmdline() -> alloc::string::String {
+    alloc::format!("{}\n", limine::kernel_cmdline())
+}
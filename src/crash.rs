@@ -0,0 +1,188 @@
+//! The panic report. On the screen: the message, the registers of the
+//! exception that caused it (if one did) and the backtrace, white on blue
+//! so it can't be taken for ordinary output. On serial, where there is
+//! room, also the stack around RSP and the last lines of the kernel log.
+//!
+//! Everything here runs with the kernel stopped for good: locks whoever
+//! panicked may hold are broken rather than waited for, and nothing is
+//! allocated.
+use crate::arch::x86_64::idt::InterruptFrame;
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::drivers::vga;
+use crate::sync::spinlock::SpinLock;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Log lines in the report.
+const LOG_LINES: usize = 20;
+
+/// Stack dumped: from a little below RSP, where a faulting push would have
+/// gone, up into the callers' frames.
+const STACK_BELOW: u64 = 64;
+const STACK_ABOVE: u64 = 448;
+
+/// Set by a fatal exception just before it panics.
+static FRAME: SpinLock<Option<InterruptFrame>> = SpinLock::new(None);
+
+/// A panic while reporting one.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Record the exception about to panic, for the report.
+pub fn set_frame(frame: &InterruptFrame) {
+    *FRAME.lock() = Some(frame.clone());
+}
+
+/// To serial and the screen alike.
+macro_rules! both {
+    ($($a:tt)*) => {{
+        crate::serial_println!($($a)*);
+        crate::println!($($a)*);
+    }};
+}
+
+fn control_registers() -> [u64; 4] {
+    let (cr0, cr2, cr3, cr4): (u64, u64, u64, u64);
+    unsafe {
+        core::arch::asm!(
+            "mov {}, cr0",
+            "mov {}, cr2",
+            "mov {}, cr3",
+            "mov {}, cr4",
+            out(reg) cr0,
+            out(reg) cr2,
+            out(reg) cr3,
+            out(reg) cr4,
+            options(nomem, nostack)
+        );
+    }
+    [cr0, cr2, cr3, cr4]
+}
+
+fn print_frame(f: &InterruptFrame) {
+    both!(
+        "Exception {} error {:#x} in {} mode",
+        f.vector,
+        f.error_code,
+        if f.cs & 3 == 3 { "user" } else { "kernel" }
+    );
+    both!(
+        "RIP {:#018x}  CS  {:#06x}  RFLAGS {:#010x}",
+        f.rip,
+        f.cs,
+        f.rflags
+    );
+    both!("RSP {:#018x}  SS  {:#06x}", f.rsp, f.ss);
+    let regs = [
+        ("RAX", f.rax),
+        ("RBX", f.rbx),
+        ("RCX", f.rcx),
+        ("RDX", f.rdx),
+        ("RSI", f.rsi),
+        ("RDI", f.rdi),
+        ("RBP", f.rbp),
+        ("R8 ", f.r8),
+        ("R9 ", f.r9),
+        ("R10", f.r10),
+        ("R11", f.r11),
+        ("R12", f.r12),
+        ("R13", f.r13),
+        ("R14", f.r14),
+        ("R15", f.r15),
+    ];
+    for r in regs.chunks(3) {
+        both!(
+            "{} {:#018x}  {} {:#018x}  {} {:#018x}",
+            r[0].0,
+            r[0].1,
+            r[1].0,
+            r[1].1,
+            r[2].0,
+            r[2].1
+        );
+    }
+}
+
+/// Two words a line from below `rsp` upwards, pages that aren't mapped
+/// skipped. Read through the HHDM, which SMAP leaves alone when the stack
+/// is a user one.
+fn dump_stack(rsp: u64) {
+    crate::serial_println!("Stack around RSP={:#018x}:", rsp);
+    let start = (rsp & !15).wrapping_sub(STACK_BELOW);
+    for line in (start..start + STACK_BELOW + STACK_ABOVE).step_by(16) {
+        let Some(phys) = crate::mm::vmm::translate_current(line) else {
+            continue;
+        };
+        let p = phys_to_virt(phys) as *const u64;
+        let (a, b) = unsafe { (*p, *p.add(1)) };
+        let mark = if (line..line + 16).contains(&rsp) {
+            " <- RSP"
+        } else {
+            ""
+        };
+        crate::serial_println!("  {:#018x}: {:016x} {:016x}{}", line, a, b, mark);
+    }
+}
+
+fn dump_log() {
+    crate::serial_println!("Last {} log lines:", LOG_LINES);
+    let shown = crate::drivers::logger::recent(LOG_LINES, |line| {
+        crate::serial_print!("  ");
+        for &b in line {
+            crate::drivers::serial::write_byte(b);
+        }
+        crate::serial_println!();
+    });
+    if !shown {
+        crate::serial_println!("  (the log buffer is busy)");
+    }
+}
+
+/// The panic handler's report of `info`.
+pub fn report(info: &core::panic::PanicInfo) {
+    crate::drivers::serial::break_lock();
+    if PANICKING.swap(true, Ordering::Relaxed) {
+        crate::serial_println!("\n=== PANIC DURING PANIC: {} ===", info);
+        return;
+    }
+    let frame = FRAME.try_lock().and_then(|mut f| f.take());
+
+    vga::panic_screen(vga::WHITE, vga::BLUE);
+    crate::serial_println!("\n\n=== KERNEL PANIC ===");
+    crate::println!(" *** KERNEL PANIC *** ");
+    both!("{}", info.message());
+    if let Some(loc) = info.location() {
+        both!("at {}:{}", loc.file(), loc.line());
+    }
+    both!();
+
+    if let Some(f) = &frame {
+        print_frame(f);
+    }
+    let [cr0, cr2, cr3, cr4] = control_registers();
+    both!(
+        "CR0 {:#010x}  CR2 {:#018x}  CR3 {:#018x}  CR4 {:#010x}",
+        cr0,
+        cr2,
+        cr3,
+        cr4
+    );
+    both!();
+
+    match &frame {
+        Some(f) => crate::backtrace::print(f.rip, f.rbp),
+        None => crate::backtrace::print_here(),
+    }
+
+    let rsp = match &frame {
+        Some(f) => f.rsp,
+        None => {
+            let rsp: u64;
+            unsafe { core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack)) };
+            rsp
+        }
+    };
+    crate::serial_println!();
+    dump_stack(rsp);
+    crate::serial_println!();
+    dump_log();
+    crate::serial_println!("=== END OF PANIC REPORT ===");
+}
@@ -1,7 +1,20 @@
+pub mod ahci;
+pub mod bcache;
+pub mod block;
 pub mod bmp;
+pub mod e1000;
 pub mod ide;
+pub mod inflate;
 pub mod keyboard;
 pub mod logger;
+pub mod net;
+pub mod nvme;
+pub mod partition;
 pub mod pci;
+pub mod png;
+pub mod psf;
+pub mod rtc;
 pub mod serial;
+pub mod smart;
+pub mod tty;
 pub mod vga;
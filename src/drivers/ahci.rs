@@ -0,0 +1,389 @@
+/// AHCI (SATA) driver.
+/// Polled DMA through command slot 0 of each port; data goes through a
+/// per-port bounce buffer so callers can pass any kernel buffer. Detected
+/// disks are registered as block devices (sda, sdb, ...).
+use super::block::{self, BlockDevice, SECTOR_SIZE};
+use super::ide::Identity;
+use super::pci::{self, PciDevice};
+use crate::arch::x86_64::io::{mmio_read32, mmio_write32};
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::mm::dma::{alloc_coherent, alloc_coherent_32, DmaBuffer};
+use crate::mm::pmm::PAGE_SIZE;
+use crate::mm::vmm::map_mmio;
+use crate::sync::spinlock::SpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+// ─── HBA registers (offsets from ABAR) ───────────────────────────────────────
+
+const HBA_CAP: u64 = 0x00;
+const HBA_GHC: u64 = 0x04;
+const HBA_PI: u64 = 0x0C;
+const HBA_VS: u64 = 0x10;
+const HBA_CAP2: u64 = 0x24;
+const HBA_BOHC: u64 = 0x28;
+
+const GHC_AE: u32 = 1 << 31;
+const CAP_S64A: u32 = 1 << 31;
+const CAP2_BOH: u32 = 1 << 0;
+const BOHC_BOS: u32 = 1 << 0;
+const BOHC_OOS: u32 = 1 << 1;
+
+const ABAR_SIZE: u64 = 0x1100; // globals + 32 ports
+
+// ─── Port registers (offsets from ABAR + 0x100 + port * 0x80) ─────────────────
+
+const PX_CLB: u64 = 0x00;
+const PX_CLBU: u64 = 0x04;
+const PX_FB: u64 = 0x08;
+const PX_FBU: u64 = 0x0C;
+const PX_IS: u64 = 0x10;
+const PX_IE: u64 = 0x14;
+const PX_CMD: u64 = 0x18;
+const PX_TFD: u64 = 0x20;
+const PX_SIG: u64 = 0x24;
+const PX_SSTS: u64 = 0x28;
+const PX_SERR: u64 = 0x30;
+const PX_CI: u64 = 0x38;
+
+const CMD_ST: u32 = 1 << 0;
+const CMD_FRE: u32 = 1 << 4;
+const CMD_FR: u32 = 1 << 14;
+const CMD_CR: u32 = 1 << 15;
+
+const IS_TFES: u32 = 1 << 30;
+
+const TFD_ERR: u32 = 0x01;
+const TFD_DRQ: u32 = 0x08;
+const TFD_BSY: u32 = 0x80;
+
+const SSTS_DET_PRESENT: u32 = 3;
+const SSTS_IPM_ACTIVE: u32 = 1;
+
+const SIG_ATA: u32 = 0x0000_0101;
+
+// ─── ATA commands ─────────────────────────────────────────────────────────────
+
+const ATA_READ_DMA: u8 = 0xC8;
+const ATA_READ_DMA_EX: u8 = 0x25;
+const ATA_WRITE_DMA: u8 = 0xCA;
+const ATA_WRITE_DMA_EX: u8 = 0x35;
+const ATA_FLUSH: u8 = 0xE7;
+const ATA_FLUSH_EX: u8 = 0xEA;
+const ATA_IDENTIFY: u8 = 0xEC;
+
+const FIS_TYPE_H2D: u8 = 0x27;
+
+// ─── Per-port DMA memory ──────────────────────────────────────────────────────
+//
+// One page holds everything slot 0 needs:
+//   0x000  command list (32 headers × 32 bytes, only header 0 used)
+//   0x400  received-FIS area (256 bytes)
+//   0x800  command table: CFIS at +0, PRDT at +0x80
+const MEM_FB: u64 = 0x400;
+const MEM_CTBA: u64 = 0x800;
+const CT_PRDT: u64 = 0x80;
+
+/// Bounce buffer: 64 KiB = 128 sectors per command.
+const BUF_BYTES: usize = 64 * 1024;
+const BUF_SECTORS: usize = BUF_BYTES / SECTOR_SIZE;
+
+const SPIN_TIMEOUT: u32 = 10_000_000;
+
+struct Port {
+    regs: u64, // virtual address of this port's registers
+    mem: DmaBuffer,
+    buf: DmaBuffer,
+    lba48: bool,
+}
+
+/// A SATA disk behind one AHCI port.
+struct AhciDisk {
+    name: String,
+    port_no: u32,
+    id: Identity,
+    port: SpinLock<Port>,
+}
+
+// ─── MMIO helpers ─────────────────────────────────────────────────────────────
+
+fn rd(addr: u64) -> u32 {
+    unsafe { mmio_read32(addr) }
+}
+
+fn wr(addr: u64, val: u32) {
+    unsafe { mmio_write32(addr, val) }
+}
+
+/// Spin until `addr & mask == want`; false on timeout.
+fn wait_reg(addr: u64, mask: u32, want: u32) -> bool {
+    for _ in 0..SPIN_TIMEOUT {
+        if rd(addr) & mask == want {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    false
+}
+
+// ─── Command engine ───────────────────────────────────────────────────────────
+
+impl Port {
+    fn stop(&self) -> bool {
+        let cmd = self.regs + PX_CMD;
+        wr(cmd, rd(cmd) & !CMD_ST);
+        if !wait_reg(cmd, CMD_CR, 0) {
+            return false;
+        }
+        wr(cmd, rd(cmd) & !CMD_FRE);
+        wait_reg(cmd, CMD_FR, 0)
+    }
+
+    fn start(&self) {
+        let cmd = self.regs + PX_CMD;
+        wr(cmd, rd(cmd) | CMD_FRE);
+        wr(cmd, rd(cmd) | CMD_ST);
+    }
+
+    /// Issue one ATA command on slot 0 and wait for completion.
+    /// `bytes` of data move between the device and the bounce buffer.
+    fn exec(&self, command: u8, lba: u64, count: u16, bytes: usize, write: bool) -> Result<(), &'static str> {
+        let mem = self.mem.virt();
+
+        if !wait_reg(self.regs + PX_TFD, TFD_BSY | TFD_DRQ, 0) {
+            return Err("AHCI port busy");
+        }
+
+        // Command header 0: CFL = 5 dwords, W bit, one PRD entry
+        let flags = 5 | if write { 1 << 6 } else { 0 } | if bytes > 0 { 1 << 16 } else { 0 };
+        let ctba = self.mem.phys() + MEM_CTBA;
+        unsafe {
+            let hdr = mem as *mut u32;
+            hdr.write_volatile(flags);
+            hdr.add(1).write_volatile(0); // PRDBC
+            hdr.add(2).write_volatile(ctba as u32);
+            hdr.add(3).write_volatile((ctba >> 32) as u32);
+        }
+
+        // Command table: H2D register FIS + PRDT
+        let ct = mem + MEM_CTBA;
+        unsafe {
+            core::ptr::write_bytes(ct as *mut u8, 0, (CT_PRDT + 16) as usize);
+            let fis = ct as *mut u8;
+            fis.write(FIS_TYPE_H2D);
+            fis.add(1).write(0x80); // C: command register update
+            fis.add(2).write(command);
+            fis.add(4).write(lba as u8);
+            fis.add(5).write((lba >> 8) as u8);
+            fis.add(6).write((lba >> 16) as u8);
+            fis.add(7).write(1 << 6); // LBA mode
+            fis.add(8).write((lba >> 24) as u8);
+            fis.add(9).write((lba >> 32) as u8);
+            fis.add(10).write((lba >> 40) as u8);
+            fis.add(12).write(count as u8);
+            fis.add(13).write((count >> 8) as u8);
+
+            if bytes > 0 {
+                let prd = (ct + CT_PRDT) as *mut u32;
+                prd.write_volatile(self.buf.phys() as u32);
+                prd.add(1).write_volatile((self.buf.phys() >> 32) as u32);
+                prd.add(3).write_volatile((bytes - 1) as u32); // DBC, byte count - 1
+            }
+        }
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+
+        wr(self.regs + PX_IS, u32::MAX);
+        wr(self.regs + PX_CI, 1);
+
+        for _ in 0..SPIN_TIMEOUT {
+            if rd(self.regs + PX_IS) & IS_TFES != 0 {
+                return Err("AHCI task file error");
+            }
+            if rd(self.regs + PX_CI) & 1 == 0 {
+                if rd(self.regs + PX_TFD) & TFD_ERR != 0 {
+                    return Err("AHCI device error");
+                }
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err("AHCI command timeout")
+    }
+
+    fn buf(&self) -> *mut u8 {
+        self.buf.as_ptr()
+    }
+
+    fn identify(&self) -> Option<[u16; 256]> {
+        self.exec(ATA_IDENTIFY, 0, 0, SECTOR_SIZE, false).ok()?;
+        let mut id = [0u16; 256];
+        unsafe {
+            core::ptr::copy_nonoverlapping(self.buf() as *const u16, id.as_mut_ptr(), 256);
+        }
+        Some(id)
+    }
+}
+
+// ─── Block device ────────────────────────────────────────────────────────────
+
+impl BlockDevice for AhciDisk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.id.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        let p = self.port.lock();
+        let cmd = if p.lba48 { ATA_READ_DMA_EX } else { ATA_READ_DMA };
+
+        for (i, chunk) in buf.chunks_mut(BUF_SECTORS * SECTOR_SIZE).enumerate() {
+            let n = chunk.len() / SECTOR_SIZE;
+            let at = lba + (i * BUF_SECTORS) as u64;
+            p.exec(cmd, at, n as u16, chunk.len(), false)?;
+            unsafe { core::ptr::copy_nonoverlapping(p.buf(), chunk.as_mut_ptr(), chunk.len()) };
+        }
+        Ok(())
+    }
+
+    /// Writes are followed by a cache flush, same as the IDE driver.
+    fn write_sectors(&self, lba: u64, count: u16, buf: &[u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        let p = self.port.lock();
+        let cmd = if p.lba48 { ATA_WRITE_DMA_EX } else { ATA_WRITE_DMA };
+
+        for (i, chunk) in buf.chunks(BUF_SECTORS * SECTOR_SIZE).enumerate() {
+            let n = chunk.len() / SECTOR_SIZE;
+            let at = lba + (i * BUF_SECTORS) as u64;
+            unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), p.buf(), chunk.len()) };
+            p.exec(cmd, at, n as u16, chunk.len(), true)?;
+        }
+        p.exec(if p.lba48 { ATA_FLUSH_EX } else { ATA_FLUSH }, 0, 0, 0, false)
+    }
+
+    fn info(&self) -> String {
+        alloc::format!(
+            "{} [AHCI port {}, LBA{}]",
+            self.id.model,
+            self.port_no,
+            if self.id.lba48 { 48 } else { 28 },
+        )
+    }
+}
+
+// ─── Init ─────────────────────────────────────────────────────────────────────
+
+/// Take the HBA from the firmware if it supports BIOS/OS handoff.
+fn bios_handoff(abar: u64) {
+    if rd(abar + HBA_CAP2) & CAP2_BOH == 0 {
+        return;
+    }
+    wr(abar + HBA_BOHC, rd(abar + HBA_BOHC) | BOHC_OOS);
+    if !wait_reg(abar + HBA_BOHC, BOHC_BOS, 0) {
+        log::warn!("AHCI: BIOS did not release the controller");
+    }
+}
+
+/// Bring up port `n`; returns it with the disk's IDENTIFY data.
+fn init_port(abar: u64, n: u32, s64a: bool) -> Option<(Port, Identity)> {
+    let regs = abar + 0x100 + n as u64 * 0x80;
+
+    let ssts = rd(regs + PX_SSTS);
+    if ssts & 0xF != SSTS_DET_PRESENT || (ssts >> 8) & 0xF != SSTS_IPM_ACTIVE {
+        return None;
+    }
+    let sig = rd(regs + PX_SIG);
+    if sig != SIG_ATA {
+        log::info!("AHCI: port {} signature {:#010x} — not a SATA disk, skipped", n, sig);
+        return None;
+    }
+
+    // Without 64-bit addressing the HBA can't reach buffers above 4 GiB
+    let alloc = if s64a { alloc_coherent } else { alloc_coherent_32 };
+    let (Some(mem), Some(buf)) = (alloc(PAGE_SIZE, PAGE_SIZE), alloc(BUF_BYTES, PAGE_SIZE)) else {
+        log::warn!("AHCI: port {}: no memory for DMA buffers", n);
+        return None;
+    };
+
+    let mut port = Port { regs, mem, buf, lba48: false };
+    if !port.stop() {
+        log::warn!("AHCI: port {}: command engine did not stop", n);
+        return None;
+    }
+
+    let mem_phys = port.mem.phys();
+    let fb = mem_phys + MEM_FB;
+    wr(regs + PX_CLB, mem_phys as u32);
+    wr(regs + PX_CLBU, (mem_phys >> 32) as u32);
+    wr(regs + PX_FB, fb as u32);
+    wr(regs + PX_FBU, (fb >> 32) as u32);
+    wr(regs + PX_SERR, u32::MAX);
+    wr(regs + PX_IS, u32::MAX);
+    wr(regs + PX_IE, 0); // polled
+    port.start();
+
+    let Some(id) = port.identify() else {
+        log::warn!("AHCI: port {}: IDENTIFY failed", n);
+        port.stop();
+        return None;
+    };
+    let Some(ident) = Identity::parse(&id) else {
+        port.stop();
+        return None;
+    };
+    port.lba48 = ident.lba48;
+    Some((port, ident))
+}
+
+fn init_controller(dev: &PciDevice) {
+    pci::enable_bus_master(dev.bus, dev.dev, dev.func);
+
+    let Some(abar) = map_mmio(dev.bar_base(5), ABAR_SIZE) else {
+        log::warn!("AHCI: cannot map ABAR {:#x}", dev.bar_base(5));
+        return;
+    };
+
+    bios_handoff(abar);
+    wr(abar + HBA_GHC, rd(abar + HBA_GHC) | GHC_AE);
+
+    let cap = rd(abar + HBA_CAP);
+    let vs = rd(abar + HBA_VS);
+    let pi = rd(abar + HBA_PI);
+    log::info!(
+        "AHCI: {:02x}:{:02x}.{} v{}.{} ports={:#x} abar={:#x}",
+        dev.bus, dev.dev, dev.func,
+        vs >> 16, (vs >> 8) & 0xFF,
+        pi, dev.bar_base(5),
+    );
+
+    for n in (0..32).filter(|n| pi & (1 << n) != 0) {
+        let Some((port, id)) = init_port(abar, n, cap & CAP_S64A != 0) else { continue };
+
+        log::info!("AHCI: port {} — {}  s/n: {}", n, id.model, id.serial);
+        block::register(Arc::new(AhciDisk {
+            name: block::next_name("sd"),
+            port_no: n,
+            id,
+            port: SpinLock::new(port),
+        }));
+    }
+}
+
+pub fn init() {
+    let mut ctrls = Vec::new();
+    pci::devices(|d| {
+        if d.is_ahci() {
+            ctrls.push(d.clone());
+        }
+    });
+    if ctrls.is_empty() {
+        log::info!("AHCI: no controllers found");
+    }
+    for dev in &ctrls {
+        init_controller(dev);
+    }
+}
@@ -0,0 +1,285 @@
+/// Sector cache in front of every registered block device.
+///
+/// One global LRU of 512-byte sectors keyed by (device, lba). Reads are
+/// served from it when possible and misses are fetched in runs. Writes
+/// either go straight to the disk (write-through) or only mark the cached
+/// sector dirty (write-back) until `sync()` or eviction writes it out.
+use super::block::{BlockDevice, SECTOR_SIZE};
+use crate::sync::mutex::Mutex;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// 2048 sectors = 1 MiB of cached data.
+const CAPACITY: usize = 2048;
+
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    WriteThrough,
+    WriteBack,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub hits: u64,
+    pub misses: u64,
+    pub writebacks: u64,
+    pub cached: usize,
+    pub dirty: usize,
+}
+
+type Key = (usize, u64); // (device id, lba)
+
+struct Entry {
+    key: Key,
+    data: Box<[u8; SECTOR_SIZE]>,
+    dirty: bool,
+    prev: usize,
+    next: usize,
+}
+
+struct Cache {
+    policy: Policy,
+    map: BTreeMap<Key, usize>,
+    entries: Vec<Entry>,
+    /// LRU list through `entries`: head = most recently used.
+    head: usize,
+    tail: usize,
+    /// Underlying devices by id, for write-back on eviction.
+    devs: Vec<Arc<dyn BlockDevice>>,
+    stats: Stats,
+}
+
+/// Held across the device I/O of misses and write-backs, hence a Mutex.
+static CACHE: Mutex<Cache> = Mutex::new(Cache {
+    policy: Policy::WriteBack,
+    map: BTreeMap::new(),
+    entries: Vec::new(),
+    head: NIL,
+    tail: NIL,
+    devs: Vec::new(),
+    stats: Stats { hits: 0, misses: 0, writebacks: 0, cached: 0, dirty: 0 },
+});
+
+impl Cache {
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.entries[i].prev, self.entries[i].next);
+        if prev != NIL {
+            self.entries[prev].next = next;
+        } else {
+            self.head = next;
+        }
+        if next != NIL {
+            self.entries[next].prev = prev;
+        } else {
+            self.tail = prev;
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.entries[i].prev = NIL;
+        self.entries[i].next = self.head;
+        if self.head != NIL {
+            self.entries[self.head].prev = i;
+        }
+        self.head = i;
+        if self.tail == NIL {
+            self.tail = i;
+        }
+    }
+
+    fn touch(&mut self, i: usize) {
+        if self.head != i {
+            self.unlink(i);
+            self.push_front(i);
+        }
+    }
+
+    fn write_out(&mut self, i: usize) -> Result<(), &'static str> {
+        let (dev, lba) = self.entries[i].key;
+        self.devs[dev].write_sectors(lba, 1, &self.entries[i].data[..])?;
+        self.entries[i].dirty = false;
+        self.stats.writebacks += 1;
+        Ok(())
+    }
+
+    /// Slot for `key`: a fresh one while below capacity, else the LRU victim
+    /// (written out first if dirty).
+    fn slot_for(&mut self, key: Key) -> Result<usize, &'static str> {
+        let i = if self.entries.len() < CAPACITY {
+            self.entries.push(Entry {
+                key,
+                data: Box::new([0; SECTOR_SIZE]),
+                dirty: false,
+                prev: NIL,
+                next: NIL,
+            });
+            self.entries.len() - 1
+        } else {
+            let victim = self.tail;
+            if self.entries[victim].dirty {
+                self.write_out(victim)?;
+            }
+            self.unlink(victim);
+            let old = self.entries[victim].key;
+            self.map.remove(&old);
+            self.entries[victim].key = key;
+            victim
+        };
+        self.map.insert(key, i);
+        self.push_front(i);
+        Ok(i)
+    }
+
+    /// Store `data` for `key`, replacing any cached copy.
+    fn store(&mut self, key: Key, data: &[u8], dirty: bool) -> Result<(), &'static str> {
+        let i = match self.map.get(&key) {
+            Some(&i) => {
+                self.touch(i);
+                i
+            }
+            None => self.slot_for(key)?,
+        };
+        let e = &mut self.entries[i];
+        e.data.copy_from_slice(data);
+        e.dirty |= dirty;
+        Ok(())
+    }
+
+    /// Write every dirty sector out, in (device, lba) order.
+    fn flush(&mut self) -> Result<usize, &'static str> {
+        let dirty: Vec<usize> = self
+            .map
+            .values()
+            .copied()
+            .filter(|&i| self.entries[i].dirty)
+            .collect();
+        for &i in &dirty {
+            self.write_out(i)?;
+        }
+        Ok(dirty.len())
+    }
+}
+
+// ─── Cached device ───────────────────────────────────────────────────────────
+
+/// What the block registry hands out: the raw driver behind the cache.
+struct CachedDevice {
+    id: usize,
+    inner: Arc<dyn BlockDevice>,
+}
+
+/// Put `dev` behind the cache.
+pub fn wrap(dev: Arc<dyn BlockDevice>) -> Arc<dyn BlockDevice> {
+    let id = {
+        let mut c = CACHE.lock();
+        c.devs.push(Arc::clone(&dev));
+        c.devs.len() - 1
+    };
+    Arc::new(CachedDevice { id, inner: dev })
+}
+
+impl BlockDevice for CachedDevice {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn read_sectors(&self, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        let mut c = CACHE.lock();
+        let count = count as usize;
+        let sector = |i: usize| i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE;
+
+        let mut i = 0;
+        while i < count {
+            let key = (self.id, lba + i as u64);
+            if let Some(&e) = c.map.get(&key) {
+                c.touch(e);
+                c.stats.hits += 1;
+                buf[sector(i)].copy_from_slice(&c.entries[e].data[..]);
+                i += 1;
+                continue;
+            }
+
+            // Fetch the whole run of missing sectors in one request
+            let mut j = i + 1;
+            while j < count && !c.map.contains_key(&(self.id, lba + j as u64)) {
+                j += 1;
+            }
+            let run = &mut buf[i * SECTOR_SIZE..j * SECTOR_SIZE];
+            self.inner.read_sectors(lba + i as u64, (j - i) as u16, run)?;
+            c.stats.misses += (j - i) as u64;
+            for k in i..j {
+                c.store((self.id, lba + k as u64), &buf[sector(k)], false)?;
+            }
+            i = j;
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, count: u16, buf: &[u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        if self.inner.read_only() {
+            return Err("read-only device");
+        }
+        let mut c = CACHE.lock();
+        let write_back = c.policy == Policy::WriteBack;
+        if !write_back {
+            self.inner.write_sectors(lba, count, buf)?;
+        }
+        for (i, data) in buf.chunks(SECTOR_SIZE).enumerate() {
+            c.store((self.id, lba + i as u64), data, write_back)?;
+        }
+        Ok(())
+    }
+
+    fn read_only(&self) -> bool {
+        self.inner.read_only()
+    }
+
+    fn info(&self) -> String {
+        self.inner.info()
+    }
+
+    fn smart(&self) -> Option<Result<super::smart::Report, &'static str>> {
+        self.inner.smart()
+    }
+}
+
+// ─── Public control ──────────────────────────────────────────────────────────
+
+/// Write all dirty sectors to disk; returns how many were written.
+pub fn sync() -> Result<usize, &'static str> {
+    CACHE.lock().flush()
+}
+
+/// Switch policy. Going to write-through flushes what is dirty first.
+pub fn set_policy(policy: Policy) -> Result<(), &'static str> {
+    let mut c = CACHE.lock();
+    if policy == Policy::WriteThrough {
+        c.flush()?;
+    }
+    c.policy = policy;
+    Ok(())
+}
+
+pub fn policy() -> Policy {
+    CACHE.lock().policy
+}
+
+pub fn stats() -> Stats {
+    let c = CACHE.lock();
+    Stats {
+        cached: c.entries.len(),
+        dirty: c.entries.iter().filter(|e| e.dirty).count(),
+        ..c.stats
+    }
+}
@@ -0,0 +1,122 @@
+/// Generic block-device layer.
+/// Disk drivers register an `Arc<dyn BlockDevice>` here; filesystems and the
+/// partition reader only ever see the trait, never a concrete controller.
+/// Partitions found on a disk are kept apart from the disks, each with its
+/// disk, and are looked up by name like them.
+use super::partition::{self, Partition};
+use crate::sync::spinlock::SpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub const SECTOR_SIZE: usize = 512;
+
+pub trait BlockDevice: Send + Sync {
+    /// Short device name, e.g. "hda" or "sda".
+    fn name(&self) -> &str;
+
+    /// Total number of addressable 512-byte sectors.
+    fn sector_count(&self) -> u64;
+
+    /// Read `count` sectors at `lba` into `buf` (`count * 512` bytes).
+    fn read_sectors(&self, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str>;
+
+    /// Write `count` sectors at `lba` from `buf` (`count * 512` bytes).
+    fn write_sectors(&self, lba: u64, count: u16, buf: &[u8]) -> Result<(), &'static str>;
+
+    /// Media that refuse writes (CD-ROM).
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Human-readable description for `drives` (model, bus, ...).
+    fn info(&self) -> String {
+        String::new()
+    }
+
+    /// The drive's S.M.A.R.T. status and attributes; None if it has none.
+    fn smart(&self) -> Option<Result<super::smart::Report, &'static str>> {
+        None
+    }
+
+    fn size_mb(&self) -> u64 {
+        self.sector_count() * SECTOR_SIZE as u64 / 1024 / 1024
+    }
+}
+
+// ─── Registry ────────────────────────────────────────────────────────────────
+
+static DEVICES: SpinLock<Vec<Arc<dyn BlockDevice>>> = SpinLock::new(Vec::new());
+static PARTITIONS: SpinLock<Vec<Arc<Partition>>> = SpinLock::new(Vec::new());
+
+/// Add a device behind the sector cache, and its partitions; returns its
+/// index.
+pub fn register(dev: Arc<dyn BlockDevice>) -> usize {
+    log::info!("block: {} — {} MiB {}", dev.name(), dev.size_mb(), dev.info());
+    let dev = super::bcache::wrap(dev);
+    let idx = {
+        let mut devs = DEVICES.lock();
+        devs.push(Arc::clone(&dev));
+        devs.len() - 1
+    };
+    partition::scan(&dev);
+    idx
+}
+
+/// Add a partition found by `partition::scan`. It is already cached, through
+/// its disk.
+pub fn register_partition(part: Arc<Partition>) {
+    log::info!("block: {} — {} MiB {}", part.name(), part.size_mb(), part.info());
+    PARTITIONS.lock().push(part);
+}
+
+pub fn count() -> usize {
+    DEVICES.lock().len()
+}
+
+pub fn get(idx: usize) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().get(idx).cloned()
+}
+
+/// A disk or partition by name.
+pub fn by_name(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    if let Some(d) = DEVICES.lock().iter().find(|d| d.name() == name) {
+        return Some(Arc::clone(d));
+    }
+    let parts = PARTITIONS.lock();
+    let part = parts.iter().find(|p| p.name() == name)?;
+    Some(Arc::clone(part) as Arc<dyn BlockDevice>)
+}
+
+/// Look a device up by index ("0") or by name ("hda", "hda1").
+pub fn lookup(spec: &str) -> Option<Arc<dyn BlockDevice>> {
+    match spec.parse::<usize>() {
+        Ok(idx) => get(idx),
+        Err(_) => by_name(spec.trim_start_matches("/dev/")),
+    }
+}
+
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
+
+/// The partitions on disk `disk`, in table order.
+pub fn partitions(disk: &str) -> Vec<Arc<Partition>> {
+    PARTITIONS.lock().iter().filter(|p| p.disk_name() == disk).cloned().collect()
+}
+
+/// Partition `index` (from 1) on `disk`.
+pub fn partition(disk: &str, index: usize) -> Option<Arc<Partition>> {
+    PARTITIONS
+        .lock()
+        .iter()
+        .find(|p| p.disk_name() == disk && p.index == index)
+        .cloned()
+}
+
+/// Next free name with `prefix`: "hd" → "hda", "hdb", ...
+pub fn next_name(prefix: &str) -> String {
+    let devs = DEVICES.lock();
+    let taken = devs.iter().filter(|d| d.name().starts_with(prefix)).count();
+    alloc::format!("{}{}", prefix, (b'a' + taken as u8) as char)
+}
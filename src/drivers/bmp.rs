@@ -1,3 +1,6 @@
+/// Images for `view` and `draw_bitmap`: BMP decoded here, PNG in `png.rs`,
+/// both to the same `Bitmap`.
+use crate::drivers::png;
 use crate::drivers::vga::Color;
 use alloc::vec::Vec;
 
@@ -7,53 +10,126 @@ pub struct Bitmap {
     pub pixels: Vec<Color>, // 0x00RRGGBB, row-major, top-to-bottom
 }
 
-/// Decode a 24-bit uncompressed BMP.  Returns None on any format mismatch.
-pub fn decode(data: &[u8]) -> Option<Bitmap> {
-    if data.len() < 54 {
-        return None;
-    }
-    if data[0] != b'B' || data[1] != b'M' {
-        return None;
+/// The most pixels an image may have: 16M of them take 64 MiB to hold.
+pub const MAX_PIXELS: usize = 1 << 24;
+
+/// A BMP or a PNG, told apart by their magic.
+pub fn decode_image(data: &[u8]) -> Result<Bitmap, &'static str> {
+    if data.starts_with(&png::SIGNATURE) {
+        png::decode(data)
+    } else if data.starts_with(b"BM") {
+        decode(data)
+    } else {
+        Err("not a BMP or PNG image")
     }
+}
 
-    let pixel_offset = u32::from_le_bytes([data[10], data[11], data[12], data[13]]) as usize;
-    let width = u32::from_le_bytes([data[18], data[19], data[20], data[21]]) as usize;
-    let height_raw = i32::from_le_bytes([data[22], data[23], data[24], data[25]]);
-    let bpp = u16::from_le_bytes([data[28], data[29]]) as usize;
-    let compression = u32::from_le_bytes([data[30], data[31], data[32], data[33]]);
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
 
-    if bpp != 24 || compression != 0 || width == 0 {
-        return None;
-    }
+fn u16_at(d: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([d[at], d[at + 1]])
+}
 
-    let (height, bottom_up) = if height_raw < 0 {
-        ((-height_raw) as usize, false)
-    } else {
-        (height_raw as usize, true)
-    };
+fn u32_at(d: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([d[at], d[at + 1], d[at + 2], d[at + 3]])
+}
 
-    if height == 0 {
-        return None;
+/// Decode an uncompressed BMP: 1, 4 or 8 bits per pixel through its
+/// palette, 24-bit, or 32-bit as BGRx or with BI_BITFIELDS masks.
+pub fn decode(data: &[u8]) -> Result<Bitmap, &'static str> {
+    if data.len() < 54 || !data.starts_with(b"BM") {
+        return Err("not a BMP image");
+    }
+    let header_size = u32_at(data, 14) as usize;
+    if header_size < 40 {
+        return Err("OS/2 BMP headers not supported");
     }
+    let pixel_offset = u32_at(data, 10) as usize;
+    let width = u32_at(data, 18) as i32;
+    let height_raw = u32_at(data, 22) as i32;
+    let bpp = u16_at(data, 28) as usize;
+    let compression = u32_at(data, 30);
+    let colors_used = u32_at(data, 46) as usize;
 
-    // Each row is padded to a 4-byte boundary
-    let row_bytes = (width * 3 + 3) & !3;
+    match (bpp, compression) {
+        (1 | 4 | 8 | 24 | 32, BI_RGB) | (32, BI_BITFIELDS) => {}
+        (_, BI_RGB | BI_BITFIELDS) => return Err("unsupported BMP bit depth"),
+        _ => return Err("compressed BMPs not supported"),
+    }
+    if width <= 0 || height_raw == 0 || height_raw == i32::MIN {
+        return Err("empty image");
+    }
+    let width = width as usize;
+    // Rows are stored bottom-up unless the height is negative
+    let (height, bottom_up) = (height_raw.unsigned_abs() as usize, height_raw > 0);
+    if width.checked_mul(height).is_none_or(|n| n > MAX_PIXELS) {
+        return Err("image too large");
+    }
 
-    if data.len() < pixel_offset + row_bytes * height {
-        return None;
+    // Each row is padded to a 4-byte boundary
+    let row_bytes = (width * bpp).div_ceil(32) * 4;
+    if pixel_offset.checked_add(row_bytes * height).is_none_or(|end| end > data.len()) {
+        return Err("BMP pixel data cut short");
     }
 
+    // Palette entries are BGR plus a pad byte, right after the header
+    let palette: Vec<Color> = if bpp <= 8 {
+        let count = if colors_used == 0 { 1 << bpp } else { colors_used.min(256) };
+        let table = data.get(14 + header_size..).unwrap_or(&[]);
+        table.chunks_exact(4).take(count).map(|e| u32_at(e, 0) & 0x00FF_FFFF).collect()
+    } else {
+        Vec::new()
+    };
+    // BI_BITFIELDS masks sit at the same place whether the header is the
+    // 40-byte one they follow or a V4/V5 one that holds them
+    let masks = if compression == BI_BITFIELDS {
+        if data.len() < 66 {
+            return Err("BMP bit masks cut short");
+        }
+        [u32_at(data, 54), u32_at(data, 58), u32_at(data, 62)]
+    } else {
+        [0x00FF_0000, 0x0000_FF00, 0x0000_00FF]
+    };
+
     let mut pixels = Vec::with_capacity(width * height);
     for y in 0..height {
         let src_y = if bottom_up { height - 1 - y } else { y };
-        let row_start = pixel_offset + src_y * row_bytes;
+        let row = &data[pixel_offset + src_y * row_bytes..][..row_bytes];
         for x in 0..width {
-            let b = data[row_start + x * 3] as u32;
-            let g = data[row_start + x * 3 + 1] as u32;
-            let r = data[row_start + x * 3 + 2] as u32;
-            pixels.push((r << 16) | (g << 8) | b);
+            let color = match bpp {
+                32 => {
+                    let v = u32_at(row, x * 4);
+                    let [r, g, b] = masks.map(|m| channel(v, m));
+                    (r << 16) | (g << 8) | b
+                }
+                24 => {
+                    let p = &row[x * 3..x * 3 + 3];
+                    ((p[2] as u32) << 16) | ((p[1] as u32) << 8) | p[0] as u32
+                }
+                _ => {
+                    let bit = x * bpp;
+                    let index = (row[bit / 8] >> (8 - bpp - bit % 8)) & ((1u16 << bpp) - 1) as u8;
+                    palette.get(index as usize).copied().unwrap_or(0)
+                }
+            };
+            pixels.push(color);
         }
     }
 
-    Some(Bitmap { width, height, pixels })
+    Ok(Bitmap { width, height, pixels })
+}
+
+/// The bits of `v` under `mask`, scaled to 0..=255.
+fn channel(v: u32, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = (mask >> mask.trailing_zeros()).count_ones();
+    let value = (v & mask) >> mask.trailing_zeros();
+    if bits >= 8 {
+        value >> (bits - 8)
+    } else {
+        value * 255 / ((1 << bits) - 1)
+    }
 }
@@ -0,0 +1,465 @@
+/// Intel 8254x / 82574 (e1000, e1000e) Ethernet driver.
+/// Legacy descriptors: one receive and one transmit ring of `RING_SIZE`
+/// entries, each entry with its own 2 KiB DMA buffer. The interrupt
+/// handler drains finished receive descriptors into the NIC layer's queue
+/// (`net::deliver`); `transmit` copies the frame into the next transmit
+/// buffer, which is free again once the NIC sets its descriptor-done bit.
+/// Without a usable interrupt line the receive ring is polled from a timer.
+use super::net::{self, MacAddr, NetDevice};
+use super::pci::{self, PciDevice};
+use crate::arch::x86_64::io::Mmio;
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::mm::dma::{alloc_coherent, DmaBuffer};
+use crate::sync::spinlock::SpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, AtomicU16, AtomicUsize, Ordering};
+
+/// (device id, model) of the supported Intel NICs.
+const MODELS: &[(u16, &str)] = &[
+    (0x100E, "82540EM"), // QEMU's default `-nic model=e1000`
+    (0x100F, "82545EM"),
+    (0x10D3, "82574L"), // QEMU's `e1000e`
+];
+
+const INTEL: u16 = 0x8086;
+
+// ─── Registers ────────────────────────────────────────────────────────────────
+
+const REG_CTRL: u64 = 0x0000;
+const REG_STATUS: u64 = 0x0008;
+const REG_EERD: u64 = 0x0014;
+const REG_ICR: u64 = 0x00C0;
+const REG_IMS: u64 = 0x00D0;
+const REG_IMC: u64 = 0x00D8;
+const REG_RCTL: u64 = 0x0100;
+const REG_TCTL: u64 = 0x0400;
+const REG_TIPG: u64 = 0x0410;
+const REG_RDBAL: u64 = 0x2800;
+const REG_RDBAH: u64 = 0x2804;
+const REG_RDLEN: u64 = 0x2808;
+const REG_RDH: u64 = 0x2810;
+const REG_RDT: u64 = 0x2818;
+const REG_TDBAL: u64 = 0x3800;
+const REG_TDBAH: u64 = 0x3804;
+const REG_TDLEN: u64 = 0x3808;
+const REG_TDH: u64 = 0x3810;
+const REG_TDT: u64 = 0x3818;
+const REG_MTA: u64 = 0x5200;
+const REG_RAL0: u64 = 0x5400;
+const REG_RAH0: u64 = 0x5404;
+
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const RAH_AV: u32 = 1 << 31;
+
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+/// Strip the FCS: frames reach `net` without it.
+const RCTL_SECRC: u32 = 1 << 26;
+// BSIZE = 00 with BSEX = 0: 2048-byte receive buffers
+
+const TCTL_EN: u32 = 1 << 1;
+/// Pad short packets to the 64-byte minimum.
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x0F << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+/// IPGT 10, IPGR1 8, IPGR2 6: the values the manual gives for copper.
+const TIPG_COPPER: u32 = 10 | (8 << 10) | (6 << 20);
+
+// Interrupt causes (ICR / IMS)
+const INT_LSC: u32 = 1 << 2;
+const INT_RXDMT0: u32 = 1 << 4;
+const INT_RXO: u32 = 1 << 6;
+const INT_RXT0: u32 = 1 << 7;
+const INT_RX: u32 = INT_RXDMT0 | INT_RXO | INT_RXT0;
+
+const MMIO_SIZE: u64 = 0x20000;
+
+// ─── Descriptors ──────────────────────────────────────────────────────────────
+
+/// Descriptors per ring; the ring length must be a multiple of 128 bytes.
+const RING_SIZE: usize = 32;
+const BUF_SIZE: usize = 2048;
+/// Descriptor rings start on a 128-byte boundary at least.
+const RING_ALIGN: usize = 4096;
+
+const RX_STA_DD: u8 = 1 << 0;
+const RX_STA_EOP: u8 = 1 << 1;
+
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+const TX_STA_DD: u8 = 1 << 0;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct RxDesc {
+    addr: u64,
+    length: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct TxDesc {
+    addr: u64,
+    length: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+/// Receive polling period when there is no interrupt.
+const POLL_NS: u64 = 10_000_000;
+
+const SPIN_TIMEOUT: u32 = 1_000_000;
+
+// ─── Rings ────────────────────────────────────────────────────────────────────
+
+struct Ring {
+    /// RING_SIZE descriptors, and RING_SIZE buffers of BUF_SIZE bytes.
+    desc: DmaBuffer,
+    bufs: DmaBuffer,
+    /// Next descriptor the driver looks at.
+    next: usize,
+}
+
+impl Ring {
+    fn new() -> Option<Ring> {
+        Some(Ring {
+            desc: alloc_coherent(RING_SIZE * core::mem::size_of::<RxDesc>(), RING_ALIGN)?,
+            bufs: alloc_coherent(RING_SIZE * BUF_SIZE, RING_ALIGN)?,
+            next: 0,
+        })
+    }
+
+    fn buf_phys(&self, i: usize) -> u64 {
+        self.bufs.phys() + (i * BUF_SIZE) as u64
+    }
+
+    fn buf(&self, i: usize) -> *mut u8 {
+        unsafe { self.bufs.as_ptr::<u8>().add(i * BUF_SIZE) }
+    }
+
+    fn desc<T>(&self, i: usize) -> *mut T {
+        unsafe { self.desc.as_ptr::<T>().add(i) }
+    }
+}
+
+// ─── NIC ──────────────────────────────────────────────────────────────────────
+
+struct E1000 {
+    name: String,
+    model: &'static str,
+    regs: Mmio,
+    mac: MacAddr,
+    /// Legacy interrupt line, or None when polled.
+    irq: Option<u8>,
+    rx: SpinLock<Ring>,
+    tx: SpinLock<Ring>,
+    /// Index in the NIC layer, once registered.
+    index: AtomicUsize,
+}
+
+impl E1000 {
+    fn reg(&self, off: u64) -> u32 {
+        self.regs.read32(off)
+    }
+
+    fn set_reg(&self, off: u64, val: u32) {
+        self.regs.write32(off, val)
+    }
+
+    /// Hand every finished receive descriptor's frame to the NIC layer and
+    /// give the descriptors back to the hardware.
+    fn drain_rx(&self) {
+        let index = self.index.load(Ordering::Relaxed);
+        let mut rx = self.rx.lock();
+        loop {
+            let i = rx.next;
+            let d = rx.desc::<RxDesc>(i);
+            let desc = unsafe { d.read_volatile() };
+            if desc.status & RX_STA_DD == 0 {
+                break;
+            }
+            // Frames are at most 1514 bytes and always fit one buffer;
+            // anything else (errors, a frame split over buffers) is dropped
+            let len = desc.length as usize;
+            if desc.status & RX_STA_EOP != 0 && desc.errors == 0 && len <= BUF_SIZE {
+                let frame = unsafe { core::slice::from_raw_parts(rx.buf(i), len) };
+                net::deliver(index, frame);
+            }
+            unsafe { core::ptr::addr_of_mut!((*d).status).write_volatile(0) };
+            rx.next = (i + 1) % RING_SIZE;
+            fence(Ordering::SeqCst);
+            // The tail trails the descriptor just handed back
+            self.set_reg(REG_RDT, i as u32);
+        }
+    }
+
+    fn interrupt(&self) {
+        let icr = self.reg(REG_ICR); // reading clears the causes
+        if icr & INT_RX != 0 {
+            self.drain_rx();
+        }
+        if icr & INT_LSC != 0 {
+            log::info!("{}: link {}", self.name, if self.link_up() { "up" } else { "down" });
+        }
+    }
+}
+
+impl NetDevice for E1000 {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    fn link_up(&self) -> bool {
+        self.reg(REG_STATUS) & STATUS_LU != 0
+    }
+
+    fn transmit(&self, frame: &[u8]) -> Result<(), &'static str> {
+        if frame.len() > BUF_SIZE {
+            return Err("frame too long");
+        }
+        let mut tx = self.tx.lock();
+        let i = tx.next;
+        let d = tx.desc::<TxDesc>(i);
+        // Every descriptor starts out done; wait for this one to come back
+        let mut spins = 0;
+        while unsafe { d.read_volatile() }.status & TX_STA_DD == 0 {
+            spins += 1;
+            if spins == SPIN_TIMEOUT {
+                return Err("transmit ring full");
+            }
+            core::hint::spin_loop();
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(frame.as_ptr(), tx.buf(i), frame.len());
+            d.write_volatile(TxDesc {
+                addr: tx.buf_phys(i),
+                length: frame.len() as u16,
+                cso: 0,
+                cmd: TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS,
+                status: 0,
+                css: 0,
+                special: 0,
+            });
+        }
+        tx.next = (i + 1) % RING_SIZE;
+        fence(Ordering::SeqCst);
+        self.set_reg(REG_TDT, tx.next as u32);
+        Ok(())
+    }
+
+    fn info(&self) -> String {
+        match self.irq {
+            Some(irq) => alloc::format!("[Intel {}, IRQ {}]", self.model, irq),
+            None => alloc::format!("[Intel {}, polled]", self.model),
+        }
+    }
+}
+
+static NICS: SpinLock<Vec<Arc<E1000>>> = SpinLock::new(Vec::new());
+
+/// Interrupt lines some NIC uses, one bit per line.
+static IRQ_MASK: AtomicU16 = AtomicU16::new(0);
+
+/// Whether `irq` belongs to one of the NICs (`idt::irq_dispatch`).
+pub fn owns_irq(irq: u8) -> bool {
+    irq < 16 && IRQ_MASK.load(Ordering::Relaxed) & (1 << irq) != 0
+}
+
+/// Called from the IRQ handler of a NIC's interrupt line, which other
+/// devices may share: the ones with no cause pending ignore it.
+pub fn irq_e1000(irq: u8) {
+    let nics: Vec<Arc<E1000>> = NICS
+        .lock()
+        .iter()
+        .filter(|n| n.irq == Some(irq))
+        .cloned()
+        .collect();
+    for nic in nics {
+        nic.interrupt();
+    }
+}
+
+/// Timer callback for NICs without an interrupt: drain the receive rings
+/// and come back in `POLL_NS`.
+fn poll(_: u64) {
+    let nics: Vec<Arc<E1000>> = NICS.lock().iter().filter(|n| n.irq.is_none()).cloned().collect();
+    for nic in nics {
+        nic.drain_rx();
+    }
+    let now = crate::arch::x86_64::timer::nanos();
+    crate::proc::timers::call_at(now + POLL_NS, poll, 0);
+}
+
+// ─── Init ─────────────────────────────────────────────────────────────────────
+
+/// One word of the EEPROM through EERD. The 82574 moved the address and
+/// the done bit.
+fn eeprom_read(regs: Mmio, word: u8, e1000e: bool) -> Option<u16> {
+    let (start, done) = if e1000e {
+        (((word as u32) << 2) | 1, 1 << 1)
+    } else {
+        (((word as u32) << 8) | 1, 1 << 4)
+    };
+    regs.write32(REG_EERD, start);
+    for _ in 0..SPIN_TIMEOUT {
+        let v = regs.read32(REG_EERD);
+        if v & done != 0 {
+            return Some((v >> 16) as u16);
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+/// The MAC address: receive address 0 as firmware left it, else from the
+/// EEPROM (words 0–2).
+fn read_mac(regs: Mmio, e1000e: bool) -> Option<MacAddr> {
+    let (lo, hi) = (regs.read32(REG_RAL0), regs.read32(REG_RAH0));
+    if hi & RAH_AV != 0 {
+        let l = lo.to_le_bytes();
+        let h = hi.to_le_bytes();
+        return Some([l[0], l[1], l[2], l[3], h[0], h[1]]);
+    }
+    let mut mac = [0; 6];
+    for w in 0..3 {
+        let v = eeprom_read(regs, w, e1000e)?.to_le_bytes();
+        mac[w as usize * 2] = v[0];
+        mac[w as usize * 2 + 1] = v[1];
+    }
+    Some(mac)
+}
+
+fn init_nic(dev: &PciDevice, model: &'static str) -> Result<(), &'static str> {
+    if dev.bar_is_io(0) {
+        return Err("BAR0 is not memory-mapped");
+    }
+    pci::enable_bus_master(dev.bus, dev.dev, dev.func);
+    let regs = unsafe { Mmio::map(dev.bar_base(0), MMIO_SIZE) }.ok_or("cannot map BAR0")?;
+    let e1000e = dev.device_id == 0x10D3;
+
+    // Reset with interrupts masked, then force the link up
+    regs.write32(REG_IMC, u32::MAX);
+    regs.write32(REG_CTRL, regs.read32(REG_CTRL) | CTRL_RST);
+    let mut spins = 0;
+    while regs.read32(REG_CTRL) & CTRL_RST != 0 {
+        spins += 1;
+        if spins == SPIN_TIMEOUT {
+            return Err("reset timeout");
+        }
+        core::hint::spin_loop();
+    }
+    regs.write32(REG_IMC, u32::MAX);
+    regs.read32(REG_ICR);
+    regs.write32(REG_CTRL, regs.read32(REG_CTRL) | CTRL_SLU | CTRL_ASDE);
+
+    let mac = read_mac(regs, e1000e).ok_or("cannot read the MAC address")?;
+    let l = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
+    let h = u16::from_le_bytes([mac[4], mac[5]]) as u32;
+    regs.write32(REG_RAL0, l);
+    regs.write32(REG_RAH0, h | RAH_AV);
+    for i in 0..128 {
+        regs.write32(REG_MTA + i * 4, 0);
+    }
+
+    // Both rings before either is handed over: a ring dropped on the
+    // error path must not be one the NIC already writes to
+    let rx = Ring::new().ok_or("out of memory")?;
+    let tx = Ring::new().ok_or("out of memory")?;
+
+    // Receive ring: every descriptor but one owned by the hardware
+    for i in 0..RING_SIZE {
+        unsafe {
+            rx.desc::<RxDesc>(i).write_volatile(RxDesc {
+                addr: rx.buf_phys(i),
+                length: 0,
+                checksum: 0,
+                status: 0,
+                errors: 0,
+                special: 0,
+            });
+        }
+    }
+    let ring_bytes = (RING_SIZE * core::mem::size_of::<RxDesc>()) as u32;
+    regs.write32(REG_RDBAL, rx.desc.phys() as u32);
+    regs.write32(REG_RDBAH, (rx.desc.phys() >> 32) as u32);
+    regs.write32(REG_RDLEN, ring_bytes);
+    regs.write32(REG_RDH, 0);
+    regs.write32(REG_RDT, (RING_SIZE - 1) as u32);
+    regs.write32(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+
+    // Transmit ring: every descriptor free (done)
+    for i in 0..RING_SIZE {
+        unsafe {
+            (*tx.desc::<TxDesc>(i)).status = TX_STA_DD;
+        }
+    }
+    regs.write32(REG_TDBAL, tx.desc.phys() as u32);
+    regs.write32(REG_TDBAH, (tx.desc.phys() >> 32) as u32);
+    regs.write32(REG_TDLEN, ring_bytes);
+    regs.write32(REG_TDH, 0);
+    regs.write32(REG_TDT, 0);
+    regs.write32(REG_TIPG, TIPG_COPPER);
+    regs.write32(REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+
+    let irq = (dev.irq_pin != 0 && dev.irq_line < 16).then_some(dev.irq_line);
+    let nic = Arc::new(E1000 {
+        name: net::next_name(),
+        model,
+        regs,
+        mac,
+        irq,
+        rx: SpinLock::new(rx),
+        tx: SpinLock::new(tx),
+        index: AtomicUsize::new(usize::MAX),
+    });
+    let index = net::register(nic.clone());
+    nic.index.store(index, Ordering::Relaxed);
+    NICS.lock().push(nic);
+
+    match irq {
+        Some(irq) => {
+            IRQ_MASK.fetch_or(1 << irq, Ordering::Relaxed);
+            crate::arch::x86_64::apic::enable_irq(irq);
+            regs.write32(REG_IMS, INT_RX | INT_LSC);
+        }
+        None => {
+            if NICS.lock().iter().filter(|n| n.irq.is_none()).count() == 1 {
+                crate::proc::timers::call_at(0, poll, 0);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn init() {
+    let mut nics = Vec::new();
+    pci::devices(|d| {
+        if d.vendor_id == INTEL {
+            if let Some(&(_, model)) = MODELS.iter().find(|(id, _)| *id == d.device_id) {
+                nics.push((d.clone(), model));
+            }
+        }
+    });
+    for (dev, model) in &nics {
+        if let Err(e) = init_nic(dev, model) {
+            log::warn!("e1000: {:02x}:{:02x}.{}: {}", dev.bus, dev.dev, dev.func, e);
+        }
+    }
+}
@@ -1,16 +1,39 @@
 /// ATA PIO driver for IDE controllers.
 /// Supports LBA28/LBA48, master/slave on primary and secondary channels.
-use crate::arch::x86_64::io::{inb, inw, outb, outw};
+/// Each detected drive is registered as a block device (hda, hdb, ...);
+/// ATAPI CD/DVD drives are read through PACKET commands (cda, ...).
+///
+/// Disk transfers are interrupt-driven: a caller queues its request on the
+/// channel and sleeps, and the channel's IRQ (14 or 15) moves each sector
+/// and starts the next request when one completes. Behind a PCI IDE
+/// controller that can bus-master, drives with Ultra DMA move their data
+/// by DMA through a per-channel bounce buffer; PIO is the fallback.
+use super::block::{self, BlockDevice};
+use super::pci;
+use super::smart;
+use crate::arch::x86_64::io::{inb, inw, outb, outl, outw, read_rflags, RFLAGS_IF};
+use crate::arch::x86_64::timer::nanos;
+use crate::mm::dma::{alloc_coherent_32, DmaBuffer};
 use crate::sync::spinlock::SpinLock;
+use crate::sync::waitqueue::WaitQueue;
+use alloc::collections::VecDeque;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 // ─── Channel I/O base addresses ───────────────────────────────────────────────
 
 const PRIMARY_BASE: u16   = 0x1F0;
 const PRIMARY_CTRL: u16   = 0x3F6;
+const PRIMARY_IRQ: u8     = 14;
 const SECONDARY_BASE: u16 = 0x170;
 const SECONDARY_CTRL: u16 = 0x376;
+const SECONDARY_IRQ: u8   = 15;
+
+// Device control register (at ctrl)
+const CTRL_NIEN: u8 = 0x02; // interrupts off
+const CTRL_SRST: u8 = 0x04; // software reset of both drives
 
 // ─── Register offsets from base ───────────────────────────────────────────────
 
@@ -39,31 +62,564 @@ const CMD_READ_PIO:    u8 = 0x20;
 const CMD_READ_PIO_EX: u8 = 0x24; // LBA48
 const CMD_WRITE_PIO:   u8 = 0x30;
 const CMD_WRITE_PIO_EX:u8 = 0x34; // LBA48
+const CMD_READ_DMA:    u8 = 0xC8;
+const CMD_READ_DMA_EX: u8 = 0x25; // LBA48
+const CMD_WRITE_DMA:   u8 = 0xCA;
+const CMD_WRITE_DMA_EX:u8 = 0x35; // LBA48
 const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_SET_FEATURES: u8 = 0xEF;
+const CMD_SMART:       u8 = 0xB0;
 const CMD_IDENTIFY:    u8 = 0xEC;
+const CMD_PACKET:      u8 = 0xA0;
+const CMD_IDENTIFY_PACKET: u8 = 0xA1;
+
+// ─── SCSI commands (ATAPI packets) ────────────────────────────────────────────
+
+const SCSI_READ_CAPACITY: u8 = 0x25;
+const SCSI_READ_10:       u8 = 0x28;
+
+pub const CD_BLOCK_SIZE: usize = 2048;
+
+/// Polls for a packet command: media access is much slower than disk PIO
+const ATAPI_SPINS: u32 = 5_000_000;
+
+/// Sectors per command. More is split: LBA28 can't count higher, and other
+/// requests on the channel get their turn in between.
+const MAX_SECTORS: usize = 256;
+
+/// A disk command not done by then is abandoned and the channel reset.
+const COMMAND_TIMEOUT_NS: u64 = 5_000_000_000;
+
+pub use super::block::SECTOR_SIZE;
+
+// ─── S.M.A.R.T. ──────────────────────────────────────────────────────────────
+
+// SMART subcommands, in the features register
+const SMART_READ_DATA: u8 = 0xD0;
+const SMART_ENABLE: u8 = 0xD8;
+const SMART_RETURN_STATUS: u8 = 0xDA;
+
+/// LBA mid/high a SMART command must carry; RETURN STATUS leaves them
+/// as they are when the drive is fine and flips them to 0xF4/0x2C when a
+/// threshold has been exceeded.
+const SMART_KEY: (u8, u8) = (0x4F, 0xC2);
+const SMART_FAILING: (u8, u8) = (0xF4, 0x2C);
+
+// ─── Bus-master DMA (PCI IDE, BAR4) ──────────────────────────────────────────
+
+// Registers from the channel's base: primary at BAR4, secondary 8 above
+const BM_COMMAND: u16 = 0x00;
+const BM_STATUS: u16 = 0x02;
+const BM_PRDT: u16 = 0x04;
+const BM_CHANNEL_STRIDE: u16 = 8;
+
+const BM_CMD_START: u8 = 1 << 0;
+const BM_CMD_TO_MEMORY: u8 = 1 << 3; // device to memory: a read
+const BM_ST_ACTIVE: u8 = 1 << 0;
+const BM_ST_ERROR: u8 = 1 << 1;
+const BM_ST_IRQ: u8 = 1 << 2;
+
+/// PCI IDE programming interface: the controller can bus-master.
+const PROG_IF_BUS_MASTER: u8 = 1 << 7;
+
+/// A physical region descriptor covers up to 64 KiB (a count of 0) and
+/// must not cross a 64 KiB boundary; the last has bit 15 of its flags set.
+const PRD_MAX: usize = 64 * 1024;
+const PRD_EOT: u32 = 1 << 31;
+
+/// Bounce buffer per channel: one full command.
+const DMA_BUF_SIZE: usize = MAX_SECTORS * SECTOR_SIZE;
+
+/// SET FEATURES: set transfer mode, Ultra DMA mode n as 0x40 | n. Without
+/// knowing the cable, 2 (33 MB/s) is as far as is safe to go.
+const FEAT_TRANSFER_MODE: u8 = 0x03;
+const XFER_UDMA: u8 = 0x40;
+const UDMA_SAFE_MAX: u8 = 2;
 
-pub const SECTOR_SIZE: usize = 512;
+/// A channel's bus-master registers and the memory they point at.
+struct BusMaster {
+    io: u16,
+    /// The PRD table, below 4 GiB like everything a PRD points at.
+    prdt: DmaBuffer,
+    buf: DmaBuffer,
+}
+
+impl BusMaster {
+    fn new(io: u16) -> Option<Self> {
+        Some(Self {
+            io,
+            prdt: alloc_coherent_32(4096, 4096)?,
+            // Aligned to its size, so no 64 KiB boundary falls inside a PRD
+            buf: alloc_coherent_32(DMA_BUF_SIZE, DMA_BUF_SIZE)?,
+        })
+    }
+
+    /// Describe the first `bytes` of the bounce buffer and arm the engine
+    /// for a transfer in direction `to_memory`.
+    fn prepare(&self, bytes: usize, to_memory: bool) {
+        let prd = self.prdt.as_ptr::<u32>();
+        let entries = bytes.div_ceil(PRD_MAX);
+        for i in 0..entries {
+            let len = (bytes - i * PRD_MAX).min(PRD_MAX);
+            let eot = if i + 1 == entries { PRD_EOT } else { 0 };
+            unsafe {
+                prd.add(2 * i).write_volatile((self.buf.phys() + (i * PRD_MAX) as u64) as u32);
+                prd.add(2 * i + 1).write_volatile(eot | (len as u32 & 0xFFFF));
+            }
+        }
+        core::sync::atomic::fence(Ordering::SeqCst);
+        unsafe {
+            outb(self.io + BM_COMMAND, 0);
+            outl(self.io + BM_PRDT, self.prdt.phys() as u32);
+            // Write-one-to-clear
+            outb(self.io + BM_STATUS, BM_ST_ERROR | BM_ST_IRQ);
+            outb(self.io + BM_COMMAND, if to_memory { BM_CMD_TO_MEMORY } else { 0 });
+        }
+    }
+
+    fn start(&self) {
+        unsafe {
+            let cmd = inb(self.io + BM_COMMAND);
+            outb(self.io + BM_COMMAND, cmd | BM_CMD_START);
+        }
+    }
+
+    /// Stop the engine; the status it stopped with.
+    fn stop(&self) -> u8 {
+        unsafe {
+            let cmd = inb(self.io + BM_COMMAND);
+            outb(self.io + BM_COMMAND, cmd & !BM_CMD_START);
+            let st = inb(self.io + BM_STATUS);
+            outb(self.io + BM_STATUS, BM_ST_ERROR | BM_ST_IRQ);
+            st
+        }
+    }
+
+    fn status(&self) -> u8 {
+        unsafe { inb(self.io + BM_STATUS) }
+    }
+}
+
+// ─── Request queue ───────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Read,
+    Write,
+    Flush,
+    /// The channel to oneself: for the polled ATAPI commands. Completes as
+    /// soon as it starts; `Channel::release` hands the channel on.
+    Exclusive,
+}
+
+/// One command, from being queued until its result is collected.
+struct Request {
+    id: u64,
+    op: Op,
+    drive: u8,
+    lba48: bool,
+    lba: u64,
+    count: u16,
+    /// `count` sectors of the submitter's, which sleeps until the request
+    /// is done with them.
+    buf: *mut u8,
+    /// Sectors moved so far.
+    done: usize,
+    /// By bus-master DMA rather than PIO.
+    dma: bool,
+}
+
+// `buf` is only touched while the submitter waits for the request
+unsafe impl Send for Request {}
+
+struct ChannelState {
+    /// Waiting, oldest first.
+    queue: VecDeque<Request>,
+    /// On the drive now.
+    active: Option<Request>,
+    /// Results not collected yet, by request id.
+    finished: Vec<(u64, Result<(), &'static str>)>,
+    next_id: u64,
+    /// Set up by `init` behind a controller that can bus-master.
+    bm: Option<BusMaster>,
+}
+
+/// An IDE channel: master and slave share its task file, so it runs one
+/// command at a time.
+struct Channel {
+    base: u16,
+    ctrl: u16,
+    irq: u8,
+    state: SpinLock<ChannelState>,
+    /// Submitters waiting for their request's result.
+    done: WaitQueue,
+}
+
+static CHANNELS: [Channel; 2] = [
+    Channel::new(PRIMARY_BASE, PRIMARY_CTRL, PRIMARY_IRQ),
+    Channel::new(SECONDARY_BASE, SECONDARY_CTRL, SECONDARY_IRQ),
+];
+
+impl Channel {
+    const fn new(base: u16, ctrl: u16, irq: u8) -> Self {
+        Self {
+            base,
+            ctrl,
+            irq,
+            state: SpinLock::new(ChannelState {
+                queue: VecDeque::new(),
+                active: None,
+                finished: Vec::new(),
+                next_id: 0,
+                bm: None,
+            }),
+            done: WaitQueue::new("ide"),
+        }
+    }
+
+    /// Program the task file and issue `r`'s command. A PIO write's first
+    /// sector goes out right away; the drive interrupts for the rest. A DMA
+    /// transfer interrupts once, when it is over.
+    fn issue(&self, r: &mut Request, bm: Option<&BusMaster>) -> Result<(), &'static str> {
+        let (base, ctrl) = (self.base, self.ctrl);
+        if r.op == Op::Exclusive {
+            return Ok(());
+        }
+        let bm = if r.dma { Some(bm.ok_or("no bus master")?) } else { None };
+        let top = if r.lba48 { 0 } else { (r.lba >> 24) as u8 & 0x0F };
+        select_drive(base, ctrl, r.drive, top);
+        if !wait_bsy(base) {
+            return Err("BSY timeout");
+        }
+        let command = match (r.op, r.lba48, r.dma) {
+            (Op::Read, false, false) => CMD_READ_PIO,
+            (Op::Read, true, false) => CMD_READ_PIO_EX,
+            (Op::Write, false, false) => CMD_WRITE_PIO,
+            (Op::Write, true, false) => CMD_WRITE_PIO_EX,
+            (Op::Read, false, true) => CMD_READ_DMA,
+            (Op::Read, true, true) => CMD_READ_DMA_EX,
+            (Op::Write, false, true) => CMD_WRITE_DMA,
+            (Op::Write, true, true) => CMD_WRITE_DMA_EX,
+            _ => CMD_CACHE_FLUSH,
+        };
+        if let Some(bm) = bm {
+            let bytes = r.count as usize * SECTOR_SIZE;
+            if r.op == Op::Write {
+                unsafe { core::ptr::copy_nonoverlapping(r.buf, bm.buf.as_ptr(), bytes) };
+            }
+            bm.prepare(bytes, r.op == Op::Read);
+        }
+        if r.op != Op::Flush {
+            set_task_file(base, r.lba, r.count, r.lba48);
+        }
+        unsafe { outb(base + REG_COMMAND, command) };
+        if let Some(bm) = bm {
+            bm.start();
+            return Ok(());
+        }
+        if r.op == Op::Write {
+            delay400(ctrl);
+            if !wait_bsy(base) {
+                return Err("BSY timeout");
+            }
+            wait_drq(base)?;
+            unsafe { write_sector(base, r.buf) };
+            r.done = 1;
+        }
+        Ok(())
+    }
+
+    /// Start queued requests until one is on the drive or none are left.
+    fn start_next(&self, st: &mut ChannelState) {
+        while st.active.is_none() {
+            let Some(mut r) = st.queue.pop_front() else {
+                break;
+            };
+            match self.issue(&mut r, st.bm.as_ref()) {
+                Ok(()) if r.op == Op::Exclusive => {
+                    st.finished.push((r.id, Ok(())));
+                    st.active = Some(r);
+                }
+                Ok(()) => st.active = Some(r),
+                Err(e) => st.finished.push((r.id, Err(e))),
+            }
+        }
+        self.done.wake_all();
+    }
+
+    fn complete(&self, st: &mut ChannelState, result: Result<(), &'static str>) {
+        if let Some(r) = st.active.take() {
+            st.finished.push((r.id, result));
+        }
+        self.start_next(st);
+    }
+
+    /// The drive interrupted, or may have: move the next sector, or finish
+    /// the request.
+    fn service(&self) {
+        let mut st = self.state.lock();
+        let ChannelState { active, bm, .. } = &mut *st;
+        let Some(r) = active.as_mut() else {
+            return;
+        };
+        if r.op == Op::Exclusive {
+            // Polled; the status read would be its owner's to make
+            return;
+        }
+        if r.dma {
+            let Some(bm) = bm.as_ref() else {
+                return;
+            };
+            let st_bm = bm.status();
+            if st_bm & (BM_ST_IRQ | BM_ST_ERROR) == 0 {
+                return; // not done yet
+            }
+            // Still active after the drive is done: it moved less than the
+            // PRDs describe
+            let short = st_bm & BM_ST_ACTIVE != 0;
+            let st_bm = bm.stop();
+            let status = status(self.base);
+            let result = if st_bm & BM_ST_ERROR != 0 {
+                Err("IDE bus-master DMA error")
+            } else if short {
+                Err("IDE DMA transfer short")
+            } else if status & (SR_ERR | SR_DF) != 0 {
+                Err("ATA error/device-fault")
+            } else {
+                if r.op == Op::Read {
+                    let bytes = r.count as usize * SECTOR_SIZE;
+                    unsafe { core::ptr::copy_nonoverlapping(bm.buf.as_ptr(), r.buf, bytes) };
+                }
+                r.done = r.count as usize;
+                Ok(())
+            };
+            self.complete(&mut st, result);
+            return;
+        }
+        // Reading the status acknowledges the interrupt
+        let status = status(self.base);
+        if status & SR_BSY != 0 {
+            return;
+        }
+        if status & (SR_ERR | SR_DF) != 0 {
+            self.complete(&mut st, Err("ATA error/device-fault"));
+            return;
+        }
+        let total = r.count as usize;
+        match r.op {
+            Op::Read if status & SR_DRQ != 0 => {
+                unsafe { read_sector(self.base, r.buf.add(r.done * SECTOR_SIZE)) };
+                r.done += 1;
+                if r.done == total {
+                    self.complete(&mut st, Ok(()));
+                }
+            }
+            Op::Write if r.done < total => {
+                if status & SR_DRQ == 0 {
+                    self.complete(&mut st, Err("ATA DRQ missing"));
+                    return;
+                }
+                unsafe { write_sector(self.base, r.buf.add(r.done * SECTOR_SIZE)) };
+                r.done += 1;
+            }
+            Op::Write | Op::Flush => self.complete(&mut st, Ok(())),
+            _ => {}
+        }
+    }
+
+    /// Queue a request; the id it is given, for `wait`.
+    fn submit(&self, mut r: Request) -> u64 {
+        let mut st = self.state.lock();
+        let id = st.next_id;
+        st.next_id += 1;
+        r.id = id;
+        st.queue.push_back(r);
+        if st.active.is_none() {
+            self.start_next(&mut st);
+        }
+        id
+    }
+
+    fn take_result(&self, id: u64) -> Option<Result<(), &'static str>> {
+        let mut st = self.state.lock();
+        let at = st.finished.iter().position(|&(i, _)| i == id)?;
+        Some(st.finished.swap_remove(at).1)
+    }
+
+    /// Give up on request `id`: out of the queue if it never started,
+    /// else off the drive, which is reset.
+    fn abort(&self, id: u64) -> Result<(), &'static str> {
+        let mut st = self.state.lock();
+        if let Some(at) = st.finished.iter().position(|&(i, _)| i == id) {
+            // Made it after all
+            return st.finished.swap_remove(at).1;
+        }
+        st.queue.retain(|r| r.id != id);
+        if st.active.as_ref().is_some_and(|r| r.id == id) {
+            log::warn!("IDE: command timed out on IRQ {}, resetting the channel", self.irq);
+            if let Some(bm) = st.bm.as_ref() {
+                bm.stop();
+            }
+            self.reset();
+            st.active = None;
+            self.start_next(&mut st);
+        }
+        Err("IDE command timeout")
+    }
+
+    fn reset(&self) {
+        unsafe {
+            outb(self.ctrl, CTRL_SRST);
+            delay400(self.ctrl);
+            outb(self.ctrl, 0);
+        }
+        wait_bsy_spins(self.base, ATAPI_SPINS);
+    }
+
+    /// Wait for request `id`'s result. Sleeps if interrupts are on; with
+    /// them off, polls the drive instead of waiting for its IRQ.
+    fn wait(&self, id: u64, deadline: Option<u64>) -> Result<(), &'static str> {
+        let expired = || deadline.is_some_and(|d| nanos() >= d);
+        if read_rflags() & RFLAGS_IF == 0 {
+            loop {
+                if let Some(r) = self.take_result(id) {
+                    return r;
+                }
+                if expired() {
+                    return self.abort(id);
+                }
+                if unsafe { inb(self.ctrl) } & SR_BSY == 0 {
+                    self.service();
+                }
+                core::hint::spin_loop();
+            }
+        }
+
+        let pid = crate::proc::current_process().map(|p| p.lock().pid);
+        let timer = pid.zip(deadline).map(|(pid, d)| crate::proc::timers::wake_at(d, pid));
+        let result = self.done.wait_event(|| match self.take_result(id) {
+            Some(r) => Some(Some(r)),
+            None if expired() => Some(None),
+            None => None,
+        });
+        if let Some(key) = timer {
+            crate::proc::timers::cancel(key);
+        }
+        match result {
+            Some(r) => r,
+            None => self.abort(id),
+        }
+    }
+
+    /// Queue a disk command and sleep until it is done.
+    fn run(
+        &self,
+        op: Op,
+        drive: &Drive,
+        lba: u64,
+        count: u16,
+        buf: *mut u8,
+        dma: bool,
+    ) -> Result<(), &'static str> {
+        let id = self.submit(Request {
+            id: 0,
+            op,
+            drive: drive.drive,
+            lba48: drive.id.lba48,
+            lba,
+            count,
+            buf,
+            done: 0,
+            dma,
+        });
+        self.wait(id, Some(nanos() + COMMAND_TIMEOUT_NS))
+    }
+
+    /// Wait for the channel to be ours alone; `release` gives it back.
+    fn acquire(&self) {
+        let id = self.submit(Request {
+            id: 0,
+            op: Op::Exclusive,
+            drive: 0,
+            lba48: false,
+            lba: 0,
+            count: 0,
+            buf: core::ptr::null_mut(),
+            done: 0,
+            dma: false,
+        });
+        let _ = self.wait(id, None);
+    }
+
+    fn release(&self) {
+        let mut st = self.state.lock();
+        st.active = None;
+        self.start_next(&mut st);
+    }
+}
+
+/// IRQ 14/15: the channel's drive wants service.
+pub fn irq_ide(irq: u8) {
+    if let Some(ch) = CHANNELS.iter().find(|c| c.irq == irq) {
+        ch.service();
+    }
+}
 
 // ─── Drive ───────────────────────────────────────────────────────────────────
 
+/// Capacity and strings from IDENTIFY DEVICE data (also used by AHCI).
 #[derive(Debug, Clone)]
-pub struct Drive {
-    pub channel: u8,   // 0 = primary, 1 = secondary
-    pub drive: u8,     // 0 = master, 1 = slave
+pub struct Identity {
     pub lba48: bool,
     pub sectors: u64,
     pub model: String,
     pub serial: String,
-    base: u16,
-    ctrl: u16,
 }
 
-impl Drive {
-    pub fn size_mb(&self) -> u64 {
-        self.sectors * SECTOR_SIZE as u64 / 1024 / 1024
+impl Identity {
+    /// None if the device reports no addressable sectors.
+    pub fn parse(id: &[u16; 256]) -> Option<Identity> {
+        // word 83 bit 10 = LBA48 support
+        let lba48 = id[83] & (1 << 10) != 0;
+
+        let sectors = if lba48 {
+            (id[100] as u64)
+                | ((id[101] as u64) << 16)
+                | ((id[102] as u64) << 32)
+                | ((id[103] as u64) << 48)
+        } else {
+            (id[60] as u64) | ((id[61] as u64) << 16)
+        };
+
+        if sectors == 0 {
+            return None;
+        }
+
+        Some(Identity {
+            lba48,
+            sectors,
+            model: ata_string(id, 27, 20),
+            serial: ata_string(id, 10, 10),
+        })
     }
 }
 
+#[derive(Debug)]
+pub struct Drive {
+    pub name: String,
+    pub channel: u8,   // 0 = primary, 1 = secondary
+    pub drive: u8,     // 0 = master, 1 = slave
+    pub id: Identity,
+    base: u16,
+    ctrl: u16,
+    /// Ultra DMA mode the drive is (to be) set to, if it has any.
+    udma: Option<u8>,
+    /// Transfers go by DMA; cleared when DMA fails and PIO takes over.
+    dma: AtomicBool,
+    /// SMART feature set supported (IDENTIFY word 82 bit 0).
+    smart: bool,
+}
+
 // ─── Low-level helpers ────────────────────────────────────────────────────────
 
 fn status(base: u16) -> u8 {
@@ -79,7 +635,11 @@ fn delay400(ctrl: u16) {
 
 /// Wait until BSY clears; returns false on timeout or error
 fn wait_bsy(base: u16) -> bool {
-    for _ in 0..100_000u32 {
+    wait_bsy_spins(base, 100_000)
+}
+
+fn wait_bsy_spins(base: u16, spins: u32) -> bool {
+    for _ in 0..spins {
         let s = status(base);
         if s & SR_BSY == 0 {
             return true;
@@ -109,6 +669,42 @@ fn select_drive(base: u16, ctrl: u16, drive: u8, lba_top: u8) {
     delay400(ctrl);
 }
 
+/// Sector count and LBA registers; the high bytes first for LBA48.
+fn set_task_file(base: u16, lba: u64, count: u16, lba48: bool) {
+    unsafe {
+        if lba48 {
+            outb(base + REG_FEATURES, 0);
+            outb(base + REG_SECCOUNT, (count >> 8) as u8);
+            outb(base + REG_LBA0, (lba >> 24) as u8);
+            outb(base + REG_LBA1, (lba >> 32) as u8);
+            outb(base + REG_LBA2, (lba >> 40) as u8);
+        }
+        // 256 sectors is a count of 0 with LBA28
+        outb(base + REG_FEATURES, 0);
+        outb(base + REG_SECCOUNT, count as u8);
+        outb(base + REG_LBA0, lba as u8);
+        outb(base + REG_LBA1, (lba >> 8) as u8);
+        outb(base + REG_LBA2, (lba >> 16) as u8);
+    }
+}
+
+/// One sector from the data port into `dst`.
+unsafe fn read_sector(base: u16, dst: *mut u8) {
+    for i in 0..SECTOR_SIZE / 2 {
+        let w = inw(base + REG_DATA);
+        dst.add(2 * i).write(w as u8);
+        dst.add(2 * i + 1).write((w >> 8) as u8);
+    }
+}
+
+/// One sector from `src` out through the data port.
+unsafe fn write_sector(base: u16, src: *const u8) {
+    for i in 0..SECTOR_SIZE / 2 {
+        let w = src.add(2 * i).read() as u16 | (src.add(2 * i + 1).read() as u16) << 8;
+        outw(base + REG_DATA, w);
+    }
+}
+
 fn ata_string(words: &[u16], word_start: usize, word_count: usize) -> String {
     let mut bytes = Vec::with_capacity(word_count * 2);
     for w in &words[word_start..word_start + word_count] {
@@ -122,7 +718,8 @@ fn ata_string(words: &[u16], word_start: usize, word_count: usize) -> String {
 
 // ─── Identify ────────────────────────────────────────────────────────────────
 
-fn identify(base: u16, ctrl: u16, drive_sel: u8) -> Option<[u16; 256]> {
+/// IDENTIFY (PACKET) DEVICE; the flag is true for ATAPI devices.
+fn identify(base: u16, ctrl: u16, drive_sel: u8) -> Option<(bool, [u16; 256])> {
     unsafe {
         // Select drive, no LBA bits needed for IDENTIFY
         outb(base + REG_HDDEVSEL, 0xA0 | ((drive_sel & 1) << 4));
@@ -146,11 +743,21 @@ fn identify(base: u16, ctrl: u16, drive_sel: u8) -> Option<[u16; 256]> {
             return None;
         }
 
-        // Check if ATAPI (LBA1/LBA2 non-zero = not plain ATA)
+        // LBA1/LBA2 = 0x14/0xEB is the ATAPI signature; anything else
+        // non-zero is not a device we know
         let lba1 = inb(base + REG_LBA1);
         let lba2 = inb(base + REG_LBA2);
-        if lba1 != 0 || lba2 != 0 {
-            return None; // ATAPI — skip for now
+        let atapi = match (lba1, lba2) {
+            (0, 0) => false,
+            (0x14, 0xEB) => true,
+            _ => return None,
+        };
+        if atapi {
+            outb(base + REG_COMMAND, CMD_IDENTIFY_PACKET);
+            delay400(ctrl);
+            if !wait_bsy(base) {
+                return None;
+            }
         }
 
         if wait_drq(base).is_err() {
@@ -161,259 +768,403 @@ fn identify(base: u16, ctrl: u16, drive_sel: u8) -> Option<[u16; 256]> {
         for w in buf.iter_mut() {
             *w = inw(base + REG_DATA);
         }
-        Some(buf)
+        Some((atapi, buf))
     }
 }
 
-// ─── Public read/write ───────────────────────────────────────────────────────
-
-static DRIVES: SpinLock<Vec<Drive>> = SpinLock::new(Vec::new());
+// ─── Block device ────────────────────────────────────────────────────────────
 
-fn with_drive<F, R>(idx: usize, f: F) -> Option<R>
-where
-    F: FnOnce(&Drive) -> R,
-{
-    DRIVES.lock().get(idx).map(|d| f(d))
+impl Drive {
+    /// `count` sectors at `lba` to or from `buf`, MAX_SECTORS a command.
+    fn transfer(&self, op: Op, lba: u64, buf: *mut u8, count: usize) -> Result<(), &'static str> {
+        let channel = &CHANNELS[self.channel as usize];
+        let mut done = 0;
+        while done < count {
+            let n = (count - done).min(MAX_SECTORS);
+            let at = unsafe { buf.add(done * SECTOR_SIZE) };
+            let lba = lba + done as u64;
+            let dma = self.dma.load(Ordering::Relaxed);
+            match channel.run(op, self, lba, n as u16, at, dma) {
+                Err(e) if dma => {
+                    log::warn!("IDE: {}: DMA failed ({}), falling back to PIO", self.name, e);
+                    self.dma.store(false, Ordering::Relaxed);
+                    channel.run(op, self, lba, n as u16, at, false)?;
+                }
+                r => r?,
+            }
+            done += n;
+        }
+        Ok(())
+    }
 }
 
-/// Read `count` sectors starting at `lba` into `buf`.
-/// `buf` must be exactly `count * 512` bytes.
-pub fn read_sectors(idx: usize, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str> {
-    assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+impl BlockDevice for Drive {
+    fn name(&self) -> &str {
+        &self.name
+    }
 
-    let (base, ctrl, drive_sel, lba48) = {
-        let drives = DRIVES.lock();
-        let d = drives.get(idx).ok_or("no such drive")?;
-        (d.base, d.ctrl, d.drive, d.lba48)
-    };
+    fn sector_count(&self) -> u64 {
+        self.id.sectors
+    }
 
-    if lba48 {
-        read_lba48(base, ctrl, drive_sel, lba, count, buf)
-    } else {
-        read_lba28(base, ctrl, drive_sel, lba as u32, count as u8, buf)
+    fn read_sectors(&self, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        self.transfer(Op::Read, lba, buf.as_mut_ptr(), count as usize)
     }
-}
 
-/// Write `count` sectors starting at `lba` from `buf`.
-pub fn write_sectors(idx: usize, lba: u64, count: u16, buf: &[u8]) -> Result<(), &'static str> {
-    assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+    fn write_sectors(&self, lba: u64, count: u16, buf: &[u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        // Never written through: a write only reads from the buffer
+        self.transfer(Op::Write, lba, buf.as_ptr() as *mut u8, count as usize)?;
+        CHANNELS[self.channel as usize].run(Op::Flush, self, 0, 0, core::ptr::null_mut(), false)
+    }
 
-    let (base, ctrl, drive_sel, lba48) = {
-        let drives = DRIVES.lock();
-        let d = drives.get(idx).ok_or("no such drive")?;
-        (d.base, d.ctrl, d.drive, d.lba48)
-    };
+    fn info(&self) -> String {
+        let mode = match self.udma {
+            Some(m) if self.dma.load(Ordering::Relaxed) => alloc::format!("UDMA{}", m),
+            _ => String::from("PIO"),
+        };
+        alloc::format!(
+            "{} [IDE {}/{}, LBA{}, {}]",
+            self.id.model,
+            if self.channel == 0 { "primary" } else { "secondary" },
+            if self.drive == 0 { "master" } else { "slave" },
+            if self.id.lba48 { 48 } else { 28 },
+            mode,
+        )
+    }
 
-    if lba48 {
-        write_lba48(base, ctrl, drive_sel, lba, count, buf)
-    } else {
-        write_lba28(base, ctrl, drive_sel, lba as u32, count as u8, buf)
+    fn smart(&self) -> Option<Result<smart::Report, &'static str>> {
+        if !self.smart {
+            return None;
+        }
+        let report = (|| {
+            // Off until enabled on some drives; enabling twice is harmless
+            self.smart_command(SMART_ENABLE, None)?;
+            let healthy = match self.smart_command(SMART_RETURN_STATUS, None)? {
+                SMART_KEY => true,
+                SMART_FAILING => false,
+                _ => return Err("SMART status not reported"),
+            };
+            let mut data = [0u8; SECTOR_SIZE];
+            self.smart_command(SMART_READ_DATA, Some(&mut data))?;
+            Ok(smart::Report::parse(healthy, &data))
+        })();
+        Some(report)
     }
 }
 
-// ─── LBA28 ───────────────────────────────────────────────────────────────────
+// ─── S.M.A.R.T. commands ─────────────────────────────────────────────────────
+
+impl Drive {
+    /// Issue SMART subcommand `feature`, polled, with the channel held;
+    /// the LBA mid/high registers after it, and its data sector if `data`.
+    fn smart_command(
+        &self,
+        feature: u8,
+        data: Option<&mut [u8; SECTOR_SIZE]>,
+    ) -> Result<(u8, u8), &'static str> {
+        let channel = &CHANNELS[self.channel as usize];
+        channel.acquire();
+        let result = (|| {
+            select_drive(self.base, self.ctrl, self.drive, 0);
+            if !wait_bsy(self.base) {
+                return Err("BSY timeout");
+            }
+            unsafe {
+                outb(self.base + REG_FEATURES, feature);
+                outb(self.base + REG_SECCOUNT, 0);
+                outb(self.base + REG_LBA0, 0);
+                outb(self.base + REG_LBA1, SMART_KEY.0);
+                outb(self.base + REG_LBA2, SMART_KEY.1);
+                outb(self.base + REG_COMMAND, CMD_SMART);
+            }
+            delay400(self.ctrl);
+            if let Some(buf) = data {
+                wait_drq(self.base)?;
+                unsafe { read_sector(self.base, buf.as_mut_ptr()) };
+            }
+            if !wait_bsy(self.base) {
+                return Err("BSY timeout");
+            }
+            if status(self.base) & (SR_ERR | SR_DF) != 0 {
+                return Err("SMART command aborted");
+            }
+            Ok(unsafe { (inb(self.base + REG_LBA1), inb(self.base + REG_LBA2)) })
+        })();
+        channel.release();
+        result
+    }
+}
 
-fn read_lba28(base: u16, ctrl: u16, drive: u8, lba: u32, count: u8, buf: &mut [u8]) -> Result<(), &'static str> {
-    select_drive(base, ctrl, drive, ((lba >> 24) & 0x0F) as u8);
-    if !wait_bsy(base) { return Err("BSY timeout"); }
+// ─── ATAPI ───────────────────────────────────────────────────────────────────
 
+/// Send a 12-byte SCSI packet and read its PIO data phase into `buf`.
+/// Returns the number of bytes transferred.
+fn atapi_packet(base: u16, ctrl: u16, drive: u8, packet: &[u8; 12], buf: &mut [u8]) -> Result<usize, &'static str> {
     unsafe {
-        outb(base + REG_FEATURES, 0);
-        outb(base + REG_SECCOUNT, count);
-        outb(base + REG_LBA0, (lba & 0xFF) as u8);
-        outb(base + REG_LBA1, ((lba >> 8) & 0xFF) as u8);
-        outb(base + REG_LBA2, ((lba >> 16) & 0xFF) as u8);
-        outb(base + REG_COMMAND, CMD_READ_PIO);
-    }
+        outb(base + REG_HDDEVSEL, 0xA0 | ((drive & 1) << 4));
+        delay400(ctrl);
+        if !wait_bsy(base) { return Err("BSY timeout"); }
 
-    for sec in 0..count as usize {
+        outb(base + REG_FEATURES, 0); // PIO, no DMA
+        outb(base + REG_LBA1, (CD_BLOCK_SIZE & 0xFF) as u8); // max bytes per DRQ
+        outb(base + REG_LBA2, (CD_BLOCK_SIZE >> 8) as u8);
+        outb(base + REG_COMMAND, CMD_PACKET);
         delay400(ctrl);
         if !wait_bsy(base) { return Err("BSY timeout"); }
         wait_drq(base)?;
 
-        let off = sec * SECTOR_SIZE;
-        unsafe {
-            for i in (0..SECTOR_SIZE).step_by(2) {
+        for pair in packet.chunks(2) {
+            outw(base + REG_DATA, pair[0] as u16 | (pair[1] as u16) << 8);
+        }
+
+        let mut done = 0;
+        loop {
+            delay400(ctrl);
+            if !wait_bsy_spins(base, ATAPI_SPINS) { return Err("ATAPI timeout"); }
+            let s = status(base);
+            if s & (SR_ERR | SR_DF) != 0 {
+                return Err("ATAPI command failed");
+            }
+            if s & SR_DRQ == 0 {
+                return Ok(done); // command complete
+            }
+            let bytes = inb(base + REG_LBA1) as usize | (inb(base + REG_LBA2) as usize) << 8;
+            for _ in 0..bytes.div_ceil(2) {
                 let w = inw(base + REG_DATA);
-                buf[off + i]     = (w & 0xFF) as u8;
-                buf[off + i + 1] = (w >> 8) as u8;
+                if done + 1 < buf.len() {
+                    buf[done] = w as u8;
+                    buf[done + 1] = (w >> 8) as u8;
+                }
+                done += 2;
             }
         }
     }
-    Ok(())
 }
 
-fn write_lba28(base: u16, ctrl: u16, drive: u8, lba: u32, count: u8, buf: &[u8]) -> Result<(), &'static str> {
-    select_drive(base, ctrl, drive, ((lba >> 24) & 0x0F) as u8);
-    if !wait_bsy(base) { return Err("BSY timeout"); }
+/// ATAPI CD/DVD drive. Exposed as 512-byte sectors, read-only.
+pub struct Cdrom {
+    name: String,
+    channel: u8,
+    drive: u8,
+    model: String,
+    blocks: u64, // 2048-byte blocks on the medium
+    base: u16,
+    ctrl: u16,
+}
 
-    unsafe {
-        outb(base + REG_FEATURES, 0);
-        outb(base + REG_SECCOUNT, count);
-        outb(base + REG_LBA0, (lba & 0xFF) as u8);
-        outb(base + REG_LBA1, ((lba >> 8) & 0xFF) as u8);
-        outb(base + REG_LBA2, ((lba >> 16) & 0xFF) as u8);
-        outb(base + REG_COMMAND, CMD_WRITE_PIO);
+impl Cdrom {
+    fn read_capacity(base: u16, ctrl: u16, drive: u8) -> Option<u64> {
+        let mut packet = [0u8; 12];
+        packet[0] = SCSI_READ_CAPACITY;
+        let mut resp = [0u8; 8];
+        atapi_packet(base, ctrl, drive, &packet, &mut resp).ok()?;
+        let last = u32::from_be_bytes([resp[0], resp[1], resp[2], resp[3]]) as u64;
+        let bsize = u32::from_be_bytes([resp[4], resp[5], resp[6], resp[7]]) as usize;
+        (bsize == CD_BLOCK_SIZE).then_some(last + 1)
     }
 
-    for sec in 0..count as usize {
-        delay400(ctrl);
-        if !wait_bsy(base) { return Err("BSY timeout"); }
-        wait_drq(base)?;
-
-        let off = sec * SECTOR_SIZE;
-        unsafe {
-            for i in (0..SECTOR_SIZE).step_by(2) {
-                let w = (buf[off + i] as u16) | ((buf[off + i + 1] as u16) << 8);
-                outw(base + REG_DATA, w);
-            }
+    fn read_block(&self, block: u64, buf: &mut [u8; CD_BLOCK_SIZE]) -> Result<(), &'static str> {
+        let mut packet = [0u8; 12];
+        packet[0] = SCSI_READ_10;
+        packet[2..6].copy_from_slice(&(block as u32).to_be_bytes());
+        packet[8] = 1; // one block
+        let channel = &CHANNELS[self.channel as usize];
+        channel.acquire();
+        let read = atapi_packet(self.base, self.ctrl, self.drive, &packet, buf);
+        channel.release();
+        match read? {
+            CD_BLOCK_SIZE => Ok(()),
+            _ => Err("ATAPI short read"),
         }
     }
-
-    unsafe { outb(base + REG_COMMAND, CMD_CACHE_FLUSH); }
-    if !wait_bsy(base) { return Err("flush timeout"); }
-    Ok(())
 }
 
-// ─── LBA48 ───────────────────────────────────────────────────────────────────
-
-fn read_lba48(base: u16, ctrl: u16, drive: u8, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str> {
-    select_drive(base, ctrl, drive, 0);
-    if !wait_bsy(base) { return Err("BSY timeout"); }
+impl BlockDevice for Cdrom {
+    fn name(&self) -> &str {
+        &self.name
+    }
 
-    unsafe {
-        outb(base + REG_FEATURES, 0);
-        outb(base + REG_FEATURES, 0);
-        outb(base + REG_SECCOUNT, (count >> 8) as u8);
-        outb(base + REG_LBA0, ((lba >> 24) & 0xFF) as u8);
-        outb(base + REG_LBA1, ((lba >> 32) & 0xFF) as u8);
-        outb(base + REG_LBA2, ((lba >> 40) & 0xFF) as u8);
-        outb(base + REG_SECCOUNT, (count & 0xFF) as u8);
-        outb(base + REG_LBA0, (lba & 0xFF) as u8);
-        outb(base + REG_LBA1, ((lba >> 8) & 0xFF) as u8);
-        outb(base + REG_LBA2, ((lba >> 16) & 0xFF) as u8);
-        outb(base + REG_COMMAND, CMD_READ_PIO_EX);
-    }
-
-    for sec in 0..count as usize {
-        delay400(ctrl);
-        if !wait_bsy(base) { return Err("BSY timeout"); }
-        wait_drq(base)?;
+    fn sector_count(&self) -> u64 {
+        self.blocks * (CD_BLOCK_SIZE / SECTOR_SIZE) as u64
+    }
 
-        let off = sec * SECTOR_SIZE;
-        unsafe {
-            for i in (0..SECTOR_SIZE).step_by(2) {
-                let w = inw(base + REG_DATA);
-                buf[off + i]     = (w & 0xFF) as u8;
-                buf[off + i + 1] = (w >> 8) as u8;
-            }
+    fn read_sectors(&self, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        const PER_BLOCK: u64 = (CD_BLOCK_SIZE / SECTOR_SIZE) as u64;
+        let mut block_buf = [0u8; CD_BLOCK_SIZE];
+        let mut sector = lba;
+        let end = lba + count as u64;
+        while sector < end {
+            self.read_block(sector / PER_BLOCK, &mut block_buf)?;
+            let first = sector % PER_BLOCK;
+            let n = (PER_BLOCK - first).min(end - sector);
+            let src = first as usize * SECTOR_SIZE;
+            let dst = (sector - lba) as usize * SECTOR_SIZE;
+            let len = n as usize * SECTOR_SIZE;
+            buf[dst..dst + len].copy_from_slice(&block_buf[src..src + len]);
+            sector += n;
         }
+        Ok(())
     }
-    Ok(())
-}
 
-fn write_lba48(base: u16, ctrl: u16, drive: u8, lba: u64, count: u16, buf: &[u8]) -> Result<(), &'static str> {
-    select_drive(base, ctrl, drive, 0);
-    if !wait_bsy(base) { return Err("BSY timeout"); }
-
-    unsafe {
-        outb(base + REG_FEATURES, 0);
-        outb(base + REG_FEATURES, 0);
-        outb(base + REG_SECCOUNT, (count >> 8) as u8);
-        outb(base + REG_LBA0, ((lba >> 24) & 0xFF) as u8);
-        outb(base + REG_LBA1, ((lba >> 32) & 0xFF) as u8);
-        outb(base + REG_LBA2, ((lba >> 40) & 0xFF) as u8);
-        outb(base + REG_SECCOUNT, (count & 0xFF) as u8);
-        outb(base + REG_LBA0, (lba & 0xFF) as u8);
-        outb(base + REG_LBA1, ((lba >> 8) & 0xFF) as u8);
-        outb(base + REG_LBA2, ((lba >> 16) & 0xFF) as u8);
-        outb(base + REG_COMMAND, CMD_WRITE_PIO_EX);
-    }
-
-    for sec in 0..count as usize {
-        delay400(ctrl);
-        if !wait_bsy(base) { return Err("BSY timeout"); }
-        wait_drq(base)?;
+    fn write_sectors(&self, _: u64, _: u16, _: &[u8]) -> Result<(), &'static str> {
+        Err("read-only device")
+    }
 
-        let off = sec * SECTOR_SIZE;
-        unsafe {
-            for i in (0..SECTOR_SIZE).step_by(2) {
-                let w = (buf[off + i] as u16) | ((buf[off + i + 1] as u16) << 8);
-                outw(base + REG_DATA, w);
-            }
-        }
+    fn read_only(&self) -> bool {
+        true
     }
 
-    unsafe { outb(base + REG_COMMAND, CMD_CACHE_FLUSH); }
-    if !wait_bsy(base) { return Err("flush timeout"); }
-    Ok(())
+    fn info(&self) -> String {
+        alloc::format!(
+            "{} [ATAPI {}/{}]",
+            self.model,
+            if self.channel == 0 { "primary" } else { "secondary" },
+            if self.drive == 0 { "master" } else { "slave" },
+        )
+    }
 }
 
 // ─── Init ─────────────────────────────────────────────────────────────────────
 
-fn probe_channel(channel: u8, base: u16, ctrl: u16, list: &mut Vec<Drive>) {
-    for drive_sel in 0u8..2 {
-        let Some(id) = identify(base, ctrl, drive_sel) else { continue };
+/// The Ultra DMA mode to run `id`'s drive in, if it has one. IDENTIFY
+/// word 88 lists the modes it supports in its low byte and the one
+/// selected in its high byte; word 53 bit 2 says it is valid.
+fn udma_mode(id: &[u16; 256]) -> Option<u8> {
+    let supported = id[88] & 0x7F;
+    let selected = (id[88] >> 8) & 0x7F;
+    if id[49] & (1 << 8) == 0 || id[53] & (1 << 2) == 0 || supported == 0 {
+        return None;
+    }
+    // Keep what the firmware chose; it knows the cable
+    Some(match selected {
+        0 => (15 - supported.leading_zeros() as u8).min(UDMA_SAFE_MAX),
+        m => 15 - m.leading_zeros() as u8,
+    })
+}
 
-        // word 83 bit 10 = LBA48 support
-        let lba48 = id[83] & (1 << 10) != 0;
+/// Put `drive` in Ultra DMA mode `mode`, polled.
+fn set_udma_mode(d: &Drive, mode: u8) -> bool {
+    select_drive(d.base, d.ctrl, d.drive, 0);
+    if !wait_bsy(d.base) {
+        return false;
+    }
+    unsafe {
+        outb(d.base + REG_FEATURES, FEAT_TRANSFER_MODE);
+        outb(d.base + REG_SECCOUNT, XFER_UDMA | mode);
+        outb(d.base + REG_COMMAND, CMD_SET_FEATURES);
+    }
+    delay400(d.ctrl);
+    wait_bsy(d.base) && status(d.base) & (SR_ERR | SR_DF) == 0
+}
 
-        let sectors = if lba48 {
-            (id[100] as u64)
-                | ((id[101] as u64) << 16)
-                | ((id[102] as u64) << 32)
-                | ((id[103] as u64) << 48)
-        } else {
-            (id[60] as u64) | ((id[61] as u64) << 16)
+/// Bus-master DMA for the drives that can use it, when the IDE
+/// controller is a PCI one that can bus-master. Everything else stays PIO.
+fn init_dma(list: &[Drive]) {
+    if !list.iter().any(|d| d.udma.is_some()) {
+        return;
+    }
+    let Some(ctl) = pci::find(|d| d.is_ide() && d.prog_if & PROG_IF_BUS_MASTER != 0) else {
+        log::info!("IDE: no bus-mastering controller, PIO only");
+        return;
+    };
+    if !ctl.bar_is_io(4) || ctl.bar_base(4) == 0 {
+        log::info!("IDE: bus-master registers not in I/O space, PIO only");
+        return;
+    }
+    pci::enable_bus_master(ctl.bus, ctl.dev, ctl.func);
+    let io = ctl.bar_base(4) as u16;
+
+    for (i, ch) in CHANNELS.iter().enumerate() {
+        let drives: Vec<&Drive> = list
+            .iter()
+            .filter(|d| d.channel as usize == i && d.udma.is_some())
+            .collect();
+        if drives.is_empty() {
+            continue;
+        }
+        let Some(bm) = BusMaster::new(io + i as u16 * BM_CHANNEL_STRIDE) else {
+            log::warn!("IDE: no memory for DMA buffers, PIO only");
+            return;
         };
+        ch.state.lock().bm = Some(bm);
+        for d in drives {
+            let mode = d.udma.unwrap_or(0);
+            if set_udma_mode(d, mode) {
+                d.dma.store(true, Ordering::Relaxed);
+            } else {
+                log::warn!("IDE: {}/{}: SET FEATURES UDMA{} failed, PIO", i, d.drive, mode);
+            }
+        }
+    }
+}
 
-        if sectors == 0 {
+fn probe_channel(channel: u8, base: u16, ctrl: u16, list: &mut Vec<Drive>, cds: &mut Vec<Cdrom>) {
+    // Polled until `init` is done with both channels
+    unsafe { outb(ctrl, CTRL_NIEN) };
+    for drive_sel in 0u8..2 {
+        let Some((atapi, raw)) = identify(base, ctrl, drive_sel) else { continue };
+        if atapi {
+            let model = ata_string(&raw, 27, 20);
+            match Cdrom::read_capacity(base, ctrl, drive_sel) {
+                Some(blocks) => cds.push(Cdrom {
+                    name: String::new(),
+                    channel,
+                    drive: drive_sel,
+                    model,
+                    blocks,
+                    base,
+                    ctrl,
+                }),
+                None => log::info!("IDE: ATAPI {} — no medium", model),
+            }
             continue;
         }
-
-        let model  = ata_string(&id, 27, 20);
-        let serial = ata_string(&id, 10, 10);
-
+        let Some(id) = Identity::parse(&raw) else { continue };
         list.push(Drive {
+            name: String::new(),
             channel,
             drive: drive_sel,
-            lba48,
-            sectors,
-            model,
-            serial,
+            id,
             base,
             ctrl,
+            udma: udma_mode(&raw),
+            dma: AtomicBool::new(false),
+            smart: raw[82] & 1 != 0,
         });
     }
 }
 
 pub fn init() {
     let mut list = Vec::new();
+    let mut cds = Vec::new();
 
-    probe_channel(0, PRIMARY_BASE,   PRIMARY_CTRL,   &mut list);
-    probe_channel(1, SECONDARY_BASE, SECONDARY_CTRL, &mut list);
+    probe_channel(0, PRIMARY_BASE,   PRIMARY_CTRL,   &mut list, &mut cds);
+    probe_channel(1, SECONDARY_BASE, SECONDARY_CTRL, &mut list, &mut cds);
 
-    if list.is_empty() {
-        log::info!("IDE: no drives found");
-    } else {
-        for (i, d) in list.iter().enumerate() {
-            log::info!(
-                "IDE: drive {} — {} [{} MiB, LBA{}]  s/n: {}",
-                i, d.model, d.size_mb(),
-                if d.lba48 { 48 } else { 28 },
-                d.serial,
-            );
+    init_dma(&list);
+
+    for (i, ch) in CHANNELS.iter().enumerate() {
+        let used = list.iter().any(|d| d.channel as usize == i)
+            || cds.iter().any(|cd| cd.channel as usize == i);
+        if used {
+            unsafe { outb(ch.ctrl, 0) }; // nIEN clear: interrupts on
+            crate::arch::x86_64::apic::enable_irq(ch.irq);
         }
     }
 
-    *DRIVES.lock() = list;
-}
-
-/// Number of detected drives
-pub fn drive_count() -> usize {
-    DRIVES.lock().len()
-}
-
-pub fn drive_info(idx: usize) -> Option<Drive> {
-    DRIVES.lock().get(idx).cloned()
+    if list.is_empty() && cds.is_empty() {
+        log::info!("IDE: no drives found");
+    }
+    for mut d in list {
+        log::info!("IDE: {} — {} MiB  s/n: {}", d.id.model, d.id.sectors / 2048, d.id.serial);
+        d.name = block::next_name("hd");
+        block::register(Arc::new(d));
+    }
+    for mut cd in cds {
+        cd.name = block::next_name("cd");
+        block::register(Arc::new(cd));
+    }
 }
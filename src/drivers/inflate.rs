@@ -0,0 +1,294 @@
+/// Deflate (RFC 1951) and its zlib wrapper (RFC 1950), decompression only:
+/// what PNG stores its pixels in, and what compressed initrds use.
+///
+/// Huffman codes are decoded canonically a bit at a time, as in zlib's
+/// `puff`: no tables to build beyond a count per length, which is slower
+/// than a lookup table but plenty for a picture or a boot image.
+use alloc::vec::Vec;
+
+type Result<T> = core::result::Result<T, &'static str>;
+
+const MAX_BITS: usize = 15;
+
+/// Bits of the input, least significant first, as deflate packs them.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+    nbits: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Bits { data, pos: 0, bit: 0, nbits: 0 }
+    }
+
+    fn need(&mut self, n: u32) -> Result<u32> {
+        while self.nbits < n {
+            let &b = self.data.get(self.pos).ok_or("deflate data cut short")?;
+            self.pos += 1;
+            self.bit |= (b as u32) << self.nbits;
+            self.nbits += 8;
+        }
+        let v = self.bit & ((1u32 << n) - 1);
+        self.bit >>= n;
+        self.nbits -= n;
+        Ok(v)
+    }
+
+    /// Drop what is left of the current byte, for a stored block.
+    fn align(&mut self) {
+        self.bit = 0;
+        self.nbits = 0;
+    }
+}
+
+/// A canonical Huffman code: how many codes there are of each length, and
+/// the symbols in code order.
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    /// From the code length of each symbol, 0 for unused. Codes that would
+    /// need more than the lengths allow are an error; an incomplete code is
+    /// let through, since a single-distance-code block is one.
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut count = [0u16; MAX_BITS + 1];
+        for &l in lengths.iter().filter(|&&l| l != 0) {
+            count[l as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for &c in &count[1..] {
+            left = left * 2 - c as i32;
+            if left < 0 {
+                return Err("over-subscribed Huffman code");
+            }
+        }
+        let mut offs = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offs[len + 1] = offs[len] + count[len];
+        }
+        let mut symbol = alloc::vec![0u16; lengths.len()];
+        for (sym, &l) in lengths.iter().enumerate() {
+            if l != 0 {
+                symbol[offs[l as usize] as usize] = sym as u16;
+                offs[l as usize] += 1;
+            }
+        }
+        Ok(Huffman { count, symbol })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16> {
+        // `code` is the bits so far; `first` the first code of this length
+        // and `index` where its symbols start
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= bits.need(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - count < first {
+                return Ok(self.symbol[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("bad Huffman code")
+    }
+}
+
+/// Base lengths and extra bits for length symbols 257..=285.
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] =
+    [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// Base distances and extra bits for distance symbols 0..=29.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order the code length code lengths come in.
+const CLEN_ORDER: [usize; 19] =
+    [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+struct Inflater<'a> {
+    bits: Bits<'a>,
+    out: Vec<u8>,
+    limit: usize,
+}
+
+impl Inflater<'_> {
+    fn push(&mut self, b: u8) -> Result<()> {
+        if self.out.len() >= self.limit {
+            return Err("inflated data too large");
+        }
+        self.out.push(b);
+        Ok(())
+    }
+
+    fn extra(&mut self, bits: u8) -> Result<usize> {
+        Ok(self.bits.need(bits as u32)? as usize)
+    }
+
+    fn stored(&mut self) -> Result<()> {
+        self.bits.align();
+        let d = self.bits.data;
+        let p = self.bits.pos;
+        let hdr = d.get(p..p + 4).ok_or("deflate data cut short")?;
+        let len = u16::from_le_bytes([hdr[0], hdr[1]]) as usize;
+        if len != !u16::from_le_bytes([hdr[2], hdr[3]]) as usize {
+            return Err("stored block length mismatch");
+        }
+        let data = d.get(p + 4..p + 4 + len).ok_or("deflate data cut short")?;
+        if self.out.len() + len > self.limit {
+            return Err("inflated data too large");
+        }
+        self.out.extend_from_slice(data);
+        self.bits.pos = p + 4 + len;
+        Ok(())
+    }
+
+    fn codes(&mut self, lit: &Huffman, dist: &Huffman) -> Result<()> {
+        loop {
+            let sym = lit.decode(&mut self.bits)? as usize;
+            match sym {
+                0..=255 => self.push(sym as u8)?,
+                256 => return Ok(()),
+                257..=285 => {
+                    let i = sym - 257;
+                    let len = LEN_BASE[i] as usize + self.extra(LEN_EXTRA[i])?;
+                    let d = dist.decode(&mut self.bits)? as usize;
+                    if d >= 30 {
+                        return Err("bad distance symbol");
+                    }
+                    let back = DIST_BASE[d] as usize + self.extra(DIST_EXTRA[d])?;
+                    if back > self.out.len() {
+                        return Err("distance before start of output");
+                    }
+                    // Byte by byte: the copy may overlap what it's writing
+                    let from = self.out.len() - back;
+                    for k in 0..len {
+                        self.push(self.out[from + k])?;
+                    }
+                }
+                _ => return Err("bad length symbol"),
+            }
+        }
+    }
+
+    fn fixed(&mut self) -> Result<()> {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        let lit = Huffman::new(&lengths)?;
+        let dist = Huffman::new(&[5; 30])?;
+        self.codes(&lit, &dist)
+    }
+
+    fn dynamic(&mut self) -> Result<()> {
+        let nlen = self.bits.need(5)? as usize + 257;
+        let ndist = self.bits.need(5)? as usize + 1;
+        let ncode = self.bits.need(4)? as usize + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err("bad dynamic block counts");
+        }
+        let mut clen = [0u8; 19];
+        for &i in &CLEN_ORDER[..ncode] {
+            clen[i] = self.bits.need(3)? as u8;
+        }
+        let clen = Huffman::new(&clen)?;
+
+        // Literal/length and distance code lengths, run-length coded as one
+        // sequence: a repeat may run from one into the other
+        let mut lengths = [0u8; 286 + 30];
+        let mut n = 0;
+        while n < nlen + ndist {
+            let sym = clen.decode(&mut self.bits)?;
+            let (value, times) = match sym {
+                0..=15 => (sym as u8, 1),
+                16 => {
+                    let prev = n.checked_sub(1).ok_or("repeat with no length")?;
+                    (lengths[prev], 3 + self.extra(2)?)
+                }
+                17 => (0, 3 + self.extra(3)?),
+                _ => (0, 11 + self.extra(7)?),
+            };
+            if n + times > nlen + ndist {
+                return Err("code lengths overrun");
+            }
+            lengths[n..n + times].fill(value);
+            n += times;
+        }
+        if lengths[256] == 0 {
+            return Err("no end-of-block code");
+        }
+        let lit = Huffman::new(&lengths[..nlen])?;
+        let dist = Huffman::new(&lengths[nlen..nlen + ndist])?;
+        self.codes(&lit, &dist)
+    }
+}
+
+/// Decompress raw deflate `data`, refusing to produce more than `limit`
+/// bytes. Also gives how much of `data` the stream took, for a wrapper
+/// that has a trailer after it.
+pub fn inflate(data: &[u8], limit: usize) -> Result<(Vec<u8>, usize)> {
+    let mut inf = Inflater { bits: Bits::new(data), out: Vec::new(), limit };
+    loop {
+        let last = inf.bits.need(1)? == 1;
+        match inf.bits.need(2)? {
+            0 => inf.stored()?,
+            1 => inf.fixed()?,
+            2 => inf.dynamic()?,
+            _ => return Err("bad deflate block type"),
+        }
+        if last {
+            return Ok((inf.out, inf.bits.pos));
+        }
+    }
+}
+
+/// Decompress a zlib stream: deflate with a two-byte header in front and
+/// the Adler-32 of the output after.
+pub fn zlib(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let [cmf, flg, ..] = *data else {
+        return Err("zlib data cut short");
+    };
+    if cmf & 0x0F != 8 || cmf >> 4 > 7 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err("not a zlib stream");
+    }
+    if flg & 0x20 != 0 {
+        return Err("zlib preset dictionary");
+    }
+    let (out, used) = inflate(&data[2..], limit)?;
+    let tail = data.get(2 + used..2 + used + 4).ok_or("zlib checksum missing")?;
+    if u32::from_be_bytes(tail.try_into().unwrap()) != adler32(&out) {
+        return Err("zlib checksum mismatch");
+    }
+    Ok(out)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before `b` could overflow
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
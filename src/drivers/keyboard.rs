@@ -50,14 +50,16 @@ pub fn init() {
 
         // Bit 0 = Keyboard Interrupt Enable (KIE).
         // Bit 4 = Keyboard Clock Disable — clear it so the keyboard is enabled.
-        let new_ccb = (ccb | 0x01) & !0x10;
+        // Bit 6 = translation to scancode set 1, whichever set the keyboard
+        // itself speaks; the decoder only knows set 1.
+        let new_ccb = (ccb | 0x41) & !0x10;
 
         // Command 0x60 = "Write CCB"; follow with the new byte on port 0x60.
         i8042_wait_write();
         outb(KB_STATUS, 0x60);
         i8042_wait_write();
         outb(KB_DATA, new_ccb);
-        crate::serial_println!("[KB] i8042 CCB → {:#04x} (KIE=1)", new_ccb);
+        crate::serial_println!("[KB] i8042 CCB → {:#04x} (KIE=1, XLAT=1)", new_ccb);
 
         // Re-enable first PS/2 port — this is the critical step that lets the
         // keyboard generate IRQ1 for keystrokes.
@@ -81,126 +83,271 @@ static SCANCODE_MAP: &[u8] = &[
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
 ];
 
-const KB_BUF_SIZE: usize = 256;
-
-struct KbBuffer {
-    buf: [u8; KB_BUF_SIZE],
-    head: usize,
-    tail: usize,
+// ─── Key events ──────────────────────────────────────────────────────────────
+
+/// A key, whatever the scancode sequence that reported it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyCode {
+    /// A key that types something, as its unshifted byte: letters, digits,
+    /// punctuation, '\n', '\t', 8 (Backspace), 27 (Escape).
+    Char(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    /// F1..F12
+    F(u8),
+    Shift,
+    Ctrl,
+    Alt,
+    CapsLock,
 }
 
-impl KbBuffer {
-    const fn new() -> Self {
-        Self {
-            buf: [0; KB_BUF_SIZE],
-            head: 0,
-            tail: 0,
-        }
-    }
-
-    fn push(&mut self, c: u8) {
-        let next = (self.head + 1) % KB_BUF_SIZE;
-        if next != self.tail {
-            self.buf[self.head] = c;
-            self.head = next;
-        }
-    }
-
-    fn pop(&mut self) -> Option<u8> {
-        if self.head == self.tail {
-            return None;
-        }
-        let c = self.buf[self.tail];
-        self.tail = (self.tail + 1) % KB_BUF_SIZE;
-        Some(c)
-    }
-
-    fn is_empty(&self) -> bool {
-        self.head == self.tail
-    }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub caps: bool,
 }
 
-static KB_BUF: SpinLock<KbBuffer> = SpinLock::new(KbBuffer::new());
-
-struct Modifiers {
-    shift: bool,
-    ctrl: bool,
-    alt: bool,
-    caps: bool,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    /// Modifiers held when the key changed state (after the change, for a
+    /// modifier key itself).
+    pub mods: Modifiers,
+    pub pressed: bool,
 }
 
-static MODS: SpinLock<Modifiers> = SpinLock::new(Modifiers {
-    shift: false,
-    ctrl: false,
-    alt: false,
-    caps: false,
-});
-
 const SC_LSHIFT: u8 = 0x2A;
 const SC_RSHIFT: u8 = 0x36;
-const SC_LCTRL: u8 = 0x1D;
-const SC_LALT: u8 = 0x38;
+const SC_CTRL: u8 = 0x1D;
+const SC_ALT: u8 = 0x38;
 const SC_CAPS: u8 = 0x3A;
 const SC_BREAK: u8 = 0x80;
+/// Prefix of the extended keys (arrows, right Ctrl/Alt, the nav block).
+const SC_EXT: u8 = 0xE0;
+/// Prefix of Pause, the one key with a 3-byte make code.
+const SC_EXT1: u8 = 0xE1;
+
+/// Set 1 key that isn't in SCANCODE_MAP: modifiers, F-keys and the
+/// keypad, read with NumLock off.
+fn base_key(sc: u8) -> Option<KeyCode> {
+    Some(match sc {
+        SC_LSHIFT | SC_RSHIFT => KeyCode::Shift,
+        SC_CTRL => KeyCode::Ctrl,
+        SC_ALT => KeyCode::Alt,
+        SC_CAPS => KeyCode::CapsLock,
+        0x3B..=0x44 => KeyCode::F(sc - 0x3A),
+        0x57 => KeyCode::F(11),
+        0x58 => KeyCode::F(12),
+        0x4A => KeyCode::Char(b'-'),
+        0x4E => KeyCode::Char(b'+'),
+        other => return nav_key(other),
+    })
+}
 
-pub fn irq_keyboard(_frame: &mut InterruptFrame) {
-    let sc = unsafe { inb(KB_DATA) };
-    crate::serial_println!("[KB] sc={:#04x}", sc);
-    process_scancode(sc);
+/// The navigation block; the same codes come E0-prefixed from the
+/// dedicated keys and bare from the keypad.
+fn nav_key(sc: u8) -> Option<KeyCode> {
+    Some(match sc {
+        0x47 => KeyCode::Home,
+        0x48 => KeyCode::Up,
+        0x49 => KeyCode::PageUp,
+        0x4B => KeyCode::Left,
+        0x4D => KeyCode::Right,
+        0x4F => KeyCode::End,
+        0x50 => KeyCode::Down,
+        0x51 => KeyCode::PageDown,
+        0x52 => KeyCode::Insert,
+        0x53 => KeyCode::Delete,
+        _ => return None,
+    })
 }
 
-fn process_scancode(sc: u8) {
-    let released = sc & SC_BREAK != 0;
-    let sc_clean = sc & !SC_BREAK;
+fn extended_key(sc: u8) -> Option<KeyCode> {
+    match sc {
+        0x1C => Some(KeyCode::Char(b'\n')), // keypad Enter
+        SC_CTRL => Some(KeyCode::Ctrl),
+        0x35 => Some(KeyCode::Char(b'/')), // keypad /
+        SC_ALT => Some(KeyCode::Alt),
+        // E0 2A / E0 36: the "fake shifts" around nav keys, ignored
+        other => nav_key(other),
+    }
+}
 
-    let mut mods = MODS.lock();
+/// Scancode set 1 (what the i8042 translates every keyboard to) → events.
+struct Decoder {
+    /// E0 seen, waiting for the key byte.
+    ext: bool,
+    /// Bytes of a Pause sequence still to swallow.
+    skip: u8,
+    mods: Modifiers,
+}
 
-    match sc_clean {
-        SC_LSHIFT | SC_RSHIFT => {
-            mods.shift = !released;
-            return;
-        }
-        SC_LCTRL => {
-            mods.ctrl = !released;
-            return;
+impl Decoder {
+    fn feed(&mut self, sc: u8) -> Option<KeyEvent> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
         }
-        SC_LALT => {
-            mods.alt = !released;
-            return;
+        match sc {
+            SC_EXT => {
+                self.ext = true;
+                return None;
+            }
+            // E1 1D 45 on press, E1 9D C5 on release
+            SC_EXT1 => {
+                self.skip = 2;
+                return None;
+            }
+            _ => {}
         }
-        SC_CAPS if !released => {
-            mods.caps = !mods.caps;
-            return;
+        let ext = core::mem::take(&mut self.ext);
+        let pressed = sc & SC_BREAK == 0;
+        let sc = sc & !SC_BREAK;
+
+        let code = if ext {
+            extended_key(sc)?
+        } else {
+            match SCANCODE_MAP.get(sc as usize) {
+                Some(&c) if c != 0 => KeyCode::Char(c),
+                _ => base_key(sc)?,
+            }
+        };
+        match code {
+            KeyCode::Shift => self.mods.shift = pressed,
+            KeyCode::Ctrl => self.mods.ctrl = pressed,
+            KeyCode::Alt => self.mods.alt = pressed,
+            KeyCode::CapsLock if pressed => self.mods.caps = !self.mods.caps,
+            _ => {}
         }
-        _ => {}
+        Some(KeyEvent {
+            code,
+            mods: self.mods,
+            pressed,
+        })
     }
+}
 
-    if released {
+static DECODER: SpinLock<Decoder> = SpinLock::new(Decoder {
+    ext: false,
+    skip: 0,
+    mods: Modifiers {
+        shift: false,
+        ctrl: false,
+        alt: false,
+        caps: false,
+    },
+});
+
+/// IRQ1: only latch the scancode; decoding runs later in kworker.
+pub fn irq_keyboard(_frame: &mut InterruptFrame) {
+    let sc = unsafe { inb(KB_DATA) };
+    crate::proc::workqueue::schedule_work(scancode_work, sc as u64);
+}
+
+fn scancode_work(sc: u64) {
+    let ev = DECODER.lock().feed(sc as u8);
+    if let Some(ev) = ev {
+        handle_event(ev);
+    }
+}
+
+fn handle_event(ev: KeyEvent) {
+    if !ev.pressed {
         return;
     }
+    match ev.code {
+        // Alt+F1…F4: switch virtual terminals
+        KeyCode::F(n) if ev.mods.alt && (n as usize) <= super::vga::VT_COUNT => {
+            super::vga::switch_to(n as usize - 1);
+        }
+        // Shift+PageUp/PageDown: page through the scrollback
+        KeyCode::PageUp if ev.mods.shift => super::vga::scroll_view(1),
+        KeyCode::PageDown if ev.mods.shift => super::vga::scroll_view(-1),
+        _ => key_bytes(&ev, super::tty::keyboard_input),
+    }
+}
 
-    if (sc_clean as usize) < SCANCODE_MAP.len() {
-        let mut c = SCANCODE_MAP[sc_clean as usize];
-        if c == 0 {
-            return;
+/// The byte with Shift, CapsLock and Ctrl applied.
+fn char_byte(c: u8, mods: Modifiers) -> u8 {
+    let mut c = c;
+    if c.is_ascii_alphabetic() {
+        if mods.shift ^ mods.caps {
+            c = c.to_ascii_uppercase();
         }
+    } else if mods.shift {
+        c = shifted_char(c);
+    }
+    if mods.ctrl && c.is_ascii_alphabetic() {
+        c = c.to_ascii_uppercase() - b'@';
+    }
+    c
+}
 
-        if c.is_ascii_alphabetic() {
-            let upper = mods.shift ^ mods.caps;
-            if upper {
-                c = c.to_ascii_uppercase();
+/// What a key press types, as an xterm would send it: plain bytes for
+/// characters (ESC-prefixed with Alt), CSI sequences for the rest, with
+/// a ";<mod>" parameter when Shift/Alt/Ctrl are held.
+fn key_bytes(ev: &KeyEvent, mut push: impl FnMut(u8)) {
+    let m = ev.mods;
+    let csi = |push: &mut dyn FnMut(u8), num: u8, fin: u8| {
+        let modifier = 1 + m.shift as u8 + 2 * m.alt as u8 + 4 * m.ctrl as u8;
+        push(0x1b);
+        push(b'[');
+        if num >= 10 {
+            push(b'0' + num / 10);
+        }
+        if num > 0 {
+            push(b'0' + num % 10);
+        }
+        if modifier > 1 {
+            if num == 0 {
+                push(b'1');
             }
-        } else if mods.shift {
-            c = shifted_char(c);
+            push(b';');
+            push(b'0' + modifier);
         }
-
-        if mods.ctrl && c.is_ascii_alphabetic() {
-            c = c.to_ascii_uppercase() - b'@';
+        push(fin);
+    };
+    match ev.code {
+        // Backspace sends DEL, as on the Linux console and in xterm
+        KeyCode::Char(8) => push(0x7F),
+        KeyCode::Char(c) => {
+            if m.alt {
+                push(0x1b);
+            }
+            push(char_byte(c, m));
         }
-
-        drop(mods);
-        KB_BUF.lock().push(c);
-        crate::proc::wake_up_all_sleeping();
+        KeyCode::Up => csi(&mut push, 0, b'A'),
+        KeyCode::Down => csi(&mut push, 0, b'B'),
+        KeyCode::Right => csi(&mut push, 0, b'C'),
+        KeyCode::Left => csi(&mut push, 0, b'D'),
+        KeyCode::Home => csi(&mut push, 0, b'H'),
+        KeyCode::End => csi(&mut push, 0, b'F'),
+        KeyCode::Insert => csi(&mut push, 2, b'~'),
+        KeyCode::Delete => csi(&mut push, 3, b'~'),
+        KeyCode::PageUp => csi(&mut push, 5, b'~'),
+        KeyCode::PageDown => csi(&mut push, 6, b'~'),
+        KeyCode::F(n @ 1..=4) => {
+            push(0x1b);
+            push(b'O');
+            push(b'P' + n - 1);
+        }
+        KeyCode::F(n) => {
+            const CODES: [u8; 8] = [15, 17, 18, 19, 20, 21, 23, 24];
+            if let Some(&code) = CODES.get(n as usize - 5) {
+                csi(&mut push, code, b'~');
+            }
+        }
+        KeyCode::Shift | KeyCode::Ctrl | KeyCode::Alt | KeyCode::CapsLock => {}
     }
 }
 
@@ -230,29 +377,3 @@ fn shifted_char(c: u8) -> u8 {
         c => c,
     }
 }
-
-pub fn read_char() -> Option<u8> {
-    KB_BUF.lock().pop()
-}
-
-pub fn push_char(c: u8) {
-    KB_BUF.lock().push(c);
-    crate::proc::wake_up_all_sleeping();
-}
-
-/// Race-free blocking read: holds IF=0 across the check-and-sleep transition
-/// so a keyboard IRQ cannot arrive after the buffer check but before the
-/// process is marked Sleeping (which would leave it asleep with data pending).
-pub fn wait_key() -> u8 {
-    use crate::arch::x86_64::io::{cli, sti, RFLAGS_IF};
-    loop {
-        let rflags = unsafe { cli() };
-        if let Some(c) = read_char() {
-            if rflags & RFLAGS_IF != 0 {
-                sti();
-            }
-            return c;
-        }
-        crate::proc::scheduler::sleep_current();
-    }
-}
@@ -1,8 +1,93 @@
+use crate::sync::spinlock::SpinLock;
 use core::fmt::Write;
 use log::{Level, LevelFilter, Log, Metadata, Record};
 
+/// The most recent log output, without colors, for the panic report.
+const RING_SIZE: usize = 16 * 1024;
+
+/// Longest line `recent` hands out; the rest of a longer one is cut.
+const LINE_MAX: usize = 160;
+
+struct Ring {
+    buf: [u8; RING_SIZE],
+    /// Where the next byte goes.
+    head: usize,
+    len: usize,
+}
+
+impl Ring {
+    fn byte(&self, i: usize) -> u8 {
+        self.buf[(self.head + RING_SIZE - self.len + i) % RING_SIZE]
+    }
+}
+
+impl core::fmt::Write for Ring {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for b in s.bytes() {
+            self.buf[self.head] = b;
+            self.head = (self.head + 1) % RING_SIZE;
+            self.len = (self.len + 1).min(RING_SIZE);
+        }
+        Ok(())
+    }
+}
+
+static RING: SpinLock<Ring> = SpinLock::new(Ring {
+    buf: [0; RING_SIZE],
+    head: 0,
+    len: 0,
+});
+
+/// The last `lines` lines logged, oldest first. False if the buffer is
+/// busy: the panic came from inside the logger.
+pub fn recent(lines: usize, mut f: impl FnMut(&[u8])) -> bool {
+    let Some(ring) = RING.try_lock() else {
+        return false;
+    };
+    // Back from the end over `lines` newlines; the first line may be the
+    // tail of one already overwritten
+    let mut start = ring.len;
+    let mut seen = 0;
+    while start > 0 {
+        if ring.byte(start - 1) == b'\n' && start != ring.len {
+            seen += 1;
+            if seen == lines {
+                break;
+            }
+        }
+        start -= 1;
+    }
+    let mut line = [0u8; LINE_MAX];
+    let mut n = 0;
+    for i in start..ring.len {
+        match ring.byte(i) {
+            b'\n' => {
+                f(&line[..n]);
+                n = 0;
+            }
+            b if n < LINE_MAX => {
+                line[n] = b;
+                n += 1;
+            }
+            _ => {}
+        }
+    }
+    if n > 0 {
+        f(&line[..n]);
+    }
+    true
+}
+
 pub struct KernelLogger;
 
+struct LogVt;
+impl core::fmt::Write for LogVt {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        crate::drivers::vga::write_vt(crate::drivers::vga::LOG_VT, s);
+        Ok(())
+    }
+}
+
 impl Log for KernelLogger {
     fn enabled(&self, meta: &Metadata) -> bool {
         meta.level() <= Level::Trace
@@ -13,6 +98,18 @@ impl Log for KernelLogger {
             return;
         }
 
+        let _ = writeln!(
+            RING.lock(),
+            "[{:>5}] {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        // `loglevel=` quiets the console, not the record kept above
+        if record.level() > crate::cmdline::options().loglevel {
+            return;
+        }
+
         let level_str = match record.level() {
             Level::Error => "\x1b[31mERROR\x1b[0m",
             Level::Warn => "\x1b[33m WARN\x1b[0m",
@@ -23,24 +120,10 @@ impl Log for KernelLogger {
 
         crate::serial_println!("[{}] {}: {}", level_str, record.target(), record.args());
 
-        match record.level() {
-            Level::Error | Level::Warn => {
-                crate::drivers::vga::set_color(
-                    if record.level() == Level::Error {
-                        crate::drivers::vga::RED
-                    } else {
-                        crate::drivers::vga::YELLOW
-                    },
-                    crate::drivers::vga::BLACK,
-                );
-            }
-            _ => {
-                crate::drivers::vga::set_color(
-                    crate::drivers::vga::LIGHT_GRAY,
-                    crate::drivers::vga::BLACK,
-                );
-            }
-        }
+        // And to the log VT, where the level's color comes through as well
+        let mut vt = LogVt;
+        let _ = writeln!(vt, "[{}] {}: {}", level_str, record.target(), record.args());
+        crate::drivers::vga::flush();
     }
 
     fn flush(&self) {}
@@ -0,0 +1,178 @@
+/// Generic network-device layer.
+/// NIC drivers register an `Arc<dyn NetDevice>` here and hand the frames
+/// they receive to `deliver`; protocol code sends and receives through the
+/// `Interface`, never touching a controller directly. Sending goes
+/// straight to the driver's transmit ring, received frames wait in a
+/// per-interface queue until `receive` takes them.
+use crate::sync::spinlock::SpinLock;
+use crate::sync::waitqueue::WaitQueue;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub type MacAddr = [u8; 6];
+
+/// Largest Ethernet frame: 14-byte header and 1500 bytes of payload, no FCS.
+pub const FRAME_MAX: usize = 1514;
+
+/// Received frames kept per interface; more are dropped until it drains.
+const RX_QUEUE_MAX: usize = 128;
+
+pub trait NetDevice: Send + Sync {
+    /// Short interface name, e.g. "eth0".
+    fn name(&self) -> &str;
+
+    fn mac(&self) -> MacAddr;
+
+    fn link_up(&self) -> bool;
+
+    /// Queue one frame (destination MAC onwards, without FCS) for sending.
+    fn transmit(&self, frame: &[u8]) -> Result<(), &'static str>;
+
+    /// Human-readable description (model, bus, IRQ, ...).
+    fn info(&self) -> String {
+        String::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub rx_dropped: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    pub tx_errors: u64,
+}
+
+pub struct Interface {
+    /// Position in the registry.
+    pub index: usize,
+    pub dev: Arc<dyn NetDevice>,
+    rx: SpinLock<VecDeque<Vec<u8>>>,
+    stats: SpinLock<Stats>,
+}
+
+impl Interface {
+    pub fn send(&self, frame: &[u8]) -> Result<(), &'static str> {
+        if frame.len() > FRAME_MAX {
+            return Err("frame too long");
+        }
+        let res = self.dev.transmit(frame);
+        let mut s = self.stats.lock();
+        match res {
+            Ok(()) => {
+                s.tx_packets += 1;
+                s.tx_bytes += frame.len() as u64;
+            }
+            Err(_) => s.tx_errors += 1,
+        }
+        res
+    }
+
+    /// The oldest received frame, if any.
+    pub fn try_receive(&self) -> Option<Vec<u8>> {
+        self.rx.lock().pop_front()
+    }
+
+    pub fn stats(&self) -> Stats {
+        *self.stats.lock()
+    }
+
+    fn push(&self, frame: &[u8]) {
+        let queued = {
+            let mut rx = self.rx.lock();
+            if rx.len() < RX_QUEUE_MAX {
+                rx.push_back(frame.to_vec());
+                true
+            } else {
+                false
+            }
+        };
+        let mut s = self.stats.lock();
+        if queued {
+            s.rx_packets += 1;
+            s.rx_bytes += frame.len() as u64;
+        } else {
+            s.rx_dropped += 1;
+        }
+        drop(s);
+        if queued {
+            RX_WAIT.wake_all();
+        }
+    }
+}
+
+// ─── Registry ────────────────────────────────────────────────────────────────
+
+static INTERFACES: SpinLock<Vec<Arc<Interface>>> = SpinLock::new(Vec::new());
+
+/// Sleepers in `receive`, woken by a frame on any interface.
+static RX_WAIT: WaitQueue = WaitQueue::new("netrx");
+
+/// Add a NIC; returns its index, what the driver passes to `deliver`.
+pub fn register(dev: Arc<dyn NetDevice>) -> usize {
+    log::info!(
+        "net: {} — {} link {} {}",
+        dev.name(),
+        format_mac(&dev.mac()),
+        if dev.link_up() { "up" } else { "down" },
+        dev.info()
+    );
+    let mut ifaces = INTERFACES.lock();
+    let index = ifaces.len();
+    ifaces.push(Arc::new(Interface {
+        index,
+        dev,
+        rx: SpinLock::new(VecDeque::new()),
+        stats: SpinLock::new(Stats::default()),
+    }));
+    index
+}
+
+/// The oldest frame waiting on any interface, sleeping until one comes.
+pub fn receive() -> (Arc<Interface>, Vec<u8>) {
+    RX_WAIT.wait_event(|| {
+        INTERFACES
+            .lock()
+            .iter()
+            .find_map(|i| i.try_receive().map(|f| (i.clone(), f)))
+    })
+}
+
+/// A frame the NIC at `idx` received; called from its interrupt handler.
+pub fn deliver(idx: usize, frame: &[u8]) {
+    let iface = INTERFACES.lock().get(idx).cloned();
+    if let Some(iface) = iface {
+        iface.push(frame);
+    }
+}
+
+pub fn count() -> usize {
+    INTERFACES.lock().len()
+}
+
+pub fn get(idx: usize) -> Option<Arc<Interface>> {
+    INTERFACES.lock().get(idx).cloned()
+}
+
+pub fn by_name(name: &str) -> Option<Arc<Interface>> {
+    INTERFACES.lock().iter().find(|i| i.dev.name() == name).cloned()
+}
+
+pub fn interfaces() -> Vec<Arc<Interface>> {
+    INTERFACES.lock().clone()
+}
+
+/// Name for the next NIC to register: "eth0", "eth1", ...
+pub fn next_name() -> String {
+    alloc::format!("eth{}", INTERFACES.lock().len())
+}
+
+pub fn format_mac(mac: &MacAddr) -> String {
+    alloc::format!(
+        "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    )
+}
@@ -0,0 +1,416 @@
+/// NVMe driver.
+/// One admin queue pair and one I/O queue pair per controller, both polled
+/// (controller interrupts stay masked). Data goes through a contiguous
+/// bounce buffer described by PRP1 + a PRP list. Every namespace with
+/// 512-byte LBAs is registered as a block device (nvme0n1, ...).
+use super::block::{self, BlockDevice, SECTOR_SIZE};
+use super::pci::{self, PciDevice};
+use crate::arch::x86_64::io::{mmio_read32, mmio_write32};
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::mm::pmm::{alloc_frames, alloc_zeroed_frame, PAGE_SIZE};
+use crate::mm::vmm::map_mmio;
+use crate::sync::spinlock::SpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+// ─── Controller registers ─────────────────────────────────────────────────────
+
+const REG_CAP: u64 = 0x00;
+const REG_VS: u64 = 0x08;
+const REG_INTMS: u64 = 0x0C;
+const REG_CC: u64 = 0x14;
+const REG_CSTS: u64 = 0x1C;
+const REG_AQA: u64 = 0x24;
+const REG_ASQ: u64 = 0x28;
+const REG_ACQ: u64 = 0x30;
+const REG_DOORBELL: u64 = 0x1000;
+
+const CC_EN: u32 = 1 << 0;
+const CC_IOSQES: u32 = 6 << 16; // 64-byte submission entries
+const CC_IOCQES: u32 = 4 << 20; // 16-byte completion entries
+const CSTS_RDY: u32 = 1 << 0;
+const CSTS_CFS: u32 = 1 << 1;
+
+const MMIO_SIZE: u64 = 0x2000;
+
+// ─── Commands ─────────────────────────────────────────────────────────────────
+
+const ADMIN_CREATE_SQ: u8 = 0x01;
+const ADMIN_CREATE_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+
+const NVM_FLUSH: u8 = 0x00;
+const NVM_WRITE: u8 = 0x01;
+const NVM_READ: u8 = 0x02;
+
+const CNS_NAMESPACE: u32 = 0x00;
+const CNS_CONTROLLER: u32 = 0x01;
+
+/// Entries per queue; one page of submissions (64 B each).
+const QUEUE_DEPTH: u16 = 64;
+
+/// Bounce buffer: 2^4 pages = 64 KiB per command (before MDTS clamping).
+const BUF_ORDER: usize = 4;
+const BUF_PAGES: usize = 1 << BUF_ORDER;
+
+/// Only the first few namespaces get probed.
+const MAX_NAMESPACES: u32 = 16;
+
+const SPIN_TIMEOUT: u32 = 10_000_000;
+
+// ─── MMIO helpers ─────────────────────────────────────────────────────────────
+
+fn rd32(addr: u64) -> u32 {
+    unsafe { mmio_read32(addr) }
+}
+
+fn wr32(addr: u64, val: u32) {
+    unsafe { mmio_write32(addr, val) }
+}
+
+fn rd64(addr: u64) -> u64 {
+    // Two 32-bit reads: not every controller accepts 64-bit MMIO accesses
+    (rd32(addr) as u64) | ((rd32(addr + 4) as u64) << 32)
+}
+
+fn wr64(addr: u64, val: u64) {
+    wr32(addr, val as u32);
+    wr32(addr + 4, (val >> 32) as u32);
+}
+
+// ─── Queue pair ───────────────────────────────────────────────────────────────
+
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct Command {
+    cdw0: u32, // opcode | command id << 16
+    nsid: u32,
+    _rsvd: u64,
+    mptr: u64,
+    prp1: u64,
+    prp2: u64,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+}
+
+struct Queue {
+    sq_phys: u64,
+    cq_phys: u64,
+    sq_db: u64,
+    cq_db: u64,
+    tail: u16,
+    head: u16,
+    phase: bool,
+    next_cid: u16,
+}
+
+impl Queue {
+    fn new(regs: u64, dstrd: u32, qid: u16) -> Option<Queue> {
+        let stride = 4u64 << dstrd;
+        Some(Queue {
+            sq_phys: alloc_zeroed_frame()?,
+            cq_phys: alloc_zeroed_frame()?,
+            sq_db: regs + REG_DOORBELL + (2 * qid as u64) * stride,
+            cq_db: regs + REG_DOORBELL + (2 * qid as u64 + 1) * stride,
+            tail: 0,
+            head: 0,
+            phase: true,
+            next_cid: 0,
+        })
+    }
+
+    /// Submit `cmd` and poll for its completion.
+    fn submit(&mut self, mut cmd: Command) -> Result<(), &'static str> {
+        let cid = self.next_cid;
+        self.next_cid = self.next_cid.wrapping_add(1);
+        cmd.cdw0 |= (cid as u32) << 16;
+
+        let sq = phys_to_virt(self.sq_phys) as *mut Command;
+        unsafe { sq.add(self.tail as usize).write_volatile(cmd) };
+        self.tail = (self.tail + 1) % QUEUE_DEPTH;
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+        wr32(self.sq_db, self.tail as u32);
+
+        // Completion entry: dword 3 = status (31:17) | phase (16) | cid (15:0)
+        let cq = phys_to_virt(self.cq_phys) as *const u32;
+        for _ in 0..SPIN_TIMEOUT {
+            let dw3 = unsafe { cq.add(self.head as usize * 4 + 3).read_volatile() };
+            if (dw3 & (1 << 16) != 0) == self.phase {
+                self.head += 1;
+                if self.head == QUEUE_DEPTH {
+                    self.head = 0;
+                    self.phase = !self.phase;
+                }
+                wr32(self.cq_db, self.head as u32);
+                if dw3 >> 17 != 0 {
+                    log::warn!("NVMe: command {:#x} failed, status {:#x}", cmd.cdw0 & 0xFF, dw3 >> 17);
+                    return Err("NVMe command error");
+                }
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err("NVMe command timeout")
+    }
+}
+
+// ─── Controller ───────────────────────────────────────────────────────────────
+
+struct Controller {
+    admin: Queue,
+    io: Queue,
+    buf_phys: u64,
+    /// PRP list covering bounce-buffer pages 1.. (page 0 goes in PRP1)
+    prp_list_phys: u64,
+    /// Max sectors per command (bounce buffer size clamped to MDTS)
+    max_sectors: usize,
+    /// Volatile write cache present: flush after writes
+    vwc: bool,
+}
+
+impl Controller {
+    fn buf(&self) -> *mut u8 {
+        phys_to_virt(self.buf_phys) as *mut u8
+    }
+
+    /// PRP1/PRP2 for a transfer of `bytes` from the start of the bounce buffer.
+    fn prps(&self, bytes: usize) -> (u64, u64) {
+        let prp2 = match bytes.div_ceil(PAGE_SIZE) {
+            0 | 1 => 0,
+            2 => self.buf_phys + PAGE_SIZE as u64,
+            _ => self.prp_list_phys,
+        };
+        (self.buf_phys, prp2)
+    }
+
+    fn identify(&mut self, cns: u32, nsid: u32) -> Result<(), &'static str> {
+        let (prp1, _) = self.prps(PAGE_SIZE);
+        self.admin.submit(Command {
+            cdw0: ADMIN_IDENTIFY as u32,
+            nsid,
+            prp1,
+            cdw10: cns,
+            ..Default::default()
+        })
+    }
+
+    fn rw(&mut self, opcode: u8, nsid: u32, lba: u64, sectors: usize) -> Result<(), &'static str> {
+        let (prp1, prp2) = self.prps(sectors * SECTOR_SIZE);
+        self.io.submit(Command {
+            cdw0: opcode as u32,
+            nsid,
+            prp1,
+            prp2,
+            cdw10: lba as u32,
+            cdw11: (lba >> 32) as u32,
+            cdw12: (sectors - 1) as u32, // 0-based block count
+            ..Default::default()
+        })
+    }
+}
+
+// ─── Namespace (block device) ─────────────────────────────────────────────────
+
+struct Namespace {
+    name: String,
+    nsid: u32,
+    sectors: u64,
+    model: String,
+    ctrl: Arc<SpinLock<Controller>>,
+}
+
+impl BlockDevice for Namespace {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read_sectors(&self, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        let mut c = self.ctrl.lock();
+        let step = c.max_sectors;
+        for (i, chunk) in buf.chunks_mut(step * SECTOR_SIZE).enumerate() {
+            let n = chunk.len() / SECTOR_SIZE;
+            c.rw(NVM_READ, self.nsid, lba + (i * step) as u64, n)?;
+            unsafe { core::ptr::copy_nonoverlapping(c.buf(), chunk.as_mut_ptr(), chunk.len()) };
+        }
+        Ok(())
+    }
+
+    fn write_sectors(&self, lba: u64, count: u16, buf: &[u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        let mut c = self.ctrl.lock();
+        let step = c.max_sectors;
+        for (i, chunk) in buf.chunks(step * SECTOR_SIZE).enumerate() {
+            let n = chunk.len() / SECTOR_SIZE;
+            unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), c.buf(), chunk.len()) };
+            c.rw(NVM_WRITE, self.nsid, lba + (i * step) as u64, n)?;
+        }
+        if c.vwc {
+            let nsid = self.nsid;
+            c.io.submit(Command { cdw0: NVM_FLUSH as u32, nsid, ..Default::default() })?;
+        }
+        Ok(())
+    }
+
+    fn info(&self) -> String {
+        alloc::format!("{} [NVMe ns {}]", self.model, self.nsid)
+    }
+}
+
+// ─── Init ─────────────────────────────────────────────────────────────────────
+
+fn wait_ready(regs: u64, ready: bool) -> Result<(), &'static str> {
+    for _ in 0..SPIN_TIMEOUT {
+        let csts = rd32(regs + REG_CSTS);
+        if csts & CSTS_CFS != 0 {
+            return Err("controller fatal status");
+        }
+        if (csts & CSTS_RDY != 0) == ready {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err("timeout waiting for CSTS.RDY")
+}
+
+fn id_string(bytes: &[u8]) -> String {
+    String::from(core::str::from_utf8(bytes).unwrap_or("").trim())
+}
+
+fn init_controller(index: usize, dev: &PciDevice) -> Result<(), &'static str> {
+    pci::enable_bus_master(dev.bus, dev.dev, dev.func);
+    let regs = map_mmio(dev.bar_base(0), MMIO_SIZE).ok_or("cannot map BAR0")?;
+
+    let cap = rd64(regs + REG_CAP);
+    let dstrd = ((cap >> 32) & 0xF) as u32;
+    let mqes = (cap & 0xFFFF) as u32 + 1; // 0-based
+    if (cap >> 48) & 0xF != 0 {
+        return Err("4 KiB pages not supported (MPSMIN)");
+    }
+    if mqes < QUEUE_DEPTH as u32 {
+        return Err("controller queues too small");
+    }
+
+    // Reset, then set up the admin queue pair
+    wr32(regs + REG_CC, rd32(regs + REG_CC) & !CC_EN);
+    wait_ready(regs, false)?;
+
+    let admin = Queue::new(regs, dstrd, 0).ok_or("out of memory")?;
+    let qsize = (QUEUE_DEPTH - 1) as u32;
+    wr32(regs + REG_AQA, (qsize << 16) | qsize);
+    wr64(regs + REG_ASQ, admin.sq_phys);
+    wr64(regs + REG_ACQ, admin.cq_phys);
+    wr32(regs + REG_INTMS, u32::MAX); // polled
+    wr32(regs + REG_CC, CC_EN | CC_IOSQES | CC_IOCQES);
+    wait_ready(regs, true)?;
+
+    let buf_phys = alloc_frames(BUF_ORDER).ok_or("out of memory")?;
+    let prp_list_phys = alloc_zeroed_frame().ok_or("out of memory")?;
+    let prp_list = phys_to_virt(prp_list_phys) as *mut u64;
+    for i in 1..BUF_PAGES {
+        unsafe { prp_list.add(i - 1).write(buf_phys + (i * PAGE_SIZE) as u64) };
+    }
+
+    let io = Queue::new(regs, dstrd, 1).ok_or("out of memory")?;
+    let mut c = Controller {
+        admin,
+        io,
+        buf_phys,
+        prp_list_phys,
+        max_sectors: BUF_PAGES * PAGE_SIZE / SECTOR_SIZE,
+        vwc: false,
+    };
+
+    // Identify controller: serial 4..24, model 24..64, MDTS 77, VWC 525, NN 516
+    c.identify(CNS_CONTROLLER, 0)?;
+    let id = unsafe { core::slice::from_raw_parts(c.buf(), PAGE_SIZE) };
+    let serial = id_string(&id[4..24]);
+    let model = id_string(&id[24..64]);
+    let mdts = id[77];
+    let nn = u32::from_le_bytes([id[516], id[517], id[518], id[519]]);
+    c.vwc = id[525] & 1 != 0;
+    if mdts != 0 {
+        c.max_sectors = c.max_sectors.min((PAGE_SIZE << mdts) / SECTOR_SIZE);
+    }
+
+    let vs = rd32(regs + REG_VS);
+    log::info!(
+        "NVMe: {:02x}:{:02x}.{} v{}.{} — {}  s/n: {}  namespaces={}",
+        dev.bus, dev.dev, dev.func,
+        vs >> 16, (vs >> 8) & 0xFF,
+        model, serial, nn,
+    );
+
+    // I/O queue pair 1: completion queue first, no interrupts (IEN = 0)
+    let cq_phys = c.io.cq_phys;
+    let sq_phys = c.io.sq_phys;
+    c.admin.submit(Command {
+        cdw0: ADMIN_CREATE_CQ as u32,
+        prp1: cq_phys,
+        cdw10: (qsize << 16) | 1,
+        cdw11: 1, // physically contiguous
+        ..Default::default()
+    })?;
+    c.admin.submit(Command {
+        cdw0: ADMIN_CREATE_SQ as u32,
+        prp1: sq_phys,
+        cdw10: (qsize << 16) | 1,
+        cdw11: (1 << 16) | 1, // CQ 1, physically contiguous
+        ..Default::default()
+    })?;
+
+    let mut found = Vec::new();
+    for nsid in 1..=nn.min(MAX_NAMESPACES) {
+        if c.identify(CNS_NAMESPACE, nsid).is_err() {
+            continue;
+        }
+        let ns = unsafe { core::slice::from_raw_parts(c.buf(), PAGE_SIZE) };
+        let nsze = u64::from_le_bytes(ns[0..8].try_into().unwrap_or([0; 8]));
+        if nsze == 0 {
+            continue; // inactive
+        }
+        // FLBAS picks the LBA format; LBADS is log2 of the block size
+        let fmt = (ns[26] & 0xF) as usize;
+        let lbads = ns[128 + fmt * 4 + 2];
+        if 1usize << lbads != SECTOR_SIZE {
+            log::warn!("NVMe: ns {}: {}-byte blocks not supported", nsid, 1u64 << lbads);
+            continue;
+        }
+        found.push((nsid, nsze));
+    }
+
+    let ctrl = Arc::new(SpinLock::new(c));
+    for (nsid, sectors) in found {
+        block::register(Arc::new(Namespace {
+            name: alloc::format!("nvme{}n{}", index, nsid),
+            nsid,
+            sectors,
+            model: model.clone(),
+            ctrl: Arc::clone(&ctrl),
+        }));
+    }
+    Ok(())
+}
+
+pub fn init() {
+    let mut ctrls = Vec::new();
+    pci::devices(|d| {
+        if d.is_nvme() {
+            ctrls.push(d.clone());
+        }
+    });
+    for (i, dev) in ctrls.iter().enumerate() {
+        if let Err(e) = init_controller(i, dev) {
+            log::warn!("NVMe: {:02x}:{:02x}.{}: {}", dev.bus, dev.dev, dev.func, e);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+/// MBR partitions as block devices of their own: "hda1" is sectors
+/// `start..start + count` of "hda", so `mount hda1 /mnt` reaches the
+/// filesystem on it like any disk.
+///
+/// A partition reads and writes through its disk's cached device, so a
+/// sector seen through both is cached once and the two views agree.
+use super::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::fs::mbr;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+pub struct Partition {
+    name: String,
+    disk: Arc<dyn BlockDevice>,
+    /// Number in the table, from 1.
+    pub index: usize,
+    pub entry: mbr::Partition,
+}
+
+impl Partition {
+    /// Whole disk's name.
+    pub fn disk_name(&self) -> &str {
+        self.disk.name()
+    }
+
+    fn check(&self, lba: u64, count: u16) -> Result<u64, &'static str> {
+        if lba + count as u64 > self.entry.lba_count {
+            return Err("access beyond end of partition");
+        }
+        Ok(self.entry.lba_start + lba)
+    }
+}
+
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.entry.lba_count
+    }
+
+    fn read_sectors(&self, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        self.disk.read_sectors(self.check(lba, count)?, count, buf)
+    }
+
+    fn write_sectors(&self, lba: u64, count: u16, buf: &[u8]) -> Result<(), &'static str> {
+        assert_eq!(buf.len(), count as usize * SECTOR_SIZE);
+        self.disk.write_sectors(self.check(lba, count)?, count, buf)
+    }
+
+    fn read_only(&self) -> bool {
+        self.disk.read_only()
+    }
+
+    fn info(&self) -> String {
+        alloc::format!(
+            "{} (type {:#04x}{}), sectors {}–{}",
+            self.entry.type_name(),
+            self.entry.part_type,
+            if self.entry.status == 0x80 { ", boot" } else { "" },
+            self.entry.lba_start,
+            self.entry.lba_start + self.entry.lba_count - 1,
+        )
+    }
+}
+
+/// "hda" + 1 → "hda1"; names that end in a digit take a "p" first, as in
+/// "nvme0n1p1".
+fn part_name(disk: &str, index: usize) -> String {
+    if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        alloc::format!("{}p{}", disk, index)
+    } else {
+        alloc::format!("{}{}", disk, index)
+    }
+}
+
+/// Register the primary partitions in `disk`'s MBR, if it has one.
+///
+/// A FAT boot sector carries the same 0x55AA signature as an MBR, so
+/// entries are only taken if they are plausible: a valid boot flag and
+/// lying within the disk. Extended partitions aren't followed.
+pub fn scan(disk: &Arc<dyn BlockDevice>) {
+    let Some(table) = mbr::read(disk.as_ref()) else {
+        return;
+    };
+    let sectors = disk.sector_count();
+    let plausible = |p: &mbr::Partition| {
+        matches!(p.status, 0x00 | 0x80) && p.lba_start + p.lba_count <= sectors
+    };
+    if !table.iter().flatten().all(plausible) {
+        return;
+    }
+    for (i, entry) in table.iter().enumerate() {
+        let Some(entry) = *entry else { continue };
+        if entry.is_extended() {
+            continue;
+        }
+        block::register_partition(Arc::new(Partition {
+            name: part_name(disk.name(), i + 1),
+            disk: Arc::clone(disk),
+            index: i + 1,
+            entry,
+        }));
+    }
+}
@@ -1,6 +1,8 @@
-use crate::arch::x86_64::io::{inl, outl};
+use crate::arch::x86_64::acpi;
+use crate::arch::x86_64::io::{inl, mmio_read32, mmio_write32, outl};
 use crate::sync::spinlock::SpinLock;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
 
 // ─── Config-space I/O ────────────────────────────────────────────────────────
 
@@ -45,6 +47,130 @@ pub fn enable_bus_master(bus: u8, dev: u8, func: u8) {
     write_u32(bus, dev, func, 0x04, (cmd | 0x0007) as u32);
 }
 
+// ─── Extended config space (ECAM) ─────────────────────────────────────────────
+
+/// Config space per function: the ports reach the first 256 bytes, ECAM
+/// all 4 KiB, PCIe extended capabilities included.
+pub const CONFIG_SIZE_LEGACY: u16 = 0x100;
+pub const CONFIG_SIZE_EXTENDED: u16 = 0x1000;
+
+/// Segment 0's ECAM, mapped: the virtual address bus 0's space would have,
+/// 0 until `init` finds the MCFG table; and the buses it covers, first in
+/// the low byte, last in the high one.
+static ECAM_BASE: AtomicU64 = AtomicU64::new(0);
+static ECAM_BUSES: AtomicU16 = AtomicU16::new(0);
+
+fn ecam_addr(bus: u8, dev: u8, func: u8, offset: u16) -> Option<u64> {
+    let base = ECAM_BASE.load(Ordering::Relaxed);
+    let [first, last] = ECAM_BUSES.load(Ordering::Relaxed).to_le_bytes();
+    if base == 0 || !(first..=last).contains(&bus) || offset >= CONFIG_SIZE_EXTENDED {
+        return None;
+    }
+    Some(
+        base + ((bus as u64) << 20)
+            + ((dev as u64 & 0x1F) << 15)
+            + ((func as u64 & 7) << 12)
+            + (offset & !3) as u64,
+    )
+}
+
+fn init_ecam() {
+    let Some(e) = acpi::mcfg().into_iter().find(|e| e.segment == 0) else {
+        log::info!("PCI: no MCFG, config access through ports 0xCF8/0xCFC only");
+        return;
+    };
+    let start = e.base + ((e.bus_start as u64) << 20);
+    let size = (e.bus_end as u64 - e.bus_start as u64 + 1) << 20;
+    let Some(virt) = crate::mm::vmm::map_mmio(start, size) else {
+        log::warn!("PCI: cannot map ECAM at {:#x}", start);
+        return;
+    };
+    ECAM_BUSES.store(u16::from_le_bytes([e.bus_start, e.bus_end]), Ordering::Relaxed);
+    ECAM_BASE.store(virt - ((e.bus_start as u64) << 20), Ordering::Release);
+    log::info!(
+        "PCI: ECAM at {:#x}, buses {:02x}-{:02x}",
+        e.base,
+        e.bus_start,
+        e.bus_end
+    );
+}
+
+/// Whether the whole 4 KiB of config space can be reached.
+pub fn has_ecam() -> bool {
+    ECAM_BASE.load(Ordering::Acquire) != 0
+}
+
+/// The config dword at `offset`, anywhere in the 4 KiB: through ECAM where
+/// there is one, else the ports. Past the first 256 bytes without ECAM it
+/// reads as all ones, as an absent function's space does.
+pub fn read_ext_u32(bus: u8, dev: u8, func: u8, offset: u16) -> u32 {
+    match ecam_addr(bus, dev, func, offset) {
+        Some(addr) => unsafe { mmio_read32(addr) },
+        None if offset < CONFIG_SIZE_LEGACY => read_u32(bus, dev, func, offset as u8),
+        None => u32::MAX,
+    }
+}
+
+/// Write a config dword anywhere in the 4 KiB; dropped past the first 256
+/// bytes without ECAM.
+pub fn write_ext_u32(bus: u8, dev: u8, func: u8, offset: u16, val: u32) {
+    match ecam_addr(bus, dev, func, offset) {
+        Some(addr) => unsafe { mmio_write32(addr, val) },
+        None if offset < CONFIG_SIZE_LEGACY => write_u32(bus, dev, func, offset as u8, val),
+        None => {}
+    }
+}
+
+// ─── Capabilities ─────────────────────────────────────────────────────────────
+
+pub const CAP_PM: u8 = 0x01;
+pub const CAP_MSI: u8 = 0x05;
+pub const CAP_VENDOR: u8 = 0x09;
+pub const CAP_PCIE: u8 = 0x10;
+pub const CAP_MSIX: u8 = 0x11;
+
+pub const EXT_CAP_AER: u16 = 0x0001;
+pub const EXT_CAP_SERIAL: u16 = 0x0003;
+pub const EXT_CAP_SRIOV: u16 = 0x0010;
+
+/// Status register: the device has a capability list.
+const STATUS_CAP_LIST: u16 = 1 << 4;
+/// Where the list starts in type 0 and type 1 headers.
+const CAP_POINTER: u8 = 0x34;
+
+/// Entries followed at most: stops a list that loops back on itself.
+const MAX_CAPS: usize = 48;
+
+pub fn cap_name(id: u8) -> &'static str {
+    match id {
+        CAP_PM => "Power Management",
+        0x03 => "VPD",
+        CAP_MSI => "MSI",
+        0x07 => "PCI-X",
+        CAP_VENDOR => "Vendor Specific",
+        0x0D => "Bridge Subsystem ID",
+        CAP_PCIE => "PCI Express",
+        CAP_MSIX => "MSI-X",
+        0x12 => "SATA",
+        0x13 => "Advanced Features",
+        _ => "Unknown",
+    }
+}
+
+pub fn ext_cap_name(id: u16) -> &'static str {
+    match id {
+        EXT_CAP_AER => "Advanced Error Reporting",
+        0x0002 => "Virtual Channel",
+        EXT_CAP_SERIAL => "Device Serial Number",
+        0x000B => "Vendor Specific",
+        0x000E => "ARI",
+        EXT_CAP_SRIOV => "SR-IOV",
+        0x0018 => "Latency Tolerance Reporting",
+        0x001E => "L1 PM Substates",
+        _ => "Unknown",
+    }
+}
+
 // ─── PCI device descriptor ────────────────────────────────────────────────────
 
 #[derive(Debug, Clone)]
@@ -116,6 +242,11 @@ impl PciDevice {
         self.class == 0x01 && self.subclass == 0x06 && self.prog_if == 0x01
     }
 
+    /// Returns true if this device is an NVMe controller
+    pub fn is_nvme(&self) -> bool {
+        self.class == 0x01 && self.subclass == 0x08 && self.prog_if == 0x02
+    }
+
     /// Returns true if this device looks like an IDE controller
     pub fn is_ide(&self) -> bool {
         self.class == 0x01 && self.subclass == 0x01
@@ -146,6 +277,57 @@ impl PciDevice {
     pub fn bar_is_io(&self, n: usize) -> bool {
         self.bars[n] & 1 != 0
     }
+
+    /// The standard capability list as (ID, offset) pairs, in list order.
+    pub fn capabilities(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        let (bus, dev, func) = (self.bus, self.dev, self.func);
+        let has_list =
+            self.header_type <= 1 && read_u16(bus, dev, func, 0x06) & STATUS_CAP_LIST != 0;
+        let mut off = if has_list { read_u8(bus, dev, func, CAP_POINTER) & !3 } else { 0 };
+        core::iter::from_fn(move || {
+            // The first 64 bytes are the header: a pointer into it ends the list
+            if off < 0x40 {
+                return None;
+            }
+            let at = off;
+            let hdr = read_u16(bus, dev, func, at);
+            off = (hdr >> 8) as u8 & !3;
+            Some((hdr as u8, at))
+        })
+        .take(MAX_CAPS)
+    }
+
+    /// Offset of the first capability `id` in config space.
+    pub fn find_capability(&self, id: u8) -> Option<u8> {
+        self.capabilities().find(|&(c, _)| c == id).map(|(_, off)| off)
+    }
+
+    /// The PCIe extended capabilities, from offset 0x100 on, as (ID,
+    /// version, offset). Empty without ECAM, or for a plain PCI device.
+    pub fn ext_capabilities(&self) -> impl Iterator<Item = (u16, u8, u16)> + '_ {
+        let pcie = has_ecam() && self.find_capability(CAP_PCIE).is_some();
+        let mut off = if pcie { CONFIG_SIZE_LEGACY } else { 0 };
+        core::iter::from_fn(move || {
+            if off < CONFIG_SIZE_LEGACY {
+                return None;
+            }
+            let hdr = read_ext_u32(self.bus, self.dev, self.func, off);
+            if hdr == 0 || hdr == u32::MAX {
+                return None;
+            }
+            let at = off;
+            off = (hdr >> 20) as u16 & !3;
+            Some((hdr as u16, (hdr >> 16) as u8 & 0xF, at))
+        })
+        .take(MAX_CAPS)
+    }
+
+    /// Offset of the first extended capability `id`.
+    pub fn find_ext_capability(&self, id: u16) -> Option<u16> {
+        self.ext_capabilities()
+            .find(|&(c, _, _)| c == id)
+            .map(|(_, _, off)| off)
+    }
 }
 
 // ─── Global device list ───────────────────────────────────────────────────────
@@ -223,6 +405,7 @@ fn scan_bus(bus: u8, list: &mut Vec<PciDevice>) {
 }
 
 pub fn init() {
+    init_ecam();
     let mut list = Vec::new();
 
     // Check if the host controller itself is multi-function
@@ -0,0 +1,229 @@
+/// PNG decoding for `view`: the chunk stream, zlib-compressed scanlines
+/// (see `inflate.rs`) and their per-line filters, to a `Bitmap`.
+///
+/// Every colour type and bit depth is read, with palette or colour-key
+/// transparency from tRNS; Adam7-interlaced images are not. Chunk CRCs go
+/// unchecked, the zlib Adler-32 over the pixels being the check that
+/// matters. Anything with alpha is shown over black, the viewer's backdrop.
+use super::bmp::{Bitmap, MAX_PIXELS};
+use super::inflate;
+use alloc::vec::Vec;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+const GRAY: u8 = 0;
+const RGB: u8 = 2;
+const PALETTE: u8 = 3;
+const GRAY_ALPHA: u8 = 4;
+const RGBA: u8 = 6;
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: u8,
+    color: u8,
+}
+
+impl Header {
+    fn parse(d: &[u8]) -> Result<Self, &'static str> {
+        if d.len() < 13 {
+            return Err("short IHDR");
+        }
+        let width = u32::from_be_bytes([d[0], d[1], d[2], d[3]]) as usize;
+        let height = u32::from_be_bytes([d[4], d[5], d[6], d[7]]) as usize;
+        let (depth, color) = (d[8], d[9]);
+        if width == 0 || height == 0 {
+            return Err("empty image");
+        }
+        if width.checked_mul(height).is_none_or(|n| n > MAX_PIXELS) {
+            return Err("image too large");
+        }
+        let depth_ok = match color {
+            GRAY => matches!(depth, 1 | 2 | 4 | 8 | 16),
+            PALETTE => matches!(depth, 1 | 2 | 4 | 8),
+            RGB | GRAY_ALPHA | RGBA => matches!(depth, 8 | 16),
+            _ => return Err("unknown PNG colour type"),
+        };
+        if !depth_ok {
+            return Err("bad bit depth for colour type");
+        }
+        if d[10] != 0 || d[11] != 0 {
+            return Err("unknown PNG compression or filter method");
+        }
+        if d[12] != 0 {
+            return Err("interlaced PNG not supported");
+        }
+        Ok(Header { width, height, depth, color })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color {
+            RGB => 3,
+            GRAY_ALPHA => 2,
+            RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    /// Bytes in one scanline, without its filter byte.
+    fn stride(&self) -> usize {
+        (self.width * self.channels() * self.depth as usize).div_ceil(8)
+    }
+
+    /// Sample `c` of pixel `x` in `row`, at its full depth.
+    fn sample(&self, row: &[u8], x: usize, c: usize) -> u16 {
+        let i = x * self.channels() + c;
+        match self.depth {
+            16 => u16::from_be_bytes([row[i * 2], row[i * 2 + 1]]),
+            8 => row[i] as u16,
+            d => {
+                let bit = i * d as usize;
+                let shift = 8 - d as usize - bit % 8;
+                ((row[bit / 8] >> shift) & ((1 << d) - 1)) as u16
+            }
+        }
+    }
+
+    /// A sample scaled to 8 bits.
+    fn to8(&self, v: u16) -> u32 {
+        match self.depth {
+            16 => (v >> 8) as u32,
+            8 => v as u32,
+            d => v as u32 * 255 / ((1 << d) - 1),
+        }
+    }
+}
+
+pub fn decode(data: &[u8]) -> Result<Bitmap, &'static str> {
+    if !data.starts_with(&SIGNATURE) {
+        return Err("not a PNG image");
+    }
+    let mut pos = SIGNATURE.len();
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut trns: &[u8] = &[];
+    let mut idat = Vec::new();
+    loop {
+        let len = data.get(pos..pos + 4).ok_or("PNG cut short")?;
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        let kind = data.get(pos + 4..pos + 8).ok_or("PNG cut short")?;
+        let body = data.get(pos + 8..pos + 8 + len).ok_or("PNG cut short")?;
+        pos += 12 + len;
+        match kind {
+            b"IHDR" => header = Some(Header::parse(body)?),
+            b"PLTE" => palette = body,
+            b"tRNS" => trns = body,
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            // An unknown critical chunk (upper-case first letter) changes
+            // how the image is to be read; ancillary ones can be skipped
+            _ if kind[0].is_ascii_uppercase() => return Err("unknown critical PNG chunk"),
+            _ => {}
+        }
+    }
+    let h = header.ok_or("no IHDR")?;
+    if h.color == PALETTE && palette.is_empty() {
+        return Err("no palette");
+    }
+
+    let stride = h.stride();
+    let size = (stride + 1) * h.height;
+    let mut raw = inflate::zlib(&idat, size)?;
+    if raw.len() < size {
+        return Err("PNG pixel data cut short");
+    }
+    unfilter(&mut raw, stride, h.height, (h.channels() * h.depth as usize).div_ceil(8))?;
+
+    let mut pixels = Vec::with_capacity(h.width * h.height);
+    for y in 0..h.height {
+        let row = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..h.width {
+            let s = |c| h.sample(row, x, c);
+            let (rgb, alpha) = match h.color {
+                GRAY => {
+                    let key = trns.get(..2).map(|k| u16::from_be_bytes([k[0], k[1]]));
+                    let g = h.to8(s(0));
+                    ([g, g, g], if key == Some(s(0)) { 0 } else { 255 })
+                }
+                RGB => {
+                    let raw = [s(0), s(1), s(2)];
+                    let key = trns.get(..6).map(|k| {
+                        [0, 2, 4].map(|i| u16::from_be_bytes([k[i], k[i + 1]]))
+                    });
+                    (raw.map(|v| h.to8(v)), if key == Some(raw) { 0 } else { 255 })
+                }
+                PALETTE => {
+                    let i = s(0) as usize;
+                    let rgb = match palette.get(i * 3..i * 3 + 3) {
+                        Some(p) => [p[0] as u32, p[1] as u32, p[2] as u32],
+                        None => [0; 3],
+                    };
+                    (rgb, trns.get(i).map_or(255, |&a| a as u32))
+                }
+                GRAY_ALPHA => {
+                    let g = h.to8(s(0));
+                    ([g, g, g], h.to8(s(1)))
+                }
+                _ => ([h.to8(s(0)), h.to8(s(1)), h.to8(s(2))], h.to8(s(3))),
+            };
+            let [r, g, b] = rgb.map(|v| v * alpha / 255);
+            pixels.push((r << 16) | (g << 8) | b);
+        }
+    }
+    Ok(Bitmap { width: h.width, height: h.height, pixels })
+}
+
+/// Undo the filter on each of `height` lines in `raw`, each its filter
+/// byte and then `stride` bytes, in place. `bpp` is the bytes per pixel
+/// (at least 1) the filters look back by.
+fn unfilter(raw: &mut [u8], stride: usize, height: usize, bpp: usize) -> Result<(), &'static str> {
+    let line = stride + 1;
+    for y in 0..height {
+        let (done, rest) = raw.split_at_mut(y * line);
+        let prev = if y == 0 { None } else { Some(&done[(y - 1) * line + 1..]) };
+        let (filter, cur) = rest[..line].split_first_mut().unwrap();
+        let up = |i: usize| prev.map_or(0, |p| p[i]);
+        match *filter {
+            0 => {}
+            1 => {
+                for i in bpp..stride {
+                    cur[i] = cur[i].wrapping_add(cur[i - bpp]);
+                }
+            }
+            2 => {
+                for (i, c) in cur.iter_mut().enumerate() {
+                    *c = c.wrapping_add(up(i));
+                }
+            }
+            3 => {
+                for i in 0..stride {
+                    let left = if i >= bpp { cur[i - bpp] } else { 0 };
+                    cur[i] = cur[i].wrapping_add(((left as u16 + up(i) as u16) / 2) as u8);
+                }
+            }
+            4 => {
+                for i in 0..stride {
+                    let (left, corner) =
+                        if i >= bpp { (cur[i - bpp], up(i - bpp)) } else { (0, 0) };
+                    cur[i] = cur[i].wrapping_add(paeth(left, up(i), corner));
+                }
+            }
+            _ => return Err("bad PNG filter type"),
+        }
+    }
+    Ok(())
+}
+
+/// Whichever of left, above and upper-left is nearest to left + above -
+/// upper-left, ties going in that order.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
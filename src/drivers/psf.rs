@@ -0,0 +1,179 @@
+/// Console fonts: the built-in 8×16 one, and PC Screen Fonts (PSF1 and
+/// PSF2, what Linux's `setfont` loads) read from a file.
+///
+/// The console draws bytes, so a glyph is found by byte. A font with a
+/// Unicode table is looked up through it, taking byte `b` as code point
+/// U+00`b`; without one, byte `b` is glyph `b`.
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_MODEHASTAB: u8 = 0x02;
+const PSF1_SEPARATOR: u16 = 0xFFFF;
+const PSF1_STARTSEQ: u16 = 0xFFFE;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xFF;
+const PSF2_STARTSEQ: u8 = 0xFE;
+
+/// Glyphs no bigger than this are taken; a scaled cell still has to fit
+/// on the screen, which `vga` checks.
+const MAX_SIZE: usize = 64;
+
+pub struct Font {
+    pub width: usize,
+    pub height: usize,
+    /// Each glyph is `height` rows of `(width + 7) / 8` bytes, the
+    /// leftmost pixel in the top bit.
+    glyphs: Cow<'static, [u8]>,
+    /// Glyph of each byte, from the Unicode table.
+    map: Option<Box<[u16; 256]>>,
+}
+
+impl Font {
+    pub const fn builtin() -> Font {
+        Font {
+            width: 8,
+            height: 16,
+            glyphs: Cow::Borrowed(include_bytes!("font8x16.bin")),
+            map: None,
+        }
+    }
+
+    pub fn row_bytes(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+
+    /// The rows of the glyph for `ch`; empty if the font has none.
+    pub fn glyph(&self, ch: u8) -> &[u8] {
+        let index = self.map.as_ref().map_or(ch as usize, |m| m[ch as usize] as usize);
+        let size = self.row_bytes() * self.height;
+        self.glyphs.get(index * size..(index + 1) * size).unwrap_or(&[])
+    }
+
+    /// Read a PSF1 or PSF2 file.
+    pub fn parse(data: &[u8]) -> Result<Font, &'static str> {
+        if data.starts_with(&PSF1_MAGIC) {
+            parse_psf1(data)
+        } else if data.starts_with(&PSF2_MAGIC) {
+            parse_psf2(data)
+        } else {
+            Err("not a PSF font")
+        }
+    }
+}
+
+fn u32_at(d: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([d[at], d[at + 1], d[at + 2], d[at + 3]])
+}
+
+/// The glyphs of a font file and what follows them, checking the sizes.
+fn split(
+    data: &[u8],
+    start: usize,
+    size: usize,
+    count: usize,
+) -> Result<(&[u8], &[u8]), &'static str> {
+    let end = count
+        .checked_mul(size)
+        .and_then(|n| n.checked_add(start))
+        .filter(|&end| end <= data.len())
+        .ok_or("font file cut short")?;
+    Ok((&data[start..end], &data[end..]))
+}
+
+/// Byte → glyph from `(glyph, code point)` pairs; the first glyph given
+/// for a code point wins, and bytes with none keep their own index.
+fn byte_map(pairs: impl Iterator<Item = (usize, u32)>) -> Box<[u16; 256]> {
+    let mut map = Box::new([u16::MAX; 256]);
+    for (glyph, cp) in pairs {
+        if let Some(slot) = map.get_mut(cp as usize) {
+            if *slot == u16::MAX {
+                *slot = glyph as u16;
+            }
+        }
+    }
+    for (b, slot) in map.iter_mut().enumerate() {
+        if *slot == u16::MAX {
+            *slot = b as u16;
+        }
+    }
+    map
+}
+
+fn parse_psf1(data: &[u8]) -> Result<Font, &'static str> {
+    let [_, _, mode, height, ..] = *data else {
+        return Err("font file cut short");
+    };
+    let height = height as usize;
+    if height == 0 || height > MAX_SIZE {
+        return Err("unsupported glyph size");
+    }
+    let count = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+    let (glyphs, table) = split(data, 4, height, count)?;
+    // Per glyph, UCS-2 code points up to a separator; a sequence marker
+    // starts combining sequences, which a byte can't be, so they're skipped
+    let map = (mode & PSF1_MODEHASTAB != 0).then(|| {
+        let mut pairs = Vec::new();
+        let words = table.chunks_exact(2).map(|w| u16::from_le_bytes([w[0], w[1]]));
+        let (mut glyph, mut in_seq) = (0, false);
+        for w in words {
+            match w {
+                PSF1_SEPARATOR => {
+                    glyph += 1;
+                    in_seq = false;
+                }
+                PSF1_STARTSEQ => in_seq = true,
+                cp if !in_seq && glyph < count => pairs.push((glyph, cp as u32)),
+                _ => {}
+            }
+        }
+        byte_map(pairs.into_iter())
+    });
+    Ok(Font {
+        width: 8,
+        height,
+        glyphs: Cow::Owned(glyphs.to_vec()),
+        map,
+    })
+}
+
+fn parse_psf2(data: &[u8]) -> Result<Font, &'static str> {
+    if data.len() < 32 {
+        return Err("font file cut short");
+    }
+    let header_size = u32_at(data, 8) as usize;
+    let flags = u32_at(data, 12);
+    let count = u32_at(data, 16) as usize;
+    let glyph_size = u32_at(data, 20) as usize;
+    let height = u32_at(data, 24) as usize;
+    let width = u32_at(data, 28) as usize;
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err("unsupported glyph size");
+    }
+    if glyph_size != width.div_ceil(8) * height || count == 0 || count > u16::MAX as usize {
+        return Err("bad PSF2 header");
+    }
+    let (glyphs, table) = split(data, header_size, glyph_size, count)?;
+    // Per glyph, UTF-8 characters up to a separator byte, sequences after
+    // a marker byte as in PSF1
+    let map = (flags & PSF2_HAS_UNICODE_TABLE != 0).then(|| {
+        let mut pairs = Vec::new();
+        for (glyph, entry) in table.split(|&b| b == PSF2_SEPARATOR).enumerate().take(count) {
+            let singles = entry.split(|&b| b == PSF2_STARTSEQ).next().unwrap_or(&[]);
+            if let Ok(s) = core::str::from_utf8(singles) {
+                pairs.extend(s.chars().map(|c| (glyph, c as u32)));
+            }
+        }
+        byte_map(pairs.into_iter())
+    });
+    Ok(Font {
+        width,
+        height,
+        glyphs: Cow::Owned(glyphs.to_vec()),
+        map,
+    })
+}
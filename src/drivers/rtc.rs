@@ -0,0 +1,197 @@
+//! CMOS real-time clock. Read once at boot to set the wall clock, which
+//! the timer keeps advancing from there; the RTC is assumed to run in UTC.
+use crate::arch::x86_64::acpi;
+use crate::arch::x86_64::io::{inb, outb};
+use core::fmt;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Keeps NMIs masked while a register is selected.
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATING: u8 = 0x80;
+const STATUS_B_24H: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+/// Hour register bit for PM in 12-hour mode.
+const HOUR_PM: u8 = 0x80;
+
+/// Offset of the FADT's CMOS century register index.
+const FADT_CENTURY: usize = 108;
+
+fn read_reg(reg: u8) -> u8 {
+    unsafe {
+        outb(CMOS_INDEX, NMI_DISABLE | reg);
+        inb(CMOS_DATA)
+    }
+}
+
+fn updating() -> bool {
+    read_reg(REG_STATUS_A) & STATUS_A_UPDATING != 0
+}
+
+/// CMOS index of the century register, if the firmware names one.
+fn century_register() -> Option<u8> {
+    let va = acpi::find_table(b"FACP")?;
+    let hdr = unsafe { core::ptr::read_unaligned(va as *const acpi::SdtHeader) };
+    if (hdr.length as usize) <= FADT_CENTURY {
+        return None;
+    }
+    let reg = unsafe { *((va as usize + FADT_CENTURY) as *const u8) };
+    (reg != 0).then_some(reg)
+}
+
+/// A UTC calendar time, to the second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Days from 1970-01-01 to the given date (proleptic Gregorian).
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+impl DateTime {
+    pub fn to_unix(self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as u32, self.day as u32);
+        let secs = days * 86400
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64;
+        secs.max(0) as u64
+    }
+
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / 86400) as i64;
+        let rem = secs % 86400;
+        // Inverse of days_from_civil
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (yoe + era * 400 + (month <= 2) as i64) as u32;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// "Mon" … "Sun".
+    pub fn weekday(self) -> &'static str {
+        WEEKDAYS[(self.to_unix() / 86400 % 7) as usize]
+    }
+
+    /// "Jan" … "Dec".
+    pub fn month_name(self) -> &'static str {
+        MONTHS[(self.month as usize).clamp(1, 12) - 1]
+    }
+}
+
+/// ISO 8601, e.g. "2026-10-14 09:30:00".
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Raw register values, in whatever encoding the RTC uses.
+fn read_raw(century: Option<u8>) -> [u8; 7] {
+    [
+        read_reg(REG_SECONDS),
+        read_reg(REG_MINUTES),
+        read_reg(REG_HOURS),
+        read_reg(REG_DAY),
+        read_reg(REG_MONTH),
+        read_reg(REG_YEAR),
+        century.map_or(0, read_reg),
+    ]
+}
+
+/// Current RTC time. Waits out an update in progress and reads until two
+/// passes agree, so a rollover in the middle can't mix two seconds.
+pub fn read() -> DateTime {
+    let century = century_register();
+    let mut raw = loop {
+        while updating() {
+            core::hint::spin_loop();
+        }
+        let a = read_raw(century);
+        while updating() {
+            core::hint::spin_loop();
+        }
+        if read_raw(century) == a {
+            break a;
+        }
+    };
+
+    let status_b = read_reg(REG_STATUS_B);
+    let pm = raw[2] & HOUR_PM != 0;
+    raw[2] &= !HOUR_PM;
+    if status_b & STATUS_B_BINARY == 0 {
+        for v in raw.iter_mut() {
+            *v = (*v & 0x0F) + (*v >> 4) * 10;
+        }
+    }
+    if status_b & STATUS_B_24H == 0 {
+        // 12-hour mode: 12 AM is 0, 12 PM is 12
+        raw[2] = raw[2] % 12 + if pm { 12 } else { 0 };
+    }
+
+    let year = match century {
+        Some(_) => raw[6] as u32 * 100 + raw[5] as u32,
+        // Without a century register assume this century
+        None => 2000 + raw[5] as u32,
+    };
+    DateTime {
+        year,
+        month: raw[4],
+        day: raw[3],
+        hour: raw[2],
+        minute: raw[1],
+        second: raw[0],
+    }
+}
+
+/// Set the wall clock from the RTC.
+pub fn init() {
+    let now = read();
+    crate::arch::x86_64::timer::set_realtime(now.to_unix() * 1_000_000_000);
+    log::info!("RTC: {} UTC", now);
+}
@@ -1,46 +1,102 @@
 use crate::arch::x86_64::io::{inb, outb};
 use crate::sync::spinlock::SpinLock;
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 const COM1: u16 = 0x3F8;
+/// The second port belongs to the GDB stub, which polls it.
+pub const COM2: u16 = 0x2F8;
 
-pub fn init() {
+/// LSR bits.
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_PARITY_ERROR: u8 = 0x04;
+const LSR_FRAMING_ERROR: u8 = 0x08;
+const LSR_THR_EMPTY: u8 = 0x20;
+
+/// COM1 answered the loopback test in `init`. Without a UART every
+/// register reads 0xFF, which looks like endless received data.
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+/// Program `port` for 115200 8N1 with receive interrupts. False if no UART
+/// answers the loopback test.
+pub fn setup(port: u16) -> bool {
     unsafe {
-        outb(COM1 + 1, 0x00); // IER: disable all interrupts while configuring
-        outb(COM1 + 3, 0x80); // LCR: DLAB=1 to set baud rate
-        outb(COM1 + 0, 0x01); // DLL: divisor low  → 115200 baud
-        outb(COM1 + 1, 0x00); // DLH: divisor high
-        outb(COM1 + 3, 0x03); // LCR: DLAB=0, 8N1
-        outb(COM1 + 2, 0xC7); // FCR: enable FIFO, clear, 14-byte threshold
-        outb(COM1 + 4, 0x0B); // MCR: DTR + RTS + OUT2 (OUT2 gates IRQ to PIC)
-        outb(COM1 + 1, 0x01); // IER: enable Received Data Available Interrupt
+        outb(port + 1, 0x00); // IER: disable all interrupts while configuring
+        outb(port + 3, 0x80); // LCR: DLAB=1 to set baud rate
+        outb(port + 0, 0x01); // DLL: divisor low  → 115200 baud
+        outb(port + 1, 0x00); // DLH: divisor high
+        outb(port + 3, 0x03); // LCR: DLAB=0, 8N1
+        outb(port + 2, 0xC7); // FCR: enable FIFO, clear, 14-byte threshold
+
+        // Loopback: a byte sent must come straight back
+        outb(port + 4, 0x1E); // MCR: loopback + OUT1/OUT2 + RTS
+        outb(port, 0xAE);
+        if inb(port) != 0xAE {
+            return false;
+        }
+
+        outb(port + 4, 0x0B); // MCR: DTR + RTS + OUT2 (OUT2 gates IRQ to PIC)
+        // Whatever arrived before now, and any interrupt it left pending:
+        // an edge-triggered IRQ that is already up never fires again
+        while inb(port + 5) & LSR_DATA_READY != 0 {
+            inb(port);
+        }
+        inb(port + 2); // IIR
+        inb(port + 6); // MSR
+        outb(port + 1, 0x01); // IER: enable Received Data Available Interrupt
     }
+    true
 }
 
-/// Called from IRQ4 handler: drain the COM1 FIFO and push bytes to the
-/// keyboard buffer so the shell readline loop receives them.
-pub fn irq_serial(_frame: &mut crate::arch::x86_64::idt::InterruptFrame) {
-    unsafe {
-        while inb(COM1 + 5) & 0x01 != 0 {
-            let b = inb(COM1);
-            crate::drivers::keyboard::push_char(b);
-        }
+pub fn init() {
+    if setup(COM1) {
+        PRESENT.store(true, Ordering::Relaxed);
     }
 }
 
-fn is_transmit_empty() -> bool {
-    unsafe { inb(COM1 + 5) & 0x20 != 0 }
+/// A byte received on `port`, if one is waiting. Bytes with a parity or
+/// framing error are dropped.
+pub fn poll_byte(port: u16) -> Option<u8> {
+    loop {
+        let lsr = unsafe { inb(port + 5) };
+        if lsr & LSR_DATA_READY == 0 {
+            return None;
+        }
+        let b = unsafe { inb(port) };
+        if lsr & (LSR_PARITY_ERROR | LSR_FRAMING_ERROR) == 0 {
+            return Some(b);
+        }
+    }
 }
 
-pub fn write_byte(b: u8) {
-    while !is_transmit_empty() {
+/// Send `b` on `port`, waiting for room.
+pub fn put_byte(port: u16, b: u8) {
+    while unsafe { inb(port + 5) } & LSR_THR_EMPTY == 0 {
         core::hint::spin_loop();
     }
-    unsafe {
-        outb(COM1, b);
+    unsafe { outb(port, b) };
+}
+
+/// Called from IRQ4 handler: drain the COM1 FIFO into the console's (VT1's)
+/// input, as if typed at the keyboard. Line discipline is the TTY's: CR
+/// from a terminal's Enter becomes LF, DEL erases, Ctrl-C signals.
+pub fn irq_serial(_frame: &mut crate::arch::x86_64::idt::InterruptFrame) {
+    if !PRESENT.load(Ordering::Relaxed) {
+        return;
+    }
+    // Line noise, or a terminal at another baud rate, never gets this far
+    while let Some(b) = poll_byte(COM1) {
+        crate::drivers::tty::console().input(b);
     }
 }
 
+pub fn write_byte(b: u8) {
+    if !PRESENT.load(Ordering::Relaxed) {
+        return;
+    }
+    put_byte(COM1, b);
+}
+
 pub fn write_str(s: &str) {
     for b in s.bytes() {
         if b == b'\n' {
@@ -60,6 +116,12 @@ impl fmt::Write for SerialWriter {
 
 static SERIAL_LOCK: SpinLock<()> = SpinLock::new(());
 
+/// For the panic report: whoever was printing when the kernel stopped
+/// won't finish.
+pub fn break_lock() {
+    unsafe { SERIAL_LOCK.force_unlock() };
+}
+
 pub fn print_fmt(args: fmt::Arguments) {
     use fmt::Write;
     let _g = SERIAL_LOCK.lock();
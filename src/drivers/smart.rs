@@ -0,0 +1,88 @@
+/// S.M.A.R.T.: the drive's own health bookkeeping, as ATA returns it.
+/// The transport (IDE here) issues SMART RETURN STATUS and SMART READ DATA;
+/// this is the format of what comes back, whoever fetched it.
+use alloc::vec::Vec;
+
+/// Attribute IDs by vendor convention; what their raw values mean varies,
+/// these two are the ones everyone agrees on.
+pub const ATTR_REALLOCATED: u8 = 5;
+pub const ATTR_AIRFLOW_TEMP: u8 = 190;
+pub const ATTR_TEMPERATURE: u8 = 194;
+
+/// Attribute table in the 512-byte SMART data: 30 entries of 12 bytes.
+const TABLE_OFFSET: usize = 2;
+const ENTRY_SIZE: usize = 12;
+const ENTRIES: usize = 30;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Attribute {
+    pub id: u8,
+    pub flags: u16,
+    /// Normalized: higher is better, 1–253.
+    pub value: u8,
+    pub worst: u8,
+    /// Vendor-specific, 48 bits.
+    pub raw: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// SMART RETURN STATUS: false once an attribute is past its threshold.
+    pub healthy: bool,
+    pub attrs: Vec<Attribute>,
+}
+
+impl Report {
+    /// The used entries of SMART READ DATA's attribute table.
+    pub fn parse(healthy: bool, data: &[u8; 512]) -> Report {
+        let attrs = data[TABLE_OFFSET..TABLE_OFFSET + ENTRIES * ENTRY_SIZE]
+            .chunks(ENTRY_SIZE)
+            .filter(|e| e[0] != 0)
+            .map(|e| Attribute {
+                id: e[0],
+                flags: u16::from_le_bytes([e[1], e[2]]),
+                value: e[3],
+                worst: e[4],
+                raw: e[5..11].iter().rev().fold(0, |acc, &b| acc << 8 | b as u64),
+            })
+            .collect();
+        Report { healthy, attrs }
+    }
+
+    pub fn attr(&self, id: u8) -> Option<&Attribute> {
+        self.attrs.iter().find(|a| a.id == id)
+    }
+
+    /// Degrees Celsius: the low byte of the raw value, the rest being
+    /// min/max on the drives that keep them.
+    pub fn temperature(&self) -> Option<u8> {
+        self.attr(ATTR_TEMPERATURE)
+            .or_else(|| self.attr(ATTR_AIRFLOW_TEMP))
+            .map(|a| a.raw as u8)
+    }
+
+    pub fn reallocated(&self) -> Option<u64> {
+        self.attr(ATTR_REALLOCATED).map(|a| a.raw & 0xFFFF_FFFF)
+    }
+}
+
+/// Names for the common attributes.
+pub fn attr_name(id: u8) -> &'static str {
+    match id {
+        1 => "Raw_Read_Error_Rate",
+        3 => "Spin_Up_Time",
+        4 => "Start_Stop_Count",
+        5 => "Reallocated_Sector_Ct",
+        7 => "Seek_Error_Rate",
+        9 => "Power_On_Hours",
+        10 => "Spin_Retry_Count",
+        12 => "Power_Cycle_Count",
+        190 => "Airflow_Temperature",
+        194 => "Temperature_Celsius",
+        196 => "Reallocated_Event_Count",
+        197 => "Current_Pending_Sector",
+        198 => "Offline_Uncorrectable",
+        199 => "UDMA_CRC_Error_Count",
+        _ => "Unknown_Attribute",
+    }
+}
@@ -0,0 +1,624 @@
+//! The console terminals, one per VT: the line discipline between what is
+//! typed and whoever reads the terminal, and its job-control side, the
+//! foreground group that the control characters signal. The keyboard
+//! types into the VT on screen. VT1 is the console proper: the shell,
+//! /dev/console and fds 0–2 of user programs use it, the serial port
+//! types into it too and gets a copy of its output.
+//!
+//! In canonical mode (ICANON) typed bytes collect in a line that erase
+//! (Backspace), kill (Ctrl-U) and word-erase (Ctrl-W) edit, and a read gets
+//! it only once Enter or EOF (Ctrl-D) ends it, one line per read at most.
+//! Without ICANON every byte is readable as it arrives. ECHO prints what is
+//! typed. Programs switch these with the TCGETS/TCSETS ioctls; the shell's
+//! line editor reads raw and echoes for itself. TIOCGPGRP/TIOCSPGRP get and
+//! set the foreground process group. TIOCGWINSZ reports the
+//! framebuffer's text size, which is also what makes musl's `isatty` true:
+//! everything else says ENOTTY.
+//!
+//! Network terminals are the same minus the screen: their output goes to
+//! whatever `Remote` the connection that claimed one attached (the telnet
+//! server), which also types into them. Once it hangs up, reads see EOF and
+//! output goes nowhere until the terminal is released and claimed again.
+use crate::fs::vfs::Errno;
+use crate::mm::uaccess::{get_user, put_user};
+use crate::drivers::vga::VT_COUNT;
+use crate::proc::signal::{self, SIGHUP, SIGINT, SIGQUIT, SIGTSTP};
+use crate::sync::{SpinLock, WaitQueue};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Network terminals, for remote sessions.
+pub const NET_TTYS: usize = 4;
+
+/// What reads of a hung-up terminal by `getc` see: EOF (Ctrl-D).
+const CTRL_D: u8 = 0x04;
+
+// c_iflag
+pub const ICRNL: u32 = 0o400;
+// c_oflag
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+// c_cflag
+const CS8: u32 = 0o60;
+const CREAD: u32 = 0o200;
+// c_lflag
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHOCTL: u32 = 0o1000;
+pub const IEXTEN: u32 = 0o100000;
+
+// c_cc indices
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+pub const VWERASE: usize = 14;
+const NCCS: usize = 19;
+
+pub const TCGETS: u32 = 0x5401;
+pub const TCSETS: u32 = 0x5402;
+pub const TCSETSW: u32 = 0x5403;
+pub const TCSETSF: u32 = 0x5404;
+pub const TIOCGPGRP: u32 = 0x540F;
+pub const TIOCSPGRP: u32 = 0x5410;
+pub const TIOCGWINSZ: u32 = 0x5413;
+pub const TIOCSWINSZ: u32 = 0x5414;
+
+/// Longest line canonical mode collects; further bytes are dropped.
+const MAX_LINE: usize = 4095;
+/// Readable bytes kept before input is dropped.
+const MAX_INPUT: usize = 4096;
+
+/// `struct termios` as the kernel ABI has it (what TCGETS copies out).
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// What the console starts as: cooked, echoing, signals on.
+    const fn new() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VINTR] = 0x03;
+        cc[VQUIT] = 0x1C;
+        cc[VERASE] = 0x7F;
+        cc[VKILL] = 0x15;
+        cc[VEOF] = 0x04;
+        cc[VMIN] = 1;
+        cc[VSUSP] = 0x1A;
+        cc[VWERASE] = 0x17;
+        Termios {
+            iflag: ICRNL,
+            oflag: OPOST | ONLCR,
+            cflag: CS8 | CREAD,
+            lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | IEXTEN,
+            line: 0,
+            cc,
+        }
+    }
+
+    /// These settings with line editing and echo off.
+    pub fn raw(self) -> Self {
+        Termios {
+            lflag: self.lflag & !(ICANON | ECHO),
+            ..self
+        }
+    }
+
+    /// Whether `c` is the control character at `index` (0 disables one).
+    fn is(&self, index: usize, c: u8) -> bool {
+        self.cc[index] != 0 && self.cc[index] == c
+    }
+}
+
+/// `struct winsize` of TIOCGWINSZ.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Winsize {
+    pub rows: u16,
+    pub cols: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
+struct TtyState {
+    termios: Termios,
+    /// Set by TIOCSWINSZ (`stty rows … cols …` for a serial terminal that
+    /// is not the screen's size); until then the framebuffer's text size.
+    winsize: Option<Winsize>,
+    /// The line being typed, canonical mode only.
+    line: Vec<u8>,
+    /// What reads get.
+    ready: VecDeque<u8>,
+    /// Canonical mode: the lengths of the lines in `ready`, which a read
+    /// takes one at a time. A 0 is EOF typed on an empty line.
+    lines: VecDeque<usize>,
+}
+
+/// How `c` shows when echoed: control characters as `^X` with ECHOCTL.
+fn echo_char(c: u8, lflag: u32, out: &mut Vec<u8>) {
+    let control = (c < 0x20 && c != b'\n' && c != b'\t') || c == 0x7F;
+    if control && lflag & ECHOCTL != 0 {
+        out.extend_from_slice(&[b'^', c ^ 0x40]);
+    } else {
+        out.push(c);
+    }
+}
+
+impl TtyState {
+    fn flush(&mut self) {
+        self.line.clear();
+        self.ready.clear();
+        self.lines.clear();
+    }
+
+    /// Take back the last byte of the line, rubbing it out on screen.
+    fn erase(&mut self, out: &mut Vec<u8>) -> Option<u8> {
+        let c = self.line.pop()?;
+        let lflag = self.termios.lflag;
+        if lflag & ECHO != 0 && lflag & ECHOE != 0 {
+            let mut shown = Vec::new();
+            echo_char(c, lflag, &mut shown);
+            for _ in shown {
+                out.extend_from_slice(b"\x08 \x08");
+            }
+        }
+        Some(c)
+    }
+
+    /// The line is done: hand it to readers.
+    fn finish_line(&mut self) {
+        self.lines.push_back(self.line.len());
+        self.ready.extend(self.line.drain(..));
+    }
+
+    /// Apply the line discipline to one typed byte, collecting what to echo
+    /// in `out`. True if readers have something new.
+    fn receive(&mut self, c: u8, out: &mut Vec<u8>) -> bool {
+        let t = self.termios;
+        let echo = t.lflag & ECHO != 0;
+        if t.lflag & ICANON == 0 {
+            if self.ready.len() >= MAX_INPUT {
+                return false;
+            }
+            self.ready.push_back(c);
+            if echo {
+                echo_char(c, t.lflag, out);
+            }
+            return true;
+        }
+
+        if t.is(VERASE, c) {
+            self.erase(out);
+        } else if t.is(VWERASE, c) && t.lflag & IEXTEN != 0 {
+            while self.line.last() == Some(&b' ') {
+                self.erase(out);
+            }
+            while self.line.last().is_some_and(|&b| b != b' ') {
+                self.erase(out);
+            }
+        } else if t.is(VKILL, c) {
+            if echo && t.lflag & ECHOE == 0 && t.lflag & ECHOK != 0 {
+                self.line.clear();
+                out.push(b'\n');
+            }
+            while self.erase(out).is_some() {}
+        } else if t.is(VEOF, c) {
+            // Whatever was typed goes without a newline; on its own, EOF
+            self.finish_line();
+            return true;
+        } else if c == b'\n' {
+            self.line.push(c);
+            self.finish_line();
+            if echo {
+                out.push(c);
+            }
+            return true;
+        } else if self.line.len() < MAX_LINE && self.ready.len() < MAX_INPUT {
+            self.line.push(c);
+            if echo {
+                echo_char(c, t.lflag, out);
+            }
+        }
+        false
+    }
+
+    /// Move what a read may have into `buf`; None if it has to wait.
+    fn take(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let n = if self.termios.lflag & ICANON == 0 {
+            // VMIN 0: don't wait for anything. VTIME isn't implemented.
+            if self.ready.is_empty() && self.termios.cc[VMIN] > 0 {
+                return None;
+            }
+            buf.len().min(self.ready.len())
+        } else {
+            let len = self.lines.front_mut()?;
+            let n = buf.len().min(*len);
+            *len -= n;
+            if *len == 0 {
+                self.lines.pop_front();
+            }
+            n
+        };
+        for (b, c) in buf.iter_mut().zip(self.ready.drain(..n)) {
+            *b = c;
+        }
+        Some(n)
+    }
+
+    fn set_termios(&mut self, t: Termios, flush: bool) {
+        let was_canon = self.termios.lflag & ICANON != 0;
+        let canon = t.lflag & ICANON != 0;
+        self.termios = t;
+        if flush {
+            self.flush();
+        } else if was_canon && !canon {
+            // A half-typed line becomes readable as it is
+            self.lines.clear();
+            self.ready.extend(self.line.drain(..));
+        } else if !was_canon && canon && !self.ready.is_empty() {
+            self.lines.push_back(self.ready.len());
+        }
+    }
+}
+
+/// Where a terminal's output goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line {
+    /// VT n of the framebuffer console, from 0.
+    Vt(usize),
+    /// Network terminal n, from 0.
+    Net(usize),
+}
+
+/// The far end of a network terminal.
+pub trait Remote: Send + Sync {
+    /// Output, translated as the terminal's termios has it.
+    fn send(&self, buf: &[u8]);
+}
+
+/// One terminal.
+pub struct Tty {
+    line: Line,
+    state: SpinLock<TtyState>,
+    /// Network terminals: who has it, if anyone but the console does.
+    remote: SpinLock<Option<Arc<dyn Remote>>>,
+    in_use: AtomicBool,
+    hung_up: AtomicBool,
+    /// Readers waiting for input.
+    readers: WaitQueue,
+    /// Process group the terminal's signals go to; 0 while the shell
+    /// itself reads it.
+    foreground: AtomicU32,
+}
+
+impl Tty {
+    const fn new(line: Line) -> Self {
+        Tty {
+            line,
+            state: SpinLock::new(TtyState {
+                termios: Termios::new(),
+                winsize: None,
+                line: Vec::new(),
+                ready: VecDeque::new(),
+                lines: VecDeque::new(),
+            }),
+            remote: SpinLock::new(None),
+            in_use: AtomicBool::new(false),
+            hung_up: AtomicBool::new(false),
+            readers: WaitQueue::new("tty"),
+            foreground: AtomicU32::new(0),
+        }
+    }
+
+    pub fn line(&self) -> Line {
+        self.line
+    }
+
+    pub fn is_console(&self) -> bool {
+        self.line == Line::Vt(0)
+    }
+
+    /// The remote end went away (or was sent away): readers get EOF and
+    /// the foreground group SIGHUP.
+    pub fn hangup(&self) {
+        if self.hung_up.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        self.remote.lock().take();
+        self.readers.wake_all();
+        if let pgid @ 1.. = self.foreground() {
+            let _ = signal::send_group(pgid, SIGHUP);
+        }
+    }
+
+    pub fn hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::Acquire)
+    }
+
+    /// Give a network terminal back to the pool once its session is over.
+    pub fn release(&self) {
+        self.hangup();
+        self.in_use.store(false, Ordering::Release);
+    }
+
+    pub fn set_foreground(&self, pgid: u32) {
+        self.foreground.store(pgid, Ordering::Relaxed);
+    }
+
+    pub fn foreground(&self) -> u32 {
+        self.foreground.load(Ordering::Relaxed)
+    }
+
+    /// INTR, QUIT and SUSP (Ctrl-C, Ctrl-\, Ctrl-Z) signal the foreground
+    /// group, if there is one. True if `c` was taken that way.
+    fn isig(&self, t: &Termios, c: u8) -> bool {
+        let sig = if t.is(VINTR, c) {
+            SIGINT
+        } else if t.is(VQUIT, c) {
+            SIGQUIT
+        } else if t.is(VSUSP, c) {
+            SIGTSTP
+        } else {
+            return false;
+        };
+        match self.foreground() {
+            0 => false,
+            pgid => {
+                let _ = signal::send_group(pgid, sig);
+                true
+            }
+        }
+    }
+
+    /// A byte typed at the keyboard or received on the serial port.
+    pub fn input(&self, c: u8) {
+        let t = self.state.lock().termios;
+        let c = if c == b'\r' && t.iflag & ICRNL != 0 {
+            b'\n'
+        } else {
+            c
+        };
+        let mut out = Vec::new();
+        let readable = if t.lflag & ISIG != 0 && self.isig(&t, c) {
+            // The signal ends whatever was being typed
+            self.state.lock().flush();
+            if t.lflag & ECHO != 0 {
+                echo_char(c, t.lflag, &mut out);
+            }
+            false
+        } else {
+            self.state.lock().receive(c, &mut out)
+        };
+        if !out.is_empty() {
+            self.write(&out);
+        }
+        if readable {
+            self.readers.wake_all();
+        }
+    }
+
+    /// Read for user programs and /dev/console, as the current termios has
+    /// it. Waits for input; a signal makes it EINTR.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.readers.wait_event(|| {
+            if let Some(n) = self.state.lock().take(buf) {
+                return Some(Ok(n));
+            }
+            if self.hung_up() {
+                return Some(Ok(0));
+            }
+            signal::interrupted().then_some(Err(Errno::EINTR))
+        })
+    }
+
+    /// A byte for the kernel's own readers (the shell's line editor,
+    /// `view`), which run the terminal raw: see `with_raw`. Signals don't
+    /// cut it short; a hangup is an endless EOF.
+    pub fn getc(&self) -> u8 {
+        self.readers
+            .wait_event(|| self.try_getc().or_else(|| self.hung_up().then_some(CTRL_D)))
+    }
+
+    pub fn try_getc(&self) -> Option<u8> {
+        self.state.lock().ready.pop_front()
+    }
+
+    /// Run `f` with line editing and echo off, then put the settings back.
+    pub fn with_raw<R>(&self, f: impl FnOnce() -> R) -> R {
+        let saved = self.termios();
+        self.set_termios(saved.raw(), false);
+        let r = f();
+        self.set_termios(saved, false);
+        r
+    }
+
+    /// Output: the VT, and for the console the serial port as well; or
+    /// the remote end.
+    pub fn write(&self, buf: &[u8]) {
+        let oflag = self.state.lock().termios.oflag;
+        let vt = match self.line {
+            Line::Vt(vt) => vt,
+            Line::Net(_) => {
+                // Cloned out: sending may wait for the connection
+                let Some(remote) = self.remote.lock().clone() else {
+                    return;
+                };
+                if oflag & (OPOST | ONLCR) == OPOST | ONLCR {
+                    let mut out = Vec::with_capacity(buf.len() + 16);
+                    for &b in buf {
+                        if b == b'\n' {
+                            out.push(b'\r');
+                        }
+                        out.push(b);
+                    }
+                    remote.send(&out);
+                } else {
+                    remote.send(buf);
+                }
+                return;
+            }
+        };
+        let s = String::from_utf8_lossy(buf);
+        if vt == 0 {
+            if oflag & (OPOST | ONLCR) == OPOST | ONLCR {
+                crate::drivers::serial::write_str(&s);
+            } else {
+                buf.iter().for_each(|&b| crate::drivers::serial::write_byte(b));
+            }
+        }
+        crate::drivers::vga::write_vt(vt, &s);
+        crate::drivers::vga::flush();
+    }
+
+    pub fn write_str(&self, s: &str) {
+        self.write(s.as_bytes());
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.state.lock().termios
+    }
+
+    /// New settings; `flush` drops typed input that wasn't read yet.
+    pub fn set_termios(&self, t: Termios, flush: bool) {
+        self.state.lock().set_termios(t, flush);
+        // Switching ICANON off (or on, with input waiting) can make a read go
+        self.readers.wake_all();
+    }
+
+    /// The terminal's size in characters. Without a framebuffer (or before
+    /// it is set up), and for a network terminal, it is the classic 80×25
+    /// of a serial terminal.
+    pub fn winsize(&self) -> Winsize {
+        if let Some(ws) = self.state.lock().winsize {
+            return ws;
+        }
+        let size = match self.line {
+            Line::Vt(_) => crate::drivers::vga::text_size(),
+            Line::Net(_) => (0, 0),
+        };
+        match size {
+            (0, _) | (_, 0) => Winsize {
+                rows: 25,
+                cols: 80,
+                xpixel: 0,
+                ypixel: 0,
+            },
+            (cols, rows) => {
+                let (cw, ch) = crate::drivers::vga::cell_size();
+                Winsize {
+                    rows: rows as u16,
+                    cols: cols as u16,
+                    xpixel: (cols * cw) as u16,
+                    ypixel: (rows * ch) as u16,
+                }
+            }
+        }
+    }
+
+    /// The terminal ioctls.
+    pub fn ioctl(&self, cmd: u32, arg: u64) -> Result<i64, Errno> {
+        match cmd {
+            TCGETS => put_user(arg, &self.termios())?,
+            // Output is never queued, so TCSETSW has nothing to wait for
+            TCSETS | TCSETSW | TCSETSF => self.set_termios(get_user(arg)?, cmd == TCSETSF),
+            TIOCGWINSZ => put_user(arg, &self.winsize())?,
+            TIOCSWINSZ => self.state.lock().winsize = Some(get_user(arg)?),
+            // The group job control signals; a shell hands it to a job
+            TIOCGPGRP => put_user(arg, &(self.foreground() as i32))?,
+            TIOCSPGRP => match get_user::<i32>(arg)? {
+                pgid if pgid > 0 => self.set_foreground(pgid as u32),
+                _ => return Err(Errno::EINVAL),
+            },
+            _ => return Err(Errno::ENOTTY),
+        }
+        Ok(0)
+    }
+}
+
+static TTYS: [Tty; VT_COUNT] = [
+    Tty::new(Line::Vt(0)),
+    Tty::new(Line::Vt(1)),
+    Tty::new(Line::Vt(2)),
+    Tty::new(Line::Vt(3)),
+];
+
+static NET: [Tty; NET_TTYS] = [
+    Tty::new(Line::Net(0)),
+    Tty::new(Line::Net(1)),
+    Tty::new(Line::Net(2)),
+    Tty::new(Line::Net(3)),
+];
+
+/// VT1's terminal: the shell's, /dev/console, and fds 0–2 of programs
+/// that have no file there.
+pub fn console() -> &'static Tty {
+    &TTYS[0]
+}
+
+/// The terminal of VT `vt` (from 0), /dev/tty1… .
+pub fn get(vt: usize) -> Option<&'static Tty> {
+    TTYS.get(vt)
+}
+
+/// Network terminal `n`.
+pub fn net(n: usize) -> Option<&'static Tty> {
+    NET.get(n)
+}
+
+/// Claim a free network terminal for `remote`, set up fresh: default
+/// termios, nothing typed, no foreground group. None if all are taken.
+pub fn attach(remote: Arc<dyn Remote>) -> Option<&'static Tty> {
+    let tty = NET.iter().find(|t| {
+        t.in_use
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    })?;
+    {
+        let mut st = tty.state.lock();
+        st.termios = Termios::new();
+        st.winsize = None;
+        st.flush();
+    }
+    tty.set_foreground(0);
+    *tty.remote.lock() = Some(remote);
+    tty.hung_up.store(false, Ordering::Release);
+    Some(tty)
+}
+
+/// The VTs' text size changed (a new console font): their foreground
+/// groups get SIGWINCH, unless a size was set for the terminal by hand.
+pub fn resized() {
+    for tty in &TTYS {
+        let pgid = tty.foreground();
+        if pgid != 0 && tty.state.lock().winsize.is_none() {
+            let _ = signal::send_group(pgid, signal::SIGWINCH);
+        }
+    }
+}
+
+/// A byte from the keyboard: it goes to the VT on screen, whose view
+/// comes back from the scrollback.
+pub fn keyboard_input(c: u8) {
+    crate::drivers::vga::scroll_reset();
+    TTYS[crate::drivers::vga::active()].input(c);
+}
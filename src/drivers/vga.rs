@@ -1,10 +1,47 @@
-use crate::arch::x86_64::limine::FRAMEBUFFER_REQUEST;
+//! Framebuffer text console with virtual terminals.
+//!
+//! Each VT (`Console`) keeps its text as cells — byte and colors — with
+//! its own cursor and escape-sequence state, whether it is on screen or
+//! not. `flush` renders the active one into a RAM back buffer, drawing
+//! only the cells that differ from what is already there, and copies the
+//! changed rectangle to the framebuffer; switching VTs is just a flush.
+//!
+//! Rows that scroll off the top are kept as scrollback, `SCROLLBACK`
+//! screens of it per VT; Shift+PageUp/PageDown move the view back into
+//! it and typing brings it back to the bottom.
+//!
+//! The console comes up twice. `early_init`, right after the logger and
+//! before there is a PMM, draws straight to the framebuffer with its
+//! state in the heap's early arena, so that a machine without a serial
+//! port doesn't look dead while it boots; only the log VT exists then,
+//! and it is on screen. `init` adds the back buffer and the other VTs,
+//! and shows the Limine module with cmdline `splash` (a BMP or PNG) above
+//! the last lines of the log until `boot_done` hands the screen to VT1.
+use crate::arch::x86_64::limine::{self, FRAMEBUFFER_REQUEST};
+use crate::drivers::psf::Font;
 use crate::sync::spinlock::SpinLock;
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::sync::atomic::Ordering;
 
-static FONT: &[u8] = include_bytes!("font8x16.bin");
-const FONT_WIDTH: usize = 8;
-const FONT_HEIGHT: usize = 16;
+/// Glyphs are drawn at up to this many times their size.
+pub const MAX_SCALE: usize = 4;
+/// The cursor is an underline this many (unscaled) scanlines high.
+const CURSOR_HEIGHT: usize = 2;
+/// Half a blink period.
+const BLINK_NS: u64 = 500_000_000;
+
+/// Alt+F1…F4 switch between these. The shell runs on VT1, kernel log
+/// messages go to VT2.
+pub const VT_COUNT: usize = 4;
+pub const LOG_VT: usize = 1;
+
+/// Scrollback kept per VT, in screens.
+const SCROLLBACK: usize = 4;
+
+/// Log lines shown under the splash image.
+const SPLASH_LOG_ROWS: usize = 6;
 
 pub type Color = u32;
 
@@ -21,76 +58,171 @@ pub const DARK_GRAY: Color = 0x55_55_55;
 pub const LIGHT_GREEN: Color = 0x55_FF_55;
 pub const LIGHT_BLUE: Color = 0x55_55_FF;
 
-struct Screen {
-    base: *mut u8,
-    width: usize,
-    height: usize,
-    pitch: usize,
-    bpp: usize,
+/// The VGA text palette, which is also what the 16 ANSI colors look like:
+/// SGR 30–37 pick 0–7, 90–97 (or bold) 8–15.
+const PALETTE: [Color; 16] = [
+    0x00_00_00, 0xAA_00_00, 0x00_AA_00, 0xAA_55_00, 0x00_00_AA, 0xAA_00_AA, 0x00_AA_AA, 0xAA_AA_AA,
+    0x55_55_55, 0xFF_55_55, 0x55_FF_55, 0xFF_FF_55, 0x55_55_FF, 0xFF_55_FF, 0x55_FF_FF, 0xFF_FF_FF,
+];
 
-    col: usize,
-    row: usize,
+/// A color as SGR chose it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ink {
+    /// What `set_color` set.
+    Default,
+    Palette(u8),
+    Rgb(Color),
+}
+
+/// The SGR attributes text is drawn with.
+#[derive(Debug, Clone, Copy)]
+struct Pen {
+    fg: Ink,
+    bg: Ink,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Pen {
+    const PLAIN: Pen = Pen {
+        fg: Ink::Default,
+        bg: Ink::Default,
+        bold: false,
+        reverse: false,
+    };
+}
+
+/// Where the escape-sequence parser is; sequences may be split across
+/// writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parse {
+    Text,
+    /// After ESC.
+    Escape,
+    /// After ESC [: collecting parameter bytes up to the final byte.
+    Csi,
+}
+
+/// Parameter bytes of a CSI sequence kept; longer ones are cut.
+const CSI_MAX: usize = 32;
+
+/// One character position of a console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell {
+    ch: u8,
+    fg: Color,
+    bg: Color,
+}
+
+/// A virtual terminal's text and state.
+struct Console {
+    /// Scrollback, then the screen's `rows` rows; oldest first.
+    lines: VecDeque<Vec<Cell>>,
     cols: usize,
     rows: usize,
+    col: usize,
+    row: usize,
+    /// How many rows the view is scrolled back from the bottom.
+    scroll: usize,
 
     fg: Color,
     bg: Color,
-}
+    pen: Pen,
+    /// ESC 7 / CSI s.
+    saved: (usize, usize),
+    /// The cursor should show (CSI ? 25 h / l).
+    cursor_shown: bool,
 
-unsafe impl Send for Screen {}
+    parse: Parse,
+    csi: [u8; CSI_MAX],
+    csi_len: usize,
+}
 
-impl Screen {
-    const fn uninit() -> Self {
-        Self {
-            base: core::ptr::null_mut(),
-            width: 0,
-            height: 0,
-            pitch: 0,
-            bpp: 4,
+impl Console {
+    fn new(cols: usize, rows: usize) -> Self {
+        let mut con = Console {
+            lines: VecDeque::new(),
+            cols,
+            rows,
             col: 0,
             row: 0,
-            cols: 0,
-            rows: 0,
+            scroll: 0,
             fg: WHITE,
             bg: BLACK,
-        }
+            pen: Pen::PLAIN,
+            saved: (0, 0),
+            cursor_shown: true,
+            parse: Parse::Text,
+            csi: [0; CSI_MAX],
+            csi_len: 0,
+        };
+        con.clear();
+        con
     }
 
-    fn put_pixel(&mut self, x: usize, y: usize, color: Color) {
-        if x >= self.width || y >= self.height {
-            return;
-        }
-        let offset = y * self.pitch + x * self.bpp;
-        unsafe {
-            let ptr = self.base.add(offset) as *mut u32;
-            ptr.write_volatile(color);
+    fn ink(&self, ink: Ink, default: Color, bright: bool) -> Color {
+        match ink {
+            Ink::Default => default,
+            Ink::Palette(i) if bright && i < 8 => PALETTE[i as usize + 8],
+            Ink::Palette(i) => PALETTE[i as usize & 15],
+            Ink::Rgb(c) => c,
         }
     }
 
-    fn draw_char(&mut self, ch: u8, px: usize, py: usize, fg: Color, bg: Color) {
-        let glyph_offset = (ch as usize) * FONT_HEIGHT;
-        if glyph_offset + FONT_HEIGHT > FONT.len() {
-            return;
+    /// (foreground, background) the pen draws with.
+    fn colors(&self) -> (Color, Color) {
+        let fg = self.ink(self.pen.fg, self.fg, self.pen.bold);
+        let bg = self.ink(self.pen.bg, self.bg, false);
+        if self.pen.reverse {
+            (bg, fg)
+        } else {
+            (fg, bg)
         }
+    }
 
-        for row in 0..FONT_HEIGHT {
-            let byte = FONT[glyph_offset + row];
-            for col in 0..FONT_WIDTH {
-                let set = byte & (0x80 >> col) != 0;
-                self.put_pixel(px + col, py + row, if set { fg } else { bg });
-            }
-        }
+    /// A cell showing `ch` in the pen's colors.
+    fn cell(&self, ch: u8) -> Cell {
+        let (fg, bg) = self.colors();
+        Cell { ch, fg, bg }
     }
 
-    fn scroll_up(&mut self) {
-        let line_bytes = FONT_HEIGHT * self.pitch;
-        let total = self.height * self.pitch;
+    fn blank_line(&self) -> Vec<Cell> {
+        vec![self.cell(b' '); self.cols]
+    }
 
-        unsafe {
-            core::ptr::copy(self.base.add(line_bytes), self.base, total - line_bytes);
-            core::ptr::write_bytes(self.base.add(total - line_bytes), 0, line_bytes);
-        }
+    /// Index in `lines` of the screen's top row.
+    fn top(&self) -> usize {
+        self.lines.len() - self.rows
+    }
+
+    /// Screen row `row`.
+    fn line(&mut self, row: usize) -> &mut Vec<Cell> {
+        let top = self.top();
+        &mut self.lines[top + row]
+    }
+
+    /// The rows in view, top first.
+    fn view(&self) -> impl Iterator<Item = &Vec<Cell>> {
+        self.lines.range(self.top() - self.scroll..).take(self.rows)
+    }
+
+    /// Move the view `delta` rows back into the scrollback (forward if
+    /// negative). Returns whether it moved.
+    fn scroll_view(&mut self, delta: isize) -> bool {
+        let scroll = self.scroll.saturating_add_signed(delta).min(self.top());
+        core::mem::replace(&mut self.scroll, scroll) != scroll
+    }
 
+    fn scroll_up(&mut self) {
+        let blank = self.blank_line();
+        self.lines.push_back(blank);
+        if self.lines.len() > self.rows * (SCROLLBACK + 1) {
+            self.lines.pop_front();
+        }
+        // A view scrolled back stays on the rows it shows
+        if self.scroll > 0 {
+            self.scroll = (self.scroll + 1).min(self.top());
+        }
         if self.row > 0 {
             self.row -= 1;
         }
@@ -108,15 +240,13 @@ impl Screen {
             8 => {
                 if self.col > 0 {
                     self.col -= 1;
-                    let px = self.col * FONT_WIDTH;
-                    let py = self.row * FONT_HEIGHT;
-                    self.draw_char(b' ', px, py, self.fg, self.bg);
+                    let (row, col, blank) = (self.row, self.col, self.cell(b' '));
+                    self.line(row)[col] = blank;
                 }
             }
             ch => {
-                let px = self.col * FONT_WIDTH;
-                let py = self.row * FONT_HEIGHT;
-                self.draw_char(ch, px, py, self.fg, self.bg);
+                let (row, col, cell) = (self.row, self.col, self.cell(ch));
+                self.line(row)[col] = cell;
                 self.col += 1;
                 if self.col >= self.cols {
                     self.col = 0;
@@ -130,134 +260,846 @@ impl Screen {
         }
     }
 
+    /// Blank every row of the screen in the default colors, cursor to the
+    /// top left. The scrollback stays.
     fn clear(&mut self) {
-        let total = self.height * self.pitch;
-        unsafe {
-            core::ptr::write_bytes(self.base, 0, total);
+        let blank = Cell {
+            ch: b' ',
+            fg: self.fg,
+            bg: self.bg,
+        };
+        while self.lines.len() < self.rows {
+            self.lines.push_back(Vec::new());
+        }
+        let top = self.top();
+        for line in self.lines.range_mut(top..) {
+            *line = vec![blank; self.cols];
         }
         self.col = 0;
         self.row = 0;
+        self.scroll = 0;
+    }
+
+    /// Change to `cols × rows`, for a new font. Lines are cut or padded
+    /// rather than rewrapped; when the screen gets shorter, blank rows
+    /// below the cursor go first so that it stays on screen.
+    fn resize(&mut self, cols: usize, rows: usize) {
+        let blank = Cell {
+            ch: b' ',
+            fg: self.fg,
+            bg: self.bg,
+        };
+        let mut at = self.top() + self.row;
+        for line in self.lines.iter_mut() {
+            line.resize(cols, blank);
+        }
+        while self.lines.len() > at + 1 && at + rows < self.lines.len() {
+            self.lines.pop_back();
+        }
+        while self.lines.len() < rows {
+            self.lines.push_back(vec![blank; cols]);
+        }
+        while self.lines.len() > rows * (SCROLLBACK + 1) {
+            self.lines.pop_front();
+            at -= 1;
+        }
+        self.cols = cols;
+        self.rows = rows;
+        self.row = at - self.top();
+        self.col = self.col.min(cols - 1);
+        self.saved = (self.saved.0.min(rows - 1), self.saved.1.min(cols - 1));
+        self.scroll = 0;
     }
 
     fn write_str(&mut self, s: &str) {
+        if self.rows == 0 || self.cols == 0 {
+            return;
+        }
         for b in s.bytes() {
-            self.put_char(b);
+            self.feed(b);
+        }
+    }
+
+    /// One byte of output: text, or part of an escape sequence.
+    fn feed(&mut self, b: u8) {
+        match self.parse {
+            Parse::Text if b == 0x1b => self.parse = Parse::Escape,
+            Parse::Text => self.put_char(b),
+            Parse::Escape => {
+                self.parse = Parse::Text;
+                match b {
+                    b'[' => {
+                        self.parse = Parse::Csi;
+                        self.csi_len = 0;
+                    }
+                    b'7' => self.saved = (self.row, self.col),
+                    b'8' => (self.row, self.col) = self.saved,
+                    b'c' => {
+                        self.pen = Pen::PLAIN;
+                        self.clear();
+                    }
+                    // Other two-byte sequences (charsets, keypad modes) are
+                    // dropped
+                    _ => {}
+                }
+            }
+            Parse::Csi if (0x40..=0x7E).contains(&b) => {
+                self.parse = Parse::Text;
+                let params = self.csi;
+                self.csi(&params[..self.csi_len], b);
+            }
+            Parse::Csi => {
+                if self.csi_len < CSI_MAX {
+                    self.csi[self.csi_len] = b;
+                    self.csi_len += 1;
+                }
+            }
         }
     }
 
-    fn set_color(&mut self, fg: Color, bg: Color) {
-        self.fg = fg;
-        self.bg = bg;
+    /// Blank cells `cols` of row `row` in the pen's background.
+    fn erase_cells(&mut self, row: usize, cols: core::ops::Range<usize>) {
+        let blank = self.cell(b' ');
+        let end = cols.end.min(self.cols);
+        self.line(row)[cols.start.min(end)..end].fill(blank);
+    }
+
+    /// SGR: colors and attributes.
+    fn sgr(&mut self, p: &[usize]) {
+        let mut i = 0;
+        while i < p.len() {
+            match p[i] {
+                0 => self.pen = Pen::PLAIN,
+                1 => self.pen.bold = true,
+                22 => self.pen.bold = false,
+                7 => self.pen.reverse = true,
+                27 => self.pen.reverse = false,
+                n @ 30..=37 => self.pen.fg = Ink::Palette((n - 30) as u8),
+                39 => self.pen.fg = Ink::Default,
+                n @ 40..=47 => self.pen.bg = Ink::Palette((n - 40) as u8),
+                49 => self.pen.bg = Ink::Default,
+                n @ 90..=97 => self.pen.fg = Ink::Palette((n - 90 + 8) as u8),
+                n @ 100..=107 => self.pen.bg = Ink::Palette((n - 100 + 8) as u8),
+                // 38;5;n / 38;2;r;g;b and the same with 48 for the background
+                n @ (38 | 48) => {
+                    let ink = match p.get(i + 1) {
+                        Some(5) => {
+                            let ink = p.get(i + 2).map(|&c| color_256(c));
+                            i += 2;
+                            ink
+                        }
+                        Some(2) => {
+                            let ch = |k: usize| p.get(i + k).map_or(0, |&c| c.min(255) as u32);
+                            let ink = Ink::Rgb(ch(2) << 16 | ch(3) << 8 | ch(4));
+                            i += 4;
+                            Some(ink)
+                        }
+                        _ => None,
+                    };
+                    match ink {
+                        Some(ink) if n == 38 => self.pen.fg = ink,
+                        Some(ink) => self.pen.bg = ink,
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    /// CSI sequence with parameter bytes `params` and final byte `fin`.
+    fn csi(&mut self, params: &[u8], fin: u8) {
+        // DECTCEM: cursor on / off. Other private sequences are dropped.
+        match (params, fin) {
+            (b"?25", b'h') => self.cursor_shown = true,
+            (b"?25", b'l') => self.cursor_shown = false,
+            _ => {}
+        }
+        if params.first().is_some_and(|b| !b.is_ascii_digit() && *b != b';') {
+            return;
+        }
+        let mut p = [0usize; 16];
+        let mut np = 0;
+        for part in params.split(|&b| b == b';').take(p.len()) {
+            p[np] = core::str::from_utf8(part)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(0);
+            np += 1;
+        }
+        let p = &p[..np];
+        // The count or position in parameter `i`, 1 when missing or 0
+        let arg = |i: usize| p.get(i).copied().filter(|&n| n > 0).unwrap_or(1);
+        let last_row = self.rows.saturating_sub(1);
+        let last_col = self.cols.saturating_sub(1);
+        match fin {
+            // CUU / CUD / CUF / CUB: cursor moves, stopping at the edges
+            b'A' => self.row = self.row.saturating_sub(arg(0)),
+            b'B' => self.row = (self.row + arg(0)).min(last_row),
+            b'C' => self.col = (self.col + arg(0)).min(last_col),
+            b'D' => self.col = self.col.saturating_sub(arg(0)),
+            // CHA / VPA: column / row; CUP: row;column, all from 1
+            b'G' | b'`' => self.col = (arg(0) - 1).min(last_col),
+            b'd' => self.row = (arg(0) - 1).min(last_row),
+            b'H' | b'f' => {
+                self.row = (arg(0) - 1).min(last_row);
+                self.col = (arg(1) - 1).min(last_col);
+            }
+            // ED: erase below (0), above (1) or all of the screen (2, 3)
+            b'J' => {
+                let (row, col) = (self.row, self.col);
+                let (rows, cols) = (self.rows, self.cols);
+                match p.first().copied().unwrap_or(0) {
+                    0 => {
+                        self.erase_cells(row, col..cols);
+                        (row + 1..rows).for_each(|r| self.erase_cells(r, 0..cols));
+                    }
+                    1 => {
+                        (0..row).for_each(|r| self.erase_cells(r, 0..cols));
+                        self.erase_cells(row, 0..col + 1);
+                    }
+                    _ => (0..rows).for_each(|r| self.erase_cells(r, 0..cols)),
+                }
+            }
+            // EL: erase to the end (0), from the start (1) or all of the line (2)
+            b'K' => {
+                let (row, col, cols) = (self.row, self.col, self.cols);
+                match p.first().copied().unwrap_or(0) {
+                    0 => self.erase_cells(row, col..cols),
+                    1 => self.erase_cells(row, 0..col + 1),
+                    _ => self.erase_cells(row, 0..cols),
+                }
+            }
+            b'm' if p.is_empty() => self.sgr(&[0]),
+            b'm' => self.sgr(p),
+            b's' => self.saved = (self.row, self.col),
+            b'u' => (self.row, self.col) = self.saved,
+            _ => {}
+        }
+    }
+}
+
+/// xterm's 256-color palette: the 16 ANSI colors, a 6×6×6 cube, 24 greys.
+fn color_256(n: usize) -> Ink {
+    match n {
+        0..=15 => Ink::Palette(n as u8),
+        16..=231 => {
+            let level = |l: usize| if l == 0 { 0 } else { 55 + 40 * l as u32 };
+            let n = n - 16;
+            Ink::Rgb(level(n / 36) << 16 | level(n / 6 % 6) << 8 | level(n % 6))
+        }
+        _ => {
+            let grey = 8 + 10 * (n.min(255) - 232) as u32;
+            Ink::Rgb(grey << 16 | grey << 8 | grey)
+        }
     }
 }
 
-static SCREEN: SpinLock<Screen> = SpinLock::new(Screen::uninit());
+/// Pixels of the back buffer changed since the last flush: [x0, x1) ×
+/// [y0, y1).
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x0: usize,
+    y0: usize,
+    x1: usize,
+    y1: usize,
+}
 
-pub fn init() {
+/// The framebuffer, and what is drawn on it.
+struct Screen {
+    base: *mut u8,
+    /// Where drawing goes: `width × height` pixels in RAM, copied to the
+    /// framebuffer by `blit`. Null (no vmalloc, not 32 bpp) draws straight
+    /// to the framebuffer instead.
+    back: *mut u32,
+    dirty: Option<Rect>,
+    width: usize,
+    height: usize,
+    pitch: usize,
+    bpp: usize,
+    font: Font,
+    /// Each font pixel is drawn as a `scale × scale` square.
+    scale: usize,
+    cols: usize,
+    rows: usize,
+    /// Per text cell, what it shows (the cell, and whether the cursor is
+    /// on it); None when unknown, which forces a redraw.
+    shown: Vec<Option<(Cell, bool)>>,
+}
+
+unsafe impl Send for Screen {}
+
+impl Screen {
+    const fn uninit() -> Self {
+        Self {
+            base: core::ptr::null_mut(),
+            back: core::ptr::null_mut(),
+            dirty: None,
+            width: 0,
+            height: 0,
+            pitch: 0,
+            bpp: 4,
+            font: Font::builtin(),
+            scale: 1,
+            cols: 0,
+            rows: 0,
+            shown: Vec::new(),
+        }
+    }
+
+    /// A text cell's size in pixels.
+    fn cell_size(&self) -> (usize, usize) {
+        (self.font.width * self.scale, self.font.height * self.scale)
+    }
+
+    /// Size the text grid to the font, everything to be drawn afresh.
+    fn layout(&mut self) {
+        let (cw, ch) = self.cell_size();
+        self.cols = self.width / cw;
+        self.rows = self.height / ch;
+        self.shown = vec![None; self.cols * self.rows];
+        self.fill_black();
+    }
+
+    /// Set one pixel; whoever draws marks the area with `touch`.
+    fn put_pixel(&mut self, x: usize, y: usize, color: Color) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        unsafe {
+            if !self.back.is_null() {
+                *self.back.add(y * self.width + x) = color;
+                return;
+            }
+            let ptr = self.base.add(y * self.pitch + x * self.bpp) as *mut u32;
+            ptr.write_volatile(color);
+        }
+    }
+
+    /// Mark a `w × h` area at (x, y) for the next blit.
+    fn touch(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        let (x1, y1) = ((x + w).min(self.width), (y + h).min(self.height));
+        self.dirty = Some(match self.dirty {
+            None => Rect { x0: x, y0: y, x1, y1 },
+            Some(r) => Rect {
+                x0: r.x0.min(x),
+                y0: r.y0.min(y),
+                x1: r.x1.max(x1),
+                y1: r.y1.max(y1),
+            },
+        });
+    }
+
+    /// Black out the whole screen.
+    fn fill_black(&mut self) {
+        unsafe {
+            if self.back.is_null() {
+                core::ptr::write_bytes(self.base, 0, self.height * self.pitch);
+            } else {
+                core::ptr::write_bytes(self.back, 0, self.width * self.height);
+            }
+        }
+        self.shown.fill(None);
+        self.touch(0, 0, self.width, self.height);
+    }
+
+    fn draw_glyph(&mut self, ch: u8, px: usize, py: usize, fg: Color, bg: Color) {
+        let (width, height, scale) = (self.font.width, self.font.height, self.scale);
+        let row_bytes = self.font.row_bytes();
+        for row in 0..height {
+            // The row as one number, leftmost pixel highest; a glyph the
+            // font lacks comes out blank
+            let bits = self.font.glyph(ch).get(row * row_bytes..(row + 1) * row_bytes);
+            let bits = bits.map_or(0, |b| b.iter().fold(0u64, |acc, &x| (acc << 8) | x as u64));
+            for col in 0..width {
+                let set = (bits >> (row_bytes * 8 - 1 - col)) & 1 != 0;
+                let color = if set { fg } else { bg };
+                let (x, y) = (px + col * scale, py + row * scale);
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        self.put_pixel(x + dx, y + dy, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw the cells of `con` that differ from what is shown, with the
+    /// cursor underlined at `cursor`.
+    fn render(&mut self, con: &Console, cursor: Option<(usize, usize)>) {
+        for (row, line) in con.view().enumerate().take(self.rows) {
+            let cursor = cursor.filter(|c| c.0 == row).map(|c| c.1);
+            self.render_line(row, line, cursor);
+        }
+    }
+
+    /// Draw `line` as text row `row`, where it differs from what is shown.
+    fn render_line(&mut self, row: usize, line: &[Cell], cursor: Option<usize>) {
+        for (col, &cell) in line.iter().enumerate().take(self.cols) {
+            let want = (cell, cursor == Some(col));
+            let i = row * self.cols + col;
+            if self.shown[i] == Some(want) {
+                continue;
+            }
+            let (cw, ch) = self.cell_size();
+            let (px, py) = (col * cw, row * ch);
+            self.draw_glyph(cell.ch, px, py, cell.fg, cell.bg);
+            if want.1 {
+                for y in py + ch - CURSOR_HEIGHT * self.scale..py + ch {
+                    for x in px..px + cw {
+                        self.put_pixel(x, y, cell.fg);
+                    }
+                }
+            }
+            self.touch(px, py, cw, ch);
+            self.shown[i] = Some(want);
+        }
+    }
+
+    /// The last `n` lines written to `con`, up to the cursor's, on the
+    /// bottom `n` text rows.
+    fn render_tail(&mut self, con: &Console, n: usize) {
+        let end = con.top() + con.row + (con.col > 0) as usize;
+        let start = end.saturating_sub(n);
+        let first = self.rows - (end - start);
+        for (i, line) in con.lines.range(start..end).enumerate() {
+            self.render_line(first + i, line, None);
+        }
+    }
+
+    /// Draw a bitmap centred in the top left `w × h` pixels, scaled
+    /// nearest-neighbour, aspect ratio kept: one too big shrinks to fit,
+    /// a small one grows by the largest whole factor that fits, so its
+    /// pixels stay square.
+    fn draw_picture(&mut self, bmp: &crate::drivers::bmp::Bitmap, w: usize, h: usize) {
+        let iw = bmp.width;
+        let ih = bmp.height;
+
+        let (dst_w, dst_h) = if iw <= w && ih <= h {
+            let k = (w / iw).min(h / ih);
+            (iw * k, ih * k)
+        // Compare w/iw vs h/ih using cross-multiplication to avoid division.
+        } else if w * ih <= h * iw {
+            // width is the limiting dimension
+            (w, ih * w / iw)
+        } else {
+            // height is the limiting dimension
+            (iw * h / ih, h)
+        };
+
+        let off_x = (w - dst_w) / 2;
+        let off_y = (h - dst_h) / 2;
+
+        for dy in 0..dst_h {
+            let sy = dy * ih / dst_h;
+            for dx in 0..dst_w {
+                let sx = dx * iw / dst_w;
+                let color = bmp.pixels[sy * iw + sx];
+                self.put_pixel(off_x + dx, off_y + dy, color);
+            }
+        }
+        self.touch(off_x, off_y, dst_w, dst_h);
+    }
+
+    /// Copy what changed in the back buffer to the framebuffer, one run of
+    /// pixels per scanline.
+    fn blit(&mut self) {
+        let Some(r) = self.dirty.take() else {
+            return;
+        };
+        if self.back.is_null() {
+            return;
+        }
+        for y in r.y0..r.y1 {
+            unsafe {
+                let src = self.back.add(y * self.width + r.x0);
+                let dst = self.base.add(y * self.pitch + r.x0 * self.bpp) as *mut u32;
+                core::ptr::copy_nonoverlapping(src, dst, r.x1 - r.x0);
+            }
+        }
+    }
+}
+
+struct Vga {
+    screen: Screen,
+    vts: Vec<Console>,
+    active: usize,
+    /// The blinking cursor is in its lit half.
+    blink_on: bool,
+    /// A bitmap (`draw_bitmap`) covers the screen until the next `clear`.
+    picture: bool,
+    /// The boot splash is up: a picture over the log VT's last lines.
+    splash: bool,
+}
+
+impl Vga {
+    /// Render the active console and put it on the framebuffer.
+    fn flush(&mut self) {
+        if self.screen.base.is_null() || self.picture {
+            return;
+        }
+        if self.splash {
+            if let Some(con) = self.vts.get(LOG_VT) {
+                self.screen.render_tail(con, SPLASH_LOG_ROWS);
+            }
+            self.screen.blit();
+            return;
+        }
+        let Some(con) = self.vts.get(self.active) else {
+            return;
+        };
+        let lit = con.cursor_shown && self.blink_on && con.scroll == 0;
+        let cursor = lit.then_some((con.row, con.col));
+        self.screen.render(con, cursor);
+        self.screen.blit();
+    }
+
+    /// Take the splash down, for the consoles to be drawn over it.
+    fn end_splash(&mut self) {
+        if core::mem::take(&mut self.splash) {
+            self.screen.fill_black();
+        }
+    }
+}
+
+static VGA: SpinLock<Vga> = SpinLock::new(Vga {
+    screen: Screen::uninit(),
+    vts: Vec::new(),
+    active: 0,
+    blink_on: true,
+    picture: false,
+    splash: false,
+});
+
+/// The framebuffer Limine set up, drawn on directly for now, its text
+/// grid not yet laid out. None if there is none.
+fn open_screen() -> Option<Screen> {
     let resp = FRAMEBUFFER_REQUEST.response.load(Ordering::Relaxed);
     if resp.is_null() {
-        log::warn!("No framebuffer available");
-        return;
+        return None;
     }
 
+    let mut screen = Screen::uninit();
     unsafe {
         let fbs = (*resp).framebuffers();
         if fbs.is_empty() {
-            return;
+            return None;
         }
 
         let fb = &*fbs[0];
-        let mut screen = SCREEN.lock();
         screen.base = fb.address;
         screen.width = fb.width as usize;
         screen.height = fb.height as usize;
         screen.pitch = fb.pitch as usize;
         screen.bpp = (fb.bpp / 8) as usize;
-        screen.cols = fb.width as usize / FONT_WIDTH;
-        screen.rows = fb.height as usize / FONT_HEIGHT;
+    }
+    // `font.scale=N` on the command line for a screen too fine for 8×16
+    let scale = limine::cmdline_value("font.scale").and_then(|v| v.parse().ok());
+    if let Some(scale) = scale.filter(|s| (1..=MAX_SCALE).contains(s)) {
+        if screen.width >= 8 * scale && screen.height >= 16 * scale {
+            screen.scale = scale;
+        }
+    }
+    Some(screen)
+}
+
+/// Bring the log VT up on the framebuffer, before the PMM; see the top of
+/// the file. Skipped if the screen needs more than the early arena has
+/// left, in which case `init` does it all later.
+pub fn early_init() {
+    let Some(mut screen) = open_screen() else {
+        return;
+    };
+    let (cw, ch) = screen.cell_size();
+    let cells = (screen.width / cw) * (screen.height / ch);
+    // The shown cache and the log VT's screen, and some room for scrolling
+    let per_cell = core::mem::size_of::<Cell>() + core::mem::size_of::<Option<(Cell, bool)>>();
+    if cells * per_cell * 3 / 2 > crate::mm::heap::early_left() {
+        return;
+    }
+    screen.layout();
+    let (cols, rows) = (screen.cols, screen.rows);
+    let vts = (0..VT_COUNT)
+        .map(|vt| if vt == LOG_VT { Console::new(cols, rows) } else { Console::new(0, 0) })
+        .collect();
+
+    let mut vga = VGA.lock();
+    vga.screen = screen;
+    vga.vts = vts;
+    vga.active = LOG_VT;
+    vga.flush();
+}
+
+/// The splash image: the Limine module with cmdline `splash`.
+fn splash_image() -> Option<crate::drivers::bmp::Bitmap> {
+    let mut modules = limine::modules().iter().map(|&m| unsafe { &*m });
+    let module = modules.find(|m| m.cmdline() == "splash")?;
+    match crate::drivers::bmp::decode_image(module.data()) {
+        Ok(bmp) => Some(bmp),
+        Err(e) => {
+            log::warn!("Splash {}: {}", module.path(), e);
+            None
+        }
+    }
+}
 
-        screen.clear();
+/// The rest of the console, once there is vmalloc: the back buffer, every
+/// VT, and the splash. Everything `early_init` would have done, if it
+/// didn't. The log VT stays on screen until `boot_done`.
+pub fn init() {
+    if VGA.lock().screen.base.is_null() {
+        let Some(screen) = open_screen() else {
+            log::warn!("No framebuffer available");
+            return;
+        };
+        let mut vga = VGA.lock();
+        vga.screen = screen;
+        vga.active = LOG_VT;
     }
+    let size = {
+        let vga = VGA.lock();
+        (vga.screen.bpp == 4).then_some(vga.screen.width * vga.screen.height * 4)
+    };
+    let back = size.and_then(crate::mm::vmalloc::vmalloc);
+    let splash = splash_image();
 
-    let (w, h, bpp) = {
-        let scr = SCREEN.lock();
-        (scr.width, scr.height, scr.bpp * 8)
+    let (w, h, bpp, buffered) = {
+        let mut vga = VGA.lock();
+        if let Some(p) = back {
+            vga.screen.back = p as *mut u32;
+        }
+        vga.screen.layout();
+        let (cols, rows) = (vga.screen.cols, vga.screen.rows);
+        vga.vts.resize_with(VT_COUNT, || Console::new(0, 0));
+        for con in vga.vts.iter_mut().filter(|con| con.rows == 0) {
+            con.resize(cols, rows);
+        }
+        if let Some(bmp) = splash.filter(|_| rows > SPLASH_LOG_ROWS) {
+            let (w, ch) = (vga.screen.width, vga.screen.cell_size().1);
+            vga.screen.draw_picture(&bmp, w, (rows - SPLASH_LOG_ROWS) * ch);
+            vga.splash = true;
+        }
+        vga.flush();
+        let scr = &vga.screen;
+        (scr.width, scr.height, scr.bpp * 8, !scr.back.is_null())
     };
-    log::info!("Framebuffer: {}x{} {}bpp", w, h, bpp);
+    // The first blink comes with the first timer tick
+    crate::proc::timers::call_at(0, blink, 0);
+
+    log::info!(
+        "Framebuffer: {}x{} {}bpp{}, {} VTs",
+        w,
+        h,
+        bpp,
+        if buffered { ", back-buffered" } else { "" },
+        VT_COUNT
+    );
+}
+
+/// Booting is over: the splash goes, and VT1, where the shell runs, comes
+/// on screen.
+pub fn boot_done() {
+    let mut vga = VGA.lock();
+    vga.end_splash();
+    vga.active = 0;
+    vga.blink_on = true;
+    vga.flush();
 }
 
+/// Draw `s` on console `vt`; it shows at the next `flush` if `vt` is the
+/// active one. ANSI/VT100 escape sequences are interpreted like a Linux
+/// console does: SGR colors, cursor moves and erases; the rest is dropped
+/// so it doesn't appear as garbage on the framebuffer.
+pub fn write_vt(vt: usize, s: &str) {
+    let mut vga = VGA.lock();
+    if vt == vga.active {
+        // Typing restarts the blink rather than catching the cursor dark
+        vga.blink_on = true;
+    }
+    if let Some(con) = vga.vts.get_mut(vt) {
+        con.write_str(s);
+    }
+}
+
+/// `write_vt` to the console on screen.
 pub fn write_str(s: &str) {
-    let mut scr = SCREEN.lock();
-    if scr.base.is_null() {
+    write_vt(active(), s);
+}
+
+/// Put what was drawn since the last flush on the screen. The print paths
+/// (the console TTYs, `print!`) call this once per write.
+pub fn flush() {
+    VGA.lock().flush();
+}
+
+pub fn active() -> usize {
+    VGA.lock().active
+}
+
+/// Bring console `vt` on screen.
+pub fn switch_to(vt: usize) {
+    let mut vga = VGA.lock();
+    if vt >= vga.vts.len() || vt == vga.active {
         return;
     }
-    // Strip ANSI/VT100 CSI escape sequences (ESC [ ... <final 0x40-0x7E>)
-    // so they don't appear as garbage on the framebuffer.
-    let b = s.as_bytes();
-    let mut i = 0;
-    while i < b.len() {
-        if b[i] == 0x1b && i + 1 < b.len() && b[i + 1] == b'[' {
-            i += 2;
-            while i < b.len() && !(b[i] >= 0x40 && b[i] <= 0x7e) {
-                i += 1;
-            }
-            i += 1; // skip final byte
-        } else {
-            scr.put_char(b[i]);
-            i += 1;
-        }
+    vga.active = vt;
+    vga.picture = false;
+    vga.end_splash();
+    vga.blink_on = true;
+    vga.flush();
+}
+
+/// Shift+PageUp (`halves` > 0) / PageDown: move the view of the console on
+/// screen that many half screens into its scrollback, or back towards the
+/// bottom.
+pub fn scroll_view(halves: isize) {
+    let mut vga = VGA.lock();
+    let active = vga.active;
+    let Some(con) = vga.vts.get_mut(active) else {
+        return;
+    };
+    let rows = (con.rows / 2).max(1) as isize;
+    if con.scroll_view(halves * rows) {
+        vga.flush();
     }
 }
 
-pub fn set_color(fg: Color, bg: Color) {
-    SCREEN.lock().set_color(fg, bg);
+/// Back to the bottom of the scrollback, where the cursor is.
+pub fn scroll_reset() {
+    let mut vga = VGA.lock();
+    let active = vga.active;
+    let Some(con) = vga.vts.get_mut(active) else {
+        return;
+    };
+    if con.scroll_view(-(con.scroll as isize)) {
+        vga.flush();
+    }
 }
 
-pub fn clear() {
-    SCREEN.lock().clear();
+/// Timer callback: blink the cursor, and come back in half a period.
+fn blink(_: u64) {
+    {
+        let mut vga = VGA.lock();
+        vga.blink_on = !vga.blink_on;
+        vga.flush();
+    }
+    let now = crate::arch::x86_64::timer::nanos();
+    crate::proc::timers::call_at(now + BLINK_NS, blink, 0);
 }
 
-/// Draw a bitmap scaled to fit the screen (nearest-neighbour, aspect-ratio preserved).
-pub fn draw_bitmap(bmp: &crate::drivers::bmp::Bitmap) {
-    let mut scr = SCREEN.lock();
-    if scr.base.is_null() {
-        return;
+/// Text columns and rows of the console; (0, 0) without a framebuffer.
+pub fn text_size() -> (usize, usize) {
+    let vga = VGA.lock();
+    (vga.screen.cols, vga.screen.rows)
+}
+
+/// The size of a text cell in pixels, as drawn.
+pub fn cell_size() -> (usize, usize) {
+    VGA.lock().screen.cell_size()
+}
+
+/// The font's own glyph size and the scale it is drawn at.
+pub fn font_info() -> (usize, usize, usize) {
+    let vga = VGA.lock();
+    (vga.screen.font.width, vga.screen.font.height, vga.screen.scale)
+}
+
+/// Draw the consoles in `font` (None keeps the current one) at `scale`
+/// (None keeps the current one), every VT reflowed to the new grid. A
+/// cell has to fit on the screen. Gives the new columns and rows; their
+/// terminals' foreground groups are for the caller to tell (`tty::resized`).
+pub fn set_font(font: Option<Font>, scale: Option<usize>) -> Result<(usize, usize), &'static str> {
+    let mut vga = VGA.lock();
+    if vga.screen.base.is_null() {
+        return Err("no framebuffer");
+    }
+    let scale = scale.unwrap_or(vga.screen.scale);
+    if !(1..=MAX_SCALE).contains(&scale) {
+        return Err("scale out of range");
+    }
+    let (fw, fh) = font.as_ref().map_or((vga.screen.font.width, vga.screen.font.height), |f| {
+        (f.width, f.height)
+    });
+    if fw * scale > vga.screen.width || fh * scale > vga.screen.height {
+        return Err("font too big for the screen");
+    }
+    if let Some(font) = font {
+        vga.screen.font = font;
     }
+    vga.screen.scale = scale;
+    vga.screen.layout();
+    let (cols, rows) = (vga.screen.cols, vga.screen.rows);
+    for con in vga.vts.iter_mut() {
+        con.resize(cols, rows);
+    }
+    vga.picture = false;
+    vga.splash = false;
+    vga.flush();
+    Ok((cols, rows))
+}
 
-    let sw = scr.width;
-    let sh = scr.height;
-    let iw = bmp.width;
-    let ih = bmp.height;
+/// Default colors of the console on screen, what SGR 0 goes back to.
+pub fn set_color(fg: Color, bg: Color) {
+    let mut vga = VGA.lock();
+    let active = vga.active;
+    if let Some(con) = vga.vts.get_mut(active) {
+        con.fg = fg;
+        con.bg = bg;
+    }
+}
 
-    // Clear to black
-    let total = sh * scr.pitch;
-    unsafe {
-        core::ptr::write_bytes(scr.base, 0, total);
-    }
-    scr.col = 0;
-    scr.row = 0;
-
-    // Compute destination rectangle that fits within screen, keeping aspect ratio.
-    // Compare sw/iw vs sh/ih using cross-multiplication to avoid division.
-    let (dst_w, dst_h) = if sw * ih <= sh * iw {
-        // width is the limiting dimension
-        (sw, ih * sw / iw)
-    } else {
-        // height is the limiting dimension
-        (iw * sh / ih, sh)
+/// `font.file=<path>` on the command line: switch to that PSF font, once
+/// the VFS is there to read it from.
+pub fn load_boot_font() {
+    let Some(path) = limine::cmdline_value("font.file") else {
+        return;
     };
+    let font = crate::fs::mount::with_vfs(|vfs| vfs.read_file(path))
+        .map_err(|_| "cannot read it")
+        .and_then(|data| Font::parse(&data));
+    match font.and_then(|f| set_font(Some(f), None)) {
+        Ok((cols, rows)) => log::info!("Console font {}: {}x{} text", path, cols, rows),
+        Err(e) => log::warn!("Console font {}: {}", path, e),
+    }
+}
 
-    let off_x = (sw - dst_w) / 2;
-    let off_y = (sh - dst_h) / 2;
+/// Blank the console on screen, cursor back at the top left; this also
+/// takes down a bitmap shown by `draw_bitmap`, and the splash.
+pub fn clear() {
+    let mut vga = VGA.lock();
+    let active = vga.active;
+    if let Some(con) = vga.vts.get_mut(active) {
+        con.clear();
+    }
+    vga.picture = false;
+    vga.end_splash();
+    vga.flush();
+}
 
-    for dy in 0..dst_h {
-        let sy = dy * ih / dst_h;
-        for dx in 0..dst_w {
-            let sx = dx * iw / dst_w;
-            let color = bmp.pixels[sy * iw + sx];
-            scr.put_pixel(off_x + dx, off_y + dy, color);
-        }
+/// Take the screen over for the panic report: the console on screen,
+/// blanked in `fg` on `bg`, its scrollback view and any bitmap gone. The
+/// lock is broken first, in case the code that panicked held it.
+pub fn panic_screen(fg: Color, bg: Color) {
+    unsafe { VGA.force_unlock() };
+    scroll_reset();
+    set_color(fg, bg);
+    clear();
+}
+
+/// Draw a bitmap over the whole screen, as `Screen::draw_picture` fits it.
+pub fn draw_bitmap(bmp: &crate::drivers::bmp::Bitmap) {
+    let mut vga = VGA.lock();
+    if vga.screen.base.is_null() {
+        return;
     }
+    vga.picture = true;
+    vga.splash = false;
+    let scr = &mut vga.screen;
+    scr.fill_black();
+    let (w, h) = (scr.width, scr.height);
+    scr.draw_picture(bmp, w, h);
+    scr.blit();
 }
 
 use core::fmt;
@@ -270,10 +1112,12 @@ impl fmt::Write for VgaWriter {
     }
 }
 
+/// `print!`: to the console on screen.
 pub fn print_fmt(args: fmt::Arguments) {
     use fmt::Write;
     let mut w = VgaWriter;
     let _ = w.write_fmt(args);
+    flush();
 }
 
 #[macro_export]
@@ -0,0 +1,428 @@
+/// Archives of VFS trees: ustar (POSIX tar) here, newc cpio in cpio.rs.
+/// `tar` in the shell goes through this to pack a directory into one file
+/// and unpack it elsewhere, so a tree can cross to the host on a FAT disk.
+///
+/// Extraction reads the GNU long-name entries and the path of pax headers
+/// that host tars write for names over 100 bytes; creation writes GNU
+/// long names, which both GNU tar and bsdtar read.
+use super::cpio::{self, Summary};
+use super::mount::VfsContext;
+use super::path;
+use super::vfs::{Errno, FileType, Timespec};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+const BLOCK: usize = 512;
+const NAME_LEN: usize = 100;
+const GNU_LONGNAME: &str = "././@LongLink";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Ustar,
+    Newc,
+}
+
+impl Format {
+    /// The format `data` is in, by its magic.
+    pub fn detect(data: &[u8]) -> Option<Format> {
+        if data.starts_with(b"070701") || data.starts_with(b"070702") {
+            Some(Format::Newc)
+        } else if data.len() >= BLOCK && &data[257..262] == b"ustar" {
+            Some(Format::Ustar)
+        } else {
+            None
+        }
+    }
+}
+
+/// One file to archive: its name in the archive, and for a symlink the
+/// target as its data.
+pub struct Member {
+    pub name: String,
+    pub kind: FileType,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mtime: i64,
+    pub data: Vec<u8>,
+}
+
+// ─── Reading ─────────────────────────────────────────────────────────────────
+
+pub struct TarEntry<'a> {
+    pub name: String,
+    /// Typeflag: b'0' (or 0) file, b'1' hard link, b'2' symlink, b'5'
+    /// directory; others are kept as they are.
+    pub kind: u8,
+    pub mode: u32,
+    pub mtime: i64,
+    /// Target of a link.
+    pub link: String,
+    pub data: &'a [u8],
+}
+
+/// A NUL- or space-terminated octal field.
+fn octal(field: &[u8]) -> Option<u64> {
+    let digits = field
+        .iter()
+        .copied()
+        .skip_while(|&b| b == b' ')
+        .take_while(|&b| b != 0 && b != b' ');
+    let mut v = 0u64;
+    let mut any = false;
+    for b in digits {
+        if !(b'0'..=b'7').contains(&b) {
+            return None;
+        }
+        v = v.checked_mul(8)? + (b - b'0') as u64;
+        any = true;
+    }
+    any.then_some(v)
+}
+
+/// A NUL-padded string field.
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Sum of the header's bytes with the checksum field taken as spaces.
+fn checksum(hdr: &[u8]) -> u64 {
+    hdr.iter()
+        .enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum()
+}
+
+/// The `path` and `linkpath` records of a pax extended header, each
+/// "<len> <key>=<value>\n".
+fn pax_paths(mut data: &[u8]) -> (Option<String>, Option<String>) {
+    let (mut path, mut link) = (None, None);
+    while let Some(sp) = data.iter().position(|&b| b == b' ') {
+        let Some(len) = core::str::from_utf8(&data[..sp]).ok().and_then(|l| l.parse().ok())
+        else {
+            break;
+        };
+        if len <= sp || len > data.len() {
+            break;
+        }
+        let record = &data[sp + 1..len];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(eq) = record.iter().position(|&b| b == b'=') {
+            let value = String::from_utf8_lossy(&record[eq + 1..]).into_owned();
+            match &record[..eq] {
+                b"path" => path = Some(value),
+                b"linkpath" => link = Some(value),
+                _ => {}
+            }
+        }
+        data = &data[len..];
+    }
+    (path, link)
+}
+
+/// Iterate over a tar archive's members up to its end blocks. Long-name
+/// and pax entries are folded into the member they describe.
+pub struct TarReader<'a> {
+    archive: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> TarReader<'a> {
+    pub fn new(archive: &'a [u8]) -> Self {
+        Self { archive, offset: 0 }
+    }
+
+    fn fail(&mut self, why: &'static str) -> Option<Result<TarEntry<'a>, &'static str>> {
+        self.offset = self.archive.len();
+        Some(Err(why))
+    }
+}
+
+impl<'a> Iterator for TarReader<'a> {
+    type Item = Result<TarEntry<'a>, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (mut long_name, mut long_link) = (None, None);
+        loop {
+            let a = self.archive;
+            let off = self.offset;
+            if off + BLOCK > a.len() {
+                return None;
+            }
+            let hdr = &a[off..off + BLOCK];
+            if hdr.iter().all(|&b| b == 0) {
+                self.offset = a.len();
+                return None;
+            }
+            if octal(&hdr[148..156]) != Some(checksum(hdr)) {
+                return self.fail("bad tar header checksum");
+            }
+            let (Some(mode), Some(size), Some(mtime)) =
+                (octal(&hdr[100..108]), octal(&hdr[124..136]), octal(&hdr[136..148]))
+            else {
+                return self.fail("bad tar header");
+            };
+            let data_start = off + BLOCK;
+            let data_end = data_start + size as usize;
+            if data_end > a.len() {
+                return self.fail("truncated tar archive");
+            }
+            let data = &a[data_start..data_end];
+            self.offset = data_start + (size as usize).next_multiple_of(BLOCK);
+
+            let kind = hdr[156];
+            match kind {
+                b'L' => long_name = Some(text(data)),
+                b'K' => long_link = Some(text(data)),
+                b'x' => {
+                    let (p, l) = pax_paths(data);
+                    long_name = p.or(long_name);
+                    long_link = l.or(long_link);
+                }
+                b'g' => {}
+                _ => {
+                    let name = long_name.unwrap_or_else(|| {
+                        let (prefix, name) = (text(&hdr[345..500]), text(&hdr[..NAME_LEN]));
+                        if &hdr[257..262] == b"ustar" && !prefix.is_empty() {
+                            alloc::format!("{}/{}", prefix, name)
+                        } else {
+                            name
+                        }
+                    });
+                    return Some(Ok(TarEntry {
+                        name,
+                        kind,
+                        mode: mode as u32,
+                        mtime: mtime as i64,
+                        link: long_link.unwrap_or_else(|| text(&hdr[157..257])),
+                        // Links and directories carry no data of their own
+                        data: if matches!(kind, 0 | b'0' | b'7') { data } else { &[] },
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// Names of the members of an archive in either format.
+pub fn list(archive: &[u8]) -> Result<Vec<String>, &'static str> {
+    match Format::detect(archive) {
+        Some(Format::Newc) => cpio::Reader::new(archive)
+            .map(|e| e.map(|e| String::from(e.name)))
+            .collect(),
+        Some(Format::Ustar) => TarReader::new(archive).map(|e| e.map(|e| e.name)).collect(),
+        None => Err("not a tar or newc cpio archive"),
+    }
+}
+
+// ─── Extracting ──────────────────────────────────────────────────────────────
+
+fn extract_tar(vfs: &VfsContext, archive: &[u8], base: &str) -> Result<Summary, &'static str> {
+    let mut sum = Summary::default();
+    for entry in TarReader::new(archive) {
+        let e = entry?;
+        let dest = cpio::under(base, &e.name);
+        let perm = e.mode & 0o7777;
+        let (parent, _) = path::split_last(&dest);
+        let res = match e.kind {
+            b'5' => cpio::ensure_dir(vfs, &dest, perm).map(|_| sum.dirs += 1),
+            b'2' => vfs
+                .mkdir_p(parent)
+                .and_then(|_| match vfs.unlink(&dest) {
+                    Ok(()) | Err(Errno::ENOENT) => vfs.symlink(&e.link, &dest),
+                    Err(err) => Err(err),
+                })
+                .map(|_| sum.symlinks += 1),
+            0 | b'0' | b'1' | b'7' => {
+                // A hard link is a copy of the member it names, already out
+                let linked;
+                let data = if e.kind == b'1' {
+                    linked = vfs.read_file(&cpio::under(base, &e.link));
+                    linked.as_deref().unwrap_or(&[])
+                } else {
+                    e.data
+                };
+                cpio::create_file(vfs, &dest, perm).and_then(|inode| {
+                    cpio::write_all(&inode, data)?;
+                    let mtime = Timespec { sec: e.mtime, nsec: 0 };
+                    let _ = inode.ops.set_times(None, Some(mtime));
+                    sum.files += 1;
+                    sum.bytes += data.len();
+                    Ok(())
+                })
+            }
+            _ => {
+                sum.skipped += 1;
+                Ok(())
+            }
+        };
+        if let Err(err) = res {
+            log::warn!("tar: {}: {:?}", dest, err);
+            sum.skipped += 1;
+        }
+    }
+    Ok(sum)
+}
+
+/// Unpack `archive`, tar or newc cpio, into directory `base`, creating it
+/// if need be. Existing files are overwritten; what can't be created is
+/// counted as skipped.
+pub fn extract(vfs: &VfsContext, archive: &[u8], base: &str) -> Result<Summary, &'static str> {
+    let format = Format::detect(archive).ok_or("not a tar or newc cpio archive")?;
+    vfs.mkdir_p(base).map_err(|_| "can't create the destination directory")?;
+    match format {
+        Format::Ustar => extract_tar(vfs, archive, base),
+        Format::Newc => cpio::unpack(vfs, archive, base),
+    }
+}
+
+// ─── Creating ────────────────────────────────────────────────────────────────
+
+/// `path` and, for a directory, everything under it, as members named from
+/// `name` down. Symlinks are stored, not followed; devices, FIFOs and
+/// sockets are left out. `above` holds the directories on the way down,
+/// so a bind mount of one below itself isn't walked into.
+fn collect(
+    vfs: &VfsContext,
+    path: &str,
+    name: String,
+    above: &mut Vec<u64>,
+    out: &mut Vec<Member>,
+) -> Result<(), Errno> {
+    let st = vfs.stat_at(None, path, false)?;
+    let data = match st.kind {
+        FileType::Regular => vfs.read_file(path)?,
+        FileType::Symlink => vfs.readlink(path)?.into_bytes(),
+        FileType::Directory => Vec::new(),
+        _ => return Ok(()),
+    };
+    let dir = st.kind == FileType::Directory;
+    out.push(Member {
+        name: name.clone(),
+        kind: st.kind,
+        mode: st.mode,
+        uid: st.uid,
+        gid: st.gid,
+        mtime: st.mtime.sec,
+        data,
+    });
+    if !dir || above.contains(&st.ino) {
+        return Ok(());
+    }
+    let mut entries = vfs.readdir_all(path)?;
+    entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    above.push(st.ino);
+    for e in entries.iter().filter(|e| !path::is_dot(&e.name)) {
+        let child = alloc::format!("{}/{}", path.trim_end_matches('/'), e.name);
+        let child_name = alloc::format!("{}/{}", name.trim_end_matches('/'), e.name);
+        collect(vfs, &child, child_name, above, out)?;
+    }
+    above.pop();
+    Ok(())
+}
+
+/// Write `value` in octal into `field`, zero-padded and NUL-terminated.
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = alloc::format!("{:0width$o}", value, width = field.len() - 1);
+    let digits = &digits.as_bytes()[digits.len() - (field.len() - 1)..];
+    field[..digits.len()].copy_from_slice(digits);
+    field[field.len() - 1] = 0;
+}
+
+/// A header for `m`, or with None for a long-name entry.
+fn tar_header(
+    name: &str,
+    kind: u8,
+    mode: u32,
+    m: Option<&Member>,
+    size: usize,
+    link: &str,
+) -> [u8; BLOCK] {
+    let mut hdr = [0u8; BLOCK];
+    let n = name.len().min(NAME_LEN);
+    hdr[..n].copy_from_slice(&name.as_bytes()[..n]);
+    put_octal(&mut hdr[100..108], mode as u64);
+    put_octal(&mut hdr[108..116], m.map_or(0, |m| m.uid) as u64);
+    put_octal(&mut hdr[116..124], m.map_or(0, |m| m.gid) as u64);
+    put_octal(&mut hdr[124..136], size as u64);
+    put_octal(&mut hdr[136..148], m.map_or(0, |m| m.mtime.max(0)) as u64);
+    hdr[156] = kind;
+    let l = link.len().min(NAME_LEN);
+    hdr[157..157 + l].copy_from_slice(&link.as_bytes()[..l]);
+    hdr[257..263].copy_from_slice(b"ustar\0");
+    hdr[263..265].copy_from_slice(b"00");
+    let sum = checksum(&hdr);
+    // Six digits, NUL, space
+    put_octal(&mut hdr[148..155], sum);
+    hdr[155] = b' ';
+    hdr
+}
+
+/// Append `data` padded to whole blocks.
+fn put_data(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(data);
+    out.resize(out.len().next_multiple_of(BLOCK), 0);
+}
+
+/// A ustar archive of `members`, with the two end blocks.
+pub fn pack_tar(members: &[Member]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for m in members {
+        let (kind, link) = match m.kind {
+            FileType::Directory => (b'5', ""),
+            FileType::Symlink => (b'2', core::str::from_utf8(&m.data).unwrap_or("")),
+            _ => (b'0', ""),
+        };
+        // Directories end in '/' in tar
+        let name = if kind == b'5' && !m.name.ends_with('/') {
+            alloc::format!("{}/", m.name)
+        } else {
+            m.name.clone()
+        };
+        for (long, flag) in [(name.as_str(), b'L'), (link, b'K')] {
+            if long.len() > NAME_LEN {
+                let mut data = Vec::from(long.as_bytes());
+                data.push(0);
+                out.extend_from_slice(&tar_header(GNU_LONGNAME, flag, 0, None, data.len(), ""));
+                put_data(&mut out, &data);
+            }
+        }
+        let data: &[u8] = if kind == b'0' { &m.data } else { &[] };
+        let hdr = tar_header(&name, kind, m.mode & 0o7777, Some(m), data.len(), link);
+        out.extend_from_slice(&hdr);
+        put_data(&mut out, data);
+    }
+    out.resize(out.len() + 2 * BLOCK, 0);
+    out
+}
+
+/// An archive of `paths` in `format`, and what went into it. Members are
+/// named as the paths were given, less any leading '/', as tar does.
+pub fn create(
+    vfs: &VfsContext,
+    paths: &[&str],
+    format: Format,
+) -> Result<(Vec<u8>, Summary), Errno> {
+    let mut members = Vec::new();
+    for &p in paths {
+        let name = path::normalize(p.trim_start_matches('/'));
+        collect(vfs, p, name, &mut Vec::new(), &mut members)?;
+    }
+    let mut sum = Summary::default();
+    for m in &members {
+        match m.kind {
+            FileType::Directory => sum.dirs += 1,
+            FileType::Symlink => sum.symlinks += 1,
+            _ => {
+                sum.files += 1;
+                sum.bytes += m.data.len();
+            }
+        }
+    }
+    let data = match format {
+        Format::Ustar => pack_tar(&members),
+        Format::Newc => cpio::pack(&members),
+    };
+    Ok((data, sum))
+}
@@ -0,0 +1,327 @@
+/// newc ("070701"/"070702") CPIO archives, as produced by
+/// `find . | cpio -o -H newc`. Used to unpack the initrd into ramfs, and
+/// by `tar` (see archive.rs) for .cpio files.
+use super::archive::Member;
+use super::mount::VfsContext;
+use super::path;
+use super::vfs::{Errno, FileType, Inode};
+use crate::arch::x86_64::limine::{self, LimineFile};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+pub struct Entry<'a> {
+    pub name: &'a str,
+    pub ino: u32,
+    pub mode: u32,
+    pub nlink: u32,
+    pub dev: (u32, u32),
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Summary {
+    pub dirs: usize,
+    pub files: usize,
+    pub symlinks: usize,
+    pub skipped: usize,
+    pub bytes: usize,
+}
+
+fn hex8(field: &[u8]) -> Option<u32> {
+    u32::from_str_radix(core::str::from_utf8(field).ok()?, 16).ok()
+}
+
+fn align4(v: usize) -> usize {
+    (v + 3) & !3
+}
+
+/// Iterate over archive members up to the trailer.
+pub struct Reader<'a> {
+    archive: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(archive: &'a [u8]) -> Self {
+        Self { archive, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<Entry<'a>, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let a = self.archive;
+        let off = self.offset;
+        if off + HEADER_SIZE > a.len() {
+            return None;
+        }
+        let hdr = &a[off..off + HEADER_SIZE];
+        if &hdr[..6] != b"070701" && &hdr[..6] != b"070702" {
+            self.offset = a.len();
+            return Some(Err("bad cpio magic"));
+        }
+
+        // Fields after the magic: 13 × 8 hex digits
+        let field = |i: usize| hex8(&hdr[6 + i * 8..14 + i * 8]);
+        let parsed = (|| {
+            Some((
+                field(0)?,  // ino
+                field(1)?,  // mode
+                field(4)?,  // nlink
+                field(6)?,  // filesize
+                field(7)?,  // devmajor
+                field(8)?,  // devminor
+                field(11)?, // namesize
+            ))
+        })();
+        let Some((ino, mode, nlink, filesize, devmajor, devminor, namesize)) = parsed else {
+            self.offset = a.len();
+            return Some(Err("bad cpio header"));
+        };
+
+        let name_start = off + HEADER_SIZE;
+        let name_end = name_start + namesize as usize;
+        let data_start = align4(name_end);
+        let data_end = data_start + filesize as usize;
+        if namesize == 0 || data_end > a.len() {
+            self.offset = a.len();
+            return Some(Err("truncated cpio archive"));
+        }
+
+        // namesize includes the terminating NUL
+        let name = core::str::from_utf8(&a[name_start..name_end - 1]).unwrap_or("");
+        if name == TRAILER {
+            self.offset = a.len();
+            return None;
+        }
+        self.offset = align4(data_end);
+
+        Some(Ok(Entry {
+            name,
+            ino,
+            mode,
+            nlink,
+            dev: (devmajor, devminor),
+            data: &a[data_start..data_end],
+        }))
+    }
+}
+
+// ─── Unpack ──────────────────────────────────────────────────────────────────
+
+/// Where member `name` goes under `base`: "./bin/sh" under "/" is
+/// "/bin/sh", "." is `base` itself. Nothing gets above `base`.
+pub(super) fn under(base: &str, name: &str) -> String {
+    let name = path::normalize(&alloc::format!("/{}", name));
+    match (base.trim_end_matches('/'), name.as_str()) {
+        ("", _) => name,
+        (base, "/") => String::from(base),
+        (base, name) => alloc::format!("{}{}", base, name),
+    }
+}
+
+/// Directory `dir` must exist; create it with `mode` if it doesn't.
+pub(super) fn ensure_dir(vfs: &VfsContext, dir: &str, mode: u32) -> Result<(), Errno> {
+    match vfs.resolve(dir) {
+        Ok(i) if i.is_dir() => Ok(()),
+        Ok(_) => Err(Errno::ENOTDIR),
+        Err(Errno::ENOENT) => {
+            let (parent, name) = path::split_last(dir);
+            vfs.mkdir_p(parent)?;
+            vfs.resolve(parent)?.ops.mkdir(name, mode)?;
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Create (or truncate) a regular file keeping the archive's permission bits.
+pub(super) fn create_file(vfs: &VfsContext, file: &str, mode: u32) -> Result<Arc<Inode>, Errno> {
+    let (parent, name) = path::split_last(file);
+    vfs.mkdir_p(parent)?;
+    let dir = vfs.resolve(parent)?;
+    match dir.ops.lookup(name) {
+        Ok(existing) => {
+            existing.ops.truncate(0)?;
+            Ok(existing)
+        }
+        Err(Errno::ENOENT) => dir.ops.create(name, mode),
+        Err(e) => Err(e),
+    }
+}
+
+pub(super) fn write_all(inode: &Inode, data: &[u8]) -> Result<(), Errno> {
+    let mut done = 0;
+    while done < data.len() {
+        let n = inode.ops.write(done as u64, &data[done..])?;
+        if n == 0 {
+            return Err(Errno::ENOSPC);
+        }
+        done += n;
+    }
+    Ok(())
+}
+
+/// Unpack `archive` under directory `base`. Existing files are
+/// overwritten, device nodes and FIFOs are skipped.
+pub fn unpack(vfs: &VfsContext, archive: &[u8], base: &str) -> Result<Summary, &'static str> {
+    let mut sum = Summary::default();
+    // newc stores the data of a hard-linked file only with its last link;
+    // earlier links wait here, keyed by (dev, ino).
+    let mut pending: BTreeMap<((u32, u32), u32), Vec<String>> = BTreeMap::new();
+
+    for entry in Reader::new(archive) {
+        let e = entry?;
+        let dest = under(base, e.name);
+        let perm = e.mode & 0o7777;
+
+        let res = match e.mode & S_IFMT {
+            S_IFDIR => ensure_dir(vfs, &dest, perm).map(|_| sum.dirs += 1),
+            S_IFLNK => {
+                let target = core::str::from_utf8(e.data).unwrap_or("");
+                let (parent, _) = path::split_last(&dest);
+                vfs.mkdir_p(parent)
+                    .and_then(|_| vfs.symlink(target, &dest))
+                    .map(|_| sum.symlinks += 1)
+            }
+            S_IFREG => {
+                let links = if e.nlink > 1 {
+                    let mut l = pending.remove(&(e.dev, e.ino)).unwrap_or_default();
+                    if e.data.is_empty() {
+                        l.push(dest.clone());
+                        pending.insert((e.dev, e.ino), l);
+                        continue;
+                    }
+                    l
+                } else {
+                    Vec::new()
+                };
+                links
+                    .iter()
+                    .chain(core::iter::once(&dest))
+                    .try_for_each(|p| {
+                        let inode = create_file(vfs, p, perm)?;
+                        write_all(&inode, e.data)?;
+                        sum.files += 1;
+                        sum.bytes += e.data.len();
+                        Ok(())
+                    })
+            }
+            _ => {
+                sum.skipped += 1;
+                Ok(())
+            }
+        };
+        if let Err(err) = res {
+            log::warn!("cpio: {}: {:?}", dest, err);
+            sum.skipped += 1;
+        }
+    }
+
+    // Hard links whose data never showed up are empty files
+    for p in pending.into_values().flatten() {
+        if create_file(vfs, &p, 0o644).is_ok() {
+            sum.files += 1;
+        }
+    }
+    Ok(sum)
+}
+
+// ─── Pack ────────────────────────────────────────────────────────────────────
+
+/// A newc archive of `members`, numbered from inode 1, and its trailer.
+pub fn pack(members: &[Member]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut put = |ino: usize, mode: u32, m: Option<&Member>, name: &str, data: &[u8]| {
+        let (uid, gid, mtime) = m.map_or((0, 0, 0), |m| (m.uid, m.gid, m.mtime.max(0) as u32));
+        let fields = [
+            ino as u32,
+            mode,
+            uid,
+            gid,
+            1, // nlink
+            mtime,
+            data.len() as u32,
+            0, // devmajor
+            0, // devminor
+            0, // rdevmajor
+            0, // rdevminor
+            name.len() as u32 + 1,
+            0, // check
+        ];
+        out.extend_from_slice(b"070701");
+        for f in fields {
+            out.extend_from_slice(alloc::format!("{:08x}", f).as_bytes());
+        }
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+        out.resize(align4(out.len()), 0);
+        out.extend_from_slice(data);
+        out.resize(align4(out.len()), 0);
+    };
+    for (i, m) in members.iter().enumerate() {
+        let kind = match m.kind {
+            FileType::Directory => S_IFDIR,
+            FileType::Symlink => S_IFLNK,
+            _ => S_IFREG,
+        };
+        put(i + 1, kind | (m.mode & 0o7777), Some(m), &m.name, &m.data);
+    }
+    put(0, 0, None, TRAILER, &[]);
+    out
+}
+
+// ─── initrd ──────────────────────────────────────────────────────────────────
+
+/// Pick the initrd among the Limine modules: the one with cmdline "initrd",
+/// else one whose path looks like a CPIO archive, else the first module
+/// that isn't the boot splash.
+fn find_initrd() -> Option<&'static [u8]> {
+    let modules: Vec<&LimineFile> = limine::modules()
+        .iter()
+        .map(|&m| unsafe { &*m })
+        .collect();
+    for m in &modules {
+        log::info!("limine: module {} ({} bytes) '{}'", m.path(), m.size, m.cmdline());
+    }
+    modules
+        .iter()
+        .find(|m| m.cmdline() == "initrd")
+        .or_else(|| {
+            modules
+                .iter()
+                .find(|m| m.path().ends_with(".cpio") || m.path().ends_with("initrd"))
+        })
+        .or_else(|| modules.iter().find(|m| m.cmdline() != "splash"))
+        .map(|m| m.data())
+}
+
+/// Unpack the boot initrd (if any) into the ramfs root.
+pub fn load_initrd() {
+    let Some(archive) = find_initrd() else {
+        log::info!("initrd: none");
+        return;
+    };
+    match super::with_vfs(|vfs| unpack(vfs, archive, "/")) {
+        Ok(s) => log::info!(
+            "initrd: {} files ({} KiB), {} dirs, {} symlinks, {} skipped",
+            s.files,
+            s.bytes / 1024,
+            s.dirs,
+            s.symlinks,
+            s.skipped
+        ),
+        Err(e) => log::warn!("initrd: {}", e),
+    }
+}
@@ -0,0 +1,226 @@
+/// Character devices: /dev/null, /dev/zero, /dev/console and /dev/tty1…,
+/// /dev/random.
+///
+/// The nodes are plain inodes inserted into the ramfs `/dev` directory, so
+/// they are opened, redirected to and listed like any other file. Network
+/// terminals have no node; `tty_file` opens them for a session's programs.
+use super::mount::VfsContext;
+use super::vfs::{alloc_ino, DirEntry, Errno, File, FileType, Ino, Inode, InodeOps, Stat, Timespec};
+use super::vfs::O_RDWR;
+use crate::drivers::tty::{self, Line, Tty};
+use crate::sync::spinlock::SpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevKind {
+    Null,
+    Zero,
+    Console,
+    /// The terminal of one VT, from 0.
+    Vt(u8),
+    /// A network terminal, from 0.
+    Net(u8),
+    Random,
+}
+
+impl DevKind {
+    fn tty(self) -> Option<&'static Tty> {
+        match self {
+            DevKind::Console => Some(tty::console()),
+            DevKind::Vt(n) => tty::get(n as usize),
+            DevKind::Net(n) => tty::net(n as usize),
+            _ => None,
+        }
+    }
+}
+
+pub struct CharDev {
+    ino: Ino,
+    kind: DevKind,
+}
+
+impl CharDev {
+    pub fn new_inode(kind: DevKind) -> Arc<Inode> {
+        let ops = Arc::new(CharDev {
+            ino: alloc_ino(),
+            kind,
+        });
+        let ino = ops.ino;
+        Inode::new(ino, ops)
+    }
+}
+
+fn not_dir<T>() -> Result<T, Errno> {
+    Err(Errno::ENOTDIR)
+}
+
+/// `tty` opened for reading and writing.
+pub fn tty_file(tty: &Tty) -> Arc<File> {
+    let kind = match tty.line() {
+        Line::Vt(n) => DevKind::Vt(n as u8),
+        Line::Net(n) => DevKind::Net(n as u8),
+    };
+    File::new(CharDev::new_inode(kind), O_RDWR)
+}
+
+// ─── Random ──────────────────────────────────────────────────────────────────
+
+/// xorshift64* state; reseeded from RDRAND (or the TSC) on every read.
+static RNG_STATE: SpinLock<u64> = SpinLock::new(0x9E37_79B9_7F4A_7C15);
+
+fn hw_entropy() -> u64 {
+    use crate::arch::x86_64::io::has_feature_ecx;
+    // CPUID.1:ECX bit 30 — RDRAND
+    if has_feature_ecx(1, 30) {
+        for _ in 0..10 {
+            let val: u64;
+            let ok: u8;
+            unsafe {
+                core::arch::asm!(
+                    "rdrand {v}",
+                    "setc {ok}",
+                    v = out(reg) val,
+                    ok = out(reg_byte) ok,
+                    options(nomem, nostack),
+                );
+            }
+            if ok != 0 {
+                return val;
+            }
+        }
+    }
+    crate::arch::x86_64::timer::rdtsc()
+}
+
+/// Fill `buf` from the kernel's generator, as /dev/urandom does.
+pub fn random_fill(buf: &mut [u8]) {
+    let mut state = RNG_STATE.lock();
+    *state ^= hw_entropy();
+    if *state == 0 {
+        *state = 0x9E37_79B9_7F4A_7C15;
+    }
+    for chunk in buf.chunks_mut(8) {
+        let mut x = *state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        *state = x;
+        let out = x.wrapping_mul(0x2545_F491_4F6C_DD1D).to_le_bytes();
+        chunk.copy_from_slice(&out[..chunk.len()]);
+    }
+}
+
+// ─── InodeOps ────────────────────────────────────────────────────────────────
+
+impl InodeOps for CharDev {
+    fn stat(&self) -> Stat {
+        let mode = match self.kind {
+            DevKind::Console | DevKind::Vt(_) | DevKind::Net(_) => 0o620,
+            _ => 0o666,
+        };
+        let now = Timespec::now();
+        Stat {
+            ino: self.ino,
+            kind: FileType::CharDevice,
+            size: 0,
+            mode,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    fn read(&self, _: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(tty) = self.kind.tty() {
+            return tty.read(buf);
+        }
+        Ok(match self.kind {
+            DevKind::Null => 0,
+            DevKind::Zero => {
+                buf.fill(0);
+                buf.len()
+            }
+            DevKind::Random => {
+                random_fill(buf);
+                buf.len()
+            }
+            DevKind::Console | DevKind::Vt(_) | DevKind::Net(_) => 0,
+        })
+    }
+
+    fn write(&self, _: u64, buf: &[u8]) -> Result<usize, Errno> {
+        if let Some(tty) = self.kind.tty() {
+            tty.write(buf);
+        }
+        // null/zero/random swallow everything
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _: u64) -> Result<(), Errno> {
+        Ok(()) // O_TRUNC on a device is a no-op
+    }
+    fn lookup(&self, _: &str) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn readdir(&self, _: usize) -> Result<Option<DirEntry>, Errno> {
+        not_dir()
+    }
+    fn create(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn mkdir(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn unlink(&self, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn rmdir(&self, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        Err(Errno::EINVAL)
+    }
+    fn rename(&self, _: &str, _: &Arc<Inode>, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn insert_child(&self, _: &str, _: Arc<Inode>) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn ioctl(&self, cmd: u32, arg: u64) -> Result<i64, Errno> {
+        match self.kind.tty() {
+            Some(tty) => tty.ioctl(cmd, arg),
+            None => Err(Errno::ENOTTY),
+        }
+    }
+}
+
+/// Create the standard device nodes in `/dev`.
+pub fn populate(vfs: &VfsContext) -> Result<(), Errno> {
+    let dev = vfs.resolve("/dev")?;
+    let nodes = [
+        ("null", DevKind::Null),
+        ("zero", DevKind::Zero),
+        ("console", DevKind::Console),
+        ("tty", DevKind::Console),
+        ("random", DevKind::Random),
+        ("urandom", DevKind::Random),
+    ];
+    for (name, kind) in nodes {
+        dev.ops.insert_child(name, CharDev::new_inode(kind))?;
+    }
+    for vt in 0..crate::drivers::vga::VT_COUNT {
+        let name = alloc::format!("tty{}", vt + 1);
+        dev.ops.insert_child(&name, CharDev::new_inode(DevKind::Vt(vt as u8)))?;
+    }
+    Ok(())
+}
@@ -1,33 +1,82 @@
-/// Read-only FAT32 filesystem driver.
+/// FAT32 filesystem driver.
 ///
 /// Implements the VFS `Filesystem` / `InodeOps` traits so that `ls`, `cat`,
-/// `stat`, `cd` etc. work transparently on FAT32 partitions.
+/// `stat`, `cd`, `cp`, `write`, `rm` etc. work transparently on FAT32
+/// partitions. Writes go straight to disk: FAT updates are mirrored into
+/// every FAT copy and directory entries are patched in place.
 use super::vfs::{
-    alloc_ino, DirEntry, Errno, FileType, Filesystem, Inode, InodeOps, Stat,
+    alloc_ino, DirEntry, Errno, FileType, Filesystem, Ino, Inode, InodeOps, Stat, Timespec,
 };
-use crate::drivers::ide;
+use crate::drivers::block::BlockDevice;
+use crate::drivers::rtc::DateTime;
+use crate::sync::mutex::Mutex;
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 // ─── FAT32 directory entry attribute bits ────────────────────────────────────
 
 const ATTR_VOLUME_ID: u8 = 0x08;
 const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
 const ATTR_LFN: u8 = 0x0F; // Long File Name marker
 
+const FAT_MASK: u32 = 0x0FFF_FFFF; // top 4 bits of an entry are reserved
+const FAT_EOC: u32 = 0x0FFF_FFFF;  // end-of-chain marker we write
+
+const ENTRY_FREE: u8 = 0xE5;
+const ENTRY_END: u8 = 0x00;
+
+/// 1980-01-01, the earliest date FAT can store.
+const FAT_DATE_EPOCH: u16 = (1 << 5) | 1;
+
+/// NTRes bits: base / extension stored in lower case (Windows NT+).
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+/// Byte offsets of the 13 UTF-16 characters inside an LFN entry.
+const LFN_CHAR_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
 // ─── Shared filesystem context ───────────────────────────────────────────────
 
 struct Fat32Ctx {
-    drive: usize,
+    dev: Arc<dyn BlockDevice>,
     part_lba: u64,   // absolute LBA of partition start
     spc: u64,        // sectors per cluster
     fat_start: u64,  // absolute LBA of FAT region
+    fat_sectors: u64, // sectors per FAT copy
+    num_fats: u64,
     data_start: u64, // absolute LBA of cluster 2
     root_cluster: u32,
+    max_cluster: u32, // highest valid cluster number
+    fsinfo_lba: Option<u64>,
+    fsinfo_stale: AtomicBool,
+    /// Serializes FAT and directory mutation; holds the next-free hint.
+    /// Held across sector I/O, hence a Mutex.
+    meta: Mutex<u32>,
+    /// Inode numbers of the directories seen so far, by first cluster.
+    dir_inos: SpinLock<BTreeMap<u32, Ino>>,
+}
+
+/// Location of a 32-byte directory entry on disk.
+#[derive(Clone, Copy)]
+struct DirentLoc {
+    lba: u64,
+    off: usize,
 }
 
 impl Fat32Ctx {
+    /// A directory keeps its inode number however often it is looked up,
+    /// so a mount on it stays attached. Files get a new one each time.
+    fn dir_ino(&self, cluster: u32) -> Ino {
+        // ".." entries name the root as cluster 0
+        let cluster = if cluster == 0 { self.root_cluster } else { cluster };
+        *self.dir_inos.lock().entry(cluster).or_insert_with(alloc_ino)
+    }
+
     fn cluster_lba(&self, c: u32) -> u64 {
         self.data_start + (c as u64 - 2) * self.spc
     }
@@ -36,15 +85,38 @@ impl Fat32Ctx {
         self.spc as usize * 512
     }
 
+    fn dirent_loc(&self, cluster: u32, index: usize) -> DirentLoc {
+        let byte = index * 32;
+        DirentLoc {
+            lba: self.cluster_lba(cluster) + (byte / 512) as u64,
+            off: byte % 512,
+        }
+    }
+
     fn read_sectors(&self, lba: u64, count: u16, buf: &mut [u8]) -> Result<(), Errno> {
-        ide::read_sectors(self.drive, lba, count, buf).map_err(|_| Errno::EIO)
+        self.dev.read_sectors(lba, count, buf).map_err(|_| Errno::EIO)
+    }
+
+    fn write_sectors(&self, lba: u64, count: u16, buf: &[u8]) -> Result<(), Errno> {
+        self.dev.write_sectors(lba, count, buf).map_err(|_| Errno::EIO)
     }
 
     fn read_cluster(&self, c: u32, buf: &mut [u8]) -> Result<(), Errno> {
         self.read_sectors(self.cluster_lba(c), self.spc as u16, buf)
     }
 
-    fn next_cluster(&self, c: u32) -> Result<Option<u32>, Errno> {
+    fn write_cluster(&self, c: u32, buf: &[u8]) -> Result<(), Errno> {
+        self.write_sectors(self.cluster_lba(c), self.spc as u16, buf)
+    }
+
+    fn zero_cluster(&self, c: u32) -> Result<(), Errno> {
+        let zeros = alloc::vec![0u8; self.cluster_bytes()];
+        self.write_cluster(c, &zeros)
+    }
+
+    // ── FAT access ────────────────────────────────────────────────────────────
+
+    fn fat_entry(&self, c: u32) -> Result<u32, Errno> {
         let byte_off = c as u64 * 4;
         let sec = self.fat_start + byte_off / 512;
         let off = (byte_off % 512) as usize;
@@ -52,20 +124,96 @@ impl Fat32Ctx {
         let mut buf = [0u8; 512];
         self.read_sectors(sec, 1, &mut buf)?;
 
-        let entry = u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
-            & 0x0FFF_FFFF;
+        Ok(u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]]) & FAT_MASK)
+    }
 
-        if entry < 2 || entry >= 0x0FFF_FFF8 {
+    fn next_cluster(&self, c: u32) -> Result<Option<u32>, Errno> {
+        let entry = self.fat_entry(c)?;
+        if entry < 2 || entry >= 0x0FFF_FFF8 || entry > self.max_cluster {
             Ok(None)
         } else {
             Ok(Some(entry))
         }
     }
 
+    /// Set the FAT entry for `c` in every FAT copy. Caller holds `meta`.
+    fn set_fat_entry(&self, c: u32, val: u32) -> Result<(), Errno> {
+        let byte_off = c as u64 * 4;
+        let rel = byte_off / 512;
+        let off = (byte_off % 512) as usize;
+
+        let mut buf = [0u8; 512];
+        self.read_sectors(self.fat_start + rel, 1, &mut buf)?;
+        let old = u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]]);
+        let new = (old & !FAT_MASK) | (val & FAT_MASK);
+        buf[off..off + 4].copy_from_slice(&new.to_le_bytes());
+
+        for i in 0..self.num_fats {
+            self.write_sectors(self.fat_start + i * self.fat_sectors + rel, 1, &buf)?;
+        }
+        Ok(())
+    }
+
+    /// Find a free cluster at or after `hint` and mark it end-of-chain.
+    /// Its data is left as is. Caller holds `meta` and passes its value.
+    fn alloc_cluster(&self, hint: &mut u32) -> Result<u32, Errno> {
+        self.invalidate_fsinfo()?;
+
+        let mut c = if (2..=self.max_cluster).contains(hint) { *hint } else { 2 };
+        let mut buf = [0u8; 512];
+        let mut cached = u64::MAX;
+
+        for _ in 2..=self.max_cluster {
+            let byte_off = c as u64 * 4;
+            let sec = self.fat_start + byte_off / 512;
+            if sec != cached {
+                self.read_sectors(sec, 1, &mut buf)?;
+                cached = sec;
+            }
+            let off = (byte_off % 512) as usize;
+            let entry = u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+                & FAT_MASK;
+
+            let next = if c >= self.max_cluster { 2 } else { c + 1 };
+            if entry == 0 {
+                self.set_fat_entry(c, FAT_EOC)?;
+                *hint = next;
+                return Ok(c);
+            }
+            c = next;
+        }
+        Err(Errno::ENOSPC)
+    }
+
+    /// Return `clusters` to the free pool. Caller holds `meta`.
+    fn free_clusters(&self, clusters: &[u32]) -> Result<(), Errno> {
+        self.invalidate_fsinfo()?;
+        for &c in clusters {
+            self.set_fat_entry(c, 0)?;
+        }
+        Ok(())
+    }
+
+    /// The FSInfo free-cluster count goes stale as soon as we touch the FAT;
+    /// mark it "unknown" (0xFFFFFFFF) once so other OSes recount.
+    fn invalidate_fsinfo(&self) -> Result<(), Errno> {
+        let Some(lba) = self.fsinfo_lba else { return Ok(()) };
+        if self.fsinfo_stale.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut buf = [0u8; 512];
+        self.read_sectors(lba, 1, &mut buf)?;
+        if buf[0..4] != 0x4161_5252u32.to_le_bytes() || buf[484..488] != 0x6141_7272u32.to_le_bytes() {
+            return Ok(());
+        }
+        buf[488..492].copy_from_slice(&u32::MAX.to_le_bytes());
+        self.write_sectors(lba, 1, &buf)
+    }
+
     fn cluster_chain(&self, start: u32) -> Result<Vec<u32>, Errno> {
         let mut chain = Vec::new();
         let mut c = start;
-        while c >= 2 {
+        while c >= 2 && c <= self.max_cluster {
             chain.push(c);
             match self.next_cluster(c)? {
                 Some(next) => c = next,
@@ -78,15 +226,51 @@ impl Fat32Ctx {
         }
         Ok(chain)
     }
+
+    // ── Directory entry access ────────────────────────────────────────────────
+
+    fn patch_dirent(&self, loc: DirentLoc, f: impl FnOnce(&mut [u8])) -> Result<(), Errno> {
+        let mut buf = [0u8; 512];
+        self.read_sectors(loc.lba, 1, &mut buf)?;
+        f(&mut buf[loc.off..loc.off + 32]);
+        self.write_sectors(loc.lba, 1, &buf)
+    }
+
+    /// New first cluster and size after a write, which happened at `mtime`.
+    fn update_dirent(
+        &self,
+        loc: DirentLoc,
+        cluster: u32,
+        size: u32,
+        mtime: Timespec,
+    ) -> Result<(), Errno> {
+        self.patch_dirent(loc, |raw| {
+            set_entry_cluster(raw, cluster);
+            set_entry_time(raw, 22, mtime, true);
+            raw[28..32].copy_from_slice(&size.to_le_bytes());
+        })
+    }
 }
 
 // ─── Directory entry parsing ──────────────────────────────────────────────────
 
 struct FatEntry {
     name: String,
+    short: [u8; 11],
     first_cluster: u32,
     file_size: u32,
     is_dir: bool,
+    mtime: Timespec,
+    /// Only the day is kept.
+    atime: Timespec,
+    /// LFN slots followed by the short entry itself (always last).
+    slots: Vec<DirentLoc>,
+}
+
+impl FatEntry {
+    fn short_loc(&self) -> DirentLoc {
+        self.slots[self.slots.len() - 1]
+    }
 }
 
 fn parse_83_name(raw: &[u8]) -> String {
@@ -116,27 +300,65 @@ fn parse_83_name(raw: &[u8]) -> String {
 
 fn lfn_chars(entry: &[u8]) -> [u16; 13] {
     let mut ch = [0u16; 13];
-    let pairs: &[(usize, usize)] = &[
-        (1, 5),   // bytes 1-10:  chars 0-4
-        (14, 6),  // bytes 14-23: chars 5-10
-        (28, 2),  // bytes 28-31: chars 11-12
-    ];
-    let mut idx = 0;
-    for &(start, count) in pairs {
-        for k in 0..count {
-            ch[idx] = u16::from_le_bytes([entry[start + k * 2], entry[start + k * 2 + 1]]);
-            idx += 1;
-        }
+    for (i, &pos) in LFN_CHAR_OFFSETS.iter().enumerate() {
+        ch[i] = u16::from_le_bytes([entry[pos], entry[pos + 1]]);
     }
     ch
 }
 
+/// FAT times are local time, taken as UTC here like the RTC's. A date
+/// packs years since 1980, month and day; a time hours, minutes and
+/// two-second units.
+fn fat_time(date: u16, time: u16) -> Timespec {
+    if date == 0 {
+        return Timespec::default();
+    }
+    let dt = DateTime {
+        year: 1980 + (date >> 9) as u32,
+        month: (date >> 5 & 0xF) as u8,
+        day: (date & 0x1F) as u8,
+        hour: (time >> 11) as u8,
+        minute: (time >> 5 & 0x3F) as u8,
+        second: (time & 0x1F) as u8 * 2,
+    };
+    Timespec { sec: dt.to_unix() as i64, nsec: 0 }
+}
+
+/// (date, time) for `t`; before 1980 is 1980-01-01.
+fn fat_stamp(t: Timespec) -> (u16, u16) {
+    let dt = DateTime::from_unix(t.sec.max(0) as u64);
+    if dt.year < 1980 {
+        return (FAT_DATE_EPOCH, 0);
+    }
+    let date = ((dt.year.min(2107) - 1980) << 9) as u16 | (dt.month as u16) << 5 | dt.day as u16;
+    let time = (dt.hour as u16) << 11 | (dt.minute as u16) << 5 | (dt.second / 2) as u16;
+    (date, time)
+}
+
+/// Store `t` in the entry: the time at `at`, its date right after, or
+/// only the date there (the access date has no time) unless `with_time`.
+fn set_entry_time(raw: &mut [u8], at: usize, t: Timespec, with_time: bool) {
+    let (date, time) = fat_stamp(t);
+    if with_time {
+        raw[at..at + 2].copy_from_slice(&time.to_le_bytes());
+        raw[at + 2..at + 4].copy_from_slice(&date.to_le_bytes());
+    } else {
+        raw[at..at + 2].copy_from_slice(&date.to_le_bytes());
+    }
+}
+
+fn set_entry_cluster(raw: &mut [u8], cluster: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
 fn read_dir_entries(ctx: &Fat32Ctx, start_cluster: u32) -> Result<Vec<FatEntry>, Errno> {
     let chain = ctx.cluster_chain(start_cluster)?;
     let cs = ctx.cluster_bytes();
     let mut cluster_buf = alloc::vec![0u8; cs];
     let mut entries = Vec::new();
     let mut lfn_chunks: Vec<[u16; 13]> = Vec::new();
+    let mut lfn_slots: Vec<DirentLoc> = Vec::new();
 
     'outer: for &cluster in &chain {
         ctx.read_cluster(cluster, &mut cluster_buf)?;
@@ -146,11 +368,12 @@ fn read_dir_entries(ctx: &Fat32Ctx, start_cluster: u32) -> Result<Vec<FatEntry>,
             let raw = &cluster_buf[e * 32..(e + 1) * 32];
             let first = raw[0];
 
-            if first == 0x00 {
+            if first == ENTRY_END {
                 break 'outer; // end of directory
             }
-            if first == 0xE5 {
+            if first == ENTRY_FREE {
                 lfn_chunks.clear(); // deleted
+                lfn_slots.clear();
                 continue;
             }
 
@@ -158,18 +381,21 @@ fn read_dir_entries(ctx: &Fat32Ctx, start_cluster: u32) -> Result<Vec<FatEntry>,
 
             if attr == ATTR_LFN {
                 lfn_chunks.push(lfn_chars(raw));
+                lfn_slots.push(ctx.dirent_loc(cluster, e));
                 continue;
             }
 
             // Skip volume labels
             if attr & ATTR_VOLUME_ID != 0 && attr & ATTR_DIRECTORY == 0 {
                 lfn_chunks.clear();
+                lfn_slots.clear();
                 continue;
             }
 
             // Skip . and ..
             if raw[0] == b'.' {
                 lfn_chunks.clear();
+                lfn_slots.clear();
                 continue;
             }
 
@@ -190,25 +416,283 @@ fn read_dir_entries(ctx: &Fat32Ctx, start_cluster: u32) -> Result<Vec<FatEntry>,
                 parse_83_name(raw)
             };
 
+            let mut short = [0u8; 11];
+            short.copy_from_slice(&raw[0..11]);
             let cluster_hi = u16::from_le_bytes([raw[20], raw[21]]) as u32;
             let cluster_lo = u16::from_le_bytes([raw[26], raw[27]]) as u32;
             let first_cluster = (cluster_hi << 16) | cluster_lo;
             let file_size = u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]);
             let is_dir = attr & ATTR_DIRECTORY != 0;
+            let le16 = |at: usize| u16::from_le_bytes([raw[at], raw[at + 1]]);
+            let mtime = fat_time(le16(24), le16(22));
+            let atime = fat_time(le16(18), 0);
+
+            let mut slots = core::mem::take(&mut lfn_slots);
+            slots.push(ctx.dirent_loc(cluster, e));
 
-            entries.push(FatEntry { name, first_cluster, file_size, is_dir });
+            entries.push(FatEntry {
+                name,
+                short,
+                first_cluster,
+                file_size,
+                is_dir,
+                mtime,
+                atime,
+                slots,
+            });
         }
     }
 
     Ok(entries)
 }
 
+// ─── Directory entry creation ─────────────────────────────────────────────────
+
+fn valid_short_char(b: u8) -> bool {
+    b.is_ascii_uppercase()
+        || b.is_ascii_digit()
+        || b"!#$%&'()-@^_`{}~".contains(&b)
+}
+
+fn check_name(name: &str) -> Result<(), Errno> {
+    if name.is_empty() || name == "." || name == ".." || name.encode_utf16().count() > 255 {
+        return Err(Errno::EINVAL);
+    }
+    if name.chars().any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c)) {
+        return Err(Errno::EINVAL);
+    }
+    Ok(())
+}
+
+/// `name` as an 8.3 entry, if it can be stored without an LFN. The flag is
+/// set when the name is all lower case (recorded in the NTRes byte).
+fn exact_short_name(name: &str) -> Option<([u8; 11], bool)> {
+    let (base, ext) = match name.rfind('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || (ext.is_empty() && name.ends_with('.')) {
+        return None;
+    }
+    let has_upper = name.bytes().any(|b| b.is_ascii_uppercase());
+    let has_lower = name.bytes().any(|b| b.is_ascii_lowercase());
+    if has_upper && has_lower {
+        return None; // mixed case needs an LFN to round-trip
+    }
+
+    let mut short = [b' '; 11];
+    for (dst, b) in short[..8].iter_mut().zip(base.bytes()) {
+        *dst = b.to_ascii_uppercase();
+    }
+    for (dst, b) in short[8..].iter_mut().zip(ext.bytes()) {
+        *dst = b.to_ascii_uppercase();
+    }
+    let mut used = short[..base.len()].iter().chain(&short[8..8 + ext.len()]);
+    if !used.all(|&b| valid_short_char(b)) {
+        return None;
+    }
+    Some((short, has_lower))
+}
+
+/// Generate a unique `BASIS~N.EXT` alias for a long name.
+fn generate_short_name(name: &str, existing: &[FatEntry]) -> Result<[u8; 11], Errno> {
+    let squash = |s: &str, max: usize| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| {
+                let b = if c.is_ascii() { c.to_ascii_uppercase() as u8 } else { b'_' };
+                if valid_short_char(b) { b } else { b'_' }
+            })
+            .take(max)
+            .collect()
+    };
+
+    let trimmed = name.trim_start_matches(['.', ' ']);
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(i) => (squash(&trimmed[..i], 8), squash(&trimmed[i + 1..], 3)),
+        None => (squash(trimmed, 8), Vec::new()),
+    };
+    let base = if base.is_empty() { alloc::vec![b'_'] } else { base };
+
+    for n in 1..1_000_000u32 {
+        let tail = alloc::format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if !existing.iter().any(|e| e.short == short) {
+            return Ok(short);
+        }
+    }
+    Err(Errno::EEXIST)
+}
+
+fn lfn_checksum(short: &[u8; 11]) -> u8 {
+    short.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+}
+
+/// LFN entries for `name`, in on-disk order (highest sequence number first).
+fn lfn_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; 32]> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(13);
+    let sum = lfn_checksum(short);
+    let mut out = Vec::with_capacity(count);
+
+    for seq in (1..=count).rev() {
+        let mut raw = [0u8; 32];
+        raw[0] = seq as u8 | if seq == count { 0x40 } else { 0 };
+        raw[11] = ATTR_LFN;
+        raw[13] = sum;
+        let base = (seq - 1) * 13;
+        for (k, &pos) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            let ch = match units.get(base + k) {
+                Some(&c) => c,
+                None if base + k == units.len() => 0x0000, // terminator
+                None => 0xFFFF,                            // padding
+            };
+            raw[pos..pos + 2].copy_from_slice(&ch.to_le_bytes());
+        }
+        out.push(raw);
+    }
+    out
+}
+
+fn short_entry(short: &[u8; 11], attr: u8, cluster: u32) -> [u8; 32] {
+    let mut raw = [0u8; 32];
+    raw[0..11].copy_from_slice(short);
+    raw[11] = attr;
+    let now = Timespec::now();
+    set_entry_time(&mut raw, 14, now, true); // creation
+    set_entry_time(&mut raw, 18, now, false); // last access
+    set_entry_time(&mut raw, 22, now, true); // last write
+    set_entry_cluster(&mut raw, cluster);
+    raw
+}
+
+/// Find `n` consecutive free slots in a directory, growing it by zeroed
+/// clusters if needed. Caller holds `meta`.
+fn find_free_slots(
+    ctx: &Fat32Ctx,
+    dir_cluster: u32,
+    n: usize,
+    hint: &mut u32,
+) -> Result<Vec<DirentLoc>, Errno> {
+    let chain = ctx.cluster_chain(dir_cluster)?;
+    let cs = ctx.cluster_bytes();
+    let per_cluster = cs / 32;
+    let mut cluster_buf = alloc::vec![0u8; cs];
+    let mut run = Vec::with_capacity(n);
+
+    for &cluster in &chain {
+        ctx.read_cluster(cluster, &mut cluster_buf)?;
+        for e in 0..per_cluster {
+            let first = cluster_buf[e * 32];
+            if first == ENTRY_END || first == ENTRY_FREE {
+                run.push(ctx.dirent_loc(cluster, e));
+                if run.len() == n {
+                    return Ok(run);
+                }
+            } else {
+                run.clear();
+            }
+        }
+    }
+
+    let mut last = *chain.last().ok_or(Errno::EIO)?;
+    while run.len() < n {
+        let c = ctx.alloc_cluster(hint)?;
+        ctx.zero_cluster(c)?;
+        ctx.set_fat_entry(last, c)?;
+        last = c;
+        for e in 0..per_cluster {
+            run.push(ctx.dirent_loc(c, e));
+            if run.len() == n {
+                break;
+            }
+        }
+    }
+    Ok(run)
+}
+
 // ─── Directory inode ─────────────────────────────────────────────────────────
 
 struct Fat32DirInode {
     ctx: Arc<Fat32Ctx>,
     cluster: u32,
     ino: u64,
+    /// From the entry when it was looked up; the root has none.
+    mtime: Timespec,
+    atime: Timespec,
+}
+
+impl Fat32DirInode {
+    fn find(&self, name: &str) -> Result<FatEntry, Errno> {
+        let name_low = name.to_ascii_lowercase();
+        read_dir_entries(&self.ctx, self.cluster)?
+            .into_iter()
+            .find(|e| e.name.to_ascii_lowercase() == name_low)
+            .ok_or(Errno::ENOENT)
+    }
+
+    /// Write a new entry (plus LFN slots when needed) pointing at `cluster`.
+    fn add_entry(&self, name: &str, attr: u8, cluster: u32) -> Result<FatEntry, Errno> {
+        check_name(name)?;
+        let ctx = &self.ctx;
+        let mut hint = ctx.meta.lock();
+
+        let existing = read_dir_entries(ctx, self.cluster)?;
+        let name_low = name.to_ascii_lowercase();
+        if existing.iter().any(|e| e.name.to_ascii_lowercase() == name_low) {
+            return Err(Errno::EEXIST);
+        }
+
+        let mut raws = Vec::new();
+        let short = match exact_short_name(name) {
+            Some((short, lower)) if !existing.iter().any(|e| e.short == short) => {
+                let mut raw = short_entry(&short, attr, cluster);
+                if lower {
+                    raw[12] = NT_LOWER_BASE | NT_LOWER_EXT;
+                }
+                raws.push(raw);
+                short
+            }
+            _ => {
+                let short = generate_short_name(name, &existing)?;
+                raws = lfn_entries(name, &short);
+                raws.push(short_entry(&short, attr, cluster));
+                short
+            }
+        };
+
+        let slots = find_free_slots(ctx, self.cluster, raws.len(), &mut hint)?;
+        for (&loc, raw) in slots.iter().zip(&raws) {
+            ctx.patch_dirent(loc, |dst| dst.copy_from_slice(raw))?;
+        }
+
+        let now = Timespec::now();
+        Ok(FatEntry {
+            name: String::from(name),
+            short,
+            first_cluster: cluster,
+            file_size: 0,
+            is_dir: attr & ATTR_DIRECTORY != 0,
+            mtime: now,
+            atime: now,
+            slots,
+        })
+    }
+
+    /// Free the entry's clusters and mark all of its slots deleted.
+    fn remove_entry(&self, e: &FatEntry) -> Result<(), Errno> {
+        let ctx = &self.ctx;
+        let _meta = ctx.meta.lock();
+        let chain = ctx.cluster_chain(e.first_cluster)?;
+        for &loc in &e.slots {
+            ctx.patch_dirent(loc, |raw| raw[0] = ENTRY_FREE)?;
+        }
+        ctx.free_clusters(&chain)
+    }
 }
 
 impl InodeOps for Fat32DirInode {
@@ -217,22 +701,18 @@ impl InodeOps for Fat32DirInode {
             ino: self.ino,
             kind: FileType::Directory,
             size: 0,
-            mode: 0o555,
+            mode: 0o755,
             nlink: 2,
             uid: 0,
             gid: 0,
+            atime: self.atime,
+            mtime: self.mtime,
+            ctime: self.mtime,
         }
     }
 
     fn lookup(&self, name: &str) -> Result<Arc<Inode>, Errno> {
-        let entries = read_dir_entries(&self.ctx, self.cluster)?;
-        let name_low = name.to_ascii_lowercase();
-        for e in entries {
-            if e.name.to_ascii_lowercase() == name_low {
-                return Ok(make_inode(&self.ctx, &e));
-            }
-        }
-        Err(Errno::ENOENT)
+        self.find(name).map(|e| make_inode(&self.ctx, &e))
     }
 
     fn readdir(&self, offset: usize) -> Result<Option<DirEntry>, Errno> {
@@ -244,33 +724,70 @@ impl InodeOps for Fat32DirInode {
                 FileType::Regular
             };
             DirEntry {
+                ino: if e.is_dir { self.ctx.dir_ino(e.first_cluster) } else { alloc_ino() },
                 name: e.name,
-                ino: alloc_ino(),
                 kind,
             }
         }))
     }
 
-    fn read(&self, _: u64, _: &mut [u8]) -> Result<usize, Errno> {
-        Err(Errno::EISDIR)
+    fn create(&self, name: &str, _mode: u32) -> Result<Arc<Inode>, Errno> {
+        let e = self.add_entry(name, ATTR_ARCHIVE, 0)?;
+        Ok(make_inode(&self.ctx, &e))
     }
-    fn write(&self, _: u64, _: &[u8]) -> Result<usize, Errno> {
-        Err(Errno::ENOTSUP)
+
+    fn mkdir(&self, name: &str, _mode: u32) -> Result<Arc<Inode>, Errno> {
+        check_name(name)?;
+        let ctx = &self.ctx;
+        let cluster = {
+            let mut hint = ctx.meta.lock();
+            let c = ctx.alloc_cluster(&mut hint)?;
+            // "." and ".." — the root directory is referred to as cluster 0
+            let parent = if self.cluster == ctx.root_cluster { 0 } else { self.cluster };
+            let mut buf = alloc::vec![0u8; ctx.cluster_bytes()];
+            buf[0..32].copy_from_slice(&short_entry(b".          ", ATTR_DIRECTORY, c));
+            buf[32..64].copy_from_slice(&short_entry(b"..         ", ATTR_DIRECTORY, parent));
+            ctx.write_cluster(c, &buf)?;
+            c
+        };
+
+        match self.add_entry(name, ATTR_DIRECTORY, cluster) {
+            Ok(e) => Ok(make_inode(ctx, &e)),
+            Err(err) => {
+                let _meta = ctx.meta.lock();
+                ctx.free_clusters(&[cluster])?;
+                Err(err)
+            }
+        }
     }
-    fn truncate(&self, _: u64) -> Result<(), Errno> {
-        Err(Errno::ENOTSUP)
+
+    fn unlink(&self, name: &str) -> Result<(), Errno> {
+        let e = self.find(name)?;
+        if e.is_dir {
+            return Err(Errno::EISDIR);
+        }
+        self.remove_entry(&e)
     }
-    fn create(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
-        Err(Errno::ENOTSUP)
+
+    fn rmdir(&self, name: &str) -> Result<(), Errno> {
+        let e = self.find(name)?;
+        if !e.is_dir {
+            return Err(Errno::ENOTDIR);
+        }
+        if !read_dir_entries(&self.ctx, e.first_cluster)?.is_empty() {
+            return Err(Errno::ENOTEMPTY);
+        }
+        self.remove_entry(&e)
     }
-    fn mkdir(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
-        Err(Errno::ENOTSUP)
+
+    fn read(&self, _: u64, _: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EISDIR)
     }
-    fn unlink(&self, _: &str) -> Result<(), Errno> {
-        Err(Errno::ENOTSUP)
+    fn write(&self, _: u64, _: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EISDIR)
     }
-    fn rmdir(&self, _: &str) -> Result<(), Errno> {
-        Err(Errno::ENOTSUP)
+    fn truncate(&self, _: u64) -> Result<(), Errno> {
+        Err(Errno::EISDIR)
     }
     fn symlink(&self, _: &str, _: &str) -> Result<Arc<Inode>, Errno> {
         Err(Errno::ENOTSUP)
@@ -288,35 +805,116 @@ impl InodeOps for Fat32DirInode {
 
 // ─── File inode ───────────────────────────────────────────────────────────────
 
+struct FileState {
+    cluster: u32, // 0 while the file is empty
+    size: u32,
+    mtime: Timespec,
+    atime: Timespec,
+}
+
 struct Fat32FileInode {
     ctx: Arc<Fat32Ctx>,
-    cluster: u32,
-    size: u32,
+    dirent: DirentLoc, // short entry holding size and first cluster
+    /// Held across cluster reads and writes, like `meta`.
+    state: Mutex<FileState>,
     ino: u64,
 }
 
+impl Fat32FileInode {
+    fn write_locked(&self, st: &mut FileState, offset: u64, buf: &[u8]) -> Result<usize, Errno> {
+        let end = offset + buf.len() as u64;
+        if end > u32::MAX as u64 {
+            return Err(Errno::EFBIG);
+        }
+        let ctx = &self.ctx;
+        let cs = ctx.cluster_bytes() as u64;
+        let mut chain = ctx.cluster_chain(st.cluster)?;
+        let old_len = chain.len();
+        let need = end.div_ceil(cs) as usize;
+
+        if need > chain.len() {
+            let mut hint = ctx.meta.lock();
+            while chain.len() < need {
+                let c = ctx.alloc_cluster(&mut hint)?;
+                match chain.last() {
+                    Some(&last) => ctx.set_fat_entry(last, c)?,
+                    None => st.cluster = c,
+                }
+                chain.push(c);
+            }
+        }
+
+        // Bytes between the old EOF and `offset` must read back as zeros
+        let start = offset.min(st.size as u64);
+        let mut cluster_buf = alloc::vec![0u8; cs as usize];
+
+        for (i, &cluster) in chain.iter().enumerate().take(need).skip((start / cs) as usize) {
+            let cluster_start = i as u64 * cs;
+            let lo = (start.max(cluster_start) - cluster_start) as usize;
+            let hi = (end.min(cluster_start + cs) - cluster_start) as usize;
+            let data_lo = ((offset.max(cluster_start) - cluster_start) as usize).min(hi);
+
+            if lo == 0 && hi == cs as usize {
+                // Fully overwritten, no need to read
+            } else if i < old_len {
+                ctx.read_cluster(cluster, &mut cluster_buf)?;
+            } else {
+                cluster_buf.fill(0);
+            }
+            cluster_buf[lo..data_lo].fill(0);
+            if data_lo < hi {
+                let src = (cluster_start + data_lo as u64 - offset) as usize;
+                cluster_buf[data_lo..hi].copy_from_slice(&buf[src..src + (hi - data_lo)]);
+            }
+            ctx.write_cluster(cluster, &cluster_buf)?;
+        }
+
+        st.size = st.size.max(end as u32);
+        st.mtime = Timespec::now();
+        ctx.update_dirent(self.dirent, st.cluster, st.size, st.mtime)?;
+        Ok(buf.len())
+    }
+}
+
 impl InodeOps for Fat32FileInode {
     fn stat(&self) -> Stat {
+        let st = self.state.lock();
         Stat {
             ino: self.ino,
             kind: FileType::Regular,
-            size: self.size as u64,
-            mode: 0o444,
+            size: st.size as u64,
+            mode: 0o644,
             nlink: 1,
             uid: 0,
             gid: 0,
+            atime: st.atime,
+            mtime: st.mtime,
+            // FAT has no change time; Linux's vfat reports mtime too
+            ctime: st.mtime,
         }
     }
 
+    fn set_times(&self, atime: Option<Timespec>, mtime: Option<Timespec>) -> Result<(), Errno> {
+        let mut st = self.state.lock();
+        st.atime = atime.unwrap_or(st.atime);
+        st.mtime = mtime.unwrap_or(st.mtime);
+        let (atime, mtime) = (st.atime, st.mtime);
+        self.ctx.patch_dirent(self.dirent, |raw| {
+            set_entry_time(raw, 18, atime, false);
+            set_entry_time(raw, 22, mtime, true);
+        })
+    }
+
     fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Errno> {
-        let size = self.size as u64;
+        let st = self.state.lock();
+        let size = st.size as u64;
         if offset >= size || buf.is_empty() {
             return Ok(0);
         }
         let to_read = buf.len().min((size - offset) as usize);
         let ctx = &self.ctx;
         let cs = ctx.cluster_bytes() as u64;
-        let chain = ctx.cluster_chain(self.cluster)?;
+        let chain = ctx.cluster_chain(st.cluster)?;
         let mut cluster_buf = alloc::vec![0u8; cs as usize];
         let mut done = 0usize;
 
@@ -344,12 +942,42 @@ impl InodeOps for Fat32FileInode {
         Ok(done)
     }
 
-    fn write(&self, _: u64, _: &[u8]) -> Result<usize, Errno> {
-        Err(Errno::ENOTSUP)
+    fn write(&self, offset: u64, buf: &[u8]) -> Result<usize, Errno> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut st = self.state.lock();
+        self.write_locked(&mut st, offset, buf)
     }
-    fn truncate(&self, _: u64) -> Result<(), Errno> {
-        Err(Errno::ENOTSUP)
+
+    fn truncate(&self, size: u64) -> Result<(), Errno> {
+        if size > u32::MAX as u64 {
+            return Err(Errno::EFBIG);
+        }
+        let mut st = self.state.lock();
+        if size > st.size as u64 {
+            // Growing: writing the last byte zero-fills everything before it
+            self.write_locked(&mut st, size - 1, &[0])?;
+            return Ok(());
+        }
+
+        let ctx = &self.ctx;
+        let keep = size.div_ceil(ctx.cluster_bytes() as u64) as usize;
+        let chain = ctx.cluster_chain(st.cluster)?;
+        if keep < chain.len() {
+            let _meta = ctx.meta.lock();
+            if keep == 0 {
+                st.cluster = 0;
+            } else {
+                ctx.set_fat_entry(chain[keep - 1], FAT_EOC)?;
+            }
+            ctx.free_clusters(&chain[keep..])?;
+        }
+        st.size = size as u32;
+        st.mtime = Timespec::now();
+        ctx.update_dirent(self.dirent, st.cluster, st.size, st.mtime)
     }
+
     fn lookup(&self, _: &str) -> Result<Arc<Inode>, Errno> {
         Err(Errno::ENOTDIR)
     }
@@ -385,19 +1013,27 @@ impl InodeOps for Fat32FileInode {
 // ─── Helpers ─────────────────────────────────────────────────────────────────
 
 fn make_inode(ctx: &Arc<Fat32Ctx>, e: &FatEntry) -> Arc<Inode> {
-    let ino = alloc_ino();
     if e.is_dir {
+        let ino = ctx.dir_ino(e.first_cluster);
         let ops = Arc::new(Fat32DirInode {
             ctx: Arc::clone(ctx),
             cluster: e.first_cluster,
             ino,
+            mtime: e.mtime,
+            atime: e.atime,
         });
         Inode::new(ino, ops)
     } else {
+        let ino = alloc_ino();
         let ops = Arc::new(Fat32FileInode {
             ctx: Arc::clone(ctx),
-            cluster: e.first_cluster,
-            size: e.file_size,
+            dirent: e.short_loc(),
+            state: Mutex::new(FileState {
+                cluster: e.first_cluster,
+                size: e.file_size,
+                mtime: e.mtime,
+                atime: e.atime,
+            }),
             ino,
         });
         Inode::new(ino, ops)
@@ -422,11 +1058,11 @@ impl Filesystem for Fat32Fs {
 
 // ─── Probe / mount ────────────────────────────────────────────────────────────
 
-/// Try to read a FAT32 BPB at `part_lba` on `drive`.
+/// Try to read a FAT32 BPB at `part_lba` on `dev`.
 /// Returns a mounted `Filesystem` or None if not FAT32.
-pub fn probe(drive: usize, part_lba: u64) -> Option<Arc<dyn Filesystem>> {
+pub fn probe(dev: &Arc<dyn BlockDevice>, part_lba: u64) -> Option<Arc<dyn Filesystem>> {
     let mut sector = [0u8; 512];
-    ide::read_sectors(drive, part_lba, 1, &mut sector).ok()?;
+    dev.read_sectors(part_lba, 1, &mut sector).ok()?;
 
     // Boot sector signature
     if sector[510] != 0x55 || sector[511] != 0xAA {
@@ -441,12 +1077,15 @@ pub fn probe(drive: usize, part_lba: u64) -> Option<Arc<dyn Filesystem>> {
     let spc              = sector[13] as u64;
     let reserved_sectors = u16::from_le_bytes([sector[14], sector[15]]) as u64;
     let num_fats         = sector[16] as u64;
+    let total_16         = u16::from_le_bytes([sector[19], sector[20]]) as u64;
     let fat_size_16      = u16::from_le_bytes([sector[22], sector[23]]) as u64;
+    let total_32         = u32::from_le_bytes([sector[32], sector[33], sector[34], sector[35]]) as u64;
     let fat_size_32      = u32::from_le_bytes([sector[36], sector[37], sector[38], sector[39]]) as u64;
     let root_cluster     = u32::from_le_bytes([sector[44], sector[45], sector[46], sector[47]]);
+    let fsinfo_sector    = u16::from_le_bytes([sector[48], sector[49]]) as u64;
 
     // FAT32 has fat_size_16 == 0 and fat_size_32 > 0
-    if fat_size_16 != 0 || fat_size_32 == 0 || spc == 0 {
+    if fat_size_16 != 0 || fat_size_32 == 0 || spc == 0 || num_fats == 0 {
         return None;
     }
 
@@ -458,43 +1097,64 @@ pub fn probe(drive: usize, part_lba: u64) -> Option<Arc<dyn Filesystem>> {
     let fat_start  = part_lba + reserved_sectors;
     let data_start = fat_start + num_fats * fat_size_32;
 
+    let total_sectors = if total_16 != 0 { total_16 } else { total_32 };
+    let data_sectors = total_sectors.saturating_sub(reserved_sectors + num_fats * fat_size_32);
+    // Clamp to what the FAT can actually describe
+    let clusters = (data_sectors / spc).min(fat_size_32 * 128 - 2);
+    if clusters == 0 {
+        return None;
+    }
+    let max_cluster = (clusters + 1) as u32;
+
+    let fsinfo_lba = (fsinfo_sector != 0 && fsinfo_sector != 0xFFFF)
+        .then_some(part_lba + fsinfo_sector);
+
     log::info!(
-        "FAT32: drive={} part_lba={} spc={} root_cluster={} data_start={}",
-        drive, part_lba, spc, root_cluster, data_start
+        "FAT32: dev={} part_lba={} spc={} root_cluster={} data_start={} clusters={}",
+        dev.name(), part_lba, spc, root_cluster, data_start, clusters
     );
 
     let ctx = Arc::new(Fat32Ctx {
-        drive,
+        dev: Arc::clone(dev),
         part_lba,
         spc,
         fat_start,
+        fat_sectors: fat_size_32,
+        num_fats,
         data_start,
         root_cluster,
+        max_cluster,
+        fsinfo_lba,
+        fsinfo_stale: AtomicBool::new(false),
+        meta: Mutex::new(2),
+        dir_inos: SpinLock::new(BTreeMap::new()),
     });
 
-    let root_ino = alloc_ino();
+    let root_ino = ctx.dir_ino(root_cluster);
     let root_ops = Arc::new(Fat32DirInode {
         ctx: Arc::clone(&ctx),
         cluster: root_cluster,
         ino: root_ino,
+        mtime: Timespec::default(),
+        atime: Timespec::default(),
     });
     let root = Inode::new(root_ino, root_ops);
 
     Some(Arc::new(Fat32Fs { ctx, root }))
 }
 
-/// Probe drive for FAT32: try MBR partitions first, then raw sector 0.
-pub fn probe_drive(drive: usize) -> Option<Arc<dyn Filesystem>> {
+/// Probe a device for FAT32: try MBR partitions first, then raw sector 0.
+pub fn probe_drive(dev: &Arc<dyn BlockDevice>) -> Option<Arc<dyn Filesystem>> {
     // Try MBR partition table
-    if let Some(parts) = super::mbr::read(drive) {
+    if let Some(parts) = super::mbr::read(dev.as_ref()) {
         for part in parts.iter().flatten() {
             if part.is_fat32() {
-                if let Some(fs) = probe(drive, part.lba_start) {
+                if let Some(fs) = probe(dev, part.lba_start) {
                     return Some(fs);
                 }
             }
         }
     }
     // Try raw FAT32 at sector 0
-    probe(drive, 0)
+    probe(dev, 0)
 }
@@ -0,0 +1,469 @@
+/// ISO9660 filesystem driver (read-only) with Rock Ridge extensions.
+///
+/// Reads the primary volume descriptor, walks directory extents and, when
+/// the root directory carries a SUSP "SP" marker, takes names, modes and
+/// symlink targets from the Rock Ridge NM / PX / SL entries (following CE
+/// continuation areas and CL/RE directory relocation). Without Rock Ridge
+/// names are the plain ISO ones, lower-cased and stripped of ";1".
+use super::vfs::{
+    alloc_ino, DirEntry, Errno, FileType, Filesystem, Ino, Inode, InodeOps, Stat, Timespec,
+};
+use crate::drivers::block::{BlockDevice, SECTOR_SIZE};
+use crate::drivers::rtc::DateTime;
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const BLOCK: usize = 2048;
+const SECTORS_PER_BLOCK: u64 = (BLOCK / SECTOR_SIZE) as u64;
+
+/// Volume descriptors start at block 16.
+const VD_START: u64 = 16;
+const VD_PRIMARY: u8 = 1;
+const VD_TERMINATOR: u8 = 255;
+
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_ASSOCIATED: u8 = 0x04;
+
+/// Depth limit for CE continuation chains.
+const MAX_CE: usize = 8;
+
+// ─── Shared filesystem context ───────────────────────────────────────────────
+
+struct IsoCtx {
+    dev: Arc<dyn BlockDevice>,
+    /// Bytes to skip at the start of each system-use area (from "SP");
+    /// None when the volume has no Rock Ridge.
+    susp_skip: Option<usize>,
+    /// Inode numbers of the directories seen so far, by extent.
+    dir_inos: SpinLock<BTreeMap<u32, Ino>>,
+}
+
+impl IsoCtx {
+    /// An inode number for `entry`: for good for a directory, so a mount on
+    /// it stays attached, a new one for anything else.
+    fn ino(&self, entry: &IsoEntry) -> Ino {
+        if entry.kind != FileType::Directory {
+            return alloc_ino();
+        }
+        *self.dir_inos.lock().entry(entry.extent).or_insert_with(alloc_ino)
+    }
+
+    fn read_blocks(&self, block: u64, buf: &mut [u8]) -> Result<(), Errno> {
+        let count = (buf.len() / SECTOR_SIZE) as u16;
+        self.dev
+            .read_sectors(block * SECTORS_PER_BLOCK, count, buf)
+            .map_err(|_| Errno::EIO)
+    }
+
+    /// Fill `buf` from byte `offset` of the extent starting at `block`.
+    fn read_range(&self, block: u32, offset: u64, buf: &mut [u8]) -> Result<(), Errno> {
+        let first = offset / SECTOR_SIZE as u64;
+        let last = (offset + buf.len() as u64).div_ceil(SECTOR_SIZE as u64);
+        let mut tmp = alloc::vec![0u8; ((last - first) as usize) * SECTOR_SIZE];
+        // Chunks stay well below the u16 sector-count limit
+        for (i, chunk) in tmp.chunks_mut(64 * 1024).enumerate() {
+            let lba = block as u64 * SECTORS_PER_BLOCK + first + (i * 128) as u64;
+            self.dev
+                .read_sectors(lba, (chunk.len() / SECTOR_SIZE) as u16, chunk)
+                .map_err(|_| Errno::EIO)?;
+        }
+        let skip = (offset % SECTOR_SIZE as u64) as usize;
+        buf.copy_from_slice(&tmp[skip..skip + buf.len()]);
+        Ok(())
+    }
+}
+
+fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+// ─── Rock Ridge ──────────────────────────────────────────────────────────────
+
+#[derive(Default)]
+struct RockRidge {
+    name: String,
+    mode: Option<u32>,
+    symlink: Option<String>,
+    /// Last SL component had CONTINUE set: no '/' before the next one
+    sl_join: bool,
+    child_link: Option<u32>,
+    relocated: bool,
+}
+
+impl RockRidge {
+    fn parse_sl(&mut self, data: &[u8]) {
+        let target = self.symlink.get_or_insert_with(String::new);
+        let mut off = 0;
+        while off + 2 <= data.len() {
+            let (flags, len) = (data[off], data[off + 1] as usize);
+            let Some(content) = data.get(off + 2..off + 2 + len) else { break };
+            if !target.is_empty() && !self.sl_join && !target.ends_with('/') {
+                target.push('/');
+            }
+            match flags & 0x0E {
+                0x02 => target.push('.'),
+                0x04 => target.push_str(".."),
+                0x08 => target.push('/'),
+                _ => target.push_str(&String::from_utf8_lossy(content)),
+            }
+            self.sl_join = flags & 0x01 != 0;
+            off += 2 + len;
+        }
+    }
+
+    /// Walk one system-use area, then any CE continuation it points to.
+    fn parse(&mut self, ctx: &IsoCtx, area: &[u8], depth: usize) {
+        let mut ce = None;
+        let mut off = 0;
+        while off + 4 <= area.len() {
+            let len = area[off + 2] as usize;
+            if len < 4 || off + len > area.len() {
+                break;
+            }
+            let e = &area[off..off + len];
+            match &e[..2] {
+                b"NM" if len >= 5 => {
+                    // CURRENT / PARENT names carry no text
+                    if e[4] & 0x06 == 0 {
+                        self.name.push_str(&String::from_utf8_lossy(&e[5..]));
+                    }
+                }
+                b"PX" if len >= 8 => self.mode = Some(le32(&e[4..])),
+                b"SL" if len >= 5 => self.parse_sl(&e[5..]),
+                b"CL" if len >= 8 => self.child_link = Some(le32(&e[4..])),
+                b"RE" => self.relocated = true,
+                b"CE" if len >= 28 => ce = Some((le32(&e[4..]), le32(&e[12..]), le32(&e[20..]))),
+                b"ST" => break,
+                _ => {}
+            }
+            off += len;
+        }
+
+        let Some((block, offset, len)) = ce else { return };
+        if depth >= MAX_CE || len == 0 {
+            return;
+        }
+        let mut buf = alloc::vec![0u8; len as usize];
+        if ctx.read_range(block, offset as u64, &mut buf).is_ok() {
+            self.parse(ctx, &buf, depth + 1);
+        }
+    }
+}
+
+// ─── Directory records ───────────────────────────────────────────────────────
+
+#[derive(Clone)]
+struct IsoEntry {
+    name: String,
+    extent: u32,
+    size: u32,
+    kind: FileType,
+    mode: u32,
+    target: Option<String>,
+    /// When the record was written; the only time ISO 9660 keeps.
+    recorded: Timespec,
+}
+
+/// Plain ISO name: drop the ";1" version and a trailing dot, lower-case.
+fn iso_name(raw: &[u8]) -> String {
+    let s = String::from_utf8_lossy(raw);
+    let s = s.split(';').next().unwrap_or("");
+    s.strip_suffix('.').unwrap_or(s).to_ascii_lowercase()
+}
+
+/// A directory record's recording date: years since 1900, month, day,
+/// hour, minute, second, then the zone as a signed count of 15 minutes.
+fn record_time(d: &[u8]) -> Timespec {
+    if d[1] == 0 {
+        return Timespec::default(); // not recorded
+    }
+    let local = DateTime {
+        year: 1900 + d[0] as u32,
+        month: d[1],
+        day: d[2],
+        hour: d[3],
+        minute: d[4],
+        second: d[5],
+    };
+    let zone = d[6] as i8 as i64 * 15 * 60;
+    Timespec { sec: local.to_unix() as i64 - zone, nsec: 0 }
+}
+
+/// System-use area of a directory record (after the padded name).
+fn system_use(rec: &[u8]) -> &[u8] {
+    let name_len = rec[32] as usize;
+    let start = 33 + name_len + (1 - name_len % 2);
+    rec.get(start..).unwrap_or(&[])
+}
+
+fn read_dir(ctx: &IsoCtx, extent: u32, size: u32) -> Result<Vec<IsoEntry>, Errno> {
+    let mut data = alloc::vec![0u8; (size as usize).div_ceil(BLOCK) * BLOCK];
+    ctx.read_blocks(extent as u64, &mut data)?;
+
+    let mut out = Vec::new();
+    let mut off = 0;
+    while off < size as usize {
+        let len = data[off] as usize;
+        if len == 0 {
+            // Records never straddle a block: the rest of this one is padding
+            off = (off / BLOCK + 1) * BLOCK;
+            continue;
+        }
+        if len < 34 || off + len > data.len() {
+            break;
+        }
+        let rec = &data[off..off + len];
+        off += len;
+
+        let name_len = rec[32] as usize;
+        if 33 + name_len > len {
+            break;
+        }
+        let raw = &rec[33..33 + name_len];
+        let flags = rec[25];
+        if raw == [0] || raw == [1] || flags & FLAG_ASSOCIATED != 0 {
+            continue; // ".", "..", associated files
+        }
+
+        let mut rr = RockRidge::default();
+        if let Some(skip) = ctx.susp_skip {
+            rr.parse(ctx, system_use(rec).get(skip..).unwrap_or(&[]), 0);
+        }
+        if rr.relocated {
+            continue; // shown where its CL placeholder lives
+        }
+
+        let mut extent = le32(&rec[2..]);
+        let mut size = le32(&rec[10..]);
+        let mut is_dir = flags & FLAG_DIRECTORY != 0;
+        if let Some(child) = rr.child_link {
+            // Relocated directory: its own "." record has the real size
+            let mut blk = alloc::vec![0u8; BLOCK];
+            ctx.read_blocks(child as u64, &mut blk)?;
+            extent = child;
+            size = le32(&blk[10..]);
+            is_dir = true;
+        }
+
+        let kind = match (&rr.symlink, is_dir) {
+            (Some(_), false) => FileType::Symlink,
+            (_, true) => FileType::Directory,
+            _ => FileType::Regular,
+        };
+        let default_mode = if is_dir { 0o555 } else { 0o444 };
+        out.push(IsoEntry {
+            name: if rr.name.is_empty() { iso_name(raw) } else { rr.name },
+            extent,
+            size,
+            kind,
+            mode: rr.mode.map_or(default_mode, |m| m & 0o7777),
+            target: rr.symlink,
+            recorded: record_time(&rec[18..25]),
+        });
+    }
+    Ok(out)
+}
+
+// ─── Inode ───────────────────────────────────────────────────────────────────
+
+struct IsoInode {
+    ctx: Arc<IsoCtx>,
+    entry: IsoEntry,
+    ino: u64,
+}
+
+fn make_inode(ctx: &Arc<IsoCtx>, entry: IsoEntry) -> Arc<Inode> {
+    let ino = ctx.ino(&entry);
+    Inode::new(ino, Arc::new(IsoInode { ctx: Arc::clone(ctx), entry, ino }))
+}
+
+impl IsoInode {
+    fn dir(&self) -> Result<Vec<IsoEntry>, Errno> {
+        if self.entry.kind != FileType::Directory {
+            return Err(Errno::ENOTDIR);
+        }
+        read_dir(&self.ctx, self.entry.extent, self.entry.size)
+    }
+}
+
+impl InodeOps for IsoInode {
+    fn stat(&self) -> Stat {
+        Stat {
+            ino: self.ino,
+            kind: self.entry.kind,
+            size: self.entry.size as u64,
+            mode: self.entry.mode,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: self.entry.recorded,
+            mtime: self.entry.recorded,
+            ctime: self.entry.recorded,
+        }
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        match self.entry.kind {
+            FileType::Directory => return Err(Errno::EISDIR),
+            FileType::Symlink => return Err(Errno::EINVAL),
+            _ => {}
+        }
+        let size = self.entry.size as u64;
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let n = buf.len().min((size - offset) as usize);
+        self.ctx.read_range(self.entry.extent, offset, &mut buf[..n])?;
+        Ok(n)
+    }
+
+    fn write(&self, _: u64, _: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn truncate(&self, _: u64) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>, Errno> {
+        let entry = self
+            .dir()?
+            .into_iter()
+            .find(|e| e.name == name)
+            .ok_or(Errno::ENOENT)?;
+        Ok(make_inode(&self.ctx, entry))
+    }
+
+    fn readdir(&self, offset: usize) -> Result<Option<DirEntry>, Errno> {
+        Ok(self.dir()?.into_iter().nth(offset).map(|e| DirEntry {
+            ino: self.ctx.ino(&e),
+            name: e.name,
+            kind: e.kind,
+        }))
+    }
+
+    fn create(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        Err(Errno::EROFS)
+    }
+    fn mkdir(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        Err(Errno::EROFS)
+    }
+    fn unlink(&self, _: &str) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+    fn rmdir(&self, _: &str) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<Inode>, Errno> {
+        Err(Errno::EROFS)
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        self.entry.target.clone().ok_or(Errno::EINVAL)
+    }
+    fn rename(&self, _: &str, _: &Arc<Inode>, _: &str) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+    fn insert_child(&self, _: &str, _: Arc<Inode>) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+    fn chmod(&self, _: u32) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+    fn chown(&self, _: u32, _: u32) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+    fn set_times(&self, _: Option<Timespec>, _: Option<Timespec>) -> Result<(), Errno> {
+        Err(Errno::EROFS)
+    }
+}
+
+// ─── Filesystem implementation ────────────────────────────────────────────────
+
+struct IsoFs {
+    root: Arc<Inode>,
+}
+
+impl Filesystem for IsoFs {
+    fn root(&self) -> Arc<Inode> {
+        Arc::clone(&self.root)
+    }
+    fn name(&self) -> &'static str {
+        "iso9660"
+    }
+}
+
+// ─── Probe / mount ────────────────────────────────────────────────────────────
+
+/// Look for an ISO9660 primary volume descriptor on `dev`.
+pub fn probe(dev: &Arc<dyn BlockDevice>) -> Option<Arc<dyn Filesystem>> {
+    let mut vd = alloc::vec![0u8; BLOCK];
+    let mut ctx = IsoCtx {
+        dev: Arc::clone(dev),
+        susp_skip: None,
+        dir_inos: SpinLock::new(BTreeMap::new()),
+    };
+
+    let mut pvd = None;
+    for block in VD_START..VD_START + 32 {
+        ctx.read_blocks(block, &mut vd).ok()?;
+        if &vd[1..6] != b"CD001" {
+            return None;
+        }
+        match vd[0] {
+            VD_PRIMARY => {
+                pvd = Some(vd.clone());
+                break;
+            }
+            VD_TERMINATOR => break,
+            _ => {}
+        }
+    }
+    let pvd = pvd?;
+    if u16::from_le_bytes([pvd[128], pvd[129]]) as usize != BLOCK {
+        log::warn!("ISO9660: {}: unsupported logical block size", dev.name());
+        return None;
+    }
+
+    let root_rec = &pvd[156..190];
+    let root_extent = le32(&root_rec[2..]);
+    let root_size = le32(&root_rec[10..]);
+
+    // Rock Ridge: SUSP "SP" entry at the start of the root's "." record
+    let mut blk = alloc::vec![0u8; BLOCK];
+    ctx.read_blocks(root_extent as u64, &mut blk).ok()?;
+    let dot_len = blk[0] as usize;
+    if dot_len >= 34 {
+        let su = system_use(&blk[..dot_len]);
+        if su.len() >= 7 && &su[..2] == b"SP" && su[4] == 0xBE && su[5] == 0xEF {
+            ctx.susp_skip = Some(su[6] as usize);
+        }
+    }
+
+    let volume = String::from_utf8_lossy(&pvd[40..72]);
+    log::info!(
+        "ISO9660: {} volume '{}' root={} ({} bytes){}",
+        dev.name(),
+        volume.trim_end(),
+        root_extent,
+        root_size,
+        if ctx.susp_skip.is_some() { ", Rock Ridge" } else { "" },
+    );
+
+    let ctx = Arc::new(ctx);
+    let root = make_inode(
+        &ctx,
+        IsoEntry {
+            name: String::new(),
+            extent: root_extent,
+            size: root_size,
+            kind: FileType::Directory,
+            mode: 0o555,
+            target: None,
+            recorded: record_time(&root_rec[18..25]),
+        },
+    );
+    Some(Arc::new(IsoFs { root }))
+}
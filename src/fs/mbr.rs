@@ -1,7 +1,7 @@
 /// MBR partition table reader.
 /// Reads the first sector of a drive and parses up to 4 primary partition entries.
 
-use crate::drivers::ide;
+use crate::drivers::block::BlockDevice;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Partition {
@@ -15,13 +15,33 @@ impl Partition {
     pub fn is_fat32(&self) -> bool {
         matches!(self.part_type, 0x0B | 0x0C | 0x1B | 0x1C)
     }
+
+    /// A container for logical partitions rather than a filesystem.
+    pub fn is_extended(&self) -> bool {
+        matches!(self.part_type, 0x05 | 0x0F | 0x85)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.part_type {
+            0x01 => "FAT12",
+            0x04 | 0x06 | 0x0E => "FAT16",
+            0x07 => "NTFS/exFAT",
+            0x0B | 0x0C | 0x1B | 0x1C => "FAT32",
+            0x05 | 0x0F | 0x85 => "Extended",
+            0x82 => "Linux swap",
+            0x83 => "Linux",
+            0xEE => "GPT protective",
+            0xEF => "EFI System",
+            _ => "unknown",
+        }
+    }
 }
 
-/// Read the MBR of `drive` and return up to 4 partition entries.
+/// Read the MBR of `dev` and return up to 4 partition entries.
 /// Returns None if no valid MBR signature found.
-pub fn read(drive: usize) -> Option<[Option<Partition>; 4]> {
+pub fn read(dev: &dyn BlockDevice) -> Option<[Option<Partition>; 4]> {
     let mut sector = [0u8; 512];
-    ide::read_sectors(drive, 0, 1, &mut sector).ok()?;
+    dev.read_sectors(0, 1, &mut sector).ok()?;
 
     // MBR signature
     if sector[510] != 0x55 || sector[511] != 0xAA {
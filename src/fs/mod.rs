@@ -1,7 +1,14 @@
+pub mod archive;
+pub mod cpio;
+pub mod devfs;
 pub mod fat32;
+pub mod iso9660;
 pub mod mbr;
 pub mod mount;
 pub mod path;
+pub mod perm;
+pub mod pipe;
+pub mod procfs;
 pub mod ramfs;
 pub mod vfs;
 
@@ -59,6 +66,41 @@ static HELLO_ELF: &[u8] = &[
     b'H',b'e',b'l',b'l',b'o',b'!',b'\n',
 ];
 
+/// Boot-time setup, run by the console shell before its first prompt.
+const RC: &[u8] = b"#!/bin/sh
+# Run by the console shell at boot, before the first prompt
+for dir in /tmp /home /home/root /var /var/log; do
+    test -d $dir || mkdir $dir
+done
+test -f /etc/motd && cat /etc/motd
+";
+
+/// Find a filesystem on `dev`: FAT32 (partitioned or raw), then ISO9660.
+pub fn probe_device(
+    dev: &alloc::sync::Arc<dyn crate::drivers::block::BlockDevice>,
+) -> Option<alloc::sync::Arc<dyn vfs::Filesystem>> {
+    fat32::probe_drive(dev).or_else(|| iso9660::probe(dev))
+}
+
+/// `root=` on the command line: mount that drive over the ramfs root,
+/// keeping /dev and /proc. On any trouble the ramfs stays the root.
+pub fn mount_root() {
+    let Some(spec) = crate::cmdline::options().root else {
+        return;
+    };
+    let Some(dev) = crate::drivers::block::lookup(spec) else {
+        log::warn!("root={}: no such drive, staying on ramfs", spec);
+        return;
+    };
+    let Some(fs) = probe_device(&dev) else {
+        log::warn!("root={}: no supported filesystem, staying on ramfs", spec);
+        return;
+    };
+    if let Err(e) = with_vfs(|vfs| vfs.mount_root(fs)) {
+        log::warn!("root={}: {:?}, staying on ramfs", spec, e);
+    }
+}
+
 pub fn init_rootfs() {
     let fs = ramfs::new_ramfs();
     init(fs);
@@ -66,13 +108,13 @@ pub fn init_rootfs() {
     with_vfs(|vfs| {
         let _ = vfs.mkdir("/bin");
         let _ = vfs.mkdir("/etc");
-        let _ = vfs.mkdir("/tmp");
-        let _ = vfs.mkdir("/home");
-        let _ = vfs.mkdir("/home/root");
         let _ = vfs.mkdir("/dev");
-        let _ = vfs.mkdir("/proc");
-        let _ = vfs.mkdir("/var");
-        let _ = vfs.mkdir("/var/log");
+        if let Err(e) = devfs::populate(vfs) {
+            log::warn!("devfs: {:?}", e);
+        }
+        if let Err(e) = procfs::populate(vfs) {
+            log::warn!("procfs: {:?}", e);
+        }
 
         let _ = vfs.write_file("/etc/hostname", b"saros\n");
         let _ = vfs.write_file("/etc/os-release", b"NAME=SarOS\nVERSION=0.1\n");
@@ -80,6 +122,7 @@ pub fn init_rootfs() {
             "/etc/motd",
             b"Welcome to SarOS!\nType 'help' for available commands.\n",
         );
+        let _ = vfs.write_file("/etc/rc", RC);
 
         let _ = vfs.write_file("/bin/hello", HELLO_ELF);
 
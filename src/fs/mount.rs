@@ -1,149 +1,305 @@
-use super::path;
-use super::vfs::{Errno, File, FileType, Filesystem, Inode, O_CREAT, O_RDWR, O_TRUNC, O_WRONLY};
+use super::path::{self, Location};
+use super::perm::{self, MAY_EXEC, MAY_READ, MAY_WRITE};
+use super::vfs::{
+    Errno, File, FileType, Filesystem, Ino, Inode, TimeSet, O_CREAT, O_RDWR, O_TRUNC, O_WRONLY,
+};
+use crate::proc::cred::{self, Cred};
+use crate::sync::mutex::Mutex;
 use crate::sync::spinlock::SpinLock;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+/// A filesystem (or, for a bind mount, a directory) attached over a
+/// directory: walks that reach the covered directory carry on from `root`.
+struct Mount {
+    /// Canonical path of the mount point, for listing and `umount`.
+    path: String,
+    /// The directory it covers, by inode number.
+    covered: Ino,
+    root: Arc<Inode>,
+    /// Filesystem name, or the bound directory's path.
+    source: String,
+}
+
+/// A task's own view of the tree: its root (changed by chroot) and its
+/// working directory. None is the VFS root, for both, so tasks made before
+/// the VFS exists need nothing from it. Shared by threads created with
+/// CLONE_FS, copied by fork.
+#[derive(Clone, Default)]
+pub struct FsContext {
+    root: Option<Arc<Inode>>,
+    cwd: Option<Location>,
+}
+
+impl FsContext {
+    pub fn new_shared(ctx: FsContext) -> Arc<SpinLock<FsContext>> {
+        Arc::new(SpinLock::new(ctx))
+    }
+}
+
+/// The calling task's context, shared; a fresh one outside any task.
+pub fn current_fs() -> Arc<SpinLock<FsContext>> {
+    match crate::proc::current_process() {
+        Some(p) => Arc::clone(&p.lock().fs),
+        None => FsContext::new_shared(FsContext::default()),
+    }
+}
+
 pub struct VfsContext {
     pub root: Arc<Inode>,
-    pub cwd: Arc<Inode>,
-    pub cwd_path: String,
-    /// Mount table: (absolute_mount_point, fs_root_inode)
-    /// Sorted longest-first for correct prefix matching.
-    mounts: Vec<(String, Arc<Inode>)>,
+    /// In mount order: a later mount on the same directory hides an
+    /// earlier one until it is unmounted.
+    mounts: Vec<Mount>,
 }
 
 impl VfsContext {
     pub fn new(root: Arc<Inode>) -> Self {
-        let cwd = Arc::clone(&root);
         Self {
             root,
-            cwd,
-            cwd_path: "/".to_string(),
             mounts: Vec::new(),
         }
     }
 
     // ── Path helpers ──────────────────────────────────────────────────────────
 
-    fn make_absolute(&self, path: &str) -> String {
-        if path.starts_with('/') {
-            path.to_string()
-        } else if self.cwd_path == "/" {
-            alloc::format!("/{}", path)
-        } else {
-            alloc::format!("{}/{}", self.cwd_path, path)
+    /// The root mounted on `ino` last, if anything is.
+    fn covering(&self, ino: Ino) -> Option<Arc<Inode>> {
+        self.mounts
+            .iter()
+            .rev()
+            // A directory bound onto itself would be found forever
+            .find(|m| m.covered == ino && m.root.ino != ino)
+            .map(|m| Arc::clone(&m.root))
+    }
+
+    /// The caller's "/" with whatever is mounted there.
+    fn root_location(&self) -> Location {
+        let root = current_fs().lock().root.clone();
+        let mut root = root.unwrap_or_else(|| Arc::clone(&self.root));
+        while let Some(r) = self.covering(root.ino) {
+            root = r;
         }
+        Location::root(root)
     }
 
-    /// Resolve an *absolute* path, checking the mount table first.
-    fn resolve_abs(&self, abs: &str) -> Result<Arc<Inode>, Errno> {
-        for (mp, fs_root) in &self.mounts {
-            if abs == mp.as_str() {
-                return Ok(Arc::clone(fs_root));
-            }
-            // path is inside this mount: e.g. mp="/mnt/d" abs="/mnt/d/foo"
-            let prefix = alloc::format!("{}/", mp);
-            if abs.starts_with(prefix.as_str()) {
-                let rel = &abs[mp.len()..]; // e.g. "/foo"
-                return path::resolve(fs_root, fs_root, rel);
-            }
+    fn cwd(&self) -> Location {
+        let cwd = current_fs().lock().cwd.clone();
+        cwd.unwrap_or_else(|| self.root_location())
+    }
+
+    fn walk(&self, path: &str) -> Result<Location, Errno> {
+        self.walk_at(None, path)
+    }
+
+    /// Walk `path`; a relative one from `dir` if given (the *at calls'
+    /// directory fd), else from the working directory.
+    fn walk_at(&self, dir: Option<&Location>, path: &str) -> Result<Location, Errno> {
+        // The cwd's chain starts at the root as it was when it was set; an
+        // absolute path starts at the root as it is
+        let from = if path.starts_with('/') {
+            self.root_location()
+        } else {
+            dir.cloned().unwrap_or_else(|| self.cwd())
+        };
+        path::walk(&from, path, &|ino| self.covering(ino), &cred::current())
+    }
+
+    /// Split `path` into (parent inode, last component), so that
+    /// create/unlink inside a mount reach the mounted filesystem.
+    ///
+    /// A path ending in ".", ".." or just "/" names a directory by way of
+    /// itself rather than an entry that could be made or removed; what that
+    /// means depends on the call, which passes `dot` to fail with.
+    fn resolve_parent<'a>(
+        &self,
+        dir: Option<&Location>,
+        path: &'a str,
+        dot: Errno,
+    ) -> Result<(Arc<Inode>, &'a str), Errno> {
+        if path.is_empty() {
+            return Err(Errno::ENOENT);
         }
-        path::resolve(&self.root, &self.cwd, abs)
+        let (parent_path, name) = path::split_last(path);
+        if path::is_dot(name) {
+            return Err(dot);
+        }
+        if name.len() > path::NAME_MAX {
+            return Err(Errno::ENAMETOOLONG);
+        }
+        let parent = if parent_path.is_empty() {
+            Arc::clone(dir.cloned().unwrap_or_else(|| self.cwd()).inode())
+        } else {
+            Arc::clone(self.walk_at(dir, parent_path)?.inode())
+        };
+        if !parent.is_dir() {
+            return Err(Errno::ENOTDIR);
+        }
+        Ok((parent, name))
+    }
+
+    /// Whether `name` in `parent` has something mounted on it.
+    fn is_mountpoint(&self, parent: &Inode, name: &str) -> bool {
+        parent
+            .ops
+            .lookup(name)
+            .is_ok_and(|i| self.covering(i.ino).is_some())
     }
 
     pub fn resolve(&self, path: &str) -> Result<Arc<Inode>, Errno> {
-        let abs = self.make_absolute(path);
-        self.resolve_abs(&abs)
+        Ok(Arc::clone(self.walk(path)?.inode()))
+    }
+
+    /// Canonical absolute path of the caller's working directory, from
+    /// its root.
+    pub fn cwd_path(&self) -> String {
+        self.cwd().path()
     }
 
     // ── Mount ─────────────────────────────────────────────────────────────────
 
-    /// Mount `fs` at `mountpoint` (absolute path).
+    /// Mount `fs` at `mountpoint`.
     /// Creates the directory in ramfs if it doesn't exist yet.
     pub fn mount(&mut self, mountpoint: &str, fs: Arc<dyn Filesystem>) -> Result<(), Errno> {
-        let mp = if mountpoint.ends_with('/') && mountpoint != "/" {
-            mountpoint.trim_end_matches('/').to_string()
-        } else {
-            mountpoint.to_string()
-        };
-
         // Ensure mount point directory exists in ramfs
-        self.mkdir_p(&mp).ok();
+        self.mkdir_p(mountpoint).ok();
+        self.attach(mountpoint, fs.root(), String::from(fs.name()))?;
+        log::info!("VFS: mounted {} at {}", fs.name(), mountpoint);
+        Ok(())
+    }
 
-        let fs_root = fs.root();
+    /// Mount `fs` over "/" (`root=`). The ramfs's /dev and /proc are bound
+    /// onto the new root, made there if it lacks them; where that can't be
+    /// done they are left out.
+    pub fn mount_root(&mut self, fs: Arc<dyn Filesystem>) -> Result<(), Errno> {
+        let kept: Vec<(&str, Arc<Inode>)> = ["/dev", "/proc"]
+            .into_iter()
+            .filter_map(|path| Some((path, self.resolve(path).ok()?)))
+            .collect();
+        self.attach("/", fs.root(), String::from(fs.name()))?;
+        for (path, dir) in kept {
+            self.mkdir(path).ok();
+            if let Err(e) = self.attach(path, dir, String::from(path)) {
+                log::warn!("VFS: {} not kept on the new root: {:?}", path, e);
+            }
+        }
+        log::info!("VFS: mounted {} at /", fs.name());
+        Ok(())
+    }
 
-        // Insert sorted by length descending (longest prefix matches first)
-        let pos = self.mounts
-            .iter()
-            .position(|(existing, _)| existing.len() < mp.len())
-            .unwrap_or(self.mounts.len());
-        self.mounts.insert(pos, (mp.clone(), fs_root));
+    /// Make the directory `source` appear at `mountpoint` as well.
+    pub fn mount_bind(&mut self, source: &str, mountpoint: &str) -> Result<(), Errno> {
+        let src = self.walk(source)?;
+        if !src.inode().is_dir() {
+            return Err(Errno::ENOTDIR);
+        }
+        self.attach(mountpoint, Arc::clone(src.inode()), src.path())
+    }
 
-        log::info!("VFS: mounted {} at {}", fs.name(), mp);
+    fn attach(&mut self, mountpoint: &str, root: Arc<Inode>, source: String) -> Result<(), Errno> {
+        let at = self.walk(mountpoint)?;
+        if !at.inode().is_dir() {
+            return Err(Errno::ENOTDIR);
+        }
+        self.mounts.push(Mount { path: at.path(), covered: at.inode().ino, root, source });
         Ok(())
     }
 
+    /// Detach the filesystem mounted last at `mountpoint`. Busy while
+    /// something is mounted below it or the working directory is in it.
     pub fn umount(&mut self, mountpoint: &str) -> Result<(), Errno> {
-        let before = self.mounts.len();
-        self.mounts.retain(|(mp, _)| mp.as_str() != mountpoint);
-        if self.mounts.len() == before {
-            Err(Errno::ENOENT)
-        } else {
-            Ok(())
+        let path = self.walk(mountpoint)?.path();
+        let idx = self
+            .mounts
+            .iter()
+            .rposition(|m| m.path == path)
+            .ok_or(Errno::EINVAL)?;
+        let inside = |p: &str| path == "/" || p == path || p.starts_with(&(path.clone() + "/"));
+        let nested = self.mounts[idx + 1..].iter().any(|m| inside(&m.path));
+        if nested || inside(&self.cwd().path()) {
+            return Err(Errno::EBUSY);
         }
+        self.mounts.remove(idx);
+        Ok(())
     }
 
+    /// "source on path" for each mount, in mount order.
     pub fn list_mounts(&self) -> Vec<String> {
-        self.mounts.iter().map(|(mp, _)| mp.clone()).collect()
+        self.mounts
+            .iter()
+            .map(|m| alloc::format!("{} on {}", m.source, m.path))
+            .collect()
     }
 
     // ── VFS operations ────────────────────────────────────────────────────────
 
     pub fn open(&self, path: &str, flags: u32) -> Result<Arc<File>, Errno> {
-        let inode = match self.resolve(path) {
-            Ok(i) => {
+        self.open_at(None, path, flags, 0o644)
+    }
+
+    /// open, with a relative `path` taken from `dir` if given, and `mode`
+    /// for a file it creates. A directory opened so remembers where it is,
+    /// for opening relative to it in turn.
+    pub fn open_at(
+        &self,
+        dir: Option<&Location>,
+        path: &str,
+        flags: u32,
+        mode: u32,
+    ) -> Result<Arc<File>, Errno> {
+        match self.walk_at(dir, path) {
+            Ok(loc) => {
+                let inode = Arc::clone(loc.inode());
+                perm::check(&inode.stat(), &cred::current(), perm::open_mask(flags))?;
+                if inode.is_dir() {
+                    if flags & (O_WRONLY | O_RDWR | O_TRUNC) != 0 {
+                        return Err(Errno::EISDIR);
+                    }
+                    return Ok(File::with_location(loc, flags));
+                }
                 if flags & O_CREAT != 0 && flags & O_TRUNC != 0 {
-                    i.ops.truncate(0)?;
+                    inode.ops.truncate(0)?;
                 }
-                i
+                Ok(File::new(inode, flags))
             }
             Err(Errno::ENOENT) if flags & O_CREAT != 0 => {
-                let abs = self.make_absolute(path);
-                let (parent, name) = path::resolve_parent(&self.root, &self.cwd, &abs)?;
-                parent.ops.create(name, 0o644)?
+                if path.ends_with('/') {
+                    return Err(Errno::EISDIR);
+                }
+                let (parent, name) = self.resolve_parent(dir, path, Errno::EISDIR)?;
+                let inode = create_in(&parent, |ops| ops.create(name, mode & 0o7777))?;
+                Ok(File::new(inode, flags))
             }
-            Err(e) => return Err(e),
-        };
-        Ok(File::new(inode, flags))
+            Err(e) => Err(e),
+        }
     }
 
     pub fn mkdir(&self, path: &str) -> Result<(), Errno> {
-        let abs = self.make_absolute(path);
-        let (parent, name) = path::resolve_parent(&self.root, &self.cwd, &abs)?;
-        parent.ops.mkdir(name, 0o755)?;
+        let (parent, name) = self.resolve_parent(None, path, Errno::EEXIST)?;
+        create_in(&parent, |ops| ops.mkdir(name, 0o755))?;
         Ok(())
     }
 
     pub fn mkdir_p(&self, path: &str) -> Result<(), Errno> {
-        let mut current = if path.starts_with('/') {
-            Arc::clone(&self.root)
+        let mut loc = if path.starts_with('/') {
+            self.root_location()
         } else {
-            Arc::clone(&self.cwd)
+            self.cwd()
         };
 
-        for component in path.split('/').filter(|s| !s.is_empty()) {
-            match current.ops.lookup(component) {
+        let cred = cred::current();
+        let cover = |ino| self.covering(ino);
+        for component in path::components(path) {
+            match path::walk(&loc, component, &cover, &cred) {
                 Ok(next) => {
-                    if !next.is_dir() {
+                    if !next.inode().is_dir() {
                         return Err(Errno::ENOTDIR);
                     }
-                    current = next;
+                    loc = next;
                 }
                 Err(Errno::ENOENT) => {
-                    let next = current.ops.mkdir(component, 0o755)?;
-                    current = next;
+                    create_in(loc.inode(), |ops| ops.mkdir(component, 0o755))?;
+                    loc = path::walk(&loc, component, &cover, &cred)?;
                 }
                 Err(e) => return Err(e),
             }
@@ -152,26 +308,42 @@ impl VfsContext {
     }
 
     pub fn unlink(&self, path: &str) -> Result<(), Errno> {
-        let abs = self.make_absolute(path);
-        let (parent, name) = path::resolve_parent(&self.root, &self.cwd, &abs)?;
+        let (parent, name) = self.resolve_parent(None, path, Errno::EISDIR)?;
+        if path.ends_with('/') {
+            // Only a directory could be named so, and unlink doesn't take those
+            let inode = parent.ops.lookup(name)?;
+            return Err(if inode.is_dir() { Errno::EISDIR } else { Errno::ENOTDIR });
+        }
+        may_remove(&parent, name)?;
         parent.ops.unlink(name)
     }
 
     pub fn rmdir(&self, path: &str) -> Result<(), Errno> {
-        let abs = self.make_absolute(path);
-        let (parent, name) = path::resolve_parent(&self.root, &self.cwd, &abs)?;
+        let (parent, name) = self.resolve_parent(None, path, Errno::EINVAL)?;
+        if self.is_mountpoint(&parent, name) {
+            return Err(Errno::EBUSY);
+        }
+        may_remove(&parent, name)?;
         parent.ops.rmdir(name)
     }
 
     pub fn rename(&self, old: &str, new: &str) -> Result<(), Errno> {
-        let (old_parent, old_name) = path::resolve_parent(&self.root, &self.cwd, old)?;
-        let (new_parent, new_name) = path::resolve_parent(&self.root, &self.cwd, new)?;
+        let (old_parent, old_name) = self.resolve_parent(None, old, Errno::EBUSY)?;
+        let (new_parent, new_name) = self.resolve_parent(None, new, Errno::EBUSY)?;
+        if self.is_mountpoint(&old_parent, old_name) || self.is_mountpoint(&new_parent, new_name) {
+            return Err(Errno::EBUSY);
+        }
+        may_remove(&old_parent, old_name)?;
+        match new_parent.ops.lookup(new_name) {
+            Ok(_) => may_remove(&new_parent, new_name)?,
+            Err(_) => perm::check(&new_parent.stat(), &cred::current(), MAY_WRITE | MAY_EXEC)?,
+        }
         old_parent.ops.rename(old_name, &new_parent, new_name)
     }
 
     pub fn symlink(&self, target: &str, link_path: &str) -> Result<(), Errno> {
-        let (parent, name) = path::resolve_parent(&self.root, &self.cwd, link_path)?;
-        parent.ops.symlink(name, target)?;
+        let (parent, name) = self.resolve_parent(None, link_path, Errno::EEXIST)?;
+        create_in(&parent, |ops| ops.symlink(name, target))?;
         Ok(())
     }
 
@@ -179,35 +351,127 @@ impl VfsContext {
         Ok(self.resolve(path)?.stat())
     }
 
-    pub fn cd(&mut self, path: &str) -> Result<(), Errno> {
+    /// stat of `path` from `dir` (as for `open_at`). Unless `follow`, a
+    /// symlink at the end is reported itself, as lstat does.
+    pub fn stat_at(
+        &self,
+        dir: Option<&Location>,
+        path: &str,
+        follow: bool,
+    ) -> Result<super::vfs::Stat, Errno> {
+        Ok(self.lookup_at(dir, path, follow)?.stat())
+    }
+
+    /// The inode `path` names from `dir`; unless `follow`, a symlink at
+    /// the end is that symlink.
+    fn lookup_at(
+        &self,
+        dir: Option<&Location>,
+        path: &str,
+        follow: bool,
+    ) -> Result<Arc<Inode>, Errno> {
+        // "link/" and "link/." are the directory behind it, even for lstat
+        if follow || path::is_dot(path::split_last(path).1) || path.ends_with('/') {
+            return Ok(self.walk_at(dir, path)?.inode().clone());
+        }
+        let (parent, name) = self.resolve_parent(dir, path, Errno::EINVAL)?;
+        let inode = parent.ops.lookup(name)?;
+        Ok(self.covering(inode.ino).unwrap_or(inode))
+    }
+
+    /// Target of the symlink at `path`.
+    pub fn readlink(&self, path: &str) -> Result<String, Errno> {
+        self.lookup_at(None, path, false)?.ops.readlink()
+    }
+
+    /// chmod, following a symlink at the end.
+    pub fn chmod(&self, path: &str, mode: u32) -> Result<(), Errno> {
         let inode = self.resolve(path)?;
-        if !inode.is_dir() {
+        perm::chmod(&inode, &cred::current(), mode)
+    }
+
+    /// chown, None leaving an id as it is. Unless `follow`, a symlink at
+    /// the end is changed itself, as lchown does.
+    pub fn chown(
+        &self,
+        path: &str,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        follow: bool,
+    ) -> Result<(), Errno> {
+        let inode = self.lookup_at(None, path, follow)?;
+        perm::chown(&inode, &cred::current(), uid, gid)
+    }
+
+    /// utimensat: set the access and modification times of `path` from
+    /// `dir`. Unless `follow`, a symlink at the end gets them itself.
+    pub fn utimens_at(
+        &self,
+        dir: Option<&Location>,
+        path: &str,
+        atime: TimeSet,
+        mtime: TimeSet,
+        follow: bool,
+    ) -> Result<(), Errno> {
+        let inode = self.lookup_at(dir, path, follow)?;
+        perm::set_times(&inode, &cred::current(), atime, mtime)
+    }
+
+    /// Change the caller's working directory.
+    pub fn cd(&self, path: &str) -> Result<(), Errno> {
+        let loc = self.walk(path)?;
+        if !loc.inode().is_dir() {
             return Err(Errno::ENOTDIR);
         }
-        self.cwd = inode;
-        let abs = self.make_absolute(path);
-        // Normalize: strip trailing slash except root
-        self.cwd_path = if abs == "/" {
-            abs
-        } else {
-            abs.trim_end_matches('/').to_string()
-        };
+        perm::check(&loc.inode().stat(), &cred::current(), MAY_EXEC)?;
+        current_fs().lock().cwd = Some(loc);
         Ok(())
     }
 
+    /// Make `path` the caller's root. The working directory moves there
+    /// too, as `chroot` tools do next anyway: nothing is left outside it.
+    pub fn chroot(&self, path: &str) -> Result<(), Errno> {
+        if !cred::current().is_root() {
+            return Err(Errno::EPERM);
+        }
+        let loc = self.walk(path)?;
+        if !loc.inode().is_dir() {
+            return Err(Errno::ENOTDIR);
+        }
+        let root = Arc::clone(loc.inode());
+        let fs = current_fs();
+        let mut fs = fs.lock();
+        fs.cwd = Some(Location::root(Arc::clone(&root)));
+        fs.root = Some(root);
+        Ok(())
+    }
+
+    /// The entries of a directory; a mount point is listed as the root of
+    /// what is mounted on it.
     pub fn readdir_all(&self, path: &str) -> Result<Vec<super::vfs::DirEntry>, Errno> {
         let inode = self.resolve(path)?;
         if !inode.is_dir() {
             return Err(Errno::ENOTDIR);
         }
+        perm::check(&inode.stat(), &cred::current(), MAY_READ)?;
         let file = File::new(inode, 0);
         let mut entries = Vec::new();
         while let Some(e) = file.readdir_next()? {
-            entries.push(e);
+            entries.push(self.dirent(e));
         }
         Ok(entries)
     }
 
+    /// A directory entry as the caller should see it: one for a mount
+    /// point is the root of what is mounted on it.
+    pub fn dirent(&self, mut e: super::vfs::DirEntry) -> super::vfs::DirEntry {
+        if let Some(root) = self.covering(e.ino) {
+            e.ino = root.ino;
+            e.kind = root.stat().kind;
+        }
+        e
+    }
+
     pub fn write_file(&self, path: &str, data: &[u8]) -> Result<(), Errno> {
         let file = self.open(path, O_WRONLY | O_CREAT | O_TRUNC)?;
         file.write(data)?;
@@ -231,7 +495,31 @@ impl VfsContext {
     }
 }
 
-static VFS: SpinLock<Option<VfsContext>> = SpinLock::new(None);
+/// Make an entry in `parent` with `make`, if the caller may write there;
+/// it belongs to the caller. Filesystems make inodes as 0:0, and those
+/// that keep no owners refuse the chown, which is fine.
+fn create_in(
+    parent: &Inode,
+    make: impl FnOnce(&dyn super::vfs::InodeOps) -> Result<Arc<Inode>, Errno>,
+) -> Result<Arc<Inode>, Errno> {
+    let cred = cred::current();
+    perm::check(&parent.stat(), &cred, MAY_WRITE | MAY_EXEC)?;
+    let inode = make(parent.ops.as_ref())?;
+    if cred != Cred::ROOT {
+        let _ = inode.ops.chown(cred.uid, cred.gid);
+    }
+    Ok(inode)
+}
+
+/// Whether the caller may take `name` out of `parent`.
+fn may_remove(parent: &Inode, name: &str) -> Result<(), Errno> {
+    let victim = parent.ops.lookup(name)?;
+    perm::check_remove(&parent.stat(), &victim.stat(), &cred::current())
+}
+
+/// A sleeping lock: filesystem calls can end up in disk I/O, which must
+/// not run with interrupts off.
+static VFS: Mutex<Option<VfsContext>> = Mutex::new(None);
 
 pub fn init(root_fs: Arc<dyn Filesystem>) {
     let root = root_fs.root();
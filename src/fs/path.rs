@@ -1,67 +1,177 @@
-use super::vfs::{Errno, FileType, Inode, O_CREAT, O_TRUNC};
-use alloc::string::{String, ToString};
+use super::perm::{self, MAY_EXEC};
+use super::vfs::{Errno, Ino, Inode};
+use crate::proc::cred::Cred;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-pub fn resolve(root: &Arc<Inode>, cwd: &Arc<Inode>, path: &str) -> Result<Arc<Inode>, Errno> {
-    resolve_inner(root, cwd, path, 0)
+/// Symlinks followed in one resolution before it fails with ELOOP.
+pub const MAX_SYMLINKS: u32 = 40;
+
+/// Longest path, and longest component, accepted.
+pub const PATH_MAX: usize = 4096;
+pub const NAME_MAX: usize = 255;
+
+/// Where a path walk has got to: each directory from the root down with
+/// the name that led to it. ".." is a step back along the chain, so it
+/// works the same on every filesystem and across mount points, and the
+/// chain spells the canonical path.
+#[derive(Clone)]
+pub struct Location {
+    chain: Vec<(String, Arc<Inode>)>,
 }
 
-pub fn resolve_parent<'a>(
-    root: &Arc<Inode>,
-    cwd: &Arc<Inode>,
-    path: &'a str,
-) -> Result<(Arc<Inode>, &'a str), Errno> {
-    let (parent_path, name) = split_last(path);
-    let parent = if parent_path.is_empty() {
-        Arc::clone(cwd)
-    } else {
-        resolve(root, cwd, parent_path)?
-    };
-    Ok((parent, name))
+impl Location {
+    pub fn root(root: Arc<Inode>) -> Self {
+        Self { chain: alloc::vec![(String::new(), root)] }
+    }
+
+    pub fn inode(&self) -> &Arc<Inode> {
+        &self.chain[self.chain.len() - 1].1
+    }
+
+    /// The absolute path, "/" for the root.
+    pub fn path(&self) -> String {
+        if self.chain.len() == 1 {
+            return String::from("/");
+        }
+        let mut p = String::new();
+        for (name, _) in &self.chain[1..] {
+            p.push('/');
+            p.push_str(name);
+        }
+        p
+    }
+
+    fn go_root(&mut self) {
+        self.chain.truncate(1);
+    }
+
+    fn up(&mut self) {
+        if self.chain.len() > 1 {
+            self.chain.pop();
+        }
+    }
+
+    fn down(&mut self, name: &str, inode: Arc<Inode>) {
+        self.chain.push((String::from(name), inode));
+    }
 }
 
-fn resolve_inner(
-    root: &Arc<Inode>,
-    cwd: &Arc<Inode>,
+/// Walk `path` from `from` (or from its root, if absolute), following
+/// symlinks and, through `cover`, stepping onto whatever is mounted on a
+/// directory: `cover` gives the root mounted on an inode number, if any.
+/// Each directory looked into needs search permission for `cred`.
+///
+/// An empty path is ENOENT, and one with a trailing slash has to end at a
+/// directory.
+pub fn walk(
+    from: &Location,
     path: &str,
-    depth: u32,
-) -> Result<Arc<Inode>, Errno> {
-    if depth > 40 {
-        return Err(Errno(40));
+    cover: &dyn Fn(Ino) -> Option<Arc<Inode>>,
+    cred: &Cred,
+) -> Result<Location, Errno> {
+    check(path)?;
+    let mut loc = from.clone();
+    let mut walker = Walker { cover, cred, hops: 0 };
+    walker.walk(&mut loc, path)?;
+    if path.ends_with('/') && !loc.inode().is_dir() {
+        return Err(Errno::ENOTDIR);
+    }
+    Ok(loc)
+}
+
+/// Whether `path` is one a walk can start on at all.
+fn check(path: &str) -> Result<(), Errno> {
+    if path.is_empty() {
+        return Err(Errno::ENOENT);
+    }
+    if path.len() > PATH_MAX || components(path).any(|c| c.len() > NAME_MAX) {
+        return Err(Errno::ENAMETOOLONG);
     }
+    Ok(())
+}
 
-    let mut current = if path.starts_with('/') {
-        Arc::clone(root)
-    } else {
-        Arc::clone(cwd)
-    };
+/// What stays the same through one walk, symlinks and all.
+struct Walker<'a> {
+    cover: &'a dyn Fn(Ino) -> Option<Arc<Inode>>,
+    cred: &'a Cred,
+    hops: u32,
+}
 
-    for component in path.split('/').filter(|s| !s.is_empty()) {
-        match component {
-            "." => {}
-            ".." => {
-                current = current
-                    .ops
-                    .lookup("..")
-                    .unwrap_or_else(|_| Arc::clone(root));
-            }
-            name => {
-                if !current.is_dir() {
-                    return Err(Errno::ENOTDIR);
-                }
-                let next = current.ops.lookup(name)?;
-                if next.is_symlink() {
-                    let target = next.ops.readlink()?;
-                    current = resolve_inner(root, &current, &target, depth + 1)?;
-                } else {
-                    current = next;
+impl Walker<'_> {
+    fn walk(&mut self, loc: &mut Location, path: &str) -> Result<(), Errno> {
+        if path.starts_with('/') {
+            loc.go_root();
+        }
+        for component in components(path) {
+            match component {
+                "." => {}
+                ".." => loc.up(),
+                name => {
+                    let dir = loc.inode().stat();
+                    if dir.kind != super::vfs::FileType::Directory {
+                        return Err(Errno::ENOTDIR);
+                    }
+                    perm::check(&dir, self.cred, MAY_EXEC)?;
+                    let mut next = loc.inode().ops.lookup(name)?;
+                    while let Some(root) = (self.cover)(next.ino) {
+                        next = root;
+                    }
+                    if next.is_symlink() {
+                        self.hops += 1;
+                        if self.hops > MAX_SYMLINKS {
+                            return Err(Errno::ELOOP);
+                        }
+                        // Relative targets are from the directory the link is in
+                        let target = next.ops.readlink()?;
+                        check(&target)?;
+                        self.walk(loc, &target)?;
+                    } else {
+                        loc.down(name, next);
+                    }
                 }
             }
         }
+        Ok(())
     }
+}
+
+/// `path` with "." and ".." worked out from the text alone, repeated and
+/// trailing slashes dropped: "/a/./b/../c/" is "/a/c". ".." stops at the
+/// root of an absolute path and is kept at the front of a relative one.
+///
+/// Only right where no symlink is involved; lookups go by `walk`, which
+/// takes ".." after following links. This is for names that aren't looked
+/// up, like the ones in an archive.
+pub fn normalize(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let mut parts: Vec<&str> = Vec::new();
+    for c in components(path) {
+        match c {
+            "." => {}
+            ".." => match parts.last() {
+                Some(&last) if last != ".." => {
+                    parts.pop();
+                }
+                _ if absolute => {}
+                _ => parts.push(".."),
+            },
+            name => parts.push(name),
+        }
+    }
+    let joined = parts.join("/");
+    match (absolute, joined.is_empty()) {
+        (true, _) => alloc::format!("/{}", joined),
+        (false, true) => String::from("."),
+        (false, false) => joined,
+    }
+}
 
-    Ok(current)
+/// A last component that names no entry of its own: "." or "..", or
+/// nothing at all as in "/".
+pub fn is_dot(name: &str) -> bool {
+    matches!(name, "" | "." | "..")
 }
 
 pub fn split_last(path: &str) -> (&str, &str) {
@@ -0,0 +1,114 @@
+/// Unix permission checks: a file's mode, owner and group against the
+/// credentials of the task asking. Filesystems only store the bits; the
+/// VFS decides with these before it calls into them.
+use super::vfs::{Errno, FileType, Inode, Stat, TimeSet, Timespec};
+use crate::proc::cred::Cred;
+
+pub const MAY_READ: u32 = 4;
+pub const MAY_WRITE: u32 = 2;
+pub const MAY_EXEC: u32 = 1;
+
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+const S_ISVTX: u32 = 0o1000;
+
+/// Whether `cred` may access a file with `st` as `mask` (MAY_* bits)
+/// says: by the owner bits if it owns the file, else the group bits if in
+/// its group, else the rest. Root may do anything but execute a file no
+/// one may execute.
+pub fn check(st: &Stat, cred: &Cred, mask: u32) -> Result<(), Errno> {
+    if cred.is_root() {
+        let exec_ok = st.kind == FileType::Directory || st.mode & 0o111 != 0;
+        return if mask & MAY_EXEC == 0 || exec_ok { Ok(()) } else { Err(Errno::EACCES) };
+    }
+    let bits = if st.uid == cred.uid {
+        st.mode >> 6
+    } else if st.gid == cred.gid {
+        st.mode >> 3
+    } else {
+        st.mode
+    };
+    if bits & mask == mask {
+        Ok(())
+    } else {
+        Err(Errno::EACCES)
+    }
+}
+
+/// Removing or renaming `victim` out of `dir`: write and search on the
+/// directory, and if it is sticky (/tmp) owning one of the two.
+pub fn check_remove(dir: &Stat, victim: &Stat, cred: &Cred) -> Result<(), Errno> {
+    check(dir, cred, MAY_WRITE | MAY_EXEC)?;
+    let owner = cred.is_root() || cred.uid == dir.uid || cred.uid == victim.uid;
+    if dir.mode & S_ISVTX != 0 && !owner {
+        return Err(Errno::EPERM);
+    }
+    Ok(())
+}
+
+/// chmod: the owner or root only.
+pub fn chmod(inode: &Inode, cred: &Cred, mode: u32) -> Result<(), Errno> {
+    let st = inode.stat();
+    if !cred.is_root() && cred.uid != st.uid {
+        return Err(Errno::EPERM);
+    }
+    inode.ops.chmod(mode & 0o7777)
+}
+
+/// chown, None leaving an id as it is. Only root gives a file away; its
+/// owner may move it to the group it is in itself. A regular file loses
+/// setuid and setgid when someone else than root changes its ownership.
+pub fn chown(inode: &Inode, cred: &Cred, uid: Option<u32>, gid: Option<u32>) -> Result<(), Errno> {
+    let st = inode.stat();
+    let uid = uid.unwrap_or(st.uid);
+    let gid = gid.unwrap_or(st.gid);
+    if !cred.is_root() {
+        if cred.uid != st.uid || uid != st.uid || (gid != st.gid && gid != cred.gid) {
+            return Err(Errno::EPERM);
+        }
+        if st.kind == FileType::Regular && st.mode & (S_ISUID | S_ISGID) != 0 {
+            inode.ops.chmod(st.mode & !(S_ISUID | S_ISGID))?;
+        }
+    }
+    inode.ops.chown(uid, gid)
+}
+
+/// utimensat: setting an explicit time takes the owner or root; setting
+/// the current time, as `touch` does, also write permission.
+pub fn set_times(inode: &Inode, cred: &Cred, atime: TimeSet, mtime: TimeSet) -> Result<(), Errno> {
+    let st = inode.stat();
+    let owner = cred.is_root() || cred.uid == st.uid;
+    let explicit = matches!(atime, TimeSet::At(_)) || matches!(mtime, TimeSet::At(_));
+    if explicit && !owner {
+        return Err(Errno::EPERM);
+    }
+    if !owner {
+        check(&st, cred, MAY_WRITE)?;
+    }
+    let now = Timespec::now();
+    let pick = |t: TimeSet| match t {
+        TimeSet::Omit => None,
+        TimeSet::Now => Some(now),
+        TimeSet::At(t) => Some(t),
+    };
+    if pick(atime).is_none() && pick(mtime).is_none() {
+        return Ok(());
+    }
+    inode.ops.set_times(pick(atime), pick(mtime))
+}
+
+/// What opening with `flags` asks of the file itself.
+pub fn open_mask(flags: u32) -> u32 {
+    use super::vfs::{O_ACCMODE, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+    let mask = match flags & O_ACCMODE {
+        O_RDONLY => MAY_READ,
+        O_WRONLY => MAY_WRITE,
+        O_RDWR => MAY_READ | MAY_WRITE,
+        _ => MAY_READ | MAY_WRITE,
+    };
+    if flags & O_TRUNC != 0 {
+        mask | MAY_WRITE
+    } else {
+        mask
+    }
+}
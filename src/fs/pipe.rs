@@ -0,0 +1,192 @@
+/// Anonymous pipes: a bounded byte ring shared by a read end and a write end.
+///
+/// Reads block while the ring is empty and return 0 (EOF) once the write end
+/// is closed; writes block while it is full and fail with EPIPE once the read
+/// end is gone (raising SIGPIPE). An end counts as closed when its last
+/// `File` is dropped. A pending signal interrupts a blocked call with EINTR.
+use super::vfs::{alloc_ino, DirEntry, Errno, File, FileType, Ino, Inode, InodeOps, Stat, Timespec};
+use super::vfs::{O_RDONLY, O_WRONLY};
+use crate::sync::spinlock::SpinLock;
+use crate::sync::waitqueue::WaitQueue;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+pub const PIPE_CAPACITY: usize = 4096;
+
+struct PipeState {
+    buf: VecDeque<u8>,
+    read_open: bool,
+    write_open: bool,
+}
+
+struct Pipe {
+    state: SpinLock<PipeState>,
+    /// Readers waiting for data, writers waiting for space.
+    readers: WaitQueue,
+    writers: WaitQueue,
+}
+
+struct PipeEnd {
+    ino: Ino,
+    pipe: Arc<Pipe>,
+    write: bool,
+}
+
+impl Drop for PipeEnd {
+    fn drop(&mut self) {
+        {
+            let mut st = self.pipe.state.lock();
+            if self.write {
+                st.write_open = false;
+            } else {
+                st.read_open = false;
+            }
+        }
+        // The peer may be asleep waiting for data / space
+        if self.write {
+            self.pipe.readers.wake_all();
+        } else {
+            self.pipe.writers.wake_all();
+        }
+    }
+}
+
+/// Create a pipe; returns (read end, write end).
+pub fn new_pipe() -> (Arc<File>, Arc<File>) {
+    let pipe = Arc::new(Pipe {
+        state: SpinLock::new(PipeState {
+            buf: VecDeque::with_capacity(PIPE_CAPACITY),
+            read_open: true,
+            write_open: true,
+        }),
+        readers: WaitQueue::new("pipe_read"),
+        writers: WaitQueue::new("pipe_write"),
+    });
+    let end = |write: bool| {
+        let ops = Arc::new(PipeEnd {
+            ino: alloc_ino(),
+            pipe: Arc::clone(&pipe),
+            write,
+        });
+        Inode::new(ops.ino, ops)
+    };
+    (File::new(end(false), O_RDONLY), File::new(end(true), O_WRONLY))
+}
+
+fn not_dir<T>() -> Result<T, Errno> {
+    Err(Errno::ENOTDIR)
+}
+
+impl InodeOps for PipeEnd {
+    fn stat(&self) -> Stat {
+        let now = Timespec::now();
+        Stat {
+            ino: self.ino,
+            kind: FileType::Fifo,
+            size: self.pipe.state.lock().buf.len() as u64,
+            mode: 0o600,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    fn read(&self, _: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.write {
+            return Err(Errno::EBADF);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let res = self.pipe.readers.wait_event(|| {
+            let mut st = self.pipe.state.lock();
+            if !st.buf.is_empty() {
+                let n = buf.len().min(st.buf.len());
+                for (dst, b) in buf.iter_mut().zip(st.buf.drain(..n)) {
+                    *dst = b;
+                }
+                return Some(Ok(n));
+            }
+            if !st.write_open {
+                return Some(Ok(0)); // EOF
+            }
+            drop(st);
+            crate::proc::signal::interrupted().then_some(Err(Errno::EINTR))
+        });
+
+        if matches!(res, Ok(n) if n > 0) {
+            self.pipe.writers.wake_all();
+        }
+        res
+    }
+
+    fn write(&self, _: u64, buf: &[u8]) -> Result<usize, Errno> {
+        if !self.write {
+            return Err(Errno::EBADF);
+        }
+
+        let mut done = 0;
+        self.pipe.writers.wait_event(|| {
+            let mut st = self.pipe.state.lock();
+            if !st.read_open {
+                drop(st);
+                crate::proc::signal::raise(crate::proc::signal::SIGPIPE);
+                return Some(if done > 0 { Ok(done) } else { Err(Errno::EPIPE) });
+            }
+            let n = (PIPE_CAPACITY - st.buf.len()).min(buf.len() - done);
+            st.buf.extend(&buf[done..done + n]);
+            done += n;
+            drop(st);
+
+            if n > 0 {
+                self.pipe.readers.wake_all();
+            }
+            if done == buf.len() {
+                return Some(Ok(done));
+            }
+            if crate::proc::signal::interrupted() {
+                return Some(if done > 0 { Ok(done) } else { Err(Errno::EINTR) });
+            }
+            None
+        })
+    }
+
+    fn truncate(&self, _: u64) -> Result<(), Errno> {
+        Err(Errno::EINVAL)
+    }
+    fn lookup(&self, _: &str) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn readdir(&self, _: usize) -> Result<Option<DirEntry>, Errno> {
+        not_dir()
+    }
+    fn create(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn mkdir(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn unlink(&self, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn rmdir(&self, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        Err(Errno::EINVAL)
+    }
+    fn rename(&self, _: &str, _: &Arc<Inode>, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn insert_child(&self, _: &str, _: Arc<Inode>) -> Result<(), Errno> {
+        not_dir()
+    }
+}
@@ -0,0 +1,316 @@
+/// Read-only kernel status files under /proc: /proc/slabinfo, /proc/<pid>/stat, …
+///
+/// /proc itself is a directory inode of its own, inserted into the ramfs
+/// root like the device nodes go into /dev. Besides the fixed files it
+/// lists one directory per task, taken from the scheduler on each readdir.
+/// File text is generated afresh on every read, so a reader that goes
+/// through it in several calls may see the numbers move.
+use super::mount::VfsContext;
+use super::vfs::{alloc_ino, DirEntry, Errno, FileType, Ino, Inode, InodeOps, Stat, Timespec};
+use crate::proc::{self, TaskInfo};
+use crate::sync::spinlock::SpinLock;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Produces the whole contents of a file; None once what it describes is
+/// gone (the task exited), which reads as ENOENT.
+pub type Generator = Box<dyn Fn() -> Option<String> + Send + Sync>;
+
+pub struct ProcFile {
+    ino: Ino,
+    gen: Generator,
+}
+
+impl ProcFile {
+    pub fn new_inode(gen: Generator) -> Arc<Inode> {
+        let ops = Arc::new(ProcFile {
+            ino: alloc_ino(),
+            gen,
+        });
+        let ino = ops.ino;
+        Inode::new(ino, ops)
+    }
+}
+
+fn not_dir<T>() -> Result<T, Errno> {
+    Err(Errno::ENOTDIR)
+}
+fn is_dir<T>() -> Result<T, Errno> {
+    Err(Errno::EISDIR)
+}
+fn read_only<T>() -> Result<T, Errno> {
+    Err(Errno::EACCES)
+}
+
+impl InodeOps for ProcFile {
+    fn stat(&self) -> Stat {
+        let now = Timespec::now();
+        Stat {
+            ino: self.ino,
+            kind: FileType::Regular,
+            size: 0, // unknown until generated, as on Linux
+            mode: 0o444,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        let text = (self.gen)().ok_or(Errno::ENOENT)?;
+        let data = text.as_bytes();
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+
+    fn write(&self, _: u64, _: &[u8]) -> Result<usize, Errno> {
+        read_only()
+    }
+    fn truncate(&self, _: u64) -> Result<(), Errno> {
+        read_only()
+    }
+    fn lookup(&self, _: &str) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn readdir(&self, _: usize) -> Result<Option<DirEntry>, Errno> {
+        not_dir()
+    }
+    fn create(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn mkdir(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn unlink(&self, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn rmdir(&self, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        Err(Errno::EINVAL)
+    }
+    fn rename(&self, _: &str, _: &Arc<Inode>, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn insert_child(&self, _: &str, _: Arc<Inode>) -> Result<(), Errno> {
+        not_dir()
+    }
+}
+
+// ─── Directories ─────────────────────────────────────────────────────────────
+
+/// The contents of a /proc directory, as (name, inode) in readdir order.
+trait Listing: Send + Sync {
+    fn entries(&self) -> Vec<(String, Arc<Inode>)>;
+    fn find(&self, name: &str) -> Option<Arc<Inode>>;
+}
+
+pub struct ProcDir {
+    ino: Ino,
+    listing: Box<dyn Listing>,
+}
+
+impl ProcDir {
+    fn new_inode(listing: impl Listing + 'static) -> Arc<Inode> {
+        let ops = Arc::new(ProcDir {
+            ino: alloc_ino(),
+            listing: Box::new(listing),
+        });
+        let ino = ops.ino;
+        Inode::new(ino, ops)
+    }
+}
+
+impl InodeOps for ProcDir {
+    fn stat(&self) -> Stat {
+        let now = Timespec::now();
+        Stat {
+            ino: self.ino,
+            kind: FileType::Directory,
+            size: 0,
+            mode: 0o555,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+    fn read(&self, _: u64, _: &mut [u8]) -> Result<usize, Errno> {
+        is_dir()
+    }
+    fn write(&self, _: u64, _: &[u8]) -> Result<usize, Errno> {
+        is_dir()
+    }
+    fn truncate(&self, _: u64) -> Result<(), Errno> {
+        is_dir()
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<Inode>, Errno> {
+        self.listing.find(name).ok_or(Errno::ENOENT)
+    }
+
+    fn readdir(&self, offset: usize) -> Result<Option<DirEntry>, Errno> {
+        Ok(self.listing.entries().into_iter().nth(offset).map(|(name, i)| DirEntry {
+            name,
+            ino: i.ino,
+            kind: i.stat().kind,
+        }))
+    }
+
+    fn create(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        read_only()
+    }
+    fn mkdir(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        read_only()
+    }
+    fn unlink(&self, _: &str) -> Result<(), Errno> {
+        read_only()
+    }
+    fn rmdir(&self, _: &str) -> Result<(), Errno> {
+        read_only()
+    }
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<Inode>, Errno> {
+        read_only()
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        Err(Errno::EINVAL)
+    }
+    fn rename(&self, _: &str, _: &Arc<Inode>, _: &str) -> Result<(), Errno> {
+        read_only()
+    }
+    fn insert_child(&self, _: &str, _: Arc<Inode>) -> Result<(), Errno> {
+        read_only()
+    }
+}
+
+/// /proc: the fixed status files, then a directory per live task. The
+/// task directories are kept while their task exists so that their inode
+/// numbers stay put between lookups.
+struct Root {
+    files: Vec<(&'static str, Arc<Inode>)>,
+    tasks: SpinLock<BTreeMap<u32, Arc<Inode>>>,
+}
+
+impl Root {
+    /// The directories of the tasks alive right now, by pid.
+    fn task_dirs(&self) -> Vec<(u32, Arc<Inode>)> {
+        let live: Vec<u32> = proc::task_snapshot().iter().map(|t| t.pid).collect();
+        let mut dirs = self.tasks.lock();
+        dirs.retain(|pid, _| live.contains(pid));
+        live.iter()
+            .map(|&pid| {
+                let dir = dirs.entry(pid).or_insert_with(|| task_dir(pid));
+                (pid, Arc::clone(dir))
+            })
+            .collect()
+    }
+}
+
+impl Listing for Root {
+    fn entries(&self) -> Vec<(String, Arc<Inode>)> {
+        let files = self.files.iter().map(|(n, i)| (n.to_string(), Arc::clone(i)));
+        let tasks = self.task_dirs().into_iter().map(|(pid, i)| (pid.to_string(), i));
+        files.chain(tasks).collect()
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        if let Some((_, i)) = self.files.iter().find(|(n, _)| *n == name) {
+            return Some(Arc::clone(i));
+        }
+        let pid: u32 = name.parse().ok()?;
+        proc::task_info(pid)?;
+        let mut dirs = self.tasks.lock();
+        Some(Arc::clone(dirs.entry(pid).or_insert_with(|| task_dir(pid))))
+    }
+}
+
+/// /proc/<pid>: files describing one task.
+struct TaskDir {
+    files: Vec<(&'static str, Arc<Inode>)>,
+}
+
+impl Listing for TaskDir {
+    fn entries(&self) -> Vec<(String, Arc<Inode>)> {
+        self.files
+            .iter()
+            .map(|(n, i)| (n.to_string(), Arc::clone(i)))
+            .collect()
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        self.files
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, i)| Arc::clone(i))
+    }
+}
+
+fn task_dir(pid: u32) -> Arc<Inode> {
+    let stat: Generator = Box::new(move || proc::task_info(pid).map(|t| task_stat(&t)));
+    ProcDir::new_inode(TaskDir {
+        files: alloc::vec![("stat", ProcFile::new_inode(stat))],
+    })
+}
+
+/// One line in the layout of Linux's /proc/<pid>/stat, through field 23
+/// (vsize). Times are in timer ticks; fields this kernel has no notion of
+/// read as 0.
+pub fn task_stat(t: &TaskInfo) -> String {
+    let threads = proc::task_snapshot()
+        .iter()
+        .filter(|o| o.tgid == t.tgid)
+        .count();
+    format!(
+        "{} ({}) {} {} {} {} 0 0 0 0 0 0 0 {} {} 0 0 {} {} {} 0 {} {}\n",
+        t.pid,
+        t.name,
+        t.state.code(),
+        t.ppid,
+        t.pgid,
+        t.sid,
+        t.utime,
+        t.stime,
+        t.priority,
+        t.nice,
+        threads,
+        t.start_time,
+        t.vsize,
+    )
+}
+
+/// Text of one of the fixed files.
+type Status = fn() -> String;
+
+/// Put the /proc directory into the root filesystem.
+pub fn populate(vfs: &VfsContext) -> Result<(), Errno> {
+    let fixed: [(&str, Status); 4] = [
+        ("buddyinfo", crate::mm::pmm::buddyinfo),
+        ("cmdline", crate::cmdline::proc_cmdline),
+        ("slabinfo", crate::mm::heap::slabinfo),
+        ("vmallocinfo", crate::mm::vmalloc::vmallocinfo),
+    ];
+    let files = fixed
+        .into_iter()
+        .map(|(name, f)| (name, ProcFile::new_inode(Box::new(move || Some(f())))))
+        .collect();
+    let root = ProcDir::new_inode(Root {
+        files,
+        tasks: SpinLock::new(BTreeMap::new()),
+    });
+    vfs.root.ops.insert_child("proc", root)
+}
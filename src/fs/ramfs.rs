@@ -1,24 +1,66 @@
-use super::vfs::{alloc_ino, DirEntry, Errno, FileType, Filesystem, Ino, Inode, InodeOps, Stat};
+use super::vfs::{
+    alloc_ino, DirEntry, Errno, FileType, Filesystem, Ino, Inode, InodeOps, Stat, Timespec,
+};
 use crate::sync::spinlock::SpinLock;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+/// The parts of the stat that aren't the data.
+#[derive(Clone, Copy)]
+struct Meta {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
+}
+
+impl Meta {
+    fn new(mode: u32) -> SpinLock<Meta> {
+        let now = Timespec::now();
+        SpinLock::new(Meta { mode, uid: 0, gid: 0, atime: now, mtime: now, ctime: now })
+    }
+
+    fn stat(&self, ino: Ino, kind: FileType, size: u64, nlink: u32) -> Stat {
+        Stat {
+            ino,
+            kind,
+            size,
+            mode: self.mode,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            atime: self.atime,
+            mtime: self.mtime,
+            ctime: self.ctime,
+        }
+    }
+
+    /// The data changed.
+    fn modified(&mut self) {
+        self.mtime = Timespec::now();
+        self.ctime = self.mtime;
+    }
+}
+
 pub struct RamDir {
     ino: Ino,
-    mode: u32,
+    meta: SpinLock<Meta>,
     children: SpinLock<BTreeMap<String, Arc<Inode>>>,
 }
 
 pub struct RamFile {
     ino: Ino,
-    mode: u32,
+    meta: SpinLock<Meta>,
     data: SpinLock<Vec<u8>>,
 }
 
 pub struct RamSymlink {
     ino: Ino,
+    meta: SpinLock<Meta>,
     target: String,
 }
 
@@ -32,11 +74,23 @@ fn no_link<T>() -> Result<T, Errno> {
     Err(Errno::EINVAL)
 }
 
+fn set_times(
+    meta: &SpinLock<Meta>,
+    atime: Option<Timespec>,
+    mtime: Option<Timespec>,
+) -> Result<(), Errno> {
+    let mut m = meta.lock();
+    m.atime = atime.unwrap_or(m.atime);
+    m.mtime = mtime.unwrap_or(m.mtime);
+    m.ctime = Timespec::now();
+    Ok(())
+}
+
 impl RamDir {
     pub fn new_inode(mode: u32) -> Arc<Inode> {
         let ops = Arc::new(RamDir {
             ino: alloc_ino(),
-            mode,
+            meta: Meta::new(mode),
             children: SpinLock::new(BTreeMap::new()),
         });
         let ino = ops.ino;
@@ -46,15 +100,7 @@ impl RamDir {
 
 impl InodeOps for RamDir {
     fn stat(&self) -> Stat {
-        Stat {
-            ino: self.ino,
-            kind: FileType::Directory,
-            size: 0,
-            mode: self.mode,
-            nlink: 2,
-            uid: 0,
-            gid: 0,
-        }
+        self.meta.lock().stat(self.ino, FileType::Directory, 0, 2)
     }
     fn read(&self, _: u64, _: &mut [u8]) -> Result<usize, Errno> {
         is_dir()
@@ -86,11 +132,12 @@ impl InodeOps for RamDir {
         }
         let ops = Arc::new(RamFile {
             ino: alloc_ino(),
-            mode,
+            meta: Meta::new(mode),
             data: SpinLock::new(Vec::new()),
         });
         let inode = Inode::new(ops.ino, ops);
         ch.insert(name.to_string(), Arc::clone(&inode));
+        self.meta.lock().modified();
         Ok(inode)
     }
 
@@ -101,6 +148,7 @@ impl InodeOps for RamDir {
         }
         let inode = RamDir::new_inode(mode);
         ch.insert(name.to_string(), Arc::clone(&inode));
+        self.meta.lock().modified();
         Ok(inode)
     }
 
@@ -112,6 +160,7 @@ impl InodeOps for RamDir {
             _ => {}
         }
         ch.remove(name);
+        self.meta.lock().modified();
         Ok(())
     }
 
@@ -124,6 +173,7 @@ impl InodeOps for RamDir {
             _ => {}
         }
         ch.remove(name);
+        self.meta.lock().modified();
         Ok(())
     }
 
@@ -134,10 +184,12 @@ impl InodeOps for RamDir {
         }
         let ops = Arc::new(RamSymlink {
             ino: alloc_ino(),
+            meta: Meta::new(0o777),
             target: target.to_string(),
         });
         let inode = Inode::new(ops.ino, ops);
         ch.insert(name.to_string(), Arc::clone(&inode));
+        self.meta.lock().modified();
         Ok(inode)
     }
 
@@ -147,28 +199,37 @@ impl InodeOps for RamDir {
 
     fn rename(&self, old: &str, new_dir: &Arc<Inode>, new: &str) -> Result<(), Errno> {
         let inode = self.children.lock().remove(old).ok_or(Errno::ENOENT)?;
+        self.meta.lock().modified();
         new_dir.ops.insert_child(new, inode)
     }
 
     fn insert_child(&self, name: &str, child: Arc<Inode>) -> Result<(), Errno> {
         self.children.lock().insert(name.to_string(), child);
+        self.meta.lock().modified();
+        Ok(())
+    }
+    fn chmod(&self, mode: u32) -> Result<(), Errno> {
+        let mut m = self.meta.lock();
+        (m.mode, m.ctime) = (mode, Timespec::now());
+        Ok(())
+    }
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), Errno> {
+        let mut m = self.meta.lock();
+        (m.uid, m.gid, m.ctime) = (uid, gid, Timespec::now());
         Ok(())
     }
+    fn set_times(&self, atime: Option<Timespec>, mtime: Option<Timespec>) -> Result<(), Errno> {
+        set_times(&self.meta, atime, mtime)
+    }
 }
 
 impl InodeOps for RamFile {
     fn stat(&self) -> Stat {
-        Stat {
-            ino: self.ino,
-            kind: FileType::Regular,
-            size: self.data.lock().len() as u64,
-            mode: self.mode,
-            nlink: 1,
-            uid: 0,
-            gid: 0,
-        }
+        let size = self.data.lock().len() as u64;
+        self.meta.lock().stat(self.ino, FileType::Regular, size, 1)
     }
     fn read(&self, offset: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.meta.lock().atime = Timespec::now();
         let data = self.data.lock();
         let off = offset as usize;
         if off >= data.len() {
@@ -186,10 +247,12 @@ impl InodeOps for RamFile {
             data.resize(end, 0);
         }
         data[off..end].copy_from_slice(buf);
+        self.meta.lock().modified();
         Ok(buf.len())
     }
     fn truncate(&self, size: u64) -> Result<(), Errno> {
         self.data.lock().resize(size as usize, 0);
+        self.meta.lock().modified();
         Ok(())
     }
     fn lookup(&self, _: &str) -> Result<Arc<Inode>, Errno> {
@@ -222,19 +285,33 @@ impl InodeOps for RamFile {
     fn insert_child(&self, _: &str, _: Arc<Inode>) -> Result<(), Errno> {
         not_dir()
     }
+    fn chmod(&self, mode: u32) -> Result<(), Errno> {
+        let mut m = self.meta.lock();
+        (m.mode, m.ctime) = (mode, Timespec::now());
+        Ok(())
+    }
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), Errno> {
+        let mut m = self.meta.lock();
+        (m.uid, m.gid, m.ctime) = (uid, gid, Timespec::now());
+        Ok(())
+    }
+    fn set_times(&self, atime: Option<Timespec>, mtime: Option<Timespec>) -> Result<(), Errno> {
+        set_times(&self.meta, atime, mtime)
+    }
 }
 
 impl InodeOps for RamSymlink {
     fn stat(&self) -> Stat {
-        Stat {
-            ino: self.ino,
-            kind: FileType::Symlink,
-            size: self.target.len() as u64,
-            mode: 0o777,
-            nlink: 1,
-            uid: 0,
-            gid: 0,
-        }
+        self.meta.lock().stat(self.ino, FileType::Symlink, self.target.len() as u64, 1)
+    }
+    // A symlink's own mode means nothing; chmod goes to its target
+    fn chown(&self, uid: u32, gid: u32) -> Result<(), Errno> {
+        let mut m = self.meta.lock();
+        (m.uid, m.gid, m.ctime) = (uid, gid, Timespec::now());
+        Ok(())
+    }
+    fn set_times(&self, atime: Option<Timespec>, mtime: Option<Timespec>) -> Result<(), Errno> {
+        set_times(&self.meta, atime, mtime)
     }
     fn readlink(&self) -> Result<String, Errno> {
         Ok(self.target.clone())
@@ -1,3 +1,4 @@
+use super::path::Location;
 use alloc::string::String;
 use alloc::sync::Arc;
 use core::sync::atomic::{AtomicU64, Ordering};
@@ -15,6 +16,44 @@ pub enum FileType {
     Directory,
     Symlink,
     CharDevice,
+    Fifo,
+    Socket,
+}
+
+impl FileType {
+    /// The S_IF* bits for the type, as st_mode carries them.
+    pub fn mode_bits(self) -> u32 {
+        match self {
+            FileType::Regular => S_IFREG,
+            FileType::Directory => S_IFDIR,
+            FileType::Symlink => S_IFLNK,
+            FileType::CharDevice => S_IFCHR,
+            FileType::Fifo => S_IFIFO,
+            FileType::Socket => S_IFSOCK,
+        }
+    }
+}
+
+/// A point in wall-clock time: Unix seconds and nanoseconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timespec {
+    pub sec: i64,
+    pub nsec: u32,
+}
+
+impl Timespec {
+    pub fn now() -> Self {
+        let ns = crate::arch::x86_64::timer::realtime();
+        Timespec { sec: (ns / 1_000_000_000) as i64, nsec: (ns % 1_000_000_000) as u32 }
+    }
+}
+
+/// One of the times utimensat sets: left as it is, the present, or given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeSet {
+    Omit,
+    Now,
+    At(Timespec),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -26,6 +65,11 @@ pub struct Stat {
     pub nlink: u32,
     pub uid: u32,
     pub gid: u32,
+    /// Last read, last change of the data, last change of the inode
+    /// (data, owner, mode...).
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
 }
 
 #[derive(Clone)]
@@ -50,6 +94,27 @@ pub trait InodeOps: Send + Sync {
     fn readlink(&self) -> Result<String, Errno>;
     fn rename(&self, old_name: &str, new_dir: &Arc<Inode>, new_name: &str) -> Result<(), Errno>;
     fn insert_child(&self, name: &str, child: Arc<Inode>) -> Result<(), Errno>;
+    /// Set the permission bits (the low 12 of st_mode). Filesystems that
+    /// keep no ownership refuse both this and `chown`.
+    fn chmod(&self, _mode: u32) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+    fn chown(&self, _uid: u32, _gid: u32) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+    /// Set the access and modification times, None leaving one as it is;
+    /// the change time becomes now.
+    fn set_times(&self, _atime: Option<Timespec>, _mtime: Option<Timespec>) -> Result<(), Errno> {
+        Err(Errno::EPERM)
+    }
+    /// Device control; only terminals have any.
+    fn ioctl(&self, _cmd: u32, _arg: u64) -> Result<i64, Errno> {
+        Err(Errno::ENOTTY)
+    }
+    /// The socket behind the inode, for the socket syscalls.
+    fn as_socket(&self) -> Option<&crate::net::socket::Socket> {
+        None
+    }
 }
 
 pub struct Inode {
@@ -79,6 +144,8 @@ pub struct File {
     pub inode: Arc<Inode>,
     pub offset: crate::sync::spinlock::SpinLock<u64>,
     pub flags: u32,
+    /// Where a directory was opened, for the *at calls to start from.
+    location: Option<Location>,
 }
 
 impl File {
@@ -87,10 +154,34 @@ impl File {
             inode,
             offset: crate::sync::spinlock::SpinLock::new(0),
             flags,
+            location: None,
         })
     }
 
+    /// A file opened by walking to `loc`.
+    pub fn with_location(loc: Location, flags: u32) -> Arc<Self> {
+        Arc::new(Self {
+            inode: Arc::clone(loc.inode()),
+            offset: crate::sync::spinlock::SpinLock::new(0),
+            flags,
+            location: Some(loc),
+        })
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        self.location.as_ref()
+    }
+
+    /// Devices and pipes have no file position; their reads and writes may
+    /// block, so they must not run under the offset lock.
+    fn is_stream(&self) -> bool {
+        matches!(self.inode.stat().kind, FileType::CharDevice | FileType::Fifo | FileType::Socket)
+    }
+
     pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        if self.is_stream() {
+            return self.inode.ops.read(0, buf);
+        }
         let mut off = self.offset.lock();
         let n = self.inode.ops.read(*off, buf)?;
         *off += n as u64;
@@ -98,6 +189,9 @@ impl File {
     }
 
     pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        if self.is_stream() {
+            return self.inode.ops.write(0, buf);
+        }
         let mut off = self.offset.lock();
         if self.flags & O_APPEND != 0 {
             *off = self.inode.stat().size;
@@ -107,9 +201,41 @@ impl File {
         Ok(n)
     }
 
+    pub fn ioctl(&self, cmd: u32, arg: u64) -> Result<i64, Errno> {
+        self.inode.ops.ioctl(cmd, arg)
+    }
+
     pub fn seek_set(&self, pos: u64) {
         *self.offset.lock() = pos;
     }
+
+    /// lseek(2): `whence` is SEEK_SET / SEEK_CUR / SEEK_END.
+    pub fn seek(&self, off: i64, whence: u32) -> Result<u64, Errno> {
+        if self.is_stream() {
+            return Err(Errno::ESPIPE);
+        }
+        let mut cur = self.offset.lock();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => *cur as i64,
+            SEEK_END => self.inode.stat().size as i64,
+            _ => return Err(Errno::EINVAL),
+        };
+        let new = base.checked_add(off).ok_or(Errno::EINVAL)?;
+        if new < 0 {
+            return Err(Errno::EINVAL);
+        }
+        *cur = new as u64;
+        Ok(*cur)
+    }
+
+    pub fn readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    pub fn writable(&self) -> bool {
+        self.flags & O_ACCMODE != O_RDONLY
+    }
     pub fn tell(&self) -> u64 {
         *self.offset.lock()
     }
@@ -133,23 +259,46 @@ pub trait Filesystem: Send + Sync {
 pub struct Errno(pub i64);
 
 impl Errno {
+    pub const EPERM: Errno = Errno(1);
     pub const ENOENT: Errno = Errno(2);
+    pub const EINTR: Errno = Errno(4);
     pub const EIO: Errno = Errno(5);
+    pub const E2BIG: Errno = Errno(7);
     pub const EBADF: Errno = Errno(9);
+    pub const EAGAIN: Errno = Errno(11);
     pub const ENOMEM: Errno = Errno(12);
     pub const EACCES: Errno = Errno(13);
+    pub const EFAULT: Errno = Errno(14);
+    pub const EBUSY: Errno = Errno(16);
     pub const EEXIST: Errno = Errno(17);
     pub const ENOTDIR: Errno = Errno(20);
     pub const EISDIR: Errno = Errno(21);
     pub const EINVAL: Errno = Errno(22);
+    pub const ENOTTY: Errno = Errno(25);
+    pub const EFBIG: Errno = Errno(27);
     pub const ENOSPC: Errno = Errno(28);
+    pub const ESPIPE: Errno = Errno(29);
+    pub const EROFS: Errno = Errno(30);
+    pub const EPIPE: Errno = Errno(32);
+    pub const ENAMETOOLONG: Errno = Errno(36);
     pub const ENOTEMPTY: Errno = Errno(39);
+    pub const ELOOP: Errno = Errno(40);
+    pub const EDESTADDRREQ: Errno = Errno(89);
+    pub const EMSGSIZE: Errno = Errno(90);
     pub const ENOTSUP: Errno = Errno(95);
+    pub const EADDRINUSE: Errno = Errno(98);
+    pub const EADDRNOTAVAIL: Errno = Errno(99);
+    pub const ENETUNREACH: Errno = Errno(101);
+    pub const ECONNRESET: Errno = Errno(104);
+    pub const ENOTCONN: Errno = Errno(107);
+    pub const ETIMEDOUT: Errno = Errno(110);
+    pub const ECONNREFUSED: Errno = Errno(111);
     pub fn as_neg_i64(self) -> i64 {
         -self.0
     }
 }
 
+pub const O_ACCMODE: u32 = 3;
 pub const O_RDONLY: u32 = 0;
 pub const O_WRONLY: u32 = 1;
 pub const O_RDWR: u32 = 2;
@@ -157,3 +306,14 @@ pub const O_CREAT: u32 = 0o100;
 pub const O_TRUNC: u32 = 0o1000;
 pub const O_APPEND: u32 = 0o2000;
 pub const O_DIRECTORY: u32 = 0o200000;
+
+pub const SEEK_SET: u32 = 0;
+pub const SEEK_CUR: u32 = 1;
+pub const SEEK_END: u32 = 2;
+
+pub const S_IFIFO: u32 = 0o010000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+pub const S_IFSOCK: u32 = 0o140000;
@@ -0,0 +1,548 @@
+//! GDB remote stub on COM2, for debugging the kernel itself.
+//!
+//! GDB talks to the other end of the second serial port; with QEMU,
+//! `-serial stdio -serial tcp::1234,server,nowait` and `target remote
+//! :1234`. The stub runs inside the exception that stopped the kernel,
+//! interrupts off, polling the port: a breakpoint (GDB's or an `int3` in
+//! the code), the debug exception after a single step, or the COM2
+//! interrupt when GDB sends a break (Ctrl-C) or a packet while the kernel
+//! runs, which is how it attaches. `gdb` on the kernel command line stops
+//! at boot to wait for it.
+//!
+//! Memory goes through the HHDM alias of each page once the live page
+//! tables say it is mapped, so a bad address is an error reply rather than
+//! a fault, and breakpoints can go into the read-only kernel text. There is
+//! one CPU, so the only thread is whatever was running.
+use crate::arch::x86_64::idt::InterruptFrame;
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::drivers::serial::{self, COM2};
+use crate::sync::spinlock::SpinLock;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Signals the stop replies report.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+const PACKET_SIZE: usize = 4096;
+const MAX_BREAKPOINTS: usize = 32;
+const INT3: u8 = 0xCC;
+const RFLAGS_TF: u64 = 1 << 8;
+
+/// GDB's amd64 register numbers: RAX…R15 and RIP are 8 bytes in the `g`
+/// packet, EFLAGS and the segment registers after them 4 bytes each.
+const GPRS: usize = 17;
+const REG_EFLAGS: usize = 17;
+const REGS: usize = 24;
+
+/// COM2 answered the loopback test.
+static PRESENT: AtomicBool = AtomicBool::new(false);
+/// A debugger is attached: breakpoints stop for it instead of being logged.
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+/// GDB resumed the kernel and waits for the stop reply.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+/// The next #DB ends a single step GDB asked for.
+static STEPPING: AtomicBool = AtomicBool::new(false);
+/// The stub is running: an exception inside it must not start another.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+struct Stub {
+    packet: [u8; PACKET_SIZE],
+    reply: [u8; PACKET_SIZE],
+    /// Address and the byte the int3 replaced.
+    breakpoints: [Option<(u64, u8)>; MAX_BREAKPOINTS],
+    /// Read by the COM2 interrupt before the stub took over: the '$' of
+    /// the packet that woke it.
+    unread: Option<u8>,
+}
+
+static STUB: SpinLock<Stub> = SpinLock::new(Stub {
+    packet: [0; PACKET_SIZE],
+    reply: [0; PACKET_SIZE],
+    breakpoints: [None; MAX_BREAKPOINTS],
+    unread: None,
+});
+
+/// What to do after a packet.
+enum Action {
+    /// Send the reply of this length and wait for the next packet.
+    Reply(usize),
+    /// Let the kernel run, one instruction only if `step`.
+    Resume { step: bool },
+    /// GDB leaves: breakpoints out, kernel resumed. `D` is answered, `k`
+    /// isn't.
+    Detach { reply: bool },
+}
+
+// ─── Packet I/O ──────────────────────────────────────────────────────────────
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter()
+        .try_fold(0u64, |v, &b| Some(v << 4 | hex_digit(b)? as u64))
+}
+
+/// A little-endian value as GDB writes registers: `bytes` bytes, low first.
+fn parse_le(s: &[u8], bytes: usize) -> Option<u64> {
+    if s.len() < bytes * 2 {
+        return None;
+    }
+    (0..bytes).try_fold(0u64, |v, i| {
+        let b = hex_digit(s[2 * i])? << 4 | hex_digit(s[2 * i + 1])?;
+        Some(v | (b as u64) << (8 * i))
+    })
+}
+
+/// The reply being built. Anything past the buffer is dropped; `m` is the
+/// only command with a reply that long, and it asks for less.
+struct Out<'a> {
+    buf: &'a mut [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Out<'_> {
+    fn byte(&mut self, b: u8) {
+        if self.len < PACKET_SIZE {
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+    }
+
+    fn str(&mut self, s: &str) {
+        s.bytes().for_each(|b| self.byte(b));
+    }
+
+    fn hex(&mut self, b: u8) {
+        self.byte(DIGITS[(b >> 4) as usize]);
+        self.byte(DIGITS[(b & 0xF) as usize]);
+    }
+
+    fn le(&mut self, v: u64, bytes: usize) {
+        (0..bytes).for_each(|i| self.hex((v >> (8 * i)) as u8));
+    }
+}
+
+impl Stub {
+    fn getc(&mut self) -> u8 {
+        if let Some(b) = self.unread.take() {
+            return b;
+        }
+        loop {
+            if let Some(b) = serial::poll_byte(COM2) {
+                return b;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// The next well-formed packet into `packet`, acknowledged; its length.
+    fn receive(&mut self) -> usize {
+        loop {
+            while self.getc() != b'$' {}
+            let mut len = 0;
+            let mut sum = 0u8;
+            loop {
+                match self.getc() {
+                    b'#' => break,
+                    // GDB gave up on that one and started over
+                    b'$' => (len, sum) = (0, 0),
+                    b => {
+                        if len < PACKET_SIZE {
+                            self.packet[len] = b;
+                            len += 1;
+                        }
+                        sum = sum.wrapping_add(b);
+                    }
+                }
+            }
+            let (hi, lo) = (self.getc(), self.getc());
+            if hex_digit(hi).zip(hex_digit(lo)) == Some((sum >> 4, sum & 0xF)) {
+                serial::put_byte(COM2, b'+');
+                return len;
+            }
+            serial::put_byte(COM2, b'-');
+        }
+    }
+
+    /// Send the first `len` bytes of `reply` until GDB acknowledges them.
+    fn send(&mut self, len: usize) {
+        loop {
+            serial::put_byte(COM2, b'$');
+            let mut sum = 0u8;
+            for i in 0..len {
+                serial::put_byte(COM2, self.reply[i]);
+                sum = sum.wrapping_add(self.reply[i]);
+            }
+            serial::put_byte(COM2, b'#');
+            serial::put_byte(COM2, DIGITS[(sum >> 4) as usize]);
+            serial::put_byte(COM2, DIGITS[(sum & 0xF) as usize]);
+            loop {
+                match self.getc() {
+                    b'+' => return,
+                    b'-' => break,
+                    // No ack, a packet instead: GDB moved on
+                    b'$' => {
+                        self.unread = Some(b'$');
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// ─── Registers and memory ────────────────────────────────────────────────────
+
+/// Register `n` of the stopped code, if GDB may have it.
+fn reg(frame: &InterruptFrame, n: usize) -> Option<u64> {
+    let f = frame;
+    Some(match n {
+        0 => f.rax,
+        1 => f.rbx,
+        2 => f.rcx,
+        3 => f.rdx,
+        4 => f.rsi,
+        5 => f.rdi,
+        6 => f.rbp,
+        7 => f.rsp,
+        8 => f.r8,
+        9 => f.r9,
+        10 => f.r10,
+        11 => f.r11,
+        12 => f.r12,
+        13 => f.r13,
+        14 => f.r14,
+        15 => f.r15,
+        16 => f.rip,
+        REG_EFLAGS => f.rflags,
+        18 => f.cs,
+        // SS, and DS and ES, which are the same in long mode; FS and GS
+        // selectors are always null here
+        19..=21 => f.ss,
+        22 | 23 => 0,
+        _ => return None,
+    })
+}
+
+/// Change register `n`. The segment registers stay what they are: a wrong
+/// selector would fault the return from the exception.
+fn set_reg(frame: &mut InterruptFrame, n: usize, v: u64) -> bool {
+    let f = frame;
+    let r = match n {
+        0 => &mut f.rax,
+        1 => &mut f.rbx,
+        2 => &mut f.rcx,
+        3 => &mut f.rdx,
+        4 => &mut f.rsi,
+        5 => &mut f.rdi,
+        6 => &mut f.rbp,
+        7 => &mut f.rsp,
+        8 => &mut f.r8,
+        9 => &mut f.r9,
+        10 => &mut f.r10,
+        11 => &mut f.r11,
+        12 => &mut f.r12,
+        13 => &mut f.r13,
+        14 => &mut f.r14,
+        15 => &mut f.r15,
+        16 => &mut f.rip,
+        REG_EFLAGS => {
+            // The low 32 bits are all GDB has
+            f.rflags = (f.rflags & !0xFFFF_FFFF) | (v & 0xFFFF_FFFF);
+            return true;
+        }
+        18..REGS => return true,
+        _ => return false,
+    };
+    *r = v;
+    true
+}
+
+fn reg_size(n: usize) -> usize {
+    if n < GPRS {
+        8
+    } else {
+        4
+    }
+}
+
+/// The HHDM alias of `va`, if it is mapped.
+fn alias(va: u64) -> Option<*mut u8> {
+    crate::mm::vmm::translate_current(va).map(|phys| phys_to_virt(phys) as *mut u8)
+}
+
+fn read_byte(va: u64) -> Option<u8> {
+    alias(va).map(|p| unsafe { core::ptr::read_volatile(p) })
+}
+
+fn write_byte(va: u64, b: u8) -> bool {
+    alias(va)
+        .map(|p| unsafe { core::ptr::write_volatile(p, b) })
+        .is_some()
+}
+
+// ─── Commands ────────────────────────────────────────────────────────────────
+
+/// "addr,len" as in `m`, `M` and `Z`.
+fn addr_len(s: &[u8]) -> Option<(u64, u64)> {
+    let comma = s.iter().position(|&b| b == b',')?;
+    Some((parse_hex(&s[..comma])?, parse_hex(&s[comma + 1..])?))
+}
+
+fn insert_breakpoint(bps: &mut [Option<(u64, u8)>], addr: u64) -> bool {
+    if bps.iter().flatten().any(|&(a, _)| a == addr) {
+        return true;
+    }
+    let Some(slot) = bps.iter_mut().find(|b| b.is_none()) else {
+        return false;
+    };
+    let Some(orig) = read_byte(addr) else {
+        return false;
+    };
+    if !write_byte(addr, INT3) {
+        return false;
+    }
+    *slot = Some((addr, orig));
+    true
+}
+
+fn remove_breakpoint(bps: &mut [Option<(u64, u8)>], addr: u64) -> bool {
+    for bp in bps.iter_mut() {
+        if let Some((a, orig)) = *bp {
+            if a == addr {
+                write_byte(a, orig);
+                *bp = None;
+                return true;
+            }
+        }
+    }
+    false
+}
+
+impl Stub {
+    fn handle(&mut self, frame: &mut InterruptFrame, len: usize, signal: u8) -> Action {
+        let Stub {
+            packet,
+            reply,
+            breakpoints,
+            ..
+        } = self;
+        let p = &packet[..len];
+        let mut out = Out { buf: reply, len: 0 };
+        let Some((&cmd, args)) = p.split_first() else {
+            return Action::Reply(0);
+        };
+        match cmd {
+            b'?' => {
+                out.byte(b'S');
+                out.hex(signal);
+            }
+            b'g' => {
+                for n in 0..REGS {
+                    out.le(reg(frame, n).unwrap_or(0), reg_size(n));
+                }
+            }
+            b'G' => {
+                let mut at = 0;
+                for n in 0..REGS {
+                    let size = reg_size(n);
+                    let Some(v) = args.get(at..).and_then(|s| parse_le(s, size)) else {
+                        break;
+                    };
+                    set_reg(frame, n, v);
+                    at += size * 2;
+                }
+                out.str("OK");
+            }
+            b'p' => match parse_hex(args).and_then(|n| reg(frame, n as usize).map(|v| (n, v))) {
+                Some((n, v)) => out.le(v, reg_size(n as usize)),
+                None => out.str("E16"),
+            },
+            b'P' => {
+                let eq = args.iter().position(|&b| b == b'=');
+                let set = eq.and_then(|eq| {
+                    let n = parse_hex(&args[..eq])? as usize;
+                    let v = parse_le(&args[eq + 1..], reg_size(n))?;
+                    set_reg(frame, n, v).then_some(())
+                });
+                out.str(if set.is_some() { "OK" } else { "E16" });
+            }
+            b'm' => match addr_len(args) {
+                Some((addr, n)) => {
+                    let n = n.min((PACKET_SIZE / 2) as u64);
+                    for i in 0..n {
+                        let Some(b) = read_byte(addr.wrapping_add(i)) else {
+                            // A short read is fine, unless nothing was read
+                            if i == 0 {
+                                out.str("E14");
+                            }
+                            break;
+                        };
+                        out.hex(b);
+                    }
+                }
+                None => out.str("E16"),
+            },
+            b'M' => {
+                let colon = args.iter().position(|&b| b == b':');
+                let ok = colon.and_then(|c| {
+                    let (addr, n) = addr_len(&args[..c])?;
+                    let data = &args[c + 1..];
+                    if data.len() as u64 != n * 2 {
+                        return None;
+                    }
+                    (0..n).try_for_each(|i| {
+                        let b = parse_le(&data[2 * i as usize..], 1)? as u8;
+                        write_byte(addr.wrapping_add(i), b).then_some(())
+                    })
+                });
+                out.str(if ok.is_some() { "OK" } else { "E14" });
+            }
+            // Software breakpoints only; anything else is unsupported
+            b'Z' | b'z' if args.starts_with(b"0,") => {
+                let ok = addr_len(&args[2..]).is_some_and(|(addr, _)| {
+                    if cmd == b'Z' {
+                        insert_breakpoint(breakpoints, addr)
+                    } else {
+                        remove_breakpoint(breakpoints, addr)
+                    }
+                });
+                out.str(if ok { "OK" } else { "E14" });
+            }
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    frame.rip = addr;
+                }
+                return Action::Resume { step: cmd == b's' };
+            }
+            b'D' => return Action::Detach { reply: true },
+            b'k' => return Action::Detach { reply: false },
+            b'H' | b'T' => out.str("OK"),
+            b'q' if p.starts_with(b"qSupported") => out.str("PacketSize=1000"),
+            b'q' if p == b"qAttached" => out.byte(b'1'),
+            b'q' if p == b"qC" => out.str("QC1"),
+            // Empty: not supported
+            _ => {}
+        }
+        Action::Reply(out.len)
+    }
+}
+
+// ─── Entry ───────────────────────────────────────────────────────────────────
+
+/// Hold the kernel stopped at `frame` and serve GDB until it resumes it.
+fn stop(frame: &mut InterruptFrame, signal: u8) {
+    if ACTIVE.swap(true, Ordering::Acquire) {
+        return;
+    }
+    let mut stub = STUB.lock();
+    if RUNNING.swap(false, Ordering::Relaxed) {
+        let mut out = Out {
+            buf: &mut stub.reply,
+            len: 0,
+        };
+        out.byte(b'S');
+        out.hex(signal);
+        let len = out.len;
+        stub.send(len);
+    }
+    loop {
+        let len = stub.receive();
+        ATTACHED.store(true, Ordering::Relaxed);
+        match stub.handle(frame, len, signal) {
+            Action::Reply(len) => stub.send(len),
+            Action::Resume { step } => {
+                if step {
+                    frame.rflags |= RFLAGS_TF;
+                } else {
+                    frame.rflags &= !RFLAGS_TF;
+                }
+                STEPPING.store(step, Ordering::Relaxed);
+                RUNNING.store(true, Ordering::Relaxed);
+                break;
+            }
+            Action::Detach { reply } => {
+                if reply {
+                    stub.reply[..2].copy_from_slice(b"OK");
+                    stub.send(2);
+                }
+                for bp in stub.breakpoints.iter_mut() {
+                    if let Some((addr, orig)) = bp.take() {
+                        write_byte(addr, orig);
+                    }
+                }
+                frame.rflags &= !RFLAGS_TF;
+                STEPPING.store(false, Ordering::Relaxed);
+                ATTACHED.store(false, Ordering::Relaxed);
+                break;
+            }
+        }
+    }
+    drop(stub);
+    ACTIVE.store(false, Ordering::Release);
+}
+
+/// #BP in the kernel: GDB's if it is attached. False leaves the trap to
+/// the caller.
+pub fn breakpoint(frame: &mut InterruptFrame) -> bool {
+    if !ATTACHED.load(Ordering::Relaxed) {
+        return false;
+    }
+    stop(frame, SIGTRAP);
+    true
+}
+
+/// #DB: the end of a single step, if GDB asked for one.
+pub fn debug(frame: &mut InterruptFrame) -> bool {
+    if !STEPPING.swap(false, Ordering::Relaxed) {
+        return false;
+    }
+    frame.rflags &= !RFLAGS_TF;
+    stop(frame, SIGTRAP);
+    true
+}
+
+/// COM2's interrupt: GDB breaks in, or attaches with its first packet.
+pub fn irq(frame: &mut InterruptFrame) {
+    if !PRESENT.load(Ordering::Relaxed) {
+        return;
+    }
+    while let Some(b) = serial::poll_byte(COM2) {
+        match b {
+            0x03 => stop(frame, SIGINT),
+            b'$' => {
+                STUB.lock().unread = Some(b'$');
+                stop(frame, SIGINT);
+            }
+            // Acks left over from before a detach
+            _ => {}
+        }
+    }
+}
+
+pub fn init() {
+    if !serial::setup(COM2) {
+        return;
+    }
+    PRESENT.store(true, Ordering::Relaxed);
+    crate::arch::x86_64::apic::enable_irq(3);
+    log::info!("gdbstub: listening on COM2");
+    if crate::arch::x86_64::limine::cmdline_flag("gdb") {
+        log::info!("gdbstub: waiting for GDB");
+        ATTACHED.store(true, Ordering::Relaxed);
+        unsafe { core::arch::asm!("int3") };
+    }
+}
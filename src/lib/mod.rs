@@ -0,0 +1,2 @@
+//! Self-contained algorithms with no ties to the rest of the kernel.
+pub mod hash;
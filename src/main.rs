@@ -16,10 +16,20 @@
 extern crate alloc;
 
 mod arch;
+mod backtrace;
+mod cmdline;
+mod crash;
 mod drivers;
 mod fs;
+mod gdbstub;
+// Spelled out, since a src/lib.rs would be taken for a library crate
+#[path = "lib/mod.rs"]
+mod lib;
 mod mm;
+mod net;
 mod proc;
+#[cfg(feature = "test")]
+mod selftest;
 mod shell;
 mod sync;
 mod syscall;
@@ -42,6 +52,19 @@ static _KADDR_REQ: &arch::x86_64::limine::KernelAddressRequest =
 static _FB_REQ: &arch::x86_64::limine::FramebufferRequest =
     &arch::x86_64::limine::FRAMEBUFFER_REQUEST;
 
+#[link_section = ".limine_reqs"]
+#[used]
+static _RSDP_REQ: &arch::x86_64::limine::RsdpRequest = &arch::x86_64::limine::RSDP_REQUEST;
+
+#[link_section = ".limine_reqs"]
+#[used]
+static _MODULE_REQ: &arch::x86_64::limine::ModuleRequest = &arch::x86_64::limine::MODULE_REQUEST;
+
+#[link_section = ".limine_reqs"]
+#[used]
+static _EXEC_FILE_REQ: &arch::x86_64::limine::ExecutableFileRequest =
+    &arch::x86_64::limine::EXECUTABLE_FILE_REQUEST;
+
 const KERNEL_STACK_SIZE: usize = 64 * 1024;
 
 #[repr(C, align(16))]
@@ -55,30 +78,50 @@ pub extern "C" fn kernel_main() -> ! {
     serial_println!("=== Kernel booting ===");
 
     drivers::logger::init();
+    cmdline::init();
+    drivers::vga::early_init();
     log::info!("Logger initialized");
+    log::info!("Command line: '{}'", arch::x86_64::limine::kernel_cmdline());
 
     let kernel_stack_top = unsafe { KERNEL_STACK.0.as_ptr().add(KERNEL_STACK_SIZE) as u64 };
     arch::x86_64::init_bsp(kernel_stack_top);
 
     mm::pmm::init();
+    mm::heap::online();
     log::info!(
         "PMM: {} MiB free / {} MiB total",
         mm::pmm::free_pages() * mm::PAGE_SIZE / 1024 / 1024,
         mm::pmm::total_pages() * mm::PAGE_SIZE / 1024 / 1024,
     );
+    if arch::x86_64::limine::cmdline_flag("pmm.selftest") {
+        match mm::pmm::selftest() {
+            Ok(()) => log::info!("PMM: self-test passed"),
+            Err(e) => panic!("PMM self-test failed: {}", e),
+        }
+    }
 
     mm::vmm::init();
+    mm::vmalloc::init();
+    mm::kstack::init();
     log::info!("VMM initialized");
 
+    arch::x86_64::apic::init();
+    gdbstub::init();
+
     drivers::vga::init();
-    drivers::vga::set_color(drivers::vga::LIGHT_GREEN, drivers::vga::BLACK);
-    println!("SarOS 0.1.0");
-    drivers::vga::set_color(drivers::vga::WHITE, drivers::vga::BLACK);
+    // On VT1, waiting there for init; the log VT is on screen for now
+    drivers::vga::write_vt(0, "\x1b[92mSarOS 0.1.0\x1b[0m\n");
 
     drivers::pci::init();
     drivers::ide::init();
+    drivers::ahci::init();
+    drivers::nvme::init();
+    drivers::e1000::init();
 
     fs::init_rootfs();
+    fs::cpio::load_initrd();
+    fs::mount_root();
+    drivers::vga::load_boot_font();
     log::info!("Filesystem initialized");
 
     drivers::keyboard::init();
@@ -88,17 +131,26 @@ pub extern "C" fn kernel_main() -> ! {
     log::info!("Interrupts enabled");
 
     arch::x86_64::timer::calibrate_tsc();
+    arch::x86_64::timer::init_tick_source();
+    drivers::rtc::init();
 
     let idle = proc::Process::new_kernel("idle", idle_task, u8::MAX);
     if let Some(p) = idle {
         proc::scheduler::spawn(p);
     }
 
-    let sh = proc::Process::new_kernel("shell", shell_task, 5);
-    if let Some(p) = sh {
+    proc::workqueue::init();
+    net::init();
+
+    // A test kernel runs its self-tests instead of init
+    #[cfg(not(feature = "test"))]
+    proc::init::start();
+    #[cfg(feature = "test")]
+    if let Some(p) = proc::Process::new_kernel("selftest", selftest::run, 5) {
         proc::scheduler::spawn(p);
     }
 
+    drivers::vga::boot_done();
     proc::scheduler::schedule();
 
     loop {
@@ -106,30 +158,30 @@ pub extern "C" fn kernel_main() -> ! {
     }
 }
 
+/// Runs only when nothing else can. An interrupt that wakes a task hands
+/// the CPU over right away; without a periodic tick there may be no timer
+/// IRQ to preempt us soon.
 fn idle_task() -> ! {
+    use arch::x86_64::io::{cli, sti, sti_hlt};
     loop {
-        arch::x86_64::io::hlt();
+        cli();
+        if proc::scheduler::has_runnable() {
+            sti();
+            proc::scheduler::schedule();
+        } else {
+            sti_hlt();
+        }
     }
 }
 
-fn shell_task() -> ! {
-    shell::spawn_shell();
-}
-
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     arch::x86_64::io::cli();
+    crash::report(info);
+    #[cfg(feature = "test")]
+    selftest::panicked();
 
-    serial_println!("\n\n=== KERNEL PANIC ===");
-    serial_println!("{}", info);
-
-    drivers::vga::set_color(drivers::vga::WHITE, drivers::vga::RED);
-    println!("\n *** KERNEL PANIC *** ");
-    if let Some(loc) = info.location() {
-        println!("{}:{}", loc.file(), loc.line());
-    }
-    println!("{}", info.message());
-
+    #[cfg(not(feature = "test"))]
     loop {
         arch::x86_64::io::hlt();
     }
@@ -1,5 +1,9 @@
+pub mod dma;
 pub mod heap;
+pub mod kstack;
 pub mod pmm;
+pub mod uaccess;
+pub mod vmalloc;
 pub mod vmm;
 
 pub use pmm::PAGE_SIZE;
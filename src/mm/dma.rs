@@ -0,0 +1,97 @@
+//! Buffers for devices to DMA to and from: physically contiguous, aligned,
+//! zeroed blocks from the buddy allocator, with both their physical address
+//! (for the device) and their HHDM address (for the driver) at hand.
+//!
+//! Coherent for free on x86: RAM is mapped write-back and the caches snoop
+//! bus-master traffic, so what either side writes the other sees; drivers
+//! only need fences to order it against their doorbell writes.
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::mm::pmm::{alloc_frames, free_frames, MAX_ORDER, PAGE_SIZE};
+use alloc::vec::Vec;
+
+/// Blocks tried for one below 4 GiB before giving up.
+const TRIES_32: usize = 8;
+
+/// A DMA buffer. Owns its block: dropping it gives the memory back, so the
+/// device must be done with it by then.
+#[derive(Debug)]
+pub struct DmaBuffer {
+    phys: u64,
+    len: usize,
+    order: usize,
+}
+
+impl DmaBuffer {
+    /// Address for the device.
+    pub fn phys(&self) -> u64 {
+        self.phys
+    }
+
+    /// Address for the CPU.
+    pub fn virt(&self) -> u64 {
+        phys_to_virt(self.phys)
+    }
+
+    /// Bytes asked for; the block behind them may be bigger.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_ptr<T>(&self) -> *mut T {
+        self.virt() as *mut T
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        free_frames(self.phys, self.order);
+    }
+}
+
+/// The buddy order whose blocks hold `len` bytes at `align`: blocks are
+/// aligned to their own size, so the bigger of the two decides.
+fn order_for(len: usize, align: usize) -> Option<usize> {
+    if !align.is_power_of_two() {
+        return None;
+    }
+    let pages = len.max(align).div_ceil(PAGE_SIZE).max(1).next_power_of_two();
+    let order = pages.trailing_zeros() as usize;
+    (order <= MAX_ORDER).then_some(order)
+}
+
+/// `len` zeroed bytes, physically contiguous and aligned to `align` (a
+/// power of two; page alignment at least). None if no block is free.
+pub fn alloc_coherent(len: usize, align: usize) -> Option<DmaBuffer> {
+    let order = order_for(len, align)?;
+    let phys = alloc_frames(order)?;
+    unsafe { core::ptr::write_bytes(phys_to_virt(phys) as *mut u8, 0, PAGE_SIZE << order) };
+    Some(DmaBuffer { phys, len, order })
+}
+
+/// As `alloc_coherent`, for devices that only take 32-bit addresses: the
+/// whole buffer lies below 4 GiB.
+pub fn alloc_coherent_32(len: usize, align: usize) -> Option<DmaBuffer> {
+    // Blocks above the line stay allocated while we look, so the same one
+    // doesn't come back each time; they go back on return
+    let mut rejected = Vec::new();
+    for _ in 0..TRIES_32 {
+        let buf = alloc_coherent(len, align)?;
+        if buf.phys + (PAGE_SIZE << buf.order) as u64 <= 1 << 32 {
+            return Some(buf);
+        }
+        rejected.push(buf);
+    }
+    None
+}
@@ -1,12 +1,97 @@
 use crate::arch::x86_64::limine::phys_to_virt;
-use crate::mm::pmm::{align_up, alloc_frames, alloc_zeroed_frame, free_frame, free_frames, PAGE_SIZE};
+use crate::mm::pmm::{
+    align_up, alloc_frames, alloc_zeroed_frame, free_frame, free_frames, MAX_ORDER, PAGE_SIZE,
+};
+use crate::mm::vmalloc;
 use crate::sync::spinlock::SpinLock;
+use alloc::string::String;
 use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::fmt::Write;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 const SLAB_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 const NUM_SLABS: usize = SLAB_SIZES.len();
 
+// ─── Debugging (feature "heap-debug") ────────────────────────────────────────
+//
+// Every slab object gets at least REDZONE bytes past the requested size,
+// filled with REDZONE_BYTE on alloc and checked on free (overruns). Free
+// objects hold POISON_BYTE behind their freelist link, checked on the next
+// alloc (writes after free).
+
+const HEAP_DEBUG: bool = cfg!(feature = "heap-debug");
+const REDZONE: usize = if HEAP_DEBUG { 8 } else { 0 };
+const REDZONE_BYTE: u8 = 0xbb;
+const POISON_BYTE: u8 = 0x6b;
+/// The freelist link occupies the start of a free object.
+const LINK_SIZE: usize = core::mem::size_of::<FreeObj>();
+
+#[derive(Debug, Clone, Copy)]
+enum Corruption {
+    Overrun { ptr: usize, size: usize, at: usize },
+    UseAfterFree { ptr: usize, obj_size: usize, at: usize },
+}
+
+/// Offset of the first byte in [from, to) of `obj` that isn't `byte`.
+unsafe fn find_mismatch(obj: *const u8, from: usize, to: usize, byte: u8) -> Option<usize> {
+    (from..to).find(|&i| *obj.add(i) != byte)
+}
+
+/// Called with the allocator unlocked: logging must be free to allocate.
+fn report(c: Corruption) {
+    match c {
+        Corruption::Overrun { ptr, size, at } => log::error!(
+            "heap: overrun of {:#x} (size {}): redzone byte +{} overwritten",
+            ptr,
+            size,
+            at
+        ),
+        Corruption::UseAfterFree { ptr, obj_size, at } => log::error!(
+            "heap: {:#x} (kmalloc-{}) written after free at +{}",
+            ptr,
+            obj_size,
+            at
+        ),
+    }
+}
+
+// ─── Statistics ──────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub obj_size: usize,
+    pub objs_per_slab: usize,
+    pub allocs: u64,
+    pub frees: u64,
+    /// Objects currently handed out, and the most there ever were.
+    pub active: usize,
+    pub peak: usize,
+    /// Bytes requested by the live objects (at most active × obj_size).
+    pub bytes: usize,
+    pub slabs: usize,
+}
+
+/// Allocations too big for a slab, served from the buddy allocator or,
+/// failing that, vmalloc.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargeStats {
+    pub allocs: u64,
+    pub frees: u64,
+    pub pages: usize,
+    pub peak_pages: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub caches: [CacheStats; NUM_SLABS],
+    pub large: LargeStats,
+    pub corruptions: u64,
+    pub debug: bool,
+}
+
 struct FreeObj {
     next: *mut FreeObj,
 }
@@ -40,6 +125,9 @@ impl Slab {
         let mut prev: *mut FreeObj = core::ptr::null_mut();
         for i in (0..capacity).rev() {
             let obj = objs_start.add(i * obj_size) as *mut FreeObj;
+            if HEAP_DEBUG {
+                core::ptr::write_bytes(obj as *mut u8, POISON_BYTE, obj_size);
+            }
             (*obj).next = prev;
             prev = obj;
         }
@@ -78,19 +166,71 @@ struct SlabCache {
     obj_size: usize,
     partial: *mut Slab,
     full: *mut Slab,
+    stats: CacheStats,
 }
 
 unsafe impl Send for SlabCache {}
 
 impl SlabCache {
     const fn new(obj_size: usize) -> Self {
+        // Same layout as Slab::new: header rounded up to whole objects
+        let header = core::mem::size_of::<Slab>().div_ceil(obj_size) * obj_size;
         Self {
             obj_size,
             partial: core::ptr::null_mut(),
             full: core::ptr::null_mut(),
+            stats: CacheStats {
+                obj_size,
+                objs_per_slab: (PAGE_SIZE - header) / obj_size,
+                allocs: 0,
+                frees: 0,
+                active: 0,
+                peak: 0,
+                bytes: 0,
+                slabs: 0,
+            },
         }
     }
 
+    /// An object for a `size`-byte request, plus any damage found on it.
+    unsafe fn alloc_obj(&mut self, size: usize) -> Option<(*mut u8, Option<Corruption>)> {
+        let ptr = self.alloc()?;
+        let st = &mut self.stats;
+        st.allocs += 1;
+        st.active += 1;
+        st.peak = st.peak.max(st.active);
+        st.bytes += size;
+
+        let mut bad = None;
+        if HEAP_DEBUG {
+            if let Some(at) = find_mismatch(ptr, LINK_SIZE, self.obj_size, POISON_BYTE) {
+                bad = Some(Corruption::UseAfterFree {
+                    ptr: ptr as usize,
+                    obj_size: self.obj_size,
+                    at,
+                });
+            }
+            core::ptr::write_bytes(ptr.add(size), REDZONE_BYTE, self.obj_size - size);
+        }
+        Some((ptr, bad))
+    }
+
+    unsafe fn free_obj(&mut self, ptr: *mut u8, size: usize) -> Option<Corruption> {
+        let mut bad = None;
+        if HEAP_DEBUG {
+            if let Some(at) = find_mismatch(ptr, size, self.obj_size, REDZONE_BYTE) {
+                bad = Some(Corruption::Overrun { ptr: ptr as usize, size, at });
+            }
+            core::ptr::write_bytes(ptr, POISON_BYTE, self.obj_size);
+        }
+        let st = &mut self.stats;
+        st.frees += 1;
+        st.active -= 1;
+        st.bytes -= size;
+        self.dealloc(ptr);
+        bad
+    }
+
     unsafe fn alloc(&mut self) -> Option<*mut u8> {
         if !self.partial.is_null() {
             let slab = self.partial;
@@ -106,6 +246,7 @@ impl SlabCache {
         }
 
         let slab = Slab::new(self.obj_size)?;
+        self.stats.slabs += 1;
         (*slab).next = self.partial;
         self.partial = slab;
 
@@ -125,6 +266,7 @@ impl SlabCache {
             self.partial = slab;
         } else if (*slab).is_empty() {
             self.remove_from_partial(slab);
+            self.stats.slabs -= 1;
             free_frame(crate::arch::x86_64::limine::virt_to_phys(
                 (*slab).base as u64,
             ));
@@ -156,6 +298,8 @@ impl SlabCache {
 
 struct KernelAllocator {
     caches: [SlabCache; NUM_SLABS],
+    large: LargeStats,
+    corruptions: u64,
 }
 
 unsafe impl Send for KernelAllocator {}
@@ -174,11 +318,20 @@ impl KernelAllocator {
                 SlabCache::new(1024),
                 SlabCache::new(2048),
             ],
+            large: LargeStats {
+                allocs: 0,
+                frees: 0,
+                pages: 0,
+                peak_pages: 0,
+                bytes: 0,
+            },
+            corruptions: 0,
         }
     }
 
+    /// The cache serving `size` bytes, redzone included.
     fn find_cache(&mut self, size: usize, align: usize) -> Option<&mut SlabCache> {
-        let need = size.max(align);
+        let need = (size + REDZONE).max(align);
         for cache in &mut self.caches {
             if cache.obj_size >= need {
                 return Some(cache);
@@ -192,6 +345,57 @@ static ALLOCATOR: SpinLock<KernelAllocator> = SpinLock::new(KernelAllocator::new
 
 pub struct KernelHeap;
 
+fn order_for(size: usize) -> usize {
+    let pages = (align_up(size as u64, PAGE_SIZE as u64) / PAGE_SIZE as u64) as usize;
+    usize::BITS as usize - pages.next_power_of_two().leading_zeros() as usize - 1
+}
+
+// ─── Early allocations ───────────────────────────────────────────────────────
+
+/// What is allocated before the PMM is up (the boot console, mainly) comes
+/// from here, bumped and never given back. Once `online` is called the
+/// slabs take over; frees of what came from here are then ignored.
+const EARLY_SIZE: usize = 1 << 20;
+
+#[repr(C, align(4096))]
+struct EarlyArena(UnsafeCell<[u8; EARLY_SIZE]>);
+
+unsafe impl Sync for EarlyArena {}
+
+static EARLY: EarlyArena = EarlyArena(UnsafeCell::new([0; EARLY_SIZE]));
+static EARLY_USED: AtomicUsize = AtomicUsize::new(0);
+static ONLINE: AtomicBool = AtomicBool::new(false);
+
+/// The PMM is up: allocate from it from now on.
+pub fn online() {
+    ONLINE.store(true, Ordering::Release);
+}
+
+/// Bytes the early arena has left.
+pub fn early_left() -> usize {
+    EARLY_SIZE - EARLY_USED.load(Ordering::Relaxed)
+}
+
+fn early_alloc(size: usize, align: usize) -> *mut u8 {
+    let base = EARLY.0.get() as usize;
+    let mut used = EARLY_USED.load(Ordering::Relaxed);
+    loop {
+        let start = (base + used).next_multiple_of(align) - base;
+        let Some(end) = start.checked_add(size).filter(|&end| end <= EARLY_SIZE) else {
+            return core::ptr::null_mut();
+        };
+        match EARLY_USED.compare_exchange(used, end, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return (base + start) as *mut u8,
+            Err(now) => used = now,
+        }
+    }
+}
+
+fn is_early(ptr: *mut u8) -> bool {
+    let base = EARLY.0.get() as usize;
+    (base..base + EARLY_SIZE).contains(&(ptr as usize))
+}
+
 unsafe impl GlobalAlloc for KernelHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let size = layout.size();
@@ -201,46 +405,87 @@ unsafe impl GlobalAlloc for KernelHeap {
             return align as *mut u8;
         } // ZST
 
+        if !ONLINE.load(Ordering::Acquire) {
+            return early_alloc(size, align);
+        }
+
         let mut alloc = ALLOCATOR.lock();
 
-        if size <= 2048 && align <= 2048 {
-            if let Some(cache) = alloc.find_cache(size, align) {
-                return cache.alloc().unwrap_or(core::ptr::null_mut());
+        if size + REDZONE <= 2048 && align <= 2048 {
+            let Some(cache) = alloc.find_cache(size, align) else {
+                return core::ptr::null_mut();
+            };
+            let Some((ptr, bad)) = cache.alloc_obj(size) else {
+                return core::ptr::null_mut();
+            };
+            if let Some(c) = bad {
+                alloc.corruptions += 1;
+                drop(alloc);
+                report(c);
             }
+            return ptr;
         }
 
-        let pages = (align_up(size as u64, PAGE_SIZE as u64) / PAGE_SIZE as u64) as usize;
-        let order = usize::BITS as usize - pages.next_power_of_two().leading_zeros() as usize - 1;
-        match alloc_frames(order) {
+        // Beyond MAX_ORDER, or with no block that big left, stitch single
+        // frames together instead
+        let order = order_for(size);
+        let block = if order <= MAX_ORDER { alloc_frames(order) } else { None };
+        let (virt, pages) = match block {
             Some(phys) => {
                 let virt = phys_to_virt(phys) as *mut u8;
                 unsafe { core::ptr::write_bytes(virt, 0, (1 << order) * PAGE_SIZE) };
-                virt
+                (virt, 1 << order)
             }
-            None => core::ptr::null_mut(),
-        }
+            None if align <= PAGE_SIZE => {
+                drop(alloc); // vmalloc allocates its bookkeeping from here
+                let Some(virt) = vmalloc::vmalloc(size) else {
+                    return core::ptr::null_mut();
+                };
+                alloc = ALLOCATOR.lock();
+                (virt, size.div_ceil(PAGE_SIZE))
+            }
+            None => return core::ptr::null_mut(),
+        };
+        let large = &mut alloc.large;
+        large.allocs += 1;
+        large.pages += pages;
+        large.peak_pages = large.peak_pages.max(large.pages);
+        large.bytes += size;
+        virt
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        if ptr.is_null() || layout.size() == 0 {
+        if ptr.is_null() || layout.size() == 0 || is_early(ptr) {
             return;
         }
 
         let size = layout.size();
         let align = layout.align();
+        let mut alloc = ALLOCATOR.lock();
 
-        if size <= 2048 && align <= 2048 {
-            let mut alloc = ALLOCATOR.lock();
+        if size + REDZONE <= 2048 && align <= 2048 {
             if let Some(cache) = alloc.find_cache(size, align) {
-                cache.dealloc(ptr);
+                if let Some(c) = cache.free_obj(ptr, size) {
+                    alloc.corruptions += 1;
+                    drop(alloc);
+                    report(c);
+                }
                 return;
             }
         }
 
-        let pages = (align_up(size as u64, PAGE_SIZE as u64) / PAGE_SIZE as u64) as usize;
-        let order = usize::BITS as usize - pages.next_power_of_two().leading_zeros() as usize - 1;
-        let phys = crate::arch::x86_64::limine::virt_to_phys(ptr as u64);
-        free_frames(phys, order);
+        let vmalloced = vmalloc::is_vmalloc_addr(ptr as u64);
+        let order = order_for(size);
+        alloc.large.frees += 1;
+        alloc.large.pages -= if vmalloced { size.div_ceil(PAGE_SIZE) } else { 1 << order };
+        alloc.large.bytes -= size;
+        drop(alloc);
+        if vmalloced {
+            vmalloc::vfree(ptr);
+        } else {
+            let phys = crate::arch::x86_64::limine::virt_to_phys(ptr as u64);
+            free_frames(phys, order);
+        }
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
@@ -255,6 +500,47 @@ unsafe impl GlobalAlloc for KernelHeap {
     }
 }
 
+pub fn stats() -> HeapStats {
+    let alloc = ALLOCATOR.lock();
+    HeapStats {
+        caches: core::array::from_fn(|i| alloc.caches[i].stats),
+        large: alloc.large,
+        corruptions: alloc.corruptions,
+        debug: HEAP_DEBUG,
+    }
+}
+
+/// The `slabinfo` table, also served as /proc/slabinfo.
+pub fn slabinfo() -> String {
+    let st = stats();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# name          active    peak  objsize  objperslab  slabs      allocs       frees    bytes"
+    );
+    for c in &st.caches {
+        let _ = writeln!(
+            out,
+            "kmalloc-{:<6} {:>7} {:>7} {:>8} {:>11} {:>6} {:>11} {:>11} {:>8}",
+            c.obj_size, c.active, c.peak, c.obj_size, c.objs_per_slab, c.slabs, c.allocs, c.frees,
+            c.bytes
+        );
+    }
+    let l = &st.large;
+    let _ = writeln!(
+        out,
+        "large pages: {} (peak {}), {} bytes live, {} allocs, {} frees",
+        l.pages, l.peak_pages, l.bytes, l.allocs, l.frees
+    );
+    let _ = writeln!(
+        out,
+        "debug checks: {}, corruptions: {}",
+        if st.debug { "on" } else { "off" },
+        st.corruptions
+    );
+    out
+}
+
 #[global_allocator]
 pub static HEAP: KernelHeap = KernelHeap;
 
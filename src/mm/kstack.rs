@@ -0,0 +1,133 @@
+//! Kernel stacks, each in a slot of its own in a dedicated window with an
+//! unmapped guard page at the bottom.
+//!
+//! Stacks grow down, so a task that overflows its stack runs into the
+//! guard page and faults instead of scribbling over whatever lies below.
+//! The fault handlers ask `in_guard` to tell such a fault from any other:
+//! usually it arrives as a double fault, since the CPU can't push the
+//! page fault's frame onto the stack that just overflowed.
+//!
+//! Like the vmalloc window, this is one PML4 slot whose PDPT exists from
+//! `init` on, so the stacks are mapped in every address space.
+use crate::mm::pmm::{alloc_frame, free_frame, PAGE_SIZE};
+use crate::mm::vmm::{kernel_pml4_phys, AddressSpace, PTE_NO_EXEC, PTE_WRITABLE};
+use crate::sync::spinlock::SpinLock;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub const KSTACK_START: u64 = 0xFFFF_C080_0000_0000;
+pub const KSTACK_END: u64 = KSTACK_START + (1 << 39);
+
+/// Size of every kernel stack, guard page excluded.
+pub const STACK_SIZE: usize = 16 * 1024;
+
+const GUARD: u64 = PAGE_SIZE as u64;
+const SLOT: u64 = GUARD + STACK_SIZE as u64;
+const PAGES: usize = STACK_SIZE / PAGE_SIZE;
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+struct Slots {
+    /// Slots below this one have been handed out at some point.
+    next: u64,
+    /// Slots given back, for reuse before `next` moves on.
+    free: Vec<u64>,
+}
+
+static SLOTS: SpinLock<Slots> = SpinLock::new(Slots {
+    next: 0,
+    free: Vec::new(),
+});
+
+/// The kernel page tables, whatever CR3 currently holds. Never dropped:
+/// `AddressSpace::drop` would tear down a user address space.
+fn kernel_space() -> core::mem::ManuallyDrop<AddressSpace> {
+    core::mem::ManuallyDrop::new(AddressSpace {
+        pml4_phys: kernel_pml4_phys(),
+    })
+}
+
+pub fn init() {
+    if crate::mm::vmm::reserve_kernel_pml4(KSTACK_START) {
+        READY.store(true, Ordering::Release);
+        log::info!("kstack: {:#x}-{:#x}", KSTACK_START, KSTACK_END);
+    } else {
+        log::error!("kstack: window at {:#x} unavailable", KSTACK_START);
+    }
+}
+
+/// Lowest usable address of slot `slot`'s stack, just above its guard.
+fn base(slot: u64) -> u64 {
+    KSTACK_START + slot * SLOT + GUARD
+}
+
+fn unmap_pages(start: u64, pages: usize) {
+    let mut space = kernel_space();
+    for i in 0..pages {
+        if let Some(phys) = space.unmap(start + (i * PAGE_SIZE) as u64) {
+            free_frame(phys);
+        }
+    }
+}
+
+/// A kernel stack of `STACK_SIZE` bytes; returns its lowest address.
+pub fn alloc() -> Option<u64> {
+    if !READY.load(Ordering::Acquire) {
+        return None;
+    }
+    let slot = {
+        let mut slots = SLOTS.lock();
+        match slots.free.pop() {
+            Some(slot) => slot,
+            None if base(slots.next + 1) <= KSTACK_END => {
+                slots.next += 1;
+                slots.next - 1
+            }
+            None => return None,
+        }
+    };
+
+    let start = base(slot);
+    let mut space = kernel_space();
+    for i in 0..PAGES {
+        let virt = start + (i * PAGE_SIZE) as u64;
+        let mapped = match alloc_frame() {
+            Some(phys) => {
+                space.map(virt, phys, PTE_WRITABLE | PTE_NO_EXEC) || {
+                    free_frame(phys);
+                    false
+                }
+            }
+            None => false,
+        };
+        if !mapped {
+            unmap_pages(start, i);
+            SLOTS.lock().free.push(slot);
+            return None;
+        }
+    }
+    Some(start)
+}
+
+/// Give back a stack from `alloc`. Its owner must never run on it again.
+pub fn free(stack: u64) {
+    let off = stack.wrapping_sub(KSTACK_START);
+    if !(KSTACK_START..KSTACK_END).contains(&stack) || off % SLOT != GUARD {
+        log::error!("kstack: {:#x} is not a kernel stack", stack);
+        return;
+    }
+    unmap_pages(stack, PAGES);
+    SLOTS.lock().free.push(off / SLOT);
+}
+
+/// Stacks handed out and not given back.
+pub fn in_use() -> usize {
+    let slots = SLOTS.lock();
+    slots.next as usize - slots.free.len()
+}
+
+/// Whether `addr` is in the guard page under one of the stacks: a kernel
+/// stack overflow if a fault hit it.
+pub fn in_guard(addr: u64) -> bool {
+    (KSTACK_START..KSTACK_END).contains(&addr) && (addr - KSTACK_START) % SLOT < GUARD
+}
@@ -1,5 +1,8 @@
 use crate::arch::x86_64::limine::{phys_to_virt, MemoryMapEntryType, MEMMAP_REQUEST};
 use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::fmt::Write;
 use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 pub const PAGE_SIZE: usize = 4096;
@@ -7,6 +10,7 @@ pub const PAGE_SHIFT: usize = 12;
 
 pub const MAX_ORDER: usize = 12;
 
+// Mirrors of the PMM's counters, readable without taking its lock
 static FREE_PAGES: AtomicUsize = AtomicUsize::new(0);
 static TOTAL_PAGES: AtomicUsize = AtomicUsize::new(0);
 
@@ -76,6 +80,8 @@ struct BuddyAllocator {
     lists: [FreeList; MAX_ORDER + 1],
     base_phys: u64,
     total_frames: usize,
+    free_pages: usize,
+    total_pages: usize,
 }
 
 unsafe impl Send for BuddyAllocator {}
@@ -87,6 +93,8 @@ impl BuddyAllocator {
             lists: [EMPTY; MAX_ORDER + 1],
             base_phys: 0,
             total_frames: 0,
+            free_pages: 0,
+            total_pages: 0,
         }
     }
 
@@ -101,8 +109,8 @@ impl BuddyAllocator {
                 if order == 0 || addr % block_size != 0 || addr + block_size > end {
                     if order == 0 {
                         self.lists[0].push(addr);
-                        FREE_PAGES.fetch_add(1, Ordering::Relaxed);
-                        TOTAL_PAGES.fetch_add(1, Ordering::Relaxed);
+                        self.free_pages += 1;
+                        self.total_pages += 1;
                         addr += PAGE_SIZE as u64;
                         break;
                     }
@@ -110,8 +118,8 @@ impl BuddyAllocator {
                     continue;
                 }
                 self.lists[order].push(addr);
-                FREE_PAGES.fetch_add(1 << order, Ordering::Relaxed);
-                TOTAL_PAGES.fetch_add(1 << order, Ordering::Relaxed);
+                self.free_pages += 1 << order;
+                self.total_pages += 1 << order;
                 addr += block_size;
                 break;
             }
@@ -137,7 +145,7 @@ impl BuddyAllocator {
             self.lists[current_order].push(buddy);
         }
 
-        FREE_PAGES.fetch_sub(1 << order, Ordering::Relaxed);
+        self.free_pages -= 1 << order;
         Some(phys)
     }
 
@@ -162,7 +170,16 @@ impl BuddyAllocator {
         }
 
         self.lists[current_order].push(current_phys);
-        FREE_PAGES.fetch_add(1 << order, Ordering::Relaxed);
+        self.free_pages += 1 << order;
+    }
+
+    fn free_blocks(&self) -> [usize; MAX_ORDER + 1] {
+        core::array::from_fn(|o| self.lists[o].count)
+    }
+
+    fn publish(&self) {
+        FREE_PAGES.store(self.free_pages, Ordering::Relaxed);
+        TOTAL_PAGES.store(self.total_pages, Ordering::Relaxed);
     }
 }
 
@@ -206,28 +223,34 @@ pub fn init() {
             }
         }
 
+        pmm.publish();
         log::info!(
             "PMM: {:.1} MiB usable ({} pages)",
             usable_bytes / 1024 / 1024,
-            FREE_PAGES.load(Ordering::Relaxed)
+            pmm.free_pages
         );
     }
 }
 
 pub fn alloc_frame() -> Option<u64> {
-    PMM.lock().alloc(0)
+    alloc_frames(0)
 }
 
 pub fn alloc_frames(order: usize) -> Option<u64> {
-    PMM.lock().alloc(order)
+    let mut pmm = PMM.lock();
+    let phys = pmm.alloc(order);
+    pmm.publish();
+    phys
 }
 
 pub fn free_frame(phys: u64) {
-    PMM.lock().free(phys, 0);
+    free_frames(phys, 0);
 }
 
 pub fn free_frames(phys: u64, order: usize) {
-    PMM.lock().free(phys, order);
+    let mut pmm = PMM.lock();
+    pmm.free(phys, order);
+    pmm.publish();
 }
 
 pub fn alloc_zeroed_frame() -> Option<u64> {
@@ -237,6 +260,46 @@ pub fn alloc_zeroed_frame() -> Option<u64> {
     Some(phys)
 }
 
+// ─── Frame reference counts ──────────────────────────────────────────────────
+//
+// A frame mapped by a single owner has an implicit count of 1 and no entry
+// here; only frames shared copy-on-write after fork are tracked.
+
+static SHARED: SpinLock<BTreeMap<u64, u32>> = SpinLock::new(BTreeMap::new());
+
+/// One more mapping now refers to `phys`.
+pub fn frame_share(phys: u64) {
+    *SHARED.lock().entry(phys & !(PAGE_SIZE as u64 - 1)).or_insert(1) += 1;
+}
+
+pub fn frame_refcount(phys: u64) -> u32 {
+    let phys = phys & !(PAGE_SIZE as u64 - 1);
+    SHARED.lock().get(&phys).copied().unwrap_or(1)
+}
+
+/// Drop one reference; the frame goes back to the buddy allocator with
+/// the last one. Returns true if it was freed.
+pub fn frame_release(phys: u64) -> bool {
+    let phys = phys & !(PAGE_SIZE as u64 - 1);
+    {
+        let mut shared = SHARED.lock();
+        if let Some(count) = shared.get_mut(&phys) {
+            *count -= 1;
+            if *count == 1 {
+                shared.remove(&phys);
+            }
+            return false;
+        }
+    }
+    free_frame(phys);
+    true
+}
+
+/// Frames currently mapped by more than one owner.
+pub fn shared_frames() -> usize {
+    SHARED.lock().len()
+}
+
 pub fn free_pages() -> usize {
     FREE_PAGES.load(Ordering::Relaxed)
 }
@@ -249,6 +312,122 @@ pub fn used_pages() -> usize {
     total_pages().saturating_sub(free_pages())
 }
 
+/// Free blocks on each order's list, order 0 first.
+pub fn free_blocks() -> [usize; MAX_ORDER + 1] {
+    PMM.lock().free_blocks()
+}
+
+/// Free blocks per order in the format of Linux's /proc/buddyinfo.
+pub fn buddyinfo() -> String {
+    let mut out = String::from("Node 0, zone   Normal ");
+    for n in free_blocks() {
+        let _ = write!(out, " {:6}", n);
+    }
+    out.push('\n');
+    out
+}
+
+// ─── Self-test ───────────────────────────────────────────────────────────────
+//
+// Runs a private BuddyAllocator over one block borrowed from the real one,
+// so the expected state is known exactly and nothing else can interfere.
+// Needs no heap: it is meant to run right after `init`.
+
+const TEST_ORDER: usize = 6;
+const TEST_PAGES: usize = 1 << TEST_ORDER;
+
+/// Bit i set ⇔ page i of the test block is handed out (TEST_PAGES == 64).
+struct Taken(u64);
+
+impl Taken {
+    fn claim(&mut self, base: u64, phys: u64, order: usize) -> Result<(), &'static str> {
+        let block = (PAGE_SIZE << order) as u64;
+        if phys < base || phys + block > base + (TEST_PAGES * PAGE_SIZE) as u64 {
+            return Err("block outside the managed range");
+        }
+        if !phys.is_multiple_of(block) {
+            return Err("block not aligned to its order");
+        }
+        let first = ((phys - base) / PAGE_SIZE as u64) as usize;
+        let mask = (u64::MAX >> (64 - (1 << order))) << first;
+        if self.0 & mask != 0 {
+            return Err("block overlaps one already allocated");
+        }
+        self.0 |= mask;
+        Ok(())
+    }
+}
+
+fn check_pristine(b: &BuddyAllocator) -> Result<(), &'static str> {
+    let blocks = b.free_blocks();
+    let whole = blocks.iter().enumerate().all(|(o, &n)| n == (o == TEST_ORDER) as usize);
+    if b.free_pages != TEST_PAGES {
+        return Err("free page count not restored");
+    }
+    if !whole {
+        return Err("freed blocks did not coalesce");
+    }
+    Ok(())
+}
+
+fn run_selftest(b: &mut BuddyAllocator, base: u64) -> Result<(), &'static str> {
+    b.add_region(base, (TEST_PAGES * PAGE_SIZE) as u64);
+    check_pristine(b)?;
+
+    if b.alloc(TEST_ORDER + 1).is_some() {
+        return Err("allocated more than is free");
+    }
+
+    // One page splits the block into one buddy per lower order
+    let first = b.alloc(0).ok_or("order-0 allocation failed")?;
+    if b.free_blocks()[..TEST_ORDER].iter().any(|&n| n != 1) {
+        return Err("split left the wrong free blocks");
+    }
+    b.free(first, 0);
+    check_pristine(b)?;
+
+    // Exhaust with single pages, then free them out of order
+    let mut pages = [0u64; TEST_PAGES];
+    let mut taken = Taken(0);
+    for p in pages.iter_mut() {
+        *p = b.alloc(0).ok_or("order-0 allocation failed")?;
+        taken.claim(base, *p, 0)?;
+    }
+    if b.alloc(0).is_some() || b.free_pages != 0 {
+        return Err("allocated more than is free");
+    }
+    for i in 0..TEST_PAGES {
+        b.free(pages[(i * 37) % TEST_PAGES], 0); // 37 is coprime to 64
+    }
+    check_pristine(b)?;
+
+    // Mixed orders that exactly fill the block
+    let orders = [3usize, 0, 5, 1, 2, 0, 4];
+    let mut blocks = [0u64; 7];
+    let mut taken = Taken(0);
+    for (blk, &o) in blocks.iter_mut().zip(&orders) {
+        *blk = b.alloc(o).ok_or("mixed-order allocation failed")?;
+        taken.claim(base, *blk, o)?;
+    }
+    if taken.0 != u64::MAX || b.free_pages != 0 {
+        return Err("mixed orders did not fill the block");
+    }
+    for (&blk, &o) in blocks.iter().zip(&orders).rev() {
+        b.free(blk, o);
+    }
+    check_pristine(b)
+}
+
+/// Check the buddy allocator's split, exhaustion and coalescing behaviour.
+/// Run at boot when the kernel command line has `pmm.selftest`.
+pub fn selftest() -> Result<(), &'static str> {
+    let base = alloc_frames(TEST_ORDER).ok_or("no free block to test with")?;
+    let mut b = BuddyAllocator::new();
+    let res = run_selftest(&mut b, base);
+    free_frames(base, TEST_ORDER);
+    res
+}
+
 #[inline]
 pub fn align_up(val: u64, align: u64) -> u64 {
     (val + align - 1) & !(align - 1)
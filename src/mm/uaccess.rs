@@ -0,0 +1,190 @@
+//! Copying between kernel buffers and user memory.
+//!
+//! Each access walks the target address space page by page: the VMA has
+//! to allow it, missing pages are faulted in and writes break copy-on-write
+//! sharing first, exactly as a fault from ring 3 would. An address outside
+//! every VMA gives EFAULT.
+//!
+//! The bytes then move through the user address itself when the address
+//! space is the active one — the only place the kernel opens SMAP, with
+//! `stac`/`clac` around each page-sized copy — and through the HHDM alias
+//! of the frame otherwise.
+//!
+//! The mm lock is taken a page at a time and dropped while a file page is
+//! read in, so callers must not hold the process or mm lock. The plain
+//! functions work on the current process; the `_mm` variants on a given
+//! `Mm`, such as a child's.
+use crate::arch::x86_64::io::{clac, read_cr3, stac};
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::arch::x86_64::smap_enabled;
+use crate::fs::vfs::Errno;
+use crate::mm::pmm::PAGE_SIZE;
+use crate::mm::vmm::{fault_in, Mm, PTE_ADDR_MASK, PTE_USER, PTE_WRITABLE, USER_SPACE_END};
+use crate::sync::spinlock::{SpinGuard, SpinLock};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::{size_of, MaybeUninit};
+
+/// RFLAGS.AC set for as long as it lives. Only held around plain copies:
+/// faulting pages in happens before, with SMAP still closed. The mm lock
+/// the copy runs under keeps interrupts off meanwhile.
+struct UserAccess;
+
+impl UserAccess {
+    fn begin() -> Self {
+        if smap_enabled() {
+            unsafe { stac() };
+        }
+        UserAccess
+    }
+}
+
+impl Drop for UserAccess {
+    fn drop(&mut self) {
+        if smap_enabled() {
+            unsafe { clac() };
+        }
+    }
+}
+
+fn current_mm() -> Result<Arc<SpinLock<Mm>>, Errno> {
+    let proc = crate::proc::current_process().ok_or(Errno::EFAULT)?;
+    let mm = proc.lock().mm.clone();
+    Ok(mm)
+}
+
+fn check_range(addr: u64, len: usize) -> Result<(), Errno> {
+    match addr.checked_add(len as u64) {
+        Some(end) if addr != 0 && end <= USER_SPACE_END => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Address through which the kernel reaches the user byte at `addr`, with
+/// the page made present — and private and writable if `write` — and the
+/// mm lock that keeps it so.
+fn user_byte(
+    mm: &SpinLock<Mm>,
+    addr: u64,
+    write: bool,
+) -> Result<(SpinGuard<'_, Mm>, u64), Errno> {
+    let usable = |pte: u64| pte & PTE_USER != 0 && (!write || pte & PTE_WRITABLE != 0);
+    let mut faulted = false;
+    loop {
+        let guard = mm.lock();
+        if let Some(pte) = guard.space.pte(addr).filter(|&p| usable(p)) {
+            if guard.space.pml4_phys == unsafe { read_cr3() } & PTE_ADDR_MASK {
+                return Ok((guard, addr));
+            }
+            let k = phys_to_virt(pte & PTE_ADDR_MASK) + (addr & (PAGE_SIZE as u64 - 1));
+            return Ok((guard, k));
+        }
+        drop(guard);
+        if faulted || !fault_in(mm, addr, write) {
+            return Err(Errno::EFAULT);
+        }
+        faulted = true;
+    }
+}
+
+/// Call `f(kernel_ptr, offset, len)` for each page-sized piece of
+/// [addr, addr + len).
+fn walk(
+    mm: &SpinLock<Mm>,
+    addr: u64,
+    len: usize,
+    write: bool,
+    mut f: impl FnMut(u64, usize, usize),
+) -> Result<(), Errno> {
+    check_range(addr, len)?;
+    let mut done = 0;
+    while done < len {
+        let at = addr + done as u64;
+        let in_page = PAGE_SIZE - (at as usize & (PAGE_SIZE - 1));
+        let n = in_page.min(len - done);
+        let (_mm, k) = user_byte(mm, at, write)?;
+        let _ua = UserAccess::begin();
+        f(k, done, n);
+        done += n;
+    }
+    Ok(())
+}
+
+// ─── On a given Mm ───────────────────────────────────────────────────────────
+
+pub fn copy_from_user_mm(mm: &SpinLock<Mm>, dst: &mut [u8], src: u64) -> Result<(), Errno> {
+    walk(mm, src, dst.len(), false, |k, off, n| unsafe {
+        core::ptr::copy_nonoverlapping(k as *const u8, dst[off..].as_mut_ptr(), n);
+    })
+}
+
+pub fn copy_to_user_mm(mm: &SpinLock<Mm>, dst: u64, src: &[u8]) -> Result<(), Errno> {
+    walk(mm, dst, src.len(), true, |k, off, n| unsafe {
+        core::ptr::copy_nonoverlapping(src[off..].as_ptr(), k as *mut u8, n);
+    })
+}
+
+/// Copy a NUL-terminated string of at most `max` bytes (NUL excluded).
+/// ENAMETOOLONG if there is no NUL within `max + 1` bytes.
+pub fn strncpy_from_user_mm(mm: &SpinLock<Mm>, src: u64, max: usize) -> Result<Vec<u8>, Errno> {
+    let mut out = Vec::new();
+    let mut at = src;
+    loop {
+        check_range(at, 1)?;
+        // Scan the rest of this page at once
+        let in_page = PAGE_SIZE - (at as usize & (PAGE_SIZE - 1));
+        let (_mm, k) = user_byte(mm, at, false)?;
+        let _ua = UserAccess::begin();
+        let chunk = unsafe { core::slice::from_raw_parts(k as *const u8, in_page) };
+        match chunk.iter().position(|&b| b == 0) {
+            Some(nul) if out.len() + nul <= max => {
+                out.extend_from_slice(&chunk[..nul]);
+                return Ok(out);
+            }
+            Some(_) => return Err(Errno::ENAMETOOLONG),
+            None => {
+                out.extend_from_slice(chunk);
+                if out.len() > max {
+                    return Err(Errno::ENAMETOOLONG);
+                }
+            }
+        }
+        at += in_page as u64;
+    }
+}
+
+/// Read a plain-data value (no pointers or invariants) from user memory.
+pub fn get_user_mm<T: Copy>(mm: &SpinLock<Mm>, src: u64) -> Result<T, Errno> {
+    let mut val = MaybeUninit::<T>::uninit();
+    let bytes =
+        unsafe { core::slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user_mm(mm, bytes, src)?;
+    Ok(unsafe { val.assume_init() })
+}
+
+pub fn put_user_mm<T: Copy>(mm: &SpinLock<Mm>, dst: u64, val: &T) -> Result<(), Errno> {
+    let bytes = unsafe { core::slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+    copy_to_user_mm(mm, dst, bytes)
+}
+
+// ─── On the current process ──────────────────────────────────────────────────
+
+pub fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), Errno> {
+    copy_from_user_mm(&*current_mm()?, dst, src)
+}
+
+pub fn copy_to_user(dst: u64, src: &[u8]) -> Result<(), Errno> {
+    copy_to_user_mm(&*current_mm()?, dst, src)
+}
+
+pub fn strncpy_from_user(src: u64, max: usize) -> Result<Vec<u8>, Errno> {
+    strncpy_from_user_mm(&*current_mm()?, src, max)
+}
+
+pub fn get_user<T: Copy>(src: u64) -> Result<T, Errno> {
+    get_user_mm(&*current_mm()?, src)
+}
+
+pub fn put_user<T: Copy>(dst: u64, val: &T) -> Result<(), Errno> {
+    put_user_mm(&*current_mm()?, dst, val)
+}
@@ -0,0 +1,146 @@
+//! Virtually contiguous kernel allocations backed by scattered frames.
+//!
+//! The buddy allocator can't hand out more than 2^MAX_ORDER contiguous
+//! pages, and large orders are the first to go once memory fragments.
+//! `vmalloc` takes single frames instead and maps them back to back in a
+//! kernel-only window, leaving an unmapped guard page after each area so
+//! an overrun faults rather than corrupting the neighbour.
+//!
+//! The window is one PML4 slot whose PDPT exists from `init` on: user
+//! address spaces copy the kernel's upper-half PML4 entries when they are
+//! created, so everything mapped below that slot later is visible in all
+//! of them. Not for DMA — the memory isn't physically contiguous.
+use crate::mm::pmm::{align_up, alloc_zeroed_frame, free_frame, PAGE_SIZE};
+use crate::mm::vmm::{kernel_pml4_phys, AddressSpace, PTE_NO_EXEC, PTE_WRITABLE};
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub const VMALLOC_START: u64 = 0xFFFF_C000_0000_0000;
+pub const VMALLOC_END: u64 = VMALLOC_START + (1 << 39);
+
+const GUARD: u64 = PAGE_SIZE as u64;
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Mapped areas by start address → size in pages (guard page excluded).
+static AREAS: SpinLock<BTreeMap<u64, usize>> = SpinLock::new(BTreeMap::new());
+
+/// The kernel page tables, whatever CR3 currently holds. Never dropped:
+/// `AddressSpace::drop` would tear down a user address space.
+fn kernel_space() -> core::mem::ManuallyDrop<AddressSpace> {
+    core::mem::ManuallyDrop::new(AddressSpace {
+        pml4_phys: kernel_pml4_phys(),
+    })
+}
+
+pub fn init() {
+    if crate::mm::vmm::reserve_kernel_pml4(VMALLOC_START) {
+        READY.store(true, Ordering::Release);
+        log::info!(
+            "vmalloc: {:#x}-{:#x} ({} GiB)",
+            VMALLOC_START,
+            VMALLOC_END,
+            (VMALLOC_END - VMALLOC_START) >> 30
+        );
+    } else {
+        log::warn!("vmalloc: window at {:#x} unavailable", VMALLOC_START);
+    }
+}
+
+pub fn is_vmalloc_addr(addr: u64) -> bool {
+    (VMALLOC_START..VMALLOC_END).contains(&addr)
+}
+
+/// First gap of `len` bytes (plus guard) between the existing areas.
+fn find_gap(areas: &BTreeMap<u64, usize>, len: u64) -> Option<u64> {
+    let mut at = VMALLOC_START;
+    for (&start, &pages) in areas {
+        if start - at >= len + GUARD {
+            return Some(at);
+        }
+        at = start + (pages * PAGE_SIZE) as u64 + GUARD;
+    }
+    (VMALLOC_END - at >= len + GUARD).then_some(at)
+}
+
+fn unmap_pages(start: u64, pages: usize) {
+    let mut space = kernel_space();
+    for i in 0..pages {
+        if let Some(phys) = space.unmap(start + (i * PAGE_SIZE) as u64) {
+            free_frame(phys);
+        }
+    }
+}
+
+/// At least `size` bytes of zeroed, page-aligned kernel memory.
+pub fn vmalloc(size: usize) -> Option<*mut u8> {
+    if size == 0 || !READY.load(Ordering::Acquire) {
+        return None;
+    }
+    let len = align_up(size as u64, PAGE_SIZE as u64);
+    let pages = (len / PAGE_SIZE as u64) as usize;
+
+    // Claim the range first so the frames can be mapped without the lock
+    let start = {
+        let mut areas = AREAS.lock();
+        let start = find_gap(&areas, len)?;
+        areas.insert(start, pages);
+        start
+    };
+
+    let mut space = kernel_space();
+    for i in 0..pages {
+        let virt = start + (i * PAGE_SIZE) as u64;
+        let mapped = match alloc_zeroed_frame() {
+            Some(phys) => {
+                space.map(virt, phys, PTE_WRITABLE | PTE_NO_EXEC) || {
+                    free_frame(phys);
+                    false
+                }
+            }
+            None => false,
+        };
+        if !mapped {
+            unmap_pages(start, i);
+            AREAS.lock().remove(&start);
+            return None;
+        }
+    }
+    Some(start as *mut u8)
+}
+
+/// Release an area returned by `vmalloc`.
+pub fn vfree(ptr: *mut u8) {
+    let Some(pages) = AREAS.lock().remove(&(ptr as u64)) else {
+        log::error!("vfree: {:p} is not a vmalloc area", ptr);
+        return;
+    };
+    unmap_pages(ptr as u64, pages);
+}
+
+/// (areas, pages) currently mapped.
+pub fn usage() -> (usize, usize) {
+    let areas = AREAS.lock();
+    (areas.len(), areas.values().sum())
+}
+
+/// One line per area, as /proc/vmallocinfo.
+pub fn vmallocinfo() -> String {
+    let areas = AREAS.lock().clone();
+    let mut out = String::new();
+    for (start, pages) in areas {
+        let end = start + (pages * PAGE_SIZE) as u64;
+        let _ = writeln!(
+            out,
+            "{:#018x}-{:#018x} {:>10} pages={}",
+            start,
+            end,
+            end - start,
+            pages
+        );
+    }
+    out
+}
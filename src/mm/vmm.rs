@@ -1,7 +1,14 @@
 use crate::arch::x86_64::io::invlpg;
 use crate::arch::x86_64::limine::{phys_to_virt, virt_to_phys};
-use crate::mm::pmm::{align_down, align_up, alloc_zeroed_frame, free_frame, PAGE_SIZE};
+use crate::fs::vfs::{Errno, Inode};
+use crate::mm::pmm::{
+    align_down, align_up, alloc_frame, alloc_zeroed_frame, frame_refcount, frame_release,
+    frame_share, free_frame, PAGE_SIZE,
+};
 use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicU64, Ordering};
 
 pub const PTE_PRESENT: u64 = 1 << 0;
@@ -73,6 +80,16 @@ fn pt_idx(vaddr: u64) -> usize {
     ((vaddr >> 12) & 0x1FF) as usize
 }
 
+/// Without EFER.NXE bit 63 is reserved and would fault on every access.
+#[inline]
+fn leaf_flags(flags: u64) -> u64 {
+    if crate::arch::x86_64::nx_enabled() {
+        flags
+    } else {
+        flags & !PTE_NO_EXEC
+    }
+}
+
 pub struct AddressSpace {
     pub pml4_phys: u64,
 }
@@ -131,7 +148,7 @@ impl AddressSpace {
             None => return false,
         };
 
-        pt.set_entry(pt_idx(virt), (phys & PTE_ADDR_MASK) | flags | PTE_PRESENT);
+        pt.set_entry(pt_idx(virt), (phys & PTE_ADDR_MASK) | leaf_flags(flags) | PTE_PRESENT);
 
         unsafe {
             invlpg(virt);
@@ -157,7 +174,7 @@ impl AddressSpace {
 
         pd.set_entry(
             pd_idx(virt),
-            (phys & PTE_ADDR_MASK) | flags | PTE_PRESENT | PTE_LARGE,
+            (phys & PTE_ADDR_MASK) | leaf_flags(flags) | PTE_PRESENT | PTE_LARGE,
         );
         unsafe {
             invlpg(virt);
@@ -189,21 +206,44 @@ impl AddressSpace {
         true
     }
 
-    pub fn unmap(&mut self, virt: u64) {
+    /// Remove the 4 KiB mapping at `virt`; returns the frame it pointed to.
+    pub fn unmap(&mut self, virt: u64) -> Option<u64> {
         let pml4 = self.pml4_mut();
 
-        if let Some(pdpt) = pml4.get_table(pml4_idx(virt)) {
-            if let Some(pd) = pdpt.get_table(pdpt_idx(virt)) {
-                if let Some(pt) = pd.get_table(pd_idx(virt)) {
-                    pt.set_entry(pt_idx(virt), 0);
-                    unsafe {
-                        invlpg(virt);
-                    }
-                }
+        let pt = pml4
+            .get_table(pml4_idx(virt))?
+            .get_table(pdpt_idx(virt))?
+            .get_table(pd_idx(virt))?;
+        let entry = pt.get_entry(pt_idx(virt));
+        pt.set_entry(pt_idx(virt), 0);
+        unsafe {
+            invlpg(virt);
+        }
+        (entry & PTE_PRESENT != 0).then_some(entry & PTE_ADDR_MASK)
+    }
+
+    /// Unmap a user page and drop this address space's reference to its
+    /// frame (freeing it if nobody else maps it).
+    pub fn unmap_user(&mut self, virt: u64) {
+        let user = self.pte(virt).is_some_and(|pte| pte & PTE_USER != 0);
+        if let Some(phys) = self.unmap(virt) {
+            if user {
+                frame_release(phys);
             }
         }
     }
 
+    /// Raw 4 KiB page-table entry for `virt`, if present.
+    pub fn pte(&self, virt: u64) -> Option<u64> {
+        let pdpt = self.pml4().get_table(pml4_idx(virt))?;
+        let pd = pdpt.get_table(pdpt_idx(virt))?;
+        if pd.get_entry(pd_idx(virt)) & PTE_LARGE != 0 {
+            return None;
+        }
+        let entry = pd.get_table(pd_idx(virt))?.get_entry(pt_idx(virt));
+        (entry & PTE_PRESENT != 0).then_some(entry)
+    }
+
     pub fn translate(&self, virt: u64) -> Option<u64> {
         let pml4 = self.pml4();
         let pdpt = pml4.get_table(pml4_idx(virt))?;
@@ -238,10 +278,16 @@ impl AddressSpace {
 
 impl Drop for AddressSpace {
     fn drop(&mut self) {
-        free_user_page_tables(self.pml4_phys);
+        // Kernel threads borrow the kernel page tables
+        if self.pml4_phys != kernel_pml4_phys() {
+            free_user_page_tables(self.pml4_phys);
+        }
     }
 }
 
+/// Release every user frame mapped in the lower half, then the page-table
+/// frames themselves. Frames still shared copy-on-write with another
+/// address space only lose a reference.
 fn free_user_page_tables(pml4_phys: u64) {
     let pml4 = unsafe { &*(phys_to_virt(pml4_phys) as *const PageTable) };
 
@@ -267,6 +313,13 @@ fn free_user_page_tables(pml4_phys: u64) {
                     continue;
                 } // Large Page
                 let pt_phys = pd.entries[k] & PTE_ADDR_MASK;
+                let pt = unsafe { &*(phys_to_virt(pt_phys) as *const PageTable) };
+                for l in 0..512usize {
+                    let pte = pt.entries[l];
+                    if pte & PTE_PRESENT != 0 && pte & PTE_USER != 0 {
+                        frame_release(pte & PTE_ADDR_MASK);
+                    }
+                }
                 free_frame(pt_phys);
             }
             free_frame(pd_phys);
@@ -285,6 +338,56 @@ fn get_kernel_pml4() -> &'static mut PageTable {
     }
 }
 
+pub fn kernel_pml4_phys() -> u64 {
+    unsafe { KERNEL_PML4_PHYS }
+}
+
+/// Make sure the kernel PML4 has a PDPT for the 512 GiB slot holding
+/// `virt`, so later mappings there reach every address space. Fails if
+/// the slot is already in use (HHDM, kernel image).
+pub fn reserve_kernel_pml4(virt: u64) -> bool {
+    let pml4 = get_kernel_pml4();
+    let idx = pml4_idx(virt);
+    !pml4.is_present(idx) && pml4.get_or_alloc_table(idx, PTE_WRITABLE).is_some()
+}
+
+/// Physical address `virt` maps to in the page tables CR3 holds now, 4 KiB,
+/// 2 MiB and 1 GiB pages alike. It reads nothing but the tables, so it can
+/// vet an address where a fault would be fatal: backtraces, the GDB stub.
+pub fn translate_current(virt: u64) -> Option<u64> {
+    let cr3: u64;
+    unsafe { core::arch::asm!("mov %cr3, {}", out(reg) cr3, options(att_syntax, nomem, nostack)) };
+    let mut table = cr3 & PTE_ADDR_MASK;
+    for level in (0..4).rev() {
+        let shift = 12 + 9 * level;
+        let idx = (virt >> shift) & 0x1FF;
+        let entry = unsafe { *((phys_to_virt(table) + idx * 8) as *const u64) };
+        if entry & PTE_PRESENT == 0 {
+            return None;
+        }
+        if level == 0 || (level <= 2 && entry & PTE_LARGE != 0) {
+            let mask = (1u64 << shift) - 1;
+            return Some((entry & PTE_ADDR_MASK & !mask) | (virt & mask));
+        }
+        table = entry & PTE_ADDR_MASK;
+    }
+    None
+}
+
+/// Kernel virtual address of an MMIO range. The HHDM already covers the low
+/// 4 GiB, MMIO holes included; anything above gets mapped uncached.
+pub fn map_mmio(phys: u64, size: u64) -> Option<u64> {
+    if phys + size > 1 << 32 {
+        let base = align_down(phys, PAGE_SIZE as u64);
+        let len = align_up(phys + size, PAGE_SIZE as u64) - base;
+        let flags = PTE_WRITABLE | PTE_PCD | PTE_PWT | PTE_NO_EXEC;
+        if !AddressSpace::kernel().map_range(phys_to_virt(base), base, len, flags) {
+            return None;
+        }
+    }
+    Some(phys_to_virt(phys))
+}
+
 pub fn init() {
     let cr3: u64;
     unsafe { core::arch::asm!("mov %cr3, {}", out(reg) cr3, options(att_syntax)) };
@@ -306,25 +409,24 @@ pub fn handle_page_fault(addr: u64, error: u64) -> bool {
         None => return false,
     };
 
-    let mut proc = proc.lock();
-
-    let vma = match proc.vm.find_vma(addr).cloned() {
-        Some(v) => v,
-        None => return false,
-    };
-
-    if write && !vma.flags.contains(VmaFlags::WRITE) {
-        if vma.flags.contains(VmaFlags::COPY_ON_WRITE) {
-            return handle_cow(&mut proc.address_space, addr);
-        }
+    // Present and not a write: an NX or privilege violation, not ours to fix
+    if present && !write {
         return false;
     }
 
-    if !present {
-        return handle_demand_page(&mut proc.address_space, addr, &vma);
-    }
+    let mm = proc.lock().mm.clone();
+    fault_in(&mm, addr, write)
+}
 
-    false
+fn vma_pte_flags(vma: &VmaEntry) -> u64 {
+    let mut flags = PTE_PRESENT | PTE_USER;
+    if vma.flags.contains(VmaFlags::WRITE) {
+        flags |= PTE_WRITABLE;
+    }
+    if !vma.flags.contains(VmaFlags::EXEC) {
+        flags |= PTE_NO_EXEC;
+    }
+    flags
 }
 
 fn handle_demand_page(space: &mut AddressSpace, addr: u64, vma: &VmaEntry) -> bool {
@@ -333,27 +435,125 @@ fn handle_demand_page(space: &mut AddressSpace, addr: u64, vma: &VmaEntry) -> bo
         Some(p) => p,
         None => return false,
     };
+    if !space.map(page_addr, phys, vma_pte_flags(vma)) {
+        free_frame(phys);
+        return false;
+    }
+    true
+}
 
-    let mut flags = PTE_PRESENT | PTE_USER;
-    if vma.flags.contains(VmaFlags::WRITE) {
-        flags |= PTE_WRITABLE;
+/// A frame holding the page of `f` at `pos`; past EOF it stays zero.
+fn read_file_page(f: &VmaFile, pos: u64) -> Option<u64> {
+    let phys = alloc_zeroed_frame()?;
+    let buf = unsafe { core::slice::from_raw_parts_mut(phys_to_virt(phys) as *mut u8, PAGE_SIZE) };
+    let mut done = 0;
+    while done < PAGE_SIZE {
+        match f.inode.ops.read(pos + done as u64, &mut buf[done..]) {
+            Ok(0) => break,
+            Ok(n) => done += n,
+            Err(e) => {
+                log::warn!("mmap: read of {:?} at {:#x} failed: {:?}", f, pos, e);
+                free_frame(phys);
+                return None;
+            }
+        }
     }
-    if !vma.flags.contains(VmaFlags::EXEC) {
-        flags |= PTE_NO_EXEC;
+    Some(phys)
+}
+
+/// `Mm::fault` on `mm`, taking its lock. A missing file page is read in
+/// with the lock dropped, since the read may sleep on the disk, and mapped
+/// once it is retaken if it is still missing and still backed by the same
+/// file offset; otherwise the frame is dropped and the fault looked at
+/// afresh.
+pub fn fault_in(mm: &SpinLock<Mm>, addr: u64, write: bool) -> bool {
+    loop {
+        let (file, pos) = {
+            let mut mm = mm.lock();
+            match mm.missing_file_page(addr, write) {
+                Some(page) => page,
+                None => return mm.fault(addr, write),
+            }
+        };
+        let Some(phys) = read_file_page(&file, pos) else {
+            return false;
+        };
+        let mut mm = mm.lock();
+        let flags = mm
+            .missing_file_page(addr, write)
+            .filter(|(f, p)| Arc::ptr_eq(&f.inode, &file.inode) && *p == pos)
+            .and_then(|_| mm.vm.find_vma(addr).map(vma_pte_flags));
+        if let Some(flags) = flags {
+            if mm.space.map(align_down(addr, PAGE_SIZE as u64), phys, flags) {
+                return true;
+            }
+            free_frame(phys);
+            return false;
+        }
+        drop(mm);
+        free_frame(phys);
     }
+}
+
+/// A page msync has taken to write back.
+struct Writeback {
+    inode: Arc<Inode>,
+    pos: u64,
+    virt: u64,
+    phys: u64,
+}
 
-    space.map(page_addr, phys, flags)
+/// msync: write the dirty pages of MAP_SHARED file mappings in
+/// [start, end) back to their files. The writes may sleep on the disk, so
+/// they run with the mm lock dropped; the frame references `take_dirty`
+/// holds keep a concurrent munmap from freeing a page meanwhile. Returns
+/// the number of pages written, or None if part of the range is unmapped.
+pub fn sync_range(mm: &SpinLock<Mm>, start: u64, end: u64) -> Option<Result<usize, Errno>> {
+    let pages = mm.lock().take_dirty(start, end)?;
+    let mut written = 0;
+    let mut result = Ok(());
+    let mut unwritten = Vec::new();
+    for w in &pages {
+        let size = w.inode.stat().size;
+        // Never grow the file: only the part inside it is written
+        if result.is_ok() && w.pos < size {
+            let len = (size - w.pos).min(PAGE_SIZE as u64) as usize;
+            let data =
+                unsafe { core::slice::from_raw_parts(phys_to_virt(w.phys) as *const u8, len) };
+            match w.inode.ops.write(w.pos, data) {
+                Ok(_) => written += 1,
+                Err(e) => result = Err(e),
+            }
+        }
+        if result.is_err() || w.pos >= size {
+            unwritten.push(w);
+        }
+    }
+    if !unwritten.is_empty() {
+        mm.lock().redirty(&unwritten);
+    }
+    for w in &pages {
+        frame_release(w.phys);
+    }
+    Some(result.map(|()| written))
 }
 
-fn handle_cow(space: &mut AddressSpace, addr: u64) -> bool {
+/// Break copy-on-write sharing of the page at `addr`. The last owner just
+/// gets write access back; anyone else takes a private copy and drops its
+/// reference to the shared frame.
+fn handle_cow(space: &mut AddressSpace, addr: u64, vma: &VmaEntry) -> bool {
     let page_addr = align_down(addr, PAGE_SIZE as u64);
 
-    let old_phys = match space.translate(page_addr) {
-        Some(p) => p,
+    let old_phys = match space.pte(page_addr) {
+        Some(pte) => pte & PTE_ADDR_MASK,
         None => return false,
     };
 
-    let new_phys = match alloc_zeroed_frame() {
+    if frame_refcount(old_phys) == 1 {
+        return space.map(page_addr, old_phys, vma_pte_flags(vma));
+    }
+
+    let new_phys = match alloc_frame() {
         Some(p) => p,
         None => return false,
     };
@@ -364,7 +564,11 @@ fn handle_cow(space: &mut AddressSpace, addr: u64) -> bool {
         core::ptr::copy_nonoverlapping(src, dst, PAGE_SIZE);
     }
 
-    space.map(page_addr, new_phys, PTE_PRESENT | PTE_WRITABLE | PTE_USER);
+    if !space.map(page_addr, new_phys, vma_pte_flags(vma)) {
+        free_frame(new_phys);
+        return false;
+    }
+    frame_release(old_phys);
     true
 }
 
@@ -381,11 +585,27 @@ bitflags::bitflags! {
     }
 }
 
+/// Backing of a file mapping: `inode` contents starting at `offset`
+/// appear at the VMA's start.
+#[derive(Clone)]
+pub struct VmaFile {
+    pub inode: Arc<Inode>,
+    pub offset: u64,
+}
+
+impl core::fmt::Debug for VmaFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "ino {} +{:#x}", self.inode.ino, self.offset)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VmaEntry {
     pub start: u64,
     pub end: u64,
     pub flags: VmaFlags,
+    /// None for anonymous memory
+    pub file: Option<VmaFile>,
 }
 
 impl VmaEntry {
@@ -394,29 +614,432 @@ impl VmaEntry {
     }
 }
 
+/// Largest a GROWS_DOWN stack may become (like RLIMIT_STACK).
+pub const DEFAULT_STACK_LIMIT: u64 = 8 * 1024 * 1024;
+
+/// Unmapped distance a growing stack keeps from the mapping below it.
+pub const STACK_GUARD_GAP: u64 = 256 * PAGE_SIZE as u64;
+
+/// The process's VMAs as disjoint intervals keyed by start address.
+/// Neighbours with the same flags and backing are kept merged.
 pub struct VmSpace {
-    pub(crate) areas: alloc::vec::Vec<VmaEntry>,
+    pub(crate) areas: BTreeMap<u64, VmaEntry>,
+    /// The heap is [start_brk, brk), rounded up to pages in its VMA.
+    pub start_brk: u64,
     pub brk: u64,
+    pub stack_limit: u64,
 }
 
+/// mmap without an address searches upwards from here, well clear of the heap.
+pub const MMAP_BASE: u64 = 0x0000_7000_0000_0000;
+
+/// End of the lower canonical half.
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
 impl VmSpace {
     pub fn new() -> Self {
         Self {
-            areas: alloc::vec::Vec::new(),
+            areas: BTreeMap::new(),
+            start_brk: 0x1000_0000,
             brk: 0x1000_0000,
+            stack_limit: DEFAULT_STACK_LIMIT,
         }
     }
 
+    /// Start an empty heap at `base` (the end of the loaded program).
+    pub fn set_brk_base(&mut self, base: u64) {
+        self.start_brk = base;
+        self.brk = base;
+    }
+
+    /// Some VMA intersects [start, end).
+    pub fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.areas
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, a)| a.end > start)
+    }
+
+    /// A fault at `addr` just below a GROWS_DOWN VMA extends that VMA down
+    /// to the faulting page, as long as the stack stays within
+    /// `stack_limit` and clear of the guard gap above the next mapping.
+    pub fn grow_stack(&mut self, addr: u64) -> Option<VmaEntry> {
+        let page = align_down(addr, PAGE_SIZE as u64);
+        let (&key, stack) = self.areas.range(addr + 1..).next()?;
+        if !stack.flags.contains(VmaFlags::GROWS_DOWN) {
+            return None;
+        }
+        let below = self.areas.range(..=addr).next_back().map_or(0, |(_, a)| a.end);
+
+        if stack.end - page > self.stack_limit || page < below.saturating_add(STACK_GUARD_GAP) {
+            log::warn!(
+                "stack overflow at {:#x} (stack {:#x}-{:#x}, limit {} KiB)",
+                addr,
+                stack.start,
+                stack.end,
+                self.stack_limit / 1024
+            );
+            return None;
+        }
+        let mut stack = self.areas.remove(&key)?;
+        stack.start = page;
+        self.areas.insert(page, stack.clone());
+        Some(stack)
+    }
+
     pub fn find_vma(&self, addr: u64) -> Option<&VmaEntry> {
-        self.areas.iter().find(|a| a.contains(addr))
+        self.areas
+            .range(..=addr)
+            .next_back()
+            .map(|(_, a)| a)
+            .filter(|a| a.contains(addr))
+    }
+
+    /// Add an anonymous VMA. False if [start, end) is empty or already
+    /// (partly) mapped.
+    pub fn add_vma(&mut self, start: u64, end: u64, flags: VmaFlags) -> bool {
+        self.insert(VmaEntry { start, end, flags, file: None })
+    }
+
+    /// Map `inode` from `offset` at [start, end); pages are read in on fault.
+    pub fn add_file_vma(
+        &mut self,
+        start: u64,
+        end: u64,
+        flags: VmaFlags,
+        inode: Arc<Inode>,
+        offset: u64,
+    ) -> bool {
+        let file = Some(VmaFile { inode, offset });
+        self.insert(VmaEntry { start, end, flags, file })
+    }
+
+    fn insert(&mut self, vma: VmaEntry) -> bool {
+        if vma.start >= vma.end || self.overlaps(vma.start, vma.end) {
+            return false;
+        }
+        let (start, end) = (vma.start, vma.end);
+        self.areas.insert(start, vma);
+        self.try_merge(end);
+        self.try_merge(start);
+        true
+    }
+
+    /// Add `flags` to every VMA in [start, end), e.g. for an ELF page
+    /// shared by two segments.
+    pub fn widen(&mut self, start: u64, end: u64, flags: VmaFlags) {
+        self.split_at(start);
+        self.split_at(end);
+        for vma in self.areas.range_mut(start..end).map(|(_, a)| a) {
+            vma.flags |= flags;
+        }
+        self.coalesce(start, end);
+    }
+
+    /// Drop [start, end) from the VMAs, splitting the ones it cuts into.
+    /// Returns what was removed so the caller can unmap those pages.
+    pub fn remove_range(&mut self, start: u64, end: u64) -> Vec<VmaEntry> {
+        self.split_at(start);
+        self.split_at(end);
+        let keys: Vec<u64> = self.areas.range(start..end).map(|(&k, _)| k).collect();
+        keys.iter().filter_map(|k| self.areas.remove(k)).collect()
+    }
+
+    /// Bytes the VMAs cover: what RLIMIT_AS limits.
+    pub fn size(&self) -> u64 {
+        self.areas.values().map(|v| v.end - v.start).sum()
+    }
+
+    /// Bytes of [start, end) the VMAs cover.
+    pub fn size_in(&self, start: u64, end: u64) -> u64 {
+        let first = self.areas.range(..start).next_back().map_or(start, |(&k, _)| k);
+        self.areas
+            .range(first..end)
+            .map(|(_, v)| v.end.min(end).saturating_sub(v.start.max(start)))
+            .sum()
+    }
+
+    /// Every page of [start, end) belongs to some VMA.
+    pub fn covers(&self, start: u64, end: u64) -> bool {
+        let mut at = start;
+        while at < end {
+            match self.find_vma(at) {
+                Some(v) => at = v.end,
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Lowest `align`-aligned address at or above MMAP_BASE with `len`
+    /// unmapped bytes, below the room reserved for the stack to grow into.
+    pub fn find_free_region(&self, len: u64, align: u64) -> Option<u64> {
+        let align = align.max(PAGE_SIZE as u64);
+        let limit = self
+            .areas
+            .values()
+            .find(|a| a.flags.contains(VmaFlags::GROWS_DOWN))
+            .map_or(USER_SPACE_END, |s| {
+                s.end.saturating_sub(self.stack_limit + STACK_GUARD_GAP)
+            });
+
+        let mut cand = align_up(MMAP_BASE, align);
+        for vma in self.areas.values().filter(|a| a.end > MMAP_BASE) {
+            if cand.checked_add(len)? <= vma.start {
+                break;
+            }
+            cand = cand.max(align_up(vma.end, align));
+        }
+        (cand.checked_add(len)? <= limit).then_some(cand)
+    }
+
+    /// Cut the VMA containing `addr` in two so that `addr` is a boundary.
+    pub(crate) fn split_at(&mut self, addr: u64) {
+        let Some(lower) = self.areas.range_mut(..addr).next_back().map(|(_, a)| a) else {
+            return;
+        };
+        if lower.end <= addr {
+            return;
+        }
+        let mut upper = lower.clone();
+        if let Some(ref mut f) = upper.file {
+            f.offset += addr - upper.start;
+        }
+        upper.start = addr;
+        lower.end = addr;
+        self.areas.insert(addr, upper);
+    }
+
+    /// Merge the VMA ending at `at` with the one starting there if they
+    /// only differ in copy-on-write state and any file backing continues.
+    fn try_merge(&mut self, at: u64) {
+        let Some(hi) = self.areas.get(&at) else { return };
+        let Some((_, lo)) = self.areas.range(..at).next_back() else {
+            return;
+        };
+        let cow = VmaFlags::COPY_ON_WRITE;
+        let same_file = match (&lo.file, &hi.file) {
+            (None, None) => true,
+            (Some(a), Some(b)) => {
+                Arc::ptr_eq(&a.inode, &b.inode) && a.offset + (lo.end - lo.start) == b.offset
+            }
+            _ => false,
+        };
+        if lo.end != at || lo.flags - cow != hi.flags - cow || !same_file {
+            return;
+        }
+        let Some(hi) = self.areas.remove(&at) else { return };
+        if let Some((_, lo)) = self.areas.range_mut(..at).next_back() {
+            lo.end = hi.end;
+            lo.flags |= hi.flags & cow;
+        }
+    }
+
+    /// Merge whatever can be merged across the boundaries in [start, end].
+    fn coalesce(&mut self, start: u64, end: u64) {
+        let keys: Vec<u64> = self.areas.range(start..=end).map(|(&k, _)| k).collect();
+        for k in keys {
+            self.try_merge(k);
+        }
+    }
+}
+
+/// A user memory context: page tables plus the VMAs describing them.
+/// Threads created with CLONE_VM share one through `Arc<SpinLock<Mm>>`.
+pub struct Mm {
+    pub space: AddressSpace,
+    pub vm: VmSpace,
+}
+
+impl Mm {
+    pub fn new_shared(space: AddressSpace, vm: VmSpace) -> alloc::sync::Arc<SpinLock<Mm>> {
+        alloc::sync::Arc::new(SpinLock::new(Mm { space, vm }))
     }
 
-    pub fn add_vma(&mut self, start: u64, end: u64, flags: VmaFlags) {
-        self.areas.push(VmaEntry { start, end, flags });
-        self.areas.sort_unstable_by_key(|a| a.start);
+    /// An empty Mm on the kernel page tables, for threads that have given
+    /// their user address space up.
+    pub fn kernel_shared() -> alloc::sync::Arc<SpinLock<Mm>> {
+        Self::new_shared(
+            AddressSpace {
+                pml4_phys: kernel_pml4_phys(),
+            },
+            VmSpace::new(),
+        )
     }
 
-    pub fn remove_vma(&mut self, start: u64, end: u64) {
-        self.areas.retain(|a| !(a.start >= start && a.end <= end));
+    /// brk(): move the end of the heap to `new_brk`. Growing extends the heap
+    /// VMA (pages are faulted in on use); shrinking unmaps and frees the
+    /// pages past the new end. Returns the resulting break, unchanged if the
+    /// request is below the heap start or runs into another mapping.
+    pub fn set_brk(&mut self, new_brk: u64) -> u64 {
+        let vm = &mut self.vm;
+        // Bounded before rounding, which could otherwise overflow
+        if new_brk < vm.start_brk || new_brk > USER_SPACE_END {
+            return vm.brk;
+        }
+        let page = PAGE_SIZE as u64;
+        let old_end = align_up(vm.brk, page);
+        let new_end = align_up(new_brk, page);
+
+        if new_end > old_end {
+            // Merges into the existing heap VMA, so there stays just one
+            let heap = VmaFlags::READ | VmaFlags::WRITE | VmaFlags::ANONYMOUS;
+            if !vm.add_vma(old_end, new_end, heap) {
+                return vm.brk;
+            }
+        } else if new_end < old_end {
+            self.unmap_range(new_end, old_end);
+        }
+        self.vm.brk = new_brk;
+        new_brk
+    }
+
+    /// Resolve an access to `addr` the way a page fault would: grow the
+    /// stack, page in missing memory, break copy-on-write for writes.
+    /// True when the page is now mapped with the access allowed. Missing
+    /// file pages are left to `fault_in`, which reads them unlocked.
+    fn fault(&mut self, addr: u64, write: bool) -> bool {
+        let vma = match self.vm.find_vma(addr).cloned() {
+            Some(v) => v,
+            None => match self.vm.grow_stack(addr) {
+                Some(v) => v,
+                None => return false,
+            },
+        };
+
+        if write && !vma.flags.contains(VmaFlags::WRITE) {
+            return false;
+        }
+
+        let Some(pte) = self.space.pte(addr) else {
+            return handle_demand_page(&mut self.space, addr, &vma);
+        };
+        // Another thread sharing the Mm may have got here first
+        if !write || pte & PTE_WRITABLE != 0 {
+            return true;
+        }
+
+        // Write to a present read-only page of a writable VMA. fork
+        // write-protects every user page, but a shared mapping's stay
+        // shared: it just gets write access back
+        if vma.flags.contains(VmaFlags::SHARED) {
+            let page = align_down(addr, PAGE_SIZE as u64);
+            return self.space.map(page, pte & PTE_ADDR_MASK, vma_pte_flags(&vma));
+        }
+        // Otherwise only legal when the VMA was made copy-on-write by fork
+        if vma.flags.contains(VmaFlags::COPY_ON_WRITE) {
+            return handle_cow(&mut self.space, addr, &vma);
+        }
+        false
+    }
+
+    /// The file and offset to read for a fault at `addr`, if it hits a
+    /// file mapping where no page is mapped yet.
+    fn missing_file_page(&self, addr: u64, write: bool) -> Option<(VmaFile, u64)> {
+        let vma = self.vm.find_vma(addr)?;
+        let f = vma.file.as_ref()?;
+        if (write && !vma.flags.contains(VmaFlags::WRITE)) || self.space.pte(addr).is_some() {
+            return None;
+        }
+        Some((f.clone(), f.offset + (align_down(addr, PAGE_SIZE as u64) - vma.start)))
+    }
+
+    /// Pages of the VMAs that are mapped in, shared ones included.
+    pub fn resident_pages(&self) -> u64 {
+        let mut n = 0;
+        for vma in self.vm.areas.values() {
+            n += (vma.start..vma.end)
+                .step_by(PAGE_SIZE)
+                .filter(|&v| self.space.pte(v).is_some_and(|pte| pte & PTE_USER != 0))
+                .count() as u64;
+        }
+        n
+    }
+
+    /// munmap: drop the VMAs in [start, end) and free the pages they had
+    /// mapped.
+    pub fn unmap_range(&mut self, start: u64, end: u64) {
+        for vma in self.vm.remove_range(start, end) {
+            let mut v = vma.start;
+            while v < vma.end {
+                self.space.unmap_user(v);
+                v += PAGE_SIZE as u64;
+            }
+        }
+    }
+
+    /// mprotect: give [start, end) the access bits `prot` (READ/WRITE/EXEC)
+    /// and rewrite the PTEs already present. Pages still shared
+    /// copy-on-write stay read-only until written. False if part of the
+    /// range is unmapped.
+    pub fn protect(&mut self, start: u64, end: u64, prot: VmaFlags) -> bool {
+        if !self.vm.covers(start, end) {
+            return false;
+        }
+        self.vm.split_at(start);
+        self.vm.split_at(end);
+
+        let rwx = VmaFlags::READ | VmaFlags::WRITE | VmaFlags::EXEC;
+        for vma in self.vm.areas.range_mut(start..end).map(|(_, a)| a) {
+            vma.flags = (vma.flags - rwx) | (prot & rwx);
+            // Frames may still be shared with a fork from when the area was
+            // read-only; the write fault must know it may copy them
+            if prot.contains(VmaFlags::WRITE) && !vma.flags.contains(VmaFlags::SHARED) {
+                vma.flags |= VmaFlags::COPY_ON_WRITE;
+            }
+
+            let flags = vma_pte_flags(vma);
+            let mut v = vma.start;
+            while v < vma.end {
+                if let Some(pte) = self.space.pte(v) {
+                    let phys = pte & PTE_ADDR_MASK;
+                    let mut new = flags | (pte & (PTE_ACCESSED | PTE_DIRTY));
+                    if pte & PTE_WRITABLE == 0 && frame_refcount(phys) > 1 {
+                        new &= !PTE_WRITABLE;
+                    }
+                    self.space.map(v, phys, new);
+                }
+                v += PAGE_SIZE as u64;
+            }
+        }
+        self.vm.coalesce(start, end);
+        true
+    }
+
+    /// The dirty pages of MAP_SHARED file mappings in [start, end), marked
+    /// clean, each holding a reference to its frame that the caller drops
+    /// once the page is written out. None if part of the range is unmapped.
+    fn take_dirty(&mut self, start: u64, end: u64) -> Option<Vec<Writeback>> {
+        if !self.vm.covers(start, end) {
+            return None;
+        }
+        let mut pages = Vec::new();
+        for vma in self.vm.areas.values() {
+            let Some(ref f) = vma.file else { continue };
+            if !vma.flags.contains(VmaFlags::SHARED) || vma.end <= start || vma.start >= end {
+                continue;
+            }
+            let mut v = vma.start.max(start);
+            while v < vma.end.min(end) {
+                if let Some(pte) = self.space.pte(v).filter(|&p| p & PTE_DIRTY != 0) {
+                    let phys = pte & PTE_ADDR_MASK;
+                    frame_share(phys);
+                    self.space.map(v, phys, pte & !PTE_ADDR_MASK & !PTE_DIRTY);
+                    let pos = f.offset + (v - vma.start);
+                    pages.push(Writeback { inode: f.inode.clone(), pos, virt: v, phys });
+                }
+                v += PAGE_SIZE as u64;
+            }
+        }
+        Some(pages)
+    }
+
+    /// Mark dirty again the pages of `pages` still mapped where they were.
+    fn redirty(&mut self, pages: &[&Writeback]) {
+        for w in pages {
+            if let Some(pte) = self.space.pte(w.virt).filter(|&p| p & PTE_ADDR_MASK == w.phys) {
+                self.space.map(w.virt, w.phys, (pte & !PTE_ADDR_MASK) | PTE_DIRTY);
+            }
+        }
     }
 }
@@ -0,0 +1,151 @@
+//! Network stack: Ethernet, ARP, IPv4, ICMP, UDP and TCP over the NICs
+//! that `drivers::net` knows, plus a DHCP client, a stub DNS resolver and
+//! a telnet server for the shell.
+//!
+//! Received frames are run up through the layers by one kernel task,
+//! `netd`; sending happens in the caller's context and never blocks, a
+//! packet for a neighbour not yet resolved waits in the ARP layer instead.
+//! Each interface gets an address, netmask and optional gateway with
+//! `configure`, by DHCP at boot or the `ifconfig` builtin; routing is "on
+//! one of the interfaces' subnets, or else through the first gateway".
+pub mod arp;
+pub mod dhcp;
+pub mod dns;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod socket;
+pub mod tcp;
+pub mod telnet;
+pub mod udp;
+
+pub use ipv4::Ipv4Addr;
+
+use crate::drivers::net::{self as netdev, Interface};
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No interface reaches the destination.
+    NoRoute,
+    AddrInUse,
+    TooBig,
+    /// The NIC refused the frame.
+    Device(&'static str),
+    /// No answer in time.
+    Timeout,
+    /// The name doesn't exist.
+    NotFound,
+    NoNameServer,
+    /// The peer reset the connection.
+    Reset,
+    /// Nothing listens on the port.
+    Refused,
+    /// The connection was closed for sending.
+    Closed,
+    /// A signal cut the wait short.
+    Interrupted,
+}
+
+impl Error {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::NoRoute => "network is unreachable",
+            Error::AddrInUse => "address already in use",
+            Error::TooBig => "message too long",
+            Error::Device(e) => e,
+            Error::Timeout => "timed out",
+            Error::NotFound => "host not found",
+            Error::NoNameServer => "no name server",
+            Error::Reset => "connection reset by peer",
+            Error::Refused => "connection refused",
+            Error::Closed => "broken pipe",
+            Error::Interrupted => "interrupted",
+        }
+    }
+}
+
+/// IPv4 settings of one interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IfConfig {
+    pub addr: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    /// UNSPECIFIED when there is none.
+    pub gateway: Ipv4Addr,
+}
+
+impl IfConfig {
+    fn on_link(&self, dst: Ipv4Addr) -> bool {
+        dst.to_u32() & self.netmask.to_u32() == self.addr.to_u32() & self.netmask.to_u32()
+    }
+
+    /// The subnet's broadcast address.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.addr.to_u32() | !self.netmask.to_u32())
+    }
+}
+
+/// Interface index → its settings; unconfigured interfaces are missing.
+static CONFIGS: SpinLock<BTreeMap<usize, IfConfig>> = SpinLock::new(BTreeMap::new());
+
+pub fn config(index: usize) -> Option<IfConfig> {
+    CONFIGS.lock().get(&index).copied()
+}
+
+pub fn configure(index: usize, cfg: IfConfig) {
+    CONFIGS.lock().insert(index, cfg);
+}
+
+/// Take the interface's address away.
+pub fn deconfigure(index: usize) {
+    CONFIGS.lock().remove(&index);
+}
+
+/// Whether `addr` is one of ours.
+pub fn is_local(addr: Ipv4Addr) -> bool {
+    CONFIGS.lock().values().any(|c| c.addr == addr)
+}
+
+/// The interface packets for `dst` leave through, its settings, and the
+/// next hop: `dst` itself when it is on the interface's subnet (or a
+/// broadcast), else the gateway.
+pub fn route(dst: Ipv4Addr) -> Option<(Arc<Interface>, IfConfig, Ipv4Addr)> {
+    let configs = CONFIGS.lock();
+    let (&index, cfg, hop) = if dst.is_broadcast() {
+        configs.iter().next().map(|(i, c)| (i, c, dst))?
+    } else if let Some((i, c)) = configs.iter().find(|(_, c)| c.on_link(dst)) {
+        (i, c, dst)
+    } else {
+        configs
+            .iter()
+            .find(|(_, c)| !c.gateway.is_unspecified())
+            .map(|(i, c)| (i, c, c.gateway))?
+    };
+    let cfg = *cfg;
+    drop(configs);
+    Some((netdev::get(index)?, cfg, hop))
+}
+
+/// The stack's receive loop: every frame any NIC takes in goes up from
+/// here.
+fn netd_task() -> ! {
+    loop {
+        let (iface, frame) = netdev::receive();
+        ethernet::input(&iface, &frame);
+    }
+}
+
+/// Start `netd` if there is a NIC to serve.
+pub fn init() {
+    if netdev::count() == 0 {
+        return;
+    }
+    match crate::proc::Process::new_kernel("netd", netd_task, 5) {
+        Some(p) => crate::proc::scheduler::spawn(p),
+        None => log::warn!("net: cannot start netd"),
+    }
+    dhcp::init();
+    telnet::init();
+}
@@ -0,0 +1,176 @@
+//! ARP: IPv4 → Ethernet address resolution.
+//!
+//! Answers requests for our addresses and keeps a cache of what it hears.
+//! A packet for a neighbour that isn't cached yet is held here while a
+//! request goes out, and sent as soon as the reply arrives, so nobody
+//! sending ever has to wait for the network.
+use super::ethernet::{self, BROADCAST, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use super::{Error, Ipv4Addr};
+use crate::arch::x86_64::timer::nanos;
+use crate::drivers::net::{self as netdev, Interface, MacAddr};
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const HTYPE_ETHERNET: u16 = 1;
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+const PACKET_LEN: usize = 28;
+
+/// Cached entries are trusted this long.
+const ENTRY_TTL_NS: u64 = 600_000_000_000;
+/// Held packets are dropped after this if no reply came.
+const HOLD_NS: u64 = 3_000_000_000;
+/// Packets held at once, over all neighbours.
+const HOLD_MAX: usize = 32;
+/// At most one request per neighbour this often.
+const REQUEST_INTERVAL_NS: u64 = 1_000_000_000;
+
+struct Held {
+    iface: usize,
+    ip: Ipv4Addr,
+    ethertype: u16,
+    payload: Vec<u8>,
+    since: u64,
+}
+
+struct Arp {
+    /// Neighbour → (MAC, when it was learnt).
+    cache: BTreeMap<Ipv4Addr, (MacAddr, u64)>,
+    held: Vec<Held>,
+    /// Neighbour → when we last asked for it.
+    asked: BTreeMap<Ipv4Addr, u64>,
+}
+
+static ARP: SpinLock<Arp> = SpinLock::new(Arp {
+    cache: BTreeMap::new(),
+    held: Vec::new(),
+    asked: BTreeMap::new(),
+});
+
+fn packet(op: u16, sha: MacAddr, spa: Ipv4Addr, tha: MacAddr, tpa: Ipv4Addr) -> [u8; PACKET_LEN] {
+    let mut p = [0u8; PACKET_LEN];
+    p[0..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    p[2..4].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+    p[4] = 6;
+    p[5] = 4;
+    p[6..8].copy_from_slice(&op.to_be_bytes());
+    p[8..14].copy_from_slice(&sha);
+    p[14..18].copy_from_slice(&spa.0);
+    p[18..24].copy_from_slice(&tha);
+    p[24..28].copy_from_slice(&tpa.0);
+    p
+}
+
+fn request(iface: &Interface, ip: Ipv4Addr) {
+    let spa = super::config(iface.index).map_or(Ipv4Addr::UNSPECIFIED, |c| c.addr);
+    let p = packet(OP_REQUEST, iface.dev.mac(), spa, [0; 6], ip);
+    let _ = ethernet::send(iface, BROADCAST, ETHERTYPE_ARP, &p);
+}
+
+/// The cached address of neighbour `ip`, if still fresh.
+pub fn lookup(ip: Ipv4Addr) -> Option<MacAddr> {
+    let now = nanos();
+    ARP.lock()
+        .cache
+        .get(&ip)
+        .filter(|&&(_, t)| now - t < ENTRY_TTL_NS)
+        .map(|&(mac, _)| mac)
+}
+
+/// Send `payload` to neighbour `ip` on `iface`, now if its address is
+/// known, else once it is resolved. Broadcasts need no resolving.
+pub fn send(iface: &Interface, ip: Ipv4Addr, ethertype: u16, payload: &[u8]) -> Result<(), Error> {
+    let subnet_broadcast = super::config(iface.index).is_some_and(|c| c.broadcast() == ip);
+    if ip.is_broadcast() || subnet_broadcast {
+        return ethernet::send(iface, BROADCAST, ethertype, payload);
+    }
+    if let Some(mac) = lookup(ip) {
+        return ethernet::send(iface, mac, ethertype, payload);
+    }
+
+    let now = nanos();
+    let ask = {
+        let mut arp = ARP.lock();
+        arp.held.retain(|h| now - h.since < HOLD_NS);
+        if arp.held.len() < HOLD_MAX {
+            arp.held.push(Held {
+                iface: iface.index,
+                ip,
+                ethertype,
+                payload: payload.to_vec(),
+                since: now,
+            });
+        }
+        let last = arp.asked.get(&ip).copied();
+        let ask = last.is_none_or(|t| now - t >= REQUEST_INTERVAL_NS);
+        if ask {
+            arp.asked.insert(ip, now);
+        }
+        ask
+    };
+    if ask {
+        request(iface, ip);
+    }
+    Ok(())
+}
+
+/// An ARP packet `iface` received.
+pub fn input(iface: &Arc<Interface>, p: &[u8]) {
+    if p.len() < PACKET_LEN
+        || u16::from_be_bytes([p[0], p[1]]) != HTYPE_ETHERNET
+        || u16::from_be_bytes([p[2], p[3]]) != ETHERTYPE_IPV4
+        || p[4] != 6
+        || p[5] != 4
+    {
+        return;
+    }
+    let op = u16::from_be_bytes([p[6], p[7]]);
+    let sha: MacAddr = p[8..14].try_into().unwrap_or([0; 6]);
+    let spa = Ipv4Addr(p[14..18].try_into().unwrap_or([0; 4]));
+    let tpa = Ipv4Addr(p[24..28].try_into().unwrap_or([0; 4]));
+    let ours = super::config(iface.index).filter(|c| c.addr == tpa && !tpa.is_unspecified());
+
+    // RFC 826: refresh what we know, learn the sender if it talks to us
+    // (or answers what we asked)
+    let now = nanos();
+    let ready: Vec<Held> = {
+        let mut arp = ARP.lock();
+        if spa.is_unspecified() {
+            Vec::new()
+        } else {
+            if ours.is_some() || arp.cache.contains_key(&spa) || arp.asked.contains_key(&spa) {
+                arp.cache.insert(spa, (sha, now));
+                arp.asked.remove(&spa);
+            }
+            let (ready, rest) = core::mem::take(&mut arp.held)
+                .into_iter()
+                .partition(|h| h.ip == spa && arp.cache.contains_key(&spa));
+            arp.held = rest;
+            ready
+        }
+    };
+    for h in ready {
+        if let Some(dev) = netdev::get(h.iface) {
+            let _ = ethernet::send(&dev, sha, h.ethertype, &h.payload);
+        }
+    }
+
+    if op == OP_REQUEST {
+        if let Some(cfg) = ours {
+            let reply = packet(OP_REPLY, iface.dev.mac(), cfg.addr, sha, spa);
+            let _ = ethernet::send(iface, sha, ETHERTYPE_ARP, &reply);
+        }
+    }
+}
+
+/// The cache, for display: (neighbour, MAC, age in ns).
+pub fn entries() -> Vec<(Ipv4Addr, MacAddr, u64)> {
+    let now = nanos();
+    ARP.lock()
+        .cache
+        .iter()
+        .map(|(&ip, &(mac, t))| (ip, mac, now - t))
+        .collect()
+}
@@ -0,0 +1,344 @@
+//! DHCP client (RFC 2131). A kernel task, `dhcpd`, leases an address for
+//! every interface at boot, sets the netmask, gateway and name servers it
+//! comes with, and renews the lease halfway through. An interface whose
+//! address was changed by hand (`ifconfig`) is left alone from then on.
+//!
+//! Requests go out as broadcasts with the broadcast flag set, so the server
+//! answers with broadcasts too: until it has an address, an interface takes
+//! in nothing else.
+use super::udp::UdpSocket;
+use super::{IfConfig, Ipv4Addr};
+use crate::arch::x86_64::timer::nanos;
+use crate::drivers::net::{self as netdev, Interface, MacAddr};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC: [u8; 4] = [99, 130, 83, 99];
+/// The fixed BOOTP part, up to the magic cookie.
+const FIXED_LEN: usize = 236;
+/// Some servers ignore anything shorter, as BOOTP relays may.
+const MIN_LEN: usize = 300;
+
+// Message types
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+// Options
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MSG_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMS: u8 = 55;
+const OPT_END: u8 = 255;
+
+/// Sends per exchange; the wait for an answer doubles after each.
+const TRIES: u32 = 4;
+const FIRST_TIMEOUT_NS: u64 = 1_000_000_000;
+/// Pause before trying again after getting nowhere.
+const RETRY_NS: u64 = 60_000_000_000;
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// What a server said.
+#[derive(Debug, Clone)]
+struct Reply {
+    kind: u8,
+    xid: u32,
+    chaddr: MacAddr,
+    yiaddr: Ipv4Addr,
+    server: Ipv4Addr,
+    netmask: Option<Ipv4Addr>,
+    router: Option<Ipv4Addr>,
+    dns: Vec<Ipv4Addr>,
+    /// Seconds; None for ever.
+    lease: Option<u32>,
+}
+
+fn message(kind: u8, xid: u32, mac: MacAddr, ciaddr: Ipv4Addr, opts: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut m = Vec::with_capacity(MIN_LEN);
+    m.extend_from_slice(&[OP_REQUEST, HTYPE_ETHERNET, 6, 0]);
+    m.extend_from_slice(&xid.to_be_bytes());
+    m.extend_from_slice(&[0, 0]); // secs
+    m.extend_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    m.extend_from_slice(&ciaddr.0);
+    m.resize(28, 0); // yiaddr, siaddr, giaddr
+    m.extend_from_slice(&mac);
+    m.resize(FIXED_LEN, 0); // rest of chaddr, sname, file
+    m.extend_from_slice(&MAGIC);
+    m.extend_from_slice(&[OPT_MSG_TYPE, 1, kind]);
+    for (code, data) in opts {
+        m.push(*code);
+        m.push(data.len() as u8);
+        m.extend_from_slice(data);
+    }
+    m.push(OPT_END);
+    if m.len() < MIN_LEN {
+        m.resize(MIN_LEN, 0);
+    }
+    m
+}
+
+fn addr_at(d: &[u8], at: usize) -> Ipv4Addr {
+    Ipv4Addr(d[at..at + 4].try_into().unwrap_or([0; 4]))
+}
+
+fn parse(d: &[u8]) -> Option<Reply> {
+    if d.len() < FIXED_LEN + MAGIC.len() || d[0] != OP_REPLY || d[FIXED_LEN..][..4] != MAGIC {
+        return None;
+    }
+    let mut r = Reply {
+        kind: 0,
+        xid: u32::from_be_bytes(d[4..8].try_into().ok()?),
+        chaddr: d[28..34].try_into().ok()?,
+        yiaddr: addr_at(d, 16),
+        server: Ipv4Addr::UNSPECIFIED,
+        netmask: None,
+        router: None,
+        dns: Vec::new(),
+        lease: None,
+    };
+    let mut opts = &d[FIXED_LEN + MAGIC.len()..];
+    while let [code, rest @ ..] = opts {
+        match *code {
+            OPT_PAD => {
+                opts = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let val = rest.get(..len as usize)?;
+        opts = &rest[len as usize..];
+        match (*code, val.len()) {
+            (OPT_MSG_TYPE, 1) => r.kind = val[0],
+            (OPT_SUBNET_MASK, 4) => r.netmask = Some(addr_at(val, 0)),
+            (OPT_ROUTER, 4..) => r.router = Some(addr_at(val, 0)),
+            (OPT_DNS, _) => r.dns = val.chunks_exact(4).map(|a| addr_at(a, 0)).collect(),
+            (OPT_SERVER_ID, 4) => r.server = addr_at(val, 0),
+            (OPT_LEASE_TIME, 4) => {
+                r.lease = match u32::from_be_bytes(val.try_into().ok()?) {
+                    u32::MAX => None,
+                    secs => Some(secs),
+                }
+            }
+            _ => {}
+        }
+    }
+    (r.kind != 0).then_some(r)
+}
+
+/// Broadcast `msg` from `src` and wait for a reply to it that `want`s,
+/// sending it again (with longer waits) until one comes.
+fn exchange(
+    sock: &UdpSocket,
+    iface: &Interface,
+    src: Ipv4Addr,
+    msg: &[u8],
+    want: impl Fn(&Reply) -> bool,
+) -> Option<Reply> {
+    let xid = u32::from_be_bytes(msg[4..8].try_into().ok()?);
+    let mac = iface.dev.mac();
+    let mut timeout = FIRST_TIMEOUT_NS;
+    for _ in 0..TRIES {
+        // Whatever is still queued answers an older exchange
+        while sock.try_recv().is_some() {}
+        sock.send_via(iface, src, Ipv4Addr::BROADCAST, SERVER_PORT, msg).ok()?;
+        let deadline = nanos() + timeout;
+        while let Some(d) = sock.recv(Some(deadline)) {
+            match parse(&d.data) {
+                Some(r) if r.xid == xid && r.chaddr == mac && want(&r) => return Some(r),
+                _ => {}
+            }
+        }
+        timeout *= 2;
+    }
+    None
+}
+
+fn new_xid() -> u32 {
+    let mut b = [0u8; 4];
+    crate::fs::devfs::random_fill(&mut b);
+    u32::from_ne_bytes(b)
+}
+
+/// The exchange after the offer (or when renewing `ciaddr`): a request for
+/// `addr` from `server`. The ACK or NAK, None on silence.
+fn request(
+    sock: &UdpSocket,
+    iface: &Interface,
+    ciaddr: Ipv4Addr,
+    addr: Ipv4Addr,
+    server: Ipv4Addr,
+) -> Option<Reply> {
+    let mac = iface.dev.mac();
+    let params = [OPT_SUBNET_MASK, OPT_ROUTER, OPT_DNS, OPT_LEASE_TIME];
+    // A renewing client names itself in ciaddr; a selecting one asks for
+    // the offered address and says whose offer it took
+    let opts: &[(u8, &[u8])] = if ciaddr.is_unspecified() {
+        &[(OPT_REQUESTED_IP, &addr.0), (OPT_SERVER_ID, &server.0), (OPT_PARAMS, &params)]
+    } else {
+        &[(OPT_PARAMS, &params)]
+    };
+    let msg = message(REQUEST, new_xid(), mac, ciaddr, opts);
+    exchange(sock, iface, ciaddr, &msg, |r| {
+        matches!(r.kind, ACK | NAK) && (server.is_unspecified() || r.server == server)
+    })
+}
+
+/// A lease from scratch: discover, take the first offer.
+fn acquire(sock: &UdpSocket, iface: &Interface) -> Option<Reply> {
+    let params = [OPT_SUBNET_MASK, OPT_ROUTER, OPT_DNS, OPT_LEASE_TIME];
+    let msg = message(
+        DISCOVER,
+        new_xid(),
+        iface.dev.mac(),
+        Ipv4Addr::UNSPECIFIED,
+        &[(OPT_PARAMS, &params)],
+    );
+    let offer = exchange(sock, iface, Ipv4Addr::UNSPECIFIED, &msg, |r| r.kind == OFFER)?;
+    let ack = request(sock, iface, Ipv4Addr::UNSPECIFIED, offer.yiaddr, offer.server)?;
+    (ack.kind == ACK).then_some(ack)
+}
+
+struct Lease {
+    cfg: IfConfig,
+    server: Ipv4Addr,
+    /// nanos() to renew at and to give the address up at; None for ever.
+    renew_at: Option<u64>,
+    expires: Option<u64>,
+}
+
+enum State {
+    /// No address yet; try again at this time.
+    Unbound(u64),
+    Bound(Lease),
+    /// Configured by hand: not ours to touch.
+    Manual,
+}
+
+/// Put the address in an ACK to use.
+fn bind(iface: &Interface, ack: &Reply) -> Lease {
+    let cfg = IfConfig {
+        addr: ack.yiaddr,
+        netmask: ack.netmask.unwrap_or(Ipv4Addr::netmask(24)),
+        gateway: ack.router.unwrap_or(Ipv4Addr::UNSPECIFIED),
+    };
+    super::configure(iface.index, cfg);
+    if !ack.dns.is_empty() {
+        super::dns::set_servers(&ack.dns);
+    }
+    log::info!(
+        "dhcp: {} is {} netmask {} gateway {}, lease {} s",
+        iface.dev.name(),
+        cfg.addr,
+        cfg.netmask,
+        cfg.gateway,
+        ack.lease.map_or(-1, |s| s as i64)
+    );
+    let now = nanos();
+    let lease_ns = ack.lease.map(|s| s as u64 * NS_PER_SEC);
+    Lease {
+        cfg,
+        server: ack.server,
+        renew_at: lease_ns.map(|l| now + l / 2),
+        expires: lease_ns.map(|l| now + l),
+    }
+}
+
+/// Move one interface on; returns its next state.
+fn step(sock: &UdpSocket, iface: &Arc<Interface>, state: State, now: u64) -> State {
+    match state {
+        State::Unbound(at) if at <= now => match acquire(sock, iface) {
+            Some(ack) => State::Bound(bind(iface, &ack)),
+            None => {
+                log::warn!("dhcp: {}: no answer, retrying in 60 s", iface.dev.name());
+                State::Unbound(nanos() + RETRY_NS)
+            }
+        },
+        State::Bound(lease) if super::config(iface.index) != Some(lease.cfg) => State::Manual,
+        State::Bound(lease) if lease.renew_at.is_some_and(|t| t <= now) => {
+            let cfg = lease.cfg;
+            let reply = request(sock, iface, cfg.addr, cfg.addr, lease.server);
+            let now = nanos();
+            match (reply, lease.expires) {
+                (Some(ack), _) if ack.kind == ACK => State::Bound(bind(iface, &ack)),
+                (Some(_), _) => {
+                    log::warn!("dhcp: {}: {} refused", iface.dev.name(), cfg.addr);
+                    super::deconfigure(iface.index);
+                    State::Unbound(now)
+                }
+                (None, Some(end)) if end <= now => {
+                    log::warn!("dhcp: {}: lease on {} expired", iface.dev.name(), cfg.addr);
+                    super::deconfigure(iface.index);
+                    State::Unbound(now)
+                }
+                (None, end) => State::Bound(Lease {
+                    renew_at: Some(end.map_or(now + RETRY_NS, |e| e.min(now + RETRY_NS))),
+                    ..lease
+                }),
+            }
+        }
+        s => s,
+    }
+}
+
+/// When `state` next needs attention.
+fn next_event(state: &State) -> Option<u64> {
+    match state {
+        State::Unbound(at) => Some(*at),
+        State::Bound(lease) => lease.renew_at,
+        State::Manual => None,
+    }
+}
+
+fn dhcpd_task() -> ! {
+    let sock = match UdpSocket::bind(CLIENT_PORT) {
+        Ok(s) => s,
+        Err(e) => {
+            log::warn!("dhcp: port {}: {}", CLIENT_PORT, e.as_str());
+            crate::proc::exit_current(1);
+        }
+    };
+    let mut states: BTreeMap<usize, State> = BTreeMap::new();
+    loop {
+        for iface in netdev::interfaces() {
+            let state = states.remove(&iface.index).unwrap_or_else(|| {
+                match super::config(iface.index) {
+                    Some(_) => State::Manual,
+                    None => State::Unbound(0),
+                }
+            });
+            let state = step(&sock, &iface, state, nanos());
+            states.insert(iface.index, state);
+        }
+        match states.values().filter_map(next_event).min() {
+            Some(at) => {
+                crate::proc::timers::sleep_until(at);
+            }
+            // Every address is static or leased for ever
+            None => crate::proc::exit_current(0),
+        }
+    }
+}
+
+pub fn init() {
+    match crate::proc::Process::new_kernel("dhcpd", dhcpd_task, 5) {
+        Some(p) => crate::proc::scheduler::spawn(p),
+        None => log::warn!("dhcp: cannot start dhcpd"),
+    }
+}
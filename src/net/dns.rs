@@ -0,0 +1,139 @@
+//! Stub DNS resolver: A-record queries over UDP to the name servers DHCP
+//! handed out. No cache, no TCP fallback; answers too big for one
+//! datagram come back truncated and are used as far as they go.
+use super::udp::UdpSocket;
+use super::{Error, Ipv4Addr};
+use crate::arch::x86_64::timer::nanos;
+use crate::sync::spinlock::SpinLock;
+use alloc::vec::Vec;
+
+const PORT: u16 = 53;
+const HEADER_LEN: usize = 12;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_MASK: u16 = 0xF;
+const RCODE_NXDOMAIN: u16 = 3;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+
+/// Each server gets this long to answer, and this many chances.
+const TIMEOUT_NS: u64 = 2_000_000_000;
+const ROUNDS: usize = 2;
+
+static SERVERS: SpinLock<Vec<Ipv4Addr>> = SpinLock::new(Vec::new());
+
+pub fn servers() -> Vec<Ipv4Addr> {
+    SERVERS.lock().clone()
+}
+
+pub fn set_servers(servers: &[Ipv4Addr]) {
+    *SERVERS.lock() = servers.to_vec();
+}
+
+fn query(id: u16, name: &str) -> Option<Vec<u8>> {
+    let mut q = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    q.extend_from_slice(&id.to_be_bytes());
+    q.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    q.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]); // one question
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        q.push(label.len() as u8);
+        q.extend_from_slice(label.as_bytes());
+    }
+    q.push(0);
+    q.extend_from_slice(&TYPE_A.to_be_bytes());
+    q.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Some(q)
+}
+
+/// Where the (possibly compressed) name at `at` ends.
+fn skip_name(d: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *d.get(at)?;
+        match len {
+            0 => return Some(at + 1),
+            l if l & 0xC0 == 0xC0 => return Some(at + 2),
+            l => at += 1 + l as usize,
+        }
+    }
+}
+
+fn be16(d: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*d.get(at)?, *d.get(at + 1)?]))
+}
+
+/// The A records in an answer to query `id`. None if it isn't one,
+/// NotFound if the name doesn't exist.
+fn parse(d: &[u8], id: u16) -> Option<Result<Vec<Ipv4Addr>, Error>> {
+    let flags = be16(d, 2)?;
+    if be16(d, 0)? != id || flags & FLAG_RESPONSE == 0 {
+        return None;
+    }
+    match flags & RCODE_MASK {
+        0 => {}
+        RCODE_NXDOMAIN => return Some(Err(Error::NotFound)),
+        // A server failure: let the next server try
+        _ => return None,
+    }
+    let questions = be16(d, 4)?;
+    let answers = be16(d, 6)?;
+    let mut at = HEADER_LEN;
+    for _ in 0..questions {
+        at = skip_name(d, at)? + 4;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        // CNAMEs come first and are skipped: the A records that follow
+        // are for the name they point to
+        let Some(rr) = skip_name(d, at) else { break };
+        let (Some(kind), Some(class), Some(len)) =
+            (be16(d, rr), be16(d, rr + 2), be16(d, rr + 8))
+        else {
+            break;
+        };
+        let data = rr + 10;
+        let Some(rdata) = d.get(data..data + len as usize) else { break };
+        if kind == TYPE_A && class == CLASS_IN && len == 4 {
+            addrs.push(Ipv4Addr([rdata[0], rdata[1], rdata[2], rdata[3]]));
+        }
+        at = data + len as usize;
+    }
+    Some(if addrs.is_empty() { Err(Error::NotFound) } else { Ok(addrs) })
+}
+
+/// `name`'s IPv4 addresses. A dotted quad is its own answer.
+pub fn resolve(name: &str) -> Result<Vec<Ipv4Addr>, Error> {
+    if let Some(addr) = Ipv4Addr::parse(name) {
+        return Ok(alloc::vec![addr]);
+    }
+    let servers = servers();
+    if servers.is_empty() {
+        return Err(Error::NoNameServer);
+    }
+    let mut id = [0u8; 2];
+    crate::fs::devfs::random_fill(&mut id);
+    let id = u16::from_ne_bytes(id);
+    let q = query(id, name).ok_or(Error::NotFound)?;
+    let sock = UdpSocket::bind(0)?;
+    let mut last = Error::Timeout;
+    for _ in 0..ROUNDS {
+        for &server in &servers {
+            if let Err(e) = sock.send_to(server, PORT, &q) {
+                last = e;
+                continue;
+            }
+            let deadline = nanos() + TIMEOUT_NS;
+            while let Some(d) = sock.recv(Some(deadline)) {
+                if d.from != server || d.port != PORT {
+                    continue;
+                }
+                if let Some(res) = parse(&d.data, id) {
+                    return res;
+                }
+            }
+        }
+    }
+    Err(last)
+}
@@ -0,0 +1,41 @@
+//! Ethernet II framing.
+use super::Error;
+use crate::drivers::net::{Interface, MacAddr};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub const HEADER_LEN: usize = 14;
+/// Largest payload of one frame.
+pub const MTU: usize = 1500;
+
+pub const BROADCAST: MacAddr = [0xFF; 6];
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+/// Frame `payload` for `dst` and put it on `iface`.
+pub fn send(iface: &Interface, dst: MacAddr, ethertype: u16, payload: &[u8]) -> Result<(), Error> {
+    if payload.len() > MTU {
+        return Err(Error::TooBig);
+    }
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&iface.dev.mac());
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    iface.send(&frame).map_err(Error::Device)
+}
+
+/// A frame `iface` received.
+pub fn input(iface: &Arc<Interface>, frame: &[u8]) {
+    if frame.len() < HEADER_LEN {
+        return;
+    }
+    let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+    let payload = &frame[HEADER_LEN..];
+    match ethertype {
+        ETHERTYPE_ARP => super::arp::input(iface, payload),
+        ETHERTYPE_IPV4 => super::ipv4::input(iface, payload),
+        _ => {}
+    }
+}
@@ -0,0 +1,87 @@
+//! ICMP: answering echo requests, and the echo replies `ping` waits for.
+use super::ipv4::{self, Header, Ipv4Addr, PROTO_ICMP};
+use super::Error;
+use crate::sync::spinlock::SpinLock;
+use crate::sync::waitqueue::WaitQueue;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+const HEADER_LEN: usize = 8;
+
+/// Echo replies kept for `wait_echo`; older ones nobody took are dropped.
+const REPLIES_MAX: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct EchoReply {
+    pub from: Ipv4Addr,
+    pub ident: u16,
+    pub seq: u16,
+    pub ttl: u8,
+    /// ICMP message length: header and payload.
+    pub len: usize,
+}
+
+static REPLIES: SpinLock<VecDeque<EchoReply>> = SpinLock::new(VecDeque::new());
+static REPLY_WAIT: WaitQueue = WaitQueue::new("icmp");
+
+fn message(kind: u8, ident: u16, seq: u16, payload: &[u8]) -> Vec<u8> {
+    let mut m = Vec::with_capacity(HEADER_LEN + payload.len());
+    m.extend_from_slice(&[kind, 0, 0, 0]);
+    m.extend_from_slice(&ident.to_be_bytes());
+    m.extend_from_slice(&seq.to_be_bytes());
+    m.extend_from_slice(payload);
+    let csum = ipv4::checksum(&m);
+    m[2..4].copy_from_slice(&csum.to_be_bytes());
+    m
+}
+
+/// An ICMP message for us.
+pub fn input(hdr: &Header, m: &[u8]) {
+    if m.len() < HEADER_LEN || ipv4::checksum(m) != 0 {
+        return;
+    }
+    let ident = u16::from_be_bytes([m[4], m[5]]);
+    let seq = u16::from_be_bytes([m[6], m[7]]);
+    match m[0] {
+        // Only to our address, not broadcasts: a whole subnet answering
+        // is a smurf amplifier
+        ECHO_REQUEST if super::is_local(hdr.dst) => {
+            let reply = message(ECHO_REPLY, ident, seq, &m[HEADER_LEN..]);
+            let _ = ipv4::send(hdr.src, PROTO_ICMP, &reply);
+        }
+        ECHO_REPLY => {
+            let mut replies = REPLIES.lock();
+            if replies.len() == REPLIES_MAX {
+                replies.pop_front();
+            }
+            replies.push_back(EchoReply {
+                from: hdr.src,
+                ident,
+                seq,
+                ttl: hdr.ttl,
+                len: m.len(),
+            });
+            drop(replies);
+            REPLY_WAIT.wake_all();
+        }
+        _ => {}
+    }
+}
+
+/// Send an echo request with `len` bytes of payload.
+pub fn send_echo(dst: Ipv4Addr, ident: u16, seq: u16, len: usize) -> Result<(), Error> {
+    let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+    ipv4::send(dst, PROTO_ICMP, &message(ECHO_REQUEST, ident, seq, &payload))
+}
+
+/// The reply to echo request (`ident`, `seq`), waiting for it until
+/// `nanos()` reaches `deadline`.
+pub fn wait_echo(ident: u16, seq: u16, deadline: u64) -> Option<EchoReply> {
+    REPLY_WAIT.wait_event_timeout(deadline, || {
+        let mut replies = REPLIES.lock();
+        let i = replies.iter().position(|r| r.ident == ident && r.seq == seq)?;
+        replies.remove(i)
+    })
+}
@@ -0,0 +1,211 @@
+//! IPv4: addresses, the header checksum, input and output. No options and
+//! no fragments: fragmented packets are dropped, outgoing ones must fit in
+//! one frame.
+use super::ethernet::{self, ETHERTYPE_IPV4};
+use super::Error;
+use crate::drivers::net::Interface;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU16, Ordering};
+
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+pub const HEADER_LEN: usize = 20;
+/// Largest payload of one packet.
+pub const MAX_PAYLOAD: usize = ethernet::MTU - HEADER_LEN;
+
+const DEFAULT_TTL: u8 = 64;
+/// Flags and fragment offset: MF and the offset (DF doesn't matter).
+const FRAGMENT_MASK: u16 = 0x3FFF;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0; 4]);
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr([255; 4]);
+
+    /// Dotted quad: "10.0.2.15".
+    pub fn parse(s: &str) -> Option<Ipv4Addr> {
+        let mut a = [0u8; 4];
+        let mut parts = s.split('.');
+        for b in &mut a {
+            let part = parts.next()?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            *b = part.parse().ok()?;
+        }
+        parts.next().is_none().then_some(Ipv4Addr(a))
+    }
+
+    pub fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub fn from_u32(v: u32) -> Ipv4Addr {
+        Ipv4Addr(v.to_be_bytes())
+    }
+
+    /// The netmask with `len` leading one bits.
+    pub fn netmask(len: u32) -> Ipv4Addr {
+        Ipv4Addr::from_u32(u32::MAX.checked_shl(32 - len.min(32)).unwrap_or(0))
+    }
+
+    pub fn is_unspecified(self) -> bool {
+        self == Self::UNSPECIFIED
+    }
+
+    pub fn is_broadcast(self) -> bool {
+        self == Self::BROADCAST
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+// ─── Checksum ────────────────────────────────────────────────────────────────
+
+/// One's-complement sum of `data` as 16-bit big-endian words, added to
+/// `sum`; `fold` finishes it.
+pub fn sum(mut sum: u32, data: &[u8]) -> u32 {
+    let mut words = data.chunks_exact(2);
+    for w in &mut words {
+        sum += u16::from_be_bytes([w[0], w[1]]) as u32;
+    }
+    if let [last] = words.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+pub fn fold(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The Internet checksum of `data`.
+pub fn checksum(data: &[u8]) -> u16 {
+    fold(sum(0, data))
+}
+
+/// Partial sum of the pseudo-header UDP and TCP checksums cover.
+pub fn pseudo_sum(src: Ipv4Addr, dst: Ipv4Addr, proto: u8, len: usize) -> u32 {
+    let s = sum(0, &src.0);
+    let s = sum(s, &dst.0);
+    s + proto as u32 + len as u32
+}
+
+// ─── Input ───────────────────────────────────────────────────────────────────
+
+/// What the transport layers get to know about a packet.
+#[derive(Debug, Clone, Copy)]
+pub struct Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub proto: u8,
+    pub ttl: u8,
+}
+
+/// Whether a packet for `dst` that came in on `iface` is for us.
+fn accepts(iface: &Interface, dst: Ipv4Addr) -> bool {
+    match super::config(iface.index) {
+        Some(c) => dst == c.addr || dst == c.broadcast() || dst.is_broadcast(),
+        None => dst.is_broadcast(),
+    }
+}
+
+/// An IPv4 packet `iface` received.
+pub fn input(iface: &Arc<Interface>, packet: &[u8]) {
+    if packet.len() < HEADER_LEN || packet[0] >> 4 != 4 {
+        return;
+    }
+    let ihl = (packet[0] & 0xF) as usize * 4;
+    let total = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if ihl < HEADER_LEN || total < ihl || total > packet.len() || checksum(&packet[..ihl]) != 0 {
+        return;
+    }
+    if u16::from_be_bytes([packet[6], packet[7]]) & FRAGMENT_MASK != 0 {
+        return;
+    }
+    let hdr = Header {
+        src: Ipv4Addr(packet[12..16].try_into().unwrap_or([0; 4])),
+        dst: Ipv4Addr(packet[16..20].try_into().unwrap_or([0; 4])),
+        proto: packet[9],
+        ttl: packet[8],
+    };
+    if !accepts(iface, hdr.dst) {
+        return;
+    }
+    deliver(&hdr, &packet[ihl..total]);
+}
+
+/// Hand a packet for us to its transport protocol.
+fn deliver(hdr: &Header, payload: &[u8]) {
+    match hdr.proto {
+        PROTO_ICMP => super::icmp::input(hdr, payload),
+        PROTO_UDP => super::udp::input(hdr, payload),
+        PROTO_TCP => super::tcp::input(hdr, payload),
+        _ => {}
+    }
+}
+
+// ─── Output ──────────────────────────────────────────────────────────────────
+
+/// Send `payload` to `dst` from our address on the interface that routes
+/// there; a packet to one of our own addresses is delivered right away.
+pub fn send(dst: Ipv4Addr, proto: u8, payload: &[u8]) -> Result<(), Error> {
+    if super::is_local(dst) {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(Error::TooBig);
+        }
+        let hdr = Header {
+            src: dst,
+            dst,
+            proto,
+            ttl: DEFAULT_TTL,
+        };
+        deliver(&hdr, payload);
+        return Ok(());
+    }
+    let (iface, cfg, hop) = super::route(dst).ok_or(Error::NoRoute)?;
+    send_via(&iface, cfg.addr, dst, hop, proto, payload)
+}
+
+/// Send `payload` from `src` to `dst` on `iface`, by way of neighbour `hop`.
+pub fn send_via(
+    iface: &Interface,
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    hop: Ipv4Addr,
+    proto: u8,
+    payload: &[u8],
+) -> Result<(), Error> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(Error::TooBig);
+    }
+    let total = (HEADER_LEN + payload.len()) as u16;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut packet = Vec::with_capacity(total as usize);
+    packet.extend_from_slice(&[0x45, 0]); // version 4, 5-word header; TOS 0
+    packet.extend_from_slice(&total.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, DEFAULT_TTL, proto, 0, 0]);
+    packet.extend_from_slice(&src.0);
+    packet.extend_from_slice(&dst.0);
+    let csum = checksum(&packet);
+    packet[10..12].copy_from_slice(&csum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    super::arp::send(iface, hop, ETHERTYPE_IPV4, &packet)
+}
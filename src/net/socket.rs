@@ -0,0 +1,206 @@
+//! UDP sockets as files, for the BSD socket syscalls.
+//!
+//! A socket gets its port on `bind`, or an ephemeral one the first time it
+//! sends or receives. `connect` sets the default destination and, as on
+//! Linux, makes the socket ignore datagrams from anyone else. read(2) and
+//! write(2) work on connected sockets like recv and send with no flags.
+use super::udp::{self, UdpSocket};
+use super::{Error, Ipv4Addr};
+use crate::fs::vfs::{
+    alloc_ino, DirEntry, Errno, File, FileType, Ino, Inode, InodeOps, Stat, Timespec,
+};
+use crate::fs::vfs::O_RDWR;
+use crate::sync::spinlock::SpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+impl From<Error> for Errno {
+    fn from(e: Error) -> Errno {
+        match e {
+            Error::NoRoute => Errno::ENETUNREACH,
+            Error::AddrInUse => Errno::EADDRINUSE,
+            Error::TooBig => Errno::EMSGSIZE,
+            Error::Device(_) => Errno::EIO,
+            Error::Timeout => Errno::ETIMEDOUT,
+            Error::NotFound => Errno::ENOENT,
+            Error::NoNameServer => Errno::ENETUNREACH,
+            Error::Reset => Errno::ECONNRESET,
+            Error::Refused => Errno::ECONNREFUSED,
+            Error::Closed => Errno::EPIPE,
+            Error::Interrupted => Errno::EINTR,
+        }
+    }
+}
+
+struct State {
+    udp: Option<Arc<UdpSocket>>,
+    /// Set by `connect`: where sends without an address go, and the only
+    /// sender datagrams are taken from.
+    peer: Option<(Ipv4Addr, u16)>,
+}
+
+pub struct Socket {
+    ino: Ino,
+    nonblock: bool,
+    state: SpinLock<State>,
+}
+
+/// A new unbound UDP socket, open for reading and writing.
+pub fn new_udp(nonblock: bool) -> Arc<File> {
+    let ops = Arc::new(Socket {
+        ino: alloc_ino(),
+        nonblock,
+        state: SpinLock::new(State {
+            udp: None,
+            peer: None,
+        }),
+    });
+    File::new(Inode::new(ops.ino, ops), O_RDWR)
+}
+
+impl Socket {
+    /// The socket's port, bound to an ephemeral one if it has none yet.
+    fn udp(&self) -> Result<Arc<UdpSocket>, Errno> {
+        let mut st = self.state.lock();
+        if let Some(u) = &st.udp {
+            return Ok(Arc::clone(u));
+        }
+        let u = UdpSocket::bind(0)?;
+        st.udp = Some(Arc::clone(&u));
+        Ok(u)
+    }
+
+    /// Take local `port` (0: any free one). A socket binds only once.
+    pub fn bind(&self, port: u16) -> Result<(), Errno> {
+        let mut st = self.state.lock();
+        if st.udp.is_some() {
+            return Err(Errno::EINVAL);
+        }
+        st.udp = Some(UdpSocket::bind(port)?);
+        Ok(())
+    }
+
+    /// Set the peer, or with None go back to talking to anyone.
+    pub fn connect(&self, peer: Option<(Ipv4Addr, u16)>) -> Result<(), Errno> {
+        if peer.is_some() {
+            self.udp()?;
+        }
+        self.state.lock().peer = peer;
+        Ok(())
+    }
+
+    /// The local port, 0 while unbound.
+    pub fn local_port(&self) -> u16 {
+        self.state.lock().udp.as_ref().map_or(0, |u| u.port())
+    }
+
+    pub fn peer(&self) -> Option<(Ipv4Addr, u16)> {
+        self.state.lock().peer
+    }
+
+    /// Send `data` as one datagram to `dst`, or to the peer without one.
+    pub fn send_to(&self, dst: Option<(Ipv4Addr, u16)>, data: &[u8]) -> Result<usize, Errno> {
+        let (addr, port) = dst.or_else(|| self.peer()).ok_or(Errno::EDESTADDRREQ)?;
+        if data.len() > udp::MAX_PAYLOAD {
+            return Err(Errno::EMSGSIZE);
+        }
+        self.udp()?.send_to(addr, port, data)?;
+        Ok(data.len())
+    }
+
+    /// The next datagram into `buf`, cut short if it doesn't fit (the
+    /// rest is lost). Returns its full length and where it came from.
+    pub fn recv_from(
+        &self,
+        buf: &mut [u8],
+        dontwait: bool,
+    ) -> Result<(usize, Ipv4Addr, u16), Errno> {
+        let udp = self.udp()?;
+        let dontwait = dontwait || self.nonblock;
+        loop {
+            let dgram = if dontwait {
+                udp.try_recv().ok_or(Errno::EAGAIN)?
+            } else {
+                udp.recv(None).ok_or(Errno::EINTR)?
+            };
+            if self.peer().is_some_and(|p| p != (dgram.from, dgram.port)) {
+                continue;
+            }
+            let n = buf.len().min(dgram.data.len());
+            buf[..n].copy_from_slice(&dgram.data[..n]);
+            return Ok((dgram.data.len(), dgram.from, dgram.port));
+        }
+    }
+}
+
+fn not_dir<T>() -> Result<T, Errno> {
+    Err(Errno::ENOTDIR)
+}
+
+impl InodeOps for Socket {
+    fn stat(&self) -> Stat {
+        let now = Timespec::now();
+        Stat {
+            ino: self.ino,
+            kind: FileType::Socket,
+            size: 0,
+            mode: 0o777,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    fn read(&self, _: u64, buf: &mut [u8]) -> Result<usize, Errno> {
+        let (n, _, _) = self.recv_from(buf, false)?;
+        Ok(n.min(buf.len()))
+    }
+
+    fn write(&self, _: u64, buf: &[u8]) -> Result<usize, Errno> {
+        if self.peer().is_none() {
+            return Err(Errno::ENOTCONN);
+        }
+        self.send_to(None, buf)
+    }
+
+    fn as_socket(&self) -> Option<&Socket> {
+        Some(self)
+    }
+
+    fn truncate(&self, _: u64) -> Result<(), Errno> {
+        Err(Errno::EINVAL)
+    }
+    fn lookup(&self, _: &str) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn readdir(&self, _: usize) -> Result<Option<DirEntry>, Errno> {
+        not_dir()
+    }
+    fn create(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn mkdir(&self, _: &str, _: u32) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn unlink(&self, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn rmdir(&self, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn symlink(&self, _: &str, _: &str) -> Result<Arc<Inode>, Errno> {
+        not_dir()
+    }
+    fn readlink(&self) -> Result<String, Errno> {
+        Err(Errno::EINVAL)
+    }
+    fn rename(&self, _: &str, _: &Arc<Inode>, _: &str) -> Result<(), Errno> {
+        not_dir()
+    }
+    fn insert_child(&self, _: &str, _: Arc<Inode>) -> Result<(), Errno> {
+        not_dir()
+    }
+}
@@ -0,0 +1,850 @@
+//! TCP (RFC 793): connections opened either way, in-order delivery, flow
+//! control both ways and retransmission on a timer.
+//!
+//! Every connection is a `Conn` in one table keyed by both ends; segments
+//! are run through its state machine in `netd`, user calls run in their
+//! caller. What either produces to send is collected under the connection's
+//! lock and sent after it is dropped, since a segment to one of our own
+//! addresses comes straight back in.
+//!
+//! Deliberately simple: no reassembly (a segment past a gap is dropped and
+//! the gap re-ACKed, and the sender goes back to it on its timeout), a
+//! fixed initial retransmission timeout doubled per retry instead of one
+//! measured from round trips, and no congestion control beyond that. The
+//! peer's window is honoured, and probed when it closes; ours is the free
+//! space in the receive buffer.
+use super::ipv4::{self, Header, Ipv4Addr, PROTO_TCP};
+use super::Error;
+use crate::arch::x86_64::timer::nanos;
+use crate::proc::signal;
+use crate::sync::spinlock::SpinLock;
+use crate::sync::waitqueue::WaitQueue;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+
+const HEADER_LEN: usize = 20;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+
+/// Segment size assumed when the peer doesn't state one.
+const DEFAULT_MSS: usize = 536;
+/// What fits in one of our packets.
+const OUR_MSS: usize = ipv4::MAX_PAYLOAD - HEADER_LEN;
+
+/// Send and receive buffer, each connection, each way.
+const BUF_SIZE: usize = 16 * 1024;
+/// Established connections waiting for `accept`, per listener.
+const BACKLOG: usize = 8;
+
+const RTO_INITIAL_NS: u64 = 1_000_000_000;
+const RTO_MAX_NS: u64 = 60_000_000_000;
+/// Retransmissions of one segment before the connection is given up.
+const MAX_RETRIES: u32 = 8;
+const MAX_SYN_RETRIES: u32 = 5;
+/// 2 × MSL, the maximum segment lifetime, taken as 30 s.
+const TIME_WAIT_NS: u64 = 60_000_000_000;
+
+/// Local ports `connect` picks from.
+const EPHEMERAL_FIRST: u16 = 49152;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// a < b, in sequence space.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+fn random_u32() -> u32 {
+    let mut b = [0u8; 4];
+    crate::fs::devfs::random_fill(&mut b);
+    u32::from_ne_bytes(b)
+}
+
+// ─── Segments ────────────────────────────────────────────────────────────────
+
+/// Local address and port, remote address and port.
+type Key = (Ipv4Addr, u16, Ipv4Addr, u16);
+
+struct Segment {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    /// Sent with SYNs.
+    mss: Option<u16>,
+    data: Vec<u8>,
+}
+
+impl Segment {
+    /// Sequence space it takes: data, and one each for SYN and FIN.
+    fn len(&self) -> u32 {
+        self.data.len() as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+}
+
+fn parse(hdr: &Header, d: &[u8]) -> Option<(u16, u16, Segment)> {
+    if d.len() < HEADER_LEN {
+        return None;
+    }
+    let pseudo = ipv4::pseudo_sum(hdr.src, hdr.dst, PROTO_TCP, d.len());
+    if ipv4::fold(ipv4::sum(pseudo, d)) != 0 {
+        return None;
+    }
+    let be16 = |at: usize| u16::from_be_bytes([d[at], d[at + 1]]);
+    let be32 = |at: usize| u32::from_be_bytes([d[at], d[at + 1], d[at + 2], d[at + 3]]);
+    let offset = (d[12] >> 4) as usize * 4;
+    if offset < HEADER_LEN || offset > d.len() {
+        return None;
+    }
+    let mut mss = None;
+    let mut opts = &d[HEADER_LEN..offset];
+    while let [kind, rest @ ..] = opts {
+        match *kind {
+            OPT_END => break,
+            OPT_NOP => opts = rest,
+            _ => {
+                let len = *rest.first()? as usize;
+                if len < 2 || len > opts.len() {
+                    break;
+                }
+                if *kind == OPT_MSS && len == 4 {
+                    mss = Some(u16::from_be_bytes([opts[2], opts[3]]));
+                }
+                opts = &opts[len..];
+            }
+        }
+    }
+    let seg = Segment {
+        seq: be32(4),
+        ack: be32(8),
+        flags: d[13],
+        window: be16(14),
+        mss,
+        data: d[offset..].to_vec(),
+    };
+    Some((be16(0), be16(2), seg))
+}
+
+fn transmit(key: Key, segs: Vec<Segment>) {
+    let (src, src_port, dst, dst_port) = key;
+    for seg in segs {
+        let opt_len = if seg.mss.is_some() { 4 } else { 0 };
+        let len = HEADER_LEN + opt_len + seg.data.len();
+        let mut d = Vec::with_capacity(len);
+        d.extend_from_slice(&src_port.to_be_bytes());
+        d.extend_from_slice(&dst_port.to_be_bytes());
+        d.extend_from_slice(&seg.seq.to_be_bytes());
+        d.extend_from_slice(&seg.ack.to_be_bytes());
+        d.push((((HEADER_LEN + opt_len) / 4) as u8) << 4);
+        d.push(seg.flags);
+        d.extend_from_slice(&seg.window.to_be_bytes());
+        d.extend_from_slice(&[0, 0, 0, 0]); // checksum, urgent pointer
+        if let Some(mss) = seg.mss {
+            d.extend_from_slice(&[OPT_MSS, 4]);
+            d.extend_from_slice(&mss.to_be_bytes());
+        }
+        d.extend_from_slice(&seg.data);
+        let pseudo = ipv4::pseudo_sum(src, dst, PROTO_TCP, len);
+        let csum = ipv4::fold(ipv4::sum(pseudo, &d));
+        d[16..18].copy_from_slice(&csum.to_be_bytes());
+        let _ = ipv4::send(dst, PROTO_TCP, &d);
+    }
+}
+
+/// The RST answering `seg`, which belongs to no connection (RFC 793, "If
+/// the connection does not exist").
+fn reset_for(seg: &Segment) -> Segment {
+    let (seq, ack, flags) = if seg.flags & ACK != 0 {
+        (seg.ack, 0, RST)
+    } else {
+        (0, seg.seq.wrapping_add(seg.len()), RST | ACK)
+    };
+    Segment {
+        seq,
+        ack,
+        flags,
+        window: 0,
+        mss: None,
+        data: Vec::new(),
+    }
+}
+
+// ─── Connection state ────────────────────────────────────────────────────────
+
+struct Tcb {
+    state: State,
+    /// What ended the connection, if not a clean close.
+    error: Option<Error>,
+    iss: u32,
+    /// Oldest unacknowledged and next sequence number to send.
+    snd_una: u32,
+    snd_nxt: u32,
+    /// The peer's window and segment size.
+    snd_wnd: u32,
+    mss: usize,
+    rcv_nxt: u32,
+    /// Data from `snd_una` on: sent and not yet acknowledged, then unsent.
+    tx: VecDeque<u8>,
+    rx: VecDeque<u8>,
+    /// `close` was called: a FIN goes after the last of `tx`.
+    fin_queued: bool,
+    fin_sent: bool,
+    peer_fin: bool,
+    /// Something came in that wants an ACK.
+    ack_now: bool,
+    rto: u64,
+    retries: u32,
+    /// When to retransmit (or, in TIME_WAIT, to close); None without
+    /// anything outstanding.
+    rexmit_at: Option<u64>,
+    /// Earliest `call_at` that is armed for the connection.
+    timer_armed: Option<u64>,
+}
+
+impl Tcb {
+    fn new(state: State, iss: u32) -> Tcb {
+        Tcb {
+            state,
+            error: None,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            mss: DEFAULT_MSS,
+            rcv_nxt: 0,
+            tx: VecDeque::new(),
+            rx: VecDeque::new(),
+            fin_queued: false,
+            fin_sent: false,
+            peer_fin: false,
+            ack_now: false,
+            rto: RTO_INITIAL_NS,
+            retries: 0,
+            rexmit_at: None,
+            timer_armed: None,
+        }
+    }
+
+    /// The window we offer: what `rx` has room for.
+    fn window(&self) -> u16 {
+        (BUF_SIZE - self.rx.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn in_flight(&self) -> u32 {
+        self.snd_nxt.wrapping_sub(self.snd_una)
+    }
+
+    fn segment(&self, flags: u8, seq: u32, data: Vec<u8>) -> Segment {
+        Segment {
+            seq,
+            ack: self.rcv_nxt,
+            flags,
+            window: self.window(),
+            mss: None,
+            data,
+        }
+    }
+
+    fn fail(&mut self, e: Error) {
+        self.state = State::Closed;
+        self.error = Some(e);
+        self.tx.clear();
+        self.rexmit_at = None;
+    }
+
+    fn time_wait(&mut self, now: u64) {
+        self.state = State::TimeWait;
+        self.rexmit_at = Some(now + TIME_WAIT_NS);
+    }
+
+    /// Whatever can go out now: the SYN, data the peer has room for, the
+    /// FIN once the data is all sent, or else an ACK if one is owed.
+    fn output(&mut self, now: u64, probe: bool) -> Vec<Segment> {
+        let mut out = Vec::new();
+        match self.state {
+            State::SynSent | State::SynReceived => {
+                if self.snd_nxt == self.iss {
+                    let flags = if self.state == State::SynSent { SYN } else { SYN | ACK };
+                    let mut seg = self.segment(flags, self.iss, Vec::new());
+                    if flags & ACK == 0 {
+                        seg.ack = 0;
+                    }
+                    seg.mss = Some(OUR_MSS as u16);
+                    out.push(seg);
+                    self.snd_nxt = self.iss.wrapping_add(1);
+                }
+            }
+            State::Closed | State::TimeWait => {}
+            _ if !self.fin_sent => {
+                // A closed window still lets one byte through as a probe
+                let wnd = if probe { self.snd_wnd.max(1) } else { self.snd_wnd } as usize;
+                let mut off = self.in_flight() as usize;
+                while off < self.tx.len() && off < wnd {
+                    let n = (self.tx.len() - off).min(wnd - off).min(self.mss);
+                    let data: Vec<u8> = self.tx.range(off..off + n).copied().collect();
+                    out.push(self.segment(ACK | PSH, self.snd_nxt, data));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(n as u32);
+                    off += n;
+                }
+                if self.fin_queued && off == self.tx.len() {
+                    out.push(self.segment(FIN | ACK, self.snd_nxt, Vec::new()));
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                    self.fin_sent = true;
+                }
+            }
+            _ => {}
+        }
+        if out.is_empty() && self.ack_now {
+            out.push(self.segment(ACK, self.snd_nxt, Vec::new()));
+        }
+        self.ack_now = false;
+
+        let unsent = self.tx.len() > self.in_flight() as usize;
+        if self.rexmit_at.is_none() && (self.in_flight() > 0 || unsent) {
+            self.rexmit_at = Some(now + self.rto);
+        }
+        out
+    }
+
+    /// The retransmission timer went off: go back to `snd_una` and send
+    /// from there again.
+    fn timeout(&mut self, now: u64) -> Vec<Segment> {
+        match self.rexmit_at {
+            Some(at) if at <= now => self.rexmit_at = None,
+            _ => return Vec::new(),
+        }
+        if self.state == State::TimeWait {
+            self.state = State::Closed;
+            return Vec::new();
+        }
+        let unsent = self.tx.len() > self.in_flight() as usize;
+        if self.in_flight() == 0 && !unsent {
+            return Vec::new();
+        }
+        self.retries += 1;
+        let syn = matches!(self.state, State::SynSent | State::SynReceived);
+        let limit = if syn { MAX_SYN_RETRIES } else { MAX_RETRIES };
+        if self.retries > limit {
+            self.fail(Error::Timeout);
+            let mut rst = self.segment(RST | ACK, self.snd_nxt, Vec::new());
+            rst.window = 0;
+            return alloc::vec![rst];
+        }
+        self.rto = (self.rto * 2).min(RTO_MAX_NS);
+        self.snd_nxt = if syn { self.iss } else { self.snd_una };
+        self.fin_sent = false;
+        self.output(now, true)
+    }
+
+    /// Run an arriving segment through the state machine. True if it
+    /// completed a passive open, and the connection is ready for `accept`.
+    fn receive(&mut self, seg: &Segment, now: u64) -> bool {
+        if self.state == State::SynSent {
+            self.receive_syn_sent(seg);
+            return false;
+        }
+        if self.state == State::Closed {
+            return false;
+        }
+
+        let wnd = (self.window() as u32).max(1);
+        let end = seg.seq.wrapping_add(seg.len());
+        let acceptable = if seg.len() == 0 {
+            seg.seq == self.rcv_nxt
+                || (seq_le(self.rcv_nxt, seg.seq)
+                    && seq_lt(seg.seq, self.rcv_nxt.wrapping_add(wnd)))
+        } else {
+            seq_lt(seg.seq, self.rcv_nxt.wrapping_add(wnd)) && seq_lt(self.rcv_nxt, end)
+        };
+        if !acceptable {
+            if seg.flags & RST == 0 {
+                self.ack_now = true;
+            }
+            return false;
+        }
+        if seg.flags & RST != 0 {
+            self.fail(Error::Reset);
+            return false;
+        }
+        if seg.flags & SYN != 0 {
+            // A SYN inside the window: the peer has lost track
+            self.fail(Error::Reset);
+            return false;
+        }
+        if seg.flags & ACK == 0 {
+            return false;
+        }
+
+        let mut established = false;
+        if self.state == State::SynReceived {
+            if seg.ack != self.iss.wrapping_add(1) {
+                return false;
+            }
+            self.state = State::Established;
+            established = true;
+        }
+        if seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt) {
+            let mut acked = seg.ack.wrapping_sub(self.snd_una) as usize;
+            if self.snd_una == self.iss {
+                acked -= 1; // our SYN
+            }
+            let fin_acked = self.fin_sent && seg.ack == self.snd_nxt;
+            if fin_acked {
+                acked -= 1;
+            }
+            let acked = acked.min(self.tx.len());
+            self.tx.drain(..acked);
+            self.snd_una = seg.ack;
+            self.retries = 0;
+            self.rto = RTO_INITIAL_NS;
+            self.rexmit_at = (self.in_flight() > 0).then_some(now + self.rto);
+            if fin_acked {
+                match self.state {
+                    State::FinWait1 => self.state = State::FinWait2,
+                    State::Closing => self.time_wait(now),
+                    State::LastAck => self.state = State::Closed,
+                    _ => {}
+                }
+            }
+        } else if seq_lt(self.snd_nxt, seg.ack) {
+            // Acknowledges what was never sent
+            self.ack_now = true;
+            return established;
+        }
+        if seq_le(self.snd_una, seg.ack) {
+            let opened = self.snd_wnd == 0 && seg.window > 0;
+            self.snd_wnd = seg.window as u32;
+            if opened {
+                // Don't wait out the probe timer to send the rest
+                self.rexmit_at = None;
+            } else if seg.window == 0 {
+                // Answered a probe: the peer is there, just not reading
+                self.retries = 0;
+            }
+        }
+
+        let mut took_all = true;
+        let receiving = matches!(
+            self.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+        if receiving && !seg.data.is_empty() {
+            if seq_lt(self.rcv_nxt, seg.seq) {
+                // Past a gap: ask again for what is missing
+                self.ack_now = true;
+                return established;
+            }
+            let skip = self.rcv_nxt.wrapping_sub(seg.seq) as usize;
+            let data = &seg.data[skip.min(seg.data.len())..];
+            let n = data.len().min(BUF_SIZE - self.rx.len());
+            self.rx.extend(&data[..n]);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(n as u32);
+            took_all = n == data.len();
+            self.ack_now = true;
+        }
+        let fin_seq = seg.seq.wrapping_add(seg.data.len() as u32);
+        if seg.flags & FIN != 0 && took_all && fin_seq == self.rcv_nxt && !self.peer_fin {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.peer_fin = true;
+            self.ack_now = true;
+            match self.state {
+                State::Established => self.state = State::CloseWait,
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 => self.time_wait(now),
+                _ => {}
+            }
+        }
+        established
+    }
+
+    fn receive_syn_sent(&mut self, seg: &Segment) {
+        let ack_ok = seg.ack == self.iss.wrapping_add(1);
+        if seg.flags & ACK != 0 && !ack_ok {
+            return;
+        }
+        if seg.flags & RST != 0 {
+            if seg.flags & ACK != 0 {
+                self.fail(Error::Refused);
+            }
+            return;
+        }
+        if seg.flags & SYN == 0 || seg.flags & ACK == 0 {
+            // No simultaneous opens
+            return;
+        }
+        self.rcv_nxt = seg.seq.wrapping_add(1);
+        self.mss = seg.mss.map_or(DEFAULT_MSS, |m| m as usize).min(OUR_MSS);
+        self.snd_wnd = seg.window as u32;
+        self.snd_una = seg.ack;
+        self.state = State::Established;
+        self.retries = 0;
+        self.rto = RTO_INITIAL_NS;
+        self.rexmit_at = None;
+        self.ack_now = true;
+    }
+}
+
+// ─── Connections ─────────────────────────────────────────────────────────────
+
+struct Conn {
+    id: u64,
+    key: Key,
+    tcb: SpinLock<Tcb>,
+    /// Readers waiting for data (and `connect` for the handshake), writers
+    /// for buffer space.
+    readers: WaitQueue,
+    writers: WaitQueue,
+    /// Who accepts a passively opened connection.
+    listener: Weak<TcpListener>,
+}
+
+/// Both ends → connection. A connection stays here until it is closed,
+/// whether or not anyone still holds a `TcpStream` for it.
+static CONNS: SpinLock<BTreeMap<Key, Arc<Conn>>> = SpinLock::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_PORT: AtomicU16 = AtomicU16::new(EPHEMERAL_FIRST);
+
+impl Conn {
+    fn new(key: Key, tcb: Tcb, listener: Weak<TcpListener>) -> Arc<Conn> {
+        Arc::new(Conn {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            key,
+            tcb: SpinLock::new(tcb),
+            readers: WaitQueue::new("tcp_read"),
+            writers: WaitQueue::new("tcp_write"),
+            listener,
+        })
+    }
+
+    /// Run `f` on the TCB, then send what it produced, keep the timer
+    /// armed, wake whoever waits and drop the connection if it closed.
+    fn update<R>(&self, f: impl FnOnce(&mut Tcb, u64) -> (Vec<Segment>, R)) -> R {
+        let now = nanos();
+        let (segs, r, closed) = {
+            let mut t = self.tcb.lock();
+            let (segs, r) = f(&mut t, now);
+            if let Some(at) = t.rexmit_at {
+                if t.timer_armed.is_none_or(|armed| armed > at) {
+                    crate::proc::timers::call_at(at, on_timer, self.id);
+                    t.timer_armed = Some(at);
+                }
+            }
+            (segs, r, t.state == State::Closed)
+        };
+        if closed {
+            let mut conns = CONNS.lock();
+            if conns.get(&self.key).is_some_and(|c| c.id == self.id) {
+                conns.remove(&self.key);
+            }
+        }
+        transmit(self.key, segs);
+        self.readers.wake_all();
+        self.writers.wake_all();
+        r
+    }
+
+    /// Send what can go now.
+    fn push(&self) {
+        self.update(|t, now| (t.output(now, false), ()));
+    }
+
+    fn close(&self) {
+        self.update(|t, now| {
+            match t.state {
+                State::SynSent => t.state = State::Closed,
+                State::SynReceived | State::Established => {
+                    t.fin_queued = true;
+                    t.state = State::FinWait1;
+                }
+                State::CloseWait => {
+                    t.fin_queued = true;
+                    t.state = State::LastAck;
+                }
+                _ => {}
+            }
+            (t.output(now, false), ())
+        });
+    }
+
+    /// Give the connection up at once with a RST.
+    fn abort(&self) {
+        self.update(|t, _| {
+            let live = !matches!(t.state, State::Closed | State::TimeWait | State::SynSent);
+            let rst = t.segment(RST | ACK, t.snd_nxt, Vec::new());
+            t.fail(Error::Reset);
+            (if live { alloc::vec![rst] } else { Vec::new() }, ())
+        });
+    }
+}
+
+fn on_timer(id: u64) {
+    let conn = CONNS.lock().values().find(|c| c.id == id).cloned();
+    let Some(conn) = conn else {
+        return;
+    };
+    conn.update(|t, now| {
+        if t.timer_armed.is_some_and(|at| at <= now) {
+            t.timer_armed = None;
+        }
+        (t.timeout(now), ())
+    });
+}
+
+/// A TCP segment for us.
+pub fn input(hdr: &Header, d: &[u8]) {
+    let Some((src_port, dst_port, seg)) = parse(hdr, d) else {
+        return;
+    };
+    let key = (hdr.dst, dst_port, hdr.src, src_port);
+    let conn = CONNS.lock().get(&key).cloned();
+    if let Some(conn) = conn {
+        let established = conn.update(|t, now| {
+            let established = t.receive(&seg, now);
+            (t.output(now, false), established)
+        });
+        if established {
+            match conn.listener.upgrade() {
+                Some(l) if l.queue.lock().len() < BACKLOG => {
+                    l.queue.lock().push_back(conn);
+                    l.waiters.wake_all();
+                }
+                _ => conn.abort(),
+            }
+        }
+        return;
+    }
+
+    if seg.flags & RST != 0 {
+        return;
+    }
+    let listener = LISTENERS.lock().get(&dst_port).and_then(Weak::upgrade);
+    match listener {
+        Some(l) if seg.flags & (SYN | ACK) == SYN && l.queue.lock().len() < BACKLOG => {
+            let mut tcb = Tcb::new(State::SynReceived, random_u32());
+            tcb.rcv_nxt = seg.seq.wrapping_add(1);
+            tcb.snd_wnd = seg.window as u32;
+            tcb.mss = seg.mss.map_or(DEFAULT_MSS, |m| m as usize).min(OUR_MSS);
+            let conn = Conn::new(key, tcb, Arc::downgrade(&l));
+            CONNS.lock().insert(key, Arc::clone(&conn));
+            conn.push();
+        }
+        // A full backlog drops the SYN: the peer will try again
+        Some(_) if seg.flags & (SYN | ACK) == SYN => {}
+        _ => transmit(key, alloc::vec![reset_for(&seg)]),
+    }
+}
+
+// ─── Sockets ─────────────────────────────────────────────────────────────────
+
+/// One end of a connection. Dropping it closes the connection (the close
+/// itself completes in the background); reads then fail.
+pub struct TcpStream {
+    conn: Arc<Conn>,
+}
+
+impl TcpStream {
+    /// Open a connection to `dst`:`port`, waiting for the handshake.
+    pub fn connect(dst: Ipv4Addr, port: u16) -> Result<TcpStream, Error> {
+        let src = if super::is_local(dst) {
+            dst
+        } else {
+            super::route(dst).ok_or(Error::NoRoute)?.1.addr
+        };
+        let key = {
+            let mut conns = CONNS.lock();
+            let free = (0..=u16::MAX - EPHEMERAL_FIRST)
+                .map(|_| {
+                    let p = NEXT_PORT.fetch_add(1, Ordering::Relaxed);
+                    if p < EPHEMERAL_FIRST {
+                        NEXT_PORT.store(EPHEMERAL_FIRST + 1, Ordering::Relaxed);
+                        EPHEMERAL_FIRST
+                    } else {
+                        p
+                    }
+                })
+                .find(|&p| !conns.contains_key(&(src, p, dst, port)));
+            let key = (src, free.ok_or(Error::AddrInUse)?, dst, port);
+            let conn = Conn::new(key, Tcb::new(State::SynSent, random_u32()), Weak::new());
+            conns.insert(key, conn);
+            key
+        };
+        let conn = CONNS.lock().get(&key).cloned().ok_or(Error::Reset)?;
+        let stream = TcpStream { conn };
+        stream.conn.push();
+        stream.conn.readers.wait_event(|| {
+            let t = stream.conn.tcb.lock();
+            match t.state {
+                State::SynSent => {}
+                State::Closed => return Some(Err(t.error.unwrap_or(Error::Refused))),
+                _ => return Some(Ok(())),
+            }
+            drop(t);
+            signal::interrupted().then_some(Err(Error::Interrupted))
+        })?;
+        Ok(stream)
+    }
+
+    /// Received bytes into `buf`, waiting for some; 0 once the peer has
+    /// closed its side.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let (n, reopened) = self.conn.readers.wait_event(|| {
+            let mut t = self.conn.tcb.lock();
+            if !t.rx.is_empty() {
+                let before = t.window() as usize;
+                let n = buf.len().min(t.rx.len());
+                for (b, c) in buf.iter_mut().zip(t.rx.drain(..n)) {
+                    *b = c;
+                }
+                // Tell a peer that was held up that it can go on
+                let reopened = before < t.mss && t.window() as usize >= t.mss;
+                return Some(Ok((n, reopened)));
+            }
+            if t.peer_fin {
+                return Some(Ok((0, false)));
+            }
+            if t.state == State::Closed {
+                return Some(t.error.map_or(Ok((0, false)), Err));
+            }
+            drop(t);
+            signal::interrupted().then_some(Err(Error::Interrupted))
+        })?;
+        if reopened {
+            self.conn.update(|t, now| {
+                t.ack_now = true;
+                (t.output(now, false), ())
+            });
+        }
+        Ok(n)
+    }
+
+    /// Queue all of `data`, waiting for buffer space as needed. Fails only
+    /// if nothing could be sent.
+    pub fn write(&self, data: &[u8]) -> Result<usize, Error> {
+        let mut done = 0;
+        while done < data.len() {
+            let res = self.conn.writers.wait_event(|| {
+                let mut t = self.conn.tcb.lock();
+                match t.state {
+                    State::SynSent | State::SynReceived => {}
+                    State::Established | State::CloseWait if !t.fin_queued => {
+                        let n = (BUF_SIZE - t.tx.len()).min(data.len() - done);
+                        if n > 0 {
+                            t.tx.extend(&data[done..done + n]);
+                            return Some(Ok(n));
+                        }
+                    }
+                    _ => return Some(Err(t.error.unwrap_or(Error::Closed))),
+                }
+                drop(t);
+                signal::interrupted().then_some(Err(Error::Interrupted))
+            });
+            match res {
+                Ok(n) => {
+                    done += n;
+                    self.conn.push();
+                }
+                Err(e) if done == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        Ok(done)
+    }
+
+    /// Send a FIN after what is queued; reads go on until the peer's.
+    pub fn shutdown(&self) {
+        self.conn.close();
+    }
+
+    pub fn peer(&self) -> (Ipv4Addr, u16) {
+        (self.conn.key.2, self.conn.key.3)
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        self.conn.close();
+    }
+}
+
+/// A port taking connections.
+pub struct TcpListener {
+    port: u16,
+    queue: SpinLock<VecDeque<Arc<Conn>>>,
+    waiters: WaitQueue,
+}
+
+static LISTENERS: SpinLock<BTreeMap<u16, Weak<TcpListener>>> = SpinLock::new(BTreeMap::new());
+
+impl TcpListener {
+    pub fn bind(port: u16) -> Result<Arc<TcpListener>, Error> {
+        let mut listeners = LISTENERS.lock();
+        if listeners.get(&port).is_some_and(|w| w.strong_count() > 0) {
+            return Err(Error::AddrInUse);
+        }
+        let l = Arc::new(TcpListener {
+            port,
+            queue: SpinLock::new(VecDeque::new()),
+            waiters: WaitQueue::new("tcp_accept"),
+        });
+        listeners.insert(port, Arc::downgrade(&l));
+        Ok(l)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The next connection to come in, waiting for one.
+    pub fn accept(&self) -> Result<TcpStream, Error> {
+        let conn = self.waiters.wait_event(|| {
+            if let Some(c) = self.queue.lock().pop_front() {
+                return Some(Ok(c));
+            }
+            signal::interrupted().then_some(Err(Error::Interrupted))
+        })?;
+        Ok(TcpStream { conn })
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let mut listeners = LISTENERS.lock();
+        if listeners.get(&self.port).is_some_and(|w| w.strong_count() == 0) {
+            listeners.remove(&self.port);
+        }
+        drop(listeners);
+        // Connections nobody accepted are refused after all
+        for conn in core::mem::take(&mut *self.queue.lock()) {
+            conn.abort();
+        }
+    }
+}
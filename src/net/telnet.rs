@@ -0,0 +1,208 @@
+//! Telnet server: a shell for every connection to port 23, each on a
+//! network terminal of its own. There is no login, whoever reaches the
+//! port is root. Of the options only what puts a client in character mode
+//! is offered (the server echoes, and go-aheads are suppressed); anything
+//! the client proposes is declined.
+//!
+//! A session is two tasks: the shell, whose terminal output goes to the
+//! connection, and a receiver that types what comes in into the terminal.
+//! When either ends the terminal hangs up, which ends the other one too,
+//! and the last of them gives it back.
+use super::tcp::{TcpListener, TcpStream};
+use crate::drivers::tty::{self, Remote, Tty};
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const PORT: u16 = 23;
+
+const SE: u8 = 240;
+const SB: u8 = 250;
+const WILL: u8 = 251;
+const WONT: u8 = 252;
+const DO: u8 = 253;
+const DONT: u8 = 254;
+const IAC: u8 = 255;
+
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+
+/// The connection, as the terminal's far end.
+struct Link {
+    stream: TcpStream,
+}
+
+impl Remote for Link {
+    fn send(&self, buf: &[u8]) {
+        let mut out = Vec::with_capacity(buf.len());
+        for &b in buf {
+            if b == IAC {
+                out.push(IAC);
+            }
+            out.push(b);
+        }
+        let _ = self.stream.write(&out);
+    }
+}
+
+struct Session {
+    link: Arc<Link>,
+    tty: &'static Tty,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.tty.release();
+    }
+}
+
+/// Sessions handed to the tasks just started for them.
+static RECEIVERS: SpinLock<VecDeque<Arc<Session>>> = SpinLock::new(VecDeque::new());
+static SHELLS: SpinLock<VecDeque<Arc<Session>>> = SpinLock::new(VecDeque::new());
+
+#[derive(Clone, Copy)]
+enum Rx {
+    Data,
+    /// After a CR, which the client follows with LF or NUL.
+    Cr,
+    Iac,
+    /// After IAC and WILL, WONT, DO or DONT, waiting for the option.
+    Option(u8),
+    /// In a subnegotiation, skipped up to IAC SE.
+    Sub,
+    SubIac,
+}
+
+/// What to say to `cmd` for `opt`, if anything: we do ECHO and SGA, which
+/// we offered, and nothing else; the client's offers are all turned down.
+fn answer(cmd: u8, opt: u8) -> Option<u8> {
+    match cmd {
+        DO if opt == OPT_ECHO || opt == OPT_SGA => None,
+        DO => Some(WONT),
+        WILL => Some(DONT),
+        _ => None,
+    }
+}
+
+/// Type what the client sends into the terminal until it goes away.
+fn receive(s: &Session) {
+    let mut state = Rx::Data;
+    let mut buf = [0u8; 512];
+    while let Ok(n @ 1..) = s.link.stream.read(&mut buf) {
+        let mut reply = Vec::new();
+        for &b in &buf[..n] {
+            state = match (state, b) {
+                (Rx::Iac, IAC) => {
+                    s.tty.input(IAC);
+                    Rx::Data
+                }
+                (Rx::Iac, WILL..=DONT) => Rx::Option(b),
+                (Rx::Iac, SB) => Rx::Sub,
+                // NOP, Are You There and the like
+                (Rx::Iac, _) => Rx::Data,
+                (Rx::Option(cmd), _) => {
+                    if let Some(r) = answer(cmd, b) {
+                        reply.extend_from_slice(&[IAC, r, b]);
+                    }
+                    Rx::Data
+                }
+                (Rx::Sub, IAC) => Rx::SubIac,
+                (Rx::SubIac, SE) => Rx::Data,
+                (Rx::Sub | Rx::SubIac, _) => Rx::Sub,
+                (_, IAC) => Rx::Iac,
+                (Rx::Cr, b'\n' | 0) => Rx::Data,
+                (_, b'\r') => {
+                    s.tty.input(b'\r');
+                    Rx::Cr
+                }
+                (_, b) => {
+                    s.tty.input(b);
+                    Rx::Data
+                }
+            };
+        }
+        if !reply.is_empty() {
+            let _ = s.link.stream.write(&reply);
+        }
+    }
+}
+
+fn receiver_task() -> ! {
+    if let Some(s) = RECEIVERS.lock().pop_front() {
+        receive(&s);
+        s.tty.hangup();
+    }
+    crate::proc::exit_current(0);
+}
+
+fn shell_task() -> ! {
+    if let Some(s) = SHELLS.lock().pop_front() {
+        crate::shell::serve(s.tty);
+        s.tty.hangup();
+        // The client closes its end in turn, which ends the receiver
+        s.link.stream.shutdown();
+    }
+    crate::proc::exit_current(0);
+}
+
+/// Start `entry` on `session` by way of `queue`.
+fn spawn(
+    name: &str,
+    entry: fn() -> !,
+    queue: &SpinLock<VecDeque<Arc<Session>>>,
+    session: &Arc<Session>,
+) -> bool {
+    queue.lock().push_back(Arc::clone(session));
+    match crate::proc::Process::new_kernel(name, entry, 5) {
+        Some(p) => {
+            crate::proc::scheduler::spawn(p);
+            true
+        }
+        None => {
+            queue.lock().pop_back();
+            false
+        }
+    }
+}
+
+fn start(stream: TcpStream) {
+    let (addr, port) = stream.peer();
+    let link = Arc::new(Link { stream });
+    let _ = link.stream.write(&[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA]);
+    let Some(tty) = tty::attach(Arc::clone(&link) as Arc<dyn Remote>) else {
+        let _ = link.stream.write(b"telnet: too many sessions\r\n");
+        return;
+    };
+    log::info!("telnet: session from {}:{}", addr, port);
+    let session = Arc::new(Session { link, tty });
+    if !spawn("telnet-rx", receiver_task, &RECEIVERS, &session)
+        || !spawn("sh", shell_task, &SHELLS, &session)
+    {
+        log::warn!("telnet: cannot start the session's tasks");
+        session.tty.hangup();
+        session.link.stream.shutdown();
+    }
+}
+
+fn telnetd_task() -> ! {
+    let listener = match TcpListener::bind(PORT) {
+        Ok(l) => l,
+        Err(e) => {
+            log::warn!("telnet: port {}: {}", PORT, e.as_str());
+            crate::proc::exit_current(1);
+        }
+    };
+    loop {
+        if let Ok(stream) = listener.accept() {
+            start(stream);
+        }
+    }
+}
+
+pub fn init() {
+    match crate::proc::Process::new_kernel("telnetd", telnetd_task, 5) {
+        Some(p) => crate::proc::scheduler::spawn(p),
+        None => log::warn!("telnet: cannot start telnetd"),
+    }
+}
@@ -0,0 +1,177 @@
+//! UDP sockets. A socket owns a local port while it lives; datagrams for
+//! the port queue up on it until read. Checksums are sent and, when the
+//! peer sent one, checked.
+use super::ipv4::{self, Header, Ipv4Addr, PROTO_UDP};
+use super::Error;
+use crate::drivers::net::Interface;
+use crate::sync::spinlock::SpinLock;
+use crate::sync::waitqueue::WaitQueue;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+pub const HEADER_LEN: usize = 8;
+/// Largest datagram payload that fits in one packet.
+pub const MAX_PAYLOAD: usize = ipv4::MAX_PAYLOAD - HEADER_LEN;
+
+/// Datagrams queued per socket; more are dropped until it is read.
+const QUEUE_MAX: usize = 64;
+
+/// Ports handed to sockets bound to port 0.
+const EPHEMERAL_FIRST: u16 = 49152;
+
+#[derive(Debug, Clone)]
+pub struct Datagram {
+    pub from: Ipv4Addr,
+    pub port: u16,
+    pub data: Vec<u8>,
+}
+
+pub struct UdpSocket {
+    port: u16,
+    queue: SpinLock<VecDeque<Datagram>>,
+    readers: WaitQueue,
+}
+
+/// Local port → the socket bound to it.
+static SOCKETS: SpinLock<BTreeMap<u16, Weak<UdpSocket>>> = SpinLock::new(BTreeMap::new());
+
+impl UdpSocket {
+    /// A socket on local `port`, or on a free ephemeral port for 0.
+    pub fn bind(port: u16) -> Result<Arc<UdpSocket>, Error> {
+        let mut sockets = SOCKETS.lock();
+        let live = |p: &u16, s: &BTreeMap<u16, Weak<UdpSocket>>| {
+            s.get(p).is_some_and(|w| w.strong_count() > 0)
+        };
+        let port = if port == 0 {
+            (EPHEMERAL_FIRST..=u16::MAX)
+                .find(|p| !live(p, &sockets))
+                .ok_or(Error::AddrInUse)?
+        } else if live(&port, &sockets) {
+            return Err(Error::AddrInUse);
+        } else {
+            port
+        };
+        let sock = Arc::new(UdpSocket {
+            port,
+            queue: SpinLock::new(VecDeque::new()),
+            readers: WaitQueue::new("udp"),
+        });
+        sockets.insert(port, Arc::downgrade(&sock));
+        Ok(sock)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn send_to(&self, dst: Ipv4Addr, port: u16, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_PAYLOAD {
+            return Err(Error::TooBig);
+        }
+        let src = if super::is_local(dst) {
+            dst
+        } else {
+            super::route(dst).ok_or(Error::NoRoute)?.1.addr
+        };
+        ipv4::send(dst, PROTO_UDP, &datagram(src, self.port, dst, port, data))
+    }
+
+    /// Send from `src` out of `iface` to `dst`, which must be on the link,
+    /// whatever the interface's settings: DHCP talks before it has any.
+    pub fn send_via(
+        &self,
+        iface: &Interface,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        port: u16,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        if data.len() > MAX_PAYLOAD {
+            return Err(Error::TooBig);
+        }
+        let d = datagram(src, self.port, dst, port, data);
+        ipv4::send_via(iface, src, dst, dst, PROTO_UDP, &d)
+    }
+
+    pub fn try_recv(&self) -> Option<Datagram> {
+        self.queue.lock().pop_front()
+    }
+
+    /// The next datagram, sleeping for it until `nanos()` reaches
+    /// `deadline` (for ever without one). None on timeout or a signal.
+    pub fn recv(&self, deadline: Option<u64>) -> Option<Datagram> {
+        let deadline = deadline.unwrap_or(u64::MAX);
+        self.readers.wait_event_timeout(deadline, || self.try_recv())
+    }
+
+    /// Datagrams waiting to be read.
+    pub fn pending(&self) -> usize {
+        self.queue.lock().len()
+    }
+}
+
+/// Header and `data`, checksummed for the `src` → `dst` pseudo-header.
+fn datagram(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Vec<u8> {
+    let len = HEADER_LEN + data.len();
+    let mut d = Vec::with_capacity(len);
+    d.extend_from_slice(&src_port.to_be_bytes());
+    d.extend_from_slice(&dst_port.to_be_bytes());
+    d.extend_from_slice(&(len as u16).to_be_bytes());
+    d.extend_from_slice(&[0, 0]);
+    d.extend_from_slice(data);
+    let pseudo = ipv4::pseudo_sum(src, dst, PROTO_UDP, len);
+    let csum = match ipv4::fold(ipv4::sum(pseudo, &d)) {
+        0 => 0xFFFF, // 0 means "no checksum"
+        c => c,
+    };
+    d[6..8].copy_from_slice(&csum.to_be_bytes());
+    d
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        let mut sockets = SOCKETS.lock();
+        if sockets.get(&self.port).is_some_and(|w| w.strong_count() == 0) {
+            sockets.remove(&self.port);
+        }
+    }
+}
+
+/// A UDP datagram for us.
+pub fn input(hdr: &Header, d: &[u8]) {
+    if d.len() < HEADER_LEN {
+        return;
+    }
+    let src_port = u16::from_be_bytes([d[0], d[1]]);
+    let dst_port = u16::from_be_bytes([d[2], d[3]]);
+    let len = u16::from_be_bytes([d[4], d[5]]) as usize;
+    let csum = u16::from_be_bytes([d[6], d[7]]);
+    if len < HEADER_LEN || len > d.len() {
+        return;
+    }
+    let d = &d[..len];
+    let pseudo = ipv4::pseudo_sum(hdr.src, hdr.dst, PROTO_UDP, len);
+    if csum != 0 && ipv4::fold(ipv4::sum(pseudo, d)) != 0 {
+        return;
+    }
+    let Some(sock) = SOCKETS.lock().get(&dst_port).and_then(Weak::upgrade) else {
+        return;
+    };
+    let queued = {
+        let mut q = sock.queue.lock();
+        if q.len() < QUEUE_MAX {
+            q.push_back(Datagram {
+                from: hdr.src,
+                port: src_port,
+                data: d[HEADER_LEN..].to_vec(),
+            });
+            true
+        } else {
+            false
+        }
+    };
+    if queued {
+        sock.readers.wake_all();
+    }
+}
@@ -1,15 +1,30 @@
+pub mod cred;
 pub mod elf;
 pub mod exec;
 pub mod fork;
+pub mod futex;
+pub mod init;
+pub mod priority;
+pub mod resource;
+pub mod signal;
 pub mod stack;
+pub mod timers;
+pub mod workqueue;
 
-use crate::mm::vmm::{AddressSpace, VmSpace};
+use crate::arch::x86_64::fpu::FpuState;
+use crate::fs::mount::FsContext;
+use crate::fs::vfs::File;
+use crate::mm::vmm::{AddressSpace, Mm, VmSpace};
 use crate::sync::spinlock::SpinLock;
+use crate::sync::waitqueue::WaitQueue;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
-static NEXT_PID: AtomicU32 = AtomicU32::new(1);
+/// Starts past PID 1, which only init gets.
+static NEXT_PID: AtomicU32 = AtomicU32::new(init::INIT_PID + 1);
 pub fn alloc_pid() -> u32 {
     NEXT_PID.fetch_add(1, Ordering::Relaxed)
 }
@@ -20,6 +35,8 @@ pub enum ProcessState {
     Running,
     Runnable,
     Sleeping,
+    /// Stopped by SIGSTOP/SIGTSTP until SIGCONT; in `tasks` only.
+    Stopped,
     Zombie,
     Dead,
 }
@@ -40,34 +57,175 @@ pub struct CpuContext {
     pub ss: u64,
 }
 
+/// Open files indexed by fd. Shared between threads created with
+/// CLONE_FILES; empty slots 0–2 fall back to the console.
+#[derive(Clone)]
+pub struct FdTable {
+    slots: Vec<Option<Arc<File>>>,
+}
+
+impl FdTable {
+    pub const MAX_FDS: usize = 256;
+
+    pub fn new() -> Self {
+        Self {
+            slots: alloc::vec![None, None, None],
+        }
+    }
+
+    pub fn new_shared() -> Arc<SpinLock<Self>> {
+        Arc::new(SpinLock::new(Self::new()))
+    }
+
+    /// Install `file` in the lowest free slot above stdio, below `limit`
+    /// (RLIMIT_NOFILE).
+    pub fn alloc(&mut self, file: Arc<File>, limit: usize) -> Option<i32> {
+        let limit = limit.min(Self::MAX_FDS);
+        let free = (3..self.slots.len().min(limit)).find(|&i| self.slots[i].is_none());
+        if let Some(fd) = free {
+            self.slots[fd] = Some(file);
+            return Some(fd as i32);
+        }
+        if self.slots.len() >= limit {
+            return None;
+        }
+        self.slots.push(Some(file));
+        Some(self.slots.len() as i32 - 1)
+    }
+
+    /// Put `file` at a specific fd (stdio wiring), growing the table if needed.
+    pub fn install(&mut self, fd: usize, file: Arc<File>) {
+        if fd >= self.slots.len() {
+            self.slots.resize(fd + 1, None);
+        }
+        self.slots[fd] = Some(file);
+    }
+
+    pub fn get(&self, fd: i32) -> Option<Arc<File>> {
+        if fd < 0 {
+            return None;
+        }
+        self.slots.get(fd as usize).cloned().flatten()
+    }
+
+    /// Returns the closed file, or None if `fd` wasn't open.
+    pub fn close(&mut self, fd: i32) -> Option<Arc<File>> {
+        if fd < 0 {
+            return None;
+        }
+        self.slots.get_mut(fd as usize).and_then(|slot| slot.take())
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Process {
+    /// Unique per task; this is what gettid() returns.
     pub pid: u32,
+    /// Thread group id (what getpid() returns); equals `pid` for the
+    /// group leader and for single-threaded processes.
+    pub tgid: u32,
     pub ppid: u32,
+    /// Process group: the unit job control signals, e.g. a shell pipeline.
+    pub pgid: u32,
+    /// Session: the process groups of one login, named by its leader.
+    pub sid: u32,
     pub state: ProcessState,
     pub context: CpuContext,
-    pub address_space: AddressSpace,
-    pub vm: VmSpace,
+    pub mm: Arc<SpinLock<Mm>>,
     pub kernel_stack: u64,
     pub kernel_stack_size: usize,
+    /// The run queue level, recomputed from the three below whenever the
+    /// task is queued (see `priority`).
     pub priority: u8,
+    /// The level the task was created with; u8::MAX for idle.
+    pub base_priority: u8,
+    /// -20..=19, inherited across fork and exec.
+    pub nice: i8,
+    /// Ticks of sleep credit, 0..=MAX_SLEEP_AVG, and when it last slept.
+    pub sleep_avg: u64,
+    pub slept_at: u64,
     pub time_slice: u32,
     pub base_slice: u32,
     pub exit_code: i32,
     pub name: [u8; 32],
     pub pending_signals: u64,
     pub signal_mask: u64,
+    pub signals: signal::SignalState,
+    pub files: Arc<SpinLock<FdTable>>,
+    /// Root and working directory for path lookups.
+    pub fs: Arc<SpinLock<FsContext>>,
+    /// Who the task acts as: inherited across fork and exec.
+    pub cred: cred::Cred,
+    /// CLONE_CHILD_CLEARTID / set_tid_address: zeroed when the thread exits.
+    pub clear_child_tid: u64,
+    /// waitpid() callers of this thread sleep here until a child exits.
+    pub child_wait: Arc<WaitQueue>,
+    /// Name of the wait queue the thread sleeps on, "" while it runs.
+    pub wchan: &'static str,
+    /// Timer ticks spent in ring 3 and in the kernel, and the tick count
+    /// when the task was created.
+    pub utime: u64,
+    pub stime: u64,
+    pub start_time: u64,
+    /// getrlimit()/setrlimit(): inherited across fork and exec.
+    pub limits: resource::Limits,
+    /// Most pages seen resident (see `resource::sample_rss`).
+    pub max_rss: u64,
+    /// What the children this task reaped used, theirs included.
+    pub child_usage: resource::Usage,
+    /// FS.base, the thread pointer TLS is found from (arch_prctl).
+    pub fs_base: u64,
+    /// x87/SSE/AVX registers while the task is off the CPU; kernel tasks
+    /// never use them and have none.
+    pub fpu: Option<FpuState>,
+    /// `strace`: every syscall is logged with its arguments and result.
+    /// Children inherit it, and it survives exec.
+    pub trace: bool,
+}
+
+/// The kernel stack goes back with the last reference to the task, which
+/// a task running on it still holds, unless `free_dead_stacks` took it
+/// first.
+impl Drop for Process {
+    fn drop(&mut self) {
+        if self.kernel_stack != 0 {
+            crate::mm::kstack::free(self.kernel_stack);
+        }
+    }
 }
 
 impl Process {
-    pub const KERNEL_STACK_SIZE: usize = 16 * 1024;
+    pub const KERNEL_STACK_SIZE: usize = crate::mm::kstack::STACK_SIZE;
     pub const DEFAULT_TIME_SLICE: u32 = 10;
 
+    pub fn alloc_fd(&self, file: Arc<File>) -> Option<i32> {
+        let limit = self.limits.cur(resource::RLIMIT_NOFILE);
+        self.files.lock().alloc(file, limit.min(FdTable::MAX_FDS as u64) as usize)
+    }
+
+    pub fn get_fd(&self, fd: i32) -> Option<Arc<File>> {
+        self.files.lock().get(fd)
+    }
+
+    pub fn close_fd(&self, fd: i32) -> Option<Arc<File>> {
+        self.files.lock().close(fd)
+    }
+
+    /// Private copy of the fd table for fork(); the files themselves (and
+    /// their offsets) stay shared.
+    pub fn copy_files(&self) -> Arc<SpinLock<FdTable>> {
+        let table = self.files.lock().clone();
+        Arc::new(SpinLock::new(table))
+    }
+
     pub fn new_kernel(name: &str, entry: fn() -> !, priority: u8) -> Option<Arc<SpinLock<Self>>> {
-        use crate::arch::x86_64::limine::phys_to_virt;
-        use crate::mm::pmm::alloc_frames;
         let pid = alloc_pid();
-        let stack_phys = alloc_frames(2)?;
-        let stack_virt = phys_to_virt(stack_phys);
+        let stack_virt = crate::mm::kstack::alloc()?;
         let stack_top = stack_virt + Self::KERNEL_STACK_SIZE as u64;
         let ctx = CpuContext {
             rip: entry as u64,
@@ -82,20 +240,42 @@ impl Process {
         name_bytes[..n].copy_from_slice(&name.as_bytes()[..n]);
         Some(Arc::new(SpinLock::new(Self {
             pid,
+            tgid: pid,
             ppid: 0,
+            pgid: pid,
+            sid: pid,
             state: ProcessState::Runnable,
             context: ctx,
-            address_space: AddressSpace::kernel(),
-            vm: VmSpace::new(),
+            mm: Mm::new_shared(AddressSpace::kernel(), VmSpace::new()),
             kernel_stack: stack_virt,
             kernel_stack_size: Self::KERNEL_STACK_SIZE,
             priority,
+            base_priority: priority,
+            nice: 0,
+            sleep_avg: priority::MAX_SLEEP_AVG / 2,
+            slept_at: 0,
             time_slice: Self::DEFAULT_TIME_SLICE,
             base_slice: Self::DEFAULT_TIME_SLICE,
             exit_code: 0,
             name: name_bytes,
             pending_signals: 0,
             signal_mask: 0,
+            signals: signal::SignalState::new(),
+            files: FdTable::new_shared(),
+            fs: FsContext::new_shared(FsContext::default()),
+            cred: cred::Cred::ROOT,
+            clear_child_tid: 0,
+            child_wait: Arc::new(WaitQueue::new("wait")),
+            wchan: "",
+            utime: 0,
+            stime: 0,
+            start_time: 0,
+            limits: resource::Limits::DEFAULT,
+            max_rss: 0,
+            child_usage: resource::Usage::default(),
+            fs_base: 0,
+            fpu: None,
+            trace: false,
         })))
     }
 
@@ -109,28 +289,20 @@ impl Process {
         priority: u8,
     ) -> Result<Arc<SpinLock<Self>>, &'static str> {
         use crate::arch::x86_64::gdt::{SEG_USER_CODE, SEG_USER_DATA};
-        use crate::arch::x86_64::limine::phys_to_virt;
-        use crate::mm::pmm::alloc_frames;
 
         let pid = alloc_pid();
 
-        // Kernel stack for this process
-        let kstack_phys = alloc_frames(2).ok_or("OOM: kernel stack")?;
-        let kstack_virt = phys_to_virt(kstack_phys);
-        let kstack_top = kstack_virt + Self::KERNEL_STACK_SIZE as u64;
-
         // User address space + ELF
         let mut space = AddressSpace::new_user().ok_or("OOM: address space")?;
         let mut vm = VmSpace::new();
 
-        let pie_base = if crate::proc::exec::is_pie(elf_data) {
-            crate::proc::exec::PIE_BASE
-        } else {
-            0
-        };
-        let loaded =
-            crate::proc::elf::load_elf(elf_data, &mut space, &mut vm, pie_base)
-                .map_err(|_| "ELF load failed")?;
+        let image = crate::proc::exec::load_image(elf_data, &mut space, &mut vm).map_err(|e| {
+            log::warn!("{}: {:?}", name, e);
+            match e {
+                crate::proc::exec::ExecError::NotFound => "interpreter not found",
+                _ => "ELF load failed",
+            }
+        })?;
 
         // User stack with aux vectors
         let argv_refs: Vec<&[u8]> = argv.iter().map(|v| v.as_slice()).collect();
@@ -138,12 +310,20 @@ impl Process {
         let ustack = crate::proc::stack::build_user_stack(
             &mut space,
             &mut vm,
-            &loaded,
+            &image.main,
+            image.interp_base,
             &argv_refs,
             &envp_refs,
             name.as_bytes(),
         )
         .ok_or("user stack build failed")?;
+        if !signal::map_trampoline(&mut space, &mut vm) {
+            return Err("OOM: signal trampoline");
+        }
+
+        // Kernel stack for this process, last: nothing fails after it
+        let kstack_virt = crate::mm::kstack::alloc().ok_or("OOM: kernel stack")?;
+        let kstack_top = kstack_virt + Self::KERNEL_STACK_SIZE as u64;
 
         // Set up IRETQ frame on the kernel stack so the first
         // jump_to_context → iretq_trampoline transitions to ring 3.
@@ -152,7 +332,7 @@ impl Process {
         //   [RIP] [CS] [RFLAGS] [RSP] [SS]
         let frame = unsafe {
             let p = (kstack_top as *mut u64).sub(5);
-            p.add(0).write(loaded.entry);                    // RIP
+            p.add(0).write(image.entry);                     // RIP
             p.add(1).write(SEG_USER_CODE as u64);            // CS
             p.add(2).write(0x0202u64);                       // RFLAGS (IF=1)
             p.add(3).write(ustack.initial_rsp);              // RSP
@@ -172,71 +352,241 @@ impl Process {
         let mut name_bytes = [0u8; 32];
         let n = name.len().min(31);
         name_bytes[..n].copy_from_slice(&name.as_bytes()[..n]);
+        // In the session of whoever starts it, a shell's say
+        let sid = current_process().map_or(pid, |p| p.lock().sid);
 
         Ok(Arc::new(SpinLock::new(Self {
             pid,
+            tgid: pid,
             ppid: 0,
+            pgid: pid,
+            sid,
             state: ProcessState::Runnable,
             context: ctx,
-            address_space: space,
-            vm,
+            mm: Mm::new_shared(space, vm),
             kernel_stack: kstack_virt,
             kernel_stack_size: Self::KERNEL_STACK_SIZE,
             priority,
+            base_priority: priority,
+            nice: 0,
+            sleep_avg: priority::MAX_SLEEP_AVG / 2,
+            slept_at: 0,
             time_slice: Self::DEFAULT_TIME_SLICE,
             base_slice: Self::DEFAULT_TIME_SLICE,
             exit_code: 0,
             name: name_bytes,
             pending_signals: 0,
             signal_mask: 0,
+            signals: signal::SignalState::new(),
+            files: FdTable::new_shared(),
+            // Where whoever starts it is
+            fs: FsContext::new_shared(crate::fs::mount::current_fs().lock().clone()),
+            cred: cred::current(),
+            clear_child_tid: 0,
+            child_wait: Arc::new(WaitQueue::new("wait")),
+            wchan: "",
+            utime: 0,
+            stime: 0,
+            start_time: 0,
+            limits: resource::Limits::DEFAULT,
+            max_rss: 0,
+            child_usage: resource::Usage::default(),
+            fs_base: 0,
+            fpu: Some(FpuState::new()),
+            trace: false,
         })))
     }
 
+    /// Kernel threads run on the kernel page tables and never enter ring 3.
+    pub fn is_user(&self) -> bool {
+        self.mm.lock().space.pml4_phys != crate::mm::vmm::kernel_pml4_phys()
+    }
+
     pub fn name_str(&self) -> &str {
         let end = self.name.iter().position(|&b| b == 0).unwrap_or(32);
         core::str::from_utf8(&self.name[..end]).unwrap_or("???")
     }
 }
 
+/// Priorities run from 0 (most urgent) to 255 (the idle task).
+pub const NUM_PRIORITIES: usize = 256;
+
+type Task = Arc<SpinLock<Process>>;
+
 pub struct RunQueue {
-    pub queue: Vec<Arc<SpinLock<Process>>>,
-    pub current: Option<Arc<SpinLock<Process>>>,
+    /// Every task not yet reaped — running, runnable, asleep, stopped or zombie.
+    pub tasks: BTreeMap<u32, Task>,
+    /// Runnable tasks other than `current`, one FIFO per priority.
+    ready: [VecDeque<Task>; NUM_PRIORITIES],
+    /// Bit p set ⇔ `ready[p]` is non-empty.
+    ready_mask: [u64; NUM_PRIORITIES / 64],
+    pub current: Option<Task>,
 }
 
 impl RunQueue {
     const fn new() -> Self {
         Self {
-            queue: Vec::new(),
+            tasks: BTreeMap::new(),
+            ready: [const { VecDeque::new() }; NUM_PRIORITIES],
+            ready_mask: [0; NUM_PRIORITIES / 64],
             current: None,
         }
     }
-    fn pick_next(&mut self) -> Option<Arc<SpinLock<Process>>> {
-        let best = self
-            .queue
-            .iter()
-            .enumerate()
-            .filter(|(_, p)| p.lock().state == ProcessState::Runnable)
-            .min_by_key(|(_, p)| p.lock().priority)
-            .map(|(i, _)| i);
-        best.map(|i| self.queue.remove(i))
+
+    fn enqueue(&mut self, task: Task, priority: u8) {
+        let prio = priority as usize;
+        self.ready[prio].push_back(task);
+        self.ready_mask[prio / 64] |= 1 << (prio % 64);
+    }
+
+    /// Front of the most urgent non-empty FIFO.
+    fn pick_next(&mut self) -> Option<Task> {
+        loop {
+            let (word, bits) = self.ready_mask.iter().enumerate().find(|(_, &b)| b != 0)?;
+            let prio = word * 64 + bits.trailing_zeros() as usize;
+            let task = self.ready[prio].pop_front();
+            if self.ready[prio].is_empty() {
+                self.ready_mask[word] &= !(1 << (prio % 64));
+            }
+            match task {
+                Some(t) if t.lock().state == ProcessState::Runnable => return Some(t),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Sleeping → Runnable; returns false if `task` wasn't asleep.
+    pub fn wake(&mut self, task: &Task) -> bool {
+        self.make_runnable(task, ProcessState::Sleeping)
+    }
+
+    /// Stopped → Runnable, for SIGCONT; false if `task` wasn't stopped.
+    pub fn resume(&mut self, task: &Task) -> bool {
+        self.make_runnable(task, ProcessState::Stopped)
+    }
+
+    fn make_runnable(&mut self, task: &Task, from: ProcessState) -> bool {
+        let prio = {
+            let mut p = task.lock();
+            if p.state != from {
+                return false;
+            }
+            p.state = ProcessState::Runnable;
+            if from == ProcessState::Sleeping {
+                priority::woken(&mut p);
+            }
+            p.priority = priority::effective(&p);
+            p.priority
+        };
+        // Still current if it hasn't switched away yet; schedule requeues it
+        match &self.current {
+            Some(c) if Arc::ptr_eq(c, task) => {}
+            current => {
+                // A more urgent task woken takes the CPU when the interrupt
+                // or syscall that woke it returns
+                if current.as_ref().is_some_and(|c| prio < c.lock().priority) {
+                    NEED_RESCHED.get().store(true, Ordering::Relaxed);
+                }
+                self.enqueue(task.clone(), prio);
+            }
+        }
+        true
+    }
+
+    /// The children of exiting group `tgid` lose their parent. While init
+    /// runs they become its children, zombies too, and the result is
+    /// whether it has any of those to reap now; without it, zombies nobody
+    /// will reap go at once and the others will exit as Dead.
+    fn orphan_children(&mut self, tgid: u32) -> bool {
+        let init_alive = self.tasks.get(&init::INIT_PID).is_some_and(|t| {
+            !matches!(t.lock().state, ProcessState::Zombie | ProcessState::Dead)
+        });
+        let heir = if init_alive && tgid != init::INIT_PID { init::INIT_PID } else { 0 };
+        let mut zombies = false;
+        self.tasks.retain(|_, t| {
+            let mut t = t.lock();
+            if t.ppid != tgid || t.tgid == tgid {
+                return true;
+            }
+            t.ppid = heir;
+            if t.state != ProcessState::Zombie {
+                return true;
+            }
+            zombies = true;
+            heir != 0
+        });
+        zombies && heir != 0
+    }
+
+    pub fn find(&self, pid: u32) -> Option<&Task> {
+        self.tasks.get(&pid)
+    }
+
+    /// Some task besides `current` is waiting for the CPU.
+    pub fn has_ready(&self) -> bool {
+        self.ready_mask.iter().any(|&b| b != 0)
     }
 }
 
 pub static RUN_QUEUE: SpinLock<RunQueue> = SpinLock::new(RunQueue::new());
 
+/// Tasks switched away from as zombies or dead: they never run again, but
+/// their stacks can only go once something else is on the CPU.
+static DEAD_STACKS: SpinLock<Vec<Arc<SpinLock<Process>>>> = SpinLock::new(Vec::new());
+
+/// kworker: free the kernel stacks `schedule_locked` left behind. A zombie
+/// stays in the task table until reaped; the Process goes with the last
+/// reference, without its stack.
+fn free_dead_stacks(_: u64) {
+    let dead = core::mem::take(&mut *DEAD_STACKS.lock());
+    for t in dead {
+        let stack = core::mem::take(&mut t.lock().kernel_stack);
+        crate::mm::kstack::free(stack);
+    }
+}
+
 pub fn spawn(proc: Arc<SpinLock<Process>>) {
-    RUN_QUEUE.lock().queue.push(proc);
+    let mut rq = RUN_QUEUE.lock();
+    let (pid, prio, runnable) = {
+        let mut p = proc.lock();
+        p.start_time = crate::arch::x86_64::timer::ticks();
+        p.priority = priority::effective(&p);
+        (p.pid, p.priority, p.state == ProcessState::Runnable)
+    };
+    rq.tasks.insert(pid, proc.clone());
+    if runnable {
+        rq.enqueue(proc, prio);
+    }
+}
+
+/// The idle task polls this after every interrupt to hand the CPU over.
+pub fn has_runnable() -> bool {
+    RUN_QUEUE.lock().has_ready()
 }
 
 pub fn current_process() -> Option<Arc<SpinLock<Process>>> {
     RUN_QUEUE.lock().current.clone()
 }
 
-pub fn tick() {
+crate::sync::percpu! {
+    /// Set by the tick once the running task's slice is used up, and by
+    /// the wake-up of a task more urgent than it.
+    static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Timer tick: charge it to the running task (`user` if it interrupted
+/// ring 3) and ask for a switch once its slice is used up.
+pub fn tick(user: bool) {
     let preempt = {
-        let mut rq = RUN_QUEUE.lock();
+        let rq = RUN_QUEUE.lock();
         if let Some(ref c) = rq.current {
             let mut p = c.lock();
+            if user {
+                p.utime += 1;
+            } else {
+                p.stime += 1;
+            }
+            priority::charge(&mut p);
             if p.time_slice > 0 {
                 p.time_slice -= 1;
             }
@@ -246,11 +596,39 @@ pub fn tick() {
         }
     };
     if preempt {
+        NEED_RESCHED.get().store(true, Ordering::Relaxed);
+    }
+}
+
+/// Last thing before an interrupt or syscall returns, once its handler
+/// is done and the IRQ acknowledged: switch away if the tick asked to.
+///
+/// The interrupted task's full register frame stays where the entry stub
+/// pushed it, on the task's own kernel stack, and the task sleeps in here
+/// on top of it. When it runs again, it comes back out of this call with
+/// interrupts still off and returns through its own isr_common or
+/// syscall_entry exit, whose iretq puts back exactly what it interrupted:
+/// ring 3 code, or kernel code with interrupts on.
+pub fn preempt() {
+    if NEED_RESCHED.get().load(Ordering::Relaxed) {
         schedule();
     }
 }
 
+/// Give the CPU to the next task. Returns when this one is picked again,
+/// with RFLAGS.IF as it was on the call.
 pub fn schedule() {
+    // Off until we are switched back in: an interrupt between choosing the
+    // next task and switching to it would schedule from a half-made switch
+    let rflags = crate::arch::x86_64::io::cli();
+    schedule_locked();
+    if rflags & crate::arch::x86_64::io::RFLAGS_IF != 0 {
+        crate::arch::x86_64::io::sti();
+    }
+}
+
+fn schedule_locked() {
+    NEED_RESCHED.get().store(false, Ordering::Relaxed);
     let mut rq = RUN_QUEUE.lock();
     let old = rq.current.take();
     if let Some(ref p) = old {
@@ -258,20 +636,29 @@ pub fn schedule() {
         if proc.state == ProcessState::Running {
             proc.state = ProcessState::Runnable;
             proc.time_slice = proc.base_slice;
+            proc.priority = priority::effective(&proc);
         }
-        let requeue = !matches!(proc.state, ProcessState::Dead | ProcessState::Zombie);
+        let (state, pid, prio) = (proc.state, proc.pid, proc.priority);
         drop(proc);
-        if requeue {
-            rq.queue.push(p.clone());
+        match state {
+            ProcessState::Runnable => rq.enqueue(p.clone(), prio),
+            ProcessState::Dead => {
+                rq.tasks.remove(&pid);
+            }
+            _ => {} // asleep or stopped (in `tasks` only), or a zombie waiting to be reaped
         }
     }
     let next = rq.pick_next();
+    let mut idle = false;
     if let Some(ref p) = next {
-        p.lock().state = ProcessState::Running;
+        let mut p = p.lock();
+        p.state = ProcessState::Running;
+        idle = p.priority == u8::MAX;
     }
     let next_for_switch = next.clone();
     rq.current = next.clone();
     drop(rq);
+    crate::arch::x86_64::timer::set_idle(idle);
 
     if let (Some(old_a), Some(new_a)) = (old, next_for_switch) {
         if Arc::ptr_eq(&old_a, &new_a) {
@@ -283,12 +670,48 @@ pub fn schedule() {
             {
                 let op = old_a.lock();
                 let np = new_a.lock();
-                if op.address_space.pml4_phys != np.address_space.pml4_phys {
-                    np.address_space.activate();
+                // Threads of one process share the Mm (and its lock)
+                if !Arc::ptr_eq(&op.mm, &np.mm) {
+                    let next = np.mm.lock();
+                    if op.mm.lock().space.pml4_phys != next.space.pml4_phys {
+                        next.space.activate();
+                    }
                 }
             }
-            let oc = &mut old_a.lock().context as *mut CpuContext;
-            let nc = &new_a.lock().context as *const CpuContext;
+            {
+                let mut op = old_a.lock();
+                op.fs_base = current_fs_base();
+                if let Some(fpu) = op.fpu.as_mut() {
+                    fpu.save();
+                }
+                let np = new_a.lock();
+                load_fs_base(&np);
+                if let Some(fpu) = np.fpu.as_ref() {
+                    fpu.restore();
+                }
+            }
+            // In blocks, so the guards go now: as `&mut x.lock().context`
+            // in a `let` they would live on, locked, across the switch
+            let (oc, state) = {
+                let mut op = old_a.lock();
+                (&mut op.context as *mut CpuContext, op.state)
+            };
+            let nc = {
+                let np = new_a.lock();
+                &np.context as *const CpuContext
+            };
+            // No Arc may stay in this frame either: a task that has exited
+            // never comes back to drop it. The task table keeps both alive
+            // across the switch, and a dying task's is ours until kworker
+            // has freed its stack.
+            if matches!(state, ProcessState::Zombie | ProcessState::Dead) {
+                DEAD_STACKS.lock().push(old_a);
+                crate::proc::workqueue::schedule_work(free_dead_stacks, 0);
+            } else {
+                drop(old_a);
+            }
+            drop(new_a);
+            drop(next);
             context_switch(oc, nc);
         }
     } else if let Some(a) = next {
@@ -298,49 +721,350 @@ pub fn schedule() {
             // never be dropped, leaking the process SpinLock forever.
             let ctx_ptr = {
                 let g = a.lock();
+                load_fs_base(&g);
+                if let Some(fpu) = g.fpu.as_ref() {
+                    fpu.restore();
+                }
                 &g.context as *const CpuContext
-            }; // lock released here; pointer stays valid (Arc keeps data alive)
+            }; // lock released here; pointer stays valid (rq.current keeps data alive)
+            drop(a);
             jump_to_context(ctx_ptr);
         }
     }
 }
 
+/// FS.base of the running task. Ring 3 may have moved it with wrfsbase
+/// (CR4.FSGSBASE is on where the CPU has it) since arch_prctl set it, so
+/// the MSR is what counts, and `fs_base` only while the task is off the CPU.
+pub fn current_fs_base() -> u64 {
+    unsafe { crate::arch::x86_64::io::rdmsr(crate::arch::x86_64::io::MSR_FS_BASE) }
+}
+
+/// Put `p`'s FS.base in the MSR.
+fn load_fs_base(p: &Process) {
+    use crate::arch::x86_64::io::{wrmsr, MSR_FS_BASE};
+    if current_fs_base() != p.fs_base {
+        unsafe { wrmsr(MSR_FS_BASE, p.fs_base) };
+    }
+}
+
+/// arch_prctl(): ARCH_SET_FS and ARCH_GET_FS. GS stays the kernel's: its
+/// base is the per-CPU area swapgs relies on.
+pub fn sys_arch_prctl(code: u32, addr: u64) -> i64 {
+    use crate::arch::x86_64::io::{wrmsr, MSR_FS_BASE};
+    use crate::syscall::errno::{EINVAL, EPERM, ESRCH};
+    const ARCH_SET_FS: u32 = 0x1002;
+    const ARCH_GET_FS: u32 = 0x1003;
+
+    let Some(arc) = current_process() else {
+        return -ESRCH;
+    };
+    match code {
+        ARCH_SET_FS => {
+            if addr >= crate::mm::vmm::USER_SPACE_END {
+                return -EPERM;
+            }
+            arc.lock().fs_base = addr;
+            unsafe { wrmsr(MSR_FS_BASE, addr) };
+            0
+        }
+        ARCH_GET_FS => {
+            match crate::mm::uaccess::put_user(addr, &current_fs_base()) {
+                Ok(()) => 0,
+                Err(e) => e.as_neg_i64(),
+            }
+        }
+        _ => -EINVAL,
+    }
+}
+
+/// Terminate the current thread and switch away for good. Other threads
+/// of the process keep running; see `exit_group`. A group leader with a
+/// parent stays behind as a zombie until waitpid() reaps it.
+pub fn exit_current(exit_code: i32) -> ! {
+    if let Some(arc) = current_process() {
+        let (files, clear_tid, parent, tgid) = {
+            let mut p = arc.lock();
+            // Only a group leader's exit is something waitpid() reports
+            let parent = (p.pid == p.tgid && p.ppid != 0).then_some(p.ppid);
+            p.state = match parent {
+                Some(_) => ProcessState::Zombie,
+                None => ProcessState::Dead,
+            };
+            p.exit_code = exit_code;
+            resource::sample_rss(&mut p);
+            let files = core::mem::replace(&mut p.files, FdTable::new_shared());
+            (files, core::mem::take(&mut p.clear_child_tid), parent, p.tgid)
+        };
+        let leader = arc.lock().pid == tgid;
+        if leader && RUN_QUEUE.lock().orphan_children(tgid) {
+            wake_parent(init::INIT_PID);
+        }
+        if clear_tid != 0 {
+            // Lets a joiner see the thread is gone, and wakes it if it
+            // sleeps on the tid word
+            let _ = crate::mm::uaccess::put_user(clear_tid, &0u32);
+            futex::wake(clear_tid, 1);
+        }
+        // Dropping our reference closes the files unless another thread
+        // shares them; closing may wake pipe peers, which takes our own lock
+        drop(files);
+
+        // Give the user address space back. We are still running on its
+        // page tables, so move to the kernel's before the last reference
+        // (possibly ours) goes away.
+        let mm = {
+            let mut p = arc.lock();
+            let mm = core::mem::replace(&mut p.mm, Mm::kernel_shared());
+            p.mm.lock().space.activate();
+            mm
+        };
+        drop(mm);
+        if let Some(ppid) = parent {
+            wake_parent(ppid);
+        }
+        if leader && tgid == init::INIT_PID {
+            init::exited(exit_code);
+        }
+    }
+    schedule();
+    // schedule() returns only when no other runnable process exists;
+    // halt until the next interrupt (timer/keyboard) triggers a reschedule.
+    loop {
+        crate::arch::x86_64::io::hlt();
+    }
+}
+
+/// Make every other thread of group `tgid` exit with the given status. They
+/// die on their way back to ring 3, or when a blocking call sees the signal.
+pub fn zap_other_threads(tgid: u32, exit_code: i32, term_signal: u32) {
+    let mut rq = RUN_QUEUE.lock();
+    let victims: Vec<Task> = rq
+        .tasks
+        .values()
+        .filter(|t| !rq.current.as_ref().is_some_and(|c| Arc::ptr_eq(c, t)))
+        .filter(|t| {
+            let mut t = t.lock();
+            if t.tgid != tgid || t.signals.group_exit {
+                return false;
+            }
+            t.signals.group_exit = true;
+            t.exit_code = exit_code;
+            t.signals.term_signal = term_signal;
+            t.pending_signals |= signal::sig_bit(signal::SIGKILL);
+            true
+        })
+        .cloned()
+        .collect();
+    for t in &victims {
+        rq.wake(t);
+    }
+}
+
+/// exit_group(): stop every other thread of the current process, then exit.
+/// `term_signal` is the fatal signal when the group dies of one.
+pub fn exit_group(exit_code: i32, term_signal: u32) -> ! {
+    if let Some(arc) = current_process() {
+        let tgid = {
+            let mut p = arc.lock();
+            p.signals.term_signal = term_signal;
+            p.tgid
+        };
+        zap_other_threads(tgid, exit_code, term_signal);
+    }
+    exit_current(exit_code)
+}
+
 pub fn sleep_current() {
     if let Some(ref p) = RUN_QUEUE.lock().current {
-        p.lock().state = ProcessState::Sleeping;
+        let mut p = p.lock();
+        p.state = ProcessState::Sleeping;
+        priority::sleeping(&mut p);
     }
     schedule();
 }
 
-pub fn wake_up(pid: u32) {
+/// Make `pid` runnable if it is asleep; returns whether it was.
+pub fn wake_up(pid: u32) -> bool {
+    let mut rq = RUN_QUEUE.lock();
+    match rq.find(pid).cloned() {
+        Some(t) => rq.wake(&t),
+        None => false,
+    }
+}
+
+/// Wake the threads of group `tgid` that sleep in waitpid().
+pub fn wake_parent(tgid: u32) {
+    let queues: Vec<Arc<WaitQueue>> = RUN_QUEUE
+        .lock()
+        .tasks
+        .values()
+        .filter_map(|p| {
+            let p = p.lock();
+            (p.tgid == tgid).then(|| p.child_wait.clone())
+        })
+        .collect();
+    for q in queues {
+        q.wake_all();
+    }
+}
+
+// ─── Process groups and sessions ─────────────────────────────────────────────
+
+/// setpgid(): move process `pid` (0 for the caller) into group `pgid` (0
+/// for a group of its own). Only the caller and its children can be
+/// moved, within their session and not its leader, and only into a group
+/// that exists there or one named after the process.
+pub fn sys_setpgid(pid: u32, pgid: u32) -> i64 {
+    use crate::syscall::errno::{EINVAL, EPERM, ESRCH};
+
     let rq = RUN_QUEUE.lock();
-    for p in &rq.queue {
-        let mut proc = p.lock();
-        if proc.pid == pid && proc.state == ProcessState::Sleeping {
-            proc.state = ProcessState::Runnable;
-            return;
+    let Some((tgid, sid)) = rq.current.as_ref().map(|c| {
+        let c = c.lock();
+        (c.tgid, c.sid)
+    }) else {
+        return -ESRCH;
+    };
+    let pid = if pid == 0 { tgid } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    if pgid > i32::MAX as u32 {
+        return -EINVAL;
+    }
+    let Some((ppid, target_sid)) = rq.find(pid).and_then(|t| {
+        let t = t.lock();
+        (t.tgid == t.pid).then_some((t.ppid, t.sid))
+    }) else {
+        return -ESRCH;
+    };
+    if pid != tgid && ppid != tgid {
+        return -ESRCH;
+    }
+    if target_sid != sid || pid == sid {
+        return -EPERM;
+    }
+    let group_there = |t: &Task| {
+        let t = t.lock();
+        t.pgid == pgid && t.sid == sid
+    };
+    if pgid != pid && !rq.tasks.values().any(group_there) {
+        return -EPERM;
+    }
+    for t in rq.tasks.values() {
+        let mut t = t.lock();
+        if t.tgid == pid {
+            t.pgid = pgid;
         }
     }
+    0
 }
 
-/// Wake every sleeping process — used by keyboard IRQ so the shell can receive input.
-pub fn wake_up_all_sleeping() {
+/// getpgid() / getsid(): the group or session of `pid`, 0 for the caller.
+pub fn sys_getpgid(pid: u32, session: bool) -> i64 {
+    use crate::syscall::errno::ESRCH;
+
     let rq = RUN_QUEUE.lock();
-    for p in &rq.queue {
-        let mut proc = p.lock();
-        if proc.state == ProcessState::Sleeping {
-            proc.state = ProcessState::Runnable;
+    let task = match pid {
+        0 => rq.current.as_ref(),
+        pid => rq.find(pid),
+    };
+    match task {
+        Some(t) if session => t.lock().sid as i64,
+        Some(t) => t.lock().pgid as i64,
+        None => -ESRCH,
+    }
+}
+
+/// setsid(): a new session, and a new group in it, both led by the
+/// caller, which mustn't lead a group already.
+pub fn sys_setsid() -> i64 {
+    use crate::syscall::errno::{EPERM, ESRCH};
+
+    let rq = RUN_QUEUE.lock();
+    let Some(tgid) = rq.current.as_ref().map(|c| c.lock().tgid) else {
+        return -ESRCH;
+    };
+    if rq.tasks.values().any(|t| t.lock().pgid == tgid) {
+        return -EPERM;
+    }
+    for t in rq.tasks.values() {
+        let mut t = t.lock();
+        if t.tgid == tgid {
+            t.pgid = tgid;
+            t.sid = tgid;
         }
     }
-    // Also wake the current process if it is sleeping (edge case during scheduling).
-    if let Some(ref cur) = rq.current {
-        let mut proc = cur.lock();
-        if proc.state == ProcessState::Sleeping {
-            proc.state = ProcessState::Runnable;
+    tgid as i64
+}
+
+// ─── Accounting ──────────────────────────────────────────────────────────────
+
+/// What /proc/<pid>/stat and `top` show of a task, copied out so the
+/// caller holds no locks while formatting.
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub pid: u32,
+    pub tgid: u32,
+    pub ppid: u32,
+    pub pgid: u32,
+    pub sid: u32,
+    pub uid: u32,
+    pub state: ProcessState,
+    pub priority: u8,
+    pub nice: i8,
+    pub utime: u64,
+    pub stime: u64,
+    pub start_time: u64,
+    pub name: String,
+    pub wchan: &'static str,
+    /// Bytes covered by the task's VMAs.
+    pub vsize: u64,
+}
+
+impl ProcessState {
+    /// One-letter state as in /proc/<pid>/stat.
+    pub fn code(self) -> char {
+        match self {
+            ProcessState::Running | ProcessState::Runnable => 'R',
+            ProcessState::Sleeping => 'S',
+            ProcessState::Stopped => 'T',
+            ProcessState::Zombie => 'Z',
+            ProcessState::Dead => 'X',
         }
     }
 }
 
+fn info(p: &Process) -> TaskInfo {
+    let vsize = p.mm.lock().vm.size();
+    TaskInfo {
+        pid: p.pid,
+        tgid: p.tgid,
+        ppid: p.ppid,
+        pgid: p.pgid,
+        sid: p.sid,
+        uid: p.cred.uid,
+        state: p.state,
+        priority: p.priority,
+        nice: p.nice,
+        utime: p.utime,
+        stime: p.stime,
+        start_time: p.start_time,
+        name: String::from(p.name_str()),
+        wchan: p.wchan,
+        vsize,
+    }
+}
+
+/// Every task the scheduler knows of, by pid.
+pub fn task_snapshot() -> Vec<TaskInfo> {
+    let rq = RUN_QUEUE.lock();
+    rq.tasks.values().map(|t| info(&t.lock())).collect()
+}
+
+pub fn task_info(pid: u32) -> Option<TaskInfo> {
+    let rq = RUN_QUEUE.lock();
+    rq.find(pid).map(|t| info(&t.lock()))
+}
+
 #[unsafe(naked)]
 pub unsafe extern "C" fn context_switch(old: *mut CpuContext, new: *const CpuContext) {
     core::arch::naked_asm!(
@@ -363,9 +1087,7 @@ pub unsafe extern "C" fn context_switch(old: *mut CpuContext, new: *const CpuCon
         "mov 32(%rsi),%rbp",
         "mov 40(%rsi),%rbx",
         "mov 48(%rsi),%rsp",
-        "mov 64(%rsi),%rax",
-        "or $0x200,%rax",
-        "push %rax",
+        "push 64(%rsi)",
         "popfq",
         "jmp *56(%rsi)",
         "1:",
@@ -384,9 +1106,7 @@ unsafe extern "C" fn jump_to_context(ctx: *const CpuContext) {
         "mov 32(%rdi),%rbp",
         "mov 40(%rdi),%rbx",
         "mov 48(%rdi),%rsp",
-        "mov 64(%rdi),%rax",
-        "or $0x200,%rax",
-        "push %rax",
+        "push 64(%rdi)",
         "popfq",
         "jmp *56(%rdi)",
         options(att_syntax)
@@ -401,6 +1121,38 @@ pub unsafe extern "C" fn iretq_trampoline() -> ! {
     core::arch::naked_asm!("iretq", options(att_syntax));
 }
 
+/// Kernel stack entry for a task created by clone(): the `InterruptFrame`
+/// left at `context.rsp` is popped exactly like isr_common's exit path.
+#[unsafe(naked)]
+pub unsafe extern "C" fn return_to_user() -> ! {
+    core::arch::naked_asm!(
+        // context_switch enabled IF; an IRQ between swapgs and iretq would
+        // see a kernel CS with the user GS base
+        "cli",
+        "pop %r15",
+        "pop %r14",
+        "pop %r13",
+        "pop %r12",
+        "pop %r11",
+        "pop %r10",
+        "pop %r9",
+        "pop %r8",
+        "pop %rbp",
+        "pop %rdi",
+        "pop %rsi",
+        "pop %rdx",
+        "pop %rcx",
+        "pop %rbx",
+        "pop %rax",
+        "add $16, %rsp",
+        "swapgs",
+        "iretq",
+        options(att_syntax)
+    );
+}
+
 pub mod scheduler {
-    pub use super::{current_process, schedule, sleep_current, spawn, tick, wake_up, RUN_QUEUE};
+    pub use super::{
+        current_process, has_runnable, schedule, sleep_current, spawn, tick, wake_up, RUN_QUEUE,
+    };
 }
@@ -0,0 +1,44 @@
+//! Credentials: the user and group a task acts as, for the permission
+//! checks in the VFS. One id each, no supplementary groups and no split
+//! between real and effective ids.
+use crate::syscall::errno::EPERM;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cred {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Cred {
+    pub const ROOT: Cred = Cred { uid: 0, gid: 0 };
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+}
+
+/// The current task's credentials; root before there are tasks.
+pub fn current() -> Cred {
+    crate::proc::current_process().map_or(Cred::ROOT, |p| p.lock().cred)
+}
+
+/// setuid / setgid: root takes any id, anyone else only the one they have.
+pub fn sys_setuid(uid: u32) -> i64 {
+    set(|c| &mut c.uid, uid)
+}
+
+pub fn sys_setgid(gid: u32) -> i64 {
+    set(|c| &mut c.gid, gid)
+}
+
+fn set(field: fn(&mut Cred) -> &mut u32, id: u32) -> i64 {
+    let Some(arc) = crate::proc::current_process() else {
+        return -EPERM;
+    };
+    let mut p = arc.lock();
+    if !p.cred.is_root() && *field(&mut p.cred) != id {
+        return -EPERM;
+    }
+    *field(&mut p.cred) = id;
+    0
+}
@@ -1,7 +1,8 @@
 use crate::arch::x86_64::limine::phys_to_virt;
 use crate::mm::pmm::{align_down, align_up, alloc_zeroed_frame, PAGE_SIZE};
 use crate::mm::vmm::{
-    AddressSpace, VmSpace, VmaFlags, PTE_NO_EXEC, PTE_PRESENT, PTE_USER, PTE_WRITABLE,
+    AddressSpace, VmSpace, VmaFlags, PTE_ADDR_MASK, PTE_NO_EXEC, PTE_PRESENT, PTE_USER,
+    PTE_WRITABLE,
 };
 use alloc::vec::Vec;
 
@@ -219,17 +220,34 @@ pub fn load_elf(
 
         let mut page_offset = 0u64;
         while page_offset < (page_end - page_vaddr) {
-            let frame_phys = alloc_zeroed_frame().ok_or(ElfError::AllocFailed)?;
-
             let vaddr = page_vaddr + page_offset;
-            if !addr_space.map(vaddr, frame_phys, pte_flags) {
+
+            // Adjacent segments may share a page: keep its contents and
+            // give it the union of both permissions.
+            let (frame_phys, flags) = match addr_space.pte(vaddr) {
+                Some(old) => {
+                    let nx = old & pte_flags & PTE_NO_EXEC;
+                    let merged = (old | pte_flags) & !PTE_NO_EXEC | nx;
+                    (old & PTE_ADDR_MASK, merged & !PTE_ADDR_MASK)
+                }
+                None => (alloc_zeroed_frame().ok_or(ElfError::AllocFailed)?, pte_flags),
+            };
+            if !addr_space.map(vaddr, frame_phys, flags) {
                 return Err(ElfError::MappingFailed);
             }
 
             page_offset += PAGE_SIZE as u64;
         }
 
-        vm.add_vma(page_vaddr, page_end, vma_flags);
+        // A page shared with the previous segment already has a VMA
+        let mut vma_start = page_vaddr;
+        if vm.overlaps(page_vaddr, page_vaddr + PAGE_SIZE as u64) {
+            vm.widen(page_vaddr, page_vaddr + PAGE_SIZE as u64, vma_flags);
+            vma_start += PAGE_SIZE as u64;
+        }
+        if vma_start < page_end {
+            vm.add_vma(vma_start, page_end, vma_flags);
+        }
 
         let file_offset = phdr.p_offset as usize;
         let file_size = phdr.p_filesz as usize;
@@ -273,7 +291,7 @@ pub fn load_elf(
     }
 
     let brk = align_up(load_max + slide, PAGE_SIZE as u64);
-    vm.brk = brk;
+    vm.set_brk_base(brk);
 
     if phdr_vaddr == 0 && phoff != 0 {
         for phdr in phdrs {
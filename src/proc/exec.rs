@@ -1,7 +1,8 @@
+use crate::arch::x86_64::fpu::FpuState;
 use crate::arch::x86_64::gdt::{set_kernel_stack, SEG_USER_CODE, SEG_USER_DATA};
-use crate::arch::x86_64::idt::InterruptFrame;
+use crate::arch::x86_64::io::{wrmsr, MSR_FS_BASE};
 use crate::mm::pmm::PAGE_SIZE;
-use crate::mm::vmm::{AddressSpace, VmSpace, PTE_NO_EXEC, PTE_PRESENT, PTE_USER, PTE_WRITABLE};
+use crate::mm::vmm::{AddressSpace, Mm, VmSpace, PTE_NO_EXEC, PTE_PRESENT, PTE_USER, PTE_WRITABLE};
 use crate::proc::elf::{load_elf, ElfError, LoadedElf};
 use crate::proc::stack::{build_user_stack, UserStack, USER_STACK_TOP};
 use alloc::vec::Vec;
@@ -35,30 +36,8 @@ pub fn exec(
     let mut new_space = AddressSpace::new_user().ok_or(ExecError::NoMemory)?;
     let mut new_vm = VmSpace::new();
 
-    let pie_base = if is_pie(elf_data) { PIE_BASE } else { 0 };
-
-    let loaded =
-        load_elf(elf_data, &mut new_space, &mut new_vm, pie_base).map_err(ExecError::ElfError)?;
-
-    log::debug!("execve: main ELF loaded, entry={:#x}", loaded.entry);
-
-    let actual_entry;
-    let interp_loaded;
-
-    if let Some(ref interp_path) = loaded.interp_path {
-        let interp_data = load_file_from_initrd(interp_path).ok_or(ExecError::NotFound)?;
-
-        let il = load_elf(&interp_data, &mut new_space, &mut new_vm, INTERP_BASE)
-            .map_err(ExecError::ElfError)?;
-
-        log::debug!("execve: interpreter loaded, entry={:#x}", il.entry);
-
-        actual_entry = il.entry;
-        interp_loaded = Some(il);
-    } else {
-        actual_entry = loaded.entry;
-        interp_loaded = None;
-    }
+    let image = load_image(elf_data, &mut new_space, &mut new_vm)?;
+    let actual_entry = image.entry;
 
     let argv_refs: Vec<&[u8]> = argv.iter().map(|v| v.as_slice()).collect();
     let envp_refs: Vec<&[u8]> = envp.iter().map(|v| v.as_slice()).collect();
@@ -66,20 +45,38 @@ pub fn exec(
     let stack = build_user_stack(
         &mut new_space,
         &mut new_vm,
-        &loaded,
+        &image.main,
+        image.interp_base,
         &argv_refs,
         &envp_refs,
         path,
     )
     .ok_or(ExecError::StackError)?;
+    if !crate::proc::signal::map_trampoline(&mut new_space, &mut new_vm) {
+        return Err(ExecError::NoMemory);
+    }
 
     let proc_arc = crate::proc::scheduler::current_process().ok_or(ExecError::NoMemory)?;
 
-    {
+    // The new image starts single-threaded
+    let tgid = proc_arc.lock().tgid;
+    crate::proc::zap_other_threads(tgid, 0, 0);
+
+    let old_mm = {
         let mut proc = proc_arc.lock();
 
-        proc.address_space = new_space;
-        proc.vm = new_vm;
+        // The peak carries over into the new image, as on Linux
+        crate::proc::resource::sample_rss(&mut proc);
+        let old_mm = core::mem::replace(&mut proc.mm, Mm::new_shared(new_space, new_vm));
+        proc.signals.reset_for_exec();
+        proc.pending_signals &= !proc.signal_mask; // blocked ones survive exec
+        // The new image sets up its own TLS
+        proc.fs_base = 0;
+        unsafe { wrmsr(MSR_FS_BASE, 0) };
+        // And starts with a clean FPU (a kernel task exec'ing gets one)
+        let fpu = FpuState::new();
+        fpu.restore();
+        proc.fpu = Some(fpu);
 
         let name_len = path.len().min(31);
         proc.name = [0u8; 32];
@@ -87,13 +84,19 @@ pub fn exec(
 
         let kstack_top = proc.kernel_stack + proc.kernel_stack_size as u64;
         set_kernel_stack(kstack_top);
-    }
+        old_mm
+    };
 
-    proc_arc.lock().address_space.activate();
+    proc_arc.lock().mm.lock().space.activate();
+    // The old image can go only once CR3 has left its page tables
+    drop(old_mm);
+    // jump_to_user never returns, so nothing on this stack is dropped later
+    let pid = proc_arc.lock().pid;
+    drop(proc_arc);
 
     log::info!(
         "execve: pid={} entry={:#018x} rsp={:#018x}",
-        proc_arc.lock().pid,
+        pid,
         actual_entry,
         stack.initial_rsp
     );
@@ -140,6 +143,56 @@ unsafe extern "C" fn jump_to_user(entry: u64, user_rsp: u64, user_cs: u64, user_
     );
 }
 
+/// A program mapped into a fresh address space, ready for its user stack.
+pub struct Image {
+    pub main: LoadedElf,
+    /// Where the dynamic linker was loaded (AT_BASE), 0 for static binaries.
+    pub interp_base: u64,
+    /// First user instruction: the interpreter's entry if there is one.
+    pub entry: u64,
+}
+
+/// Map `elf_data` and, for dynamically linked programs, its PT_INTERP
+/// interpreter (read from the VFS) at INTERP_BASE.
+pub fn load_image(
+    elf_data: &[u8],
+    space: &mut AddressSpace,
+    vm: &mut VmSpace,
+) -> Result<Image, ExecError> {
+    let pie_base = if is_pie(elf_data) { PIE_BASE } else { 0 };
+    let main = load_elf(elf_data, space, vm, pie_base)?;
+
+    log::debug!("execve: main ELF loaded, entry={:#x}", main.entry);
+
+    let Some(ref interp_path) = main.interp_path else {
+        let entry = main.entry;
+        return Ok(Image { main, interp_base: 0, entry });
+    };
+
+    let interp_data = read_vfs_file(interp_path).ok_or_else(|| {
+        log::warn!(
+            "execve: interpreter {:?} not found",
+            core::str::from_utf8(interp_path).unwrap_or("?")
+        );
+        ExecError::NotFound
+    })?;
+    if !is_pie(&interp_data) {
+        return Err(ExecError::ElfError(ElfError::NotExecutable));
+    }
+
+    let interp = load_elf(&interp_data, space, vm, INTERP_BASE)?;
+    // The heap follows the program, not the interpreter
+    vm.set_brk_base(main.brk);
+
+    log::debug!("execve: interpreter loaded, entry={:#x}", interp.entry);
+
+    Ok(Image {
+        interp_base: interp.load_base,
+        entry: interp.entry,
+        main,
+    })
+}
+
 pub fn is_pie(data: &[u8]) -> bool {
     if data.len() < 18 {
         return false;
@@ -148,25 +201,23 @@ pub fn is_pie(data: &[u8]) -> bool {
     e_type == 3
 }
 
-pub fn load_file_from_initrd(path: &[u8]) -> Option<Vec<u8>> {
-    log::warn!(
-        "load_file_from_initrd: VFS not implemented, path={:?}",
-        path
-    );
-    None
-}
-
-pub fn sys_execve(pathname_ptr: u64, argv_ptr: u64, envp_ptr: u64, frame: &InterruptFrame) -> i64 {
+pub fn sys_execve(pathname_ptr: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
     use crate::syscall::errno::*;
 
     let proc_arc = match crate::proc::scheduler::current_process() {
         Some(p) => p,
         None => return -EINVAL,
     };
-
-    let path = match read_user_string(&proc_arc.lock().address_space, pathname_ptr, 4096) {
-        Some(s) => s,
-        None => return -EFAULT,
+    let mm = proc_arc.lock().mm.clone();
+    let strings = strncpy_from_user_mm(&mm, pathname_ptr, PATH_MAX).and_then(|path| {
+        let argv = read_user_string_array(&mm, argv_ptr, MAX_ARG_COUNT)?;
+        let envp = read_user_string_array(&mm, envp_ptr, MAX_ARG_COUNT)?;
+        Ok((path, argv, envp))
+    });
+    drop(mm);
+    let (path, argv, envp) = match strings {
+        Ok(s) => s,
+        Err(e) => return e.as_neg_i64(),
     };
 
     log::info!(
@@ -174,19 +225,9 @@ pub fn sys_execve(pathname_ptr: u64, argv_ptr: u64, envp_ptr: u64, frame: &Inter
         core::str::from_utf8(&path).unwrap_or("?")
     );
 
-    let argv = match read_user_string_array(&proc_arc.lock().address_space, argv_ptr, 256) {
-        Some(a) => a,
-        None => return -EFAULT,
-    };
-
-    let envp = match read_user_string_array(&proc_arc.lock().address_space, envp_ptr, 256) {
-        Some(e) => e,
-        None => return -EFAULT,
-    };
-
-    let elf_data = match lookup_and_read_file(&proc_arc.lock().address_space, &path) {
-        Some(d) => d,
-        None => return -ENOENT,
+    let elf_data = match lookup_and_read_file(&path, &envp) {
+        Ok(d) => d,
+        Err(e) => return -e.0,
     };
 
     match exec(&elf_data, &argv, &envp, &path) {
@@ -202,222 +243,92 @@ pub fn sys_execve(pathname_ptr: u64, argv_ptr: u64, envp_ptr: u64, frame: &Inter
     }
 }
 
-const ENAMETOOLONG: i64 = 36;
-
-fn read_user_string(space: &AddressSpace, ptr: u64, max_len: usize) -> Option<Vec<u8>> {
-    if ptr == 0 {
-        return None;
-    }
-
-    let mut result = Vec::new();
-    let mut addr = ptr;
-
-    loop {
-        if result.len() >= max_len {
-            return None;
-        }
-
-        let phys = space.translate(addr)?;
-        let byte = unsafe { *(phys_to_virt(phys) as *const u8) };
-
-        if byte == 0 {
-            break;
-        }
-        result.push(byte);
-        addr += 1;
-    }
-
-    result.push(0);
-    Some(result)
-}
+const PATH_MAX: usize = 4096;
+const MAX_ARG_COUNT: usize = 256;
+const MAX_ARG_STRLEN: usize = 65536;
 
+/// Copy a NULL-terminated array of string pointers (argv/envp) out of `mm`.
 fn read_user_string_array(
-    space: &AddressSpace,
+    mm: &SpinLock<Mm>,
     ptr: u64,
     max_count: usize,
-) -> Option<Vec<Vec<u8>>> {
+) -> Result<Vec<Vec<u8>>, Errno> {
+    let mut result = Vec::new();
     if ptr == 0 {
-        return Some(Vec::new());
+        return Ok(result);
     }
 
-    let mut result = Vec::new();
-    let mut addr = ptr;
-
     loop {
         if result.len() >= max_count {
-            return None;
+            return Err(Errno::E2BIG);
         }
-
-        let phys = space.translate(addr)?;
-        let str_ptr = unsafe { *(phys_to_virt(phys) as *const u64) };
-
+        let str_ptr: u64 = get_user_mm(mm, ptr + 8 * result.len() as u64)?;
         if str_ptr == 0 {
             break;
         }
-
-        let s = read_user_string(space, str_ptr, 65536)?;
-        result.push(s);
-        addr += 8;
+        result.push(strncpy_from_user_mm(mm, str_ptr, MAX_ARG_STRLEN)?);
     }
 
-    Some(result)
+    Ok(result)
 }
 
-fn lookup_and_read_file(_space: &AddressSpace, path: &[u8]) -> Option<Vec<u8>> {
-    // 1. Try VFS (ramfs + mounted filesystems)
-    let path_str = core::str::from_utf8(path).ok()?.trim_end_matches('\0');
-    if let Ok(data) = crate::fs::mount::with_vfs(|vfs| vfs.read_file(path_str)) {
-        return Some(data);
+/// Search list used when the caller's environment has no PATH.
+const DEFAULT_PATH: &str = "/bin:/usr/bin";
+
+/// Read the program to execute. A name with a '/' is taken as a path
+/// (relative ones against the cwd); a bare name is searched for in the
+/// PATH from `envp`.
+fn lookup_and_read_file(path: &[u8], envp: &[Vec<u8>]) -> Result<Vec<u8>, Errno> {
+    let name = core::str::from_utf8(path)
+        .map_err(|_| Errno::ENOENT)?
+        .trim_end_matches('\0');
+    if name.is_empty() {
+        return Err(Errno::ENOENT);
     }
-
-    // 2. Fallback: CPIO initrd (if present)
-    if let Some(initrd) = unsafe { INITRD } {
-        find_in_cpio(initrd, path)
-    } else {
-        None
+    if name.contains('/') {
+        return read_program(name);
     }
-}
-
-pub static mut INITRD: Option<&'static [u8]> = None;
 
-fn find_in_cpio(cpio: &[u8], path: &[u8]) -> Option<Vec<u8>> {
-    let mut offset = 0usize;
-    let path_str = core::str::from_utf8(path).ok()?.trim_start_matches('/');
-
-    while offset + 110 <= cpio.len() {
-        if &cpio[offset..offset + 6] != b"070701" && &cpio[offset..offset + 6] != b"070702" {
-            break;
-        }
-
-        let namesize = parse_hex8(&cpio[offset + 94..offset + 102])?;
-        let filesize = parse_hex8(&cpio[offset + 54..offset + 62])?;
-
-        let name_start = offset + 110;
-        let name_end = name_start + namesize as usize;
-        if name_end > cpio.len() {
-            break;
+    let search = envp
+        .iter()
+        .filter_map(|e| core::str::from_utf8(e).ok())
+        .find_map(|e| e.trim_end_matches('\0').strip_prefix("PATH="))
+        .unwrap_or(DEFAULT_PATH);
+
+    // Like execvp: a directory we may not use is remembered, but the
+    // search goes on; ENOENT only if nothing better turned up.
+    let mut err = Errno::ENOENT;
+    for dir in search.split(':') {
+        let dir = if dir.is_empty() { "." } else { dir };
+        match read_program(&alloc::format!("{}/{}", dir.trim_end_matches('/'), name)) {
+            Ok(data) => return Ok(data),
+            Err(Errno::ENOENT) | Err(Errno::ENOTDIR) => {}
+            Err(e) => err = e,
         }
-
-        let name = &cpio[name_start..name_end.saturating_sub(1)]; // без null
-        let name_str = core::str::from_utf8(name).unwrap_or("");
-
-        let data_start = align4(name_end);
-        let data_end = data_start + filesize as usize;
-
-        if name_str == "TRAILER!!!" {
-            break; // Конец архива
-        }
-
-        if name_str == path_str || name_str == path_str.trim_start_matches('/') {
-            if data_end <= cpio.len() {
-                return Some(cpio[data_start..data_end].to_vec());
-            }
-        }
-
-        offset = align4(data_end);
-    }
-
-    None
-}
-
-fn parse_hex8(bytes: &[u8]) -> Option<u32> {
-    if bytes.len() < 8 {
-        return None;
     }
-    let s = core::str::from_utf8(&bytes[..8]).ok()?;
-    u32::from_str_radix(s, 16).ok()
-}
-
-fn align4(v: usize) -> usize {
-    (v + 3) & !3
+    Err(err)
 }
 
-use crate::arch::x86_64::limine::phys_to_virt;
-use crate::syscall::errno::{EACCES, EFAULT, EINVAL, ENOENT};
-
-pub fn sys_execve_simple(pathname: u64, argv_ptr: u64, envp_ptr: u64) -> i64 {
-    use crate::syscall::errno::*;
-
-    let arc = match crate::proc::scheduler::current_process() {
-        Some(p) => p,
-        None => return -EINVAL,
-    };
-
-    let path = match read_user_string_from(&arc.lock().address_space, pathname, 4096) {
-        Some(s) => s,
-        None => return -EFAULT,
-    };
-
-    log::info!("execve({:?})", core::str::from_utf8(&path).unwrap_or("?"));
-
-    let argv = read_string_array(&arc.lock().address_space, argv_ptr, 256).unwrap_or_default();
-    let envp = read_string_array(&arc.lock().address_space, envp_ptr, 256).unwrap_or_default();
-
-    let elf_data = match lookup_and_read_file(&arc.lock().address_space, &path) {
-        Some(d) => d,
-        None => return -ENOENT,
-    };
-
-    match exec(&elf_data, &argv, &envp, &path) {
-        Ok(never) => never,
-        Err(ExecError::NotFound) => -ENOENT,
-        Err(ExecError::NoMemory) => -ENOMEM,
-        Err(ExecError::Permission) => -EACCES,
-        Err(e) => {
-            log::error!("exec failed: {:?}", e);
-            -EINVAL
+fn read_program(path: &str) -> Result<Vec<u8>, Errno> {
+    crate::fs::mount::with_vfs(|vfs| {
+        let st = vfs.stat(path)?;
+        if st.kind != FileType::Regular {
+            return Err(Errno::EACCES);
         }
-    }
+        perm::check(&st, &cred::current(), MAY_EXEC)?;
+        vfs.read_file(path)
+    })
 }
 
-fn read_user_string_from(
-    space: &crate::mm::vmm::AddressSpace,
-    ptr: u64,
-    max: usize,
-) -> Option<alloc::vec::Vec<u8>> {
-    if ptr == 0 {
-        return None;
-    }
-    let mut result = alloc::vec::Vec::new();
-    let mut addr = ptr;
-    loop {
-        if result.len() >= max {
-            return None;
-        }
-        let phys = space.translate(addr)?;
-        let byte = unsafe { *(phys_to_virt(phys) as *const u8) };
-        if byte == 0 {
-            break;
-        }
-        result.push(byte);
-        addr += 1;
-    }
-    result.push(0);
-    Some(result)
+/// Whole file at `path` (NUL-terminated or not). The initrd is unpacked
+/// into ramfs at boot, so the VFS sees everything.
+fn read_vfs_file(path: &[u8]) -> Option<Vec<u8>> {
+    let path_str = core::str::from_utf8(path).ok()?.trim_end_matches('\0');
+    read_program(path_str).ok()
 }
 
-fn read_string_array(
-    space: &crate::mm::vmm::AddressSpace,
-    ptr: u64,
-    max: usize,
-) -> Option<alloc::vec::Vec<alloc::vec::Vec<u8>>> {
-    if ptr == 0 {
-        return Some(alloc::vec::Vec::new());
-    }
-    let mut result = alloc::vec::Vec::new();
-    let mut addr = ptr;
-    loop {
-        if result.len() >= max {
-            return None;
-        }
-        let phys = space.translate(addr)?;
-        let str_ptr = unsafe { *(phys_to_virt(phys) as *const u64) };
-        if str_ptr == 0 {
-            break;
-        }
-        let s = read_user_string_from(space, str_ptr, 65536)?;
-        result.push(s);
-        addr += 8;
-    }
-    Some(result)
-}
+use crate::fs::perm::{self, MAY_EXEC};
+use crate::fs::{Errno, FileType};
+use crate::proc::cred;
+use crate::mm::uaccess::{get_user_mm, strncpy_from_user_mm};
+use crate::sync::spinlock::SpinLock;
@@ -1,18 +1,26 @@
+use crate::arch::x86_64::fpu::FpuState;
 use crate::arch::x86_64::io::invlpg;
+use crate::fs::mount::FsContext;
 use crate::arch::x86_64::limine::{phys_to_virt, virt_to_phys};
-use crate::mm::pmm::{align_up, alloc_zeroed_frame, free_frame, PAGE_SIZE};
+use crate::mm::pmm::{align_up, alloc_zeroed_frame, frame_share, free_frame, PAGE_SIZE};
 use crate::mm::vmm::{
-    AddressSpace, PageTable, VmSpace, VmaEntry, VmaFlags, PTE_ADDR_MASK, PTE_NO_EXEC, PTE_PRESENT,
-    PTE_USER, PTE_WRITABLE,
+    AddressSpace, Mm, PageTable, VmSpace, VmaEntry, VmaFlags, PTE_ADDR_MASK, PTE_NO_EXEC,
+    PTE_PRESENT, PTE_USER, PTE_WRITABLE,
 };
-use crate::proc::{alloc_pid, Process, ProcessState};
+use crate::mm::uaccess::{put_user, put_user_mm};
+use crate::proc::{alloc_pid, CpuContext, Process, ProcessState};
 use crate::sync::spinlock::SpinLock;
+use crate::sync::waitqueue::WaitQueue;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+/// Copy the user half of the page tables. Both sides end up mapping the
+/// same frames read-only (each shared frame gains a reference) and their
+/// writable VMAs are marked COPY_ON_WRITE, so the first write to a page
+/// breaks the sharing in `vmm::handle_page_fault`.
 pub fn clone_address_space(
     parent_space: &AddressSpace,
-    parent_vm: &VmSpace,
+    parent_vm: &mut VmSpace,
 ) -> Option<(AddressSpace, VmSpace)> {
     let child_pml4_phys = alloc_zeroed_frame()?;
 
@@ -107,6 +115,7 @@ fn clone_pt(parent_entry: u64) -> Option<u64> {
 
         let pte = parent_pt.entries[i];
         let cow_pte = if pte & PTE_USER != 0 {
+            frame_share(pte & PTE_ADDR_MASK);
             pte & !PTE_WRITABLE
         } else {
             pte
@@ -168,230 +177,342 @@ fn make_cow_pt(pt_phys: u64) {
     }
 }
 
-fn clone_vmspace(parent: &VmSpace) -> VmSpace {
+fn clone_vmspace(parent: &mut VmSpace) -> VmSpace {
     let mut child = VmSpace::new();
+    child.start_brk = parent.start_brk;
     child.brk = parent.brk;
+    child.stack_limit = parent.stack_limit;
 
-    for vma in &parent.areas {
-        let mut flags = vma.flags;
-        if flags.contains(VmaFlags::WRITE) && flags.contains(VmaFlags::ANONYMOUS) {
-            flags |= VmaFlags::COPY_ON_WRITE;
+    for vma in parent.areas.values_mut() {
+        if vma.flags.contains(VmaFlags::WRITE) && !vma.flags.contains(VmaFlags::SHARED) {
+            vma.flags |= VmaFlags::COPY_ON_WRITE;
         }
-        child.areas.push(VmaEntry {
-            start: vma.start,
-            end: vma.end,
-            flags,
-        });
     }
+    child.areas = parent.areas.clone();
 
     child
 }
 
-pub fn sys_fork(parent_frame: &crate::arch::x86_64::idt::InterruptFrame) -> i64 {
-    use crate::proc::scheduler;
-
-    let parent_arc = match scheduler::current_process() {
-        Some(p) => p,
-        None => return -crate::syscall::errno::EINVAL,
-    };
-
-    let child_pid = alloc_pid();
-
-    let (child_space, child_vm, child_context, child_stack, base_slice, priority, name) = {
-        let parent = parent_arc.lock();
+/// fork(): a clone that shares nothing. The child gets a copy of the
+/// caller's trap frame and returns 0 from the same syscall. vfork is
+/// treated the same way — with copy-on-write it only costs the page tables.
+pub fn sys_fork(frame: &crate::arch::x86_64::idt::InterruptFrame) -> i64 {
+    sys_clone(frame, 0, 0, 0, 0, 0)
+}
 
-        let (space, vm) = match clone_address_space(&parent.address_space, &parent.vm) {
-            Some(r) => r,
-            None => return -crate::syscall::errno::ENOMEM,
-        };
+pub const WNOHANG: u32 = 1;
+pub const WUNTRACED: u32 = 2;
+pub const WCONTINUED: u32 = 8;
+
+/// How a child changed state, as waitpid() reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    Exited(i32),
+    Signaled(u32),
+    Stopped(u32),
+    Continued,
+}
 
-        let mut ctx = parent.context.clone();
+impl WaitStatus {
+    /// The `*wstatus` encoding: the exit code in bits 8–15, the fatal
+    /// signal in the low 7 bits, 0x7f with the stop signal in bits 8–15,
+    /// or 0xffff for a resumed child.
+    pub fn raw(self) -> u32 {
+        match self {
+            WaitStatus::Exited(code) => ((code & 0xFF) as u32) << 8,
+            WaitStatus::Signaled(sig) => sig & 0x7F,
+            WaitStatus::Stopped(sig) => (sig & 0xFF) << 8 | 0x7F,
+            WaitStatus::Continued => 0xFFFF,
+        }
+    }
+}
 
-        use crate::arch::x86_64::limine::phys_to_virt;
-        use crate::mm::pmm::alloc_frames;
-        let kstack_phys = match alloc_frames(2) {
-            Some(p) => p,
-            None => return -crate::syscall::errno::ENOMEM,
-        };
-        let kstack_virt = phys_to_virt(kstack_phys);
-        let kstack_top = kstack_virt + Process::KERNEL_STACK_SIZE as u64;
-
-        ctx.rsp = kstack_top;
-        ctx.rip = fork_child_return as u64;
-
-        (
-            space,
-            vm,
-            ctx,
-            kstack_virt,
-            parent.base_slice,
-            parent.priority,
-            parent.name,
-        )
-    };
+/// Wait for a child of the current process to exit, with WUNTRACED to
+/// stop, or with WCONTINUED to be resumed by SIGCONT: child `pid`, any
+/// child for -1, any in the caller's process group for 0, or any in group
+/// -`pid` below that. An exited child is reaped. Ok(None) under WNOHANG
+/// when no child has changed state yet.
+pub fn wait_child(pid: i32, options: u32) -> Result<Option<(u32, WaitStatus)>, i64> {
+    use crate::syscall::errno::{ECHILD, EINTR, EINVAL};
 
-    let child = Process {
-        pid: child_pid,
-        ppid: parent_arc.lock().pid,
-        state: ProcessState::Runnable,
-        context: child_context,
-        address_space: child_space,
-        vm: child_vm,
-        kernel_stack: child_stack,
-        kernel_stack_size: Process::KERNEL_STACK_SIZE,
-        priority,
-        time_slice: base_slice,
-        base_slice,
-        exit_code: 0,
-        name,
-        pending_signals: 0,
-        signal_mask: 0,
+    let Some(wait) = crate::proc::current_process().map(|p| p.lock().child_wait.clone()) else {
+        return Err(EINVAL);
     };
-
-    let child_arc = Arc::new(SpinLock::new(child));
-
-    scheduler::spawn(child_arc);
-
-    log::info!(
-        "fork(): parent={} child={}",
-        parent_arc.lock().pid,
-        child_pid
-    );
-
-    child_pid as i64
-}
-
-#[unsafe(naked)]
-unsafe extern "C" fn fork_child_return() {
-    core::arch::naked_asm!(
-        "xor %rax, %rax",
-        "pop %r15",
-        "pop %r14",
-        "pop %r13",
-        "pop %r12",
-        "pop %rbp",
-        "pop %rbx",
-        "pop %rcx",
-        "pop %r11",
-        "mov %gs:16, %rsp",
-        "cli",
-        "swapgs",
-        "sysretq",
-        options(att_syntax)
-    );
+    // Exiting and stopping children wake this queue (proc::wake_parent)
+    let found = wait.wait_event(|| match find_child(pid, options) {
+        Search::Changed(child, status) => Some(Ok(Some((child, status)))),
+        Search::NoChild => Some(Err(ECHILD)),
+        Search::Unchanged if options & WNOHANG != 0 => Some(Ok(None)),
+        Search::Unchanged if crate::proc::signal::interrupted() => Some(Err(EINTR)),
+        Search::Unchanged => None,
+    })?;
+    if let Some((child, WaitStatus::Exited(_) | WaitStatus::Signaled(_))) = found {
+        reap_zombie(child);
+    }
+    Ok(found)
 }
 
 pub fn sys_waitpid(pid: i32, wstatus_ptr: u64, options: u32) -> i64 {
-    const WNOHANG: u32 = 1;
-
-    loop {
-        let found = find_zombie_child(pid);
-
-        if let Some((child_pid, exit_code)) = found {
+    match wait_child(pid, options) {
+        Ok(None) => 0,
+        Ok(Some((child, status))) => {
             if wstatus_ptr != 0 {
-                let wstatus = ((exit_code & 0xFF) as u32) << 8;
-                let phys = crate::proc::scheduler::current_process()
-                    .and_then(|p| p.lock().address_space.translate(wstatus_ptr));
-                if let Some(phys) = phys {
-                    unsafe {
-                        *(phys_to_virt(phys) as *mut u32) = wstatus;
-                    }
+                if let Err(e) = put_user(wstatus_ptr, &status.raw()) {
+                    return e.as_neg_i64();
                 }
             }
-
-            reap_zombie(child_pid);
-
-            return child_pid as i64;
-        }
-
-        if options & WNOHANG != 0 {
-            return 0;
+            child as i64
         }
-
-        crate::proc::scheduler::sleep_current();
+        Err(e) => -e,
     }
 }
 
-fn find_zombie_child(target_pid: i32) -> Option<(u32, i32)> {
+enum Search {
+    Changed(u32, WaitStatus),
+    Unchanged,
+    NoChild,
+}
+
+/// Look for a child matching `target_pid` with a state change to report.
+fn find_child(target_pid: i32, options: u32) -> Search {
     use crate::proc::scheduler::RUN_QUEUE;
     let rq = RUN_QUEUE.lock();
-    let current_pid = rq.current.as_ref()?.lock().pid;
+    let Some((current_tgid, current_pgid)) = rq.current.as_ref().map(|c| {
+        let c = c.lock();
+        (c.tgid, c.pgid)
+    }) else {
+        return Search::NoChild;
+    };
 
-    for proc_arc in &rq.queue {
-        let proc = proc_arc.lock();
-        if proc.ppid != current_pid {
+    let mut any = false;
+    for proc_arc in rq.tasks.values() {
+        let mut proc = proc_arc.lock();
+        // Threads (tgid != pid) are not waitable children
+        if proc.ppid != current_tgid || proc.tgid != proc.pid {
             continue;
         }
-        if target_pid != -1 && proc.pid != target_pid as u32 {
+        let wanted = match target_pid {
+            -1 => true,
+            0 => proc.pgid == current_pgid,
+            ..=-2 => proc.pgid == target_pid.unsigned_abs(),
+            pid => proc.pid == pid as u32,
+        };
+        if !wanted {
             continue;
         }
+        any = true;
         if proc.state == ProcessState::Zombie {
-            return Some((proc.pid, proc.exit_code));
+            let status = match proc.signals.term_signal {
+                0 => WaitStatus::Exited(proc.exit_code),
+                sig => WaitStatus::Signaled(sig),
+            };
+            return Search::Changed(proc.pid, status);
+        }
+        if options & WUNTRACED != 0 && proc.signals.stop_report != 0 {
+            let sig = core::mem::take(&mut proc.signals.stop_report);
+            return Search::Changed(proc.pid, WaitStatus::Stopped(sig));
         }
+        if options & WCONTINUED != 0 && core::mem::take(&mut proc.signals.cont_report) {
+            return Search::Changed(proc.pid, WaitStatus::Continued);
+        }
+    }
+    if any {
+        Search::Unchanged
+    } else {
+        Search::NoChild
     }
-    None
 }
 
+/// Free a reported child, charging what it used to the waiter.
 fn reap_zombie(pid: u32) {
     use crate::proc::scheduler::RUN_QUEUE;
     let mut rq = RUN_QUEUE.lock();
-    rq.queue.retain(|p| p.lock().pid != pid);
+    let Some(child) = rq.tasks.remove(&pid) else {
+        return;
+    };
+    let usage = super::resource::Usage::total(&child.lock());
+    if let Some(current) = rq.current.as_ref() {
+        current.lock().child_usage.add(&usage);
+    }
 }
 
-pub fn sys_fork_simple() -> i64 {
-    use crate::proc::scheduler;
-    use crate::sync::spinlock::SpinLock;
+// ─── clone() ─────────────────────────────────────────────────────────────────
+
+pub const CLONE_VM: u64 = 0x0000_0100;
+pub const CLONE_FS: u64 = 0x0000_0200;
+pub const CLONE_FILES: u64 = 0x0000_0400;
+pub const CLONE_SIGHAND: u64 = 0x0000_0800;
+pub const CLONE_VFORK: u64 = 0x0000_4000;
+pub const CLONE_THREAD: u64 = 0x0001_0000;
+pub const CLONE_SETTLS: u64 = 0x0008_0000;
+pub const CLONE_PARENT_SETTID: u64 = 0x0010_0000;
+pub const CLONE_CHILD_CLEARTID: u64 = 0x0020_0000;
+pub const CLONE_CHILD_SETTID: u64 = 0x0100_0000;
+
+fn put_user_u32(mm: &SpinLock<Mm>, addr: u64, val: u32) {
+    if addr != 0 {
+        let _ = put_user_mm(mm, addr, &val);
+    }
+}
 
-    let parent_arc = match scheduler::current_process() {
+/// clone(flags, newsp, parent_tid, child_tid, tls).
+///
+/// The child resumes from a copy of the caller's trap frame with RAX = 0,
+/// on `newsp` if one is given. CLONE_VM / CLONE_FILES / CLONE_FS share the
+/// caller's `Mm` / fd table / root and cwd instead of copying them;
+/// CLONE_THREAD puts the child in the caller's thread group. Signal
+/// handlers are always copied, so CLONE_SIGHAND is accepted but not truly
+/// shared. The child gets the caller's FS.base, or `tls` with CLONE_SETTLS.
+pub fn sys_clone(
+    frame: &crate::arch::x86_64::idt::InterruptFrame,
+    flags: u64,
+    newsp: u64,
+    parent_tid: u64,
+    child_tid: u64,
+    tls: u64,
+) -> i64 {
+    use crate::arch::x86_64::gdt::{SEG_KERNEL_CODE, SEG_KERNEL_DATA};
+    use crate::arch::x86_64::idt::InterruptFrame;
+    use crate::syscall::errno::{EINVAL, ENOMEM};
+
+    // Same constraints as Linux: a thread shares handlers, shared handlers
+    // need a shared address space
+    if flags & CLONE_THREAD != 0 && flags & CLONE_SIGHAND == 0 {
+        return -EINVAL;
+    }
+    if flags & CLONE_SIGHAND != 0 && flags & CLONE_VM == 0 {
+        return -EINVAL;
+    }
+
+    let parent_arc = match crate::proc::scheduler::current_process() {
         Some(p) => p,
-        None => return -crate::syscall::errno::EINVAL,
+        None => return -EINVAL,
+    };
+
+    let kstack_virt = match crate::mm::kstack::alloc() {
+        Some(s) => s,
+        None => return -ENOMEM,
+    };
+    let kstack_top = kstack_virt + Process::KERNEL_STACK_SIZE as u64;
+
+    // User-mode state the child starts from, popped by return_to_user
+    let mut uframe = frame.clone();
+    uframe.rax = 0;
+    if newsp != 0 {
+        uframe.rsp = newsp;
+    }
+    let kframe = kstack_top - core::mem::size_of::<InterruptFrame>() as u64;
+    unsafe { core::ptr::write(kframe as *mut InterruptFrame, uframe) };
+
+    let ctx = CpuContext {
+        rip: crate::proc::return_to_user as *const () as u64,
+        rsp: kframe,
+        rflags: 0x0202,
+        cs: SEG_KERNEL_CODE as u64,
+        ss: SEG_KERNEL_DATA as u64,
+        ..Default::default()
     };
 
     let child_pid = alloc_pid();
 
-    let result = {
+    // The TID writes may fault a file page in and sleep, so they happen
+    // without the process lock
+    let parent_mm = parent_arc.lock().mm.clone();
+    // Before the address space is copied, so a forked child sees it too
+    if flags & CLONE_PARENT_SETTID != 0 {
+        put_user_u32(&parent_mm, parent_tid, child_pid);
+    }
+
+    let mm = if flags & CLONE_VM != 0 {
+        parent_mm
+    } else {
+        let mut pm = parent_mm.lock();
+        let pm = &mut *pm;
+        match clone_address_space(&pm.space, &mut pm.vm) {
+            Some((space, vm)) => Mm::new_shared(space, vm),
+            None => {
+                crate::mm::kstack::free(kstack_virt);
+                return -ENOMEM;
+            }
+        }
+    };
+    // Into the child's memory: without CLONE_VM the write takes the
+    // child its private copy of the page, leaving ours alone
+    if flags & CLONE_CHILD_SETTID != 0 {
+        put_user_u32(&mm, child_tid, child_pid);
+    }
+
+    let child = {
         let parent = parent_arc.lock();
 
-        let (space, vm) = match clone_address_space(&parent.address_space, &parent.vm) {
-            Some(r) => r,
-            None => return -crate::syscall::errno::ENOMEM,
+        let files = if flags & CLONE_FILES != 0 {
+            parent.files.clone()
+        } else {
+            parent.copy_files()
         };
-
-        use crate::arch::x86_64::limine::phys_to_virt;
-        use crate::mm::pmm::alloc_frames;
-        let kstack_phys = match alloc_frames(2) {
-            Some(p) => p,
-            None => return -crate::syscall::errno::ENOMEM,
+        let fs = if flags & CLONE_FS != 0 {
+            parent.fs.clone()
+        } else {
+            FsContext::new_shared(parent.fs.lock().clone())
+        };
+        let (tgid, ppid) = if flags & CLONE_THREAD != 0 {
+            (parent.tgid, parent.ppid)
+        } else {
+            (child_pid, parent.tgid)
         };
-        let kstack_virt = phys_to_virt(kstack_phys);
-        let kstack_top = kstack_virt + Process::KERNEL_STACK_SIZE as u64;
-
-        let mut ctx = parent.context.clone();
-        ctx.rsp = kstack_top;
-        ctx.rip = fork_child_return as u64;
 
-        let child = Process {
+        Process {
             pid: child_pid,
-            ppid: parent.pid,
+            tgid,
+            ppid,
+            pgid: parent.pgid,
+            sid: parent.sid,
             state: ProcessState::Runnable,
             context: ctx,
-            address_space: space,
-            vm,
+            mm,
             kernel_stack: kstack_virt,
             kernel_stack_size: Process::KERNEL_STACK_SIZE,
             priority: parent.priority,
+            base_priority: parent.base_priority,
+            nice: parent.nice,
+            sleep_avg: parent.sleep_avg,
+            slept_at: 0,
             time_slice: parent.base_slice,
             base_slice: parent.base_slice,
             exit_code: 0,
             name: parent.name,
             pending_signals: 0,
             signal_mask: parent.signal_mask,
-        };
-
-        Arc::new(SpinLock::new(child))
+            signals: parent.signals.fork(),
+            files,
+            fs,
+            cred: parent.cred,
+            clear_child_tid: if flags & CLONE_CHILD_CLEARTID != 0 {
+                child_tid
+            } else {
+                0
+            },
+            child_wait: Arc::new(WaitQueue::new("wait")),
+            wchan: "",
+            utime: 0,
+            stime: 0,
+            start_time: 0,
+            limits: parent.limits,
+            max_rss: 0,
+            child_usage: super::resource::Usage::default(),
+            fs_base: if flags & CLONE_SETTLS != 0 { tls } else { crate::proc::current_fs_base() },
+            // The parent's registers are live: this runs on its syscall
+            fpu: parent.fpu.as_ref().map(|_| FpuState::current()),
+            trace: parent.trace,
+        }
     };
 
-    scheduler::spawn(result);
-    log::info!("fork() -> child pid={}", child_pid);
+    log::debug!(
+        "clone(): child={} tgid={} flags={:#x}",
+        child_pid,
+        child.tgid,
+        flags
+    );
+    crate::proc::scheduler::spawn(Arc::new(SpinLock::new(child)));
     child_pid as i64
 }
@@ -0,0 +1,234 @@
+//! futex(): user-space locks sleep here while the word they watch still
+//! holds the value they saw. Waiters are keyed by where the word is in
+//! memory: its physical address, plus the address space for a private
+//! futex (or any word outside a MAP_SHARED mapping), so two processes
+//! sharing a copy-on-write page don't wake each other. The page is
+//! faulted in writable first; breaking COW later would move the word.
+//!
+//! One FIFO holds every waiter, which keeps requeueing to another word a
+//! matter of changing a key. Wakers take entries off it, so a sleeper
+//! whose entry is gone knows it was woken, rather than timed out or
+//! interrupted by a signal. Thread exit wakes the CLONE_CHILD_CLEARTID
+//! word, which is what pthread_join sleeps on.
+use super::{current_process, signal, sleep_current, wake_up};
+use crate::arch::x86_64::io::{cli, sti, RFLAGS_IF};
+use crate::arch::x86_64::timer::{nanos, realtime};
+use crate::mm::uaccess::get_user;
+use crate::mm::vmm::{fault_in, VmaFlags, USER_SPACE_END};
+use crate::proc::timers;
+use crate::sync::spinlock::SpinLock;
+use crate::syscall::errno::{EAGAIN, EFAULT, EINTR, EINVAL, ENOSYS, ETIMEDOUT};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+pub const FUTEX_WAIT: u32 = 0;
+pub const FUTEX_WAKE: u32 = 1;
+pub const FUTEX_REQUEUE: u32 = 3;
+pub const FUTEX_CMP_REQUEUE: u32 = 4;
+pub const FUTEX_WAIT_BITSET: u32 = 9;
+pub const FUTEX_WAKE_BITSET: u32 = 10;
+pub const FUTEX_PRIVATE_FLAG: u32 = 128;
+pub const FUTEX_CLOCK_REALTIME: u32 = 256;
+
+const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// Where a futex word is: (address space or 0 if shared, physical address).
+type Key = (u64, u64);
+
+struct Waiter {
+    key: Key,
+    pid: u32,
+    bitset: u32,
+}
+
+static WAITERS: SpinLock<VecDeque<Waiter>> = SpinLock::new(VecDeque::new());
+
+/// The key of the word at `uaddr`, paging it in. EFAULT if it isn't
+/// mapped, EINVAL if it isn't aligned.
+fn key_of(uaddr: u64, private: bool) -> Result<Key, i64> {
+    if !uaddr.is_multiple_of(4) {
+        return Err(-EINVAL);
+    }
+    if uaddr == 0 || uaddr >= USER_SPACE_END {
+        return Err(-EFAULT);
+    }
+    let mm = current_process().ok_or(-EFAULT)?.lock().mm.clone();
+    // A read-only word can still be waited on, just never changed
+    if !fault_in(&mm, uaddr, true) && !fault_in(&mm, uaddr, false) {
+        return Err(-EFAULT);
+    }
+    let mm = mm.lock();
+    let phys = mm.space.translate(uaddr).ok_or(-EFAULT)?;
+    let shared = mm.vm.find_vma(uaddr).is_some_and(|v| v.flags.contains(VmaFlags::SHARED));
+    let space = if private || !shared { mm.space.pml4_phys } else { 0 };
+    Ok((space, phys))
+}
+
+fn queued(pid: u32) -> bool {
+    WAITERS.lock().iter().any(|w| w.pid == pid)
+}
+
+/// Sleep while the word at `uaddr` is `val`, until woken, `deadline`
+/// passes (ETIMEDOUT) or a signal comes (EINTR).
+fn wait(uaddr: u64, private: bool, val: u32, deadline: Option<u64>, bitset: u32) -> i64 {
+    if bitset == 0 {
+        return -EINVAL;
+    }
+    let key = match key_of(uaddr, private) {
+        Ok(k) => k,
+        Err(e) => return e,
+    };
+    let Some(proc) = current_process() else {
+        return -EFAULT;
+    };
+    let pid = proc.lock().pid;
+
+    // Interrupts stay off from reading the word until we are queued and
+    // asleep, so a waker can't change it and wake nobody in between
+    let rflags = cli();
+    let r = match get_user::<u32>(uaddr) {
+        Err(e) => e.as_neg_i64(),
+        Ok(v) if v != val => -EAGAIN,
+        Ok(_) => {
+            WAITERS.lock().push_back(Waiter { key, pid, bitset });
+            let timer = deadline.map(|d| timers::wake_at(d, pid));
+            let r = loop {
+                cli();
+                if !queued(pid) {
+                    break 0;
+                }
+                if deadline.is_some_and(|d| nanos() >= d) {
+                    break -ETIMEDOUT;
+                }
+                if signal::interrupted() {
+                    break -EINTR;
+                }
+                proc.lock().wchan = "futex";
+                sleep_current();
+                proc.lock().wchan = "";
+            };
+            WAITERS.lock().retain(|w| w.pid != pid);
+            if let Some(t) = timer {
+                timers::cancel(t);
+            }
+            r
+        }
+    };
+    if rflags & RFLAGS_IF != 0 {
+        sti();
+    }
+    r
+}
+
+/// Wake up to `n` waiters on `key` whose bitset meets `bitset`, oldest
+/// first, then move up to `requeue` more of them onto `to`. Returns how
+/// many were woken and moved.
+fn wake_key(key: Key, n: u32, bitset: u32, requeue: Option<(Key, u32)>) -> i64 {
+    let (woken, moved) = {
+        let mut waiters = WAITERS.lock();
+        let mut woken = Vec::new();
+        waiters.retain(|w| {
+            let take = w.key == key && w.bitset & bitset != 0 && woken.len() < n as usize;
+            if take {
+                woken.push(w.pid);
+            }
+            !take
+        });
+        let mut moved = 0;
+        if let Some((to, max)) = requeue {
+            for w in waiters.iter_mut().filter(|w| w.key == key) {
+                if moved == max {
+                    break;
+                }
+                w.key = to;
+                moved += 1;
+            }
+        }
+        (woken, moved)
+    };
+    for &pid in &woken {
+        wake_up(pid);
+    }
+    (woken.len() as u32 + moved) as i64
+}
+
+/// Wake `n` waiters on `uaddr` and move up to `max` more to `uaddr2`; for
+/// FUTEX_CMP_REQUEUE only if the word at `uaddr` is still `expect`.
+fn requeue(
+    uaddr: u64,
+    uaddr2: u64,
+    private: bool,
+    n: u32,
+    max: u32,
+    expect: Option<u32>,
+) -> Result<i64, i64> {
+    let (from, to) = (key_of(uaddr, private)?, key_of(uaddr2, private)?);
+    // As in wait(), nothing may change the word between the check and
+    // the wake-up
+    let rflags = cli();
+    let r = match expect.map(|e| (e, get_user::<u32>(uaddr))) {
+        Some((_, Err(e))) => Err(e.as_neg_i64()),
+        Some((e, Ok(v))) if v != e => Err(-EAGAIN),
+        _ => Ok(wake_key(from, n, FUTEX_BITSET_MATCH_ANY, Some((to, max)))),
+    };
+    if rflags & RFLAGS_IF != 0 {
+        sti();
+    }
+    r
+}
+
+/// Wake up to `n` waiters on the word at `uaddr` in the current address
+/// space, whether they wait on it as private or not; for thread exit.
+pub fn wake(uaddr: u64, n: u32) {
+    let (Ok(private), Ok(shared)) = (key_of(uaddr, true), key_of(uaddr, false)) else {
+        return;
+    };
+    wake_key(private, n, FUTEX_BITSET_MATCH_ANY, None);
+    if shared != private {
+        wake_key(shared, n, FUTEX_BITSET_MATCH_ANY, None);
+    }
+}
+
+/// A user timespec as a `nanos()` deadline: `timeout` from now, or at
+/// `timeout` on `realtime` or the monotonic clock.
+fn deadline(ptr: u64, absolute: bool, realtime_clock: bool) -> Result<Option<u64>, i64> {
+    if ptr == 0 {
+        return Ok(None);
+    }
+    let [sec, nsec] = get_user::<[i64; 2]>(ptr).map_err(|e| e.as_neg_i64())?;
+    if sec < 0 || !(0..1_000_000_000).contains(&nsec) {
+        return Err(-EINVAL);
+    }
+    let ns = (sec as u64).saturating_mul(1_000_000_000).saturating_add(nsec as u64);
+    Ok(Some(match (absolute, realtime_clock) {
+        (false, _) => nanos().saturating_add(ns),
+        (true, false) => ns,
+        (true, true) => ns.saturating_sub(realtime()).saturating_add(nanos()),
+    }))
+}
+
+/// futex(uaddr, op, val, timeout or val2, uaddr2, val3).
+pub fn sys_futex(uaddr: u64, op: u32, val: u32, timeout: u64, uaddr2: u64, val3: u32) -> i64 {
+    let private = op & FUTEX_PRIVATE_FLAG != 0;
+    let realtime_clock = op & FUTEX_CLOCK_REALTIME != 0;
+    let cmd = op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+    if realtime_clock && cmd != FUTEX_WAIT_BITSET {
+        return -ENOSYS;
+    }
+    let key = |addr| key_of(addr, private);
+    let r = match cmd {
+        FUTEX_WAIT => deadline(timeout, false, false)
+            .map(|d| wait(uaddr, private, val, d, FUTEX_BITSET_MATCH_ANY)),
+        FUTEX_WAIT_BITSET => {
+            deadline(timeout, true, realtime_clock).map(|d| wait(uaddr, private, val, d, val3))
+        }
+        FUTEX_WAKE => key(uaddr).map(|k| wake_key(k, val, FUTEX_BITSET_MATCH_ANY, None)),
+        FUTEX_WAKE_BITSET if val3 == 0 => Err(-EINVAL),
+        FUTEX_WAKE_BITSET => key(uaddr).map(|k| wake_key(k, val, val3, None)),
+        // The count to move comes in the timeout argument
+        FUTEX_REQUEUE => requeue(uaddr, uaddr2, private, val, timeout as u32, None),
+        FUTEX_CMP_REQUEUE => requeue(uaddr, uaddr2, private, val, timeout as u32, Some(val3)),
+        _ => Err(-ENOSYS),
+    };
+    r.unwrap_or_else(|e| e)
+}
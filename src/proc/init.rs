@@ -0,0 +1,86 @@
+//! PID 1: the first user process, started from the VFS at the end of boot
+//! from `init=` on the command line, else /sbin/init or /bin/init. Orphans
+//! are handed to it to reap, and, as on Linux, it only gets the signals it
+//! has handlers for: the default action would take the system with it.
+//!
+//! The kernel shell is the rescue. It runs on the console instead when
+//! there is no init to start or `rescue` is on the command line, and when
+//! init exits.
+use crate::fs::mount::with_vfs;
+use alloc::vec::Vec;
+
+pub const INIT_PID: u32 = 1;
+
+/// Tried in order when the command line names no init.
+const DEFAULT_INIT: [&str; 2] = ["/sbin/init", "/bin/init"];
+
+const INIT_ENV: [&str; 3] = ["PATH=/sbin:/bin", "HOME=/", "TERM=linux"];
+
+fn c_string(s: &str) -> Vec<u8> {
+    let mut v = s.as_bytes().to_vec();
+    v.push(0);
+    v
+}
+
+/// Start `path` as PID 1, on the console.
+fn start_init(path: &str) -> Result<(), &'static str> {
+    let elf = with_vfs(|vfs| vfs.read_file(path)).map_err(|_| "cannot read it")?;
+    if !crate::proc::elf::is_valid_elf(&elf) {
+        return Err("not a valid ELF64 binary");
+    }
+    let envp: Vec<Vec<u8>> = INIT_ENV.iter().map(|s| c_string(s)).collect();
+    let proc = crate::proc::Process::new_user(path, &elf, &[c_string(path)], &envp, 5)?;
+    {
+        // PID 1 is kept for it; the one it was given just goes unused
+        let mut p = proc.lock();
+        p.pid = INIT_PID;
+        p.tgid = INIT_PID;
+        p.pgid = INIT_PID;
+        p.sid = INIT_PID;
+    }
+    crate::drivers::tty::console().set_foreground(INIT_PID);
+    crate::proc::spawn(proc);
+    log::info!("init: started {} as PID {}", path, INIT_PID);
+    Ok(())
+}
+
+/// The end of boot: start init, or the kernel shell if there is none.
+pub fn start() {
+    let opts = crate::cmdline::options();
+    if opts.rescue {
+        rescue_shell("rescue on the command line");
+        return;
+    }
+    let paths: Vec<&str> = match opts.init {
+        Some(path) => alloc::vec![path],
+        None => DEFAULT_INIT.to_vec(),
+    };
+    for path in paths {
+        // The defaults may well not be there; a named init should be
+        let exists = with_vfs(|vfs| vfs.stat(path)).is_ok();
+        if !exists && opts.init.is_none() {
+            continue;
+        }
+        match start_init(path) {
+            Ok(()) => return,
+            Err(e) => log::error!("init: {}: {}", path, e),
+        }
+    }
+    rescue_shell("no init to start");
+}
+
+/// Run the kernel shell on the console, saying why.
+fn rescue_shell(why: &str) {
+    log::warn!("init: {}, starting the kernel shell", why);
+    crate::drivers::tty::console().set_foreground(0);
+    match crate::proc::Process::new_kernel("shell", crate::shell::spawn_shell, 5) {
+        Some(p) => crate::proc::spawn(p),
+        None => log::error!("init: no memory for the kernel shell"),
+    }
+}
+
+/// Init has exited: the console still needs someone on it.
+pub fn exited(code: i32) {
+    log::error!("init: PID {} exited with status {}", INIT_PID, code);
+    rescue_shell("init is gone");
+}
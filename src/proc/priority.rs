@@ -0,0 +1,143 @@
+//! Priorities: nice values, getpriority()/setpriority(), and the boost that
+//! keeps interactive tasks ahead of CPU hogs.
+//!
+//! The run queue serves `Process::priority` strictly, lowest first. That
+//! level is worked out again each time a task is queued: the one it was
+//! created with, moved by its nice value, less a bonus of up to
+//! ±MAX_BONUS from `sleep_avg`. Sleeping earns credit tick for tick and
+//! running spends it, so a shell waiting on the keyboard drifts above a
+//! loop that never sleeps, and back down if it turns into one. The idle
+//! task never moves.
+use super::cred::Cred;
+use super::{Process, RUN_QUEUE};
+use crate::arch::x86_64::timer::{ticks, TIMER_HZ};
+use crate::syscall::errno::{EACCES, EINVAL, EPERM, ESRCH};
+
+pub const PRIO_PROCESS: i32 = 0;
+pub const PRIO_PGRP: i32 = 1;
+pub const PRIO_USER: i32 = 2;
+
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+
+/// Most levels the bonus moves a task either way.
+const MAX_BONUS: i32 = 5;
+/// Credit is capped at a second of sleep; a task starts at half that.
+pub const MAX_SLEEP_AVG: u64 = TIMER_HZ;
+
+/// The level `p` is queued at now.
+pub fn effective(p: &Process) -> u8 {
+    if p.base_priority == u8::MAX {
+        return u8::MAX;
+    }
+    // -MAX_BONUS with no credit, 0 at half, +MAX_BONUS when full
+    let steps = p.sleep_avg * (2 * MAX_BONUS as u64 + 1) / (MAX_SLEEP_AVG + 1);
+    let level = p.base_priority as i32 + p.nice as i32 - (steps as i32 - MAX_BONUS);
+    // Nothing else sinks to the idle task's level
+    level.clamp(0, u8::MAX as i32 - 1) as u8
+}
+
+/// A tick on the CPU spends a tick of credit.
+pub fn charge(p: &mut Process) {
+    p.sleep_avg = p.sleep_avg.saturating_sub(1);
+}
+
+/// `p` is going to sleep.
+pub fn sleeping(p: &mut Process) {
+    p.slept_at = ticks();
+}
+
+/// `p` has woken up: it earns the ticks it slept.
+pub fn woken(p: &mut Process) {
+    let slept = ticks().saturating_sub(p.slept_at);
+    p.sleep_avg = (p.sleep_avg + slept).min(MAX_SLEEP_AVG);
+}
+
+// ─── getpriority / setpriority ───────────────────────────────────────────────
+
+/// The caller's process, group and user: what `who` 0 stands for.
+#[derive(Clone, Copy)]
+struct Caller {
+    tgid: u32,
+    pgid: u32,
+    cred: Cred,
+}
+
+fn caller() -> Option<Caller> {
+    let rq = RUN_QUEUE.lock();
+    let c = rq.current.as_ref()?.lock();
+    Some(Caller { tgid: c.tgid, pgid: c.pgid, cred: c.cred })
+}
+
+/// Whether `t` is among the tasks `which`/`who` name. EINVAL for an
+/// unknown `which`.
+fn selects(which: i32, who: u32, me: Caller, t: &Process) -> Result<bool, i64> {
+    let or_mine = |mine| if who == 0 { mine } else { who };
+    Ok(match which {
+        PRIO_PROCESS => t.tgid == or_mine(me.tgid),
+        PRIO_PGRP => t.pgid == or_mine(me.pgid),
+        PRIO_USER => t.cred.uid == or_mine(me.cred.uid),
+        _ => return Err(-EINVAL),
+    })
+}
+
+/// getpriority(): 20 minus the lowest nice value among the tasks named,
+/// so never negative; libc turns it back into a nice value.
+pub fn sys_getpriority(which: i32, who: u32) -> i64 {
+    let Some(me) = caller() else {
+        return -ESRCH;
+    };
+    let rq = RUN_QUEUE.lock();
+    let mut nice = None;
+    for t in rq.tasks.values() {
+        let t = t.lock();
+        match selects(which, who, me, &t) {
+            Ok(true) => nice = Some(nice.map_or(t.nice, |n: i8| n.min(t.nice))),
+            Ok(false) => {}
+            Err(e) => return e,
+        }
+    }
+    match nice {
+        Some(n) => 20 - n as i64,
+        None => -ESRCH,
+    }
+}
+
+/// setpriority(): give the tasks named nice value `nice`, clamped to
+/// -20..=19. Others' tasks need root, and so does lowering a nice value.
+/// Nothing changes unless every one of them may be changed.
+pub fn sys_setpriority(which: i32, who: u32, nice: i32) -> i64 {
+    let nice = nice.clamp(NICE_MIN as i32, NICE_MAX as i32) as i8;
+    let Some(me) = caller() else {
+        return -ESRCH;
+    };
+    let root = me.cred.is_root();
+    let rq = RUN_QUEUE.lock();
+    let mut targets = alloc::vec::Vec::new();
+    for t in rq.tasks.values() {
+        let task = t.lock();
+        match selects(which, who, me, &task) {
+            Ok(true) => {
+                if !root && task.cred.uid != me.cred.uid {
+                    return -EPERM;
+                }
+                if !root && nice < task.nice {
+                    return -EACCES;
+                }
+                targets.push(t.clone());
+            }
+            Ok(false) => {}
+            Err(e) => return e,
+        }
+    }
+    if targets.is_empty() {
+        return -ESRCH;
+    }
+    // A queued task moves when it is next queued
+    for t in targets {
+        let mut t = t.lock();
+        t.nice = nice;
+        t.priority = effective(&t);
+    }
+    0
+}
@@ -0,0 +1,220 @@
+//! Resource limits and usage: getrlimit/setrlimit/prlimit64 and getrusage.
+//!
+//! Every RLIMIT_* is kept, per task and copied by fork and clone, but only
+//! two are enforced: RLIMIT_NOFILE caps the fds `FdTable::alloc` hands
+//! out, and RLIMIT_AS the bytes of VMAs mmap() and brk() may leave a
+//! process with. Lowering either below what is in use takes nothing away;
+//! it only refuses more.
+//!
+//! Usage is the tick counts the timer charges (`tick`) and the most
+//! resident pages seen, counted from the VMAs' page tables when a task
+//! asks, execs or exits rather than kept up to date on every fault.
+use super::{Process, RUN_QUEUE};
+use crate::arch::x86_64::timer::TIMER_HZ;
+use crate::mm::uaccess::{get_user, put_user};
+use crate::syscall::errno::{EINVAL, EPERM, ESRCH};
+
+pub const RLIMIT_STACK: u32 = 3;
+pub const RLIMIT_NOFILE: u32 = 7;
+pub const RLIMIT_AS: u32 = 9;
+pub const RLIM_NLIMITS: usize = 16;
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+pub const RUSAGE_SELF: i32 = 0;
+pub const RUSAGE_CHILDREN: i32 = -1;
+pub const RUSAGE_THREAD: i32 = 1;
+
+/// `struct rlimit`: the soft limit enforced, and the hard one only root
+/// may raise it past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Rlimit {
+    pub cur: u64,
+    pub max: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits([Rlimit; RLIM_NLIMITS]);
+
+impl Limits {
+    pub const DEFAULT: Limits = {
+        let mut l = [Rlimit { cur: RLIM_INFINITY, max: RLIM_INFINITY }; RLIM_NLIMITS];
+        l[RLIMIT_STACK as usize].cur = crate::mm::vmm::DEFAULT_STACK_LIMIT;
+        let fds = super::FdTable::MAX_FDS as u64;
+        l[RLIMIT_NOFILE as usize] = Rlimit { cur: fds, max: fds };
+        Limits(l)
+    };
+
+    pub fn get(&self, resource: u32) -> Rlimit {
+        self.0[resource as usize]
+    }
+
+    /// The soft limit of `resource`.
+    pub fn cur(&self, resource: u32) -> u64 {
+        self.get(resource).cur
+    }
+}
+
+/// CPU ticks and peak resident pages, of a task or of the children it has
+/// reaped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Usage {
+    pub utime: u64,
+    pub stime: u64,
+    pub max_rss: u64,
+}
+
+impl Usage {
+    /// Fold in `other`: times add up, the peak is the larger one.
+    pub fn add(&mut self, other: &Usage) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.max_rss = self.max_rss.max(other.max_rss);
+    }
+
+    /// What `p` itself has used, without its children.
+    pub fn of(p: &Process) -> Usage {
+        Usage { utime: p.utime, stime: p.stime, max_rss: p.max_rss }
+    }
+
+    /// What `p` and every child it reaped have used, for its own parent.
+    pub fn total(p: &Process) -> Usage {
+        let mut u = Usage::of(p);
+        u.add(&p.child_usage);
+        u
+    }
+}
+
+/// Count the pages `p` has resident now into its peak.
+pub fn sample_rss(p: &mut Process) {
+    let resident = p.mm.lock().resident_pages();
+    p.max_rss = p.max_rss.max(resident);
+}
+
+// ─── Limits ──────────────────────────────────────────────────────────────────
+
+/// prlimit64(): read the limit of `resource` for process `pid` (0 for the
+/// caller) into `old`, then set it from `new`, either pointer may be 0.
+/// Root may change anyone's and raise a hard limit; others only lower
+/// their own, or move the soft limit up to the hard one.
+pub fn sys_prlimit64(pid: u32, resource: u32, new: u64, old: u64) -> i64 {
+    if resource as usize >= RLIM_NLIMITS {
+        return -EINVAL;
+    }
+    let new: Option<Rlimit> = match new {
+        0 => None,
+        ptr => match get_user(ptr) {
+            Ok(l) => Some(l),
+            Err(e) => return e.as_neg_i64(),
+        },
+    };
+    if new.is_some_and(|l| l.cur > l.max) {
+        return -EINVAL;
+    }
+    // An fd table never grows past MAX_FDS, so there is nothing to raise
+    let fds = super::FdTable::MAX_FDS as u64;
+    if resource == RLIMIT_NOFILE && new.is_some_and(|l| l.max > fds) {
+        return -EPERM;
+    }
+
+    let prev = {
+        let rq = RUN_QUEUE.lock();
+        let Some((me, cred)) = rq.current.as_ref().map(|c| {
+            let c = c.lock();
+            (c.tgid, c.cred)
+        }) else {
+            return -ESRCH;
+        };
+        let Some(target) = rq.find(if pid == 0 { me } else { pid }) else {
+            return -ESRCH;
+        };
+        let (prev, uid, tgid) = {
+            let t = target.lock();
+            (t.limits.get(resource), t.cred.uid, t.tgid)
+        };
+        if let Some(l) = new {
+            if !cred.is_root() && (uid != cred.uid || l.max > prev.max) {
+                return -EPERM;
+            }
+            for t in rq.tasks.values() {
+                let mut t = t.lock();
+                if t.tgid == tgid {
+                    t.limits.0[resource as usize] = l;
+                }
+            }
+        }
+        prev
+    };
+    if old != 0 {
+        if let Err(e) = put_user(old, &prev) {
+            return e.as_neg_i64();
+        }
+    }
+    0
+}
+
+pub fn sys_getrlimit(resource: u32, old: u64) -> i64 {
+    sys_prlimit64(0, resource, 0, old)
+}
+
+pub fn sys_setrlimit(resource: u32, new: u64) -> i64 {
+    sys_prlimit64(0, resource, new, 0)
+}
+
+// ─── Usage ───────────────────────────────────────────────────────────────────
+
+/// `struct rusage`: the two times as timevals, then longs of which only
+/// the first, the peak resident set in KiB, is kept.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Rusage {
+    utime: [i64; 2],
+    stime: [i64; 2],
+    maxrss: i64,
+    unkept: [i64; 13],
+}
+
+fn timeval(ticks: u64) -> [i64; 2] {
+    let us = ticks * 1_000_000 / TIMER_HZ;
+    [(us / 1_000_000) as i64, (us % 1_000_000) as i64]
+}
+
+/// getrusage(): the caller's whole process, its reaped children, or the
+/// calling thread alone.
+pub fn sys_getrusage(who: i32, ptr: u64) -> i64 {
+    if !matches!(who, RUSAGE_SELF | RUSAGE_CHILDREN | RUSAGE_THREAD) {
+        return -EINVAL;
+    }
+    let usage = {
+        let rq = RUN_QUEUE.lock();
+        let Some(current) = rq.current.as_ref() else {
+            return -ESRCH;
+        };
+        let (pid, tgid) = {
+            let mut c = current.lock();
+            sample_rss(&mut c);
+            (c.pid, c.tgid)
+        };
+        let mut usage = Usage::default();
+        for t in rq.tasks.values() {
+            let t = t.lock();
+            match who {
+                RUSAGE_SELF if t.tgid == tgid => usage.add(&Usage::of(&t)),
+                RUSAGE_CHILDREN if t.tgid == tgid => usage.add(&t.child_usage),
+                RUSAGE_THREAD if t.pid == pid => usage.add(&Usage::of(&t)),
+                _ => {}
+            }
+        }
+        usage
+    };
+    let ru = Rusage {
+        utime: timeval(usage.utime),
+        stime: timeval(usage.stime),
+        maxrss: (usage.max_rss * crate::mm::pmm::PAGE_SIZE as u64 / 1024) as i64,
+        unkept: [0; 13],
+    };
+    match put_user(ptr, &ru) {
+        Ok(()) => 0,
+        Err(e) => e.as_neg_i64(),
+    }
+}
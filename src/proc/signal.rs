@@ -0,0 +1,756 @@
+//! POSIX signals for user processes.
+//!
+//! `send`/`force` only mark a signal pending. `deliver_pending` runs on every
+//! return to ring 3 (SYSCALL exit and interrupt exit): it either applies the
+//! default disposition or rewrites the trap frame so the process resumes in
+//! its handler, with a Linux-compatible `rt_sigframe` pushed on the user
+//! stack. The handler returns through `sa_restorer` (or the kernel-provided
+//! trampoline page) into `rt_sigreturn`, which restores the saved frame.
+use crate::arch::x86_64::gdt::{SEG_USER_CODE, SEG_USER_DATA};
+use crate::arch::x86_64::idt::InterruptFrame;
+use crate::arch::x86_64::limine::phys_to_virt;
+use crate::mm::pmm::{alloc_zeroed_frame, frame_share, PAGE_SIZE};
+use crate::mm::uaccess::{get_user, put_user};
+use crate::mm::vmm::{AddressSpace, VmSpace, VmaFlags, PTE_PRESENT, PTE_USER};
+use crate::proc::{current_process, Process, ProcessState, RUN_QUEUE};
+use crate::sync::spinlock::SpinLock;
+use crate::syscall::errno::{EFAULT, EINVAL, EPERM, ESRCH};
+use core::mem::{offset_of, size_of};
+
+pub const NSIG: usize = 64;
+
+pub const SIGHUP: u32 = 1;
+pub const SIGINT: u32 = 2;
+pub const SIGQUIT: u32 = 3;
+pub const SIGILL: u32 = 4;
+pub const SIGTRAP: u32 = 5;
+pub const SIGABRT: u32 = 6;
+pub const SIGBUS: u32 = 7;
+pub const SIGFPE: u32 = 8;
+pub const SIGKILL: u32 = 9;
+pub const SIGUSR1: u32 = 10;
+pub const SIGSEGV: u32 = 11;
+pub const SIGUSR2: u32 = 12;
+pub const SIGPIPE: u32 = 13;
+pub const SIGALRM: u32 = 14;
+pub const SIGTERM: u32 = 15;
+pub const SIGCHLD: u32 = 17;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+pub const SIGTTIN: u32 = 21;
+pub const SIGTTOU: u32 = 22;
+pub const SIGURG: u32 = 23;
+pub const SIGWINCH: u32 = 28;
+
+/// Names without the "SIG", for `kill -TERM` and `kill -l`.
+pub const NAMES: &[(u32, &str)] = &[
+    (SIGHUP, "HUP"),
+    (SIGINT, "INT"),
+    (SIGQUIT, "QUIT"),
+    (SIGILL, "ILL"),
+    (SIGTRAP, "TRAP"),
+    (SIGABRT, "ABRT"),
+    (SIGBUS, "BUS"),
+    (SIGFPE, "FPE"),
+    (SIGKILL, "KILL"),
+    (SIGUSR1, "USR1"),
+    (SIGSEGV, "SEGV"),
+    (SIGUSR2, "USR2"),
+    (SIGPIPE, "PIPE"),
+    (SIGALRM, "ALRM"),
+    (SIGTERM, "TERM"),
+    (SIGCHLD, "CHLD"),
+    (SIGCONT, "CONT"),
+    (SIGSTOP, "STOP"),
+    (SIGTSTP, "TSTP"),
+    (SIGTTIN, "TTIN"),
+    (SIGTTOU, "TTOU"),
+    (SIGURG, "URG"),
+    (SIGWINCH, "WINCH"),
+];
+
+/// A signal by number ("9") or name, with or without "SIG" ("KILL",
+/// "SIGKILL").
+pub fn parse(s: &str) -> Option<u32> {
+    if let Ok(n) = s.parse::<u32>() {
+        return valid(n).then_some(n);
+    }
+    let name = s.strip_prefix("SIG").unwrap_or(s);
+    NAMES.iter().find(|(_, n)| n.eq_ignore_ascii_case(name)).map(|&(sig, _)| sig)
+}
+
+pub const SIG_DFL: u64 = 0;
+pub const SIG_IGN: u64 = 1;
+
+pub const SA_SIGINFO: u64 = 0x0000_0004;
+pub const SA_RESTORER: u64 = 0x0400_0000;
+pub const SA_NODEFER: u64 = 0x4000_0000;
+pub const SA_RESETHAND: u64 = 0x8000_0000;
+
+const SIG_BLOCK: u64 = 0;
+const SIG_UNBLOCK: u64 = 1;
+const SIG_SETMASK: u64 = 2;
+
+const SI_USER: i32 = 0;
+const SI_KERNEL: i32 = 0x80;
+
+pub const fn sig_bit(sig: u32) -> u64 {
+    1 << (sig - 1)
+}
+
+/// SIGKILL and SIGSTOP can be neither caught, ignored nor blocked.
+const UNBLOCKABLE: u64 = sig_bit(SIGKILL) | sig_bit(SIGSTOP);
+
+/// Signals whose default action stops the task.
+const STOP_SIGNALS: u64 = sig_bit(SIGSTOP) | sig_bit(SIGTSTP) | sig_bit(SIGTTIN) | sig_bit(SIGTTOU);
+
+/// Signals raised by CPU exceptions; their siginfo carries the fault address.
+const FAULT_SIGNALS: u64 =
+    sig_bit(SIGILL) | sig_bit(SIGTRAP) | sig_bit(SIGBUS) | sig_bit(SIGFPE) | sig_bit(SIGSEGV);
+
+/// `struct kernel_sigaction` as passed to rt_sigaction on x86_64.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SigAction {
+    pub handler: u64,
+    pub flags: u64,
+    pub restorer: u64,
+    pub mask: u64,
+}
+
+impl SigAction {
+    pub const DEFAULT: Self = Self {
+        handler: SIG_DFL,
+        flags: 0,
+        restorer: 0,
+        mask: 0,
+    };
+}
+
+/// Per-process signal dispositions (the pending set and mask live directly
+/// in `Process`).
+#[derive(Clone)]
+pub struct SignalState {
+    pub actions: [SigAction; NSIG],
+    /// Address reported in siginfo for the pending fault signal.
+    pub fault_addr: u64,
+    /// Thread group that sent each pending signal, reported as si_pid.
+    pub senders: [u32; NSIG],
+    /// Signal that terminated the process, 0 after a normal exit.
+    pub term_signal: u32,
+    /// Another thread called exit_group (or took a fatal signal); the
+    /// pending SIGKILL only has to reap this thread.
+    pub group_exit: bool,
+    /// Signal that stopped the task, until waitpid(WUNTRACED) reports it.
+    pub stop_report: u32,
+    /// SIGCONT resumed the task, until waitpid(WCONTINUED) reports it.
+    pub cont_report: bool,
+}
+
+impl SignalState {
+    pub const fn new() -> Self {
+        Self {
+            actions: [SigAction::DEFAULT; NSIG],
+            fault_addr: 0,
+            senders: [0; NSIG],
+            term_signal: 0,
+            group_exit: false,
+            stop_report: 0,
+            cont_report: false,
+        }
+    }
+
+    /// State inherited across fork: same dispositions, nothing pending.
+    pub fn fork(&self) -> Self {
+        Self {
+            actions: self.actions,
+            ..Self::new()
+        }
+    }
+
+    /// execve: caught signals revert to SIG_DFL, ignored ones stay ignored.
+    pub fn reset_for_exec(&mut self) {
+        for act in self.actions.iter_mut() {
+            if act.handler != SIG_IGN {
+                *act = SigAction::DEFAULT;
+            }
+        }
+    }
+}
+
+impl Default for SignalState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Default action is to ignore the signal. SIGCONT's real work (resuming
+/// a stopped task) is done when it is sent.
+fn default_ignored(sig: u32) -> bool {
+    matches!(sig, SIGCHLD | SIGCONT | SIGURG | SIGWINCH)
+}
+
+fn valid(sig: u32) -> bool {
+    sig >= 1 && sig as usize <= NSIG
+}
+
+// ─── Signal frame (matches Linux rt_sigframe) ───────────────────────────────
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SigContext {
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rdi: u64,
+    rsi: u64,
+    rbp: u64,
+    rbx: u64,
+    rdx: u64,
+    rax: u64,
+    rcx: u64,
+    rsp: u64,
+    rip: u64,
+    eflags: u64,
+    /// cs | gs << 16 | fs << 32 | ss << 48
+    csgsfs: u64,
+    err: u64,
+    trapno: u64,
+    oldmask: u64,
+    cr2: u64,
+    fpstate: u64,
+    reserved: [u64; 8],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct UContext {
+    flags: u64,
+    link: u64,
+    ss_sp: u64,
+    ss_flags: i32,
+    _pad: i32,
+    ss_size: u64,
+    mcontext: SigContext,
+    sigmask: u64,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    /// si_addr for faults, si_pid | si_uid << 32 otherwise
+    fields: [u64; 14],
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct SigFrame {
+    pretcode: u64,
+    uc: UContext,
+    info: SigInfo,
+}
+
+/// Bytes below the interrupted RSP the handler must not touch (SysV red zone).
+const RED_ZONE: u64 = 128;
+
+/// Flags user code may change through sigreturn: CF PF AF ZF SF TF DF OF AC.
+const USER_RFLAGS: u64 = 0x40DD5;
+const RFLAGS_TF_DF: u64 = 0x500;
+
+impl SigContext {
+    fn save(frame: &InterruptFrame, mask: u64, cr2: u64) -> Self {
+        Self {
+            r8: frame.r8,
+            r9: frame.r9,
+            r10: frame.r10,
+            r11: frame.r11,
+            r12: frame.r12,
+            r13: frame.r13,
+            r14: frame.r14,
+            r15: frame.r15,
+            rdi: frame.rdi,
+            rsi: frame.rsi,
+            rbp: frame.rbp,
+            rbx: frame.rbx,
+            rdx: frame.rdx,
+            rax: frame.rax,
+            rcx: frame.rcx,
+            rsp: frame.rsp,
+            rip: frame.rip,
+            eflags: frame.rflags,
+            csgsfs: (frame.cs & 0xFFFF) | (frame.ss & 0xFFFF) << 48,
+            err: frame.error_code,
+            trapno: frame.vector,
+            oldmask: mask,
+            cr2,
+            fpstate: 0,
+            reserved: [0; 8],
+        }
+    }
+
+    fn restore(&self, frame: &mut InterruptFrame) {
+        frame.r8 = self.r8;
+        frame.r9 = self.r9;
+        frame.r10 = self.r10;
+        frame.r11 = self.r11;
+        frame.r12 = self.r12;
+        frame.r13 = self.r13;
+        frame.r14 = self.r14;
+        frame.r15 = self.r15;
+        frame.rdi = self.rdi;
+        frame.rsi = self.rsi;
+        frame.rbp = self.rbp;
+        frame.rbx = self.rbx;
+        frame.rdx = self.rdx;
+        frame.rax = self.rax;
+        frame.rcx = self.rcx;
+        frame.rsp = self.rsp;
+        frame.rip = self.rip;
+        // Never let user code pick its privilege level or clear IF
+        frame.rflags = (self.eflags & USER_RFLAGS) | 0x202;
+        frame.cs = SEG_USER_CODE as u64;
+        frame.ss = SEG_USER_DATA as u64;
+    }
+}
+
+// ─── Trampoline ──────────────────────────────────────────────────────────────
+
+/// User address of the shared sigreturn trampoline, just above the stack.
+pub const SIGTRAMP_ADDR: u64 = 0x0000_7FFF_FFFF_E000;
+
+/// mov $15, %eax (SYS_RT_SIGRETURN); syscall; ud2
+const TRAMPOLINE_CODE: [u8; 9] = [0xB8, 0x0F, 0x00, 0x00, 0x00, 0x0F, 0x05, 0x0F, 0x0B];
+
+/// One read-only frame shared by every process; never freed.
+static TRAMPOLINE_PHYS: SpinLock<u64> = SpinLock::new(0);
+
+/// Map the sigreturn trampoline into a fresh user address space.
+pub fn map_trampoline(space: &mut AddressSpace, vm: &mut VmSpace) -> bool {
+    let phys = {
+        let mut tp = TRAMPOLINE_PHYS.lock();
+        if *tp == 0 {
+            let Some(frame) = alloc_zeroed_frame() else {
+                return false;
+            };
+            unsafe {
+                let dst = phys_to_virt(frame) as *mut u8;
+                core::ptr::copy_nonoverlapping(TRAMPOLINE_CODE.as_ptr(), dst, TRAMPOLINE_CODE.len());
+            }
+            *tp = frame;
+        }
+        *tp
+    };
+    // The page is never freed: TRAMPOLINE_PHYS keeps a reference of its
+    // own on top of one per address space mapping it
+    frame_share(phys);
+    vm.add_vma(
+        SIGTRAMP_ADDR,
+        SIGTRAMP_ADDR + PAGE_SIZE as u64,
+        VmaFlags::READ | VmaFlags::EXEC,
+    );
+    space.map(SIGTRAMP_ADDR, phys, PTE_PRESENT | PTE_USER)
+}
+
+// ─── Sending ─────────────────────────────────────────────────────────────────
+
+fn find_process(pid: u32) -> Option<alloc::sync::Arc<SpinLock<Process>>> {
+    RUN_QUEUE.lock().find(pid).cloned()
+}
+
+/// Queue `sig` for process `pid`, waking it if it sleeps. Signal 0 only
+/// checks that the target exists. SIGCONT resumes a stopped task and
+/// cancels a pending stop, and a stop signal cancels a pending SIGCONT;
+/// SIGKILL resumes a stopped task so it can die. Sent by the kernel, so
+/// the handler sees si_pid 0.
+pub fn send(pid: u32, sig: u32) -> Result<(), i64> {
+    send_from(0, pid, sig)
+}
+
+/// `send` on behalf of thread group `sender`, which siginfo reports.
+fn send_from(sender: u32, pid: u32, sig: u32) -> Result<(), i64> {
+    if sig != 0 && !valid(sig) {
+        return Err(EINVAL);
+    }
+    let target = find_process(pid).ok_or(ESRCH)?;
+    let mut p = target.lock();
+    if matches!(p.state, ProcessState::Zombie | ProcessState::Dead) {
+        return Err(ESRCH);
+    }
+    if !p.is_user() {
+        return Err(EPERM); // kernel threads never return to ring 3
+    }
+    if sig == 0 {
+        return Ok(());
+    }
+    // Init gets only what it has a handler for (not SIGKILL, then)
+    let caught = !matches!(p.signals.actions[sig as usize - 1].handler, SIG_DFL | SIG_IGN);
+    if p.tgid == crate::proc::init::INIT_PID && !caught {
+        return Ok(());
+    }
+    let bit = sig_bit(sig);
+    if bit & STOP_SIGNALS != 0 {
+        p.pending_signals &= !sig_bit(SIGCONT);
+    }
+    if sig == SIGCONT {
+        p.pending_signals &= !STOP_SIGNALS;
+        p.signals.stop_report = 0;
+    }
+    p.pending_signals |= bit;
+    p.signals.senders[sig as usize - 1] = sender;
+    drop(p);
+    let mut rq = RUN_QUEUE.lock();
+    let resumed = (sig == SIGCONT || sig == SIGKILL) && rq.resume(&target);
+    rq.wake(&target);
+    drop(rq);
+    if resumed && sig == SIGCONT {
+        let ppid = {
+            let mut p = target.lock();
+            p.signals.cont_report = true;
+            p.ppid
+        };
+        crate::proc::wake_parent(ppid);
+    }
+    Ok(())
+}
+
+/// `send` to every user task of process group `pgid`.
+pub fn send_group(pgid: u32, sig: u32) -> Result<(), i64> {
+    send_group_from(0, pgid, sig)
+}
+
+fn send_group_from(sender: u32, pgid: u32, sig: u32) -> Result<(), i64> {
+    if sig != 0 && !valid(sig) {
+        return Err(EINVAL);
+    }
+    let members: alloc::vec::Vec<u32> = RUN_QUEUE
+        .lock()
+        .tasks
+        .values()
+        .filter_map(|t| {
+            let t = t.lock();
+            (t.pgid == pgid && t.is_user()).then_some(t.pid)
+        })
+        .collect();
+    // Members exiting meanwhile don't matter as long as one was reached
+    let mut result = Err(ESRCH);
+    for pid in members {
+        if send_from(sender, pid, sig).is_ok() {
+            result = Ok(());
+        }
+    }
+    result
+}
+
+/// Queue `sig` for the current process (no-op for kernel threads).
+pub fn raise(sig: u32) {
+    if let Some(arc) = current_process() {
+        let mut p = arc.lock();
+        if p.is_user() {
+            p.pending_signals |= sig_bit(sig);
+            p.signals.senders[sig as usize - 1] = p.tgid;
+        }
+    }
+}
+
+/// Signal raised by a CPU exception in user mode. Like Linux force_sig: the
+/// signal is unblocked and an ignored disposition reverts to the default,
+/// since returning to the faulting instruction would just fault again.
+pub fn force(sig: u32, fault_addr: u64) {
+    let Some(arc) = current_process() else {
+        return;
+    };
+    let mut p = arc.lock();
+    let bit = sig_bit(sig);
+    let act = &mut p.signals.actions[sig as usize - 1];
+    if act.handler == SIG_IGN {
+        *act = SigAction::DEFAULT;
+    }
+    p.signal_mask &= !bit;
+    p.pending_signals |= bit;
+    p.signals.fault_addr = fault_addr;
+}
+
+/// True if the current process has a signal it would act on — blocking
+/// calls use this to bail out with EINTR.
+pub fn interrupted() -> bool {
+    match current_process() {
+        Some(arc) => {
+            let p = arc.lock();
+            p.pending_signals & (!p.signal_mask | UNBLOCKABLE) != 0
+        }
+        None => false,
+    }
+}
+
+// ─── Delivery ────────────────────────────────────────────────────────────────
+
+/// Default action of a stop signal: sleep as Stopped until SIGCONT or
+/// SIGKILL, letting the parent's waitpid(WUNTRACED) know. Only this task
+/// stops, not the rest of its thread group.
+fn stop(arc: &SpinLock<Process>, sig: u32) {
+    let ppid = {
+        let mut p = arc.lock();
+        p.state = ProcessState::Stopped;
+        p.signals.stop_report = sig;
+        p.signals.cont_report = false;
+        p.ppid
+    };
+    crate::proc::wake_parent(ppid);
+    crate::proc::schedule();
+}
+
+/// Default action of a fatal signal: the whole thread group dies.
+fn terminate(sig: u32) -> ! {
+    if let Some(arc) = current_process() {
+        let p = arc.lock();
+        if p.signals.group_exit {
+            let code = p.exit_code;
+            drop(p);
+            // Nothing may be left holding us in a frame that never returns
+            drop(arc);
+            crate::proc::exit_current(code);
+        }
+        log::info!("pid {} ({}) killed by signal {}", p.tgid, p.name_str(), sig);
+    }
+    crate::proc::exit_group(128 + sig as i32, sig)
+}
+
+/// Act on pending unblocked signals before returning to user mode.
+pub fn deliver_pending(frame: &mut InterruptFrame) {
+    if frame.cs & 3 != 3 {
+        return;
+    }
+    let Some(arc) = current_process() else {
+        return;
+    };
+    loop {
+        let (sig, act, old_mask, fault_addr, sender) = {
+            let mut p = arc.lock();
+            let ready = p.pending_signals & (!p.signal_mask | UNBLOCKABLE);
+            if ready == 0 {
+                return;
+            }
+            let sig = ready.trailing_zeros() + 1;
+            p.pending_signals &= !sig_bit(sig);
+            let fault_addr = if sig_bit(sig) & FAULT_SIGNALS != 0 {
+                core::mem::take(&mut p.signals.fault_addr)
+            } else {
+                0
+            };
+            let sender = p.signals.senders[sig as usize - 1];
+            (sig, p.signals.actions[sig as usize - 1], p.signal_mask, fault_addr, sender)
+        };
+
+        match act.handler {
+            SIG_IGN => continue,
+            SIG_DFL if default_ignored(sig) => continue,
+            SIG_DFL if sig_bit(sig) & STOP_SIGNALS != 0 => {
+                stop(&arc, sig);
+                continue;
+            }
+            SIG_DFL => {
+                drop(arc);
+                terminate(sig)
+            }
+            _ => {}
+        }
+
+        if !setup_frame(frame, sig, &act, old_mask, fault_addr, sender) {
+            drop(arc);
+            terminate(SIGSEGV);
+        }
+
+        let mut p = arc.lock();
+        let mut block = act.mask;
+        if act.flags & SA_NODEFER == 0 {
+            block |= sig_bit(sig);
+        }
+        p.signal_mask |= block & !UNBLOCKABLE;
+        if act.flags & SA_RESETHAND != 0 {
+            p.signals.actions[sig as usize - 1] = SigAction::DEFAULT;
+        }
+        return;
+    }
+}
+
+/// Push an rt_sigframe below the interrupted stack and point the frame at
+/// the handler. Returns false if the user stack can't hold it.
+fn setup_frame(
+    frame: &mut InterruptFrame,
+    sig: u32,
+    act: &SigAction,
+    old_mask: u64,
+    fault_addr: u64,
+    sender: u32,
+) -> bool {
+    let size = size_of::<SigFrame>() as u64;
+    // Handler entry sees RSP ≡ 8 (mod 16), as if `pretcode` was just pushed by a call
+    let sp = match frame.rsp.checked_sub(RED_ZONE + size) {
+        Some(sp) => (sp & !15) - 8,
+        None => return false,
+    };
+
+    let forced = sig_bit(sig) & FAULT_SIGNALS != 0 && fault_addr != 0;
+    let info = SigInfo {
+        signo: sig as i32,
+        errno: 0,
+        code: if forced { SI_KERNEL } else { SI_USER },
+        _pad: 0,
+        fields: {
+            let mut f = [0u64; 14];
+            f[0] = if forced { fault_addr } else { sender as u64 };
+            f
+        },
+    };
+    let restorer = if act.flags & SA_RESTORER != 0 && act.restorer != 0 {
+        act.restorer
+    } else {
+        SIGTRAMP_ADDR
+    };
+    let sf = SigFrame {
+        pretcode: restorer,
+        uc: UContext {
+            flags: 0,
+            link: 0,
+            ss_sp: 0,
+            ss_flags: 2, // SS_DISABLE: no alternate stack
+            _pad: 0,
+            ss_size: 0,
+            mcontext: SigContext::save(frame, old_mask, fault_addr),
+            sigmask: old_mask,
+        },
+        info,
+    };
+    // Missing stack pages are faulted in (or the stack grown) on the way
+    if put_user(sp, &sf).is_err() {
+        return false;
+    }
+
+    frame.rip = act.handler;
+    frame.rsp = sp;
+    frame.rdi = sig as u64;
+    frame.rsi = sp + offset_of!(SigFrame, info) as u64;
+    frame.rdx = sp + offset_of!(SigFrame, uc) as u64;
+    frame.rax = 0;
+    frame.rflags &= !RFLAGS_TF_DF;
+    true
+}
+
+// ─── Syscalls ────────────────────────────────────────────────────────────────
+
+/// rt_sigreturn: restore the context saved by `setup_frame`. The `ret` into
+/// the restorer popped `pretcode`, so the frame starts 8 bytes below RSP.
+pub fn sys_rt_sigreturn(frame: &mut InterruptFrame) {
+    let Some(arc) = current_process() else {
+        return;
+    };
+    let sp = frame.rsp.wrapping_sub(8);
+    let Ok(sf) = get_user::<SigFrame>(sp) else {
+        terminate(SIGSEGV);
+    };
+    // A non-canonical RIP would fault on iretq in kernel mode
+    if sf.uc.mcontext.rip >= SIGTRAMP_ADDR + PAGE_SIZE as u64 {
+        terminate(SIGSEGV);
+    }
+    sf.uc.mcontext.restore(frame);
+    arc.lock().signal_mask = sf.uc.sigmask & !UNBLOCKABLE;
+}
+
+pub fn sys_rt_sigaction(sig: u32, act: u64, oldact: u64, sigsetsize: usize) -> i64 {
+    if !valid(sig) || sigsetsize != size_of::<u64>() {
+        return -EINVAL;
+    }
+    if act != 0 && sig_bit(sig) & UNBLOCKABLE != 0 {
+        return -EINVAL;
+    }
+    let Some(arc) = current_process() else {
+        return -EINVAL;
+    };
+    let new = match act {
+        0 => None,
+        _ => match get_user::<SigAction>(act) {
+            Ok(a) => Some(a),
+            Err(_) => return -EFAULT,
+        },
+    };
+
+    let mut p = arc.lock();
+    let slot = sig as usize - 1;
+    let old = p.signals.actions[slot];
+    if let Some(mut new) = new {
+        new.mask &= !UNBLOCKABLE;
+        p.signals.actions[slot] = new;
+        // Setting SIG_IGN discards a pending instance (POSIX)
+        if new.handler == SIG_IGN || (new.handler == SIG_DFL && default_ignored(sig)) {
+            p.pending_signals &= !sig_bit(sig);
+        }
+    }
+    drop(p);
+    if oldact != 0 && put_user(oldact, &old).is_err() {
+        return -EFAULT;
+    }
+    0
+}
+
+pub fn sys_rt_sigprocmask(how: u64, set: u64, oldset: u64, sigsetsize: usize) -> i64 {
+    if sigsetsize != size_of::<u64>() {
+        return -EINVAL;
+    }
+    let Some(arc) = current_process() else {
+        return -EINVAL;
+    };
+    let bits = match set {
+        0 => None,
+        _ => match get_user::<u64>(set) {
+            Ok(b) => Some(b),
+            Err(_) => return -EFAULT,
+        },
+    };
+
+    let mut p = arc.lock();
+    let old = p.signal_mask;
+    if let Some(bits) = bits {
+        p.signal_mask = match how {
+            SIG_BLOCK => old | bits,
+            SIG_UNBLOCK => old & !bits,
+            SIG_SETMASK => bits,
+            _ => return -EINVAL,
+        } & !UNBLOCKABLE;
+    }
+    drop(p);
+    if oldset != 0 && put_user(oldset, &old).is_err() {
+        return -EFAULT;
+    }
+    0
+}
+
+/// kill(): `pid` > 0 is one process, 0 the caller's process group and
+/// -pgid that group. Broadcast (-1) isn't supported.
+pub fn sys_kill(pid: i32, sig: u32) -> i64 {
+    let Some((me, my_pgid)) = current_process().map(|p| {
+        let p = p.lock();
+        (p.tgid, p.pgid)
+    }) else {
+        return -ESRCH;
+    };
+    let result = match pid {
+        1.. => send_from(me, pid as u32, sig),
+        0 => send_group_from(me, my_pgid, sig),
+        -1 => Err(EINVAL),
+        _ => send_group_from(me, pid.unsigned_abs(), sig),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => -e,
+    }
+}
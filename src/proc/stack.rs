@@ -24,9 +24,12 @@ const AT_RANDOM: u64 = 25;
 const AT_EXECFN: u64 = 31;
 
 pub const USER_STACK_TOP: u64 = 0x0000_7FFF_FFFF_0000;
-pub const USER_STACK_SIZE: u64 = 8 * 1024 * 1024;
+pub const USER_STACK_SIZE: u64 = crate::mm::vmm::DEFAULT_STACK_LIMIT;
 pub const USER_STACK_BOTTOM: u64 = USER_STACK_TOP - USER_STACK_SIZE;
 
+/// Mapped up front; the rest of the stack grows on demand.
+const INITIAL_COMMIT: u64 = 64 * 1024;
+
 pub struct StackBuilder {
     kernel_ptr: u64,
     user_ptr: u64,
@@ -96,6 +99,7 @@ pub fn build_user_stack(
     addr_space: &mut AddressSpace,
     vm: &mut VmSpace,
     loaded: &LoadedElf,
+    interp_base: u64,
     argv: &[&[u8]],
     envp: &[&[u8]],
     execfn: &[u8],
@@ -175,7 +179,7 @@ pub fn build_user_stack(
     push!(0u64);
     push!(AT_FLAGS);
 
-    push!(loaded.load_base);
+    push!(interp_base);
     push!(AT_BASE);
 
     push!(PAGE_SIZE as u64);
@@ -217,14 +221,14 @@ pub fn build_user_stack(
 }
 
 fn map_user_stack(addr_space: &mut AddressSpace, vm: &mut VmSpace) -> Option<()> {
+    // The VMA starts out covering just the committed part; faults below it
+    // extend it down to the stack limit (see VmSpace::grow_stack)
     let stack_flags = VmaFlags::READ | VmaFlags::WRITE | VmaFlags::GROWS_DOWN | VmaFlags::ANONYMOUS;
-    vm.add_vma(USER_STACK_BOTTOM, USER_STACK_TOP, stack_flags);
+    let commit_start = USER_STACK_TOP - INITIAL_COMMIT;
+    vm.add_vma(commit_start, USER_STACK_TOP, stack_flags);
 
     let pte_flags = PTE_PRESENT | PTE_WRITABLE | PTE_USER | PTE_NO_EXEC;
 
-    let initial_committed = 64 * 1024u64;
-    let commit_start = USER_STACK_TOP - initial_committed;
-
     let mut vaddr = commit_start;
     while vaddr < USER_STACK_TOP {
         let phys = alloc_zeroed_frame()?;
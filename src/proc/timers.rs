@@ -0,0 +1,124 @@
+//! Kernel timers: tasks sleeping until a deadline, and functions called at
+//! one.
+//!
+//! Deadlines are in `timer::nanos()` time and kept in a sorted list that
+//! the timer IRQ trims from the front, waking every task whose deadline
+//! has passed. A sleeper checks the clock again once it runs, so waking it
+//! early (a signal, a stray `wake_up`) only costs another trip round. A
+//! callback goes to the workqueue rather than running in the IRQ; one that
+//! should repeat arms itself again.
+use super::workqueue::{schedule_work, WorkFn};
+use super::{current_process, signal, sleep_current};
+use crate::arch::x86_64::io::{cli, hlt, sti, RFLAGS_IF};
+use crate::arch::x86_64::timer::nanos;
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// What a timer does when its deadline comes.
+#[derive(Clone, Copy)]
+enum Action {
+    Wake(u32),
+    Call(WorkFn, u64),
+}
+
+/// (deadline, sequence number) → action; the sequence keeps equal
+/// deadlines apart and fires them in the order they were armed.
+static TIMERS: SpinLock<BTreeMap<(u64, u64), Action>> = SpinLock::new(BTreeMap::new());
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Called from the timer IRQ: fire the timers whose deadline is `now` or
+/// earlier.
+pub fn expire(now: u64) {
+    loop {
+        let action = {
+            let mut timers = TIMERS.lock();
+            match timers.first_key_value() {
+                Some((&(deadline, _), _)) if deadline <= now => timers.pop_first().unwrap().1,
+                _ => return,
+            }
+        };
+        match action {
+            Action::Wake(pid) => {
+                super::wake_up(pid);
+            }
+            Action::Call(func, arg) => {
+                schedule_work(func, arg);
+            }
+        }
+    }
+}
+
+/// Have kworker run `func(arg)` once `nanos()` reaches `deadline`.
+pub fn call_at(deadline: u64, func: WorkFn, arg: u64) {
+    let key = (deadline, NEXT_SEQ.fetch_add(1, Ordering::Relaxed));
+    TIMERS.lock().insert(key, Action::Call(func, arg));
+}
+
+/// Identifies an armed timer for `cancel`.
+pub type TimerKey = (u64, u64);
+
+/// Wake `pid` once `nanos()` reaches `deadline`, unless the timer is
+/// `cancel`led first.
+pub fn wake_at(deadline: u64, pid: u32) -> TimerKey {
+    let key = (deadline, NEXT_SEQ.fetch_add(1, Ordering::Relaxed));
+    TIMERS.lock().insert(key, Action::Wake(pid));
+    key
+}
+
+/// Disarm a timer that may or may not have fired yet.
+pub fn cancel(key: TimerKey) {
+    TIMERS.lock().remove(&key);
+}
+
+/// Earliest armed deadline, for programming a one-shot tick while idle.
+pub fn next_deadline() -> Option<u64> {
+    TIMERS.lock().first_key_value().map(|(&(deadline, _), _)| deadline)
+}
+
+/// Sleep until `nanos()` reaches `deadline`. False if a signal cut the
+/// sleep short.
+pub fn sleep_until(deadline: u64) -> bool {
+    let Some(proc) = current_process() else {
+        // Before the scheduler runs: wait for the ticks to get there
+        while nanos() < deadline {
+            sti();
+            hlt();
+        }
+        return true;
+    };
+    let pid = proc.lock().pid;
+    let key = (deadline, NEXT_SEQ.fetch_add(1, Ordering::Relaxed));
+
+    // Interrupts stay off from the clock check until we are asleep, so the
+    // IRQ can't fire the timer in between and leave us sleeping forever
+    let rflags = cli();
+    let done = loop {
+        cli();
+        if nanos() >= deadline {
+            break true;
+        }
+        if signal::interrupted() {
+            break false;
+        }
+        TIMERS.lock().insert(key, Action::Wake(pid));
+        proc.lock().wchan = "nanosleep";
+        sleep_current();
+        proc.lock().wchan = "";
+    };
+    TIMERS.lock().remove(&key);
+    if rflags & RFLAGS_IF != 0 {
+        sti();
+    }
+    done
+}
+
+/// Sleep for `ns` nanoseconds; on a signal, the time that was left.
+pub fn sleep_ns(ns: u64) -> Result<(), u64> {
+    let deadline = nanos().saturating_add(ns);
+    if sleep_until(deadline) {
+        Ok(())
+    } else {
+        Err(deadline.saturating_sub(nanos()))
+    }
+}
@@ -0,0 +1,135 @@
+//! Deferred work: IRQ handlers push `(fn, arg)` items onto a lock-free MPSC
+//! ring with `schedule_work`, and the `kworker` kernel thread runs them with
+//! interrupts enabled. Keeps the time spent inside handlers bounded.
+use crate::sync::waitqueue::WaitQueue;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub type WorkFn = fn(u64);
+
+const QUEUE_SIZE: usize = 256; // must be a power of two
+
+#[derive(Clone, Copy)]
+struct Work {
+    func: WorkFn,
+    arg: u64,
+}
+
+fn noop(_: u64) {}
+
+/// Slot stamp: `2 * lap` — empty and writable on that lap,
+/// `2 * lap + 1` — holds an item for the consumer on that lap.
+struct Slot {
+    stamp: AtomicUsize,
+    work: UnsafeCell<Work>,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            stamp: AtomicUsize::new(0),
+            work: UnsafeCell::new(Work { func: noop, arg: 0 }),
+        }
+    }
+}
+
+struct WorkQueue {
+    slots: [Slot; QUEUE_SIZE],
+    head: AtomicUsize, // consumer position (kworker only)
+    tail: AtomicUsize, // producer position
+}
+
+unsafe impl Sync for WorkQueue {}
+
+impl WorkQueue {
+    const fn new() -> Self {
+        Self {
+            slots: [const { Slot::new() }; QUEUE_SIZE],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    fn push(&self, work: Work) -> bool {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos % QUEUE_SIZE];
+            let want = 2 * (pos / QUEUE_SIZE);
+            let stamp = slot.stamp.load(Ordering::Acquire);
+            let diff = stamp as isize - want as isize;
+
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { *slot.work.get() = work };
+                        slot.stamp.store(want + 1, Ordering::Release);
+                        return true;
+                    }
+                    Err(cur) => pos = cur,
+                }
+            } else if diff < 0 {
+                // Slot still holds last lap's item: queue is full
+                return false;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<Work> {
+        let pos = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[pos % QUEUE_SIZE];
+        let lap = pos / QUEUE_SIZE;
+        if slot.stamp.load(Ordering::Acquire) != 2 * lap + 1 {
+            return None;
+        }
+        let work = unsafe { *slot.work.get() };
+        slot.stamp.store(2 * (lap + 1), Ordering::Release);
+        self.head.store(pos + 1, Ordering::Relaxed);
+        Some(work)
+    }
+
+    fn is_empty(&self) -> bool {
+        let pos = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[pos % QUEUE_SIZE];
+        slot.stamp.load(Ordering::Acquire) != 2 * (pos / QUEUE_SIZE) + 1
+    }
+}
+
+static QUEUE: WorkQueue = WorkQueue::new();
+static WORKER_WAIT: WaitQueue = WaitQueue::new("kworker");
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Queue `func(arg)` to run in the kworker thread. Safe from IRQ context.
+/// Returns false (and counts a drop) if the queue is full.
+pub fn schedule_work(func: WorkFn, arg: u64) -> bool {
+    if !QUEUE.push(Work { func, arg }) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return false;
+    }
+    WORKER_WAIT.wake_one();
+    true
+}
+
+pub fn dropped() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+fn worker_task() -> ! {
+    loop {
+        let w = WORKER_WAIT.wait_event(|| QUEUE.pop());
+        (w.func)(w.arg);
+    }
+}
+
+pub fn init() {
+    match crate::proc::Process::new_kernel("kworker", worker_task, 1) {
+        Some(p) => crate::proc::scheduler::spawn(p),
+        None => log::error!("workqueue: failed to spawn kworker"),
+    }
+}
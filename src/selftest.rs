@@ -0,0 +1,913 @@
+//! The kernel's self-tests, built in with `--features test`. Such a kernel
+//! boots as usual up to the scheduler, then runs this suite in place of the
+//! shell and leaves QEMU through the isa-debug-exit device: status 33 if
+//! every test passed, 35 if one failed or the kernel panicked.
+//!
+//! Each test is a plain function in `TESTS`, run in order from the
+//! `selftest` kernel task, so the scheduler tests can sleep and the syscall
+//! test can wait for the user process it starts.
+use crate::arch::x86_64::power::qemu_exit;
+use crate::arch::x86_64::timer::nanos;
+use crate::fs::mount::with_vfs;
+use crate::fs::vfs::Errno;
+use crate::proc::cred::Cred;
+use crate::proc::fork::{wait_child, WaitStatus, WNOHANG};
+use crate::proc::timers::sleep_ns;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+type TestResult = Result<(), &'static str>;
+type Test = (&'static str, fn() -> TestResult);
+
+const TESTS: &[Test] = &[
+    ("boot: command line options", boot_cmdline),
+    ("pmm: buddy allocator", pmm_buddy),
+    ("pmm: frames given back", pmm_balance),
+    ("heap: slab objects", heap_slabs),
+    ("heap: large blocks", heap_large),
+    ("heap: vmalloc", heap_vmalloc),
+    ("vfs: files and directories", vfs_files),
+    ("vfs: rename", vfs_rename),
+    ("vfs: mounts", vfs_mounts),
+    ("vfs: per-task cwd", vfs_cwd),
+    ("vfs: path resolution", vfs_paths),
+    ("vfs: permissions", vfs_perms),
+    ("vfs: timestamps", vfs_times),
+    ("vfs: tar and cpio archives", vfs_archives),
+    ("lib: md5 and sha256", lib_hashes),
+    ("sched: sleep", sched_sleep),
+    ("sched: kernel tasks", sched_tasks),
+    ("sched: exited tasks' stacks freed", sched_stacks_freed),
+    ("sched: nice and sleep credit", sched_priority),
+    ("syscall: user process", syscall_user),
+    ("syscall: table", syscall_table),
+    ("syscall: traced user process", syscall_traced),
+    ("mm: shared mapping written after fork", mm_shared_fork),
+    ("sched: CPU-bound user task preempted", sched_user_preempt),
+    ("proc: process groups and sessions", proc_groups),
+    ("proc: resource limits", proc_limits),
+    ("proc: futex arguments", proc_futex),
+    ("proc: FS.base across switches", proc_fs_base),
+    ("fpu: save and restore", fpu_state),
+];
+
+fn check(ok: bool, what: &'static str) -> TestResult {
+    if ok {
+        Ok(())
+    } else {
+        Err(what)
+    }
+}
+
+// ─── Boot ────────────────────────────────────────────────────────────────────
+
+fn boot_cmdline() -> TestResult {
+    use crate::cmdline::Options;
+    use log::LevelFilter;
+
+    let o = Options::parse("");
+    check(o.loglevel == LevelFilter::Trace && o.root.is_none(), "defaults")?;
+    let o = Options::parse("quiet root=/dev/hda1 noapic init=/sbin/init gdb loglevel=7");
+    check(o.root == Some("/dev/hda1") && o.init == Some("/sbin/init"), "root and init")?;
+    check(o.noapic && !o.nosmp && !o.rescue, "flags")?;
+    check(Options::parse("rescue").rescue, "rescue")?;
+    check(o.loglevel == LevelFilter::Info, "numeric loglevel")?;
+    let o = Options::parse("loglevel=debug nosmp root= loglevel=WARN");
+    check(o.loglevel == LevelFilter::Warn, "last loglevel by name wins")?;
+    check(o.nosmp && o.root.is_none(), "empty root= is no root")?;
+    check(Options::parse("loglevel=0").loglevel == LevelFilter::Off, "loglevel=0")?;
+    check(Options::parse("loglevel=4").loglevel == LevelFilter::Error, "loglevel=4")?;
+    Ok(())
+}
+
+// ─── Memory ──────────────────────────────────────────────────────────────────
+
+fn pmm_buddy() -> TestResult {
+    crate::mm::pmm::selftest()
+}
+
+fn pmm_balance() -> TestResult {
+    use crate::mm::pmm::{alloc_frame, alloc_frames, free_frame, free_frames, free_pages};
+
+    let mut frames = Vec::with_capacity(64);
+    let before = free_pages();
+    for _ in 0..64 {
+        frames.push(alloc_frame().ok_or("out of frames")?);
+    }
+    let block = alloc_frames(4).ok_or("no order-4 block")?;
+    check(block.is_multiple_of(16 * 4096), "order-4 block misaligned")?;
+    check(free_pages() + 64 + 16 <= before, "free count didn't drop")?;
+    frames.sort_unstable();
+    frames.dedup();
+    check(frames.len() == 64, "a frame was handed out twice")?;
+    for f in frames.drain(..) {
+        free_frame(f);
+    }
+    free_frames(block, 4);
+    check(free_pages() >= before, "frames leaked")
+}
+
+fn heap_slabs() -> TestResult {
+    let corruptions = crate::mm::heap::stats().corruptions;
+    let mut boxes: Vec<Box<[u8]>> = Vec::new();
+    for round in 0..4 {
+        for shift in 3..12 {
+            let size = (1usize << shift) - round;
+            let fill = (shift * 7 + round) as u8;
+            boxes.push(vec![fill; size].into_boxed_slice());
+        }
+    }
+    for (i, b) in boxes.iter().enumerate() {
+        let (round, shift) = (i / 9, i % 9 + 3);
+        let fill = (shift * 7 + round) as u8;
+        check(b.len() == (1 << shift) - round, "wrong length")?;
+        check(b.iter().all(|&x| x == fill), "contents overwritten")?;
+    }
+    // Free every other one, then fill the holes again
+    let mut i = 0;
+    boxes.retain(|_| {
+        i += 1;
+        i % 2 == 0
+    });
+    let refill: Vec<Box<[u8]>> = (0..boxes.len()).map(|_| vec![0xA5; 48].into()).collect();
+    check(refill.iter().all(|b| b.iter().all(|&x| x == 0xA5)), "refill clobbered")?;
+    drop(refill);
+    drop(boxes);
+    check(
+        crate::mm::heap::stats().corruptions == corruptions,
+        "heap corruption reported",
+    )
+}
+
+fn heap_large() -> TestResult {
+    let pages = crate::mm::heap::stats().large.pages;
+    let mut big = vec![0u8; 256 * 1024];
+    check(big.iter().all(|&x| x == 0), "large block not zeroed")?;
+    for (i, b) in big.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    check(
+        big.iter().enumerate().all(|(i, &b)| b == i as u8),
+        "large block contents",
+    )?;
+    drop(big);
+    check(
+        crate::mm::heap::stats().large.pages == pages,
+        "large block pages not given back",
+    )
+}
+
+fn heap_vmalloc() -> TestResult {
+    use crate::mm::vmalloc::{is_vmalloc_addr, usage, vfree, vmalloc};
+
+    let before = usage();
+    let size = 40 * 1024;
+    let p = vmalloc(size).ok_or("vmalloc failed")?;
+    check(is_vmalloc_addr(p as u64), "address outside the window")?;
+    let area = unsafe { core::slice::from_raw_parts_mut(p, size) };
+    area.fill(0x5A);
+    check(area.iter().all(|&b| b == 0x5A), "vmalloc area contents")?;
+    vfree(p);
+    check(usage() == before, "area not unmapped")
+}
+
+// ─── VFS ─────────────────────────────────────────────────────────────────────
+
+const DIR: &str = "/selftest";
+
+fn vfs_files() -> TestResult {
+    with_vfs(|vfs| {
+        vfs.mkdir(DIR).map_err(|_| "mkdir")?;
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        vfs.write_file("/selftest/a", &data).map_err(|_| "write_file")?;
+        let back = vfs.read_file("/selftest/a").map_err(|_| "read_file")?;
+        check(back == data, "read back different data")?;
+        let st = vfs.stat("/selftest/a").map_err(|_| "stat")?;
+        check(st.size == data.len() as u64, "stat size")?;
+
+        vfs.write_file("/selftest/a", b"short").map_err(|_| "rewrite")?;
+        let back = vfs.read_file("/selftest/a").map_err(|_| "read_file")?;
+        check(back == b"short", "O_TRUNC didn't truncate")?;
+
+        let names: Vec<_> = vfs
+            .readdir_all(DIR)
+            .map_err(|_| "readdir")?
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        check(names.iter().any(|n| n == "a"), "file missing from readdir")?;
+        check(vfs.rmdir(DIR).is_err(), "rmdir of a non-empty directory")?;
+
+        vfs.unlink("/selftest/a").map_err(|_| "unlink")?;
+        check(vfs.stat("/selftest/a").is_err(), "file still there")?;
+        vfs.rmdir(DIR).map_err(|_| "rmdir")?;
+        check(vfs.stat(DIR).is_err(), "directory still there")
+    })
+}
+
+fn vfs_rename() -> TestResult {
+    with_vfs(|vfs| {
+        vfs.mkdir(DIR).map_err(|_| "mkdir")?;
+        vfs.mkdir("/selftest/sub").map_err(|_| "mkdir sub")?;
+        vfs.write_file("/selftest/x", b"moved").map_err(|_| "write_file")?;
+        vfs.rename("/selftest/x", "/selftest/sub/y").map_err(|_| "rename")?;
+        check(vfs.stat("/selftest/x").is_err(), "old name still there")?;
+        let back = vfs.read_file("/selftest/sub/y").map_err(|_| "read_file")?;
+        check(back == b"moved", "contents after rename")?;
+        vfs.unlink("/selftest/sub/y").map_err(|_| "unlink")?;
+        vfs.rmdir("/selftest/sub").map_err(|_| "rmdir sub")?;
+        vfs.rmdir(DIR).map_err(|_| "rmdir")
+    })
+}
+
+fn vfs_mounts() -> TestResult {
+    with_vfs(|vfs| {
+        vfs.mkdir(DIR).map_err(|_| "mkdir")?;
+        vfs.mkdir("/selftest/src").map_err(|_| "mkdir src")?;
+        vfs.mkdir("/selftest/dst").map_err(|_| "mkdir dst")?;
+        vfs.write_file("/selftest/src/f", b"bound").map_err(|_| "write_file")?;
+        vfs.mount_bind("/selftest/src", "/selftest/dst").map_err(|_| "mount_bind")?;
+
+        let back = vfs.read_file("/selftest/dst/f").map_err(|_| "read through mount")?;
+        check(back == b"bound", "contents through mount")?;
+        let src = vfs.stat("/selftest/src").map_err(|_| "stat src")?.ino;
+        let listed = vfs.readdir_all(DIR).map_err(|_| "readdir")?;
+        check(
+            listed.iter().any(|e| e.name == "dst" && e.ino == src),
+            "mount point not listed as the mounted root",
+        )?;
+        vfs.cd("/selftest/dst").map_err(|_| "cd into mount")?;
+        vfs.cd("..").map_err(|_| "cd ..")?;
+        check(vfs.cwd_path() == DIR, "`..` out of a mount")?;
+        vfs.cd("/").map_err(|_| "cd /")?;
+        check(vfs.rmdir("/selftest/dst").is_err(), "removed a mount point")?;
+
+        vfs.umount("/selftest/dst").map_err(|_| "umount")?;
+        check(vfs.stat("/selftest/dst/f").is_err(), "mount still there after umount")?;
+        vfs.unlink("/selftest/src/f").map_err(|_| "unlink")?;
+        vfs.rmdir("/selftest/src").map_err(|_| "rmdir src")?;
+        vfs.rmdir("/selftest/dst").map_err(|_| "rmdir dst")?;
+        vfs.rmdir(DIR).map_err(|_| "rmdir")
+    })
+}
+
+/// 0 until `cwd_worker` has run, then 1 if it saw its own cd, 2 if not.
+static CWD_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+fn cwd_worker() -> ! {
+    let ok = with_vfs(|vfs| vfs.cd(DIR).is_ok() && vfs.cwd_path() == DIR);
+    CWD_SEEN.store(if ok { 1 } else { 2 }, Ordering::SeqCst);
+    crate::proc::exit_current(0);
+}
+
+fn vfs_cwd() -> TestResult {
+    with_vfs(|vfs| vfs.mkdir(DIR)).map_err(|_| "mkdir")?;
+    CWD_SEEN.store(0, Ordering::SeqCst);
+    let p = crate::proc::Process::new_kernel("selftest-cwd", cwd_worker, 5).ok_or("new_kernel")?;
+    crate::proc::scheduler::spawn(p);
+    let deadline = nanos() + 2_000_000_000;
+    while CWD_SEEN.load(Ordering::SeqCst) == 0 {
+        check(nanos() < deadline, "worker didn't run")?;
+        let _ = sleep_ns(1_000_000);
+    }
+    check(CWD_SEEN.load(Ordering::SeqCst) == 1, "cd in the worker")?;
+    check(with_vfs(|vfs| vfs.cwd_path()) == "/", "the worker's cd moved ours")?;
+    with_vfs(|vfs| vfs.rmdir(DIR)).map_err(|_| "rmdir")
+}
+
+fn vfs_paths() -> TestResult {
+    use crate::fs::path::normalize;
+    check(normalize("/a/./b/../c/") == "/a/c", "normalize absolute")?;
+    check(normalize("/../..") == "/", "normalize above the root")?;
+    check(normalize("../a/..//b") == "../b", "normalize relative")?;
+    with_vfs(|vfs| {
+        vfs.mkdir(DIR).map_err(|_| "mkdir")?;
+        vfs.mkdir("/selftest/d").map_err(|_| "mkdir d")?;
+        vfs.write_file("/selftest/f", b"x").map_err(|_| "write_file")?;
+        vfs.symlink("d", "/selftest/l").map_err(|_| "symlink")?;
+        vfs.symlink("loop", "/selftest/loop").map_err(|_| "symlink loop")?;
+
+        check(vfs.stat("/selftest/./d/../f").is_ok(), "`.` and `..` in a path")?;
+        // ".." after a link is taken from where the link led
+        check(vfs.stat("/selftest/l/../f").is_ok(), "`..` after a symlink")?;
+        check(vfs.stat("/selftest/loop").err() == Some(Errno::ELOOP), "symlink loop")?;
+        check(vfs.stat("/selftest/f/").err() == Some(Errno::ENOTDIR), "trailing slash")?;
+        check(vfs.stat("").err() == Some(Errno::ENOENT), "empty path")?;
+        check(vfs.mkdir("/selftest/d/..").err() == Some(Errno::EEXIST), "mkdir ..")?;
+        check(vfs.rmdir("/selftest/d/.").err() == Some(Errno::EINVAL), "rmdir .")?;
+        check(vfs.unlink("/selftest/f/").is_err(), "unlink with a trailing slash")?;
+        vfs.cd("/selftest/l").map_err(|_| "cd through symlink")?;
+        check(vfs.cwd_path() == "/selftest/d", "cwd through symlink")?;
+        vfs.cd("../..").map_err(|_| "cd ../..")?;
+        check(vfs.cwd_path() == "/", "cd ../..")?;
+
+        vfs.unlink("/selftest/loop").map_err(|_| "unlink loop")?;
+        vfs.unlink("/selftest/l").map_err(|_| "unlink l")?;
+        vfs.unlink("/selftest/f").map_err(|_| "unlink f")?;
+        vfs.rmdir("/selftest/d").map_err(|_| "rmdir d")?;
+        vfs.rmdir(DIR).map_err(|_| "rmdir")
+    })
+}
+
+/// Run `f` as `cred`, then as root again.
+fn as_user<R>(cred: Cred, f: impl FnOnce() -> R) -> R {
+    let me = crate::proc::current_process().expect("no current task");
+    me.lock().cred = cred;
+    let r = f();
+    me.lock().cred = Cred::ROOT;
+    r
+}
+
+fn vfs_perms() -> TestResult {
+    const USER: Cred = Cred { uid: 1000, gid: 1000 };
+    const F: &str = "/selftest/f";
+    with_vfs(|vfs| {
+        vfs.mkdir(DIR).map_err(|_| "mkdir")?;
+        vfs.write_file(F, b"secret").map_err(|_| "write_file")?;
+        vfs.chmod(F, 0o600).map_err(|_| "chmod")?;
+
+        let denied = as_user(USER, || vfs.read_file(F).err());
+        check(denied == Some(Errno::EACCES), "read a 0600 file of root's")?;
+        let denied = as_user(USER, || vfs.chmod(F, 0o644).err());
+        check(denied == Some(Errno::EPERM), "chmod another's file")?;
+        let denied = as_user(USER, || vfs.mkdir("/selftest/d").err());
+        check(denied == Some(Errno::EACCES), "mkdir in root's 0755 directory")?;
+
+        vfs.chown(F, Some(USER.uid), Some(USER.gid), true).map_err(|_| "chown")?;
+        as_user(USER, || vfs.read_file(F)).map_err(|_| "read own file")?;
+        let denied = as_user(USER, || vfs.chown(F, Some(0), None, true).err());
+        check(denied == Some(Errno::EPERM), "give a file away")?;
+
+        // Sticky and world-writable, like /tmp: others' files stay put
+        vfs.chmod(DIR, 0o1777).map_err(|_| "chmod sticky")?;
+        vfs.write_file("/selftest/root", b"").map_err(|_| "write root's")?;
+        let denied = as_user(USER, || vfs.unlink("/selftest/root").err());
+        check(denied == Some(Errno::EPERM), "unlink another's file in a sticky dir")?;
+        as_user(USER, || vfs.unlink(F)).map_err(|_| "unlink own file in a sticky dir")?;
+
+        vfs.unlink("/selftest/root").map_err(|_| "unlink")?;
+        vfs.rmdir(DIR).map_err(|_| "rmdir")
+    })
+}
+
+fn vfs_times() -> TestResult {
+    use crate::fs::vfs::{TimeSet, Timespec};
+    const USER: Cred = Cred { uid: 1000, gid: 1000 };
+    const F: &str = "/selftest/f";
+    let old = Timespec { sec: 1_000_000_000, nsec: 500 };
+    with_vfs(|vfs| {
+        vfs.mkdir(DIR).map_err(|_| "mkdir")?;
+        vfs.write_file(F, b"x").map_err(|_| "write_file")?;
+        let st = vfs.stat(F).map_err(|_| "stat")?;
+        check(st.mtime == st.ctime && st.mtime.sec > old.sec, "times of a new file")?;
+
+        vfs.utimens_at(None, F, TimeSet::At(old), TimeSet::At(old), true)
+            .map_err(|_| "utimens")?;
+        let st = vfs.stat(F).map_err(|_| "stat")?;
+        check(st.atime == old && st.mtime == old, "explicit times")?;
+        check(st.ctime > old, "utimens changes ctime")?;
+        vfs.write_file(F, b"y").map_err(|_| "write_file again")?;
+        check(vfs.stat(F).map_err(|_| "stat")?.mtime > old, "a write moves mtime")?;
+
+        // Anyone who may write may touch; only the owner sets a time
+        vfs.chmod(F, 0o666).map_err(|_| "chmod")?;
+        let at = as_user(USER, || vfs.utimens_at(None, F, TimeSet::At(old), TimeSet::Omit, true));
+        check(at.err() == Some(Errno::EPERM), "explicit time on another's file")?;
+        as_user(USER, || vfs.utimens_at(None, F, TimeSet::Now, TimeSet::Now, true))
+            .map_err(|_| "touch a writable file")?;
+
+        vfs.unlink(F).map_err(|_| "unlink")?;
+        vfs.rmdir(DIR).map_err(|_| "rmdir")
+    })
+}
+
+fn vfs_archives() -> TestResult {
+    use crate::fs::archive::{self, Format};
+    with_vfs(|vfs| {
+        vfs.mkdir_p("/selftest/src/sub").map_err(|_| "mkdir_p")?;
+        vfs.write_file("/selftest/src/a", b"alpha").map_err(|_| "write a")?;
+        let long = "n".repeat(120);
+        let long_path = alloc::format!("/selftest/src/sub/{}", long);
+        vfs.write_file(&long_path, b"a name over 100 bytes").map_err(|_| "write long")?;
+        vfs.symlink("../a", "/selftest/src/sub/l").map_err(|_| "symlink")?;
+
+        for (format, out) in [(Format::Ustar, "/selftest/t"), (Format::Newc, "/selftest/c")] {
+            vfs.cd(DIR).map_err(|_| "cd")?;
+            let (data, sum) = archive::create(vfs, &["src"], format).map_err(|_| "create")?;
+            check(sum.files == 2 && sum.dirs == 2 && sum.symlinks == 1, "members archived")?;
+            check(Format::detect(&data) == Some(format), "format detected")?;
+            archive::extract(vfs, &data, out).map_err(|_| "extract")?;
+            let read = |p: &str| vfs.read_file(&alloc::format!("{}/src/{}", out, p));
+            check(read("a").ok().as_deref() == Some(b"alpha"), "file contents")?;
+            let long = read(&alloc::format!("sub/{}", long)).map_err(|_| "long name")?;
+            check(long == b"a name over 100 bytes", "long name contents")?;
+            let link = vfs.readlink(&alloc::format!("{}/src/sub/l", out));
+            check(link.ok().as_deref() == Some("../a"), "symlink target")?;
+        }
+        vfs.cd("/").map_err(|_| "cd /")?;
+        remove_tree(vfs, DIR).map_err(|_| "remove the tree")
+    })
+}
+
+/// `rm -r`.
+fn remove_tree(vfs: &crate::fs::VfsContext, path: &str) -> Result<(), Errno> {
+    if vfs.stat_at(None, path, false)?.kind != crate::fs::FileType::Directory {
+        return vfs.unlink(path);
+    }
+    for e in vfs.readdir_all(path)? {
+        if e.name != "." && e.name != ".." {
+            remove_tree(vfs, &alloc::format!("{}/{}", path, e.name))?;
+        }
+    }
+    vfs.rmdir(path)
+}
+
+// ─── Library ─────────────────────────────────────────────────────────────────
+
+fn lib_hashes() -> TestResult {
+    use crate::lib::hash::{hex, md5, sha256, Md5, Sha256};
+
+    // FIPS 180-4 and RFC 1321 test vectors; the long one spans two blocks
+    let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+    check(hex(&md5(b"")) == "d41d8cd98f00b204e9800998ecf8427e", "md5 of nothing")?;
+    check(hex(&md5(b"abc")) == "900150983cd24fb0d6963f7d28e17f72", "md5(abc)")?;
+    check(hex(&md5(long)) == "8215ef0796a20bcaaae116d3876c664a", "md5 of two blocks")?;
+    check(
+        hex(&sha256(b"abc")) == "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        "sha256(abc)",
+    )?;
+    check(
+        hex(&sha256(long)) == "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+        "sha256 of two blocks",
+    )?;
+
+    // Fed in pieces that straddle block boundaries, the sums don't change
+    let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+    for cut in [1, 55, 63, 64, 65] {
+        let (mut m, mut s) = (Md5::new(), Sha256::new());
+        for piece in data.chunks(cut) {
+            m.update(piece);
+            s.update(piece);
+        }
+        check(m.finish() == md5(&data), "md5 in pieces")?;
+        check(s.finish() == sha256(&data), "sha256 in pieces")?;
+    }
+    Ok(())
+}
+
+// ─── Scheduler ───────────────────────────────────────────────────────────────
+
+const WORKERS: usize = 4;
+
+static DONE: AtomicUsize = AtomicUsize::new(0);
+
+fn sched_sleep() -> TestResult {
+    let ns = 20_000_000;
+    let start = nanos();
+    sleep_ns(ns).map_err(|_| "sleep cut short")?;
+    let slept = nanos() - start;
+    check(slept >= ns, "woke up early")?;
+    check(slept < 50 * ns, "overslept")
+}
+
+fn worker() -> ! {
+    // Give the others a chance to run in between
+    let _ = sleep_ns(5_000_000);
+    DONE.fetch_add(1, Ordering::SeqCst);
+    crate::proc::exit_current(0);
+}
+
+fn sched_priority() -> TestResult {
+    use crate::proc::priority::{
+        effective, sys_getpriority, sys_setpriority, MAX_SLEEP_AVG, PRIO_PGRP, PRIO_PROCESS,
+    };
+    use crate::syscall::errno::{EINVAL, ESRCH};
+
+    check(sys_getpriority(PRIO_PROCESS, 0) == 20, "nice isn't 0")?;
+    check(sys_setpriority(PRIO_PROCESS, 0, 5) == 0, "setpriority")?;
+    let got = sys_getpriority(PRIO_PGRP, 0);
+    check(sys_setpriority(PRIO_PROCESS, 0, 0) == 0, "setpriority back to 0")?;
+    check(got == 15, "nice 5 not read back")?;
+    check(sys_setpriority(PRIO_PROCESS, 0, 99) == 0, "out of range not clamped")?;
+    let clamped = sys_getpriority(PRIO_PROCESS, 0);
+    check(sys_setpriority(PRIO_PROCESS, 0, 0) == 0, "setpriority back to 0")?;
+    check(clamped == 1, "nice not clamped to 19")?;
+    check(sys_getpriority(7, 0) == -EINVAL, "unknown which")?;
+    check(sys_getpriority(PRIO_PROCESS, i32::MAX as u32) == -ESRCH, "no such process")?;
+
+    // Sleep credit moves a task up to 5 levels either way
+    let task = crate::proc::Process::new_kernel("selftest-prio", worker, 5).ok_or("new_kernel")?;
+    let mut p = task.lock();
+    let neutral = effective(&p);
+    p.sleep_avg = MAX_SLEEP_AVG;
+    let sleeper = effective(&p);
+    p.sleep_avg = 0;
+    let hog = effective(&p);
+    p.nice = 19;
+    let nice_hog = effective(&p);
+    check(neutral == 5, "neutral credit moved the level")?;
+    check(sleeper == 0, "a sleeper isn't boosted")?;
+    check(hog == 10, "a hog isn't held back")?;
+    check(nice_hog == 29, "nice not added")
+}
+
+fn sched_tasks() -> TestResult {
+    DONE.store(0, Ordering::SeqCst);
+    for _ in 0..WORKERS {
+        let p = crate::proc::Process::new_kernel("selftest-worker", worker, 5)
+            .ok_or("new_kernel")?;
+        crate::proc::scheduler::spawn(p);
+    }
+    let deadline = nanos() + 2_000_000_000;
+    while DONE.load(Ordering::SeqCst) < WORKERS {
+        check(nanos() < deadline, "workers didn't all finish")?;
+        let _ = sleep_ns(1_000_000);
+    }
+    Ok(())
+}
+
+/// Exited tasks give their kernel stacks back once they are off the CPU
+/// for good: a batch of workers leaves no more in use than before.
+fn sched_stacks_freed() -> TestResult {
+    use crate::mm::kstack::in_use;
+    let before = in_use();
+    sched_tasks()?;
+    // kworker frees them after each one's last switch away
+    let deadline = nanos() + 1_000_000_000;
+    while in_use() > before {
+        check(nanos() < deadline, "exited tasks' stacks not freed")?;
+        let _ = sleep_ns(1_000_000);
+    }
+    Ok(())
+}
+
+// ─── Syscalls ────────────────────────────────────────────────────────────────
+
+const USER_BASE: u64 = 0x40_0000;
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+/// `write(1, "ok\n", 3)` must return 3 and `getpid()` something positive;
+/// then `exit(42)`, or `exit(1)` if either was wrong.
+const USER_CODE: &[u8] = &[
+    0xb8, 0x01, 0x00, 0x00, 0x00, // mov eax, 1 (write)
+    0xbf, 0x01, 0x00, 0x00, 0x00, // mov edi, 1
+    0x48, 0x8d, 0x35, 0x2c, 0x00, 0x00, 0x00, // lea rsi, [rip + msg]
+    0xba, 0x03, 0x00, 0x00, 0x00, // mov edx, 3
+    0x0f, 0x05, // syscall
+    0x48, 0x83, 0xf8, 0x03, // cmp rax, 3
+    0x75, 0x13, // jne fail
+    0xb8, 0x27, 0x00, 0x00, 0x00, // mov eax, 39 (getpid)
+    0x0f, 0x05, // syscall
+    0x48, 0x85, 0xc0, // test rax, rax
+    0x7e, 0x07, // jle fail
+    0xbf, 0x2a, 0x00, 0x00, 0x00, // mov edi, 42
+    0xeb, 0x05, // jmp exit
+    0xbf, 0x01, 0x00, 0x00, 0x00, // fail: mov edi, 1
+    0xb8, 0x3c, 0x00, 0x00, 0x00, // exit: mov eax, 60 (exit)
+    0x0f, 0x05, // syscall
+    b'o', b'k', b'\n', // msg
+];
+
+/// An anonymous MAP_SHARED page, touched and then forked: the child
+/// stores 7 in it and exits, the parent waits, expects the 7 and stores 8
+/// itself; then `exit(42)`, or `exit(1)` if the 7 didn't arrive.
+const SHARED_FORK_CODE: &[u8] = &[
+    0xb8, 0x09, 0x00, 0x00, 0x00, // mov eax, 9 (mmap)
+    0x31, 0xff, // xor edi, edi
+    0xbe, 0x00, 0x10, 0x00, 0x00, // mov esi, 0x1000
+    0xba, 0x03, 0x00, 0x00, 0x00, // mov edx, 3 (PROT_READ | PROT_WRITE)
+    0x41, 0xba, 0x21, 0x00, 0x00, 0x00, // mov r10d, 0x21 (MAP_SHARED | MAP_ANONYMOUS)
+    0x49, 0xc7, 0xc0, 0xff, 0xff, 0xff, 0xff, // mov r8, -1
+    0x45, 0x31, 0xc9, // xor r9d, r9d
+    0x0f, 0x05, // syscall
+    0x48, 0x89, 0xc3, // mov rbx, rax
+    0xc7, 0x03, 0x01, 0x00, 0x00, 0x00, // mov dword [rbx], 1
+    0xb8, 0x39, 0x00, 0x00, 0x00, // mov eax, 57 (fork)
+    0x0f, 0x05, // syscall
+    0x48, 0x85, 0xc0, // test rax, rax
+    0x75, 0x0f, // jnz parent
+    0xc7, 0x03, 0x07, 0x00, 0x00, 0x00, // mov dword [rbx], 7
+    0x31, 0xff, // xor edi, edi
+    0xb8, 0x3c, 0x00, 0x00, 0x00, // mov eax, 60 (exit)
+    0x0f, 0x05, // syscall
+    0xbf, 0xff, 0xff, 0xff, 0xff, // parent: mov edi, -1
+    0x31, 0xf6, // xor esi, esi
+    0x31, 0xd2, // xor edx, edx
+    0x45, 0x31, 0xd2, // xor r10d, r10d
+    0xb8, 0x3d, 0x00, 0x00, 0x00, // mov eax, 61 (wait4)
+    0x0f, 0x05, // syscall
+    0x83, 0x3b, 0x07, // cmp dword [rbx], 7
+    0x75, 0x0d, // jne fail
+    0xc7, 0x03, 0x08, 0x00, 0x00, 0x00, // mov dword [rbx], 8
+    0xbf, 0x2a, 0x00, 0x00, 0x00, // mov edi, 42
+    0xeb, 0x05, // jmp exit
+    0xbf, 0x01, 0x00, 0x00, 0x00, // fail: mov edi, 1
+    0xb8, 0x3c, 0x00, 0x00, 0x00, // exit: mov eax, 60 (exit)
+    0x0f, 0x05, // syscall
+];
+
+/// `jmp .`: never makes a syscall, so only the tick can take the CPU back.
+const SPIN_CODE: &[u8] = &[0xeb, 0xfe];
+
+/// A static ELF64 executable around `code`: the headers and the code in
+/// one read-execute segment at `USER_BASE`.
+fn user_elf(code: &[u8]) -> Vec<u8> {
+    let code_off = (EHDR_SIZE + PHDR_SIZE) as u64;
+    let total = code_off + code.len() as u64;
+    let mut elf = Vec::with_capacity(total as usize);
+    elf.extend_from_slice(b"\x7fELF");
+    elf.extend_from_slice(&[2, 1, 1, 0]); // 64-bit, little-endian, SysV
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    elf.extend_from_slice(&62u16.to_le_bytes()); // EM_X86_64
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&(USER_BASE + code_off).to_le_bytes()); // entry
+    elf.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes()); // phoff
+    elf.extend_from_slice(&0u64.to_le_bytes()); // shoff
+    elf.extend_from_slice(&0u32.to_le_bytes()); // flags
+    elf.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&1u16.to_le_bytes()); // phnum
+    elf.extend_from_slice(&[0; 6]); // no section headers
+
+    elf.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+    elf.extend_from_slice(&5u32.to_le_bytes()); // R | X
+    elf.extend_from_slice(&0u64.to_le_bytes()); // offset
+    elf.extend_from_slice(&USER_BASE.to_le_bytes()); // vaddr
+    elf.extend_from_slice(&USER_BASE.to_le_bytes()); // paddr
+    elf.extend_from_slice(&total.to_le_bytes()); // filesz
+    elf.extend_from_slice(&total.to_le_bytes()); // memsz
+    elf.extend_from_slice(&0x1000u64.to_le_bytes()); // align
+
+    elf.extend_from_slice(code);
+    elf
+}
+
+fn syscall_user() -> TestResult {
+    run_user_elf(false)
+}
+
+/// The same program, its syscalls logged as `strace` would: tracing must
+/// not change what they do.
+fn syscall_traced() -> TestResult {
+    run_user_elf(true)
+}
+
+fn run_user_elf(trace: bool) -> TestResult {
+    let parent = crate::proc::current_process()
+        .ok_or("no current task")?
+        .lock()
+        .tgid;
+    let argv = [b"selftest\0".to_vec()];
+    let proc = crate::proc::Process::new_user("selftest", &user_elf(USER_CODE), &argv, &[], 5)?;
+    let pid = {
+        let mut p = proc.lock();
+        p.ppid = parent;
+        p.trace = trace;
+        p.pid
+    };
+    crate::proc::spawn(proc);
+
+    match wait_child(pid as i32, 0) {
+        Ok(Some((_, WaitStatus::Exited(42)))) => Ok(()),
+        Ok(Some((_, WaitStatus::Exited(_)))) => Err("write or getpid returned wrong values"),
+        Ok(Some((_, WaitStatus::Signaled(_)))) => Err("killed by a signal"),
+        Ok(_) => Err("unexpected wait status"),
+        Err(_) => Err("waitpid failed"),
+    }
+}
+
+/// fork write-protects the pages of both processes; writes to a shared
+/// mapping must still go through, and be seen by the other side.
+fn mm_shared_fork() -> TestResult {
+    let parent = crate::proc::current_process()
+        .ok_or("no current task")?
+        .lock()
+        .tgid;
+    let argv = [b"shared\0".to_vec()];
+    let proc =
+        crate::proc::Process::new_user("shared", &user_elf(SHARED_FORK_CODE), &argv, &[], 5)?;
+    let pid = {
+        let mut p = proc.lock();
+        p.ppid = parent;
+        p.pid
+    };
+    crate::proc::spawn(proc);
+
+    match wait_child(pid as i32, 0) {
+        Ok(Some((_, WaitStatus::Exited(42)))) => Ok(()),
+        Ok(Some((_, WaitStatus::Exited(_)))) => Err("the child's write didn't reach the parent"),
+        Ok(Some((_, WaitStatus::Signaled(_)))) => Err("killed by a signal"),
+        Ok(_) => Err("unexpected wait status"),
+        Err(_) => Err("waitpid failed"),
+    }
+}
+
+/// A ring-3 loop at our priority must not keep us off the CPU: the tick
+/// preempts it, and it resumes where it was.
+fn sched_user_preempt() -> TestResult {
+    use crate::proc::signal::{send, SIGKILL};
+    let me = crate::proc::current_process().ok_or("no current task")?.lock().tgid;
+    let argv = [b"spin\0".to_vec()];
+    let proc = crate::proc::Process::new_user("spin", &user_elf(SPIN_CODE), &argv, &[], 5)?;
+    let pid = {
+        let mut p = proc.lock();
+        p.ppid = me;
+        p.pid
+    };
+    crate::proc::spawn(proc);
+
+    let start = nanos();
+    let mut ran = 0;
+    for _ in 0..5 {
+        let _ = sleep_ns(10_000_000);
+        ran = crate::proc::task_info(pid).map_or(0, |t| t.utime);
+    }
+    let took = nanos() - start;
+    send(pid, SIGKILL).map_err(|_| "kill failed")?;
+    let status = wait_child(pid as i32, 0);
+    check(took < 1_000_000_000, "the loop kept the CPU")?;
+    check(ran > 0, "the loop never ran in ring 3")?;
+    match status {
+        Ok(Some((_, WaitStatus::Signaled(SIGKILL)))) => Ok(()),
+        _ => Err("the loop didn't die of SIGKILL"),
+    }
+}
+
+fn syscall_table() -> TestResult {
+    use crate::syscall::{lookup, nr};
+    let name = |n| lookup(n).map(|s| s.name);
+    check(name(nr::SYS_READ) == Some("read"), "read")?;
+    check(name(nr::SYS_CLONE) == Some("clone"), "clone")?;
+    check(name(nr::SYS_RT_SIGRETURN) == Some("rt_sigreturn"), "rt_sigreturn")?;
+    check(lookup(nr::SYS_PRLIMIT64).is_some_and(|s| s.nr == nr::SYS_PRLIMIT64), "prlimit64")?;
+    check(lookup(7).is_none(), "unregistered number found")?;
+    check(lookup(u64::MAX).is_none(), "number past the table found")
+}
+
+// ─── Process groups ──────────────────────────────────────────────────────────
+
+fn grouped() -> ! {
+    let _ = sleep_ns(50_000_000);
+    crate::proc::exit_current(7);
+}
+
+fn proc_groups() -> TestResult {
+    use crate::proc::{sys_getpgid, sys_setpgid, sys_setsid};
+    use crate::syscall::errno::{ECHILD, EPERM, ESRCH};
+
+    let me = crate::proc::current_process().ok_or("no current task")?.lock().tgid;
+    // The selftest task leads its own session, as kernel tasks do
+    check(sys_getpgid(0, true) == me as i64, "kernel task not a session leader")?;
+    check(sys_setpgid(0, 0) == -EPERM, "session leader changed group")?;
+    check(sys_setsid() == -EPERM, "group leader started a session")?;
+
+    let proc = crate::proc::Process::new_kernel("selftest-group", grouped, 5)
+        .ok_or("new_kernel")?;
+    let child = {
+        let mut p = proc.lock();
+        p.ppid = me;
+        p.pid
+    };
+    crate::proc::spawn(proc);
+    check(sys_getpgid(child, false) == me as i64, "child not in the parent's group")?;
+    check(sys_getpgid(child, true) == me as i64, "child not in the parent's session")?;
+    check(sys_setpgid(child, 0) == 0, "setpgid into a group of its own")?;
+    check(sys_getpgid(child, false) == child as i64, "child's group")?;
+    check(sys_setpgid(child, 99999) == -EPERM, "joined a group that doesn't exist")?;
+    check(sys_setpgid(99999, 0) == -ESRCH, "moved a task that doesn't exist")?;
+
+    // Only the child's own group finds it now
+    check(wait_child(0, WNOHANG) == Err(ECHILD), "waited in own group")?;
+    check(wait_child(-(child as i32), WNOHANG) == Ok(None), "child done early")?;
+    match wait_child(-(child as i32), 0) {
+        Ok(Some((pid, WaitStatus::Exited(7)))) if pid == child => Ok(()),
+        Ok(_) => Err("unexpected wait status"),
+        Err(_) => Err("waitpid on the group failed"),
+    }
+}
+
+fn proc_limits() -> TestResult {
+    use crate::mm::vmm::{VmSpace, VmaFlags};
+    use crate::proc::resource::{Limits, RLIMIT_AS, RLIMIT_NOFILE, RLIM_INFINITY};
+    use crate::proc::FdTable;
+
+    let limits = Limits::DEFAULT;
+    check(limits.cur(RLIMIT_NOFILE) == FdTable::MAX_FDS as u64, "default RLIMIT_NOFILE")?;
+    check(limits.cur(RLIMIT_AS) == RLIM_INFINITY, "default RLIMIT_AS")?;
+
+    // RLIMIT_NOFILE of 5: fds 3 and 4, then nothing until one is closed
+    let file = with_vfs(|vfs| vfs.open("/", 0)).map_err(|_| "open /")?;
+    let mut fds = FdTable::new();
+    check(fds.alloc(file.clone(), 5) == Some(3), "first fd")?;
+    check(fds.alloc(file.clone(), 5) == Some(4), "second fd")?;
+    check(fds.alloc(file.clone(), 5).is_none(), "fd past RLIMIT_NOFILE")?;
+    check(fds.close(3).is_some(), "close")?;
+    check(fds.alloc(file.clone(), 5) == Some(3), "freed fd not reused")?;
+    check(fds.close(4).is_some(), "close")?;
+    check(fds.alloc(file, 4).is_none(), "fd above a lowered limit")?;
+
+    // What RLIMIT_AS counts
+    let mut vm = VmSpace::new();
+    let rw = VmaFlags::READ | VmaFlags::WRITE | VmaFlags::ANONYMOUS;
+    check(vm.add_vma(0x1000, 0x5000, rw) && vm.add_vma(0x8000, 0x9000, rw), "add_vma")?;
+    check(vm.size() == 0x5000, "VMA size")?;
+    check(vm.size_in(0x3000, 0x8800) == 0x2800, "VMA size in a range")?;
+    check(vm.size_in(0x5000, 0x8000) == 0, "VMA size in a gap")
+}
+
+fn proc_futex() -> TestResult {
+    use crate::proc::futex::*;
+    use crate::syscall::errno::{EFAULT, EINVAL, ENOSYS};
+
+    // The selftest task has no user mappings: every word is unmapped
+    let futex = |addr, op, val3| sys_futex(addr, op, 1, 0, 0, val3);
+    check(futex(0x1002, FUTEX_WAKE, 0) == -EINVAL, "misaligned word")?;
+    check(futex(0, FUTEX_WAIT, 0) == -EFAULT, "null word")?;
+    check(futex(0x1000, FUTEX_WAKE | FUTEX_PRIVATE_FLAG, 0) == -EFAULT, "unmapped word")?;
+    check(futex(0x1000, FUTEX_WAKE_BITSET, 0) == -EINVAL, "empty bitset")?;
+    check(futex(0x1000, FUTEX_WAKE | FUTEX_CLOCK_REALTIME, 0) == -ENOSYS, "realtime wake")?;
+    check(futex(0x1000, 99, 0) == -ENOSYS, "unknown op")
+}
+
+fn proc_fs_base() -> TestResult {
+    use crate::arch::x86_64::io::{rdmsr, MSR_FS_BASE};
+    use crate::proc::sys_arch_prctl;
+    use crate::syscall::errno::{EINVAL, EPERM};
+    const ARCH_SET_FS: u32 = 0x1002;
+    const TLS: u64 = 0x7fff_1234_0000;
+
+    check(sys_arch_prctl(ARCH_SET_FS, TLS) == 0, "ARCH_SET_FS")?;
+    check(unsafe { rdmsr(MSR_FS_BASE) } == TLS, "MSR not written")?;
+    // The kworker and idle tasks run in between, on FS.base 0
+    let _ = sleep_ns(2_000_000);
+    let kept = unsafe { rdmsr(MSR_FS_BASE) } == TLS;
+    check(sys_arch_prctl(ARCH_SET_FS, 0) == 0, "ARCH_SET_FS back to 0")?;
+    check(kept, "FS.base lost across a switch")?;
+    let kernel = crate::mm::vmm::USER_SPACE_END;
+    check(sys_arch_prctl(ARCH_SET_FS, kernel) == -EPERM, "kernel FS.base taken")?;
+    check(sys_arch_prctl(0x1001, 0) == -EINVAL, "ARCH_SET_GS taken")
+}
+
+fn fpu_state() -> TestResult {
+    use crate::arch::x86_64::fpu::FpuState;
+    use crate::arch::x86_64::io::{cli, sti, RFLAGS_IF};
+    use core::arch::asm;
+    const PATTERN: u64 = 0x0123_4567_89ab_cdef;
+    let set = |v: u64| unsafe { asm!("movq xmm0, {}", in(reg) v, options(nomem, nostack)) };
+    let get = || -> u64 {
+        let v;
+        unsafe { asm!("movq {}, xmm0", out(reg) v, options(nomem, nostack)) };
+        v
+    };
+
+    // A kernel task's registers aren't switched, so nothing may run in
+    // between; what a user task left in them goes back afterwards
+    let rflags = cli();
+    let before = FpuState::current();
+    set(PATTERN);
+    let saved = FpuState::current();
+    set(0);
+    saved.restore();
+    let kept = get();
+    FpuState::new().restore();
+    let fresh = get();
+    before.restore();
+    if rflags & RFLAGS_IF != 0 {
+        sti();
+    }
+    check(kept == PATTERN, "xmm0 not restored")?;
+    check(fresh == 0, "initial state not clear")
+}
+
+// ─── Runner ──────────────────────────────────────────────────────────────────
+
+/// The self-test task: run everything, report, leave QEMU.
+pub fn run() -> ! {
+    log::info!("selftest: running {} tests", TESTS.len());
+    let mut failed = 0;
+    for (name, test) in TESTS {
+        match test() {
+            Ok(()) => log::info!("selftest: {} ... ok", name),
+            Err(why) => {
+                log::error!("selftest: {} ... FAILED: {}", name, why);
+                failed += 1;
+            }
+        }
+    }
+    if failed == 0 {
+        log::info!("selftest: all {} passed", TESTS.len());
+    } else {
+        log::error!("selftest: {} of {} failed", failed, TESTS.len());
+    }
+    qemu_exit(failed == 0);
+}
+
+/// Called by the panic handler: a panic fails the run.
+pub fn panicked() -> ! {
+    crate::serial_println!("selftest: kernel panic, failing the run");
+    qemu_exit(false);
+}
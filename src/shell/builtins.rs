@@ -1,123 +1,1038 @@
-use super::{shell_print, shell_println};
+use super::{io, shell_eprintln, shell_print, shell_println};
+use crate::drivers::rtc::DateTime;
 use crate::fs::mount::with_vfs;
-use crate::fs::vfs::FileType;
+use crate::fs::path;
+use crate::fs::vfs::{Errno, FileType, Timespec};
+use crate::lib::hash;
+use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 pub fn cmd_help() {
     shell_println!("Built-in commands:");
-    shell_println!("  ls [path]          list directory contents");
+    shell_println!("  ls [-lah] [path]   list directory contents (-l long, -a all, -h sizes)");
     shell_println!("  cd <path>          change directory");
     shell_println!("  pwd                print working directory");
     shell_println!("  cat <file>         print file contents");
     shell_println!("  echo <text>        print text");
     shell_println!("  mkdir <path>       create directory");
-    shell_println!("  touch <file>       create empty file");
+    shell_println!("  touch <file>       create file or update its times");
     shell_println!("  rm <file>          remove file");
     shell_println!("  rmdir <dir>        remove empty directory");
     shell_println!("  mv <src> <dst>     move/rename");
     shell_println!("  cp <src> <dst>     copy file");
     shell_println!("  write <file> <text> write text to file");
     shell_println!("  stat <path>        show file info");
+    shell_println!("  find [path...] [-name pat] [-type f|d|l] [-maxdepth n] search a tree");
+    shell_println!("  du [-sh] [path...] disk use per directory (-s: totals only)");
+    shell_println!("  grep [-ivnc] <pattern> [file...] lines containing a string");
+    shell_println!("  head/tail [-n N] [file...] first / last lines (default 10)");
+    shell_println!("  wc [-lwc] [file...] count lines, words and bytes");
+    shell_println!("  hexdump [-C] [-n len] [file...] hex and ASCII dump");
+    shell_println!("  md5sum/sha256sum [-c] [file...] print or (-c) check checksums");
     shell_println!("  ln -s <target> <link> create symlink");
-    shell_println!("  run <file> [args]  execute ELF binary from VFS");
-    shell_println!("  mount [n /path]    mount drive n at /path (no args: list mounts)");
+    shell_println!("  tar -cf|-xf|-tf <archive> ... pack, unpack (-C dir) or list; ustar or cpio");
+    shell_println!("  chmod <mode> <path>... change permissions (octal, or like u+x,go-w)");
+    shell_println!("  chown <uid>[:gid] <path>... change owner and group (numeric, or root)");
+    shell_println!("  run <file> [args]  execute ELF binary from VFS and wait for it (`&`: don't)");
+    shell_println!("  strace <file> [args] run it with its syscalls (and its children's) logged");
+    shell_println!("  mount [dev [part] /path] mount a drive or partition (hda1, hda 1) at /path");
+    shell_println!("  mount --bind <dir> <path> make a directory appear at path as well");
     shell_println!("  umount <path>      unmount filesystem");
     shell_println!("  drives             list detected disk drives");
-    shell_println!("  lspci              list PCI devices");
-    shell_println!("  view <file.bmp>    display BMP image");
+    shell_println!("  sync [wb|wt]       flush disk cache (optionally set write-back/write-through)");
+    shell_println!("  lspci [-v]         list PCI devices (-v: with their capabilities)");
+    shell_println!("  smartctl [-a] [drive] drive health and temperature (-a: all attributes)");
+    shell_println!("  ifconfig [if [addr[/len] [netmask m] [gw g]]] show / set interface addresses");
+    shell_println!("  ping [-c n] <host> send ICMP echo requests (default 4)");
+    shell_println!("  host <name>        look up a name's addresses in DNS");
+    shell_println!("  view <image>       display a BMP or PNG image, scaled to the screen");
+    shell_println!("  font [-s 1-4] [file.psf|default] console font and its scale");
     shell_println!("  clear              clear screen");
     shell_println!("  history            command history");
     shell_println!("  uname              system info");
     shell_println!("  uptime             system uptime");
-    shell_println!("  free               memory usage");
+    shell_println!("  date               current date and time (UTC)");
+    shell_println!("  sleep <seconds>    pause, e.g. sleep 2 or sleep 0.25");
+    shell_println!("  free [-v]          memory usage (-v: free blocks per buddy order)");
+    shell_println!("  slabinfo           kernel heap per-cache statistics");
+    shell_println!("  top [-b]           tasks by CPU use, every second until q (-b: once)");
+    shell_println!("  ps                 list tasks");
+    shell_println!("  kill [-SIG] <pid>... send a signal, TERM by default (-l: list them)");
+    shell_println!("  renice <n> <pid>... set the nice value of processes (-20 to 19)");
     shell_println!("  reboot             restart system");
     shell_println!("  halt               halt system");
+    shell_println!("  sh <file> [args]   run a shell script (also: run on a #! or .sh file)");
+    shell_println!("  test <expr>, [ ]   -e/-f/-d/-z/-n, = != -eq -ne -lt -le -gt -ge, !");
+    shell_println!("  true, false        succeed / fail");
+    shell_println!("  exit [code]        leave a script");
+    shell_println!("  export [VAR[=val]] add to the environment of spawned programs (no args: list)");
+    shell_println!("  unset <VAR>        remove a variable");
+    shell_println!("  env                print the environment");
+    shell_println!("  jobs               background and stopped jobs");
+    shell_println!("  fg [%n], bg [%n]   continue a job in the foreground / background");
+    shell_println!();
+    shell_println!("Pipelines and redirection: cmd1 | cmd2, cmd > file, cmd >> file, cmd < file");
+    shell_println!("Job control: cmd &, Ctrl-Z stops the foreground job");
+    shell_println!("Scripting: VAR=value, VAR=value cmd, $VAR, $?, $1, cmd1 && cmd2 || cmd3, cmd1; cmd2,");
+    shell_println!("  if cmd; then ...; elif ...; else ...; fi, for x in a b c; do ...; done");
 }
 
-pub fn cmd_ls(args: &[String]) {
-    let path = args.first().map(|s| s.as_str()).unwrap_or(".");
+/// `ls [-lah] [path...]`: names in columns, or with -l one line each of
+/// mode, links, owner, group, size, modification time and name. Names
+/// starting with a dot only show with -a; -h gives sizes as 4.0K, 12M.
+pub fn cmd_ls(args: &[String]) -> i32 {
+    let (mut long, mut all, mut human) = (false, false, false);
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for c in flags.chars() {
+                    match c {
+                        'l' => long = true,
+                        'a' => all = true,
+                        'h' => human = true,
+                        _ => {
+                            shell_eprintln!("ls: invalid option -- '{}'", c);
+                            return 2;
+                        }
+                    }
+                }
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    if paths.is_empty() {
+        paths.push(".");
+    }
 
-    let entries = with_vfs(|vfs| vfs.readdir_all(path));
+    let mut status = 0;
+    for (i, &path) in paths.iter().enumerate() {
+        let kind = match with_vfs(|vfs| vfs.stat(path)) {
+            Ok(st) => st.kind,
+            Err(e) => {
+                shell_eprintln!("ls: {}: error {}", path, e.0);
+                status = 1;
+                continue;
+            }
+        };
+        // A file given by name lists as itself
+        let listed: Vec<Listed> = if kind == FileType::Directory {
+            let entries = match with_vfs(|vfs| vfs.readdir_all(path)) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    shell_eprintln!("ls: {}: error {}", path, e.0);
+                    status = 1;
+                    continue;
+                }
+            };
+            let mut listed: Vec<Listed> = entries
+                .into_iter()
+                .filter(|e| all || !e.name.starts_with('.'))
+                .map(|e| Listed {
+                    path: alloc::format!("{}/{}", path.trim_end_matches('/'), e.name),
+                    name: e.name,
+                    kind: e.kind,
+                })
+                .collect();
+            listed.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+            listed
+        } else {
+            alloc::vec![Listed { name: path.to_string(), path: path.to_string(), kind }]
+        };
 
-    match entries {
-        Err(e) => shell_println!("ls: {}: error {}", path, e.0),
-        Ok(mut entries) => {
-            entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        if paths.len() > 1 {
+            if i > 0 {
+                shell_println!();
+            }
+            shell_println!("{}:", path);
+        }
+        if long {
+            ls_long(&listed, human);
+        } else {
+            ls_columns(&listed);
+        }
+    }
+    status
+}
 
-            let mut line_len = 0usize;
-            let col_width = 20usize;
+/// One name `ls` shows, with the path to look it up by.
+struct Listed {
+    name: String,
+    path: String,
+    kind: FileType,
+}
 
-            for entry in &entries {
-                let prefix = match entry.kind {
-                    FileType::Directory => "\x1b[34m",
-                    FileType::Symlink => "\x1b[36m",
-                    FileType::CharDevice => "\x1b[33m",
-                    FileType::Regular => "\x1b[0m",
-                };
-                let suffix = match entry.kind {
-                    FileType::Directory => "/",
-                    FileType::Symlink => "@",
-                    _ => "",
-                };
+/// Colour and classifying suffix for a name of kind `kind`.
+fn ls_style(kind: FileType) -> (&'static str, &'static str) {
+    match kind {
+        FileType::Directory => ("\x1b[34m", "/"),
+        FileType::Symlink => ("\x1b[36m", "@"),
+        FileType::Fifo => ("\x1b[33m", "|"),
+        FileType::Socket => ("\x1b[33m", "="),
+        FileType::CharDevice => ("\x1b[33m", ""),
+        FileType::Regular => ("\x1b[0m", ""),
+    }
+}
+
+fn ls_columns(listed: &[Listed]) {
+    let mut line_len = 0usize;
+    let col_width = 20usize;
+
+    for entry in listed {
+        let (prefix, suffix) = ls_style(entry.kind);
+        let raw_len = entry.name.len() + suffix.len();
+
+        shell_print!("{}{}{}\x1b[0m", prefix, entry.name, suffix);
+
+        line_len += raw_len;
+        if line_len + col_width >= 80 {
+            shell_println!();
+            line_len = 0;
+        } else {
+            let pad = col_width.saturating_sub(raw_len);
+            for _ in 0..pad {
+                shell_print!(" ");
+            }
+            line_len += pad;
+        }
+    }
+
+    if line_len > 0 {
+        shell_println!();
+    }
+}
+
+/// One line per entry, with the columns as wide as their widest value.
+/// Symlinks are shown themselves, with where they point.
+fn ls_long(listed: &[Listed], human: bool) {
+    struct Row<'a> {
+        st: crate::fs::vfs::Stat,
+        name: &'a str,
+        size: String,
+        target: Option<String>,
+    }
+    let mut rows = Vec::new();
+    for entry in listed {
+        let st = match with_vfs(|vfs| vfs.stat_at(None, &entry.path, false)) {
+            Ok(st) => st,
+            Err(e) => {
+                shell_eprintln!("ls: {}: error {}", entry.path, e.0);
+                continue;
+            }
+        };
+        let size = if human { human_size(st.size) } else { st.size.to_string() };
+        let target = match st.kind {
+            FileType::Symlink => with_vfs(|vfs| vfs.readlink(&entry.path)).ok(),
+            _ => None,
+        };
+        rows.push(Row { st, name: &entry.name, size, target });
+    }
+
+    let width = |f: &dyn Fn(&Row) -> usize| rows.iter().map(f).max().unwrap_or(0);
+    let links_w = width(&|r| r.st.nlink.to_string().len());
+    let uid_w = width(&|r| id_name(r.st.uid).len());
+    let gid_w = width(&|r| id_name(r.st.gid).len());
+    let size_w = width(&|r| r.size.len());
+    let now = crate::arch::x86_64::timer::realtime() / 1_000_000_000;
+
+    for r in &rows {
+        let (prefix, _) = ls_style(r.st.kind);
+        shell_print!(
+            "{} {:>lw$} {:<uw$} {:<gw$} {:>sw$} {} {}{}\x1b[0m",
+            mode_string(r.st.kind, r.st.mode),
+            r.st.nlink,
+            id_name(r.st.uid),
+            id_name(r.st.gid),
+            r.size,
+            ls_time(r.st.mtime, now as i64),
+            prefix,
+            r.name,
+            lw = links_w,
+            uw = uid_w,
+            gw = gid_w,
+            sw = size_w,
+        );
+        match &r.target {
+            Some(target) => shell_println!(" -> {}", target),
+            None => shell_println!(),
+        }
+    }
+}
+
+/// "drwxr-xr-x" and the like, setuid, setgid and sticky as s and t.
+fn mode_string(kind: FileType, mode: u32) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match kind {
+        FileType::Regular => '-',
+        FileType::Directory => 'd',
+        FileType::Symlink => 'l',
+        FileType::CharDevice => 'c',
+        FileType::Fifo => 'p',
+        FileType::Socket => 's',
+    });
+    // (shift of the rwx triple, its special bit, the letter for it)
+    for (shift, special, letter) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = mode >> shift;
+        s.push(if bits & 4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 2 != 0 { 'w' } else { '-' });
+        s.push(match (bits & 1 != 0, mode & special != 0) {
+            (true, true) => letter,
+            (false, true) => letter.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    s
+}
 
-                let display = alloc::format!("{}{}{}\x1b[0m", prefix, entry.name, suffix);
-                let raw_len = entry.name.len() + suffix.len();
+/// "Oct 14 09:30" within half a year of `now`, "Oct 14  2025" otherwise.
+fn ls_time(t: Timespec, now: i64) -> String {
+    const HALF_YEAR: i64 = 182 * 86400;
+    let dt = DateTime::from_unix(t.sec.max(0) as u64);
+    if (now - t.sec).abs() < HALF_YEAR {
+        alloc::format!("{} {:2} {:02}:{:02}", dt.month_name(), dt.day, dt.hour, dt.minute)
+    } else {
+        alloc::format!("{} {:2}  {}", dt.month_name(), dt.day, dt.year)
+    }
+}
 
-                shell_print!("{}", display);
+/// Bytes below 1K, else in K, M, G or T: one decimal under ten, always
+/// rounded up like `ls -h` does.
+fn human_size(n: u64) -> String {
+    const UNITS: [char; 4] = ['K', 'M', 'G', 'T'];
+    if n < 1024 {
+        return n.to_string();
+    }
+    let (mut unit, mut i) = (1024u64, 0);
+    while n >= unit * 1024 && i + 1 < UNITS.len() {
+        unit *= 1024;
+        i += 1;
+    }
+    let tenths = (n * 10).div_ceil(unit);
+    if tenths < 100 {
+        alloc::format!("{}.{}{}", tenths / 10, tenths % 10, UNITS[i])
+    } else {
+        alloc::format!("{}{}", n.div_ceil(unit), UNITS[i])
+    }
+}
 
-                line_len += raw_len;
-                if line_len + col_width >= 80 {
-                    shell_println!();
-                    line_len = 0;
+/// Shell-style wildcards: `*` any run, `?` any one character, `[abc]`,
+/// `[a-z]` and `[!abc]` a set.
+fn glob_match(pat: &[u8], name: &[u8]) -> bool {
+    match pat.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| glob_match(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && glob_match(rest, &name[1..]),
+        Some((b'[', rest)) if rest.contains(&b']') => {
+            let Some((&c, name_rest)) = name.split_first() else {
+                return false;
+            };
+            let (negate, set) = match rest.strip_prefix(b"!") {
+                Some(set) => (true, set),
+                None => (false, rest),
+            };
+            // A ']' right at the start is part of the set
+            let end = 1 + set[1..].iter().position(|&b| b == b']').unwrap_or(set.len() - 1);
+            let (set, after) = (&set[..end.min(set.len())], &set[(end + 1).min(set.len())..]);
+            let mut hit = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == b'-' {
+                    hit |= (set[i]..=set[i + 2]).contains(&c);
+                    i += 3;
                 } else {
-                    let pad = col_width.saturating_sub(raw_len);
-                    for _ in 0..pad {
-                        shell_print!(" ");
+                    hit |= set[i] == c;
+                    i += 1;
+                }
+            }
+            hit != negate && glob_match(after, name_rest)
+        }
+        Some((&p, rest)) => name.first() == Some(&p) && glob_match(rest, &name[1..]),
+    }
+}
+
+/// `dir/name`, without doubling a slash `dir` already ends in.
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.ends_with('/') {
+        alloc::format!("{}{}", dir, name)
+    } else {
+        alloc::format!("{}/{}", dir, name)
+    }
+}
+
+/// The entries of directory `path` by name, "." and ".." left out; None
+/// (and a message) if it can't be read.
+fn tree_children(cmd: &str, path: &str) -> Option<Vec<String>> {
+    match with_vfs(|vfs| vfs.readdir_all(path)) {
+        Ok(entries) => {
+            let mut names: Vec<String> = entries
+                .into_iter()
+                .map(|e| e.name)
+                .filter(|n| n != "." && n != "..")
+                .collect();
+            names.sort_unstable();
+            Some(names)
+        }
+        Err(e) => {
+            shell_eprintln!("{}: {}: error {}", cmd, path, e.0);
+            None
+        }
+    }
+}
+
+/// What `find` is looking for.
+struct FindFilter {
+    name: Option<String>,
+    kind: Option<FileType>,
+    max_depth: usize,
+}
+
+/// Where `find` has got to: the inodes of the directories above, so a bind
+/// mount of one of them below itself isn't walked round forever.
+fn find_in(path: &str, depth: usize, above: &mut Vec<u64>, filter: &FindFilter) -> bool {
+    let st = match with_vfs(|vfs| vfs.stat_at(None, path, false)) {
+        Ok(st) => st,
+        Err(e) => {
+            shell_eprintln!("find: {}: error {}", path, e.0);
+            return false;
+        }
+    };
+    let name = path::split_last(path).1;
+    let name = if name.is_empty() { path } else { name };
+    let name_ok = filter.name.as_ref().is_none_or(|p| glob_match(p.as_bytes(), name.as_bytes()));
+    if name_ok && filter.kind.is_none_or(|k| k == st.kind) {
+        shell_println!("{}", path);
+    }
+    if st.kind != FileType::Directory || depth >= filter.max_depth || above.contains(&st.ino) {
+        return true;
+    }
+    let Some(children) = tree_children("find", path) else {
+        return false;
+    };
+    above.push(st.ino);
+    let mut ok = true;
+    for child in children {
+        ok &= find_in(&join_path(path, &child), depth + 1, above, filter);
+    }
+    above.pop();
+    ok
+}
+
+/// `find [path...] [-name pattern] [-type f|d|l|c|p|s] [-maxdepth N]`:
+/// every path under each starting point (the working directory if none)
+/// that matches. Symlinks are listed, not followed.
+pub fn cmd_find(args: &[String]) -> i32 {
+    let mut filter = FindFilter { name: None, kind: None, max_depth: usize::MAX };
+    let mut starts = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if !arg.starts_with('-') {
+            starts.push(arg.as_str());
+            continue;
+        }
+        let Some(value) = it.next() else {
+            shell_eprintln!("find: {} needs an argument", arg);
+            return 1;
+        };
+        match arg.as_str() {
+            "-name" => filter.name = Some(value.clone()),
+            "-type" => {
+                filter.kind = Some(match value.as_str() {
+                    "f" => FileType::Regular,
+                    "d" => FileType::Directory,
+                    "l" => FileType::Symlink,
+                    "c" => FileType::CharDevice,
+                    "p" => FileType::Fifo,
+                    "s" => FileType::Socket,
+                    _ => {
+                        shell_eprintln!("find: unknown type '{}'", value);
+                        return 1;
                     }
-                    line_len += pad;
+                })
+            }
+            "-maxdepth" => match value.parse() {
+                Ok(n) => filter.max_depth = n,
+                Err(_) => {
+                    shell_eprintln!("find: -maxdepth needs a number");
+                    return 1;
                 }
+            },
+            _ => {
+                shell_eprintln!("find: unknown predicate '{}'", arg);
+                return 1;
             }
+        }
+    }
+    if starts.is_empty() {
+        starts.push(".");
+    }
+    let mut ok = true;
+    for start in starts {
+        ok &= find_in(start, 0, &mut Vec::new(), &filter);
+    }
+    if ok {
+        0
+    } else {
+        1
+    }
+}
 
-            if line_len > 0 {
-                shell_println!();
+/// What `du` has been asked for.
+struct DuOptions {
+    summarize: bool,
+    human: bool,
+}
+
+fn du_show(kib: u64, path: &str, opts: &DuOptions) {
+    if opts.human {
+        shell_println!("{}\t{}", human_size(kib * 1024), path);
+    } else {
+        shell_println!("{}\t{}", kib, path);
+    }
+}
+
+/// KiB used under `path`, each file rounded up to a whole KiB; prints the
+/// subdirectories' totals on the way unless summarizing. Symlinks count
+/// as themselves.
+fn du_in(path: &str, above: &mut Vec<u64>, opts: &DuOptions, ok: &mut bool) -> u64 {
+    let st = match with_vfs(|vfs| vfs.stat_at(None, path, false)) {
+        Ok(st) => st,
+        Err(e) => {
+            shell_eprintln!("du: {}: error {}", path, e.0);
+            *ok = false;
+            return 0;
+        }
+    };
+    let mut kib = st.size.div_ceil(1024);
+    if st.kind != FileType::Directory || above.contains(&st.ino) {
+        return kib;
+    }
+    let Some(children) = tree_children("du", path) else {
+        *ok = false;
+        return kib;
+    };
+    above.push(st.ino);
+    for child in children {
+        kib += du_in(&join_path(path, &child), above, opts, ok);
+    }
+    above.pop();
+    if !opts.summarize && !above.is_empty() {
+        du_show(kib, path, opts);
+    }
+    kib
+}
+
+/// `du [-sh] [path...]`: disk use in KiB of each directory under each
+/// path, or with -s of just the paths; -h as 4.0K, 12M.
+pub fn cmd_du(args: &[String]) -> i32 {
+    let mut opts = DuOptions { summarize: false, human: false };
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for c in flags.chars() {
+                    match c {
+                        's' => opts.summarize = true,
+                        'h' => opts.human = true,
+                        _ => {
+                            shell_eprintln!("du: invalid option -- '{}'", c);
+                            return 1;
+                        }
+                    }
+                }
             }
+            _ => paths.push(arg.as_str()),
         }
     }
+    if paths.is_empty() {
+        paths.push(".");
+    }
+    let mut ok = true;
+    for path in paths {
+        let kib = du_in(path, &mut Vec::new(), &opts, &mut ok);
+        du_show(kib, path, &opts);
+    }
+    if ok {
+        0
+    } else {
+        1
+    }
 }
 
-pub fn cmd_cd(args: &[String]) {
-    let path = args.first().map(|s| s.as_str()).unwrap_or("/home/root");
+pub fn cmd_cd(args: &[String], home: &str) {
+    let path = args.first().map(|s| s.as_str()).unwrap_or(home);
     with_vfs(|vfs| {
         if let Err(e) = vfs.cd(path) {
-            shell_println!("cd: {}: error {}", path, e.0);
+            shell_eprintln!("cd: {}: error {}", path, e.0);
         }
     });
 }
 
 pub fn cmd_pwd() {
     with_vfs(|vfs| {
-        shell_println!("{}", vfs.cwd_path);
+        shell_println!("{}", vfs.cwd_path());
     });
 }
 
 pub fn cmd_cat(args: &[String]) {
     if args.is_empty() {
-        shell_println!("cat: missing operand");
+        match io::read_stdin() {
+            Some(data) => io::write_bytes(&data),
+            None => shell_eprintln!("cat: missing operand"),
+        }
         return;
     }
     for path in args {
         match with_vfs(|vfs| vfs.read_file(path)) {
+            // Raw bytes are fine when redirected; the console wants text
+            Ok(data) if !io::stdout_is_console() => io::write_bytes(&data),
             Ok(data) => match core::str::from_utf8(&data) {
-                Ok(s) => {
-                    crate::drivers::serial::write_str(s);
-                    crate::drivers::vga::write_str(s);
+                Ok(s) => io::write_str(s),
+                Err(_) => shell_eprintln!("cat: {}: binary file", path),
+            },
+            Err(e) => shell_eprintln!("cat: {}: error {}", path, e.0),
+        }
+    }
+}
+
+/// Feed a text command its inputs: each file in `paths`, or stdin when
+/// there are none. Files that can't be read are reported and skipped;
+/// false if any was.
+fn each_input(cmd: &str, paths: &[&str], mut f: impl FnMut(&str, &[u8])) -> bool {
+    if paths.is_empty() {
+        return match io::read_stdin() {
+            Some(data) => {
+                f("-", &data);
+                true
+            }
+            None => {
+                shell_eprintln!("{}: missing operand", cmd);
+                false
+            }
+        };
+    }
+    let mut ok = true;
+    for &path in paths {
+        match with_vfs(|vfs| vfs.read_file(path)) {
+            Ok(data) => f(path, &data),
+            Err(e) => {
+                shell_eprintln!("{}: {}: error {}", cmd, path, e.0);
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+/// Lines of `data` without their newlines; a last line needn't have one.
+fn lines(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let text = data.strip_suffix(b"\n").unwrap_or(data);
+    // Nothing at all is no lines, not one empty one
+    text.split(|&b| b == b'\n').take(if data.is_empty() { 0 } else { usize::MAX })
+}
+
+fn write_line(prefix: &str, line: &[u8]) {
+    io::write_str(prefix);
+    io::write_bytes(line);
+    io::write_str("\n");
+}
+
+/// `grep [-ivnc] <pattern> [file...]`: lines containing `pattern`, a
+/// plain string. Status 0 if any line matched, 1 if none, 2 on error.
+pub fn cmd_grep(args: &[String]) -> i32 {
+    let (mut ignore_case, mut invert, mut number, mut count) = (false, false, false, false);
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() && rest.is_empty() => {
+                for c in flags.chars() {
+                    match c {
+                        'i' => ignore_case = true,
+                        'v' => invert = true,
+                        'n' => number = true,
+                        'c' => count = true,
+                        _ => {
+                            shell_eprintln!("grep: invalid option -- '{}'", c);
+                            return 2;
+                        }
+                    }
+                }
+            }
+            _ => rest.push(arg.as_str()),
+        }
+    }
+    let Some((&pattern, paths)) = rest.split_first() else {
+        shell_eprintln!("grep: usage: grep [-ivnc] <pattern> [file...]");
+        return 2;
+    };
+    let pattern = pattern.as_bytes();
+    let matches = |line: &[u8]| {
+        let found = pattern.is_empty()
+            || line.windows(pattern.len()).any(|w| {
+                if ignore_case {
+                    w.eq_ignore_ascii_case(pattern)
+                } else {
+                    w == pattern
+                }
+            });
+        found != invert
+    };
+
+    let mut any = false;
+    let ok = each_input("grep", paths, |path, data| {
+        let name = if paths.len() > 1 { alloc::format!("{}:", path) } else { String::new() };
+        let mut n = 0;
+        for (i, line) in lines(data).enumerate() {
+            if !matches(line) {
+                continue;
+            }
+            n += 1;
+            if count {
+                continue;
+            }
+            if number {
+                write_line(&alloc::format!("{}{}:", name, i + 1), line);
+            } else {
+                write_line(&name, line);
+            }
+        }
+        if count {
+            shell_println!("{}{}", name, n);
+        }
+        any |= n > 0;
+    });
+    match (ok, any) {
+        (false, _) => 2,
+        (true, true) => 0,
+        (true, false) => 1,
+    }
+}
+
+/// Line count and paths for head and tail: `-n N`, `-nN` or `-N`, ten by
+/// default.
+fn parse_count<'a>(cmd: &str, args: &'a [String]) -> Option<(usize, Vec<&'a str>)> {
+    let mut n = 10;
+    let mut paths = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let value = match arg.as_str() {
+            "-n" => it.next().map(|s| s.as_str()),
+            a if a.starts_with("-n") => Some(&a[2..]),
+            a if a.len() > 1 && a.starts_with('-') => Some(&a[1..]),
+            a => {
+                paths.push(a);
+                continue;
+            }
+        };
+        match value.and_then(|v| v.parse().ok()) {
+            Some(v) => n = v,
+            None => {
+                shell_eprintln!("{}: invalid number of lines: {}", cmd, arg);
+                return None;
+            }
+        }
+    }
+    Some((n, paths))
+}
+
+/// head and tail: the lines of each input that `pick` chooses, under a
+/// "==> name <==" header when there is more than one.
+fn head_tail(cmd: &str, args: &[String], pick: fn(&[u8], usize) -> Vec<&[u8]>) -> i32 {
+    let Some((n, paths)) = parse_count(cmd, args) else {
+        return 1;
+    };
+    let mut first = true;
+    let ok = each_input(cmd, &paths, |path, data| {
+        if paths.len() > 1 {
+            if !first {
+                shell_println!();
+            }
+            shell_println!("==> {} <==", path);
+        }
+        first = false;
+        for line in pick(data, n) {
+            write_line("", line);
+        }
+    });
+    if ok {
+        0
+    } else {
+        1
+    }
+}
+
+/// `head [-n N] [file...]`: the first N lines.
+pub fn cmd_head(args: &[String]) -> i32 {
+    head_tail("head", args, |data, n| lines(data).take(n).collect())
+}
+
+/// `tail [-n N] [file...]`: the last N lines.
+pub fn cmd_tail(args: &[String]) -> i32 {
+    head_tail("tail", args, |data, n| {
+        let all: Vec<&[u8]> = lines(data).collect();
+        all[all.len().saturating_sub(n)..].to_vec()
+    })
+}
+
+/// `wc [-lwc] [file...]`: lines, words and bytes of each input, and
+/// their total when there are several.
+pub fn cmd_wc(args: &[String]) -> i32 {
+    let (mut show_lines, mut show_words, mut show_bytes) = (false, false, false);
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('-') {
+            Some(flags) if !flags.is_empty() => {
+                for c in flags.chars() {
+                    match c {
+                        'l' => show_lines = true,
+                        'w' => show_words = true,
+                        'c' => show_bytes = true,
+                        _ => {
+                            shell_eprintln!("wc: invalid option -- '{}'", c);
+                            return 1;
+                        }
+                    }
+                }
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    if !(show_lines || show_words || show_bytes) {
+        (show_lines, show_words, show_bytes) = (true, true, true);
+    }
+    let show = |counts: [usize; 3], name: &str| {
+        let mut out = String::new();
+        for (count, on) in counts.iter().zip([show_lines, show_words, show_bytes]) {
+            if on {
+                out.push_str(&alloc::format!("{:>8}", count));
+            }
+        }
+        shell_println!("{} {}", out, name);
+    };
+
+    let mut total = [0usize; 3];
+    let ok = each_input("wc", &paths, |path, data| {
+        let counts = [
+            data.iter().filter(|&&b| b == b'\n').count(),
+            data.split(|b| b.is_ascii_whitespace()).filter(|w| !w.is_empty()).count(),
+            data.len(),
+        ];
+        for (t, c) in total.iter_mut().zip(counts) {
+            *t += c;
+        }
+        show(counts, if path == "-" { "" } else { path });
+    });
+    if paths.len() > 1 {
+        show(total, "total");
+    }
+    if ok {
+        0
+    } else {
+        1
+    }
+}
+
+/// `hexdump [-C] [-n len] [file...]`: the canonical hex+ASCII format,
+/// 16 bytes a line, runs of identical lines squeezed into a "*".
+pub fn cmd_hexdump(args: &[String]) -> i32 {
+    let mut limit = usize::MAX;
+    let mut paths = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-C" => {}
+            "-n" => match it.next().and_then(|v| v.parse().ok()) {
+                Some(n) => limit = n,
+                None => {
+                    shell_eprintln!("hexdump: -n needs a length");
+                    return 1;
                 }
-                Err(_) => shell_println!("cat: {}: binary file", path),
             },
-            Err(e) => shell_println!("cat: {}: error {}", path, e.0),
+            a => paths.push(a),
+        }
+    }
+    // Several files dump as one stream, as hexdump does
+    let mut data = Vec::new();
+    let ok = each_input("hexdump", &paths, |_, d| data.extend_from_slice(d));
+    data.truncate(limit);
+
+    let mut prev: Option<&[u8]> = None;
+    let mut squeezed = false;
+    for (i, row) in data.chunks(16).enumerate() {
+        if row.len() == 16 && prev == Some(row) {
+            if !squeezed {
+                shell_println!("*");
+                squeezed = true;
+            }
+            continue;
+        }
+        prev = Some(row);
+        squeezed = false;
+        let mut line = alloc::format!("{:08x} ", i * 16);
+        for j in 0..16 {
+            if j == 8 {
+                line.push(' ');
+            }
+            match row.get(j) {
+                Some(b) => line.push_str(&alloc::format!(" {:02x}", b)),
+                None => line.push_str("   "),
+            }
+        }
+        line.push_str("  |");
+        line.extend(row.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }));
+        line.push('|');
+        shell_println!("{}", line);
+    }
+    if !data.is_empty() {
+        shell_println!("{:08x}", data.len());
+    }
+    if ok {
+        0
+    } else {
+        1
+    }
+}
+
+/// One of the hashes in `lib::hash`, as a checksum command uses it.
+struct Checksum<H> {
+    cmd: &'static str,
+    new: fn() -> H,
+    update: fn(&mut H, &[u8]),
+    digest: fn(H) -> String,
+}
+
+impl<H> Checksum<H> {
+    /// Hash the file at `path` as it is read, so a big one needn't fit in
+    /// memory; "-" is stdin.
+    fn file(&self, path: &str) -> Result<String, Errno> {
+        let mut h = (self.new)();
+        if path == "-" {
+            let data = io::read_stdin().ok_or(Errno::EBADF)?;
+            (self.update)(&mut h, &data);
+            return Ok((self.digest)(h));
+        }
+        let file = with_vfs(|vfs| vfs.open(path, 0))?;
+        if file.inode.is_dir() {
+            return Err(Errno::EISDIR);
+        }
+        let mut buf = alloc::vec![0u8; 64 * 1024];
+        loop {
+            match file.read(&mut buf)? {
+                0 => break,
+                n => (self.update)(&mut h, &buf[..n]),
+            }
+        }
+        Ok((self.digest)(h))
+    }
+
+    /// `-c`: go through "digest  name" lines as the command itself prints
+    /// them, rehashing each name. False unless every one matched.
+    fn check(&self, list: &[u8]) -> bool {
+        let (mut failed, mut unreadable, mut malformed) = (0, 0, 0);
+        for line in lines(list) {
+            let line = core::str::from_utf8(line).unwrap_or("").trim_end_matches('\r');
+            if line.is_empty() {
+                continue;
+            }
+            // "  name" for text mode, " *name" for binary; the same here
+            let Some((want, name)) = line.split_once(' ') else {
+                malformed += 1;
+                continue;
+            };
+            let name = name.strip_prefix([' ', '*']).unwrap_or(name);
+            if !want.bytes().all(|b| b.is_ascii_hexdigit()) || name.is_empty() {
+                malformed += 1;
+                continue;
+            }
+            match self.file(name) {
+                Ok(got) if got.eq_ignore_ascii_case(want) => shell_println!("{}: OK", name),
+                Ok(_) => {
+                    shell_println!("{}: FAILED", name);
+                    failed += 1;
+                }
+                Err(e) => {
+                    shell_println!("{}: FAILED open or read (error {})", name, e.0);
+                    unreadable += 1;
+                }
+            }
+        }
+        if malformed > 0 {
+            shell_eprintln!("{}: {} improperly formatted line(s)", self.cmd, malformed);
+        }
+        if unreadable > 0 {
+            shell_eprintln!("{}: {} listed file(s) could not be read", self.cmd, unreadable);
+        }
+        if failed > 0 {
+            shell_eprintln!("{}: {} computed checksum(s) did NOT match", self.cmd, failed);
         }
+        failed == 0 && unreadable == 0 && malformed == 0
     }
+
+    /// `<cmd> [-c] [file...]`: the digest of each file (or stdin) in the
+    /// format the host's coreutils print, or with `-c`, check the files
+    /// named in such lists against it.
+    fn run(&self, args: &[String]) -> i32 {
+        let check = args.first().is_some_and(|a| a == "-c");
+        let mut paths: Vec<&str> = args[check as usize..].iter().map(String::as_str).collect();
+        if paths.is_empty() {
+            paths.push("-");
+        }
+        let mut ok = true;
+        for path in paths {
+            if check {
+                let list = if path == "-" {
+                    io::read_stdin().ok_or(Errno::EBADF)
+                } else {
+                    with_vfs(|vfs| vfs.read_file(path))
+                };
+                match list {
+                    Ok(list) => ok &= self.check(&list),
+                    Err(e) => {
+                        shell_eprintln!("{}: {}: error {}", self.cmd, path, e.0);
+                        ok = false;
+                    }
+                }
+                continue;
+            }
+            match self.file(path) {
+                Ok(sum) => shell_println!("{}  {}", sum, path),
+                Err(_) if path == "-" => {
+                    shell_eprintln!("{}: missing operand", self.cmd);
+                    ok = false;
+                }
+                Err(e) => {
+                    shell_eprintln!("{}: {}: error {}", self.cmd, path, e.0);
+                    ok = false;
+                }
+            }
+        }
+        if ok {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+pub fn cmd_md5sum(args: &[String]) -> i32 {
+    Checksum {
+        cmd: "md5sum",
+        new: hash::Md5::new,
+        update: hash::Md5::update,
+        digest: |h| hash::hex(&h.finish()),
+    }
+    .run(args)
+}
+
+pub fn cmd_sha256sum(args: &[String]) -> i32 {
+    Checksum {
+        cmd: "sha256sum",
+        new: hash::Sha256::new,
+        update: hash::Sha256::update,
+        digest: |h| hash::hex(&h.finish()),
+    }
+    .run(args)
 }
 
 pub fn cmd_echo(args: &[String]) {
@@ -125,15 +1040,104 @@ pub fn cmd_echo(args: &[String]) {
     shell_println!("{}", s);
 }
 
+/// "1.5" → 1_500_000_000; at most nanosecond precision.
+fn parse_seconds(s: &str) -> Option<u64> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if frac.len() > 9
+        || !frac.bytes().all(|b| b.is_ascii_digit())
+        || (int.is_empty() && frac.is_empty())
+    {
+        return None;
+    }
+    let secs: u64 = if int.is_empty() { 0 } else { int.parse().ok()? };
+    let mut ns = 0u64;
+    for (i, b) in frac.bytes().enumerate() {
+        ns += (b - b'0') as u64 * 10u64.pow(8 - i as u32);
+    }
+    secs.checked_mul(1_000_000_000)?.checked_add(ns)
+}
+
+pub fn cmd_sleep(args: &[String]) {
+    let Some(arg) = args.first() else {
+        shell_eprintln!("sleep: missing operand");
+        return;
+    };
+    let Some(ns) = parse_seconds(arg) else {
+        shell_eprintln!("sleep: invalid time interval '{}'", arg);
+        return;
+    };
+    if crate::proc::timers::sleep_ns(ns).is_err() {
+        shell_eprintln!("sleep: interrupted");
+    }
+}
+
+pub fn cmd_date() {
+    let now = DateTime::from_unix(crate::arch::x86_64::timer::realtime() / 1_000_000_000);
+    shell_println!(
+        "{} {} {:2} {:02}:{:02}:{:02} UTC {}",
+        now.weekday(),
+        now.month_name(),
+        now.day,
+        now.hour,
+        now.minute,
+        now.second,
+        now.year
+    );
+}
+
+/// `test`: the status of the expression, 0 for true, 1 for false and 2
+/// if it doesn't parse.
+pub fn cmd_test(args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    match eval_test(&args) {
+        Some(true) => 0,
+        Some(false) => 1,
+        None => {
+            shell_eprintln!("test: bad expression");
+            2
+        }
+    }
+}
+
+fn eval_test(args: &[&str]) -> Option<bool> {
+    let kind = |path: &str| with_vfs(|vfs| vfs.stat(path)).ok().map(|s| s.kind);
+    let num = |s: &str| s.parse::<i64>().ok();
+    Some(match *args {
+        [] => false,
+        ["!", ref rest @ ..] => !eval_test(rest)?,
+        [s] => !s.is_empty(),
+        ["-e", path] => kind(path).is_some(),
+        ["-f", path] => kind(path) == Some(FileType::Regular),
+        ["-d", path] => kind(path) == Some(FileType::Directory),
+        ["-z", s] => s.is_empty(),
+        ["-n", s] => !s.is_empty(),
+        [a, "=", b] => a == b,
+        [a, "!=", b] => a != b,
+        [a, op, b] => {
+            let (a, b) = (num(a)?, num(b)?);
+            match op {
+                "-eq" => a == b,
+                "-ne" => a != b,
+                "-lt" => a < b,
+                "-le" => a <= b,
+                "-gt" => a > b,
+                "-ge" => a >= b,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    })
+}
+
 pub fn cmd_mkdir(args: &[String]) {
     if args.is_empty() {
-        shell_println!("mkdir: missing operand");
+        shell_eprintln!("mkdir: missing operand");
         return;
     }
     for path in args {
         with_vfs(|vfs| {
             if let Err(e) = vfs.mkdir(path) {
-                shell_println!("mkdir: {}: error {}", path, e.0);
+                shell_eprintln!("mkdir: {}: error {}", path, e.0);
             }
         });
     }
@@ -141,15 +1145,19 @@ pub fn cmd_mkdir(args: &[String]) {
 
 pub fn cmd_touch(args: &[String]) {
     if args.is_empty() {
-        shell_println!("touch: missing operand");
+        shell_eprintln!("touch: missing operand");
         return;
     }
+    use crate::fs::vfs::{TimeSet, O_CREAT, O_WRONLY};
     for path in args {
-        with_vfs(|vfs| {
-            if let Err(e) = vfs.open(path, crate::fs::vfs::O_CREAT | crate::fs::vfs::O_WRONLY) {
-                shell_println!("touch: {}: error {}", path, e.0);
-            }
+        // Creates the file, or brings an existing one's times up to now
+        let res = with_vfs(|vfs| {
+            vfs.open(path, O_CREAT | O_WRONLY)?;
+            vfs.utimens_at(None, path, TimeSet::Now, TimeSet::Now, true)
         });
+        if let Err(e) = res {
+            shell_eprintln!("touch: {}: error {}", path, e.0);
+        }
     }
 }
 
@@ -170,7 +1178,7 @@ pub fn cmd_rm(args: &[String]) {
     for path in paths {
         with_vfs(|vfs| {
             if let Err(e) = vfs.unlink(path) {
-                shell_println!("rm: {}: error {}", path, e.0);
+                shell_eprintln!("rm: {}: error {}", path, e.0);
             }
         });
     }
@@ -178,13 +1186,13 @@ pub fn cmd_rm(args: &[String]) {
 
 pub fn cmd_rmdir(args: &[String]) {
     if args.is_empty() {
-        shell_println!("rmdir: missing operand");
+        shell_eprintln!("rmdir: missing operand");
         return;
     }
     for path in args {
         with_vfs(|vfs| {
             if let Err(e) = vfs.rmdir(path) {
-                shell_println!("rmdir: {}: error {}", path, e.0);
+                shell_eprintln!("rmdir: {}: error {}", path, e.0);
             }
         });
     }
@@ -192,19 +1200,19 @@ pub fn cmd_rmdir(args: &[String]) {
 
 pub fn cmd_mv(args: &[String]) {
     if args.len() < 2 {
-        shell_println!("mv: missing operand");
+        shell_eprintln!("mv: missing operand");
         return;
     }
     with_vfs(|vfs| {
         if let Err(e) = vfs.rename(&args[0], &args[1]) {
-            shell_println!("mv: error {}", e.0);
+            shell_eprintln!("mv: error {}", e.0);
         }
     });
 }
 
 pub fn cmd_cp(args: &[String]) {
     if args.len() < 2 {
-        shell_println!("cp: missing operand");
+        shell_eprintln!("cp: missing operand");
         return;
     }
     let result = with_vfs(|vfs| {
@@ -212,13 +1220,13 @@ pub fn cmd_cp(args: &[String]) {
         vfs.write_file(&args[1], &data)
     });
     if let Err(e) = result {
-        shell_println!("cp: error {}", e.0);
+        shell_eprintln!("cp: error {}", e.0);
     }
 }
 
 pub fn cmd_write(args: &[String]) {
     if args.len() < 2 {
-        shell_println!("write: usage: write <file> <text...>");
+        shell_eprintln!("write: usage: write <file> <text...>");
         return;
     }
     let content = args[1..].join(" ");
@@ -226,66 +1234,313 @@ pub fn cmd_write(args: &[String]) {
     data.push(b'\n');
     with_vfs(|vfs| {
         if let Err(e) = vfs.write_file(&args[0], &data) {
-            shell_println!("write: error {}", e.0);
+            shell_eprintln!("write: error {}", e.0);
         }
     });
 }
 
 pub fn cmd_stat(args: &[String]) {
     if args.is_empty() {
-        shell_println!("stat: missing operand");
+        shell_eprintln!("stat: missing operand");
         return;
     }
     for path in args {
         match with_vfs(|vfs| vfs.stat(path)) {
-            Err(e) => shell_println!("stat: {}: error {}", path, e.0),
+            Err(e) => shell_eprintln!("stat: {}: error {}", path, e.0),
             Ok(s) => {
                 let kind = match s.kind {
                     FileType::Regular => "regular file",
                     FileType::Directory => "directory",
                     FileType::Symlink => "symbolic link",
                     FileType::CharDevice => "character device",
+                    FileType::Fifo => "fifo",
+                    FileType::Socket => "socket",
                 };
                 shell_println!("  File: {}", path);
                 shell_println!("  Size: {}  Type: {}", s.size, kind);
                 shell_println!(" Inode: {}  Links: {}", s.ino, s.nlink);
-                shell_println!("  Mode: {:o}", s.mode);
+                shell_println!("  Mode: {:o}  Uid: {}  Gid: {}", s.mode, s.uid, s.gid);
+                let when = |t: Timespec| DateTime::from_unix(t.sec.max(0) as u64);
+                shell_println!("Access: {} UTC", when(s.atime));
+                shell_println!("Modify: {} UTC", when(s.mtime));
+                shell_println!("Change: {} UTC", when(s.ctime));
+            }
+        }
+    }
+}
+
+/// `tar -c|-x|-t [v] f <archive> ...`: create an archive of paths, extract
+/// one into the working directory (or `-C dir`), or list it. Creation
+/// writes ustar, or newc cpio for a .cpio name or `--format newc`;
+/// extraction takes either. The letters may come without the dash.
+pub fn cmd_tar(args: &[String]) -> i32 {
+    use crate::fs::archive::{self, Format};
+
+    const USAGE: &str =
+        "tar: usage: tar -cf <archive> <path>... | -xf <archive> [-C dir] | -tf <archive>";
+    let Some((flags, rest)) = args.split_first() else {
+        shell_eprintln!("{}", USAGE);
+        return 2;
+    };
+    let (mut mode, mut verbose, mut file) = (None, false, false);
+    for c in flags.trim_start_matches('-').chars() {
+        match c {
+            'c' | 'x' | 't' => mode = Some(c),
+            'v' => verbose = true,
+            'f' => file = true,
+            _ => {
+                shell_eprintln!("tar: invalid option -- '{}'", c);
+                return 2;
+            }
+        }
+    }
+    let (Some(mode), true, Some((archive_path, rest))) = (mode, file, rest.split_first()) else {
+        shell_eprintln!("{}", USAGE);
+        return 2;
+    };
+
+    let mut dir = ".";
+    let mut format = None;
+    let mut paths = Vec::new();
+    let mut it = rest.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-C" => match it.next() {
+                Some(d) => dir = d,
+                None => {
+                    shell_eprintln!("tar: -C needs a directory");
+                    return 2;
+                }
+            },
+            "--format=newc" => format = Some(Format::Newc),
+            "--format=ustar" => format = Some(Format::Ustar),
+            a => paths.push(a),
+        }
+    }
+
+    if mode == 'c' {
+        if paths.is_empty() {
+            shell_eprintln!("tar: nothing to archive");
+            return 2;
+        }
+        let format = format.unwrap_or(if archive_path.ends_with(".cpio") {
+            Format::Newc
+        } else {
+            Format::Ustar
+        });
+        let res = with_vfs(|vfs| {
+            let (data, sum) = archive::create(vfs, &paths, format)?;
+            vfs.write_file(archive_path, &data)?;
+            Ok::<_, crate::fs::vfs::Errno>(sum)
+        });
+        return match res {
+            Ok(sum) => {
+                if verbose {
+                    shell_println!(
+                        "tar: {} files ({} KiB), {} dirs, {} symlinks",
+                        sum.files,
+                        sum.bytes.div_ceil(1024),
+                        sum.dirs,
+                        sum.symlinks
+                    );
+                }
+                0
+            }
+            Err(e) => {
+                shell_eprintln!("tar: {}: error {}", archive_path, e.0);
+                1
+            }
+        };
+    }
+
+    let data = match with_vfs(|vfs| vfs.read_file(archive_path)) {
+        Ok(d) => d,
+        Err(e) => {
+            shell_eprintln!("tar: {}: error {}", archive_path, e.0);
+            return 1;
+        }
+    };
+    if mode == 't' || verbose {
+        match archive::list(&data) {
+            Ok(names) => names.iter().for_each(|n| shell_println!("{}", n)),
+            Err(e) => {
+                shell_eprintln!("tar: {}: {}", archive_path, e);
+                return 1;
             }
         }
     }
+    if mode == 't' {
+        return 0;
+    }
+    match with_vfs(|vfs| archive::extract(vfs, &data, dir)) {
+        Ok(sum) if sum.skipped > 0 => {
+            shell_eprintln!("tar: {} members could not be extracted", sum.skipped);
+            1
+        }
+        Ok(_) => 0,
+        Err(e) => {
+            shell_eprintln!("tar: {}: {}", archive_path, e);
+            1
+        }
+    }
 }
 
 pub fn cmd_ln(args: &[String]) {
     if args.len() < 3 || args[0] != "-s" {
-        shell_println!("ln: usage: ln -s <target> <link>");
+        shell_eprintln!("ln: usage: ln -s <target> <link>");
         return;
     }
     with_vfs(|vfs| {
         if let Err(e) = vfs.symlink(&args[1], &args[2]) {
-            shell_println!("ln: error {}", e.0);
+            shell_eprintln!("ln: error {}", e.0);
         }
     });
 }
 
-pub fn cmd_run(args: &[String]) {
+/// A chmod mode: octal, or symbolic clauses like "u+x,go-w" applied to
+/// `old`. No who means everyone.
+fn parse_mode(spec: &str, old: u32) -> Option<u32> {
+    if spec.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return u32::from_str_radix(spec, 8).ok().filter(|&m| m <= 0o7777);
+    }
+    let mut mode = old;
+    for clause in spec.split(',') {
+        let (who, rest) = clause.split_at(clause.find(['+', '-', '='])?);
+        let mut mask = 0;
+        for c in who.chars() {
+            mask |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+        if who.is_empty() {
+            mask = 0o7777;
+        }
+        let mut bits = 0;
+        for c in rest[1..].chars() {
+            bits |= match c {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                's' => 0o6000,
+                't' => 0o1000,
+                _ => return None,
+            };
+        }
+        bits &= mask;
+        mode = match rest.as_bytes()[0] {
+            b'+' => mode | bits,
+            b'-' => mode & !bits,
+            _ => (mode & !mask) | bits,
+        };
+    }
+    Some(mode)
+}
+
+/// `chmod <mode> <path>...`. Status 1 if any path failed.
+pub fn cmd_chmod(args: &[String]) -> i32 {
+    if args.len() < 2 {
+        shell_eprintln!("chmod: usage: chmod <mode> <path>...");
+        return 2;
+    }
+    let mut status = 0;
+    for path in &args[1..] {
+        let res = with_vfs(|vfs| {
+            let old = vfs.stat(path)?.mode;
+            match parse_mode(&args[0], old) {
+                Some(mode) => vfs.chmod(path, mode).map(|_| true),
+                None => Ok(false),
+            }
+        });
+        match res {
+            Ok(true) => {}
+            Ok(false) => {
+                shell_eprintln!("chmod: invalid mode: {}", args[0]);
+                return 2;
+            }
+            Err(e) => {
+                shell_eprintln!("chmod: {}: error {}", path, e.0);
+                status = 1;
+            }
+        }
+    }
+    status
+}
+
+/// A user or group id; there is no user database, so only root by name.
+fn parse_id(s: &str) -> Option<u32> {
+    if s == "root" {
+        Some(0)
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// How `ls` shows an id: root by name, everyone else by number.
+fn id_name(id: u32) -> String {
+    if id == 0 {
+        String::from("root")
+    } else {
+        id.to_string()
+    }
+}
+
+/// `chown <uid>[:gid] <path>...`, either id may be left out (":0",
+/// "100:"). Symlinks are changed themselves, as with `chown -h`.
+pub fn cmd_chown(args: &[String]) -> i32 {
+    if args.len() < 2 {
+        shell_eprintln!("chown: usage: chown <uid>[:gid] <path>...");
+        return 2;
+    }
+    let (user, group) = args[0].split_once(':').unwrap_or((&args[0], ""));
+    let id = |s: &str| if s.is_empty() { Some(None) } else { parse_id(s).map(Some) };
+    let (Some(uid), Some(gid)) = (id(user), id(group)) else {
+        shell_eprintln!("chown: invalid owner: {}", args[0]);
+        return 2;
+    };
+    let mut status = 0;
+    for path in &args[1..] {
+        if let Err(e) = with_vfs(|vfs| vfs.chown(path, uid, gid, false)) {
+            shell_eprintln!("chown: {}: error {}", path, e.0);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// Start an ELF binary as a child of the shell, wired to this stage's
+/// stdin and stdout, in process group `pgid` (a group of its own for
+/// None); `trace` logs its syscalls, as `strace` does. Returns its pid;
+/// waiting for it is up to the caller.
+pub fn cmd_run(
+    args: &[String],
+    envp: &[Vec<u8>],
+    pgid: Option<u32>,
+    trace: bool,
+) -> Option<u32> {
     if args.is_empty() {
-        shell_println!("run: usage: run <path> [args...]");
-        return;
+        let cmd = if trace { "strace" } else { "run" };
+        shell_eprintln!("{}: usage: {} <path> [args...]", cmd, cmd);
+        return None;
     }
     let path = &args[0];
 
     let elf_data = match with_vfs(|vfs| vfs.read_file(path)) {
         Ok(d) => d,
         Err(e) => {
-            shell_println!("run: {}: error {}", path, e.0);
-            return;
+            shell_eprintln!("run: {}: error {}", path, e.0);
+            return None;
         }
     };
 
     if !crate::proc::elf::is_valid_elf(&elf_data) {
-        shell_println!("run: {}: not a valid ELF64 binary", path);
-        return;
+        shell_eprintln!("run: {}: not a valid ELF64 binary", path);
+        return None;
     }
+    let shell_pid = crate::proc::current_process().map_or(0, |p| p.lock().tgid);
 
     let argv: alloc::vec::Vec<alloc::vec::Vec<u8>> = args
         .iter()
@@ -296,23 +1551,62 @@ pub fn cmd_run(args: &[String]) {
         })
         .collect();
 
-    let envp: alloc::vec::Vec<alloc::vec::Vec<u8>> = alloc::vec![
-        b"PATH=/bin\0".to_vec(),
-        b"HOME=/root\0".to_vec(),
-        b"TERM=linux\0".to_vec(),
-    ];
-
-    match crate::proc::Process::new_user(path, &elf_data, &argv, &envp, 5) {
+    match crate::proc::Process::new_user(path, &elf_data, &argv, envp, 5) {
         Ok(proc) => {
-            let pid = proc.lock().pid;
+            // Wire the child's stdin/stdout to whatever this stage was given
+            let mut feed = None;
+            let pid = {
+                let mut p = proc.lock();
+                p.ppid = shell_pid;
+                p.pgid = pgid.unwrap_or(p.pid);
+                p.trace = trace;
+                let mut files = p.files.lock();
+                let tty = io::tty();
+                if !tty.is_console() {
+                    // Without files there, fds 0–2 would be the console's
+                    let f = crate::fs::devfs::tty_file(tty);
+                    for fd in 0..3 {
+                        files.install(fd, alloc::sync::Arc::clone(&f));
+                    }
+                }
+                match io::take_stdin() {
+                    io::Input::Console => {}
+                    io::Input::File(f) => files.install(0, f),
+                    io::Input::Data(d) => {
+                        let (rd, wr) = crate::fs::pipe::new_pipe();
+                        files.install(0, rd);
+                        feed = Some((wr, d));
+                    }
+                }
+                match io::stdout() {
+                    io::Output::Console => {}
+                    io::Output::File(f) => files.install(1, f),
+                    io::Output::Capture(_) => {
+                        let (rd, wr) = crate::fs::pipe::new_pipe();
+                        files.install(1, wr);
+                        io::set_next_stdin(rd);
+                    }
+                }
+                drop(files);
+                p.pid
+            };
             crate::proc::spawn(proc);
-            shell_println!("Spawned '{}' as pid {}", path, pid);
+            if let Some((wr, data)) = feed {
+                // Blocks while the pipe is full; dropping `wr` signals EOF
+                let _ = wr.write(&data);
+            }
+            Some(pid)
+        }
+        Err(e) => {
+            shell_eprintln!("run: {}: {}", path, e);
+            None
         }
-        Err(e) => shell_println!("run: {}: {}", path, e),
     }
 }
 
 pub fn cmd_mount(args: &[String]) {
+    use crate::drivers::block;
+
     if args.len() < 2 {
         // List mounts
         with_vfs(|vfs| {
@@ -323,59 +1617,421 @@ pub fn cmd_mount(args: &[String]) {
         return;
     }
 
-    // mount <drive_idx> <mountpoint>
-    let drive_idx: usize = match args[0].parse() {
-        Ok(n) => n,
-        Err(_) => { shell_println!("mount: invalid drive index"); return; }
+    if args[0] == "--bind" {
+        let [_, source, target] = args else {
+            shell_eprintln!("mount: usage: mount --bind <dir> <mountpoint>");
+            return;
+        };
+        with_vfs(|vfs| match vfs.mount_bind(source, target) {
+            Ok(()) => shell_println!("bound {} at {}", source, target),
+            Err(e) => shell_eprintln!("mount: {}: error {}", source, e.0),
+        });
+        return;
+    }
+
+    // mount <drive_idx | name> [partition] <mountpoint>; "hda 2" is "hda2"
+    let Some(mut dev) = block::lookup(&args[0]) else {
+        shell_eprintln!("mount: {}: no such drive", args[0]);
+        return;
     };
-    let mountpoint = args[1].as_str();
+    if let [_, part, _] = args {
+        let part = part.parse().ok().and_then(|n| block::partition(dev.name(), n));
+        match part {
+            Some(p) => dev = p,
+            None => {
+                shell_eprintln!("mount: {}: no partition {}", dev.name(), args[1]);
+                return;
+            }
+        }
+    }
+    let mountpoint = args[args.len() - 1].as_str();
 
-    match crate::fs::fat32::probe_drive(drive_idx) {
+    match crate::fs::probe_device(&dev) {
         Some(fs) => {
             with_vfs(|vfs| {
                 if let Err(e) = vfs.mount(mountpoint, fs) {
-                    shell_println!("mount: error {}", e.0);
+                    shell_eprintln!("mount: error {}", e.0);
                 } else {
-                    shell_println!("mounted drive {} at {}", drive_idx, mountpoint);
+                    shell_println!("mounted {} at {}", dev.name(), mountpoint);
                 }
             });
         }
-        None => shell_println!("mount: no supported filesystem found on drive {}", drive_idx),
+        None => shell_eprintln!("mount: no supported filesystem found on {}", dev.name()),
     }
 }
 
 pub fn cmd_umount(args: &[String]) {
     if args.is_empty() {
-        shell_println!("umount: usage: umount <mountpoint>");
+        shell_eprintln!("umount: usage: umount <mountpoint>");
         return;
     }
+    sync_disks();
     with_vfs(|vfs| {
         if let Err(e) = vfs.umount(&args[0]) {
-            shell_println!("umount: {}: error {}", args[0], e.0);
+            shell_eprintln!("umount: {}: error {}", args[0], e.0);
         }
     });
 }
 
 pub fn cmd_drives() {
-    let count = crate::drivers::ide::drive_count();
-    if count == 0 {
+    use crate::drivers::block::{self, BlockDevice};
+
+    let devs = block::devices();
+    if devs.is_empty() {
         shell_println!("No drives detected.");
         return;
     }
-    for i in 0..count {
-        if let Some(d) = crate::drivers::ide::drive_info(i) {
-            shell_println!(
-                "  hd{} — {} [{} MiB, LBA{}]",
-                (b'a' + i as u8) as char,
-                d.model,
-                d.size_mb(),
-                if d.lba48 { 48 } else { 28 },
-            );
+    for (i, d) in devs.iter().enumerate() {
+        shell_println!("  {} {} — {} [{} MiB]", i, d.name(), d.info(), d.size_mb());
+        for p in block::partitions(d.name()) {
+            shell_println!("      {} — {} [{} MiB]", p.name(), p.info(), p.size_mb());
         }
     }
 }
 
-pub fn cmd_lspci() {
+pub fn cmd_free(args: &[String]) {
+    use crate::mm::{pmm, PAGE_SIZE};
+
+    let free = pmm::free_pages() * PAGE_SIZE / 1024;
+    let total = pmm::total_pages() * PAGE_SIZE / 1024;
+    let shared = pmm::shared_frames() * PAGE_SIZE / 1024;
+    shell_println!("              total        free      shared");
+    shell_println!("Mem:      {:8} K  {:8} K  {:8} K", total, free, shared);
+
+    if args.iter().any(|a| a == "-v") {
+        shell_println!();
+        shell_println!("order  block      free blocks       free K");
+        for (order, n) in pmm::free_blocks().iter().enumerate() {
+            let block = (PAGE_SIZE << order) / 1024;
+            shell_println!("{:5}  {:6} K  {:11}  {:11}", order, block, n, n * block);
+        }
+    }
+}
+
+pub fn cmd_slabinfo() {
+    shell_print!("{}", crate::mm::heap::slabinfo());
+}
+
+/// Busy ticks of each task at the last `top` refresh, and when that was.
+struct TopSample {
+    ticks: u64,
+    busy: BTreeMap<u32, u64>,
+}
+
+/// One `top` screen, each line ending in "erase to end of line" so it can
+/// be drawn over the last one. %CPU is since `prev`, or over the task's
+/// lifetime without one; VIRT is what its VMAs cover.
+fn top_screen(prev: Option<&TopSample>, max_rows: usize) -> (String, TopSample) {
+    use crate::arch::x86_64::timer::{ticks, TIMER_HZ};
+    use crate::mm::{heap, pmm, PAGE_SIZE};
+    use core::fmt::Write;
+
+    let now = ticks();
+    let tasks = crate::proc::task_snapshot();
+    let mut rows: Vec<(u64, &crate::proc::TaskInfo)> = tasks
+        .iter()
+        .map(|t| {
+            let busy = t.utime + t.stime;
+            let (since, then) = match prev.and_then(|p| Some((p.ticks, *p.busy.get(&t.pid)?))) {
+                Some((at, then)) => (at, then),
+                None => (t.start_time, 0),
+            };
+            let tenths = busy.saturating_sub(then) * 1000 / now.saturating_sub(since).max(1);
+            (tenths, t)
+        })
+        .collect();
+    rows.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.pid.cmp(&b.1.pid)));
+
+    let running = tasks.iter().filter(|t| t.state.code() == 'R').count();
+    let heap = heap::stats();
+    let slab_bytes: usize = heap.caches.iter().map(|c| c.bytes).sum();
+    let kib = |pages: usize| pages * PAGE_SIZE / 1024;
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "up {} s, {} tasks, {} running\x1b[K\n\
+         Mem: {} K total, {} K free, {} K shared\x1b[K\n\
+         Heap: {} K in slabs, {} K in {} large pages\x1b[K\n\x1b[K\n",
+        now / TIMER_HZ,
+        tasks.len(),
+        running,
+        kib(pmm::total_pages()),
+        kib(pmm::free_pages()),
+        kib(pmm::shared_frames()),
+        slab_bytes / 1024,
+        heap.large.bytes / 1024,
+        heap.large.pages,
+    );
+    let _ = writeln!(out, "  PID  PPID S PRI  NI  %CPU     VIRT      TIME WCHAN    NAME\x1b[K");
+    for (tenths, t) in rows.iter().take(max_rows) {
+        let cs = (t.utime + t.stime) * 100 / TIMER_HZ;
+        let _ = writeln!(
+            out,
+            "{:5} {:5} {} {:3} {:3} {:3}.{} {:7}K {:3}:{:02}.{:02} {:8} {}\x1b[K",
+            t.pid,
+            t.ppid,
+            t.state.code(),
+            t.priority,
+            t.nice,
+            tenths / 10,
+            tenths % 10,
+            t.vsize / 1024,
+            cs / 6000,
+            cs / 100 % 60,
+            cs % 100,
+            if t.wchan.is_empty() { "-" } else { t.wchan },
+            t.name
+        );
+    }
+    let busy = tasks.iter().map(|t| (t.pid, t.utime + t.stime)).collect();
+    (out, TopSample { ticks: now, busy })
+}
+
+/// `top [-b]`: the tasks by CPU use, redrawn every second until 'q'.
+/// With -b, or when the output isn't the terminal, a single screen.
+pub fn cmd_top(args: &[String]) {
+    if args.iter().any(|a| a == "-b") || !io::stdout_is_console() {
+        let (screen, _) = top_screen(None, usize::MAX);
+        io::write_str(&screen.replace("\x1b[K", ""));
+        return;
+    }
+    let tty = io::tty();
+    // Header and column titles take five lines, the cursor one more
+    let max_rows = (tty.winsize().rows as usize).saturating_sub(6).max(1);
+    tty.with_raw(|| {
+        let mut prev = None;
+        tty.write_str("\x1b[2J");
+        loop {
+            let (screen, sample) = top_screen(prev.as_ref(), max_rows);
+            tty.write_str("\x1b[H");
+            tty.write_str(&screen);
+            tty.write_str("\x1b[J");
+            prev = Some(sample);
+            // A second in tenths, looking for a key in between
+            for _ in 0..10 {
+                if crate::proc::timers::sleep_ns(100_000_000).is_err() || tty.hung_up() {
+                    return;
+                }
+                if let Some(b'q' | b'Q' | 0x03) = tty.try_getc() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+/// `ps`: one line per task with its accumulated CPU time.
+pub fn cmd_ps() {
+    use crate::arch::x86_64::timer::TIMER_HZ;
+
+    shell_println!("  PID  PPID   UID S PRI  NI      TIME NAME");
+    for t in &crate::proc::task_snapshot() {
+        let cs = (t.utime + t.stime) * 100 / TIMER_HZ;
+        shell_println!(
+            "{:5} {:5} {:5} {} {:3} {:3} {:3}:{:02}.{:02} {}",
+            t.pid,
+            t.ppid,
+            t.uid,
+            t.state.code(),
+            t.priority,
+            t.nice,
+            cs / 6000,
+            cs / 100 % 60,
+            cs % 100,
+            t.name
+        );
+    }
+}
+
+/// `kill [-SIG | -s SIG] <pid>...`, SIGTERM unless told otherwise; the
+/// signal may also come after the pids (`kill 42 -9`). `kill -l` lists
+/// the signal names.
+pub fn cmd_kill(args: &[String]) -> i32 {
+    use crate::proc::signal::{self, SIGTERM};
+
+    if args.first().is_some_and(|a| a == "-l") {
+        for (sig, name) in signal::NAMES {
+            shell_println!("{:2}) SIG{}", sig, name);
+        }
+        return 0;
+    }
+    let mut sig = SIGTERM;
+    let mut pids = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let spec = match arg.as_str() {
+            "-s" => it.next().map(|s| s.as_str()),
+            a if a.len() > 1 && a.starts_with('-') => Some(&a[1..]),
+            a => {
+                match a.parse::<u32>() {
+                    Ok(pid) if pid > 0 => pids.push(pid),
+                    _ => {
+                        shell_eprintln!("kill: {}: not a pid", a);
+                        return 1;
+                    }
+                }
+                continue;
+            }
+        };
+        match spec.and_then(signal::parse) {
+            Some(s) => sig = s,
+            None => {
+                shell_eprintln!("kill: {}: unknown signal", arg);
+                return 1;
+            }
+        }
+    }
+    if pids.is_empty() {
+        shell_eprintln!("kill: usage: kill [-SIG] <pid>...");
+        return 2;
+    }
+    let mut status = 0;
+    for pid in pids {
+        if let Err(e) = signal::send(pid, sig) {
+            let why = match e {
+                crate::syscall::errno::ESRCH => "no such process",
+                crate::syscall::errno::EPERM => "kernel tasks can't be signalled",
+                _ => "failed",
+            };
+            shell_eprintln!("kill: ({}): {}", pid, why);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// `renice <nice> <pid>...`: set the nice value (-20 to 19) of processes.
+pub fn cmd_renice(args: &[String]) -> i32 {
+    use crate::proc::priority::{sys_setpriority, PRIO_PROCESS};
+
+    let Some((nice, pids)) = args.split_first().filter(|(_, pids)| !pids.is_empty()) else {
+        shell_eprintln!("renice: usage: renice <nice> <pid>...");
+        return 2;
+    };
+    let Ok(nice) = nice.parse::<i32>() else {
+        shell_eprintln!("renice: {}: not a number", nice);
+        return 2;
+    };
+    let mut status = 0;
+    for arg in pids {
+        let Ok(pid) = arg.parse::<u32>() else {
+            shell_eprintln!("renice: {}: not a pid", arg);
+            status = 1;
+            continue;
+        };
+        let why = match -sys_setpriority(PRIO_PROCESS, pid, nice) {
+            0 => continue,
+            crate::syscall::errno::ESRCH => "no such process",
+            _ => "permission denied",
+        };
+        shell_eprintln!("renice: ({}): {}", pid, why);
+        status = 1;
+    }
+    status
+}
+
+/// Flush the block cache, reporting failures on the console.
+pub fn sync_disks() {
+    if let Err(e) = crate::drivers::bcache::sync() {
+        shell_eprintln!("sync: {}", e);
+    }
+}
+
+pub fn cmd_sync(args: &[String]) {
+    use crate::drivers::bcache::{self, Policy};
+
+    let policy = match args.first().map(|s| s.as_str()) {
+        None => None,
+        Some("wb") | Some("write-back") => Some(Policy::WriteBack),
+        Some("wt") | Some("write-through") => Some(Policy::WriteThrough),
+        Some(other) => {
+            shell_eprintln!("sync: unknown policy '{}' (use wb or wt)", other);
+            return;
+        }
+    };
+
+    match bcache::sync() {
+        Ok(n) => shell_println!("sync: {} sector(s) written", n),
+        Err(e) => shell_eprintln!("sync: {}", e),
+    }
+    if let Some(p) = policy {
+        if let Err(e) = bcache::set_policy(p) {
+            shell_eprintln!("sync: {}", e);
+        }
+    }
+
+    let st = bcache::stats();
+    shell_println!(
+        "cache: {:?}, {} sectors cached, {} dirty, {} hits / {} misses, {} written back",
+        bcache::policy(), st.cached, st.dirty, st.hits, st.misses, st.writebacks,
+    );
+}
+
+/// `smartctl [-a] [drive]`: health, temperature and reallocated sectors of
+/// every drive that keeps S.M.A.R.T. data (or just `drive`); `-a` adds the
+/// whole attribute table.
+pub fn cmd_smartctl(args: &[String]) -> i32 {
+    use crate::drivers::block;
+    use crate::drivers::smart::attr_name;
+
+    let all = args.iter().any(|a| a == "-a");
+    let devs = match args.iter().find(|a| *a != "-a") {
+        Some(spec) => match block::lookup(spec) {
+            Some(d) => alloc::vec![d],
+            None => {
+                shell_eprintln!("smartctl: {}: no such drive", spec);
+                return 1;
+            }
+        },
+        None => block::devices(),
+    };
+
+    let mut status = 0;
+    let mut shown = 0;
+    for d in &devs {
+        let report = match d.smart() {
+            None => continue,
+            Some(Ok(r)) => r,
+            Some(Err(e)) => {
+                shell_eprintln!("smartctl: {}: {}", d.name(), e);
+                status = 1;
+                continue;
+            }
+        };
+        shown += 1;
+        shell_println!("{}: {}", d.name(), d.info());
+        shell_println!("  health:      {}", if report.healthy { "PASSED" } else { "FAILING" });
+        match report.temperature() {
+            Some(t) => shell_println!("  temperature: {} C", t),
+            None => shell_println!("  temperature: n/a"),
+        }
+        match report.reallocated() {
+            Some(n) => shell_println!("  reallocated: {} sector(s)", n),
+            None => shell_println!("  reallocated: n/a"),
+        }
+        if !report.healthy {
+            status = 2;
+        }
+        if all {
+            shell_println!("  ID  ATTRIBUTE                 FLAGS VALUE WORST  RAW");
+            for a in &report.attrs {
+                shell_println!(
+                    "  {:3} {:<25} {:04x}  {:3}   {:3}  {}",
+                    a.id, attr_name(a.id), a.flags, a.value, a.worst, a.raw,
+                );
+            }
+        }
+    }
+    if shown == 0 && status == 0 {
+        shell_println!("No drives with S.M.A.R.T. data.");
+    }
+    status
+}
+
+pub fn cmd_lspci(args: &[String]) {
+    use crate::drivers::pci::{cap_name, ext_cap_name};
+
+    let verbose = args.iter().any(|a| a == "-v");
     crate::drivers::pci::devices(|d| {
         shell_println!(
             "{:02x}:{:02x}.{} [{:04x}:{:04x}] {}",
@@ -383,40 +2039,322 @@ pub fn cmd_lspci() {
             d.vendor_id, d.device_id,
             d.class_name(),
         );
+        if !verbose {
+            return;
+        }
+        for (id, off) in d.capabilities() {
+            shell_println!("        [{:02x}] capability {:02x}: {}", off, id, cap_name(id));
+        }
+        for (id, ver, off) in d.ext_capabilities() {
+            shell_println!(
+                "        [{:03x}] extended capability {:04x} v{}: {}",
+                off, id, ver, ext_cap_name(id),
+            );
+        }
     });
 }
 
+fn show_interface(iface: &crate::drivers::net::Interface) {
+    use crate::drivers::net::format_mac;
+
+    let dev = &iface.dev;
+    let st = iface.stats();
+    let link = if dev.link_up() { "UP" } else { "DOWN" };
+    shell_println!("{}: link {}  {}", dev.name(), link, dev.info());
+    match crate::net::config(iface.index) {
+        Some(c) if c.gateway.is_unspecified() => {
+            shell_println!("        inet {}  netmask {}", c.addr, c.netmask);
+        }
+        Some(c) => {
+            shell_println!("        inet {}  netmask {}  gateway {}", c.addr, c.netmask, c.gateway);
+        }
+        None => shell_println!("        inet not configured"),
+    }
+    shell_println!("        ether {}", format_mac(&dev.mac()));
+    shell_println!(
+        "        RX packets {}  bytes {}  dropped {}",
+        st.rx_packets, st.rx_bytes, st.rx_dropped
+    );
+    shell_println!(
+        "        TX packets {}  bytes {}  errors {}",
+        st.tx_packets, st.tx_bytes, st.tx_errors
+    );
+}
+
+/// `ifconfig`: list the interfaces; `ifconfig eth0`: just that one;
+/// `ifconfig eth0 10.0.2.15/24 gw 10.0.2.2`: set its address (netmask /24
+/// unless given, as a length or with `netmask`).
+pub fn cmd_ifconfig(args: &[String]) -> i32 {
+    use crate::net::{IfConfig, Ipv4Addr};
+
+    let Some(name) = args.first() else {
+        let ifaces = crate::drivers::net::interfaces();
+        if ifaces.is_empty() {
+            shell_println!("No network interfaces.");
+        }
+        for iface in &ifaces {
+            show_interface(iface);
+        }
+        for ns in crate::net::dns::servers() {
+            shell_println!("nameserver {}", ns);
+        }
+        return 0;
+    };
+    let Some(iface) = crate::drivers::net::by_name(name) else {
+        shell_eprintln!("ifconfig: {}: no such interface", name);
+        return 1;
+    };
+    let Some(spec) = args.get(1) else {
+        show_interface(&iface);
+        return 0;
+    };
+
+    let bad = |what: &str, s: &str| {
+        shell_eprintln!("ifconfig: bad {} '{}'", what, s);
+        1
+    };
+    let (addr, len) = match spec.split_once('/') {
+        Some((a, l)) => match l.parse::<u32>() {
+            Ok(l) if l <= 32 => (a, l),
+            _ => return bad("prefix length", l),
+        },
+        None => (spec.as_str(), 24),
+    };
+    let Some(addr) = Ipv4Addr::parse(addr) else {
+        return bad("address", addr);
+    };
+    let mut cfg = IfConfig {
+        addr,
+        netmask: Ipv4Addr::netmask(len),
+        gateway: Ipv4Addr::UNSPECIFIED,
+    };
+    let mut rest = args[2..].iter();
+    while let Some(opt) = rest.next() {
+        let Some(val) = rest.next() else {
+            shell_eprintln!("ifconfig: {} needs an address", opt);
+            return 1;
+        };
+        let Some(ip) = Ipv4Addr::parse(val) else {
+            return bad("address", val);
+        };
+        match opt.as_str() {
+            "netmask" => cfg.netmask = ip,
+            "gw" => cfg.gateway = ip,
+            _ => {
+                shell_eprintln!("ifconfig: unknown option '{}'", opt);
+                return 1;
+            }
+        }
+    }
+    crate::net::configure(iface.index, cfg);
+    0
+}
+
+/// `ping [-c count] <host>`: one echo request a second, each given a
+/// second to come back. Status 0 if any reply came.
+pub fn cmd_ping(args: &[String]) -> i32 {
+    use crate::arch::x86_64::timer::nanos;
+    use crate::net::{dns, icmp};
+    use core::sync::atomic::{AtomicU16, Ordering};
+
+    /// Tells concurrent pings' replies apart.
+    static NEXT_IDENT: AtomicU16 = AtomicU16::new(1);
+    const PAYLOAD: usize = 56;
+    const INTERVAL_NS: u64 = 1_000_000_000;
+
+    let mut count = 4u32;
+    let mut target = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if arg == "-c" {
+            match it.next().and_then(|n| n.parse().ok()) {
+                Some(n) if n > 0 => count = n,
+                _ => {
+                    shell_eprintln!("ping: -c needs a positive count");
+                    return 2;
+                }
+            }
+        } else {
+            target = Some(arg);
+        }
+    }
+    let Some(target) = target else {
+        shell_eprintln!("ping: usage: ping [-c count] <host>");
+        return 2;
+    };
+    let dst = match dns::resolve(target) {
+        Ok(addrs) => addrs[0],
+        Err(e) => {
+            shell_eprintln!("ping: {}: {}", target, e.as_str());
+            return 2;
+        }
+    };
+
+    let ident = NEXT_IDENT.fetch_add(1, Ordering::Relaxed);
+    let (mut transmitted, mut received) = (0, 0);
+    shell_println!("PING {} ({}) {} data bytes", target, dst, PAYLOAD);
+    for seq in 1..=count {
+        let sent = nanos();
+        if let Err(e) = icmp::send_echo(dst, ident, seq as u16, PAYLOAD) {
+            shell_eprintln!("ping: {}", e.as_str());
+            return 2;
+        }
+        transmitted += 1;
+        match icmp::wait_echo(ident, seq as u16, sent + INTERVAL_NS) {
+            Some(r) => {
+                let us = (nanos() - sent) / 1000;
+                received += 1;
+                shell_println!(
+                    "{} bytes from {}: icmp_seq={} ttl={} time={}.{:03} ms",
+                    r.len, r.from, r.seq, r.ttl, us / 1000, us % 1000
+                );
+            }
+            None => shell_println!("Request timeout for icmp_seq {}", seq),
+        }
+        if seq < count {
+            let wait = (sent + INTERVAL_NS).saturating_sub(nanos());
+            if crate::proc::timers::sleep_ns(wait).is_err() {
+                break;
+            }
+        }
+    }
+    shell_println!("--- {} ping statistics ---", dst);
+    shell_println!(
+        "{} packets transmitted, {} received, {}% packet loss",
+        transmitted,
+        received,
+        (transmitted - received) * 100 / transmitted
+    );
+    if received > 0 {
+        0
+    } else {
+        1
+    }
+}
+
+/// `host <name>`: the name's addresses, as host(1) prints them.
+pub fn cmd_host(args: &[String]) -> i32 {
+    let [name] = args else {
+        shell_eprintln!("host: usage: host <name>");
+        return 2;
+    };
+    match crate::net::dns::resolve(name) {
+        Ok(addrs) => {
+            for a in addrs {
+                shell_println!("{} has address {}", name, a);
+            }
+            0
+        }
+        Err(e) => {
+            shell_eprintln!("host: {}: {}", name, e.as_str());
+            1
+        }
+    }
+}
+
 pub fn cmd_view(args: &[String]) {
     if args.is_empty() {
-        shell_println!("view: usage: view <file.bmp>");
+        shell_eprintln!("view: usage: view <image.bmp|image.png>");
+        return;
+    }
+    if !io::tty().is_console() {
+        shell_eprintln!("view: only on the console");
         return;
     }
     let path = args[0].as_str();
     let data = match with_vfs(|vfs| vfs.read_file(path)) {
         Ok(d) => d,
         Err(e) => {
-            shell_println!("view: {}: error {}", path, e.0);
+            shell_eprintln!("view: {}: error {}", path, e.0);
             return;
         }
     };
-    match crate::drivers::bmp::decode(&data) {
-        Some(bmp) => {
+    match crate::drivers::bmp::decode_image(&data) {
+        Ok(bmp) => {
             shell_println!(
                 "Displaying {}x{} — press any key to exit",
                 bmp.width,
                 bmp.height
             );
             crate::drivers::vga::draw_bitmap(&bmp);
-            crate::drivers::keyboard::wait_key();
+            let tty = crate::drivers::tty::console();
+            tty.with_raw(|| tty.getc());
             crate::drivers::vga::clear();
         }
-        None => {
-            shell_println!("view: {}: unsupported format (24-bit uncompressed BMP only)", path);
+        Err(why) => shell_eprintln!("view: {}: {}", path, why),
+    }
+}
+
+/// `font [-s scale] [file.psf | default]`: the console font, a PSF file
+/// from the VFS or the built-in 8×16, drawn `scale` (1–4) times its size.
+/// With no arguments, shows what is in use.
+pub fn cmd_font(args: &[String]) -> i32 {
+    use crate::drivers::{psf::Font, vga};
+    const USAGE: &str = "font: usage: font [-s 1-4] [file.psf | default]";
+
+    let (mut scale, mut source) = (None, None);
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match arg.as_str() {
+            "-s" => match it.next().and_then(|v| v.parse().ok()) {
+                Some(n) => scale = Some(n),
+                None => {
+                    shell_eprintln!("{}", USAGE);
+                    return 1;
+                }
+            },
+            a if a.starts_with('-') || source.is_some() => {
+                shell_eprintln!("{}", USAGE);
+                return 1;
+            }
+            a => source = Some(a),
+        }
+    }
+    if scale.is_none() && source.is_none() {
+        let (w, h, scale) = vga::font_info();
+        let (cols, rows) = vga::text_size();
+        shell_println!("{}x{} font at {}x: {}x{} text", w, h, scale, cols, rows);
+        return 0;
+    }
+
+    let font = match source {
+        None => None,
+        Some("default") => Some(Font::builtin()),
+        Some(path) => {
+            let data = match with_vfs(|vfs| vfs.read_file(path)) {
+                Ok(d) => d,
+                Err(e) => {
+                    shell_eprintln!("font: {}: error {}", path, e.0);
+                    return 1;
+                }
+            };
+            match Font::parse(&data) {
+                Ok(f) => Some(f),
+                Err(why) => {
+                    shell_eprintln!("font: {}: {}", path, why);
+                    return 1;
+                }
+            }
+        }
+    };
+    match vga::set_font(font, scale) {
+        Ok((cols, rows)) => {
+            crate::drivers::tty::resized();
+            shell_println!("{}x{} text", cols, rows);
+            0
+        }
+        Err(why) => {
+            shell_eprintln!("font: {}", why);
+            1
         }
     }
 }
 
 pub fn cmd_clear() {
+    if !io::tty().is_console() {
+        io::tty().write_str("\x1b[2J\x1b[H");
+        return;
+    }
     crate::drivers::vga::clear();
     crate::drivers::serial::write_str("\x1b[2J\x1b[H");
 }
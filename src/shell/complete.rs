@@ -0,0 +1,104 @@
+//! Tab completion for readline: command names (builtins and /bin) for the
+//! first word of a pipeline stage or statement, VFS paths everywhere else.
+use crate::fs::mount::with_vfs;
+use crate::fs::vfs::FileType;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub struct Candidate {
+    /// Replacement for the whole word being completed.
+    pub text: String,
+    /// What the candidate list shows: the last path component.
+    pub display: String,
+    pub is_dir: bool,
+}
+
+pub struct Completion {
+    /// Byte offset in the line where the word being completed starts.
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+}
+
+/// Candidates for the word that ends at the end of `before` (the line up
+/// to the cursor), sorted.
+pub fn complete(before: &str) -> Completion {
+    let start = before.rfind([' ', '|', '&', ';', '<', '>']).map_or(0, |i| i + 1);
+    let word = &before[start..];
+    let head = before[..start].trim_end();
+    let command = head.is_empty() || head.ends_with(['|', '&', ';']);
+
+    let mut candidates = if command && !word.contains('/') {
+        commands(word)
+    } else {
+        paths(word)
+    };
+    candidates.sort_by(|a, b| a.text.cmp(&b.text));
+    candidates.dedup_by(|a, b| a.text == b.text);
+    Completion { start, candidates }
+}
+
+fn commands(prefix: &str) -> Vec<Candidate> {
+    let mut names: Vec<String> = super::BUILTINS
+        .iter()
+        .filter(|n| n.starts_with(prefix))
+        .map(|&n| String::from(n))
+        .collect();
+    if let Ok(entries) = with_vfs(|vfs| vfs.readdir_all("/bin")) {
+        names.extend(
+            entries
+                .into_iter()
+                .filter(|e| e.kind != FileType::Directory && e.name.starts_with(prefix))
+                .map(|e| e.name),
+        );
+    }
+    names
+        .into_iter()
+        .map(|n| Candidate {
+            display: n.clone(),
+            text: n,
+            is_dir: false,
+        })
+        .collect()
+}
+
+fn paths(word: &str) -> Vec<Candidate> {
+    let (dir, prefix) = match word.rfind('/') {
+        Some(i) => (&word[..=i], &word[i + 1..]),
+        None => ("", word),
+    };
+    let list_dir = if dir.is_empty() { "." } else { dir };
+    let Ok(entries) = with_vfs(|vfs| vfs.readdir_all(list_dir)) else {
+        return Vec::new();
+    };
+    entries
+        .into_iter()
+        .filter(|e| e.name != "." && e.name != "..")
+        // Dotfiles only when asked for
+        .filter(|e| {
+            e.name.starts_with(prefix) && (!e.name.starts_with('.') || prefix.starts_with('.'))
+        })
+        // The line editor only takes printable ASCII
+        .filter(|e| e.name.bytes().all(|b| (0x20..0x7F).contains(&b)))
+        .map(|e| Candidate {
+            text: alloc::format!("{}{}", dir, e.name),
+            is_dir: e.kind == FileType::Directory,
+            display: e.name,
+        })
+        .collect()
+}
+
+/// Longest prefix shared by every candidate's text.
+pub fn common_prefix(candidates: &[Candidate]) -> &str {
+    let Some(first) = candidates.first() else {
+        return "";
+    };
+    let mut len = first.text.len();
+    for c in &candidates[1..] {
+        len = first.text.as_bytes()[..len]
+            .iter()
+            .zip(c.text.as_bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    &first.text[..len]
+}
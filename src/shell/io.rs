@@ -0,0 +1,166 @@
+//! Standard input/output of the command being executed.
+//!
+//! Builtins print through `shell_print!` and read through `read_stdin`; the
+//! pipeline runner points these at the console, a file, or an in-memory
+//! capture that becomes the next stage's input. Errors go through
+//! `shell_eprintln!` to the console whatever stdout is, and mark the
+//! command as failed for `$?`, `&&` and `||`.
+//!
+//! Each shell task has its own set, found by its tgid, and its own
+//! terminal: "the console" here is that terminal, the screen's for the
+//! console shell, a network one for a remote session's.
+use crate::drivers::tty::{self, Tty};
+use crate::fs::vfs::File;
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub enum Input {
+    Console,
+    Data(Vec<u8>),
+    File(Arc<File>),
+}
+
+#[derive(Clone)]
+pub enum Output {
+    Console,
+    File(Arc<File>),
+    Capture(Arc<SpinLock<Vec<u8>>>),
+}
+
+struct Streams {
+    tty: &'static Tty,
+    stdin: Input,
+    stdout: Output,
+    /// Set by a stage that hands its output over as a file (e.g. a pipe
+    /// to a spawned process) instead of filling its capture buffer.
+    next_stdin: Option<Arc<File>>,
+    /// Exit status of the command being executed.
+    status: i32,
+}
+
+/// Shell tgid → its streams. A task without an entry is the console
+/// shell, which gets one on first use.
+static STREAMS: SpinLock<BTreeMap<u32, Streams>> = SpinLock::new(BTreeMap::new());
+
+fn shell_id() -> u32 {
+    crate::proc::current_process().map_or(0, |p| p.lock().tgid)
+}
+
+fn with<R>(f: impl FnOnce(&mut Streams) -> R) -> R {
+    let id = shell_id();
+    let mut streams = STREAMS.lock();
+    let s = streams.entry(id).or_insert_with(|| Streams {
+        tty: tty::console(),
+        stdin: Input::Console,
+        stdout: Output::Console,
+        next_stdin: None,
+        status: 0,
+    });
+    f(s)
+}
+
+/// Make `tty` the calling shell task's terminal.
+pub fn set_tty(tty: &'static Tty) {
+    with(|s| s.tty = tty);
+}
+
+/// The calling shell's terminal.
+pub fn tty() -> &'static Tty {
+    with(|s| s.tty)
+}
+
+/// The calling shell task is done: forget its streams.
+pub fn release() {
+    let id = shell_id();
+    STREAMS.lock().remove(&id);
+}
+
+pub fn set_stdin(input: Input) {
+    with(|s| s.stdin = input);
+}
+
+pub fn set_stdout(output: Output) {
+    with(|s| s.stdout = output);
+}
+
+/// Back to the console for both directions.
+pub fn reset() {
+    with(|s| {
+        s.stdin = Input::Console;
+        s.stdout = Output::Console;
+        s.next_stdin = None;
+    });
+}
+
+pub fn set_status(code: i32) {
+    with(|s| s.status = code);
+}
+
+pub fn status() -> i32 {
+    with(|s| s.status)
+}
+
+pub fn stdout() -> Output {
+    with(|s| s.stdout.clone())
+}
+
+pub fn stdout_is_console() -> bool {
+    with(|s| matches!(s.stdout, Output::Console))
+}
+
+/// Take the current input; subsequent reads see the console.
+pub fn take_stdin() -> Input {
+    with(|s| core::mem::replace(&mut s.stdin, Input::Console))
+}
+
+pub fn set_next_stdin(file: Arc<File>) {
+    with(|s| s.next_stdin = Some(file));
+}
+
+pub fn take_next_stdin() -> Option<Arc<File>> {
+    with(|s| s.next_stdin.take())
+}
+
+/// Whole redirected input, or None if stdin is the console.
+pub fn read_stdin() -> Option<Vec<u8>> {
+    match take_stdin() {
+        Input::Console => None,
+        Input::Data(d) => Some(d),
+        Input::File(f) => {
+            let mut data = Vec::new();
+            let mut chunk = [0u8; 512];
+            // Pipes block here until the writer side is closed
+            while let Ok(n) = f.read(&mut chunk) {
+                if n == 0 {
+                    break;
+                }
+                data.extend_from_slice(&chunk[..n]);
+            }
+            Some(data)
+        }
+    }
+}
+
+pub fn write_bytes(b: &[u8]) {
+    // Clone the sink out so a slow file write doesn't run under the lock
+    match stdout() {
+        Output::Console => tty().write(b),
+        Output::File(f) => {
+            let _ = f.write(b);
+        }
+        Output::Capture(buf) => buf.lock().extend_from_slice(b),
+    }
+}
+
+pub fn write_str(s: &str) {
+    write_bytes(s.as_bytes());
+}
+
+/// Error message: straight to the console, and the command has failed.
+pub fn write_err(s: &str) {
+    tty().write_str(s);
+    set_status(1);
+}
@@ -0,0 +1,184 @@
+//! Job control: the programs a pipeline started, waited for in the
+//! foreground, left running with `cmd &` or stopped with Ctrl-Z, and the
+//! `jobs`, `fg` and `bg` builtins. Each job is a process group; the one in
+//! the foreground gets the console's Ctrl-C and Ctrl-Z. Background jobs
+//! are polled before each prompt and reported once they finish.
+use super::{io, shell_eprintln, shell_print, shell_println, Shell};
+use crate::proc::fork::{wait_child, WaitStatus, WNOHANG, WUNTRACED};
+use crate::proc::signal::{self, SIGCONT, SIGINT};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+pub struct Job {
+    /// `%n`; 0 until the job is put in the background.
+    id: usize,
+    command: String,
+    /// The processes, each with how it ended once it has. The first one's
+    /// pid is the process group.
+    procs: Vec<(u32, Option<WaitStatus>)>,
+    /// The signal that stopped the job, while it is stopped.
+    stopped: Option<u32>,
+}
+
+impl Job {
+    pub fn new(command: String, pids: Vec<u32>) -> Self {
+        Job {
+            id: 0,
+            command,
+            procs: pids.into_iter().map(|pid| (pid, None)).collect(),
+            stopped: None,
+        }
+    }
+
+    fn pgid(&self) -> u32 {
+        self.procs[0].0
+    }
+
+    fn done(&self) -> bool {
+        self.procs.iter().all(|(_, s)| s.is_some())
+    }
+
+    /// What `$?` becomes: the status of the last process, as sh counts it.
+    fn status(&self) -> i32 {
+        if let Some(sig) = self.stopped {
+            return 128 + sig as i32;
+        }
+        match self.procs.last().and_then(|&(_, s)| s) {
+            Some(WaitStatus::Exited(code)) => code,
+            Some(WaitStatus::Signaled(sig) | WaitStatus::Stopped(sig)) => 128 + sig as i32,
+            Some(WaitStatus::Continued) | None => 0,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.procs.last().and_then(|&(_, s)| s) {
+            _ if self.stopped.is_some() => String::from("Stopped"),
+            None | Some(WaitStatus::Continued) => String::from("Running"),
+            Some(WaitStatus::Exited(0)) => String::from("Done"),
+            Some(WaitStatus::Exited(code)) => alloc::format!("Exit {}", code),
+            Some(WaitStatus::Signaled(sig) | WaitStatus::Stopped(sig)) => {
+                alloc::format!("Signal {}", sig)
+            }
+        }
+    }
+
+    /// Collect what happened to the processes still running, blocking for
+    /// each unless `options` has WNOHANG. Stops at the first one found
+    /// stopped: the job as a whole is then stopped.
+    fn update(&mut self, options: u32) {
+        for (pid, status) in self.procs.iter_mut().filter(|(_, s)| s.is_none()) {
+            match wait_child(*pid as i32, options | WUNTRACED) {
+                Ok(Some((_, WaitStatus::Stopped(sig)))) => {
+                    self.stopped = Some(sig);
+                    return;
+                }
+                Ok(Some((_, s))) => *status = Some(s),
+                Ok(None) => {}
+                // Not our child any more: nothing left to wait for
+                Err(_) => *status = Some(WaitStatus::Exited(0)),
+            }
+        }
+    }
+
+    /// SIGCONT to whatever is left of a stopped job.
+    fn resume(&mut self) {
+        self.stopped = None;
+        let _ = signal::send_group(self.pgid(), SIGCONT);
+    }
+}
+
+impl Shell {
+    /// Wait for `job` as the terminal's foreground group. A job that stops
+    /// joins the background list. Returns its status.
+    pub(super) fn foreground(&mut self, mut job: Job) -> i32 {
+        io::tty().set_foreground(job.pgid());
+        job.update(0);
+        io::tty().set_foreground(0);
+
+        let status = job.status();
+        if status == 128 + SIGINT as i32 {
+            // Ctrl-C left the cursor after whatever the program printed
+            shell_println!();
+        }
+        if job.stopped.is_some() {
+            if job.id == 0 {
+                job.id = self.next_job_id();
+            }
+            shell_println!();
+            shell_println!("[{}]+  Stopped    {}", job.id, job.command);
+            self.jobs.push(job);
+        }
+        status
+    }
+
+    /// `cmd &`: keep the job and give the prompt back.
+    pub(super) fn background(&mut self, mut job: Job) {
+        job.id = self.next_job_id();
+        let last = job.procs.last().map_or(0, |&(pid, _)| pid);
+        shell_println!("[{}] {}", job.id, last);
+        self.jobs.push(job);
+    }
+
+    fn next_job_id(&self) -> usize {
+        self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1
+    }
+
+    /// Before a prompt: tell which background jobs finished or stopped
+    /// since the last one, and forget the finished ones.
+    pub(super) fn report_jobs(&mut self) {
+        for job in &mut self.jobs {
+            let was_stopped = job.stopped.is_some();
+            job.update(WNOHANG);
+            if job.done() || (job.stopped.is_some() && !was_stopped) {
+                shell_println!("[{}]   {}    {}", job.id, job.describe(), job.command);
+            }
+        }
+        self.jobs.retain(|j| !j.done());
+    }
+
+    /// `%n` or `n`, or the most recent job without an argument: its index
+    /// in `jobs`.
+    fn find_job(&self, cmd: &str, args: &[String]) -> Option<usize> {
+        let index = match args.first() {
+            None => self.jobs.len().checked_sub(1),
+            Some(arg) => arg
+                .strip_prefix('%')
+                .unwrap_or(arg)
+                .parse::<usize>()
+                .ok()
+                .and_then(|id| self.jobs.iter().position(|j| j.id == id)),
+        };
+        if index.is_none() {
+            shell_eprintln!("{}: no such job", cmd);
+        }
+        index
+    }
+
+    pub(super) fn cmd_jobs(&mut self) {
+        self.report_jobs();
+        for job in &self.jobs {
+            shell_println!("[{}]   {}    {}", job.id, job.describe(), job.command);
+        }
+    }
+
+    /// `fg [job]`: continue the job in the foreground and wait for it.
+    pub(super) fn cmd_fg(&mut self, args: &[String]) -> i32 {
+        let Some(i) = self.find_job("fg", args) else {
+            return 1;
+        };
+        let mut job = self.jobs.remove(i);
+        shell_println!("{}", job.command);
+        job.resume();
+        self.foreground(job)
+    }
+
+    /// `bg [job]`: let a stopped job go on in the background.
+    pub(super) fn cmd_bg(&mut self, args: &[String]) {
+        let Some(i) = self.find_job("bg", args) else {
+            return;
+        };
+        let job = &mut self.jobs[i];
+        job.resume();
+        shell_println!("[{}]+ {} &", job.id, job.command);
+    }
+}
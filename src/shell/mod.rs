@@ -1,41 +1,156 @@
 mod builtins;
+mod complete;
+pub mod io;
+mod jobs;
 mod readline;
+mod script;
 
 use crate::fs::mount::with_vfs;
+use crate::fs::vfs::{O_APPEND, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY};
+use crate::sync::spinlock::SpinLock;
+use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 
+/// Names `Shell::execute` knows, for Tab completion.
+const BUILTINS: &[&str] = &[
+    "help", "ls", "cd", "pwd", "cat", "echo", "mkdir", "touch", "rm", "rmdir", "mv", "cp",
+    "write", "stat", "ln", "run", "mount", "umount", "drives", "sync", "lspci", "view", "clear",
+    "history", "uname", "uptime", "date", "sleep", "free", "slabinfo", "top", "reboot", "halt",
+    "poweroff", "sh", "exit", "true", "false", "test", "[", "export", "unset", "env",
+    "jobs", "fg", "bg", "ifconfig", "ping", "host", "smartctl", "chmod", "chown",
+    "grep", "head", "tail", "wc", "hexdump", "md5sum", "sha256sum", "ps", "kill", "find", "du",
+    "tar", "font", "strace", "renice",
+];
+
+/// Environment the console shell starts with.
+const DEFAULT_ENV: &[(&str, &str)] =
+    &[("PATH", "/bin"), ("HOME", "/home/root"), ("TERM", "linux")];
+
+/// Run by the console shell before its first prompt.
+const RC_SCRIPT: &str = "/etc/rc";
+
 pub struct Shell {
     pub history: Vec<String>,
+    /// Shell variables, set by `NAME=value` and `for`.
+    vars: BTreeMap<String, String>,
+    /// Exported variables: the environment of spawned processes. A name
+    /// lives in either this map or `vars`, never both.
+    env: BTreeMap<String, String>,
+    /// `$0`, `$1`, …: the running script and its arguments.
+    args: Vec<String>,
+    /// Exit status of the last pipeline, `$?`.
+    status: i32,
+    /// Set by `exit`: the rest of the script is skipped.
+    exit: Option<i32>,
+    /// Background and stopped jobs, oldest first.
+    jobs: Vec<jobs::Job>,
+    /// Pids started by the pipeline being run; the first one's is the
+    /// job's process group.
+    spawned: Vec<u32>,
 }
 
 impl Shell {
     pub fn new() -> Self {
         Self {
             history: Vec::new(),
+            vars: BTreeMap::new(),
+            env: DEFAULT_ENV
+                .iter()
+                .map(|&(k, v)| (String::from(k), String::from(v)))
+                .collect(),
+            args: alloc::vec![String::from("sh")],
+            status: 0,
+            exit: None,
+            jobs: Vec::new(),
+            spawned: Vec::new(),
         }
     }
 
-    pub fn run(&mut self) -> ! {
-        with_vfs(|vfs| {
-            if let Ok(motd) = vfs.read_file("/etc/motd") {
-                if let Ok(s) = core::str::from_utf8(&motd) {
-                    crate::drivers::vga::write_str(s);
-                }
+    /// Value of `$name`; unset variables expand to nothing.
+    fn var(&self, name: &str) -> String {
+        match name {
+            "?" => self.status.to_string(),
+            "#" => (self.args.len() - 1).to_string(),
+            "@" => self.args[1..].join(" "),
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => name
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| self.args.get(i))
+                .cloned()
+                .unwrap_or_default(),
+            _ => self
+                .vars
+                .get(name)
+                .or_else(|| self.env.get(name))
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+
+    /// `name=value`, into the environment if `name` is exported there.
+    fn set_var(&mut self, name: &str, value: &str) {
+        let map = if self.env.contains_key(name) {
+            &mut self.env
+        } else {
+            &mut self.vars
+        };
+        map.insert(name.into(), value.into());
+    }
+
+    /// The environment as `envp` strings for `Process::new_user`.
+    fn envp(&self) -> Vec<Vec<u8>> {
+        self.env
+            .iter()
+            .map(|(k, v)| {
+                let mut s = alloc::format!("{}={}", k, v).into_bytes();
+                s.push(0);
+                s
+            })
+            .collect()
+    }
+
+    /// `export [NAME[=value]...]`: move variables into the environment, or
+    /// list it.
+    fn cmd_export(&mut self, args: &[String]) {
+        if args.is_empty() {
+            for (k, v) in &self.env {
+                shell_println!("export {}={}", k, v);
             }
-        });
+            return;
+        }
+        for arg in args {
+            let (name, value) = match assignment(arg) {
+                Some((name, value)) => (name, Some(String::from(value))),
+                None if is_name(arg) => (arg.as_str(), None),
+                None => {
+                    shell_eprintln!("export: `{}`: not a valid identifier", arg);
+                    continue;
+                }
+            };
+            let old = self.vars.remove(name).or_else(|| self.env.remove(name));
+            self.env.insert(name.into(), value.or(old).unwrap_or_default());
+        }
+    }
+
+    /// Prompt, read, run, for ever on the console. Elsewhere `exit` (or
+    /// Ctrl-D, or the terminal hanging up) ends the session and returns.
+    pub fn run(&mut self) {
+        let console = io::tty().is_console();
+        if console && with_vfs(|vfs| vfs.stat(RC_SCRIPT)).is_ok() {
+            self.cmd_sh(&[String::from(RC_SCRIPT)]);
+        }
 
         loop {
+            self.report_jobs();
             let prompt = with_vfs(|vfs| {
                 alloc::format!(
                     "\x1b[32mroot@saros\x1b[0m:\x1b[34m{}\x1b[0m# ",
-                    vfs.cwd_path
+                    vfs.cwd_path()
                 )
             });
-            crate::drivers::serial::write_str(&prompt);
-            crate::drivers::vga::write_str(&prompt);
-
-            let line = readline::readline();
+            let line = readline::readline(&prompt, &self.history);
 
             if line.trim().is_empty() {
                 continue;
@@ -43,23 +158,150 @@ impl Shell {
 
             self.history.push(line.clone());
 
-            let args = parse_args(&line);
-            if args.is_empty() {
-                continue;
+            self.run_script(&line);
+            if !console && self.exit.is_some() {
+                return;
             }
+            // The console shell has nowhere to exit to
+            self.exit = None;
+        }
+    }
 
-            self.execute(&args);
+    /// One statement: pipelines joined by `&&` and `||`, each run or
+    /// skipped by the status of the one before, or ended by `&` to leave
+    /// its programs running in the background.
+    fn run_line(&mut self, line: &str) {
+        let list = match tokenize(line, &|name| self.var(name)).and_then(parse_list) {
+            Ok(list) => list,
+            Err(e) => {
+                shell_eprintln!("sh: syntax error: {}", e);
+                self.status = 2;
+                return;
+            }
+        };
+        for (join, pipeline) in &list {
+            if self.exit.is_some() {
+                return;
+            }
+            let run = match join {
+                Join::Then => true,
+                Join::And => self.status == 0,
+                Join::Or => self.status != 0,
+            };
+            if run {
+                self.run_pipeline(&pipeline.stages, pipeline.background);
+            }
+        }
+    }
+
+    /// Run `a | b | c`: stages execute one after another, each builtin's
+    /// output captured and fed to the next one as its stdin. The status of
+    /// the last stage is the pipeline's. Without a redirection the last
+    /// stage writes wherever the shell itself does (`sh script > file`).
+    /// The programs it starts make up a job, waited for unless `background`.
+    fn run_pipeline(&mut self, stages: &[Command], background: bool) {
+        let outer = io::stdout();
+        let outer_spawned = core::mem::take(&mut self.spawned);
+        let mut last_spawned = false;
+        let mut input = io::Input::Console;
+
+        for (i, cmd) in stages.iter().enumerate() {
+            let last = i + 1 == stages.len();
+            io::set_status(0);
+
+            if let Some(path) = &cmd.stdin {
+                match with_vfs(|vfs| vfs.open(path, O_RDONLY)) {
+                    Ok(f) => input = io::Input::File(f),
+                    Err(e) => {
+                        shell_eprintln!("sh: {}: error {}", path, e.0);
+                        break;
+                    }
+                }
+            }
+
+            let mut capture = None;
+            let output = if let Some((path, append)) = &cmd.stdout {
+                let mode = if *append { O_APPEND } else { O_TRUNC };
+                match with_vfs(|vfs| vfs.open(path, O_WRONLY | O_CREAT | mode)) {
+                    Ok(f) => io::Output::File(f),
+                    Err(e) => {
+                        shell_eprintln!("sh: {}: error {}", path, e.0);
+                        break;
+                    }
+                }
+            } else if last {
+                outer.clone()
+            } else {
+                let buf = Arc::new(SpinLock::new(Vec::new()));
+                capture = Some(Arc::clone(&buf));
+                io::Output::Capture(buf)
+            };
+
+            io::set_stdin(input);
+            io::set_stdout(output);
+            if !cmd.args.is_empty() {
+                let before = self.spawned.len();
+                self.execute(&cmd.args);
+                last_spawned = self.spawned.len() > before;
+            }
+            let handed_over = io::take_next_stdin();
+            io::reset();
+
+            input = match (handed_over, capture) {
+                (Some(f), _) => io::Input::File(f),
+                (None, Some(buf)) => io::Input::Data(core::mem::take(&mut *buf.lock())),
+                (None, None) => io::Input::Data(Vec::new()),
+            };
+        }
+        io::set_stdout(outer);
+        self.status = io::status();
+
+        let pids = core::mem::replace(&mut self.spawned, outer_spawned);
+        if pids.is_empty() {
+            return;
+        }
+        let command: Vec<String> = stages.iter().map(|c| c.args.join(" ")).collect();
+        let job = jobs::Job::new(command.join(" | "), pids);
+        if background {
+            self.background(job);
+        } else {
+            let status = self.foreground(job);
+            if last_spawned {
+                self.status = status;
+            }
         }
     }
 
     fn execute(&mut self, args: &[String]) {
+        let n = args.iter().take_while(|a| assignment(a).is_some()).count();
+        let assignments = args[..n].iter().filter_map(|a| assignment(a));
+        if n == args.len() {
+            for (name, value) in assignments {
+                self.set_var(name, value);
+            }
+            return;
+        }
+        if n > 0 {
+            // `NAME=value cmd`: in the environment for this command only
+            let saved: Vec<(String, Option<String>)> = assignments
+                .map(|(name, value)| (name.into(), self.env.insert(name.into(), value.into())))
+                .collect();
+            self.execute(&args[n..]);
+            for (name, old) in saved.into_iter().rev() {
+                match old {
+                    Some(v) => self.env.insert(name, v),
+                    None => self.env.remove(&name),
+                };
+            }
+            return;
+        }
         let cmd = args[0].as_str();
         let rest = &args[1..];
 
         match cmd {
             "help" => builtins::cmd_help(),
-            "ls" => builtins::cmd_ls(rest),
-            "cd" => builtins::cmd_cd(rest),
+            "ls" => io::set_status(builtins::cmd_ls(rest)),
+            "cd" => builtins::cmd_cd(rest, &self.var("HOME")),
             "pwd" => builtins::cmd_pwd(),
             "cat" => builtins::cmd_cat(rest),
             "echo" => builtins::cmd_echo(rest),
@@ -72,12 +314,84 @@ impl Shell {
             "write" => builtins::cmd_write(rest),
             "stat" => builtins::cmd_stat(rest),
             "ln" => builtins::cmd_ln(rest),
-            "run"    => builtins::cmd_run(rest),
+            "chmod" => io::set_status(builtins::cmd_chmod(rest)),
+            "chown" => io::set_status(builtins::cmd_chown(rest)),
+            "grep" => io::set_status(builtins::cmd_grep(rest)),
+            "head" => io::set_status(builtins::cmd_head(rest)),
+            "tail" => io::set_status(builtins::cmd_tail(rest)),
+            "wc" => io::set_status(builtins::cmd_wc(rest)),
+            "hexdump" => io::set_status(builtins::cmd_hexdump(rest)),
+            "md5sum" => io::set_status(builtins::cmd_md5sum(rest)),
+            "sha256sum" => io::set_status(builtins::cmd_sha256sum(rest)),
+            "ps" => builtins::cmd_ps(),
+            "kill" => io::set_status(builtins::cmd_kill(rest)),
+            "renice" => io::set_status(builtins::cmd_renice(rest)),
+            "find" => io::set_status(builtins::cmd_find(rest)),
+            "du" => io::set_status(builtins::cmd_du(rest)),
+            "tar" => io::set_status(builtins::cmd_tar(rest)),
+            "run" if rest.first().is_some_and(|p| script::is_script(p)) => {
+                let status = self.cmd_sh(rest);
+                io::set_status(status);
+            }
+            "run" | "strace" => {
+                // A pipeline's programs share the group of the first one
+                let pgid = self.spawned.first().copied();
+                let trace = cmd == "strace";
+                if let Some(pid) = builtins::cmd_run(rest, &self.envp(), pgid, trace) {
+                    self.spawned.push(pid);
+                }
+            }
+            "jobs" => self.cmd_jobs(),
+            "fg" => {
+                let status = self.cmd_fg(rest);
+                io::set_status(status);
+            }
+            "bg" => self.cmd_bg(rest),
+            "sh" => {
+                let status = self.cmd_sh(rest);
+                io::set_status(status);
+            }
+            "exit" => match rest.first().map(|s| s.parse::<i32>()) {
+                None => self.exit = Some(self.status),
+                Some(Ok(code)) => self.exit = Some(code),
+                Some(Err(_)) => {
+                    shell_eprintln!("exit: {}: numeric argument required", rest[0]);
+                    self.exit = Some(2);
+                }
+            },
+            "export" => self.cmd_export(rest),
+            "unset" => {
+                for name in rest {
+                    self.vars.remove(name);
+                    self.env.remove(name);
+                }
+            }
+            "env" => {
+                for (k, v) in &self.env {
+                    shell_println!("{}={}", k, v);
+                }
+            }
+            "true" => {}
+            "false" => io::set_status(1),
+            "test" => io::set_status(builtins::cmd_test(rest)),
+            "[" => match rest.split_last() {
+                Some((close, cond)) if close == "]" => io::set_status(builtins::cmd_test(cond)),
+                _ => {
+                    shell_eprintln!("[: missing `]`");
+                    io::set_status(2);
+                }
+            },
             "mount"  => builtins::cmd_mount(rest),
             "umount" => builtins::cmd_umount(rest),
             "drives" => builtins::cmd_drives(),
-            "lspci" => builtins::cmd_lspci(),
+            "sync" => builtins::cmd_sync(rest),
+            "lspci" => builtins::cmd_lspci(rest),
+            "smartctl" => io::set_status(builtins::cmd_smartctl(rest)),
+            "ifconfig" => io::set_status(builtins::cmd_ifconfig(rest)),
+            "ping" => io::set_status(builtins::cmd_ping(rest)),
+            "host" => io::set_status(builtins::cmd_host(rest)),
             "view" => builtins::cmd_view(rest),
+            "font" => io::set_status(builtins::cmd_font(rest)),
             "clear" => builtins::cmd_clear(),
             "history" => {
                 for (i, h) in self.history.iter().enumerate() {
@@ -89,64 +403,273 @@ impl Shell {
                 let ms = crate::arch::x86_64::timer::uptime_ms();
                 shell_println!("up {}m {}s", ms / 60000, (ms % 60000) / 1000);
             }
-            "free" => {
-                let free = crate::mm::pmm::free_pages() * crate::mm::PAGE_SIZE / 1024;
-                let total = crate::mm::pmm::total_pages() * crate::mm::PAGE_SIZE / 1024;
-                shell_println!("              total        free");
-                shell_println!("Mem:      {:8} K  {:8} K", total, free);
+            "date" => builtins::cmd_date(),
+            "sleep" => builtins::cmd_sleep(rest),
+            "free" => builtins::cmd_free(rest),
+            "slabinfo" => builtins::cmd_slabinfo(),
+            "top" => builtins::cmd_top(rest),
+            "reboot" => {
+                builtins::sync_disks();
+                crate::arch::x86_64::power::reboot();
             }
-            "reboot" => unsafe {
-                crate::arch::x86_64::io::outb(0x64, 0xFE);
-            },
             "halt" | "poweroff" => {
-                unsafe {
-                    crate::arch::x86_64::io::outw(0x604, 0x2000);
-                }
-                loop {
-                    crate::arch::x86_64::io::hlt();
+                builtins::sync_disks();
+                crate::arch::x86_64::power::poweroff();
+            }
+            _ => {
+                shell_eprintln!("{}: command not found", cmd);
+                io::set_status(127);
+            }
+        }
+    }
+}
+
+/// Whether `s` can be a variable name.
+fn is_name(s: &str) -> bool {
+    let mut bytes = s.bytes();
+    bytes.next().is_some_and(|b| b.is_ascii_alphabetic() || b == b'_')
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// `NAME=value` split in two, or None if `arg` is not an assignment.
+fn assignment(arg: &str) -> Option<(&str, &str)> {
+    arg.split_once('=').filter(|(name, _)| is_name(name))
+}
+
+/// One stage of a pipeline with its redirections.
+struct Command {
+    args: Vec<String>,
+    stdin: Option<String>,
+    stdout: Option<(String, bool)>, // (path, append)
+}
+
+enum Token {
+    Word(String),
+    Pipe,
+    In,
+    Out,
+    Append,
+    And,
+    Or,
+    /// A lone `&`.
+    Amp,
+}
+
+/// How a pipeline is joined to the one before it.
+#[derive(Clone, Copy)]
+enum Join {
+    Then,
+    And,
+    Or,
+}
+
+type Chars<'a> = core::iter::Peekable<core::str::Chars<'a>>;
+
+/// The name after a `$`: `NAME`, `{NAME}`, or one of `?`, `#`, `@` and a
+/// digit. None if what follows can't start one, leaving the `$` literal.
+fn var_name(chars: &mut Chars) -> Result<Option<String>, &'static str> {
+    match chars.peek().copied() {
+        Some('{') => {
+            chars.next();
+            let mut name = String::new();
+            loop {
+                match chars.next() {
+                    Some('}') => return Ok(Some(name)),
+                    Some(c) => name.push(c),
+                    None => return Err("unterminated `${`"),
                 }
             }
-            _ => shell_println!("{}: command not found", cmd),
         }
+        Some(c @ ('?' | '#' | '@' | '0'..='9')) => {
+            chars.next();
+            Ok(Some(c.to_string()))
+        }
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                name.push(c);
+                chars.next();
+            }
+            Ok(Some(name))
+        }
+        _ => Ok(None),
     }
 }
 
-fn parse_args(line: &str) -> Vec<String> {
-    let mut args = Vec::new();
+/// Split a line into words and operators. `$NAME` and friends are looked
+/// up through `var` except inside single quotes; an unquoted expansion is
+/// split into words at blanks, a double-quoted one stays a single word.
+fn tokenize(line: &str, var: &dyn Fn(&str) -> String) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
     let mut current = String::new();
-    let mut in_quote = false;
-    let mut quote_char = '"';
+    let mut has_word = false; // distinguishes "" from no word at all
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars().peekable();
 
-    for ch in line.chars() {
-        match ch {
-            '"' | '\'' if !in_quote => {
-                in_quote = true;
-                quote_char = ch;
-            }
-            c if in_quote && c == quote_char => {
-                in_quote = false;
+    while let Some(ch) = chars.next() {
+        if ch == '$' && quote != Some('\'') {
+            let Some(name) = var_name(&mut chars)? else {
+                current.push('$');
+                has_word = true;
+                continue;
+            };
+            let value = var(&name);
+            if quote.is_some() {
+                current.push_str(&value);
+                continue;
             }
-            ' ' | '\t' if !in_quote => {
-                if !current.is_empty() {
-                    args.push(core::mem::take(&mut current));
+            for c in value.chars() {
+                if c == ' ' || c == '\t' || c == '\n' {
+                    if has_word {
+                        tokens.push(Token::Word(core::mem::take(&mut current)));
+                        has_word = false;
+                    }
+                } else {
+                    current.push(c);
+                    has_word = true;
                 }
             }
-            c => current.push(c),
+            continue;
+        }
+        if let Some(q) = quote {
+            if ch == q {
+                quote = None;
+            } else {
+                current.push(ch);
+            }
+            continue;
+        }
+        let op = match ch {
+            '"' | '\'' => {
+                quote = Some(ch);
+                has_word = true;
+                continue;
+            }
+            ' ' | '\t' | '\n' => None,
+            '|' if chars.peek() == Some(&'|') => {
+                chars.next();
+                Some(Token::Or)
+            }
+            '&' if chars.peek() == Some(&'&') => {
+                chars.next();
+                Some(Token::And)
+            }
+            '&' => Some(Token::Amp),
+            '|' => Some(Token::Pipe),
+            '<' => Some(Token::In),
+            '>' if chars.peek() == Some(&'>') => {
+                chars.next();
+                Some(Token::Append)
+            }
+            '>' => Some(Token::Out),
+            c => {
+                current.push(c);
+                has_word = true;
+                continue;
+            }
+        };
+        if has_word {
+            tokens.push(Token::Word(core::mem::take(&mut current)));
+            has_word = false;
+        }
+        if let Some(op) = op {
+            tokens.push(op);
         }
     }
 
-    if !current.is_empty() {
-        args.push(current);
+    if quote.is_some() {
+        return Err("unterminated quote");
     }
+    if has_word {
+        tokens.push(Token::Word(current));
+    }
+    Ok(tokens)
+}
 
-    args
+struct Pipeline {
+    stages: Vec<Command>,
+    background: bool,
+}
+
+/// `a | b && c || d &`: the pipelines, each with how it is joined to the
+/// one before (`Then` for the first and after a `&`).
+fn parse_list(tokens: Vec<Token>) -> Result<Vec<(Join, Pipeline)>, &'static str> {
+    let mut list = Vec::new();
+    let mut join = Join::Then;
+    let mut tokens = tokens.into_iter();
+    loop {
+        let (stages, end) = parse_pipeline(&mut tokens)?;
+        if stages.is_empty() {
+            // Empty line, or nothing after a trailing `&`
+            if end.is_none() && matches!(join, Join::Then) {
+                return Ok(list);
+            }
+            return Err("missing command next to `&&`, `||` or `&`");
+        }
+        let background = matches!(end, Some(Token::Amp));
+        list.push((join, Pipeline { stages, background }));
+        join = match end {
+            None => return Ok(list),
+            Some(Token::And) => Join::And,
+            Some(Token::Or) => Join::Or,
+            Some(_) => Join::Then,
+        };
+    }
+}
+
+/// Stages up to the end of the line or the next `&&`, `||` or `&`, which
+/// is returned with them.
+fn parse_pipeline(
+    tokens: &mut impl Iterator<Item = Token>,
+) -> Result<(Vec<Command>, Option<Token>), &'static str> {
+    let mut stages = Vec::new();
+    let mut cmd = Command { args: Vec::new(), stdin: None, stdout: None };
+
+    let next = loop {
+        let Some(tok) = tokens.next() else {
+            break None;
+        };
+        match tok {
+            Token::Word(w) => cmd.args.push(w),
+            end @ (Token::And | Token::Or | Token::Amp) => break Some(end),
+            Token::Pipe => {
+                if cmd.args.is_empty() {
+                    return Err("unexpected `|`");
+                }
+                stages.push(core::mem::replace(
+                    &mut cmd,
+                    Command { args: Vec::new(), stdin: None, stdout: None },
+                ));
+            }
+            redir => {
+                let Some(Token::Word(path)) = tokens.next() else {
+                    return Err("missing file name after redirection");
+                };
+                match redir {
+                    Token::In => cmd.stdin = Some(path),
+                    Token::Out => cmd.stdout = Some((path, false)),
+                    _ => cmd.stdout = Some((path, true)),
+                }
+            }
+        }
+    };
+
+    if cmd.args.is_empty() {
+        if !stages.is_empty() {
+            return Err("missing command after `|`");
+        }
+        if cmd.stdin.is_none() && cmd.stdout.is_none() {
+            return Ok((stages, next));
+        }
+    }
+    stages.push(cmd);
+    Ok((stages, next))
 }
 
 macro_rules! shell_print {
     ($($a:tt)*) => {{
         let s = alloc::format!($($a)*);
-        crate::drivers::serial::write_str(&s);
-        crate::drivers::vga::write_str(&s);
+        crate::shell::io::write_str(&s);
     }};
 }
 
@@ -155,10 +678,26 @@ macro_rules! shell_println {
     ($($a:tt)*) => { shell_print!("{}\n", alloc::format!($($a)*)) };
 }
 
+macro_rules! shell_eprintln {
+    ($($a:tt)*) => {{
+        let s = alloc::format!("{}\n", alloc::format!($($a)*));
+        crate::shell::io::write_err(&s);
+    }};
+}
+
+pub(crate) use shell_eprintln;
 pub(crate) use shell_print;
 pub(crate) use shell_println;
 
 pub fn spawn_shell() -> ! {
     let mut shell = Shell::new();
     shell.run();
+    unreachable!("the console shell doesn't exit");
+}
+
+/// A shell on `tty` for the calling task, until its session ends.
+pub fn serve(tty: &'static crate::drivers::tty::Tty) {
+    io::set_tty(tty);
+    Shell::new().run();
+    io::release();
 }
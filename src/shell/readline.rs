@@ -1,57 +1,287 @@
+use super::{complete, io};
+
 use alloc::string::String;
-use crate::arch::x86_64::io::{cli, sti, RFLAGS_IF};
 
 fn read_char_blocking() -> u8 {
-    loop {
-        let rflags = unsafe { cli() };
-        if let Some(c) = crate::drivers::keyboard::read_char() {
-            if rflags & RFLAGS_IF != 0 {
-                sti();
+    io::tty().getc()
+}
+
+/// The rest of an escape sequence arrives right behind the ESC; a key
+/// that isn't there within a few ms means a lone Escape press.
+fn next_in_sequence() -> Option<u8> {
+    io::tty().try_getc().or_else(|| {
+        let _ = crate::proc::timers::sleep_ns(10_000_000);
+        io::tty().try_getc()
+    })
+}
+
+/// Input decoded from the byte stream: the PS/2 driver and a serial
+/// terminal both send xterm-style CSI sequences for non-character keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Byte(u8),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+    /// A sequence we don't act on, consumed whole.
+    Unknown,
+}
+
+fn read_key() -> Key {
+    let c = read_char_blocking();
+    if c != 0x1b {
+        return Key::Byte(c);
+    }
+    let Some(intro) = next_in_sequence() else {
+        return Key::Byte(0x1b);
+    };
+    if intro != b'[' && intro != b'O' {
+        return Key::Unknown; // Alt+key
+    }
+    // Parameters (digits, ';') up to the final byte
+    let mut first: u32 = 0;
+    let mut in_first = true;
+    let fin = loop {
+        match next_in_sequence() {
+            Some(d @ b'0'..=b'9') if in_first => first = first * 10 + (d - b'0') as u32,
+            Some(b';') => in_first = false,
+            Some(b'0'..=b'9') => {}
+            Some(f) => break f,
+            None => return Key::Unknown,
+        }
+    };
+    match (fin, first) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', 1) | (b'~', 7) => Key::Home,
+        (b'F', _) | (b'~', 4) | (b'~', 8) => Key::End,
+        (b'~', 3) => Key::Delete,
+        _ => Key::Unknown,
+    }
+}
+
+fn echo(s: &str) {
+    io::tty().write_str(s);
+}
+
+/// The line being edited. Only printable ASCII gets in, so byte offsets
+/// are also screen columns.
+struct Editor {
+    line: String,
+    cursor: usize,
+}
+
+impl Editor {
+    fn left(n: usize) {
+        if n > 0 {
+            echo(&alloc::format!("\x1b[{}D", n));
+        }
+    }
+
+    fn right(n: usize) {
+        if n > 0 {
+            echo(&alloc::format!("\x1b[{}C", n));
+        }
+    }
+
+    /// Repaint from the cursor to the end of the line and come back.
+    fn redraw_tail(&self) {
+        let tail = &self.line[self.cursor..];
+        echo(tail);
+        echo("\x1b[K");
+        Self::left(tail.len());
+    }
+
+    fn move_to(&mut self, pos: usize) {
+        let pos = pos.min(self.line.len());
+        if pos < self.cursor {
+            Self::left(self.cursor - pos);
+        } else {
+            Self::right(pos - self.cursor);
+        }
+        self.cursor = pos;
+    }
+
+    fn insert_str(&mut self, s: &str) {
+        self.line.insert_str(self.cursor, s);
+        echo(s);
+        self.cursor += s.len();
+        if self.cursor < self.line.len() {
+            self.redraw_tail();
+        }
+    }
+
+    fn insert(&mut self, c: u8) {
+        self.line.insert(self.cursor, c as char);
+        echo(&self.line[self.cursor..self.cursor + 1]);
+        self.cursor += 1;
+        if self.cursor < self.line.len() {
+            self.redraw_tail();
+        }
+    }
+
+    /// Remove [from, to), leaving the cursor at `from`.
+    fn kill(&mut self, from: usize, to: usize) {
+        let to = to.min(self.line.len());
+        if from >= to {
+            return;
+        }
+        self.move_to(from);
+        self.line.replace_range(from..to, "");
+        self.redraw_tail();
+    }
+
+    /// Tab: complete the word before the cursor. A unique match is filled
+    /// in with a trailing '/' or space; several are extended to what they
+    /// share, and listed if that adds nothing and `again` (a second Tab).
+    fn complete(&mut self, prompt: &str, again: bool) {
+        let c = complete::complete(&self.line[..self.cursor]);
+        let word_len = self.cursor - c.start;
+        match c.candidates.as_slice() {
+            [] => {}
+            [only] => {
+                let end = if only.is_dir { "/" } else { " " };
+                self.insert_str(&alloc::format!("{}{}", &only.text[word_len..], end));
+            }
+            many => {
+                let common = complete::common_prefix(many);
+                if common.len() > word_len {
+                    self.insert_str(&common[word_len..]);
+                } else if again {
+                    self.list(prompt, many);
+                }
+            }
+        }
+    }
+
+    /// Print the candidates below the line, then the prompt and line again.
+    fn list(&self, prompt: &str, candidates: &[complete::Candidate]) {
+        let width = io::tty().winsize().cols.max(1) as usize;
+        let col = candidates.iter().map(|c| c.display.len() + 1).max().unwrap_or(0) + 1;
+        let per_row = (width / col).max(1);
+        let mut out = String::from("\n");
+        for (i, c) in candidates.iter().enumerate() {
+            out.push_str(&c.display);
+            if c.is_dir {
+                out.push('/');
+            }
+            if (i + 1) % per_row == 0 || i + 1 == candidates.len() {
+                out.push('\n');
+            } else {
+                let shown = c.display.len() + c.is_dir as usize;
+                out.extend(core::iter::repeat_n(' ', col - shown));
             }
-            crate::serial_println!("[KB] got char={:#04x}", c);
-            return c;
         }
-        crate::serial_println!("[KB] sleeping...");
-        crate::proc::scheduler::sleep_current();
-        crate::serial_println!("[KB] woke up");
+        echo(&out);
+        echo(prompt);
+        echo(&self.line);
+        Self::left(self.line.len() - self.cursor);
+    }
+
+    /// Start of the word before the cursor, as Ctrl-W sees it.
+    fn word_start(&self) -> usize {
+        let b = &self.line.as_bytes()[..self.cursor];
+        let end = b.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+        b[..end].iter().rposition(|&c| c == b' ').map_or(0, |i| i + 1)
+    }
+
+    /// Show `new` in place of the whole line, cursor at its end; returns
+    /// what was there.
+    fn replace(&mut self, new: String) -> String {
+        self.move_to(0);
+        let old = core::mem::replace(&mut self.line, new);
+        echo(&self.line);
+        echo("\x1b[K");
+        self.cursor = self.line.len();
+        old
     }
 }
 
-pub fn readline() -> String {
-    let mut line = String::new();
+const CTRL_A: u8 = 0x01;
+const CTRL_B: u8 = 0x02;
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const CTRL_E: u8 = 0x05;
+const CTRL_F: u8 = 0x06;
+const CTRL_K: u8 = 0x0B;
+const CTRL_U: u8 = 0x15;
+const CTRL_W: u8 = 0x17;
+
+/// Read one line with emacs-style editing: arrows, Home/End and Ctrl-B/F/
+/// A/E move; Backspace, Delete, Ctrl-D, Ctrl-W (word), Ctrl-U (to start)
+/// and Ctrl-K (to end) delete. Up/Down step through `history` (oldest
+/// first); the line being typed is kept and comes back past the newest.
+/// Tab completes commands and paths. The console is raw meanwhile: the
+/// editor echoes for itself.
+pub fn readline(prompt: &str, history: &[String]) -> String {
+    io::tty().with_raw(|| edit(prompt, history))
+}
+
+fn edit(prompt: &str, history: &[String]) -> String {
+    let mut ed = Editor {
+        line: String::new(),
+        cursor: 0,
+    };
+    // history.len() = the line being typed, not an entry
+    let mut pos = history.len();
+    let mut draft = String::new();
+    let mut last_was_tab = false;
+    echo(prompt);
 
     loop {
-        let c = read_char_blocking();
-
-        match c {
-            b'\n' | b'\r' => {
-                crate::drivers::serial::write_str("\n");
-                crate::drivers::vga::write_str("\n");
-                return line;
-            }
-            8 | 127 => {
-                if !line.is_empty() {
-                    line.pop();
-                    crate::drivers::serial::write_str("\x08 \x08");
-                    crate::drivers::vga::write_str("\x08");
-                }
+        if io::tty().hung_up() {
+            return "exit".into();
+        }
+        let key = read_key();
+        let tab = key == Key::Byte(b'\t');
+        match key {
+            Key::Byte(b'\t') => ed.complete(prompt, last_was_tab),
+            Key::Byte(b'\n' | b'\r') => {
+                ed.move_to(ed.line.len());
+                echo("\n");
+                return ed.line;
             }
-            3 => {
-                crate::drivers::serial::write_str("^C\n");
-                crate::drivers::vga::write_str("^C\n");
+            Key::Byte(8 | 127) if ed.cursor > 0 => ed.kill(ed.cursor - 1, ed.cursor),
+            Key::Byte(CTRL_C) => {
+                ed.move_to(ed.line.len());
+                echo("^C\n");
                 return String::new();
             }
-            4 if line.is_empty() => {
+            Key::Byte(CTRL_D) if ed.line.is_empty() => {
                 return "exit".into();
             }
-            c if c >= 0x20 && c < 0x7F => {
-                let ch = c as char;
-                line.push(ch);
-                let s = alloc::format!("{}", ch);
-                crate::drivers::serial::write_str(&s);
-                crate::drivers::vga::write_str(&s);
+            Key::Delete | Key::Byte(CTRL_D) => ed.kill(ed.cursor, ed.cursor + 1),
+            Key::Left | Key::Byte(CTRL_B) if ed.cursor > 0 => ed.move_to(ed.cursor - 1),
+            Key::Right | Key::Byte(CTRL_F) => ed.move_to(ed.cursor + 1),
+            Key::Home | Key::Byte(CTRL_A) => ed.move_to(0),
+            Key::End | Key::Byte(CTRL_E) => ed.move_to(ed.line.len()),
+            Key::Byte(CTRL_W) => ed.kill(ed.word_start(), ed.cursor),
+            Key::Byte(CTRL_U) => ed.kill(0, ed.cursor),
+            Key::Byte(CTRL_K) => ed.kill(ed.cursor, ed.line.len()),
+            Key::Byte(c) if (0x20..0x7F).contains(&c) => ed.insert(c),
+            Key::Up if pos > 0 => {
+                let old = ed.replace(history[pos - 1].clone());
+                if pos == history.len() {
+                    draft = old;
+                }
+                pos -= 1;
+            }
+            Key::Down if pos < history.len() => {
+                pos += 1;
+                let next = match history.get(pos) {
+                    Some(h) => h.clone(),
+                    None => core::mem::take(&mut draft),
+                };
+                ed.replace(next);
             }
             _ => {}
         }
+        last_was_tab = tab;
     }
 }
@@ -0,0 +1,295 @@
+//! Shell scripts: `sh file`, `run` on a `#!` or `.sh` file, and /etc/rc at
+//! boot. Lines typed at the prompt go through here as well.
+//!
+//! The text is cut into statements at newlines and `;`, and `if`/`for`
+//! blocks are put together from the keyword each statement starts with.
+//! Statements stay text until they run, so `$VAR` sees the value of the
+//! moment (the loop variable of an enclosing `for`, say).
+use super::{io, shell_eprintln, tokenize, Shell, Token};
+use crate::fs::mount::with_vfs;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+enum Stmt {
+    /// Pipelines joined by `&&` / `||`.
+    List(String),
+    /// `if c; then …; elif c; then …; else …; fi`: (condition, body) per
+    /// arm, then the `else` body.
+    If {
+        arms: Vec<(Vec<Stmt>, Vec<Stmt>)>,
+        otherwise: Vec<Stmt>,
+    },
+    /// `for var in words; do …; done`
+    For {
+        var: String,
+        words: String,
+        body: Vec<Stmt>,
+    },
+}
+
+const KEYWORDS: &[&str] = &["if", "then", "elif", "else", "fi", "for", "do", "done"];
+
+/// Cut `text` at newlines and unquoted `;`, dropping `#` comments and
+/// empty statements.
+fn statements(text: &str) -> Vec<String> {
+    fn flush(out: &mut Vec<String>, cur: &mut String) {
+        let s = cur.trim();
+        if !s.is_empty() {
+            out.push(s.to_string());
+        }
+        cur.clear();
+    }
+
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut quote = None;
+    let mut comment = false;
+    for ch in text.chars() {
+        if let Some(q) = quote {
+            if ch == q {
+                quote = None;
+            }
+            cur.push(ch);
+            continue;
+        }
+        if comment && ch != '\n' {
+            continue;
+        }
+        match ch {
+            '"' | '\'' => {
+                quote = Some(ch);
+                cur.push(ch);
+            }
+            // Only at the start of a word: `$#` and `a#b` are not comments
+            '#' if cur.is_empty() || cur.ends_with([' ', '\t']) => comment = true,
+            '\n' | ';' => {
+                comment = false;
+                flush(&mut out, &mut cur);
+            }
+            c => cur.push(c),
+        }
+    }
+    flush(&mut out, &mut cur);
+    out
+}
+
+struct Parser {
+    stmts: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    /// The keyword the next statement starts with, if any.
+    fn keyword(&self) -> Option<&'static str> {
+        let first = self.stmts.get(self.pos)?.split_whitespace().next()?;
+        KEYWORDS.iter().copied().find(|&k| k == first)
+    }
+
+    /// Consume the keyword the next statement starts with. Whatever
+    /// follows it (`then echo hi`) is left as a statement of its own.
+    fn take_keyword(&mut self) {
+        let stmt = &self.stmts[self.pos];
+        let len = stmt.split_whitespace().next().map_or(0, str::len);
+        let rest = stmt[len..].trim().to_string();
+        if rest.is_empty() {
+            self.pos += 1;
+        } else {
+            self.stmts[self.pos] = rest;
+        }
+    }
+
+    /// Statements up to one of the keywords in `ends`, which is consumed
+    /// and returned. With no `ends` the block runs to the end of the text.
+    fn block(&mut self, ends: &[&'static str]) -> Result<(Vec<Stmt>, &'static str), String> {
+        let mut body = Vec::new();
+        loop {
+            let Some(stmt) = self.stmts.get(self.pos) else {
+                return match ends.first() {
+                    None => Ok((body, "")),
+                    Some(end) => Err(format!("expected `{}`", end)),
+                };
+            };
+            match self.keyword() {
+                None => {
+                    body.push(Stmt::List(stmt.clone()));
+                    self.pos += 1;
+                }
+                Some(k) if ends.contains(&k) => {
+                    self.take_keyword();
+                    return Ok((body, k));
+                }
+                Some("if") => {
+                    self.take_keyword();
+                    body.push(self.if_rest()?);
+                }
+                Some("for") => body.push(self.for_rest()?),
+                Some(k) => return Err(format!("unexpected `{}`", k)),
+            }
+        }
+    }
+
+    /// After `if`: the arms, up to and including `fi`.
+    fn if_rest(&mut self) -> Result<Stmt, String> {
+        let mut arms = Vec::new();
+        loop {
+            let (cond, _) = self.block(&["then"])?;
+            let (body, end) = self.block(&["elif", "else", "fi"])?;
+            arms.push((cond, body));
+            match end {
+                "elif" => {}
+                "else" => {
+                    let (otherwise, _) = self.block(&["fi"])?;
+                    return Ok(Stmt::If { arms, otherwise });
+                }
+                _ => {
+                    return Ok(Stmt::If {
+                        arms,
+                        otherwise: Vec::new(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// `for var [in words]`, then the body up to `done`. Without `in` the
+    /// loop goes over the script's arguments.
+    fn for_rest(&mut self) -> Result<Stmt, String> {
+        let header = self.stmts[self.pos]["for".len()..].trim().to_string();
+        self.pos += 1;
+        let (var, tail) = header.split_once([' ', '\t']).unwrap_or((&header, ""));
+        if !super::is_name(var) {
+            return Err(format!("for: bad variable name `{}`", var));
+        }
+        let tail = tail.trim_start();
+        let words = match tail.strip_prefix("in") {
+            Some(w) if w.is_empty() || w.starts_with([' ', '\t']) => w.trim().to_string(),
+            _ if tail.is_empty() => String::from("$@"),
+            _ => return Err(String::from("for: expected `in`")),
+        };
+        if self.keyword() != Some("do") {
+            return Err(String::from("expected `do`"));
+        }
+        self.take_keyword();
+        let (body, _) = self.block(&["done"])?;
+        Ok(Stmt::For {
+            var: var.to_string(),
+            words,
+            body,
+        })
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<Stmt>, String> {
+    let mut parser = Parser {
+        stmts: statements(text),
+        pos: 0,
+    };
+    parser.block(&[]).map(|(stmts, _)| stmts)
+}
+
+/// Whether `run` should hand `path` to the shell instead of the ELF loader.
+pub fn is_script(path: &str) -> bool {
+    path.ends_with(".sh") || with_vfs(|vfs| vfs.read_file(path)).is_ok_and(|d| d.starts_with(b"#!"))
+}
+
+impl Shell {
+    /// Parse and run `text`; `$?` is left at the last command's status.
+    pub(super) fn run_script(&mut self, text: &str) {
+        match parse(text) {
+            Ok(stmts) => self.run_block(&stmts),
+            Err(e) => {
+                shell_eprintln!("sh: syntax error: {}", e);
+                self.status = 2;
+            }
+        }
+    }
+
+    fn run_block(&mut self, stmts: &[Stmt]) {
+        for stmt in stmts {
+            if self.exit.is_some() {
+                return;
+            }
+            match stmt {
+                Stmt::List(line) => self.run_line(line),
+                Stmt::If { arms, otherwise } => {
+                    let taken = arms.iter().find(|(cond, _)| {
+                        self.run_block(cond);
+                        self.exit.is_none() && self.status == 0
+                    });
+                    match taken {
+                        Some((_, body)) => self.run_block(body),
+                        None if self.exit.is_none() => {
+                            self.status = 0;
+                            self.run_block(otherwise);
+                        }
+                        None => {}
+                    }
+                }
+                Stmt::For { var, words, body } => {
+                    let words = match self.words(words) {
+                        Ok(w) => w,
+                        Err(e) => {
+                            shell_eprintln!("sh: syntax error: {}", e);
+                            self.status = 2;
+                            continue;
+                        }
+                    };
+                    self.status = 0;
+                    for w in words {
+                        if self.exit.is_some() {
+                            break;
+                        }
+                        self.vars.insert(var.clone(), w);
+                        self.run_block(body);
+                    }
+                }
+            }
+        }
+    }
+
+    /// A `for` word list, expanded.
+    fn words(&self, text: &str) -> Result<Vec<String>, &'static str> {
+        tokenize(text, &|name| self.var(name))?
+            .into_iter()
+            .map(|t| match t {
+                Token::Word(w) => Ok(w),
+                _ => Err("operator in a `for` word list"),
+            })
+            .collect()
+    }
+
+    /// `sh [file [args…]]`: run a script file, or the script on stdin,
+    /// with `$0`, `$1`, … set to the file and its arguments. `exit` ends
+    /// only this script. Returns its status.
+    pub(super) fn cmd_sh(&mut self, args: &[String]) -> i32 {
+        let (args, data) = match args.first() {
+            Some(path) => match with_vfs(|vfs| vfs.read_file(path)) {
+                Ok(d) => (args.to_vec(), d),
+                Err(e) => {
+                    shell_eprintln!("sh: {}: error {}", path, e.0);
+                    return 127;
+                }
+            },
+            None => match io::read_stdin() {
+                Some(d) => (alloc::vec![String::from("sh")], d),
+                None => {
+                    shell_eprintln!("sh: usage: sh <file> [args...]");
+                    return 2;
+                }
+            },
+        };
+        let Ok(text) = String::from_utf8(data) else {
+            shell_eprintln!("sh: {}: not a text file", args[0]);
+            return 126;
+        };
+
+        let saved = core::mem::replace(&mut self.args, args);
+        self.run_script(&text);
+        self.args = saved;
+        if let Some(code) = self.exit.take() {
+            self.status = code;
+        }
+        self.status
+    }
+}
@@ -1,2 +1,11 @@
+pub mod mutex;
+pub mod percpu;
+pub mod seqlock;
 pub mod spinlock;
+pub mod waitqueue;
+pub use mutex::{Condvar, Mutex};
+pub(crate) use percpu::percpu;
+pub use percpu::PerCpu;
+pub use seqlock::SeqLock;
 pub use spinlock::{RwSpinLock, SpinLock};
+pub use waitqueue::WaitQueue;
@@ -0,0 +1,121 @@
+//! Sleeping lock for long critical sections (disk I/O, filesystem walks).
+//!
+//! Unlike `SpinLock` it leaves interrupts enabled, and a contended `lock`
+//! puts the caller to sleep on the mutex's wait queue rather than spinning.
+//! Never take one in an IRQ handler, or with a `SpinLock` held if it can
+//! be contended: the holder could then never run to release it.
+use super::waitqueue::WaitQueue;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub struct Mutex<T> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new("mutex"),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    fn acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if !self.acquire() {
+            self.waiters.wait_event(|| self.acquire().then_some(()));
+        }
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.acquire().then_some(MutexGuard { mutex: self })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+        self.mutex.waiters.wake_one();
+    }
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+/// Condition variable paired with a `Mutex`.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new("condvar"),
+        }
+    }
+
+    /// Release the mutex, sleep until notified, then take it back. The
+    /// caller is queued before the mutex is released, so a notify issued
+    /// right after can't be missed. Wake-ups may be spurious: recheck.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        self.waiters.sleep_then(|| drop(guard));
+        mutex.lock()
+    }
+
+    pub fn wait_while<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut cond: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while cond(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.wake_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.wake_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
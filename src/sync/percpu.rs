@@ -0,0 +1,109 @@
+//! Per-CPU variables.
+//!
+//! `percpu!` declares a static holding one slot per possible CPU. The slot of
+//! the executing CPU is selected by the id stored in `CpuLocal` (`%gs:24`), so
+//! hot counters and caches can be updated without a shared lock.
+//!
+//! Access is only valid after `gdt::init_bsp` has installed `GS_BASE`.
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub use crate::arch::x86_64::gdt::MAX_CPUS;
+
+/// Number of CPUs brought online (BSP only until SMP bring-up lands).
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+
+pub fn online_cpus() -> usize {
+    ONLINE_CPUS.load(Ordering::Relaxed)
+}
+
+pub fn set_online_cpus(n: usize) {
+    ONLINE_CPUS.store(n.clamp(1, MAX_CPUS), Ordering::Relaxed);
+}
+
+#[inline(always)]
+pub fn current_cpu() -> usize {
+    crate::arch::x86_64::gdt::cpu_id()
+}
+
+/// One CPU's copy, padded to a cache line so neighbouring CPUs never share it.
+#[repr(C, align(64))]
+pub struct CpuSlot<T>(UnsafeCell<T>);
+
+impl<T> CpuSlot<T> {
+    pub const fn new(val: T) -> Self {
+        Self(UnsafeCell::new(val))
+    }
+}
+
+pub struct PerCpu<T> {
+    slots: [CpuSlot<T>; MAX_CPUS],
+}
+
+// Каждый CPU трогает только свой слот; чужие слоты читаются через &T,
+// поэтому для межпроцессорного чтения T должен быть Sync (атомики и т.п.).
+unsafe impl<T: Send> Send for PerCpu<T> {}
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    pub const fn new(slots: [CpuSlot<T>; MAX_CPUS]) -> Self {
+        Self { slots }
+    }
+
+    /// Shared reference to the current CPU's copy. Intended for types with
+    /// interior mutability (atomics); the caller may migrate afterwards.
+    #[inline]
+    pub fn get(&self) -> &T {
+        unsafe { &*self.slots[current_cpu()].0.get() }
+    }
+
+    /// Run `f` on the current CPU's copy with interrupts disabled, so neither
+    /// an IRQ handler nor a reschedule can observe the value mid-update.
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        use crate::arch::x86_64::io::{cli, sti, RFLAGS_IF};
+        let rflags = cli();
+        let r = f(unsafe { &mut *self.slots[current_cpu()].0.get() });
+        if rflags & RFLAGS_IF != 0 {
+            sti();
+        }
+        r
+    }
+
+    /// Another CPU's copy (e.g. for summing statistics).
+    pub fn for_cpu(&self, cpu: usize) -> &T
+    where
+        T: Sync,
+    {
+        unsafe { &*self.slots[cpu].0.get() }
+    }
+
+    /// Iterate over the copies of all online CPUs.
+    pub fn iter(&self) -> impl Iterator<Item = &T>
+    where
+        T: Sync,
+    {
+        (0..online_cpus()).map(move |cpu| self.for_cpu(cpu))
+    }
+}
+
+/// Declare a per-CPU static:
+///
+/// ```ignore
+/// sync::percpu! {
+///     static IRQ_STATS: [AtomicU64; 16] = [const { AtomicU64::new(0) }; 16];
+/// }
+/// ```
+macro_rules! percpu {
+    ($($(#[$m:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$m])*
+            $vis static $name: $crate::sync::percpu::PerCpu<$ty> =
+                $crate::sync::percpu::PerCpu::new(
+                    [const { $crate::sync::percpu::CpuSlot::new($init) };
+                        $crate::sync::percpu::MAX_CPUS],
+                );
+        )+
+    };
+}
+
+pub(crate) use percpu;
@@ -0,0 +1,78 @@
+//! Sequence lock: writers bump a counter around each update, readers copy the
+//! data and retry if the counter was odd or changed. Readers never block a
+//! writer and never take a lock, so they are safe in IRQ/NMI context.
+use core::cell::UnsafeCell;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+pub struct SeqLock<T: Copy> {
+    seq: AtomicU64,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    pub const fn new(val: T) -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    /// Snapshot the protected value, retrying while a write is in flight.
+    pub fn read(&self) -> T {
+        loop {
+            if let Some(v) = self.try_read() {
+                return v;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Single read attempt; `None` if it raced with a writer. Use this from
+    /// NMI context, where the interrupted code may be the writer itself.
+    pub fn try_read(&self) -> Option<T> {
+        let s1 = self.seq.load(Ordering::Acquire);
+        if s1 & 1 != 0 {
+            return None;
+        }
+        let val = unsafe { core::ptr::read_volatile(self.data.get()) };
+        fence(Ordering::Acquire);
+        let s2 = self.seq.load(Ordering::Relaxed);
+        if s1 == s2 {
+            Some(val)
+        } else {
+            None
+        }
+    }
+
+    /// Update the value in place. Writers are serialized against each other
+    /// and run with interrupts disabled so a local reader can't spin on them.
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        use crate::arch::x86_64::io::{cli, sti, RFLAGS_IF};
+        let rflags = cli();
+
+        loop {
+            let s = self.seq.load(Ordering::Relaxed);
+            if s & 1 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(s, s + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        fence(Ordering::Release);
+
+        let r = f(unsafe { &mut *self.data.get() });
+
+        self.seq.fetch_add(1, Ordering::Release);
+        if rflags & RFLAGS_IF != 0 {
+            sti();
+        }
+        r
+    }
+}
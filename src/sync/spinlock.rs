@@ -52,6 +52,12 @@ impl<T> SpinLock<T> {
         }
     }
 
+    /// Release the lock without its guard. Only for the panic path: with
+    /// the one CPU stopped, a holder that was interrupted never resumes.
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
     pub unsafe fn get_mut_unchecked(&self) -> &mut T {
         &mut *self.data.get()
     }
@@ -0,0 +1,128 @@
+//! Wait queues: a FIFO of sleeping pids that a waker can rouse one or all
+//! of, instead of waking every sleeping process in the system.
+//!
+//! The condition a sleeper waits for is checked with interrupts off right
+//! up to the point it is marked Sleeping, so a wake-up from an IRQ handler
+//! can't slip in between and get lost. Before the scheduler runs there is
+//! nobody to switch to, and waiting degrades to `hlt` until an interrupt.
+use super::spinlock::SpinLock;
+use crate::arch::x86_64::io::{cli, hlt, sti, RFLAGS_IF};
+use alloc::collections::VecDeque;
+
+pub struct WaitQueue {
+    /// What a sleeper waits for, as shown for it in `wchan`.
+    name: &'static str,
+    waiters: SpinLock<VecDeque<u32>>,
+}
+
+/// Put IF back the way `rflags` had it. `schedule()` returns with IF as
+/// it found it, but waiting before there is a scheduler leaves it on after
+/// the `hlt`, so this turns it off again as well as on.
+fn restore(rflags: u64) {
+    if rflags & RFLAGS_IF != 0 {
+        sti();
+    } else {
+        cli();
+    }
+}
+
+impl WaitQueue {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            waiters: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Sleep until `f` returns a value; `f` runs with interrupts off.
+    pub fn wait_event<R>(&self, mut f: impl FnMut() -> Option<R>) -> R {
+        loop {
+            let rflags = cli();
+            if let Some(r) = f() {
+                restore(rflags);
+                return r;
+            }
+            self.sleep_then(|| {});
+            restore(rflags);
+        }
+    }
+
+    /// `wait_event`, giving up once `nanos()` reaches `deadline` or a
+    /// signal is pending: None then.
+    pub fn wait_event_timeout<R>(
+        &self,
+        deadline: u64,
+        mut f: impl FnMut() -> Option<R>,
+    ) -> Option<R> {
+        use crate::proc::{signal, timers};
+        let pid = crate::proc::current_process().map(|p| p.lock().pid);
+        let timer = pid.map(|pid| timers::wake_at(deadline, pid));
+        let r = loop {
+            let rflags = cli();
+            if let Some(r) = f() {
+                restore(rflags);
+                break Some(r);
+            }
+            if crate::arch::x86_64::timer::nanos() >= deadline || signal::interrupted() {
+                restore(rflags);
+                break None;
+            }
+            self.sleep_then(|| {});
+            restore(rflags);
+        };
+        if let Some(key) = timer {
+            timers::cancel(key);
+        }
+        r
+    }
+
+    /// Queue the current process, run `release` (e.g. drop a mutex guard)
+    /// and sleep until woken. Returns with interrupts as on entry.
+    pub fn sleep_then(&self, release: impl FnOnce()) {
+        let rflags = cli();
+        match crate::proc::current_process() {
+            Some(proc) => {
+                let pid = {
+                    let mut p = proc.lock();
+                    p.wchan = self.name;
+                    p.pid
+                };
+                self.waiters.lock().push_back(pid);
+                release();
+                crate::proc::sleep_current();
+                // Still queued if something else (a signal) woke us
+                self.waiters.lock().retain(|&p| p != pid);
+                proc.lock().wchan = "";
+            }
+            None => {
+                release();
+                sti();
+                hlt();
+            }
+        }
+        restore(rflags);
+    }
+
+    /// Wake the longest waiter still asleep; false if there was none.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let Some(pid) = self.waiters.lock().pop_front() else {
+                return false;
+            };
+            if crate::proc::wake_up(pid) {
+                return true;
+            }
+        }
+    }
+
+    pub fn wake_all(&self) {
+        let pids = core::mem::take(&mut *self.waiters.lock());
+        for pid in pids {
+            crate::proc::wake_up(pid);
+        }
+    }
+
+    pub fn has_waiters(&self) -> bool {
+        !self.waiters.lock().is_empty()
+    }
+}
@@ -3,12 +3,27 @@ pub mod nr {
     pub const SYS_WRITE: u64 = 1;
     pub const SYS_OPEN: u64 = 2;
     pub const SYS_CLOSE: u64 = 3;
+    pub const SYS_STAT: u64 = 4;
+    pub const SYS_FSTAT: u64 = 5;
+    pub const SYS_LSTAT: u64 = 6;
+    pub const SYS_LSEEK: u64 = 8;
     pub const SYS_MMAP: u64 = 9;
+    pub const SYS_MPROTECT: u64 = 10;
     pub const SYS_MUNMAP: u64 = 11;
     pub const SYS_BRK: u64 = 12;
     pub const SYS_SIGACTION: u64 = 13;
     pub const SYS_SIGPROCMASK: u64 = 14;
+    pub const SYS_RT_SIGRETURN: u64 = 15;
     pub const SYS_IOCTL: u64 = 16;
+    pub const SYS_PIPE: u64 = 22;
+    pub const SYS_NANOSLEEP: u64 = 35;
+    pub const SYS_SOCKET: u64 = 41;
+    pub const SYS_CONNECT: u64 = 42;
+    pub const SYS_SENDTO: u64 = 44;
+    pub const SYS_RECVFROM: u64 = 45;
+    pub const SYS_BIND: u64 = 49;
+    pub const SYS_MSYNC: u64 = 26;
+    pub const SYS_CLONE: u64 = 56;
     pub const SYS_FORK: u64 = 57;
     pub const SYS_VFORK: u64 = 58;
     pub const SYS_EXECVE: u64 = 59;
@@ -16,10 +31,39 @@ pub mod nr {
     pub const SYS_WAIT4: u64 = 61;
     pub const SYS_KILL: u64 = 62;
     pub const SYS_UNAME: u64 = 63;
+    pub const SYS_GETDENTS64: u64 = 217;
+    pub const SYS_OPENAT: u64 = 257;
+    pub const SYS_NEWFSTATAT: u64 = 262;
+    pub const SYS_UTIMENSAT: u64 = 280;
+    pub const SYS_GETCWD: u64 = 79;
+    pub const SYS_CHDIR: u64 = 80;
+    pub const SYS_CHROOT: u64 = 161;
     pub const SYS_GETPID: u64 = 39;
     pub const SYS_GETPPID: u64 = 110;
+    pub const SYS_SETPGID: u64 = 109;
+    pub const SYS_GETPGRP: u64 = 111;
+    pub const SYS_SETSID: u64 = 112;
+    pub const SYS_GETPGID: u64 = 121;
+    pub const SYS_GETSID: u64 = 124;
+    pub const SYS_CHMOD: u64 = 90;
+    pub const SYS_FCHMOD: u64 = 91;
+    pub const SYS_CHOWN: u64 = 92;
+    pub const SYS_FCHOWN: u64 = 93;
+    pub const SYS_LCHOWN: u64 = 94;
     pub const SYS_GETUID: u64 = 102;
     pub const SYS_GETGID: u64 = 104;
+    pub const SYS_SETUID: u64 = 105;
+    pub const SYS_SETGID: u64 = 106;
+    pub const SYS_GETEUID: u64 = 107;
+    pub const SYS_GETEGID: u64 = 108;
+    pub const SYS_GETRLIMIT: u64 = 97;
+    pub const SYS_GETRUSAGE: u64 = 98;
+    pub const SYS_SETRLIMIT: u64 = 160;
+    pub const SYS_PRLIMIT64: u64 = 302;
+    pub const SYS_GETPRIORITY: u64 = 140;
+    pub const SYS_SETPRIORITY: u64 = 141;
+    pub const SYS_FUTEX: u64 = 202;
+    pub const SYS_ARCH_PRCTL: u64 = 158;
     pub const SYS_GETTID: u64 = 186;
     pub const SYS_SET_TID_ADDRESS: u64 = 218;
     pub const SYS_EXIT_GROUP: u64 = 231;
@@ -39,159 +83,893 @@ pub mod errno {
     pub const EPERM: i64 = 1;
     pub const ECHILD: i64 = 10;
     pub const ESRCH: i64 = 3;
+    pub const EINTR: i64 = 4;
+    pub const ETIMEDOUT: i64 = 110;
+    pub const EMFILE: i64 = 24;
+    pub const ENODEV: i64 = 19;
+    pub const ENOTDIR: i64 = 20;
+    pub const ERANGE: i64 = 34;
+    pub const ENAMETOOLONG: i64 = 36;
+    pub const ENOTSOCK: i64 = 88;
+    pub const EPROTONOSUPPORT: i64 = 93;
+    pub const EAFNOSUPPORT: i64 = 97;
 }
 
 use crate::arch::x86_64::idt::InterruptFrame;
 use errno::*;
 
-#[no_mangle]
-pub extern "C" fn syscall_dispatch(
-    nr: u64,
-    a0: u64,
-    a1: u64,
-    a2: u64,
-    a3: u64,
-    a4: u64,
-    a5: u64,
-) -> i64 {
+// ─── Syscall table ───────────────────────────────────────────────────────────
+
+/// A syscall handler: the six arguments (RDI, RSI, RDX, R10, R8, R9) and
+/// the saved user registers, which sigreturn replaces and fork and clone
+/// copy. Returns what goes back in RAX.
+type Handler = fn([u64; 6], &mut InterruptFrame) -> i64;
+
+#[derive(Clone, Copy)]
+pub struct Syscall {
+    pub nr: u64,
+    /// As strace and the Linux headers spell it.
+    pub name: &'static str,
+    /// How many of the six argument registers it reads, for tracing.
+    pub nargs: u8,
+    handler: Handler,
+}
+
+const fn sc(nr: u64, name: &'static str, nargs: u8, handler: Handler) -> Syscall {
+    Syscall { nr, name, nargs, handler }
+}
+
+/// Every syscall, registered once; SYSCALL and int 0x80 both come here.
+const SYSCALLS: &[Syscall] = {
+    use crate::proc::{self, cred, exec, fork, futex, priority, resource, signal};
     use nr::*;
-    match nr {
-        SYS_READ => fs::sys_read(a0 as i32, a1 as *mut u8, a2 as usize),
-        SYS_WRITE => fs::sys_write(a0 as i32, a1 as *const u8, a2 as usize),
-        SYS_OPEN => -ENOSYS,
-        SYS_CLOSE => {
-            if a0 <= 2 {
-                0
-            } else {
-                -EBADF
-            }
+    &[
+        // Files
+        sc(SYS_READ, "read", 3, |a, _| fs::sys_read(a[0] as i32, a[1], a[2] as usize)),
+        sc(SYS_WRITE, "write", 3, |a, _| fs::sys_write(a[0] as i32, a[1], a[2] as usize)),
+        sc(SYS_OPEN, "open", 3, |a, _| fs::sys_open(a[0], a[1] as u32, a[2] as u32)),
+        sc(SYS_OPENAT, "openat", 4, |a, _| {
+            fs::sys_openat(a[0] as i32, a[1], a[2] as u32, a[3] as u32)
+        }),
+        sc(SYS_CLOSE, "close", 1, |a, _| fs::sys_close(a[0] as i32)),
+        sc(SYS_STAT, "stat", 2, |a, _| fs::sys_newfstatat(fs::AT_FDCWD, a[0], a[1], 0)),
+        sc(SYS_LSTAT, "lstat", 2, |a, _| {
+            fs::sys_newfstatat(fs::AT_FDCWD, a[0], a[1], fs::AT_SYMLINK_NOFOLLOW)
+        }),
+        sc(SYS_FSTAT, "fstat", 2, |a, _| fs::sys_fstat(a[0] as i32, a[1])),
+        sc(SYS_NEWFSTATAT, "newfstatat", 4, |a, _| {
+            fs::sys_newfstatat(a[0] as i32, a[1], a[2], a[3] as u32)
+        }),
+        sc(SYS_GETDENTS64, "getdents64", 3, |a, _| {
+            fs::sys_getdents64(a[0] as i32, a[1], a[2] as usize)
+        }),
+        sc(SYS_LSEEK, "lseek", 3, |a, _| fs::sys_lseek(a[0] as i32, a[1] as i64, a[2] as u32)),
+        sc(SYS_PIPE, "pipe", 1, |a, _| fs::sys_pipe(a[0])),
+        sc(SYS_IOCTL, "ioctl", 3, |a, _| fs::sys_ioctl(a[0] as i32, a[1] as u32, a[2])),
+        sc(SYS_GETCWD, "getcwd", 2, |a, _| fs::sys_getcwd(a[0], a[1] as usize)),
+        sc(SYS_CHDIR, "chdir", 1, |a, _| fs::sys_chdir(a[0])),
+        sc(SYS_CHROOT, "chroot", 1, |a, _| fs::sys_chroot(a[0])),
+        sc(SYS_CHMOD, "chmod", 2, |a, _| fs::sys_chmod(a[0], a[1] as u32)),
+        sc(SYS_FCHMOD, "fchmod", 2, |a, _| fs::sys_fchmod(a[0] as i32, a[1] as u32)),
+        sc(SYS_CHOWN, "chown", 3, |a, _| fs::sys_chown(a[0], a[1] as u32, a[2] as u32, true)),
+        sc(SYS_LCHOWN, "lchown", 3, |a, _| fs::sys_chown(a[0], a[1] as u32, a[2] as u32, false)),
+        sc(SYS_FCHOWN, "fchown", 3, |a, _| fs::sys_fchown(a[0] as i32, a[1] as u32, a[2] as u32)),
+        sc(SYS_UTIMENSAT, "utimensat", 4, |a, _| {
+            fs::sys_utimensat(a[0] as i32, a[1], a[2], a[3] as u32)
+        }),
+        // Processes
+        sc(SYS_FORK, "fork", 0, |_, f| fork::sys_fork(f)),
+        sc(SYS_VFORK, "vfork", 0, |_, f| fork::sys_fork(f)),
+        sc(SYS_CLONE, "clone", 5, |a, f| fork::sys_clone(f, a[0], a[1], a[2], a[3], a[4])),
+        sc(SYS_EXECVE, "execve", 3, |a, _| exec::sys_execve(a[0], a[1], a[2])),
+        sc(SYS_EXIT, "exit", 1, |a, _| proc::exit_current(a[0] as i32)),
+        sc(SYS_EXIT_GROUP, "exit_group", 1, |a, _| proc::exit_group(a[0] as i32, 0)),
+        sc(SYS_WAIT4, "wait4", 3, |a, _| fork::sys_waitpid(a[0] as i32, a[1], a[2] as u32)),
+        sc(SYS_GETPID, "getpid", 0, |_, _| {
+            proc::current_process().map(|p| p.lock().tgid as i64).unwrap_or(1)
+        }),
+        sc(SYS_GETPPID, "getppid", 0, |_, _| {
+            proc::current_process().map(|p| p.lock().ppid as i64).unwrap_or(0)
+        }),
+        sc(SYS_GETTID, "gettid", 0, |_, _| {
+            proc::current_process().map(|p| p.lock().pid as i64).unwrap_or(1)
+        }),
+        sc(SYS_SET_TID_ADDRESS, "set_tid_address", 1, |a, _| {
+            proc::current_process()
+                .map(|p| {
+                    let mut p = p.lock();
+                    p.clear_child_tid = a[0];
+                    p.pid as i64
+                })
+                .unwrap_or(1)
+        }),
+        sc(SYS_SETPGID, "setpgid", 2, |a, _| proc::sys_setpgid(a[0] as u32, a[1] as u32)),
+        sc(SYS_GETPGRP, "getpgrp", 0, |_, _| proc::sys_getpgid(0, false)),
+        sc(SYS_GETPGID, "getpgid", 1, |a, _| proc::sys_getpgid(a[0] as u32, false)),
+        sc(SYS_SETSID, "setsid", 0, |_, _| proc::sys_setsid()),
+        sc(SYS_GETSID, "getsid", 1, |a, _| proc::sys_getpgid(a[0] as u32, true)),
+        sc(SYS_GETUID, "getuid", 0, |_, _| cred::current().uid as i64),
+        sc(SYS_GETEUID, "geteuid", 0, |_, _| cred::current().uid as i64),
+        sc(SYS_GETGID, "getgid", 0, |_, _| cred::current().gid as i64),
+        sc(SYS_GETEGID, "getegid", 0, |_, _| cred::current().gid as i64),
+        sc(SYS_SETUID, "setuid", 1, |a, _| cred::sys_setuid(a[0] as u32)),
+        sc(SYS_SETGID, "setgid", 1, |a, _| cred::sys_setgid(a[0] as u32)),
+        sc(SYS_ARCH_PRCTL, "arch_prctl", 2, |a, _| proc::sys_arch_prctl(a[0] as u32, a[1])),
+        sc(SYS_FUTEX, "futex", 6, |a, _| {
+            futex::sys_futex(a[0], a[1] as u32, a[2] as u32, a[3], a[4], a[5] as u32)
+        }),
+        sc(SYS_GETRLIMIT, "getrlimit", 2, |a, _| resource::sys_getrlimit(a[0] as u32, a[1])),
+        sc(SYS_SETRLIMIT, "setrlimit", 2, |a, _| resource::sys_setrlimit(a[0] as u32, a[1])),
+        sc(SYS_PRLIMIT64, "prlimit64", 4, |a, _| {
+            resource::sys_prlimit64(a[0] as u32, a[1] as u32, a[2], a[3])
+        }),
+        sc(SYS_GETRUSAGE, "getrusage", 2, |a, _| resource::sys_getrusage(a[0] as i32, a[1])),
+        sc(SYS_GETPRIORITY, "getpriority", 2, |a, _| {
+            priority::sys_getpriority(a[0] as i32, a[1] as u32)
+        }),
+        sc(SYS_SETPRIORITY, "setpriority", 3, |a, _| {
+            priority::sys_setpriority(a[0] as i32, a[1] as u32, a[2] as i32)
+        }),
+        // Signals
+        sc(SYS_KILL, "kill", 2, |a, _| signal::sys_kill(a[0] as i32, a[1] as u32)),
+        sc(SYS_SIGACTION, "rt_sigaction", 4, |a, _| {
+            signal::sys_rt_sigaction(a[0] as u32, a[1], a[2], a[3] as usize)
+        }),
+        sc(SYS_SIGPROCMASK, "rt_sigprocmask", 4, |a, _| {
+            signal::sys_rt_sigprocmask(a[0], a[1], a[2], a[3] as usize)
+        }),
+        // Replaces the whole frame, RAX included
+        sc(SYS_RT_SIGRETURN, "rt_sigreturn", 0, |_, f| {
+            signal::sys_rt_sigreturn(f);
+            f.rax as i64
+        }),
+        // Memory
+        sc(SYS_MMAP, "mmap", 6, |a, _| {
+            mm::sys_mmap(a[0], a[1] as usize, a[2] as i32, a[3] as i32, a[4] as i32, a[5] as i64)
+        }),
+        sc(SYS_MPROTECT, "mprotect", 3, |a, _| mm::sys_mprotect(a[0], a[1] as usize, a[2] as i32)),
+        sc(SYS_MUNMAP, "munmap", 2, |a, _| mm::sys_munmap(a[0], a[1] as usize)),
+        sc(SYS_MSYNC, "msync", 3, |a, _| mm::sys_msync(a[0], a[1] as usize, a[2] as i32)),
+        sc(SYS_BRK, "brk", 1, |a, _| mm::sys_brk(a[0])),
+        // Time and the rest
+        sc(SYS_UNAME, "uname", 1, |a, _| misc::sys_uname(a[0])),
+        sc(SYS_CLOCK_GETTIME, "clock_gettime", 2, |a, _| misc::sys_clock_gettime(a[0], a[1])),
+        sc(SYS_NANOSLEEP, "nanosleep", 2, |a, _| misc::sys_nanosleep(a[0], a[1])),
+        // Sockets
+        sc(SYS_SOCKET, "socket", 3, |a, _| net::sys_socket(a[0] as u32, a[1] as u32, a[2] as u32)),
+        sc(SYS_BIND, "bind", 3, |a, _| net::sys_bind(a[0] as i32, a[1], a[2] as u32)),
+        sc(SYS_CONNECT, "connect", 3, |a, _| net::sys_connect(a[0] as i32, a[1], a[2] as u32)),
+        sc(SYS_SENDTO, "sendto", 6, |a, _| {
+            net::sys_sendto(a[0] as i32, a[1], a[2] as usize, a[3] as u32, a[4], a[5] as u32)
+        }),
+        sc(SYS_RECVFROM, "recvfrom", 6, |a, _| {
+            net::sys_recvfrom(a[0] as i32, a[1], a[2] as usize, a[3] as u32, a[4], a[5])
+        }),
+    ]
+};
+
+/// One past the highest syscall number.
+const NR_MAX: usize = 303;
+
+/// SYSCALLS indexed by number. Building it rejects a number registered
+/// twice or past NR_MAX, at compile time.
+static TABLE: [Option<Syscall>; NR_MAX] = {
+    let mut table = [None; NR_MAX];
+    let mut i = 0;
+    while i < SYSCALLS.len() {
+        let s = SYSCALLS[i];
+        assert!((s.nr as usize) < NR_MAX, "syscall number past NR_MAX");
+        assert!(table[s.nr as usize].is_none(), "syscall registered twice");
+        table[s.nr as usize] = Some(s);
+        i += 1;
+    }
+    table
+};
+
+/// The syscall numbered `nr`, if there is one.
+pub fn lookup(nr: u64) -> Option<&'static Syscall> {
+    TABLE.get(nr as usize)?.as_ref()
+}
+
+pub mod fs {
+    use super::errno::*;
+    use crate::fs::path::Location;
+    use crate::fs::vfs::{Errno, File, FileType, Stat, TimeSet, Timespec};
+    use crate::mm::uaccess::{copy_from_user, copy_to_user, get_user, put_user, strncpy_from_user};
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    const PATH_MAX: usize = 4096;
+
+    /// User buffers go through a kernel bounce buffer of at most this size.
+    const IO_CHUNK: usize = 64 * 1024;
+
+    /// dirfd meaning the working directory, and the *at flags we take.
+    pub const AT_FDCWD: i32 = -100;
+    pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+    pub const AT_EMPTY_PATH: u32 = 0x1000;
+
+    pub(super) fn lookup_fd(fd: i32) -> Option<Arc<File>> {
+        crate::proc::current_process()?.lock().get_fd(fd)
+    }
+
+    /// A path argument, copied in from user memory.
+    fn path_from_user(ptr: u64) -> Result<String, i64> {
+        let raw = strncpy_from_user(ptr, PATH_MAX).map_err(|e| e.as_neg_i64())?;
+        String::from_utf8(raw).map_err(|_| -EINVAL)
+    }
+
+    /// Where a relative `path` given with `dirfd` starts: None for the
+    /// working directory. An absolute path doesn't look at `dirfd` at all.
+    fn dir_from_fd(dirfd: i32, path: &str) -> Result<Option<Location>, i64> {
+        if dirfd == AT_FDCWD || path.starts_with('/') {
+            return Ok(None);
+        }
+        let file = lookup_fd(dirfd).ok_or(-EBADF)?;
+        match file.location() {
+            Some(loc) => Ok(Some(loc.clone())),
+            None => Err(-ENOTDIR),
+        }
+    }
+
+    pub fn sys_open(path_ptr: u64, flags: u32, mode: u32) -> i64 {
+        sys_openat(AT_FDCWD, path_ptr, flags, mode)
+    }
+
+    pub fn sys_openat(dirfd: i32, path_ptr: u64, flags: u32, mode: u32) -> i64 {
+        let arc = match crate::proc::current_process() {
+            Some(p) => p,
+            None => return -EINVAL,
+        };
+        let path = match path_from_user(path_ptr) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+        let dir = match dir_from_fd(dirfd, &path) {
+            Ok(d) => d,
+            Err(e) => return e,
+        };
+
+        let file = crate::fs::mount::with_vfs(|vfs| vfs.open_at(dir.as_ref(), &path, flags, mode));
+        let file = match file {
+            Ok(f) => f,
+            Err(e) => return e.as_neg_i64(),
+        };
+        if flags & crate::fs::vfs::O_DIRECTORY != 0 && !file.inode.is_dir() {
+            return crate::fs::vfs::Errno::ENOTDIR.as_neg_i64();
+        }
+
+        let fd = arc.lock().alloc_fd(file);
+        match fd {
+            Some(fd) => fd as i64,
+            None => -EMFILE,
+        }
+    }
+
+    /// getcwd(buf, size): the path from the caller's root, NUL-terminated;
+    /// returns its length with the NUL.
+    pub fn sys_getcwd(buf: u64, size: usize) -> i64 {
+        let mut path = crate::fs::mount::with_vfs(|vfs| vfs.cwd_path()).into_bytes();
+        path.push(0);
+        if path.len() > size {
+            return -ERANGE;
+        }
+        match copy_to_user(buf, &path) {
+            Ok(()) => path.len() as i64,
+            Err(e) => e.as_neg_i64(),
         }
-        SYS_FORK | SYS_VFORK => crate::proc::fork::sys_fork_simple(),
-        SYS_EXECVE => crate::proc::exec::sys_execve_simple(a0, a1, a2),
-        SYS_EXIT | SYS_EXIT_GROUP => {
-            if let Some(arc) = crate::proc::current_process() {
-                let mut p = arc.lock();
-                p.state = crate::proc::ProcessState::Dead;
-                p.exit_code = a0 as i32;
+    }
+
+    pub fn sys_chdir(path_ptr: u64) -> i64 {
+        match path_from_user(path_ptr) {
+            Ok(path) => match crate::fs::mount::with_vfs(|vfs| vfs.cd(&path)) {
+                Ok(()) => 0,
+                Err(e) => e.as_neg_i64(),
+            },
+            Err(e) => e,
+        }
+    }
+
+    pub fn sys_chroot(path_ptr: u64) -> i64 {
+        match path_from_user(path_ptr) {
+            Ok(path) => match crate::fs::mount::with_vfs(|vfs| vfs.chroot(&path)) {
+                Ok(()) => 0,
+                Err(e) => e.as_neg_i64(),
+            },
+            Err(e) => e,
+        }
+    }
+
+    /// struct stat as x86_64 Linux lays it out.
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct LinuxStat {
+        dev: u64,
+        ino: u64,
+        nlink: u64,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+        _pad0: u32,
+        rdev: u64,
+        size: i64,
+        blksize: i64,
+        /// In 512-byte units, whatever the filesystem's block size.
+        blocks: i64,
+        atime: i64,
+        atime_nsec: i64,
+        mtime: i64,
+        mtime_nsec: i64,
+        ctime: i64,
+        ctime_nsec: i64,
+        _unused: [i64; 3],
+    }
+
+    impl LinuxStat {
+        // No device numbers yet: every file reports st_dev 0
+        fn from(st: &Stat) -> Self {
+            LinuxStat {
+                ino: st.ino,
+                nlink: st.nlink as u64,
+                mode: st.kind.mode_bits() | (st.mode & 0o7777),
+                uid: st.uid,
+                gid: st.gid,
+                size: st.size as i64,
+                blksize: 4096,
+                blocks: st.size.div_ceil(512) as i64,
+                atime: st.atime.sec,
+                atime_nsec: st.atime.nsec as i64,
+                mtime: st.mtime.sec,
+                mtime_nsec: st.mtime.nsec as i64,
+                ctime: st.ctime.sec,
+                ctime_nsec: st.ctime.nsec as i64,
+                ..Default::default()
             }
-            crate::proc::schedule();
-            // schedule() returns only when no other runnable process exists;
-            // halt until the next interrupt (timer/keyboard) triggers a reschedule.
-            loop {
-                crate::arch::x86_64::io::hlt();
+        }
+    }
+
+    /// What fds 0-2 are when nothing is open on them: the console.
+    fn console_stat() -> Stat {
+        let now = Timespec::now();
+        Stat {
+            ino: 0,
+            kind: FileType::CharDevice,
+            size: 0,
+            mode: 0o620,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+        }
+    }
+
+    fn put_stat(ptr: u64, st: &Stat) -> i64 {
+        match put_user(ptr, &LinuxStat::from(st)) {
+            Ok(()) => 0,
+            Err(e) => e.as_neg_i64(),
+        }
+    }
+
+    pub fn sys_fstat(fd: i32, statbuf: u64) -> i64 {
+        match lookup_fd(fd) {
+            Some(f) => put_stat(statbuf, &f.inode.stat()),
+            None if (0..=2).contains(&fd) => put_stat(statbuf, &console_stat()),
+            None => -EBADF,
+        }
+    }
+
+    /// newfstatat(dirfd, path, statbuf, flags): stat and lstat relative to
+    /// `dirfd`; with AT_EMPTY_PATH an empty path is `dirfd` itself.
+    pub fn sys_newfstatat(dirfd: i32, path_ptr: u64, statbuf: u64, flags: u32) -> i64 {
+        if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+            return -EINVAL;
+        }
+        let path = match path_from_user(path_ptr) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+        if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            return sys_fstat(dirfd, statbuf);
+        }
+        let dir = match dir_from_fd(dirfd, &path) {
+            Ok(d) => d,
+            Err(e) => return e,
+        };
+        let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+        match crate::fs::mount::with_vfs(|vfs| vfs.stat_at(dir.as_ref(), &path, follow)) {
+            Ok(st) => put_stat(statbuf, &st),
+            Err(e) => e.as_neg_i64(),
+        }
+    }
+
+    /// d_type of a linux_dirent64.
+    fn dirent_type(kind: FileType) -> u8 {
+        match kind {
+            FileType::Fifo => 1,
+            FileType::CharDevice => 2,
+            FileType::Directory => 4,
+            FileType::Regular => 8,
+            FileType::Symlink => 10,
+            FileType::Socket => 12,
+        }
+    }
+
+    /// getdents64(fd, buf, count): as many linux_dirent64 records as fit.
+    /// The file position counts entries, so an entry that doesn't fit is
+    /// the first one next time.
+    pub fn sys_getdents64(fd: i32, buf: u64, count: usize) -> i64 {
+        let Some(file) = lookup_fd(fd) else {
+            return -EBADF;
+        };
+        if !file.inode.is_dir() {
+            return -ENOTDIR;
+        }
+        let limit = count.min(IO_CHUNK);
+        let mut out = alloc::vec::Vec::new();
+        // Ok(true) at the end of the directory, Ok(false) when `buf` is full
+        let res = crate::fs::mount::with_vfs(|vfs| loop {
+            let pos = file.tell();
+            let entry = match file.inode.ops.readdir(pos as usize) {
+                Ok(Some(e)) => vfs.dirent(e),
+                Ok(None) => return Ok(true),
+                Err(e) => return Err(e),
+            };
+            // ino, off, reclen, type, then the name and its NUL, padded to 8
+            let reclen = (19 + entry.name.len() + 1).next_multiple_of(8);
+            if out.len() + reclen > limit {
+                return Ok(false);
             }
+            out.extend_from_slice(&entry.ino.to_le_bytes());
+            out.extend_from_slice(&(pos + 1).to_le_bytes());
+            out.extend_from_slice(&(reclen as u16).to_le_bytes());
+            out.push(dirent_type(entry.kind));
+            out.extend_from_slice(entry.name.as_bytes());
+            out.resize(out.len() + reclen - 19 - entry.name.len(), 0);
+            file.seek_set(pos + 1);
+        });
+        match res {
+            // Not even one entry fits
+            Ok(false) if out.is_empty() => return -EINVAL,
+            Err(e) if out.is_empty() => return e.as_neg_i64(),
+            _ => {}
         }
-        SYS_WAIT4 => crate::proc::fork::sys_waitpid(a0 as i32, a1, a2 as u32),
-        SYS_KILL => 0,
-        SYS_GETPID => crate::proc::current_process()
-            .map(|p| p.lock().pid as i64)
-            .unwrap_or(1),
-        SYS_GETPPID => crate::proc::current_process()
-            .map(|p| p.lock().ppid as i64)
-            .unwrap_or(0),
-        SYS_GETTID | SYS_SET_TID_ADDRESS => crate::proc::current_process()
-            .map(|p| p.lock().pid as i64)
-            .unwrap_or(1),
-        SYS_GETUID | SYS_GETGID => 0,
-        SYS_MMAP => mm::sys_mmap(a0, a1 as usize, a2 as i32, a3 as i32, a4 as i32, a5 as i64),
-        SYS_MUNMAP => mm::sys_munmap(a0, a1 as usize),
-        SYS_BRK => mm::sys_brk(a0),
-        SYS_UNAME => misc::sys_uname(a0),
-        SYS_CLOCK_GETTIME => misc::sys_clock_gettime(a0, a1),
-        SYS_SIGACTION | SYS_SIGPROCMASK | SYS_IOCTL => 0, // stubs
-        _ => {
-            log::warn!("syscall nr={}", nr);
-            -ENOSYS
+        match copy_to_user(buf, &out) {
+            Ok(()) => out.len() as i64,
+            Err(e) => e.as_neg_i64(),
         }
     }
-}
 
-pub mod fs {
-    use super::errno::*;
-    pub fn sys_write(fd: i32, buf: *const u8, count: usize) -> i64 {
-        if buf.is_null() || count == 0 {
-            return -EFAULT;
+    pub fn sys_chmod(path_ptr: u64, mode: u32) -> i64 {
+        match path_from_user(path_ptr) {
+            Ok(path) => match crate::fs::mount::with_vfs(|vfs| vfs.chmod(&path, mode)) {
+                Ok(()) => 0,
+                Err(e) => e.as_neg_i64(),
+            },
+            Err(e) => e,
         }
-        if fd == 1 || fd == 2 {
-            let slice = unsafe { core::slice::from_raw_parts(buf, count) };
-            if let Ok(s) = core::str::from_utf8(slice) {
-                crate::drivers::serial::write_str(s);
+    }
+
+    pub fn sys_fchmod(fd: i32, mode: u32) -> i64 {
+        let Some(f) = lookup_fd(fd) else {
+            return -EBADF;
+        };
+        match crate::fs::perm::chmod(&f.inode, &crate::proc::cred::current(), mode) {
+            Ok(()) => 0,
+            Err(e) => e.as_neg_i64(),
+        }
+    }
+
+    /// An id argument of chown: -1 leaves it as it is.
+    fn id_arg(id: u32) -> Option<u32> {
+        (id != u32::MAX).then_some(id)
+    }
+
+    /// chown, or lchown unless `follow`.
+    pub fn sys_chown(path_ptr: u64, uid: u32, gid: u32, follow: bool) -> i64 {
+        let (uid, gid) = (id_arg(uid), id_arg(gid));
+        match path_from_user(path_ptr) {
+            Ok(path) => match crate::fs::mount::with_vfs(|vfs| vfs.chown(&path, uid, gid, follow)) {
+                Ok(()) => 0,
+                Err(e) => e.as_neg_i64(),
+            },
+            Err(e) => e,
+        }
+    }
+
+    pub fn sys_fchown(fd: i32, uid: u32, gid: u32) -> i64 {
+        let Some(f) = lookup_fd(fd) else {
+            return -EBADF;
+        };
+        let cred = crate::proc::cred::current();
+        match crate::fs::perm::chown(&f.inode, &cred, id_arg(uid), id_arg(gid)) {
+            Ok(()) => 0,
+            Err(e) => e.as_neg_i64(),
+        }
+    }
+
+    /// tv_nsec values in utimensat's times that aren't times.
+    const UTIME_NOW: i64 = (1 << 30) - 1;
+    const UTIME_OMIT: i64 = (1 << 30) - 2;
+
+    fn time_arg(sec: i64, nsec: i64) -> Result<TimeSet, i64> {
+        match nsec {
+            UTIME_NOW => Ok(TimeSet::Now),
+            UTIME_OMIT => Ok(TimeSet::Omit),
+            0..=999_999_999 => Ok(TimeSet::At(Timespec { sec, nsec: nsec as u32 })),
+            _ => Err(-EINVAL),
+        }
+    }
+
+    /// utimensat(dirfd, path, times, flags): `times` is the access and
+    /// the modification time as two timespecs, or NULL for now. A NULL
+    /// path is `dirfd` itself, which is how futimens comes in.
+    pub fn sys_utimensat(dirfd: i32, path_ptr: u64, times_ptr: u64, flags: u32) -> i64 {
+        if flags & !AT_SYMLINK_NOFOLLOW != 0 {
+            return -EINVAL;
+        }
+        let (atime, mtime) = if times_ptr == 0 {
+            (TimeSet::Now, TimeSet::Now)
+        } else {
+            let t: [i64; 4] = match get_user(times_ptr) {
+                Ok(t) => t,
+                Err(e) => return e.as_neg_i64(),
+            };
+            match (time_arg(t[0], t[1]), time_arg(t[2], t[3])) {
+                (Ok(a), Ok(m)) => (a, m),
+                (Err(e), _) | (_, Err(e)) => return e,
             }
-            return count as i64;
+        };
+        let cred = crate::proc::cred::current();
+        if path_ptr == 0 {
+            return match lookup_fd(dirfd) {
+                Some(f) => match crate::fs::perm::set_times(&f.inode, &cred, atime, mtime) {
+                    Ok(()) => 0,
+                    Err(e) => e.as_neg_i64(),
+                },
+                None => -EBADF,
+            };
+        }
+        let path = match path_from_user(path_ptr) {
+            Ok(p) => p,
+            Err(e) => return e,
+        };
+        let dir = match dir_from_fd(dirfd, &path) {
+            Ok(d) => d,
+            Err(e) => return e,
+        };
+        let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+        let res = crate::fs::mount::with_vfs(|vfs| {
+            vfs.utimens_at(dir.as_ref(), &path, atime, mtime, follow)
+        });
+        match res {
+            Ok(()) => 0,
+            Err(e) => e.as_neg_i64(),
+        }
+    }
+
+    pub fn sys_close(fd: i32) -> i64 {
+        let closed = match crate::proc::current_process() {
+            Some(p) => p.lock().close_fd(fd),
+            None => None,
+        };
+        match closed {
+            Some(_) => 0,
+            None if (0..=2).contains(&fd) => 0,
+            None => -EBADF,
         }
-        -EBADF
     }
-    pub fn sys_read(fd: i32, buf: *mut u8, count: usize) -> i64 {
-        if buf.is_null() || count == 0 {
-            return -EFAULT;
+
+    pub fn sys_pipe(fds: u64) -> i64 {
+        let arc = match crate::proc::current_process() {
+            Some(p) => p,
+            None => return -EINVAL,
+        };
+        // Declared before the guard so a failed install drops the ends
+        // only after the process lock is released (see PipeEnd::drop).
+        let (rd, wr) = crate::fs::pipe::new_pipe();
+        let (rfd, wfd) = {
+            let p = arc.lock();
+            let Some(rfd) = p.alloc_fd(Arc::clone(&rd)) else {
+                return -EMFILE;
+            };
+            let Some(wfd) = p.alloc_fd(Arc::clone(&wr)) else {
+                p.close_fd(rfd);
+                return -EMFILE;
+            };
+            (rfd, wfd)
+        };
+        if let Err(e) = put_user(fds, &[rfd, wfd]) {
+            let p = arc.lock();
+            p.close_fd(rfd);
+            p.close_fd(wfd);
+            return e.as_neg_i64();
         }
-        if fd == 0 {
-            match crate::drivers::keyboard::read_char() {
-                Some(c) => {
-                    unsafe {
-                        *buf = c;
+        0
+    }
+
+    pub fn sys_lseek(fd: i32, off: i64, whence: u32) -> i64 {
+        match lookup_fd(fd) {
+            Some(f) => match f.seek(off, whence) {
+                Ok(pos) => pos as i64,
+                Err(e) => e.as_neg_i64(),
+            },
+            None => -EBADF,
+        }
+    }
+
+    /// Feed [buf, buf + count) to `sink` a chunk at a time, stopping at a
+    /// short write. Returns the bytes written, or the error if none were.
+    fn write_from_user(
+        buf: u64,
+        count: usize,
+        mut sink: impl FnMut(&[u8]) -> Result<usize, Errno>,
+    ) -> i64 {
+        let mut kbuf = vec![0u8; count.min(IO_CHUNK)];
+        let mut done = 0;
+        while done < count {
+            let n = (count - done).min(kbuf.len());
+            let res = copy_from_user(&mut kbuf[..n], buf + done as u64).and_then(|_| sink(&kbuf[..n]));
+            match res {
+                Ok(w) => {
+                    done += w;
+                    if w < n {
+                        break;
                     }
-                    1
                 }
-                None => -EAGAIN,
+                Err(e) if done == 0 => return e.as_neg_i64(),
+                Err(_) => break,
             }
-        } else {
-            -EBADF
+        }
+        done as i64
+    }
+
+    pub fn sys_write(fd: i32, buf: u64, count: usize) -> i64 {
+        if count == 0 {
+            return 0;
+        }
+        if let Some(f) = lookup_fd(fd) {
+            if !f.writable() {
+                return -EBADF;
+            }
+            return write_from_user(buf, count, |data| f.write(data));
+        }
+        // No file: fds 1 and 2 are the console
+        if fd == 1 || fd == 2 {
+            return write_from_user(buf, count, |data| {
+                crate::drivers::tty::console().write(data);
+                Ok(data.len())
+            });
+        }
+        -EBADF
+    }
+
+    pub fn sys_read(fd: i32, buf: u64, count: usize) -> i64 {
+        if count == 0 {
+            return 0;
+        }
+        let file = lookup_fd(fd);
+        match &file {
+            Some(f) if !f.readable() => return -EBADF,
+            None if fd != 0 => return -EBADF,
+            _ => {}
+        }
+        // One chunk at most: a short read is always allowed
+        let mut kbuf = vec![0u8; count.min(IO_CHUNK)];
+        let res = match file {
+            Some(f) => f.read(&mut kbuf),
+            // No file: fd 0 is the console
+            None => crate::drivers::tty::console().read(&mut kbuf),
+        };
+        match res {
+            Ok(n) => match copy_to_user(buf, &kbuf[..n]) {
+                Ok(()) => n as i64,
+                Err(e) => e.as_neg_i64(),
+            },
+            Err(e) => e.as_neg_i64(),
+        }
+    }
+
+    pub fn sys_ioctl(fd: i32, cmd: u32, arg: u64) -> i64 {
+        let res = match lookup_fd(fd) {
+            Some(f) => f.ioctl(cmd, arg),
+            None if (0..=2).contains(&fd) => crate::drivers::tty::console().ioctl(cmd, arg),
+            None => return -EBADF,
+        };
+        match res {
+            Ok(v) => v,
+            Err(e) => e.as_neg_i64(),
         }
     }
 }
 
 pub mod mm {
     use super::errno::*;
-    use crate::mm::pmm::PAGE_SIZE;
-    use crate::mm::vmm::VmaFlags;
+    use crate::fs::vfs::{O_ACCMODE, O_RDWR, O_WRONLY};
+    use crate::mm::pmm::{align_up, PAGE_SIZE};
+    use crate::mm::vmm::{VmaFlags, USER_SPACE_END};
+    use crate::proc::resource::RLIMIT_AS;
+    pub const PROT_READ: i32 = 1;
+    pub const PROT_WRITE: i32 = 2;
+    pub const PROT_EXEC: i32 = 4;
+    pub const MAP_SHARED: i32 = 0x01;
+    pub const MAP_PRIVATE: i32 = 0x02;
+    pub const MAP_FIXED: i32 = 0x10;
+    pub const MAP_ANONYMOUS: i32 = 0x20;
+
+    pub fn prot_to_vma(prot: i32) -> VmaFlags {
+        let mut vf = VmaFlags::empty();
+        if prot & PROT_READ != 0 {
+            vf |= VmaFlags::READ;
+        }
+        if prot & PROT_WRITE != 0 {
+            vf |= VmaFlags::WRITE;
+        }
+        if prot & PROT_EXEC != 0 {
+            vf |= VmaFlags::EXEC;
+        }
+        vf
+    }
+
+    /// mmap(): anonymous memory or a file mapped page by page on fault.
+    /// A MAP_SHARED file mapping is loaded like a private one: writes stay
+    /// in memory until msync.
     pub fn sys_mmap(addr: u64, len: usize, prot: i32, flags: i32, fd: i32, off: i64) -> i64 {
         if len == 0 {
             return -EINVAL;
         }
+        if flags & (MAP_SHARED | MAP_PRIVATE) == 0 {
+            return -EINVAL;
+        }
         let arc = match crate::proc::current_process() {
             Some(p) => p,
             None => return -ENOMEM,
         };
-        let mut proc = arc.lock();
-        let mut vf = VmaFlags::ANONYMOUS;
-        if prot & 1 != 0 {
-            vf |= VmaFlags::READ;
-        }
-        if prot & 2 != 0 {
-            vf |= VmaFlags::WRITE;
-        }
-        if prot & 4 != 0 {
-            vf |= VmaFlags::EXEC;
+
+        let mut vf = prot_to_vma(prot);
+        if flags & MAP_SHARED != 0 {
+            vf |= VmaFlags::SHARED;
         }
-        let virt = if addr != 0 && flags & 0x10 != 0 {
+        let file = if flags & MAP_ANONYMOUS != 0 {
+            vf |= VmaFlags::ANONYMOUS;
+            None
+        } else {
+            if off < 0 || !(off as usize).is_multiple_of(PAGE_SIZE) {
+                return -EINVAL;
+            }
+            let Some(f) = arc.lock().get_fd(fd) else {
+                return -EBADF;
+            };
+            if !f.inode.is_file() {
+                return -ENODEV;
+            }
+            let acc = f.flags & O_ACCMODE;
+            let writable = acc == O_WRONLY || acc == O_RDWR;
+            if acc == O_WRONLY || (flags & MAP_SHARED != 0 && prot & PROT_WRITE != 0 && !writable)
+            {
+                return -EACCES;
+            }
+            Some(f.inode.clone())
+        };
+
+        let size = ((len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)) as u64;
+        let fits = |a: u64| a.checked_add(size).is_some_and(|e| e <= USER_SPACE_END);
+        let (mm, as_limit) = {
+            let p = arc.lock();
+            (p.mm.clone(), p.limits.cur(RLIMIT_AS))
+        };
+        let mut mm = mm.lock();
+        let virt = if flags & MAP_FIXED != 0 {
+            if addr == 0 || !addr.is_multiple_of(PAGE_SIZE as u64) {
+                return -EINVAL;
+            }
+            if !fits(addr) {
+                return -ENOMEM;
+            }
+            // What is mapped there now goes, so only the rest is new
+            let old = mm.vm.size_in(addr, addr + size);
+            if mm.vm.size() - old + size > as_limit {
+                return -ENOMEM;
+            }
+            // MAP_FIXED replaces whatever was mapped there
+            mm.unmap_range(addr, addr + size);
             addr
         } else {
-            proc.vm.brk
+            // A hint is taken if it is free, otherwise it is ignored
+            if mm.vm.size().saturating_add(size) > as_limit {
+                return -ENOMEM;
+            }
+            let hint = addr & !(PAGE_SIZE as u64 - 1);
+            if hint != 0 && fits(hint) && !mm.vm.overlaps(hint, hint + size) {
+                hint
+            } else {
+                match mm.vm.find_free_region(size, PAGE_SIZE as u64) {
+                    Some(v) => v,
+                    None => return -ENOMEM,
+                }
+            }
+        };
+        let added = match file {
+            Some(inode) => mm.vm.add_file_vma(virt, virt + size, vf, inode, off as u64),
+            None => mm.vm.add_vma(virt, virt + size, vf),
         };
-        let size = (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-        proc.vm.add_vma(virt, virt + size as u64, vf);
-        if !(addr != 0 && flags & 0x10 != 0) {
-            proc.vm.brk = virt + size as u64;
+        if !added {
+            return -ENOMEM;
         }
         virt as i64
     }
-    pub fn sys_munmap(addr: u64, len: usize) -> i64 {
-        if addr % PAGE_SIZE as u64 != 0 {
+    pub fn sys_mprotect(addr: u64, len: usize, prot: i32) -> i64 {
+        if !addr.is_multiple_of(PAGE_SIZE as u64) || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0
+        {
+            return -EINVAL;
+        }
+        if len == 0 {
+            return 0;
+        }
+        let arc = match crate::proc::current_process() {
+            Some(p) => p,
+            None => return -EINVAL,
+        };
+        let end = len.checked_next_multiple_of(PAGE_SIZE).and_then(|l| addr.checked_add(l as u64));
+        let Some(end) = end else {
+            return -ENOMEM;
+        };
+        let mm = arc.lock().mm.clone();
+        let ok = mm.lock().protect(addr, end, prot_to_vma(prot));
+        if ok {
+            0
+        } else {
+            -ENOMEM
+        }
+    }
+
+    pub const MS_ASYNC: i32 = 1;
+    pub const MS_INVALIDATE: i32 = 2;
+    pub const MS_SYNC: i32 = 4;
+
+    /// msync(): writes happen right away, so MS_ASYNC and MS_SYNC behave
+    /// alike; there is no page cache for MS_INVALIDATE to drop.
+    pub fn sys_msync(addr: u64, len: usize, flags: i32) -> i64 {
+        if !addr.is_multiple_of(PAGE_SIZE as u64)
+            || flags & !(MS_ASYNC | MS_INVALIDATE | MS_SYNC) != 0
+            || (flags & MS_ASYNC != 0 && flags & MS_SYNC != 0)
+        {
             return -EINVAL;
         }
         let arc = match crate::proc::current_process() {
             Some(p) => p,
             None => return -EINVAL,
         };
-        let mut proc = arc.lock();
-        let end = addr + len as u64;
-        proc.vm.remove_vma(addr, end);
-        let mut v = addr;
-        while v < end {
-            proc.address_space.unmap(v);
-            v += PAGE_SIZE as u64;
+        let end = len.checked_next_multiple_of(PAGE_SIZE).and_then(|l| addr.checked_add(l as u64));
+        let Some(end) = end else {
+            return -ENOMEM;
+        };
+        let mm = arc.lock().mm.clone();
+        let r = crate::mm::vmm::sync_range(&mm, addr, end);
+        match r {
+            Some(Ok(_)) => 0,
+            Some(Err(e)) => -e.0,
+            None => -ENOMEM,
         }
+    }
+
+    pub fn sys_munmap(addr: u64, len: usize) -> i64 {
+        if !addr.is_multiple_of(PAGE_SIZE as u64) || len == 0 {
+            return -EINVAL;
+        }
+        let arc = match crate::proc::current_process() {
+            Some(p) => p,
+            None => return -EINVAL,
+        };
+        let end = len.checked_next_multiple_of(PAGE_SIZE).and_then(|l| addr.checked_add(l as u64));
+        let end = match end {
+            Some(e) if e <= USER_SPACE_END => e,
+            _ => return -EINVAL,
+        };
+        let mm = arc.lock().mm.clone();
+        mm.lock().unmap_range(addr, end);
         0
     }
     pub fn sys_brk(nb: u64) -> i64 {
@@ -199,70 +977,306 @@ pub mod mm {
             Some(p) => p,
             None => return -ENOMEM,
         };
-        let mut proc = arc.lock();
-        if nb == 0 || nb < proc.vm.brk {
-            return proc.vm.brk as i64;
+        let (mm, as_limit) = {
+            let p = arc.lock();
+            (p.mm.clone(), p.limits.cur(RLIMIT_AS))
+        };
+        let mut mm = mm.lock();
+        // Nothing past user space, and nothing for align_up to overflow on
+        if nb == 0 || nb > USER_SPACE_END {
+            return mm.vm.brk as i64;
         }
-        let old = proc.vm.brk;
-        proc.vm.add_vma(
-            old,
-            nb,
-            VmaFlags::READ | VmaFlags::WRITE | VmaFlags::ANONYMOUS,
-        );
-        proc.vm.brk = nb;
-        nb as i64
+        // Past RLIMIT_AS the break stays put, which is how brk() fails
+        let page = PAGE_SIZE as u64;
+        let grow = align_up(nb, page).saturating_sub(align_up(mm.vm.brk, page));
+        if mm.vm.size().saturating_add(grow) > as_limit {
+            return mm.vm.brk as i64;
+        }
+        mm.set_brk(nb) as i64
     }
 }
 
-pub mod misc {
+pub mod net {
     use super::errno::*;
-    use crate::arch::x86_64::limine::phys_to_virt;
-    pub fn sys_uname(ptr: u64) -> i64 {
-        if ptr == 0 {
-            return -EFAULT;
+    use crate::fs::vfs::Errno;
+    use crate::mm::uaccess::{copy_from_user, copy_to_user, get_user, put_user};
+    use crate::net::socket::{self, Socket};
+    use crate::net::{udp, Ipv4Addr};
+    use alloc::vec;
+
+    pub const AF_UNSPEC: u16 = 0;
+    pub const AF_INET: u16 = 2;
+    pub const SOCK_DGRAM: u32 = 2;
+    pub const SOCK_NONBLOCK: u32 = 0o4000;
+    pub const SOCK_CLOEXEC: u32 = 0o2000000;
+    pub const IPPROTO_UDP: u32 = 17;
+    pub const MSG_DONTWAIT: u32 = 0x40;
+
+    /// struct sockaddr_in: family, port and address (both big-endian),
+    /// then padding.
+    const SOCKADDR_IN_LEN: usize = 16;
+
+    /// The address in a user sockaddr; None for AF_UNSPEC.
+    fn get_sockaddr(ptr: u64, len: u32) -> Result<Option<(Ipv4Addr, u16)>, i64> {
+        if (len as usize) < 2 {
+            return Err(-EINVAL);
         }
-        let arc = match crate::proc::current_process() {
-            Some(p) => p,
-            None => return -EFAULT,
+        let family: u16 = get_user(ptr).map_err(Errno::as_neg_i64)?;
+        match family {
+            AF_UNSPEC => return Ok(None),
+            AF_INET => {}
+            _ => return Err(-EAFNOSUPPORT),
+        }
+        if (len as usize) < SOCKADDR_IN_LEN {
+            return Err(-EINVAL);
+        }
+        let sa: [u8; SOCKADDR_IN_LEN] = get_user(ptr).map_err(Errno::as_neg_i64)?;
+        let port = u16::from_be_bytes([sa[2], sa[3]]);
+        Ok(Some((Ipv4Addr([sa[4], sa[5], sa[6], sa[7]]), port)))
+    }
+
+    /// Store a sockaddr_in at `ptr`, truncated to the buffer size in
+    /// `*len_ptr`, which is then set to the full size.
+    fn put_sockaddr(ptr: u64, len_ptr: u64, addr: Ipv4Addr, port: u16) -> Result<(), Errno> {
+        let len: u32 = get_user(len_ptr)?;
+        let mut sa = [0u8; SOCKADDR_IN_LEN];
+        sa[0..2].copy_from_slice(&AF_INET.to_ne_bytes());
+        sa[2..4].copy_from_slice(&port.to_be_bytes());
+        sa[4..8].copy_from_slice(&addr.0);
+        copy_to_user(ptr, &sa[..(len as usize).min(SOCKADDR_IN_LEN)])?;
+        put_user(len_ptr, &(SOCKADDR_IN_LEN as u32))
+    }
+
+    fn with_socket(fd: i32, f: impl FnOnce(&Socket) -> Result<i64, Errno>) -> i64 {
+        let Some(file) = super::fs::lookup_fd(fd) else {
+            return -EBADF;
         };
-        let phys = match arc.lock().address_space.translate(ptr) {
-            Some(p) => p,
-            None => return -EFAULT,
+        let Some(sock) = file.inode.ops.as_socket() else {
+            return -ENOTSOCK;
         };
-        let buf = unsafe { core::slice::from_raw_parts_mut(phys_to_virt(phys) as *mut u8, 65 * 6) };
-        buf.fill(0);
+        f(sock).unwrap_or_else(Errno::as_neg_i64)
+    }
+
+    /// Only UDP over IPv4 for now.
+    pub fn sys_socket(domain: u32, kind: u32, protocol: u32) -> i64 {
+        if domain != AF_INET as u32 {
+            return -EAFNOSUPPORT;
+        }
+        if kind & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != SOCK_DGRAM
+            || (protocol != 0 && protocol != IPPROTO_UDP)
+        {
+            return -EPROTONOSUPPORT;
+        }
+        let Some(arc) = crate::proc::current_process() else {
+            return -EINVAL;
+        };
+        let file = socket::new_udp(kind & SOCK_NONBLOCK != 0);
+        // A failed install drops the socket after the lock is released
+        let fd = arc.lock().alloc_fd(file);
+        match fd {
+            Some(fd) => fd as i64,
+            None => -EMFILE,
+        }
+    }
+
+    pub fn sys_bind(fd: i32, addr: u64, len: u32) -> i64 {
+        let (ip, port) = match get_sockaddr(addr, len) {
+            Ok(Some(a)) => a,
+            Ok(None) => return -EAFNOSUPPORT,
+            Err(e) => return e,
+        };
+        with_socket(fd, |s| {
+            if !ip.is_unspecified() && !crate::net::is_local(ip) {
+                return Err(Errno::EADDRNOTAVAIL);
+            }
+            s.bind(port).map(|()| 0)
+        })
+    }
+
+    /// AF_UNSPEC dissolves the association.
+    pub fn sys_connect(fd: i32, addr: u64, len: u32) -> i64 {
+        match get_sockaddr(addr, len) {
+            Ok(peer) => with_socket(fd, |s| s.connect(peer).map(|()| 0)),
+            Err(e) => e,
+        }
+    }
+
+    pub fn sys_sendto(fd: i32, buf: u64, len: usize, _flags: u32, addr: u64, addr_len: u32) -> i64 {
+        let dst = if addr == 0 {
+            None
+        } else {
+            match get_sockaddr(addr, addr_len) {
+                Ok(Some(a)) => Some(a),
+                Ok(None) => return -EAFNOSUPPORT,
+                Err(e) => return e,
+            }
+        };
+        with_socket(fd, |s| {
+            if len > udp::MAX_PAYLOAD {
+                return Err(Errno::EMSGSIZE);
+            }
+            let mut data = vec![0u8; len];
+            copy_from_user(&mut data, buf)?;
+            s.send_to(dst, &data).map(|n| n as i64)
+        })
+    }
+
+    /// Returns the bytes stored; the rest of a longer datagram is lost.
+    pub fn sys_recvfrom(
+        fd: i32,
+        buf: u64,
+        len: usize,
+        flags: u32,
+        addr: u64,
+        addr_len: u64,
+    ) -> i64 {
+        with_socket(fd, |s| {
+            let mut data = vec![0u8; len.min(udp::MAX_PAYLOAD)];
+            let (n, from, port) = s.recv_from(&mut data, flags & MSG_DONTWAIT != 0)?;
+            let n = n.min(data.len());
+            copy_to_user(buf, &data[..n])?;
+            if addr != 0 {
+                put_sockaddr(addr, addr_len, from, port)?;
+            }
+            Ok(n as i64)
+        })
+    }
+}
+
+pub mod misc {
+    use super::errno::*;
+    use crate::mm::uaccess::{copy_to_user, get_user, put_user};
+
+    pub fn sys_uname(ptr: u64) -> i64 {
+        let mut buf = [0u8; 65 * 6];
         buf[..5].copy_from_slice(b"MyOS\0");
         buf[65..69].copy_from_slice(b"myos");
         buf[130..135].copy_from_slice(b"0.1.0");
         buf[195..201].copy_from_slice(b"#1 SMP");
         buf[260..266].copy_from_slice(b"x86_64");
-        0
+        match copy_to_user(ptr, &buf) {
+            Ok(()) => 0,
+            Err(e) => e.as_neg_i64(),
+        }
     }
+    pub const CLOCK_REALTIME: u64 = 0;
+    pub const CLOCK_MONOTONIC: u64 = 1;
+    pub const CLOCK_MONOTONIC_RAW: u64 = 4;
+    pub const CLOCK_REALTIME_COARSE: u64 = 5;
+    pub const CLOCK_MONOTONIC_COARSE: u64 = 6;
+    pub const CLOCK_BOOTTIME: u64 = 7;
+
     pub fn sys_clock_gettime(id: u64, ptr: u64) -> i64 {
-        if ptr == 0 {
-            return -EFAULT;
-        }
-        let arc = match crate::proc::current_process() {
-            Some(p) => p,
-            None => return -EFAULT,
+        use crate::arch::x86_64::timer;
+        let ns = match id {
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE => timer::realtime(),
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+                timer::nanos()
+            }
+            _ => return -EINVAL,
         };
-        let phys = match arc.lock().address_space.translate(ptr) {
-            Some(p) => p,
-            None => return -EFAULT,
+        match put_user(ptr, &[ns / 1_000_000_000, ns % 1_000_000_000]) {
+            Ok(()) => 0,
+            Err(e) => e.as_neg_i64(),
+        }
+    }
+    /// On a signal the unslept time goes to `rem` (if given) and the call
+    /// fails with EINTR, as on Linux.
+    pub fn sys_nanosleep(req: u64, rem: u64) -> i64 {
+        let [sec, nsec] = match get_user::<[i64; 2]>(req) {
+            Ok(ts) => ts,
+            Err(e) => return e.as_neg_i64(),
         };
-        let ns = crate::arch::x86_64::timer::nanos();
-        unsafe {
-            let p = phys_to_virt(phys) as *mut u64;
-            p.write(ns / 1_000_000_000);
-            p.add(1).write(ns % 1_000_000_000);
+        if sec < 0 || !(0..1_000_000_000).contains(&nsec) {
+            return -EINVAL;
+        }
+        let ns = (sec as u64).saturating_mul(1_000_000_000).saturating_add(nsec as u64);
+        match crate::proc::timers::sleep_ns(ns) {
+            Ok(()) => 0,
+            Err(left) => {
+                if rem != 0 {
+                    let left = [(left / 1_000_000_000) as i64, (left % 1_000_000_000) as i64];
+                    if let Err(e) = put_user(rem, &left) {
+                        return e.as_neg_i64();
+                    }
+                }
+                -EINTR
+            }
         }
-        0
     }
 }
 
-pub fn handle_int80(frame: &mut InterruptFrame) {
-    let r = syscall_dispatch(
-        frame.rax, frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9,
-    );
+/// Syscall entered through SYSCALL or int 0x80: arguments and result live
+/// in the saved user registers.
+pub fn handle_syscall_frame(frame: &mut InterruptFrame) {
+    let nr = frame.rax;
+    let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9];
+    let traced = crate::proc::current_process().and_then(|p| {
+        let p = p.lock();
+        p.trace.then_some(p.pid)
+    });
+    let Some(s) = lookup(nr) else {
+        match traced {
+            Some(pid) => log::info!("strace: {}: syscall_{}(...) = -ENOSYS", pid, nr),
+            None => log::warn!("syscall nr={}", nr),
+        }
+        frame.rax = -ENOSYS as u64;
+        return;
+    };
+    // Read the arguments now: execve replaces the memory they point into
+    let call = traced.map(|pid| (pid, trace_call(s, &args)));
+    if let Some((pid, call)) = &call {
+        if matches!(nr, nr::SYS_EXIT | nr::SYS_EXIT_GROUP) {
+            // There is no return to log
+            log::info!("strace: {}: {} = ?", pid, call);
+        }
+    }
+    let r = (s.handler)(args, frame);
+    if let Some((pid, call)) = call {
+        log::info!("strace: {}: {} = {}", pid, call, TraceValue(r as u64));
+    }
     frame.rax = r as u64;
 }
+
+// ─── Tracing ─────────────────────────────────────────────────────────────────
+
+/// A register as strace would show it: small numbers and errors in
+/// decimal, anything address-like in hex.
+struct TraceValue(u64);
+
+impl core::fmt::Display for TraceValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.0 as i64 {
+            v @ -4095..=4095 => write!(f, "{}", v),
+            _ => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// `name(arg, ...)` for the strace log, with the paths the call takes
+/// copied in from user memory.
+fn trace_call(s: &Syscall, args: &[u64; 6]) -> alloc::string::String {
+    use core::fmt::Write;
+    // Which argument is a path, if any
+    let path = match s.nr {
+        nr::SYS_OPEN | nr::SYS_STAT | nr::SYS_LSTAT | nr::SYS_CHDIR | nr::SYS_CHROOT
+        | nr::SYS_CHMOD | nr::SYS_CHOWN | nr::SYS_LCHOWN | nr::SYS_EXECVE => Some(0),
+        nr::SYS_OPENAT | nr::SYS_NEWFSTATAT | nr::SYS_UTIMENSAT => Some(1),
+        _ => None,
+    };
+    let mut out = alloc::format!("{}(", s.name);
+    for (i, &a) in args.iter().take(s.nargs as usize).enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        let text = (path == Some(i) && a != 0)
+            .then(|| crate::mm::uaccess::strncpy_from_user(a, 256).ok())
+            .flatten();
+        let _ = match text {
+            Some(t) => write!(out, "\"{}\"", alloc::string::String::from_utf8_lossy(&t)),
+            None => write!(out, "{}", TraceValue(a)),
+        };
+    }
+    out.push(')');
+    out
+}